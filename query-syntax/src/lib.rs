@@ -0,0 +1,292 @@
+//! The syntax layer of the `tantivy-demo` query language, shared by the server and by
+//! frontends that want to check a query before sending it: the `wasm32-unknown-unknown` build
+//! with the `wasm` feature validates exactly as the server does (see `src/wasm.rs`).
+//!
+//! Everything here works on the query text alone: tantivy's grammar, the `field:(a OR b)`
+//! groups it lacks, the clause limit and `minimum_should_match`. What needs the index (JSON
+//! paths, protected terms, how many terms analysis produces) stays in the server.
+//!
+//! It needs `std`: tantivy's grammar parses with `nom`, which does. `wasm32-unknown-unknown`
+//! has `std`, and CI builds the crate for it.
+
+use std::fmt;
+use std::str::FromStr;
+
+pub use tantivy_query_grammar::{UserInputAst, UserInputLeaf};
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Caps on how far one query may expand; anything larger is rejected with a 400 before it
+/// reaches the index. Prefix (`foo*`) expansion is already bounded by tantivy itself.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    /// Term, phrase, range and set-element clauses after field groups are distributed
+    pub max_clauses: usize,
+    /// Index terms after analysis across all default fields; long n-gram phrases grow fastest.
+    /// Only the server, which knows the analyzers, checks it
+    pub max_terms: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits { max_clauses: 1024, max_terms: 4096 }
+    }
+}
+
+/// Why a query was rejected; the message is what the server answers with its 400.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError(pub String);
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Parses `q` into tantivy's query AST, field groups distributed, within `limits.max_clauses`.
+pub fn parse(q: &str, limits: &QueryLimits) -> Result<UserInputAst, SyntaxError> {
+    let expanded = expand_field_groups(q);
+    let ast = tantivy_query_grammar::parse_query(&expanded)
+        .map_err(|_| SyntaxError(format!("invalid query: Syntax Error: {}", q)))?;
+    let clauses = count_clauses(&ast);
+    if clauses > limits.max_clauses {
+        return Err(SyntaxError(format!(
+            "query too complex: {} clauses (limit {}); split long OR lists into several requests",
+            clauses, limits.max_clauses
+        )));
+    }
+    Ok(ast)
+}
+
+fn count_clauses(ast: &UserInputAst) -> usize {
+    match ast {
+        UserInputAst::Clause(clauses) => clauses.iter().map(|(_, sub)| count_clauses(sub)).sum(),
+        UserInputAst::Boost(inner, _) => count_clauses(inner),
+        UserInputAst::Leaf(leaf) => match leaf.as_ref() {
+            UserInputLeaf::Set { elements, .. } => elements.len(),
+            _ => 1,
+        },
+    }
+}
+
+/// The clauses of a query whose cost its terms' document frequencies don't show: prefixes
+/// expand to many terms at search time, ranges and sets walk a span of the index.
+#[derive(Debug, Clone, Default)]
+pub struct QueryShape {
+    /// Term, phrase, range and set-element clauses, as counted against the limit
+    pub clauses: usize,
+    /// Field (default fields when `None`) and the word expanded, e.g. `rus` for `rus*`
+    pub prefixes: Vec<(Option<String>, String)>,
+    /// Range, set and exists clauses
+    pub scans: usize,
+}
+
+/// The [`QueryShape`] of `q`, under the same syntax and clause limits as parsing it.
+pub fn shape(q: &str, limits: &QueryLimits) -> Result<QueryShape, SyntaxError> {
+    fn walk(ast: &UserInputAst, shape: &mut QueryShape) {
+        match ast {
+            UserInputAst::Clause(clauses) => clauses.iter().for_each(|(_, sub)| walk(sub, shape)),
+            UserInputAst::Boost(inner, _) => walk(inner, shape),
+            UserInputAst::Leaf(leaf) => match leaf.as_ref() {
+                UserInputLeaf::Literal(literal) if literal.prefix => {
+                    let word = literal.phrase.split_whitespace().last().unwrap_or_default();
+                    shape.prefixes.push((literal.field_name.clone(), word.to_string()));
+                }
+                UserInputLeaf::Range { .. } | UserInputLeaf::Set { .. } | UserInputLeaf::Exists { .. } => shape.scans += 1,
+                UserInputLeaf::Literal(_) | UserInputLeaf::All => {}
+            },
+        }
+    }
+    let ast = parse(q, limits)?;
+    let mut shape = QueryShape { clauses: count_clauses(&ast), ..QueryShape::default() };
+    walk(&ast, &mut shape);
+    Ok(shape)
+}
+
+/// Rewrites `field:(a OR "b c")` into `(field:a OR field:"b c")`, since tantivy's grammar has
+/// no field-scoped groups. Unbalanced input is returned as is for the grammar to reject.
+pub fn expand_field_groups(q: &str) -> String {
+    let chars: Vec<char> = q.chars().collect();
+    let mut out = String::with_capacity(q.len());
+    let mut i = 0;
+    let mut in_quote = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' {
+            in_quote = !in_quote;
+        }
+        if c == '(' && !in_quote && out.ends_with(':') {
+            let field = field_before_colon(&out);
+            if let (Some(field), Some(close)) = (field, matching_paren(&chars, i)) {
+                out.truncate(out.len() - field.len() - 1);
+                let inner: String = chars[i + 1..close].iter().collect();
+                out.push('(');
+                out.push_str(&scope_group(&expand_field_groups(&inner), &field));
+                out.push(')');
+                i = close + 1;
+                continue;
+            }
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+fn field_before_colon(out: &str) -> Option<String> {
+    let head = &out[..out.len() - 1];
+    let start = head
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '\\')))
+        .map(|(pos, c)| pos + c.len_utf8())
+        .unwrap_or(0);
+    let field = &head[start..];
+    (!field.is_empty() && !field.starts_with('-')).then(|| field.to_string())
+}
+
+fn matching_paren(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_quote = false;
+    for (pos, c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '"' => in_quote = !in_quote,
+            '(' if !in_quote => depth += 1,
+            ')' if !in_quote => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Prefixes every bare term, phrase and range of a group with `field:`; operators, nested
+/// parentheses and already field-scoped terms are kept.
+fn scope_group(inner: &str, field: &str) -> String {
+    let chars: Vec<char> = inner.chars().collect();
+    let mut out = String::with_capacity(inner.len() * 2);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == '(' || c == ')' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        // One token: up to whitespace or a parenthesis, keeping quoted text and ranges whole
+        let start = i;
+        let mut in_quote = false;
+        let mut in_range = false;
+        while i < chars.len() {
+            match chars[i] {
+                '"' => in_quote = !in_quote,
+                '[' | '{' if !in_quote => in_range = true,
+                ']' | '}' if !in_quote => in_range = false,
+                c if !in_quote && !in_range && (c.is_whitespace() || c == '(' || c == ')') => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        let token: String = chars[start..i].iter().collect();
+        let (sign, body) = match token.strip_prefix(['+', '-']) {
+            Some(body) => (&token[..1], body),
+            None => ("", token.as_str()),
+        };
+        let scoped = matches!(body, "AND" | "OR" | "NOT" | "&&" | "||" | "")
+            || (!body.starts_with('"') && body.contains(':'));
+        out.push_str(sign);
+        if !scoped {
+            out.push_str(field);
+            out.push(':');
+        }
+        out.push_str(body);
+    }
+    out
+}
+
+/// How many optional clauses must match: `2`, `-1` (all but one), `75%` or `-25%`.
+/// Percentages round down; the result is clamped to the number of clauses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinimumShouldMatch {
+    Count(i64),
+    Percent(f64),
+}
+
+impl MinimumShouldMatch {
+    pub fn resolve(self, clauses: usize) -> usize {
+        let n = clauses as i64;
+        // Both products are non-negative, so truncating rounds them down
+        let required = match self {
+            MinimumShouldMatch::Count(c) if c < 0 => n + c,
+            MinimumShouldMatch::Count(c) => c,
+            MinimumShouldMatch::Percent(p) if p < 0.0 => n - (n as f64 * -p / 100.0) as i64,
+            MinimumShouldMatch::Percent(p) => (n as f64 * p / 100.0) as i64,
+        };
+        required.clamp(0, n) as usize
+    }
+}
+
+impl FromStr for MinimumShouldMatch {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, SyntaxError> {
+        let invalid = || SyntaxError(format!("minimum_should_match must be like 2, -1, 75% or -25%, got {}", s));
+        match s.trim().strip_suffix('%') {
+            Some(p) => p.trim().parse().ok().filter(|p: &f64| (-100.0..=100.0).contains(p)).map(MinimumShouldMatch::Percent).ok_or_else(invalid),
+            None => s.trim().parse().map(MinimumShouldMatch::Count).map_err(|_| invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_groups_are_distributed() {
+        assert_eq!(expand_field_groups(r#"title:(rust OR "web dev")"#), r#"(title:rust OR title:"web dev")"#);
+        assert_eq!(expand_field_groups("tags:(a AND (b OR c)) body:x"), "(tags:a AND (tags:b OR tags:c)) body:x");
+        assert_eq!(expand_field_groups("title:(rust OR body:go)"), "(title:rust OR body:go)");
+        assert_eq!(expand_field_groups("tags:(a) AND title:(b)"), "(tags:a) AND (title:b)");
+    }
+
+    #[test]
+    fn field_groups_leave_quotes_and_unbalanced_input_alone() {
+        assert_eq!(expand_field_groups(r#"body:"a (b)""#), r#"body:"a (b)""#);
+        assert_eq!(expand_field_groups("title:(rust"), "title:(rust");
+        assert_eq!(expand_field_groups("(rust OR go)"), "(rust OR go)");
+    }
+
+    #[test]
+    fn scope_group_prefixes_bare_terms_only() {
+        assert_eq!(scope_group("+rust -go", "title"), "+title:rust -title:go");
+        assert_eq!(scope_group("a && b || NOT c", "title"), "title:a && title:b || NOT title:c");
+        assert_eq!(scope_group("[a TO c} OR body:x", "title"), "title:[a TO c} OR body:x");
+        assert_eq!(scope_group(r#""a:b" (c)"#, "title"), r#"title:"a:b" (title:c)"#);
+    }
+
+    #[test]
+    fn minimum_should_match_resolves_against_the_clause_count() {
+        assert_eq!(MinimumShouldMatch::Count(2).resolve(5), 2);
+        assert_eq!(MinimumShouldMatch::Count(-1).resolve(5), 4);
+        assert_eq!(MinimumShouldMatch::Count(9).resolve(3), 3);
+        assert_eq!(MinimumShouldMatch::Count(-9).resolve(3), 0);
+        // 3.75 and 1.25 round down
+        assert_eq!(MinimumShouldMatch::Percent(75.0).resolve(5), 3);
+        assert_eq!(MinimumShouldMatch::Percent(-25.0).resolve(5), 4);
+        assert_eq!(MinimumShouldMatch::Percent(100.0).resolve(0), 0);
+    }
+
+    #[test]
+    fn minimum_should_match_parses() {
+        assert_eq!("-1".parse(), Ok(MinimumShouldMatch::Count(-1)));
+        assert_eq!(" 75% ".parse(), Ok(MinimumShouldMatch::Percent(75.0)));
+        assert!("150%".parse::<MinimumShouldMatch>().is_err());
+        assert!("most".parse::<MinimumShouldMatch>().is_err());
+    }
+}
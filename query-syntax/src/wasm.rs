@@ -0,0 +1,107 @@
+//! Exports for JavaScript hosts, built with
+//! `cargo build -p tantivy-demo-query-syntax --release --target wasm32-unknown-unknown --features wasm`.
+//!
+//! Strings cross as UTF-8 in the module's memory: reserve room with [`query_alloc`], write the
+//! query there, call [`query_validate`] with room for the result's length, read the JSON it
+//! returns and hand every buffer back to [`query_free`]:
+//!
+//! ```js
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("query_syntax.wasm"));
+//! const { memory, query_alloc, query_free, query_validate } = instance.exports;
+//! const bytes = new TextEncoder().encode(q);
+//! const ptr = query_alloc(bytes.length), lenPtr = query_alloc(4);
+//! new Uint8Array(memory.buffer, ptr, bytes.length).set(bytes);
+//! const out = query_validate(ptr, bytes.length, 0, lenPtr);
+//! const len = new Uint32Array(memory.buffer, lenPtr, 1)[0];
+//! const result = JSON.parse(new TextDecoder().decode(new Uint8Array(memory.buffer, out, len)));
+//! query_free(ptr, bytes.length); query_free(lenPtr, 4); query_free(out, len);
+//! ```
+//!
+//! `result` is `{"valid": true, "clauses": 2, "prefixes": [{"field": null, "word": "rus"}],
+//! "scans": 0}` or `{"valid": false, "error": "..."}`, the error being the server's 400 message.
+
+use std::fmt::Write;
+
+use crate::{shape, QueryLimits};
+
+/// Reserves `len` bytes, to be handed back to [`query_free`].
+#[no_mangle]
+pub extern "C" fn query_alloc(len: usize) -> *mut u8 {
+    into_raw(vec![0; len])
+}
+
+fn into_raw(buf: Vec<u8>) -> *mut u8 {
+    Box::into_raw(buf.into_boxed_slice()) as *mut u8
+}
+
+/// Releases a buffer of [`query_alloc`] or [`query_validate`].
+///
+/// # Safety
+///
+/// `ptr` must come from one of them, with the same `len`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn query_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn validate(q: &str, limits: &QueryLimits) -> String {
+    let mut out = String::new();
+    match shape(q, limits) {
+        Ok(shape) => {
+            let _ = write!(out, "{{\"valid\":true,\"clauses\":{},\"prefixes\":[", shape.clauses);
+            for (i, (field, word)) in shape.prefixes.iter().enumerate() {
+                out.push_str(if i == 0 { "{\"field\":" } else { ",{\"field\":" });
+                match field {
+                    Some(field) => push_json_str(&mut out, field),
+                    None => out.push_str("null"),
+                }
+                out.push_str(",\"word\":");
+                push_json_str(&mut out, word);
+                out.push('}');
+            }
+            let _ = write!(out, "],\"scans\":{}}}", shape.scans);
+        }
+        Err(e) => {
+            out.push_str("{\"valid\":false,\"error\":");
+            push_json_str(&mut out, &e.0);
+            out.push('}');
+        }
+    }
+    out
+}
+
+/// Validates the query of `len` UTF-8 bytes at `ptr` under `max_clauses` (the server's
+/// default when 0). Returns the JSON result, its length written to `out_len`.
+///
+/// # Safety
+///
+/// `ptr..ptr + len` must be readable and `out_len` writable.
+#[no_mangle]
+pub unsafe extern "C" fn query_validate(ptr: *const u8, len: usize, max_clauses: usize, out_len: *mut u32) -> *mut u8 {
+    let bytes = std::slice::from_raw_parts(ptr, len);
+    let mut limits = QueryLimits::default();
+    if max_clauses > 0 {
+        limits.max_clauses = max_clauses;
+    }
+    let json = match std::str::from_utf8(bytes) {
+        Ok(q) => validate(q, &limits),
+        Err(_) => String::from("{\"valid\":false,\"error\":\"query is not UTF-8\"}"),
+    };
+    *out_len = json.len() as u32;
+    into_raw(json.into_bytes())
+}
@@ -0,0 +1,315 @@
+//! Cost-based admission: queries are costed before they run, and while the node is busy the
+//! ones above a budget are turned away (or made to wait their turn) so they can't crowd out
+//! the cheap queries that make up most traffic.
+//!
+//! The cost is an estimate of the postings a query reads: its terms' document frequencies,
+//! the terms its prefixes expand to, a full pass over the documents for each range or set
+//! clause, and for aggregations the buckets they may fill. It is computed from the term
+//! dictionaries only, so estimating is cheap next to running even a simple query.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tantivy::aggregation::agg_req::{get_fast_field_names, Aggregations};
+use tantivy::aggregation::DEFAULT_BUCKET_LIMIT;
+use tantivy::schema::FieldType;
+use tantivy::tokenizer::TokenStream;
+use tantivy::{Searcher, Term};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::aggs::CompositeRequest;
+use crate::error::{ServiceError, ServiceResult};
+use crate::query::{parse_query_with, query_shape, QueryLimits};
+use crate::service::{SearchRequest, SearchService};
+use crate::session::Snapshot;
+
+/// Terms a prefix is expanded to at most; tantivy's phrase-prefix queries stop at 50 too.
+const MAX_PREFIX_TERMS: usize = 50;
+
+/// Fields an unscoped prefix expands over: the analyzed default search fields.
+const PREFIX_FIELDS: &[&str] = &["title", "body", "tags"];
+
+/// What happens to an over-budget query while the node is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Answer 503 right away
+    Reject,
+    /// Run such queries one at a time, failing those that wait longer than the queue timeout
+    Queue,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdmissionConfig {
+    /// Estimated cost above which a query counts as expensive
+    pub budget: u64,
+    /// The node is busy once this many costed queries are in flight
+    pub busy_at: usize,
+    pub over_budget: OverBudget,
+    /// How long a queued query waits for its turn
+    pub queue_timeout: Duration,
+}
+
+/// Estimated cost of a query, in postings read.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct QueryCost {
+    /// Document frequencies of the query's terms
+    pub postings: u64,
+    /// Document frequencies of the terms prefixes expand to
+    pub expansions: u64,
+    /// Documents walked by range, set and exists clauses
+    pub scans: u64,
+    /// Buckets an aggregation may fill
+    pub buckets: u64,
+    pub total: u64,
+}
+
+impl QueryCost {
+    fn summed(self) -> Self {
+        QueryCost { total: self.postings + self.expansions + self.scans + self.buckets, ..self }
+    }
+}
+
+#[derive(Default)]
+struct AdmissionCounters {
+    admitted: AtomicU64,
+    /// Over-budget queries admitted because the node wasn't busy
+    expensive_admitted: AtomicU64,
+    queued: AtomicU64,
+    rejected: AtomicU64,
+}
+
+pub struct Admission {
+    config: ArcSwap<AdmissionConfig>,
+    in_flight: AtomicUsize,
+    /// Turn taken by queued over-budget queries
+    expensive: Semaphore,
+    counters: AdmissionCounters,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Admission { config: ArcSwap::from_pointee(config), in_flight: AtomicUsize::new(0), expensive: Semaphore::new(1), counters: AdmissionCounters::default() }
+    }
+
+    pub fn config(&self) -> AdmissionConfig {
+        self.config.load().as_ref().clone()
+    }
+
+    /// Applies to queries admitted from now on; those in flight or queued keep their turn.
+    pub fn set_config(&self, config: AdmissionConfig) {
+        self.config.store(Arc::new(config));
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let c = &self.counters;
+        let config = self.config.load();
+        serde_json::json!({
+            "budget": config.budget,
+            "busy_at": config.busy_at,
+            "over_budget": match config.over_budget {
+                OverBudget::Reject => "reject",
+                OverBudget::Queue => "queue",
+            },
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+            "admitted": c.admitted.load(Ordering::Relaxed),
+            "expensive_admitted": c.expensive_admitted.load(Ordering::Relaxed),
+            "queued": c.queued.load(Ordering::Relaxed),
+            "rejected": c.rejected.load(Ordering::Relaxed),
+        })
+    }
+
+    fn overloaded(&self, cost: &QueryCost) -> ServiceError {
+        self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+        ServiceError::Unavailable(format!(
+            "query too expensive while the service is busy: estimated cost {} exceeds the budget of {}; narrow it or retry later",
+            cost.total, self.config.load().budget
+        ))
+    }
+}
+
+/// Held while an admitted query runs; it counts towards the load until dropped.
+#[derive(Default)]
+pub struct Admitted<'a> {
+    in_flight: Option<&'a AtomicUsize>,
+    _turn: Option<SemaphorePermit<'a>>,
+    /// The estimate the decision rested on; `None` when admission control is off
+    pub cost: Option<QueryCost>,
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.in_flight {
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Document frequencies of `query`'s distinct terms, summed.
+fn postings(searcher: &Searcher, query: &dyn tantivy::query::Query) -> ServiceResult<u64> {
+    let mut terms: Vec<Term> = Vec::new();
+    query.query_terms(&mut |term, _| {
+        if !terms.contains(term) {
+            terms.push(term.clone());
+        }
+    });
+    let mut total = 0;
+    for term in &terms {
+        total += searcher.doc_freq(term)?;
+    }
+    Ok(total)
+}
+
+/// Document frequencies of the first terms of `field` starting with `word` as analyzed (the
+/// last token, as a phrase prefix uses it), per segment.
+fn prefix_postings(searcher: &Searcher, field_name: &str, word: &str) -> ServiceResult<u64> {
+    let index = searcher.index();
+    let schema = index.schema();
+    let Ok(field) = schema.get_field(field_name) else { return Ok(0) };
+    if !matches!(schema.get_field_entry(field).field_type(), FieldType::Str(_)) {
+        return Ok(0);
+    }
+    let mut analyzer = index.tokenizer_for_field(field)?;
+    let mut stream = analyzer.token_stream(word);
+    let mut prefix = None;
+    while stream.advance() {
+        prefix = Some(stream.token().text.clone());
+    }
+    let Some(prefix) = prefix else { return Ok(0) };
+    let mut total = 0;
+    for segment in searcher.segment_readers() {
+        let inverted = segment.inverted_index(field)?;
+        let mut terms = inverted.terms().range().ge(prefix.as_bytes()).into_stream()?;
+        let mut seen = 0;
+        while seen < MAX_PREFIX_TERMS && terms.advance() && terms.key().starts_with(prefix.as_bytes()) {
+            total += u64::from(terms.value().doc_freq);
+            seen += 1;
+        }
+    }
+    Ok(total)
+}
+
+/// Distinct indexed terms of `field` summed over segments, a bound on its bucket count.
+fn field_terms(searcher: &Searcher, field_name: &str) -> ServiceResult<u64> {
+    let Ok(field) = searcher.index().schema().get_field(field_name) else { return Ok(0) };
+    let mut total = 0;
+    for segment in searcher.segment_readers() {
+        total += segment.inverted_index(field)?.terms().num_terms() as u64;
+    }
+    Ok(total)
+}
+
+/// The cost of `q` on `searchers`, aggregations aside.
+fn query_cost(searchers: &[&Searcher], q: &str, req: Option<&SearchRequest>, limits: &QueryLimits) -> ServiceResult<QueryCost> {
+    let mut cost = QueryCost::default();
+    let msm = req.and_then(|r| r.minimum_should_match);
+    for searcher in searchers {
+        let query = parse_query_with(searcher, q, limits, msm)?;
+        cost.postings += postings(searcher, query.as_ref())?;
+    }
+    let shape = query_shape(q, limits)?;
+    for (field, word) in &shape.prefixes {
+        let fields = match field {
+            Some(f) => vec![f.as_str()],
+            None => PREFIX_FIELDS.to_vec(),
+        };
+        for searcher in searchers {
+            for field in &fields {
+                cost.expansions += prefix_postings(searcher, field, word)?;
+            }
+        }
+    }
+    let docs: u64 = searchers.iter().map(|s| s.num_docs()).sum();
+    cost.scans = shape.scans as u64 * docs;
+    Ok(cost)
+}
+
+impl SearchService {
+    /// Estimated cost of `req` over the tiers it searches. Join and nested filters aren't
+    /// counted.
+    pub fn estimate_search_cost(&self, req: &SearchRequest) -> ServiceResult<QueryCost> {
+        let Snapshot { hot, archive, .. } = req.snapshot.clone().unwrap_or_else(|| self.current_snapshot());
+        let mut searchers = vec![hot.as_ref()];
+        if req.include_archive {
+            searchers.push(archive.as_ref());
+        }
+        Ok(query_cost(&searchers, &req.q, Some(req), &self.query_limits())?.summed())
+    }
+
+    /// Estimated cost of a composite aggregation: its query's postings once per source, plus
+    /// the buckets the sources' terms can combine into.
+    pub fn estimate_composite_cost(&self, req: &CompositeRequest) -> ServiceResult<QueryCost> {
+        let searcher = self.searcher();
+        let mut cost = match req.q.as_deref().filter(|q| !q.trim().is_empty()) {
+            Some(q) => query_cost(&[&searcher], q, None, &self.query_limits())?,
+            None => QueryCost { postings: searcher.num_docs(), ..QueryCost::default() },
+        };
+        let sources = req.sources.len().max(1) as u64;
+        cost.postings *= sources;
+        let mut combinations: u64 = 1;
+        for source in &req.sources {
+            combinations = combinations.saturating_mul(field_terms(&searcher, source)?.max(1));
+        }
+        cost.buckets = combinations.min(searcher.num_docs().saturating_mul(sources));
+        Ok(cost.summed())
+    }
+
+    /// Estimated cost of tantivy aggregations: the query's postings once per fast field they
+    /// read, plus up to tantivy's bucket limit.
+    pub fn estimate_aggregation_cost(&self, q: Option<&str>, aggs: &Aggregations) -> ServiceResult<QueryCost> {
+        let searcher = self.searcher();
+        let mut cost = match q.filter(|q| !q.trim().is_empty()) {
+            Some(q) => query_cost(&[&searcher], q, None, &self.query_limits())?,
+            None => QueryCost { postings: searcher.num_docs(), ..QueryCost::default() },
+        };
+        let fields = get_fast_field_names(aggs).len().max(1) as u64;
+        cost.postings *= fields;
+        cost.buckets = u64::from(DEFAULT_BUCKET_LIMIT).min(searcher.num_docs().saturating_mul(fields));
+        Ok(cost.summed())
+    }
+
+    /// Estimated cost of significant terms: the query's postings plus a background lookup for
+    /// every term of `field`.
+    pub fn estimate_significant_terms_cost(&self, q: &str, field: &str) -> ServiceResult<QueryCost> {
+        let searcher = self.searcher();
+        let mut cost = query_cost(&[&searcher], q, None, &self.query_limits())?;
+        cost.buckets = field_terms(&searcher, field)?;
+        Ok(cost.summed())
+    }
+
+    /// Lets a query run, given its cost from `estimate`: always while the node isn't busy or
+    /// the cost is within budget; otherwise it is rejected or queued, as configured. Nothing
+    /// is estimated when admission control is off.
+    /// Admission control, when configured.
+    pub fn admission(&self) -> Option<&Admission> {
+        self.admission.as_ref()
+    }
+
+    pub async fn admit_query(&self, estimate: impl FnOnce() -> ServiceResult<QueryCost>) -> ServiceResult<Admitted<'_>> {
+        let Some(admission) = &self.admission else { return Ok(Admitted::default()) };
+        let cost = estimate()?;
+        let mut turn = None;
+        let config = admission.config.load_full();
+        if cost.total > config.budget {
+            let busy = admission.in_flight.load(Ordering::Relaxed) >= config.busy_at;
+            match (busy, config.over_budget) {
+                (false, _) => {
+                    admission.counters.expensive_admitted.fetch_add(1, Ordering::Relaxed);
+                }
+                (true, OverBudget::Reject) => return Err(admission.overloaded(&cost)),
+                (true, OverBudget::Queue) => {
+                    admission.counters.queued.fetch_add(1, Ordering::Relaxed);
+                    match tokio::time::timeout(config.queue_timeout, admission.expensive.acquire()).await {
+                        Ok(Ok(permit)) => turn = Some(permit),
+                        _ => return Err(admission.overloaded(&cost)),
+                    }
+                }
+            }
+        }
+        admission.counters.admitted.fetch_add(1, Ordering::Relaxed);
+        admission.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(Admitted { in_flight: Some(&admission.in_flight), _turn: turn, cost: Some(cost) })
+    }
+}
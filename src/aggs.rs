@@ -0,0 +1,413 @@
+//! Aggregations computed from the inverted index: composite buckets paged with `after` keys,
+//! significant terms of a result set and the facet counts of a search, plus tantivy's own
+//! aggregations over fast fields.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use tantivy::aggregation::agg_req::{get_fast_field_names, Aggregations};
+use tantivy::aggregation::agg_result::AggregationResults;
+use tantivy::aggregation::{AggregationCollector, AggregationLimits};
+use tantivy::collector::{Count, DocSetCollector};
+use tantivy::query::{AllQuery, Query};
+use tantivy::schema::{Field, FieldType, IndexRecordOption};
+use tantivy::postings::SegmentPostings;
+use tantivy::{u64_to_i64, DocAddress, DocSet, Searcher, SegmentReader, TERMINATED};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::nested::posts_only;
+use crate::query::{parse_query, QueryLimits};
+use crate::schema::RESTRICTED_FIELDS;
+use crate::service::{filtered, SearchService};
+
+/// Upper bound on buckets returned by one composite page.
+pub const MAX_COMPOSITE_SIZE: usize = 1000;
+
+/// Values a facet lists unless the search asks for another number.
+pub const DEFAULT_FACET_SIZE: usize = 10;
+
+/// Upper bound on the values one facet lists.
+pub const MAX_FACET_SIZE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct CompositeRequest {
+    /// Restricts counted documents; all documents when `None` or blank
+    pub q: Option<String>,
+    /// Fields whose indexed terms form the bucket key, in key order
+    pub sources: Vec<String>,
+    pub size: usize,
+    /// Key of the last bucket of the previous page; buckets strictly after it are returned
+    pub after: Option<Vec<String>>,
+    /// The caller's groups: restricted posts outside them aren't counted. No filtering when `None`
+    pub groups: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SignificantTerm {
+    pub term: String,
+    /// Matching documents containing the term
+    pub doc_count: u64,
+    /// All documents containing the term
+    pub bg_count: u64,
+    pub score: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SignificantTerms {
+    /// Documents matching the query
+    pub doc_count: u64,
+    /// Documents in the index
+    pub bg_count: u64,
+    pub terms: Vec<SignificantTerm>,
+}
+
+/// One value of a facet and how many of the search's matches carry it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Matches per value, per facet field; one tier's share until merged.
+pub(crate) type FacetCounts = BTreeMap<String, HashMap<String, u64>>;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CompositeBucket {
+    pub key: Vec<String>,
+    pub doc_count: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CompositePage {
+    pub buckets: Vec<CompositeBucket>,
+    /// Pass back as `after` for the next page; `None` once every bucket has been returned
+    pub after_key: Option<Vec<String>>,
+}
+
+impl SearchService {
+    /// One page of composite buckets: every combination of source values that co-occurs on a
+    /// matching document, with how many documents carry it, in ascending key order. Values are
+    /// indexed terms, so analyzed fields bucket by token (e.g. lowercased tags).
+    pub fn composite(&self, req: &CompositeRequest) -> ServiceResult<CompositePage> {
+        if req.sources.is_empty() {
+            return Err(ServiceError::Invalid("composite needs at least one source field".to_string()));
+        }
+        if req.size == 0 || req.size > MAX_COMPOSITE_SIZE {
+            return Err(ServiceError::Invalid(format!("size must be between 1 and {}", MAX_COMPOSITE_SIZE)));
+        }
+        if req.after.as_ref().is_some_and(|a| a.len() != req.sources.len()) {
+            return Err(ServiceError::Invalid("after must have one value per source".to_string()));
+        }
+        let searcher = self.searcher();
+        let fields = req.sources.iter().map(|name| term_field(&searcher, name)).collect::<ServiceResult<Vec<_>>>()?;
+        let filter = self.groups_filter(&searcher, req.groups.as_deref())?;
+        let matched = matched_by_segment(&searcher, req.q.as_deref(), &self.query_limits(), filter.as_deref())?;
+
+        // Keep only the `size + 1` smallest keys past the cursor; the extra one tells us
+        // whether another page exists
+        let mut counts: BTreeMap<Vec<String>, u64> = BTreeMap::new();
+        for (segment, matched) in searcher.segment_readers().iter().zip(&matched) {
+            let values: Vec<Vec<Vec<String>>> =
+                fields.iter().map(|(field, typ)| terms_by_doc(segment, *field, typ, matched)).collect::<ServiceResult<_>>()?;
+            for doc in 0..segment.max_doc() as usize {
+                if !matched[doc] {
+                    continue;
+                }
+                for key in cartesian(values.iter().map(|v| v[doc].as_slice())) {
+                    if req.after.as_ref().is_some_and(|after| key <= *after) {
+                        continue;
+                    }
+                    *counts.entry(key).or_default() += 1;
+                    if counts.len() > req.size + 1 {
+                        counts.pop_last();
+                    }
+                }
+            }
+        }
+
+        let more = counts.len() > req.size;
+        let buckets: Vec<CompositeBucket> =
+            counts.into_iter().take(req.size).map(|(key, doc_count)| CompositeBucket { key, doc_count }).collect();
+        let after_key = more.then(|| buckets.last().map(|b| b.key.clone())).flatten();
+        Ok(CompositePage { buckets, after_key })
+    }
+
+    /// Runs tantivy aggregations (`terms`, `histogram`, `range`, `stats`, ...) over the
+    /// documents matching `q`, all posts when `None` or blank. They read fast fields:
+    /// `create_at`, `status` and the paths of `features` (`features.score`). Bucket counts and
+    /// memory are held to tantivy's default limits. Restricted posts outside `groups` aren't
+    /// aggregated.
+    pub fn aggregate(&self, q: Option<&str>, aggs: Aggregations, groups: Option<&[String]>) -> ServiceResult<AggregationResults> {
+        let searcher = self.searcher();
+        let schema = searcher.index().schema();
+        let mut names: Vec<String> = get_fast_field_names(&aggs).into_iter().collect();
+        names.sort();
+        for name in &names {
+            let (field, path) = schema
+                .find_field(name)
+                .filter(|(field, _)| !RESTRICTED_FIELDS.contains(&schema.get_field_name(*field)))
+                .ok_or_else(|| ServiceError::Invalid(format!("unknown field: {}", name)))?;
+            let entry = schema.get_field_entry(field);
+            if !path.is_empty() && !matches!(entry.field_type(), FieldType::JsonObject(_)) {
+                return Err(ServiceError::Invalid(format!("unknown field: {}", name)));
+            }
+            if !entry.is_fast() {
+                return Err(ServiceError::Invalid(format!("{} can't be aggregated: not a fast field", name)));
+            }
+        }
+        let filter = self.groups_filter(&searcher, groups)?;
+        let query = matching_query(&searcher, q, &self.query_limits(), filter.as_deref())?;
+        let collector = AggregationCollector::from_aggs(aggs, AggregationLimits::default());
+        searcher.search(query.as_ref(), &collector).map_err(|e| match e {
+            tantivy::TantivyError::AggregationError(e) => ServiceError::Invalid(e.to_string()),
+            e => e.into(),
+        })
+    }
+
+    /// Terms of `field` that are much more common among documents matching `q` than in the
+    /// whole index, scored with JLH: `(fg% - bg%) * fg% / bg%`. Terms found on fewer than
+    /// `min_doc_count` matching documents are ignored since tiny samples score erratically.
+    /// Restricted posts outside `groups` count in neither the matches nor the background.
+    pub fn significant_terms(
+        &self,
+        q: &str,
+        field: &str,
+        size: usize,
+        min_doc_count: u64,
+        groups: Option<&[String]>,
+    ) -> ServiceResult<SignificantTerms> {
+        let searcher = self.searcher();
+        let (field, field_type) = term_field(&searcher, field)?;
+        let limits = self.query_limits();
+        let filter = self.groups_filter(&searcher, groups)?;
+        let matched = matched_by_segment(&searcher, Some(q), &limits, filter.as_deref())?;
+        let fg_total: u64 = matched.iter().map(|m| m.iter().filter(|&&hit| hit).count() as u64).sum();
+        // The background is every post the caller may see
+        let visible = filter.as_deref().map(|f| matched_by_segment(&searcher, None, &limits, Some(f))).transpose()?;
+        let bg_total = match &visible {
+            Some(visible) => visible.iter().map(|v| v.iter().filter(|&&seen| seen).count() as u64).sum(),
+            None => searcher.search(&*posts_only(&searcher.index().schema(), Box::new(AllQuery)), &Count)? as u64,
+        };
+
+        let mut counts: HashMap<String, (u64, u64)> = HashMap::new();
+        for (ord, (segment, matched)) in searcher.segment_readers().iter().zip(&matched).enumerate() {
+            let alive = segment.alive_bitset();
+            let visible = visible.as_ref().map(|v| &v[ord]);
+            for_each_term(segment, field, &field_type, |value, postings| {
+                let (mut fg, mut bg) = (0, 0);
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    let counted = match visible {
+                        Some(visible) => visible[doc as usize],
+                        None => alive.is_none_or(|a| a.is_alive(doc)),
+                    };
+                    if counted {
+                        bg += 1;
+                        fg += u64::from(matched[doc as usize]);
+                    }
+                    doc = postings.advance();
+                }
+                if fg > 0 {
+                    let entry = counts.entry(value.to_string()).or_default();
+                    entry.0 += fg;
+                    entry.1 += bg;
+                }
+            })?;
+        }
+
+        let mut terms: Vec<SignificantTerm> = counts
+            .into_iter()
+            .filter(|(_, (fg, _))| *fg >= min_doc_count)
+            .map(|(term, (fg, bg))| {
+                let fg_pct = fg as f64 / fg_total.max(1) as f64;
+                let bg_pct = bg as f64 / bg_total.max(1) as f64;
+                SignificantTerm { term, doc_count: fg, bg_count: bg, score: (fg_pct - bg_pct) * fg_pct / bg_pct }
+            })
+            .filter(|t| t.score > 0.0)
+            .collect();
+        terms.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.term.cmp(&b.term)));
+        terms.truncate(size);
+        Ok(SignificantTerms { doc_count: fg_total, bg_count: bg_total, terms })
+    }
+}
+
+/// How many tags `expand` adds to a query.
+const EXPANSION_TERMS: usize = 3;
+
+impl SearchService {
+    /// Tags that co-occur unusually often with matches of `q`, for query expansion: the top
+    /// significant `tags` terms (on at least two matches) not already in the query, among the
+    /// posts `groups` may see.
+    pub fn expansion_terms(&self, q: &str, groups: Option<&[String]>) -> ServiceResult<Vec<String>> {
+        let lowered = q.to_lowercase();
+        let candidates = self.significant_terms(q, "tags", EXPANSION_TERMS * 2, 2, groups)?;
+        Ok(candidates
+            .terms
+            .into_iter()
+            .map(|t| t.term)
+            .filter(|t| !t.contains(['"', '\\']) && !lowered.contains(t.as_str()))
+            .take(EXPANSION_TERMS)
+            .collect())
+    }
+}
+
+/// Resolves an aggregatable field: indexed text/string or i64.
+pub(crate) fn term_field(searcher: &Searcher, name: &str) -> ServiceResult<(Field, FieldType)> {
+    let schema = searcher.index().schema();
+    let field = schema.get_field(name).map_err(|_| ServiceError::Invalid(format!("unknown field: {}", name)))?;
+    let field_type = schema.get_field_entry(field).field_type().clone();
+    let indexed = match &field_type {
+        FieldType::Str(options) => options.get_indexing_options().is_some(),
+        FieldType::I64(options) => options.is_indexed(),
+        _ => false,
+    };
+    if !indexed {
+        return Err(ServiceError::Invalid(format!("{} can't be aggregated: only indexed text and i64 fields", name)));
+    }
+    Ok((field, field_type))
+}
+
+/// Adds to `counts` how many of the documents `matched` carry each indexed term of every
+/// field in `fields`. Terms are decoded like [`composite`](SearchService::composite) keys, so
+/// `tags` are counted by lowercased word.
+pub(crate) fn count_facets(
+    searcher: &Searcher,
+    matched: &HashSet<DocAddress>,
+    fields: &[String],
+    counts: &mut FacetCounts,
+) -> ServiceResult<()> {
+    let mut by_segment: Vec<Vec<bool>> =
+        searcher.segment_readers().iter().map(|s| vec![false; s.max_doc() as usize]).collect();
+    for addr in matched {
+        by_segment[addr.segment_ord as usize][addr.doc_id as usize] = true;
+    }
+    for name in fields {
+        if RESTRICTED_FIELDS.contains(&name.as_str()) {
+            return Err(ServiceError::Invalid(format!("unknown field: {}", name)));
+        }
+        let (field, field_type) = term_field(searcher, name)?;
+        let counts = counts.entry(name.clone()).or_default();
+        for (segment, matched) in searcher.segment_readers().iter().zip(&by_segment) {
+            for_each_term(segment, field, &field_type, |value, postings| {
+                let mut count = 0;
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    count += u64::from(matched[doc as usize]);
+                    doc = postings.advance();
+                }
+                if count > 0 {
+                    *counts.entry(value.to_string()).or_default() += count;
+                }
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The `size` most common values of each facet, most common first and ties by value.
+pub(crate) fn top_facets(counts: FacetCounts, size: usize) -> BTreeMap<String, Vec<FacetValue>> {
+    counts
+        .into_iter()
+        .map(|(field, values)| {
+            let mut values: Vec<FacetValue> = values.into_iter().map(|(value, count)| FacetValue { value, count }).collect();
+            values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            values.truncate(size);
+            (field, values)
+        })
+        .collect()
+}
+
+/// The query of `q`, or every post when `None` or blank, restricted to `filter` if any.
+fn matching_query(
+    searcher: &Searcher,
+    q: Option<&str>,
+    limits: &QueryLimits,
+    filter: Option<&dyn Query>,
+) -> ServiceResult<Box<dyn Query>> {
+    let query = match q.filter(|q| !q.trim().is_empty()) {
+        Some(q) => parse_query(searcher, q, limits)?,
+        None => posts_only(&searcher.index().schema(), Box::new(AllQuery)),
+    };
+    Ok(match filter {
+        Some(filter) => filtered(query, filter),
+        None => query,
+    })
+}
+
+/// Per segment, which doc ids match `q` (all live documents when `None` or blank) and `filter`.
+pub(crate) fn matched_by_segment(
+    searcher: &Searcher,
+    q: Option<&str>,
+    limits: &QueryLimits,
+    filter: Option<&dyn Query>,
+) -> ServiceResult<Vec<Vec<bool>>> {
+    let query = matching_query(searcher, q, limits, filter)?;
+    let mut matched: Vec<Vec<bool>> =
+        searcher.segment_readers().iter().map(|s| vec![false; s.max_doc() as usize]).collect();
+    for addr in searcher.search(query.as_ref(), &DocSetCollector)? {
+        matched[addr.segment_ord as usize][addr.doc_id as usize] = true;
+    }
+    Ok(matched)
+}
+
+/// The indexed terms of `field` for every document flagged in `wanted`, decoded for display
+/// (text as is, i64 as decimal). Walks the term dictionary once.
+pub(crate) fn terms_by_doc(
+    segment: &SegmentReader,
+    field: Field,
+    field_type: &FieldType,
+    wanted: &[bool],
+) -> ServiceResult<Vec<Vec<String>>> {
+    let mut values = vec![Vec::new(); segment.max_doc() as usize];
+    for_each_term(segment, field, field_type, |value, postings| {
+        let mut doc = postings.doc();
+        while doc != TERMINATED {
+            if wanted[doc as usize] {
+                values[doc as usize].push(value.to_string());
+            }
+            doc = postings.advance();
+        }
+    })?;
+    Ok(values)
+}
+
+/// Calls `f` with every decoded term of `field` in `segment` and its postings.
+fn for_each_term(
+    segment: &SegmentReader,
+    field: Field,
+    field_type: &FieldType,
+    mut f: impl FnMut(&str, &mut SegmentPostings),
+) -> ServiceResult<()> {
+    let inverted = segment.inverted_index(field)?;
+    let mut terms = inverted.terms().stream()?;
+    while terms.advance() {
+        let value = match field_type {
+            FieldType::I64(_) => match <[u8; 8]>::try_from(terms.key()) {
+                Ok(bytes) => u64_to_i64(u64::from_be_bytes(bytes)).to_string(),
+                Err(_) => continue,
+            },
+            _ => String::from_utf8_lossy(terms.key()).into_owned(),
+        };
+        let mut postings = inverted.read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)?;
+        f(&value, &mut postings);
+    }
+    Ok(())
+}
+
+/// Every key combining one value per source; empty when any source has no value.
+fn cartesian<'a>(sources: impl Iterator<Item = &'a [String]>) -> Vec<Vec<String>> {
+    let mut keys = vec![Vec::new()];
+    for values in sources {
+        keys = keys
+            .into_iter()
+            .flat_map(|key: Vec<String>| {
+                values.iter().map(move |v| {
+                    let mut key = key.clone();
+                    key.push(v.clone());
+                    key
+                })
+            })
+            .collect();
+    }
+    keys
+}
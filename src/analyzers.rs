@@ -0,0 +1,440 @@
+//! The analyzer registry: every analyzer the indexes know, described as a tokenizer and a
+//! chain of token filters, and the custom ones registered at runtime with `POST /analyzers`.
+//!
+//! The built-in analyzers are the ones the schemas refer to. Custom analyzers are registered on
+//! the tokenizer managers of every index the service opens and persisted to a JSON file, read
+//! back on startup, so a field or index provisioned later can name one without a code change.
+//! An analyzer can't be redefined once registered: terms already indexed with it would stop
+//! matching what queries analyze into.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use tantivy::schema::{FieldType, Schema};
+use tantivy::tokenizer::{
+    AlphaNumOnlyFilter, AsciiFoldingFilter, Language, LowerCaser, NgramTokenizer, RawTokenizer, RegexTokenizer, RemoveLongFilter,
+    SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer, TextAnalyzerBuilder, Tokenizer, TokenizerManager, WhitespaceTokenizer,
+};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::filters::{CharFilter, CharFilteredTokenizer, CodeTokenizer, EmojiPolicy, NfkcFilter, NumericFilter, PathHierarchyTokenizer, ShingleFilter};
+use crate::service::SearchService;
+use crate::storage::write_durably;
+
+/// Longest analyzer name accepted.
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenizerSpec {
+    /// Splits on anything but letters and digits
+    Simple,
+    /// Splits on whitespace
+    Whitespace,
+    /// The whole text as one token
+    Raw,
+    /// Character n-grams of `min_gram..=max_gram` characters, only the word's leading ones
+    /// with `prefix_only`
+    Ngram { min_gram: usize, max_gram: usize, #[serde(default)] prefix_only: bool },
+    /// Each match of `pattern` is a token
+    Regex { pattern: String },
+    /// Identifiers split at camelCase and snake_case with the whole identifier kept, generic
+    /// types and operators as tokens (see [`CodeTokenizer`])
+    Code,
+    /// A path and each of its ancestors, split at `delimiter` (see [`PathHierarchyTokenizer`])
+    PathHierarchy {
+        #[serde(default = "default_path_delimiter")]
+        delimiter: char,
+    },
+}
+
+fn default_path_delimiter() -> char {
+    '/'
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterSpec {
+    Lowercase,
+    /// Folds accented and other non-ASCII Latin characters to ASCII (`café` → `cafe`)
+    AsciiFolding,
+    /// Unicode NFKC normalization, e.g. full-width `ＲＵＳＴ` → `RUST` (see [`NfkcFilter`])
+    Nfkc,
+    /// Drops tokens with anything but ASCII letters and digits
+    AlphaNumOnly,
+    /// Drops tokens of `limit` bytes or more
+    RemoveLong { limit: usize },
+    /// Drops `words`, or the stop words of `language`
+    StopWords {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<Language>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        words: Vec<String>,
+    },
+    /// Snowball stemming for `language`
+    Stemmer { language: Language },
+    /// Number and version formatting normalized, e.g. `007` → `7`, `v1.02.3` → `1.2.3` (see
+    /// [`NumericFilter`])
+    Numeric {
+        #[serde(default = "default_true")]
+        strip_leading_zeros: bool,
+        #[serde(default = "default_true")]
+        strip_grouping: bool,
+        #[serde(default)]
+        trim_trailing_zeros: bool,
+    },
+    /// Word n-grams of `min_size..=max_size` words (see [`ShingleFilter`])
+    Shingle {
+        #[serde(default = "default_min_shingle")]
+        min_size: usize,
+        #[serde(default = "default_max_shingle")]
+        max_size: usize,
+        #[serde(default = "default_shingle_separator")]
+        separator: String,
+        #[serde(default)]
+        output_unigrams: bool,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Words, with digits joined across `.` and `,` so numbers and versions are single tokens.
+const NUMERIC_WORDS: &str = r"[\p{L}\p{N}_]+(?:[.,]\p{N}+)*";
+
+/// Longest shingle accepted, in words.
+const MAX_SHINGLE_SIZE: usize = 5;
+
+fn default_min_shingle() -> usize {
+    2
+}
+
+fn default_max_shingle() -> usize {
+    3
+}
+
+fn default_shingle_separator() -> String {
+    " ".to_string()
+}
+
+/// A rewrite of the text before it is tokenized.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CharFilterSpec {
+    /// Decodes HTML entities: `&amp;`, `&eacute;`, `&#233;`, `&#xE9;`
+    HtmlEntities,
+    /// Replaces matches of `pattern`; `replacement` may refer to groups as `$1` or `${name}`
+    PatternReplace { pattern: String, #[serde(default)] replacement: String },
+    /// Replaces each key with its value, the longest key winning, e.g. `{"C++": "cpp"}`
+    Mapping { mappings: BTreeMap<String, String> },
+    /// Unicode NFKC normalization ahead of the tokenizer, which n-gram tokenizers need (see
+    /// [`CharFilter::Nfkc`])
+    Nfkc,
+}
+
+impl CharFilterSpec {
+    fn build(&self) -> Result<CharFilter, String> {
+        Ok(match self {
+            CharFilterSpec::HtmlEntities => CharFilter::HtmlEntities,
+            CharFilterSpec::PatternReplace { pattern, replacement } => {
+                let regex = regex::Regex::new(pattern).map_err(|e| format!("pattern_replace: {}", e))?;
+                CharFilter::PatternReplace { regex, replacement: replacement.clone() }
+            }
+            CharFilterSpec::Mapping { mappings } => {
+                if mappings.keys().any(String::is_empty) {
+                    return Err("mapping: keys must not be empty".to_string());
+                }
+                CharFilter::Mapping { mappings: mappings.iter().map(|(k, v)| (k.clone(), v.clone())).collect() }
+            }
+            CharFilterSpec::Nfkc => CharFilter::Nfkc,
+        })
+    }
+}
+
+/// A named tokenizer and its filters, applied in order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnalyzerSpec {
+    pub name: String,
+    /// Run over the text in order before it is tokenized; token offsets still point into the
+    /// original text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub char_filters: Vec<CharFilterSpec>,
+    pub tokenizer: TokenizerSpec,
+    #[serde(default)]
+    pub filters: Vec<FilterSpec>,
+    /// What happens to emoji and pictographic symbols before tokenizing, after the char
+    /// filters; they are tokenized like any other character when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<EmojiPolicy>,
+    /// Names used by `emoji: name` ahead of the built-in ones, e.g. `{"🦀": "rustacean"}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub emoji_names: BTreeMap<String, String>,
+}
+
+impl AnalyzerSpec {
+    fn new(name: &str, tokenizer: TokenizerSpec, filters: Vec<FilterSpec>) -> Self {
+        AnalyzerSpec { name: name.to_string(), char_filters: Vec::new(), tokenizer, filters, emoji: None, emoji_names: BTreeMap::new() }
+    }
+
+    /// `tokenizer` behind the char filters and emoji handling, if any.
+    fn start<T: Tokenizer>(&self, tokenizer: T) -> Result<TextAnalyzerBuilder, String> {
+        let mut filters = self.char_filters.iter().map(CharFilterSpec::build).collect::<Result<Vec<_>, _>>()?;
+        if let Some(policy) = self.emoji {
+            filters.push(CharFilter::Emoji { policy, names: self.emoji_names.clone() });
+        }
+        Ok(match filters.is_empty() {
+            true => TextAnalyzer::builder(tokenizer).dynamic(),
+            false => TextAnalyzer::builder(CharFilteredTokenizer::new(tokenizer, filters)).dynamic(),
+        })
+    }
+
+    /// The analyzer this describes; fails on settings the tokenizer or a filter rejects.
+    pub fn build(&self) -> Result<TextAnalyzer, String> {
+        if !self.emoji_names.is_empty() && self.emoji != Some(EmojiPolicy::Name) {
+            return Err("emoji_names only apply with emoji: name".to_string());
+        }
+        let mut builder = match &self.tokenizer {
+            TokenizerSpec::Simple => self.start(SimpleTokenizer::default())?,
+            TokenizerSpec::Whitespace => self.start(WhitespaceTokenizer::default())?,
+            TokenizerSpec::Raw => self.start(RawTokenizer::default())?,
+            TokenizerSpec::Ngram { min_gram, max_gram, prefix_only } => {
+                let ngram = NgramTokenizer::new(*min_gram, *max_gram, *prefix_only).map_err(|e| format!("ngram: {}", e))?;
+                self.start(ngram)?
+            }
+            TokenizerSpec::Regex { pattern } => {
+                let regex = RegexTokenizer::new(pattern).map_err(|e| format!("regex: {}", e))?;
+                self.start(regex)?
+            }
+            TokenizerSpec::Code => self.start(CodeTokenizer)?,
+            TokenizerSpec::PathHierarchy { delimiter } => self.start(PathHierarchyTokenizer { delimiter: *delimiter })?,
+        };
+        for filter in &self.filters {
+            builder = match filter {
+                FilterSpec::Lowercase => builder.filter_dynamic(LowerCaser),
+                FilterSpec::AsciiFolding => builder.filter_dynamic(AsciiFoldingFilter),
+                FilterSpec::Nfkc => builder.filter_dynamic(NfkcFilter),
+                FilterSpec::AlphaNumOnly => builder.filter_dynamic(AlphaNumOnlyFilter),
+                FilterSpec::RemoveLong { limit } => builder.filter_dynamic(RemoveLongFilter::limit(*limit)),
+                FilterSpec::StopWords { language, words } => {
+                    let filter = match (language, words.is_empty()) {
+                        (None, false) => StopWordFilter::remove(words.iter().map(|w| w.to_lowercase())),
+                        (None, true) => return Err("stop_words needs a language or words".to_string()),
+                        (Some(language), true) => {
+                            StopWordFilter::new(*language).ok_or_else(|| format!("stop_words: no stop word list for {:?}", language))?
+                        }
+                        (Some(_), false) => return Err("stop_words takes a language or words, not both".to_string()),
+                    };
+                    builder.filter_dynamic(filter)
+                }
+                FilterSpec::Stemmer { language } => builder.filter_dynamic(Stemmer::new(*language)),
+                FilterSpec::Numeric { strip_leading_zeros, strip_grouping, trim_trailing_zeros } => {
+                    let numeric = NumericFilter {
+                        strip_leading_zeros: *strip_leading_zeros,
+                        strip_grouping: *strip_grouping,
+                        trim_trailing_zeros: *trim_trailing_zeros,
+                    };
+                    builder.filter_dynamic(numeric)
+                }
+                FilterSpec::Shingle { min_size, max_size, separator, output_unigrams } => {
+                    if !(2..=MAX_SHINGLE_SIZE).contains(min_size) || !(*min_size..=MAX_SHINGLE_SIZE).contains(max_size) {
+                        return Err(format!("shingle: sizes must satisfy 2 <= min_size <= max_size <= {}", MAX_SHINGLE_SIZE));
+                    }
+                    let shingle = ShingleFilter { min_size: *min_size, max_size: *max_size, separator: separator.clone(), output_unigrams: *output_unigrams };
+                    builder.filter_dynamic(shingle)
+                }
+            };
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The analyzers every index is opened with: tantivy's own (`default`, `raw`, `en_stem`,
+/// `whitespace`) and the ones the schemas use.
+pub fn builtin_analyzers() -> Vec<AnalyzerSpec> {
+    use FilterSpec::*;
+    vec![
+        AnalyzerSpec::new("default", TokenizerSpec::Simple, vec![RemoveLong { limit: 40 }, Lowercase]),
+        AnalyzerSpec::new("raw", TokenizerSpec::Raw, Vec::new()),
+        AnalyzerSpec::new("en_stem", TokenizerSpec::Simple, vec![RemoveLong { limit: 40 }, Lowercase, Stemmer { language: Language::English }]),
+        AnalyzerSpec::new("whitespace", TokenizerSpec::Whitespace, Vec::new()),
+        // Character bigrams/trigrams for CJK-friendly search
+        AnalyzerSpec::new("zh_ngram", TokenizerSpec::Ngram { min_gram: 2, max_gram: 3, prefix_only: false }, vec![Lowercase]),
+        // The same with width, compatibility forms and accents folded, so `café`/`cafe` and
+        // full-/half-width forms match each other, and emoji kept out of the n-grams. NFKC
+        // runs before the n-grams cut the text, which would split what it joins
+        AnalyzerSpec {
+            char_filters: vec![CharFilterSpec::Nfkc],
+            emoji: Some(EmojiPolicy::Keep),
+            ..AnalyzerSpec::new("zh_ngram_folded", TokenizerSpec::Ngram { min_gram: 2, max_gram: 3, prefix_only: false }, vec![Lowercase, AsciiFolding])
+        },
+        // Tags: whitespace + lowercasing
+        AnalyzerSpec::new("whitespace_lc", TokenizerSpec::Whitespace, vec![Lowercase]),
+        // Programming content: identifiers split into words, operators and generics kept
+        AnalyzerSpec::new("code", TokenizerSpec::Code, vec![RemoveLong { limit: 100 }, Lowercase]),
+        // Words plus numbers and versions kept whole (`1,000`, `v1.2.3`) and normalized, e.g.
+        // for `features` values
+        AnalyzerSpec::new(
+            "numeric",
+            TokenizerSpec::Regex { pattern: NUMERIC_WORDS.to_string() },
+            vec![
+                RemoveLong { limit: 40 },
+                Lowercase,
+                Numeric { strip_leading_zeros: true, strip_grouping: true, trim_trailing_zeros: false },
+            ],
+        ),
+        // File paths and URLs under `paths`, matched with their ancestors
+        AnalyzerSpec::new("path_hierarchy", TokenizerSpec::PathHierarchy { delimiter: '/' }, vec![]),
+        // Two- and three-word shingles of title and body, for `shingle_boost`
+        AnalyzerSpec::new(
+            "shingles",
+            TokenizerSpec::Simple,
+            vec![RemoveLong { limit: 40 }, Lowercase, Shingle { min_size: 2, max_size: 3, separator: " ".to_string(), output_unigrams: false }],
+        ),
+    ]
+}
+
+/// Reads a JSON array of analyzer specs.
+pub fn load_analyzers(path: &PathBuf) -> anyhow::Result<Vec<AnalyzerSpec>> {
+    let specs: Vec<AnalyzerSpec> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    for spec in &specs {
+        check_name(&spec.name).map_err(anyhow::Error::msg)?;
+        spec.build().map_err(|e| anyhow::anyhow!("analyzer {}: {}", spec.name, e))?;
+    }
+    Ok(specs)
+}
+
+pub(crate) fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty() && name.len() <= MAX_NAME_LEN && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    if !valid {
+        return Err(format!("analyzer names are 1 to {} of a-z, 0-9 and _, got {:?}", MAX_NAME_LEN, name));
+    }
+    if builtin_analyzers().iter().any(|b| b.name == name) {
+        return Err(format!("{} is a built-in analyzer", name));
+    }
+    Ok(())
+}
+
+/// One analyzer as `GET /analyzers` lists it.
+#[derive(Serialize, Debug, Clone)]
+pub struct AnalyzerInfo {
+    #[serde(flatten)]
+    pub spec: AnalyzerSpec,
+    pub builtin: bool,
+    /// Fields indexed with it, as `<index>.<field>`
+    pub fields: Vec<String>,
+}
+
+pub struct AnalyzerRegistry {
+    custom: RwLock<BTreeMap<String, AnalyzerSpec>>,
+    /// Where custom analyzers are persisted; `None` keeps them in memory
+    path: Option<PathBuf>,
+    /// Tokenizer managers of the indexes the custom analyzers are registered on
+    managers: Vec<TokenizerManager>,
+}
+
+impl AnalyzerRegistry {
+    /// Registers the custom analyzers persisted at `path`, if it exists, on `managers`.
+    pub fn open(path: Option<PathBuf>, managers: Vec<TokenizerManager>) -> anyhow::Result<Self> {
+        let specs = match &path {
+            Some(p) if p.exists() => load_analyzers(p)?,
+            _ => Vec::new(),
+        };
+        let registry = AnalyzerRegistry { custom: RwLock::new(BTreeMap::new()), path, managers };
+        let mut custom = registry.custom.write().unwrap_or_else(|p| p.into_inner());
+        for spec in specs {
+            registry.install(&spec).map_err(anyhow::Error::msg)?;
+            custom.insert(spec.name.clone(), spec);
+        }
+        drop(custom);
+        Ok(registry)
+    }
+
+    fn install(&self, spec: &AnalyzerSpec) -> Result<(), String> {
+        let analyzer = spec.build()?;
+        for manager in &self.managers {
+            manager.register(&spec.name, analyzer.clone());
+        }
+        Ok(())
+    }
+
+    /// Registers the custom analyzers on `manager`, of an index opened after the registry.
+    /// Fails on one that no longer builds rather than leave its fields without an analyzer.
+    pub fn install_custom(&self, manager: &TokenizerManager) -> Result<(), String> {
+        for spec in self.custom() {
+            let analyzer = spec.build().map_err(|e| format!("analyzer {}: {}", spec.name, e))?;
+            manager.register(&spec.name, analyzer);
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is a built-in or custom analyzer.
+    pub fn is_known(&self, name: &str) -> bool {
+        builtin_analyzers().iter().any(|a| a.name == name) || self.custom.read().unwrap_or_else(|p| p.into_inner()).contains_key(name)
+    }
+
+    pub fn custom(&self) -> Vec<AnalyzerSpec> {
+        self.custom.read().unwrap_or_else(|p| p.into_inner()).values().cloned().collect()
+    }
+
+    /// Registers `spec` on every index and persists it. Registering an identical spec again
+    /// is a no-op.
+    pub fn register(&self, spec: AnalyzerSpec) -> ServiceResult<()> {
+        check_name(&spec.name).map_err(ServiceError::Invalid)?;
+        spec.build().map_err(|e| ServiceError::Invalid(format!("analyzer {}: {}", spec.name, e)))?;
+        let mut custom = self.custom.write().unwrap_or_else(|p| p.into_inner());
+        match custom.get(&spec.name) {
+            Some(existing) if *existing == spec => return Ok(()),
+            Some(_) => return Err(ServiceError::Invalid(format!("analyzer {} is already registered with another definition", spec.name))),
+            None => {}
+        }
+        if let Some(path) = &self.path {
+            let mut specs: Vec<&AnalyzerSpec> = custom.values().collect();
+            specs.push(&spec);
+            let json = serde_json::to_string_pretty(&specs).map_err(|e| ServiceError::Internal(e.to_string()))?;
+            write_durably(path, json.as_bytes())?;
+        }
+        self.install(&spec).map_err(ServiceError::Invalid)?;
+        custom.insert(spec.name.clone(), spec);
+        Ok(())
+    }
+}
+
+/// `(<index>.<field>, analyzer)` for every analyzed field of `schema`.
+fn field_analyzers<'a>(index: &'a str, schema: &'a Schema) -> impl Iterator<Item = (String, String)> + 'a {
+    schema.fields().filter_map(move |(_, entry)| {
+        let indexing = match entry.field_type() {
+            FieldType::Str(options) => options.get_indexing_options(),
+            FieldType::JsonObject(options) => options.get_text_indexing_options(),
+            _ => None,
+        }?;
+        Some((format!("{}.{}", index, entry.name()), indexing.tokenizer().to_string()))
+    })
+}
+
+impl SearchService {
+    /// Built-in and custom analyzers with the fields using them.
+    pub fn analyzers(&self) -> Vec<AnalyzerInfo> {
+        let mut schemas = vec![
+            ("posts".to_string(), self.schema()),
+            ("comments".to_string(), self.comments.searcher().index().schema()),
+            ("authors".to_string(), self.authors.current_searcher.load().index().schema()),
+        ];
+        schemas.extend(self.indexes.list().iter().map(|index| (index.name.clone(), index.searcher().index().schema())));
+        let used: Vec<(String, String)> = schemas.iter().flat_map(|(index, schema)| field_analyzers(index, schema).collect::<Vec<_>>()).collect();
+        let info = |spec: AnalyzerSpec, builtin| {
+            let fields = used.iter().filter(|(_, a)| *a == spec.name).map(|(f, _)| f.clone()).collect();
+            AnalyzerInfo { spec, builtin, fields }
+        };
+        let mut all: Vec<AnalyzerInfo> = builtin_analyzers().into_iter().map(|s| info(s, true)).collect();
+        all.extend(self.analyzers.custom().into_iter().map(|s| info(s, false)));
+        all
+    }
+
+    /// Registers a custom analyzer on every index (see [`AnalyzerRegistry::register`]).
+    pub fn register_analyzer(&self, spec: AnalyzerSpec) -> ServiceResult<()> {
+        self.analyzers.register(spec)
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+use tantivy::collector::DocSetCollector;
+use tantivy::query::Query;
+use tantivy::schema::{Schema, Value};
+use tantivy::store::{Compressor, ZstdCompressor};
+use tantivy::{IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term};
+
+use crate::nested::{add_block, blocks, WithChildrenQuery};
+use crate::schema::{add_shingles, open_index};
+
+/// Indexing memory of the archive writer, which only takes retention moves and deletes.
+pub const WRITER_HEAP_BYTES: usize = 15_000_000;
+
+/// Cold tier holding documents moved out of the hot index by `archive` retention rules. It
+/// uses a zstd-compressed doc store and is only searched when a request sets `include_archive`.
+pub struct ArchiveTier {
+    pub writer: Mutex<IndexWriter>,
+    pub reader: ArcSwap<IndexReader>,
+    pub current_searcher: ArcSwap<Searcher>,
+}
+
+impl ArchiveTier {
+    pub fn open(path: &PathBuf, schema: Schema, in_memory: bool) -> anyhow::Result<Self> {
+        let index = open_index(path, schema, Self::settings(), in_memory)?;
+        let writer = index.writer(WRITER_HEAP_BYTES)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let current_searcher = ArcSwap::from_pointee(reader.searcher());
+        Ok(ArchiveTier { writer: Mutex::new(writer), reader: ArcSwap::from_pointee(reader), current_searcher })
+    }
+
+    /// Settings archive indexes are created with.
+    pub fn settings() -> IndexSettings {
+        IndexSettings {
+            docstore_compression: Compressor::Zstd(ZstdCompressor { compression_level: Some(9) }),
+            docstore_blocksize: 64 * 1024,
+            ..IndexSettings::default()
+        }
+    }
+
+    pub fn writer(&self) -> std::sync::MutexGuard<'_, IndexWriter> {
+        match self.writer.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    /// Commits, reloads and swaps in a fresh archive searcher.
+    pub fn refresh(&self) -> tantivy::Result<()> {
+        self.writer().commit()?;
+        let reader = self.reader.load();
+        reader.reload()?;
+        self.current_searcher.store(Arc::new(reader.searcher()));
+        Ok(())
+    }
+
+    /// Copies every hot document matching `query`, with its nested children, into the archive
+    /// and commits it. Archived copies are keyed by id, so re-running before the hot deletes are
+    /// committed is harmless.
+    pub fn archive_matches(&self, searcher: &Searcher, query: &dyn Query) -> tantivy::Result<()> {
+        let schema = searcher.index().schema();
+        let f_id = schema.get_field("id").unwrap();
+        let with_children = WithChildrenQuery { parent: query.box_clone() };
+        let addrs = searcher.search(&with_children, &DocSetCollector)?;
+        let mut writer = self.writer();
+        for block in blocks(searcher, addrs.into_iter().collect())? {
+            let mut docs = block.into_iter().map(|addr| searcher.doc(addr)).collect::<tantivy::Result<Vec<TantivyDocument>>>()?;
+            docs.iter_mut().for_each(|doc| add_shingles(&schema, doc));
+            if let Some(id) = docs.last().and_then(|doc| doc.get_first(f_id)).and_then(|v| v.as_str()) {
+                writer.delete_term(Term::from_field_text(f_id, id));
+            }
+            add_block(&writer, docs)?;
+        }
+        writer.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Per-document access control: posts listing `allowed_groups` are only visible to callers
+//! in one of those groups, posts without any stay public. Caller groups come from the claims
+//! of HS256-signed JWTs. Admin endpoints take an [`AdminKey`] or a JWT group of their own.
+
+use std::collections::BTreeSet;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use tantivy::query::{AllQuery, BooleanQuery, ExistsQuery, Occur, Query};
+use tantivy::schema::Schema;
+
+use crate::comments::ids_query;
+use crate::now_secs;
+
+/// Posts visible to a caller in `groups`: public ones plus those sharing a group with the
+/// caller. Indexes created before ACLs existed have no restricted posts.
+pub fn visible_to(schema: &Schema, groups: &[String]) -> Box<dyn Query> {
+    let Ok(f_groups) = schema.get_field("allowed_groups") else { return Box::new(AllQuery) };
+    let restricted: Box<dyn Query> = Box::new(ExistsQuery::new_exists_query("allowed_groups".to_string()));
+    let public: Box<dyn Query> = Box::new(BooleanQuery::new(vec![(Occur::Must, Box::new(AllQuery)), (Occur::MustNot, restricted)]));
+    let groups: BTreeSet<String> = groups.iter().cloned().collect();
+    Box::new(BooleanQuery::new(vec![(Occur::Should, public), (Occur::Should, ids_query(f_groups, &groups))]))
+}
+
+/// Header carrying the admin key.
+pub const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+
+/// The shared key admin callers send as [`ADMIN_KEY_HEADER`]. Keys are compared through HMACs
+/// under them, in constant time, so response timing doesn't tell how much of a guess was right.
+pub struct AdminKey {
+    key: hmac::Key,
+}
+
+impl AdminKey {
+    pub fn new(secret: &str) -> Self {
+        AdminKey { key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()) }
+    }
+
+    pub fn matches(&self, sent: &str) -> bool {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, sent.as_bytes()), ADMIN_KEY_HEADER.as_bytes());
+        hmac::verify(&self.key, ADMIN_KEY_HEADER.as_bytes(), tag.as_ref()).is_ok()
+    }
+}
+
+/// Verifies `Authorization: Bearer` tokens signed with a shared secret and reads the caller's
+/// groups from one claim.
+pub struct JwtVerifier {
+    key: hmac::Key,
+    /// Claim holding the groups: an array of strings, or one string
+    pub groups_claim: String,
+}
+
+impl JwtVerifier {
+    pub fn new(secret: &[u8], groups_claim: impl Into<String>) -> Self {
+        JwtVerifier { key: hmac::Key::new(hmac::HMAC_SHA256, secret), groups_claim: groups_claim.into() }
+    }
+
+    /// The groups of a valid, unexpired token. Only `HS256` is accepted.
+    pub fn groups(&self, token: &str) -> Result<Vec<String>, String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed token".to_string());
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| "malformed token".to_string());
+        let header: serde_json::Value = serde_json::from_slice(&decode(header)?).map_err(|_| "malformed token header")?;
+        if header["alg"] != "HS256" {
+            return Err("unsupported token algorithm".to_string());
+        }
+        let signed = &token[..token.len() - signature.len() - 1];
+        hmac::verify(&self.key, signed.as_bytes(), &decode(signature)?).map_err(|_| "invalid token signature")?;
+
+        let claims: serde_json::Value = serde_json::from_slice(&decode(payload)?).map_err(|_| "malformed token claims")?;
+        if claims["exp"].as_i64().is_some_and(|exp| exp <= now_secs()) {
+            return Err("token expired".to_string());
+        }
+        Ok(match &claims[&self.groups_claim] {
+            serde_json::Value::Array(groups) => groups.iter().filter_map(|g| g.as_str().map(str::to_string)).collect(),
+            serde_json::Value::String(group) => vec![group.clone()],
+            _ => Vec::new(),
+        })
+    }
+}
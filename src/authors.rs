@@ -0,0 +1,120 @@
+//! Author profiles in a secondary index, looked up by id to decorate post hits so clients
+//! don't fetch every hit's author from another service.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tantivy::collector::DocSetCollector;
+use tantivy::schema::{Schema, Value, STORED, STRING, TEXT};
+use tantivy::{IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term};
+
+use crate::comments::ids_query;
+use crate::error::ServiceResult;
+use crate::schema::open_index;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Author {
+    pub id: String,
+    pub name: String,
+    /// Avatar image URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+impl Author {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub fn author_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_text_field("id", STRING | STORED);
+    schema_builder.add_text_field("name", TEXT | STORED);
+    schema_builder.add_text_field("avatar", STORED);
+    schema_builder.build()
+}
+
+fn to_document(schema: &Schema, author: Author) -> TantivyDocument {
+    let mut document = TantivyDocument::default();
+    document.add_text(schema.get_field("id").unwrap(), author.id);
+    document.add_text(schema.get_field("name").unwrap(), author.name);
+    if let Some(avatar) = author.avatar {
+        document.add_text(schema.get_field("avatar").unwrap(), avatar);
+    }
+    document
+}
+
+fn from_document(schema: &Schema, doc: &TantivyDocument) -> Author {
+    let text = |name: &str| doc.get_first(schema.get_field(name).unwrap()).and_then(|v| v.as_str()).map(str::to_string);
+    Author { id: text("id").unwrap_or_default(), name: text("name").unwrap_or_default(), avatar: text("avatar") }
+}
+
+/// The authors index. Committed and swapped with the posts on every
+/// [`SearchService::refresh`](crate::SearchService::refresh).
+pub struct AuthorStore {
+    pub writer: Mutex<IndexWriter>,
+    pub reader: IndexReader,
+    pub current_searcher: ArcSwap<Searcher>,
+}
+
+impl AuthorStore {
+    pub fn open(path: &PathBuf, in_memory: bool) -> anyhow::Result<Self> {
+        let index = open_index(path, author_schema(), IndexSettings::default(), in_memory)?;
+        let writer = index.writer(15_000_000)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let current_searcher = ArcSwap::from_pointee(reader.searcher());
+        Ok(AuthorStore { writer: Mutex::new(writer), reader, current_searcher })
+    }
+
+    pub fn writer(&self) -> MutexGuard<'_, IndexWriter> {
+        match self.writer.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    /// Commits, reloads and swaps in a fresh authors searcher.
+    pub fn refresh(&self) -> tantivy::Result<()> {
+        self.writer().commit()?;
+        self.reader.reload()?;
+        self.current_searcher.store(Arc::new(self.reader.searcher()));
+        Ok(())
+    }
+
+    /// Adds `author`, replacing any profile with the same id.
+    pub fn upsert(&self, author: Author) -> tantivy::Result<u64> {
+        let writer = self.writer();
+        let schema = writer.index().schema();
+        writer.delete_term(Term::from_field_text(schema.get_field("id").unwrap(), &author.id));
+        writer.add_document(to_document(&schema, author))
+    }
+
+    pub fn delete(&self, id: &str) -> u64 {
+        let writer = self.writer();
+        let f_id = writer.index().schema().get_field("id").unwrap();
+        writer.delete_term(Term::from_field_text(f_id, id))
+    }
+
+    /// Profiles of `ids` in one term-set query; unknown ids are left out.
+    pub fn lookup(&self, ids: &BTreeSet<String>) -> ServiceResult<HashMap<String, Author>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let searcher = self.current_searcher.load();
+        let schema = searcher.index().schema();
+        let query = ids_query(schema.get_field("id").unwrap(), ids);
+        let mut authors = HashMap::with_capacity(ids.len());
+        for addr in searcher.search(query.as_ref(), &DocSetCollector)? {
+            let author = from_document(&schema, &searcher.doc(addr)?);
+            authors.insert(author.id.clone(), author);
+        }
+        Ok(authors)
+    }
+}
@@ -0,0 +1,66 @@
+//! Mixed index/update/delete operations applied under one commit.
+
+use serde::{Deserialize, Serialize};
+use tantivy::indexer::UserOperation;
+use tantivy::schema::Schema;
+use tantivy::{IndexWriter, Term};
+
+use crate::schema::{post_block, BlogPost, IngestPipeline};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOp {
+    /// Replaces a post with the same id, like `/index` with its default `op_type=upsert`, so
+    /// ids stay unique whichever op wrote them
+    Index { doc: BlogPost },
+    Update { doc: BlogPost },
+    Delete { id: String },
+}
+
+impl BatchOp {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            BatchOp::Index { doc } | BatchOp::Update { doc } => doc.validate(),
+            BatchOp::Delete { id } if id.trim().is_empty() => Err("id must not be empty".to_string()),
+            BatchOp::Delete { .. } => Ok(()),
+        }
+    }
+}
+
+/// What `/index` does when a post with the same id exists: `upsert` (the default) replaces it,
+/// so the id behaves as a primary key; `create` refuses it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpType {
+    Create,
+    #[default]
+    Upsert,
+}
+
+pub fn validate_batch(ops: &[BatchOp]) -> Result<(), String> {
+    for (pos, op) in ops.iter().enumerate() {
+        op.validate().map_err(|e| format!("operation {}: {}", pos, e))?;
+    }
+    Ok(())
+}
+
+/// Applies `ops` in order as one group of writer operations, all or nothing: every document is
+/// built first, so a post that can't be indexed fails the batch before the writer sees any of
+/// it. Nothing is committed; with the writer held throughout, the next commit takes in the
+/// whole batch. Returns the batch's opstamp, past those of all its operations.
+pub fn apply_batch(writer: &IndexWriter, schema: &Schema, pipeline: &IngestPipeline, ops: Vec<BatchOp>) -> tantivy::Result<u64> {
+    let f_id = schema.get_field("id").unwrap();
+    let mut operations = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            BatchOp::Index { doc } | BatchOp::Update { doc } => {
+                operations.push(UserOperation::Delete(Term::from_field_text(f_id, &doc.id)));
+                operations.extend(post_block(schema, pipeline, doc)?.into_iter().map(UserOperation::Add));
+            }
+            BatchOp::Delete { id } => {
+                operations.push(UserOperation::Delete(Term::from_field_text(f_id, &id)));
+            }
+        }
+    }
+    writer.run(operations)
+}
@@ -1,17 +1,33 @@
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
 use rand::distributions::{Alphanumeric, DistString};
 use rand::{seq::SliceRandom, Rng};
-use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tantivy::IndexSettings;
+use tantivy_demo::batch::apply_batch;
+use tantivy_demo::client::{ClientError, RetryPolicy, TantivyDemoClient};
+use tantivy_demo::schema::{open_or_create_index, posts_schema, IngestPipeline};
+use tantivy_demo::{BatchOp, BlogPost};
 use tokio::sync::Semaphore;
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    /// Only new documents
+    Append,
+    /// New documents mixed with updates and deletes of those created so far
+    Churn,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(name = "generate", about = "Generate and index a large number of documents")] 
 pub struct Opts {
+    /// Operations to send (documents, for the append workload)
     #[arg(long, default_value_t = 1000)]
     pub count: usize,
 
@@ -20,77 +36,299 @@ pub struct Opts {
 
     #[arg(long, default_value = "http://127.0.0.1:8080")]
     pub endpoint: String,
+
+    #[arg(long, value_enum, default_value_t = Workload::Append)]
+    pub workload: Workload,
+
+    /// Churn: share of operations replacing an existing document
+    #[arg(long, default_value_t = 0.3)]
+    pub update_ratio: f64,
+
+    /// Churn: share of operations deleting an existing document; the rest create new ones
+    #[arg(long, default_value_t = 0.1)]
+    pub delete_ratio: f64,
+
+    /// Write straight into the index at this path instead of through a server, with the
+    /// server's default schema and analyzers; the server must not have the index open
+    #[arg(long)]
+    pub direct: Option<PathBuf>,
+
+    /// Checkpoint file: a run picks up where the one saving it stopped, with the same document
+    /// ids, and saves its own progress there every 10,000 operations and at the end
+    #[arg(long)]
+    pub resume_from: Option<PathBuf>,
+
+    /// Retries of an operation the server sheds with 429 or 503, waiting as its Retry-After
+    /// says or backing off exponentially with jitter
+    #[arg(long, default_value_t = 5)]
+    pub max_retries: u32,
 }
 
-#[derive(Serialize, Debug, Clone)]
-struct BlogPost {
-    id: String,
-    title: String,
-    body: String,
-    tags: Vec<String>,
-    create_at: Option<i64>,
-    status: String,
-    features: serde_json::Value,
+/// Operations a direct run applies per commit.
+const DIRECT_COMMIT_EVERY: usize = 10_000;
+
+/// Writer heap of a direct run, as the server's default.
+const DIRECT_WRITER_HEAP_BYTES: usize = 50_000_000;
+
+/// Operations finished between two saves of the checkpoint.
+const CHECKPOINT_EVERY: usize = 10_000;
+
+/// How far a run got, as saved to `--resume-from`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Checkpoint {
+    /// Makes the run's document ids (`doc-<i>-<seed>`) unique across runs
+    seed: u64,
+    /// Operations finished: all of those before this one
+    done: usize,
+    created: usize,
+    updated: usize,
+    deleted: usize,
+    failed: usize,
+    /// Churn: ids created and not deleted yet
+    live_ids: Vec<String>,
+}
+
+impl Checkpoint {
+    /// The checkpoint at `path`, or a fresh run when there is none.
+    fn load(path: Option<&Path>) -> Result<Self> {
+        match path.filter(|p| p.exists()) {
+            Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)
+                .map_err(|e| anyhow::anyhow!("{} is not a generator checkpoint: {}", path.display(), e)),
+            None => Ok(Checkpoint { seed: rand::thread_rng().gen(), ..Checkpoint::default() }),
+        }
+    }
+
+    /// Rewrites the checkpoint at `path`, if any, through a temporary file.
+    fn save(&self, path: Option<&Path>) -> Result<()> {
+        let Some(path) = path else { return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn doc_id(&self, i: usize) -> String {
+        format!("doc-{}-{}", i, self.seed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Create,
+    Update,
+    Delete,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
-    let client = Client::builder().build()?;
+    let ratios = [opts.update_ratio, opts.delete_ratio];
+    if ratios.iter().any(|r| !(0.0..=1.0).contains(r)) || opts.update_ratio + opts.delete_ratio > 1.0 {
+        anyhow::bail!("--update-ratio and --delete-ratio must be between 0 and 1 and add up to at most 1");
+    }
+    let tags_pool = vec!["rust", "search", "tantivy", "actix", "json", "indexing", "performance", "concurrency"];    
+    let checkpoint_path = opts.resume_from.as_deref();
+    let mut state = Checkpoint::load(checkpoint_path)?;
+    if state.done >= opts.count {
+        println!("Nothing to resume: {} operations already done", state.done);
+        return Ok(());
+    }
+    let bar = progress_bar(&opts, state.done);
+    if let Some(path) = &opts.direct {
+        return generate_direct(&opts, path, &tags_pool, state, &bar);
+    }
+    let client = TantivyDemoClient::new(opts.endpoint.clone())
+        .with_retry(RetryPolicy { max_retries: opts.max_retries, ..RetryPolicy::default() });
 
     let semaphore = Arc::new(Semaphore::new(opts.concurrency));
-    let mut handles = Vec::with_capacity(opts.count);
+    // Operations in flight, oldest first: the checkpoint only moves past finished ones
+    let mut handles = VecDeque::new();
 
-    let tags_pool = vec!["rust", "search", "tantivy", "actix", "json", "indexing", "performance", "concurrency"];    
+    // Ids created so far, for churn to update and delete
+    let live_ids = Arc::new(Mutex::new(std::mem::take(&mut state.live_ids)));
+    let save = |state: &mut Checkpoint| -> Result<()> {
+        state.live_ids = live_ids.lock().unwrap().clone();
+        state.save(checkpoint_path)
+    };
 
-    for i in 0..opts.count {
+    for i in state.done..opts.count {
         let permit = semaphore.clone().acquire_owned().await.unwrap();
         let client = client.clone();
-        let endpoint = opts.endpoint.clone();
         let tags_pool = tags_pool.clone();
+        let live_ids = Arc::clone(&live_ids);
+        let op = pick_op(&opts);
+        let id = state.doc_id(i);
+        let churn = opts.workload == Workload::Churn;
 
         let handle = tokio::spawn(async move {
             let _p = permit;
-            let id = format!("doc-{}-{}", i, rand::thread_rng().gen::<u64>());
-            let title = format!("Post {} about Rust and search", i);
-            let body = random_body(200 + (i % 200) as usize);
-            let tags = random_tags(&tags_pool, 1 + (i % 4) as usize);
-            let create_at = Some(now_secs() as i64);
-            let status = if i % 5 == 0 { "draft" } else { "published" }.to_string();
-            let lang = ["en", "zh", "jp", "fr"].choose(&mut rand::thread_rng()).unwrap().to_string();
-            let random = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
-            let features = serde_json::json!({
-                "lang": lang,
-                "length": body.len(),
-                "score": (i as f64) * 0.1,
-                "random": random,
-            });
-
-            let post = BlogPost { id, title, body, tags, create_at, status, features };
-            let url = format!("{}/index", endpoint);
-            let resp = client.post(&url).json(&post).send().await.context("request failed")?;
-            if !resp.status().is_success() {
-                let status = resp.status();
-                let text = resp.text().await.unwrap_or_default();
-                anyhow::bail!("index failed: {} - {}", status, text);
+            // Updates and deletes take an id created earlier, or create while there is none
+            let existing = {
+                let mut ids = live_ids.lock().unwrap();
+                match op {
+                    Op::Update if !ids.is_empty() => ids.choose(&mut rand::thread_rng()).cloned(),
+                    Op::Delete if !ids.is_empty() => {
+                        let at = rand::thread_rng().gen_range(0..ids.len());
+                        Some(ids.swap_remove(at))
+                    }
+                    _ => None,
+                }
+            };
+            match (op, existing) {
+                (Op::Update, Some(id)) => (Op::Update, client.update(&random_post(id, i, &tags_pool)).await),
+                (Op::Delete, Some(id)) => (Op::Delete, client.delete(&id).await),
+                _ => {
+                    let result = client.index(&random_post(id.clone(), i, &tags_pool)).await;
+                    if result.is_ok() && churn {
+                        live_ids.lock().unwrap().push(id);
+                    }
+                    (Op::Create, result)
+                }
             }
-            Ok::<_, anyhow::Error>(())
         });
-        handles.push(handle);
+        handles.push_back(handle);
+
+        while handles.front().is_some_and(|h| h.is_finished()) {
+            record(&mut state, handles.pop_front().unwrap().await?, &bar);
+            if state.done.is_multiple_of(CHECKPOINT_EVERY) {
+                save(&mut state)?;
+            }
+        }
     }
 
-    // Wait for all
-    let mut ok = 0usize;
+    // Wait for the rest
     for h in handles {
-        match h.await? {
-            Ok(()) => ok += 1,
-            Err(e) => eprintln!("index error: {}", e),
+        record(&mut state, h.await?, &bar);
+    }
+    save(&mut state)?;
+    bar.finish();
+    print_summary(&opts, &state);
+    println!("Retries: {}", client.retry_stats());
+    Ok(())
+}
+
+/// Counts one finished operation.
+fn record(state: &mut Checkpoint, (op, result): (Op, Result<(), ClientError>), bar: &ProgressBar) {
+    match (op, result) {
+        (Op::Create, Ok(())) => state.created += 1,
+        (Op::Update, Ok(())) => state.updated += 1,
+        (Op::Delete, Ok(())) => state.deleted += 1,
+        (op, Err(e)) => {
+            state.failed += 1;
+            bar.suspend(|| eprintln!("{:?} error: {}", op, e));
         }
     }
-    println!("Indexed {}/{} documents", ok, opts.count);
+    state.done += 1;
+    bar.inc(1);
+}
+
+/// A bar over the run's operations with live throughput, starting at `done`; drawn on stderr
+/// when it is a terminal.
+fn progress_bar(opts: &Opts, done: usize) -> ProgressBar {
+    let style = ProgressStyle::with_template("{elapsed_precise} [{bar:40}] {human_pos}/{human_len} ops, {per_sec}, eta {eta}")
+        .expect("valid template")
+        .progress_chars("=> ");
+    let bar = ProgressBar::new(opts.count as u64).with_style(style);
+    // Throughput counts from here, not from the operations a resumed run skips
+    bar.set_position(done as u64);
+    bar.reset_eta();
+    bar
+}
+
+fn pick_op(opts: &Opts) -> Op {
+    if opts.workload == Workload::Append {
+        return Op::Create;
+    }
+    let roll: f64 = rand::thread_rng().gen();
+    if roll < opts.update_ratio {
+        Op::Update
+    } else if roll < opts.update_ratio + opts.delete_ratio {
+        Op::Delete
+    } else {
+        Op::Create
+    }
+}
+
+fn print_summary(opts: &Opts, state: &Checkpoint) {
+    match opts.workload {
+        Workload::Append => println!("Indexed {}/{} documents", state.created, opts.count),
+        Workload::Churn => println!(
+            "Created {}, updated {}, deleted {} documents; {} of {} operations failed",
+            state.created, state.updated, state.deleted, state.failed, opts.count
+        ),
+    }
+}
+
+/// The same workload written into the index at `path` by this process, committing every
+/// [`DIRECT_COMMIT_EVERY`] operations, and saving the checkpoint after each commit. An
+/// existing index keeps the schema it was created with.
+fn generate_direct(opts: &Opts, path: &Path, tags_pool: &[&str], mut state: Checkpoint, bar: &ProgressBar) -> Result<()> {
+    let index = open_or_create_index(&path.to_path_buf(), posts_schema("zh_ngram", "zh_ngram", "default"), IndexSettings::default())?;
+    let schema = index.schema();
+    let mut writer = index.writer(DIRECT_WRITER_HEAP_BYTES)?;
+    let pipeline = IngestPipeline::default();
+
+    // Counted as applied only once committed, so the checkpoint never runs ahead of the index
+    let mut pending = Checkpoint::default();
+    let mut ops = Vec::with_capacity(DIRECT_COMMIT_EVERY);
+    for i in state.done..opts.count {
+        let mut rng = rand::thread_rng();
+        let live_ids = &mut state.live_ids;
+        match pick_op(opts) {
+            Op::Update if !live_ids.is_empty() => {
+                let id = live_ids.choose(&mut rng).unwrap().clone();
+                ops.push(BatchOp::Update { doc: random_post(id, i, tags_pool) });
+                pending.updated += 1;
+            }
+            Op::Delete if !live_ids.is_empty() => {
+                let at = rng.gen_range(0..live_ids.len());
+                ops.push(BatchOp::Delete { id: live_ids.swap_remove(at) });
+                pending.deleted += 1;
+            }
+            _ => {
+                let id = state.doc_id(i);
+                if opts.workload == Workload::Churn {
+                    state.live_ids.push(id.clone());
+                }
+                ops.push(BatchOp::Index { doc: random_post(id, i, tags_pool) });
+                pending.created += 1;
+            }
+        }
+        bar.inc(1);
+        if ops.len() == DIRECT_COMMIT_EVERY || i + 1 == opts.count {
+            apply_batch(&writer, &schema, &pipeline, std::mem::take(&mut ops))?;
+            writer.commit()?;
+            state.done = i + 1;
+            state.created += std::mem::take(&mut pending.created);
+            state.updated += std::mem::take(&mut pending.updated);
+            state.deleted += std::mem::take(&mut pending.deleted);
+            state.save(opts.resume_from.as_deref())?;
+        }
+    }
+    writer.wait_merging_threads()?;
+    bar.finish();
+    print_summary(opts, &state);
     Ok(())
 }
 
+fn random_post(id: String, i: usize, tags_pool: &[&str]) -> BlogPost {
+    let title = format!("Post {} about Rust and search", i);
+    let body = random_body(200 + i % 200);
+    let tags = random_tags(tags_pool, 1 + i % 4);
+    let create_at = Some(now_secs() as i64);
+    let status = if i.is_multiple_of(5) { "draft" } else { "published" }.to_string();
+    let lang = ["en", "zh", "jp", "fr"].choose(&mut rand::thread_rng()).unwrap().to_string();
+    let random = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+    let features = serde_json::json!({
+        "lang": lang,
+        "length": body.len(),
+        "score": (i as f64) * 0.1,
+        "random": random,
+    });
+
+    BlogPost { id, title, body, tags, create_at, status, features, author_id: None, allowed_groups: Vec::new(), tenant: None }
+}
+
 fn random_body(len: usize) -> String {
     // Generate random lorem-like content
     let words = ["rust", "search", "engine", "tantivy", "fast", "index", "query", "http", "json", "analysis", "token", "field", "document", "commit", "reload", "reader", "writer", "arc", "mutex", "swap"];    
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tantivy_demo::client::{send_with_retry, RetryPolicy, RetryStats};
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "reconcile", about = "Diff the index against a source NDJSON file and optionally repair it")]
+pub struct Opts {
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub endpoint: String,
+
+    /// Source of truth: one BlogPost JSON object per line
+    #[arg(long)]
+    pub source: String,
+
+    /// Index missing documents, re-index stale ones and delete extras
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Operations per /batch request when repairing
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: usize,
+
+    /// How many ids to print per category
+    #[arg(long, default_value_t = 10)]
+    pub show: usize,
+
+    /// Retries of a request the server sheds with 429 or 503, waiting as its Retry-After says
+    /// or backing off exponentially with jitter
+    #[arg(long, default_value_t = 5)]
+    pub max_retries: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BlogPost {
+    id: String,
+    title: String,
+    body: String,
+    tags: Vec<String>,
+    create_at: Option<i64>,
+    status: String,
+    features: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp<'a> {
+    Index { doc: &'a BlogPost },
+    Update { doc: &'a BlogPost },
+    Delete { id: &'a str },
+}
+
+/// Columns requested from `/export?format=csv`, in the order `flatten` produces them.
+const COLUMNS: &str = "id,title,body,tags,create_at,status,features";
+
+/// Mirrors the server's CSV flattening so source rows compare equal to exported rows.
+fn flatten(post: &BlogPost) -> Vec<String> {
+    let features = match &post.features {
+        serde_json::Value::Object(_) => post.features.to_string(),
+        // The server wraps non-object features under "value"
+        other => serde_json::json!({ "value": other }).to_string(),
+    };
+    vec![
+        post.id.clone(),
+        post.title.clone(),
+        post.body.clone(),
+        post.tags.join("|"),
+        post.create_at.map(|ts| ts.to_string()).unwrap_or_default(),
+        post.status.clone(),
+        features,
+    ]
+}
+
+/// Minimal RFC 4180 reader: quoted cells may contain commas, quotes and newlines.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => cell.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut cell)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+fn print_ids(label: &str, ids: &[&str], show: usize) {
+    println!("{}: {}", label, ids.len());
+    for id in ids.iter().take(show) {
+        println!("  {}", id);
+    }
+    if ids.len() > show {
+        println!("  ... {} more", ids.len() - show);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::parse();
+    let client = Client::builder().build()?;
+    let retry = RetryPolicy { max_retries: opts.max_retries, ..RetryPolicy::default() };
+    let retry_stats = RetryStats::default();
+
+    let text = std::fs::read_to_string(&opts.source).with_context(|| format!("reading {}", opts.source))?;
+    let mut source: HashMap<String, BlogPost> = HashMap::new();
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let post: BlogPost = serde_json::from_str(line).with_context(|| format!("{} line {}", opts.source, n + 1))?;
+        source.insert(post.id.clone(), post);
+    }
+
+    let url = format!("{}/export", opts.endpoint);
+    let export = client.get(&url).query(&[("format", "csv"), ("fields", COLUMNS)]);
+    let resp = send_with_retry(export, &retry, &retry_stats).await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        anyhow::bail!("export failed: {} - {}", status, text);
+    }
+    let rows = parse_csv(&resp.text().await?);
+    let indexed: HashMap<String, Vec<String>> = rows
+        .into_iter()
+        .skip(1) // header
+        .filter(|r| !r.is_empty())
+        .map(|r| (r[0].clone(), r))
+        .collect();
+
+    let mut missing: Vec<&str> = Vec::new();
+    let mut stale: Vec<&str> = Vec::new();
+    for (id, post) in &source {
+        match indexed.get(id) {
+            None => missing.push(id),
+            Some(row) if *row != flatten(post) => stale.push(id),
+            Some(_) => {}
+        }
+    }
+    let mut extra: Vec<&str> = indexed.keys().filter(|id| !source.contains_key(*id)).map(|id| id.as_str()).collect();
+    missing.sort();
+    stale.sort();
+    extra.sort();
+
+    println!("source: {} documents, index: {} documents", source.len(), indexed.len());
+    print_ids("missing", &missing, opts.show);
+    print_ids("stale", &stale, opts.show);
+    print_ids("extra", &extra, opts.show);
+
+    if !opts.repair {
+        return Ok(());
+    }
+    let ops: Vec<BatchOp> = missing
+        .iter()
+        .map(|id| BatchOp::Index { doc: &source[*id] })
+        .chain(stale.iter().map(|id| BatchOp::Update { doc: &source[*id] }))
+        .chain(extra.iter().map(|id| BatchOp::Delete { id }))
+        .collect();
+    let url = format!("{}/batch", opts.endpoint);
+    for chunk in ops.chunks(opts.batch_size.max(1)) {
+        let resp = send_with_retry(client.post(&url).json(chunk), &retry, &retry_stats).await.context("request failed")?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            anyhow::bail!("repair batch failed: {} - {}", status, text);
+        }
+    }
+    println!("Repaired {} documents", ops.len());
+    if retry_stats.retries() > 0 {
+        println!("Retries: {}", retry_stats);
+    }
+    Ok(())
+}
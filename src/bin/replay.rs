@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "replay", about = "Replay recorded queries against the search service")]
+pub struct Opts {
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub endpoint: String,
+
+    /// Query log: NDJSON records ({"q": "...", "limit": 10, "ts": 1734050000.25}) or one query per line
+    #[arg(long)]
+    pub file: String,
+
+    /// Pace multiplier relative to the recorded `ts` gaps (2.0 = twice as fast, 0 = no pacing)
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+
+    #[arg(long, default_value_t = 16)]
+    pub concurrency: usize,
+
+    /// Limit used for records that don't carry one
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct LoggedQuery {
+    q: String,
+    limit: Option<usize>,
+    /// Seconds since the epoch when the query was originally issued
+    ts: Option<f64>,
+}
+
+fn parse_log(text: &str) -> Vec<LoggedQuery> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|line| {
+            serde_json::from_str(line).unwrap_or_else(|_| LoggedQuery { q: line.to_string(), limit: None, ts: None })
+        })
+        .collect()
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::parse();
+    let client = Client::builder().build()?;
+    let text = std::fs::read_to_string(&opts.file).with_context(|| format!("reading {}", opts.file))?;
+    let queries = parse_log(&text);
+    let first_ts = queries.iter().find_map(|q| q.ts);
+
+    let semaphore = Arc::new(Semaphore::new(opts.concurrency));
+    let mut handles = Vec::with_capacity(queries.len());
+    let started = Instant::now();
+
+    for query in queries {
+        // Original pacing: wait until this record's offset from the first one, scaled by --speed
+        if let (Some(ts), Some(first), true) = (query.ts, first_ts, opts.speed > 0.0) {
+            let offset = Duration::from_secs_f64(((ts - first) / opts.speed).max(0.0));
+            tokio::time::sleep_until((started + offset).into()).await;
+        }
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let client = client.clone();
+        let url = format!("{}/search", opts.endpoint);
+        let limit = query.limit.unwrap_or(opts.limit).to_string();
+
+        handles.push(tokio::spawn(async move {
+            let _p = permit;
+            let t0 = Instant::now();
+            let resp = client.get(url).query(&[("q", query.q.as_str()), ("limit", limit.as_str())]).send().await?;
+            let status = resp.status();
+            // Drain the body so latency covers the full response
+            let _ = resp.bytes().await;
+            Ok::<_, anyhow::Error>((status.as_u16(), t0.elapsed()))
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(handles.len());
+    let mut statuses: std::collections::BTreeMap<u16, usize> = Default::default();
+    let mut errors = 0usize;
+    for h in handles {
+        match h.await? {
+            Ok((status, latency)) => {
+                *statuses.entry(status).or_default() += 1;
+                latencies.push(latency);
+            }
+            Err(e) => {
+                errors += 1;
+                eprintln!("request error: {}", e);
+            }
+        }
+    }
+    let wall = started.elapsed();
+    latencies.sort();
+
+    let total = latencies.len() + errors;
+    let rate = total as f64 / wall.as_secs_f64().max(f64::EPSILON);
+    println!("Replayed {} queries in {:.2?} ({:.1} q/s)", total, wall, rate);
+    println!("status: {:?}, transport errors: {}", statuses, errors);
+    for p in [50.0, 90.0, 95.0, 99.0] {
+        println!("p{:<4} {:>10.2?}", p, percentile(&latencies, p));
+    }
+    println!("max   {:>10.2?}", latencies.last().copied().unwrap_or_default());
+    Ok(())
+}
@@ -1,34 +1,519 @@
-use anyhow::Result;
-use clap::Parser;
-use reqwest::Client;
-use serde_json::Value;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use tantivy::time::OffsetDateTime;
+use tantivy_demo::client::{RetryPolicy, TantivyDemoClient, TypedHit};
+
+/// Cells longer than this are cut in tables.
+const MAX_CELL_CHARS: usize = 60;
+
+/// Width of the longest bar of `timeline`.
+const TIMELINE_BAR: usize = 40;
+
+const HIGHLIGHT_START: &str = "\x1b[1;33m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Aligned columns for reading
+    Table,
+    /// One pretty-printed JSON array
+    Json,
+    /// One compact JSON object per line, for jq and friends
+    Ndjson,
+    /// One document id per line
+    Ids,
+}
 
 #[derive(Parser, Debug, Clone)]
-#[command(name = "search", about = "Query the search service")] 
+#[command(name = "search", about = "Query the search service")]
 pub struct Opts {
-    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8080")]
     pub endpoint: String,
 
-    #[arg(long, default_value = "rust")] 
-    pub q: String,
+    /// The query; searches default to `rust`, aggregations and timelines to all posts
+    #[arg(long, global = true)]
+    pub q: Option<String>,
 
     #[arg(long, default_value_t = 10)]
     pub limit: usize,
+
+    /// Searches default to json, subcommands to table; subcommands print table or json only
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<Format>,
+
+    /// Columns to print: `id`, `score`, `tier`, a field, or a path into one (`features.lang`).
+    /// Tables default to id,score,title; JSON formats to whole hits
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Color the query's matched terms in table cells
+    #[arg(long)]
+    pub highlight: bool,
+
+    /// Retries of a search the server sheds with 429 or 503, waiting as its Retry-After says
+    /// or backing off exponentially with jitter
+    #[arg(long, global = true, default_value_t = 5)]
+    pub max_retries: u32,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run tantivy aggregations (`POST /search/aggs`) over the matches of --q, one table per
+    /// aggregation
+    Aggs {
+        /// The aggregations, as JSON: `{"by_status": {"terms": {"field": "status"}}}`
+        #[arg(long, required_unless_present = "file", conflicts_with = "file")]
+        body: Option<String>,
+        /// Read the aggregations from a JSON file instead
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Count the matches of --q per value of some fields, the most common values first
+    Facets {
+        #[arg(long, value_delimiter = ',', default_value = "tags,status")]
+        fields: Vec<String>,
+        /// Values listed per field; the server's default when unset
+        #[arg(long)]
+        size: Option<usize>,
+    },
+    /// Matches of --q per hour, day or week of a timestamp field, as a bar chart
+    Timeline {
+        /// `hour`, `day`, `week` or a number of seconds
+        #[arg(long, default_value = "day")]
+        interval: String,
+        #[arg(long, default_value = "create_at")]
+        field: String,
+    },
+    /// Show one post by id: its stored fields, or with --terms how each field was indexed
+    Doc {
+        id: String,
+        /// Per field, the stored values next to the terms they were analyzed into, each with
+        /// whether the index holds it for this post, its frequency, positions and document count
+        #[arg(long)]
+        terms: bool,
+    },
+}
+
+/// The value of `column` in `hit`; null when the hit has none.
+fn column_value(hit: &TypedHit, column: &str) -> serde_json::Value {
+    match column {
+        "id" => serde_json::json!(hit.id),
+        "score" => serde_json::json!(hit.score),
+        "tier" => serde_json::json!(hit.tier),
+        _ => {
+            let mut path = column.split('.');
+            let field = path.next().unwrap_or(column);
+            let mut value = hit.fields.get(field).unwrap_or(&serde_json::Value::Null);
+            for key in path {
+                value = value.get(key).unwrap_or(&serde_json::Value::Null);
+            }
+            value.clone()
+        }
+    }
+}
+
+/// A hit as the JSON formats print it: whole, or just the selected columns.
+fn json_row(hit: &TypedHit, columns: &[String]) -> serde_json::Value {
+    if columns.is_empty() {
+        return serde_json::json!({
+            "id": hit.id,
+            "score": hit.score,
+            "tier": hit.tier,
+            "fields": hit.fields,
+            "matched": hit.matched.as_ref().map(|m| serde_json::json!({ "fields": m.fields, "terms": m.terms })),
+        });
+    }
+    serde_json::Value::Object(columns.iter().map(|c| (c.clone(), column_value(hit, c))).collect())
+}
+
+/// A value as one line of text, cut to [`MAX_CELL_CHARS`].
+fn cell_text(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) if n.is_f64() => format!("{:.3}", n.as_f64().unwrap_or_default()),
+        serde_json::Value::Array(values) => values.iter().map(cell_text).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    };
+    let text: String = text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    match text.chars().count() > MAX_CELL_CHARS {
+        true => text.chars().take(MAX_CELL_CHARS - 1).chain(['…']).collect(),
+        false => text,
+    }
+}
+
+/// The matched terms of `hit` that occur in `column`. JSON fields report `<path>:<value>`
+/// terms, which count for the column of their path.
+fn column_terms(hit: &TypedHit, column: &str) -> Vec<String> {
+    let Some(matched) = &hit.matched else { return Vec::new() };
+    let (field, path) = match column.split_once('.') {
+        Some((field, path)) => (field, Some(path)),
+        None => (column, None),
+    };
+    matched
+        .terms
+        .iter()
+        .filter(|t| t.field == field)
+        .filter_map(|t| match path {
+            Some(path) => t.term.strip_prefix(path).and_then(|rest| rest.strip_prefix(':')).map(str::to_string),
+            None => Some(t.term.clone()),
+        })
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// `text` with every case-insensitive occurrence of `terms` colored. Occurrences may overlap,
+/// as the n-grams of CJK fields do, and are colored as one run.
+fn highlight(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().map(fold).collect();
+        if term.len() > folded.len() {
+            continue;
+        }
+        for start in 0..=folded.len() - term.len() {
+            if folded[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].iter_mut().for_each(|m| *m = true);
+            }
+        }
+    }
+    let mut out = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if marked[i] && (i == 0 || !marked[i - 1]) {
+            out.push_str(HIGHLIGHT_START);
+        }
+        out.push(*c);
+        if marked[i] && (i + 1 == chars.len() || !marked[i + 1]) {
+            out.push_str(HIGHLIGHT_END);
+        }
+    }
+    out
+}
+
+fn print_table(hits: &[TypedHit], columns: &[String], colored: bool) {
+    let rows: Vec<Vec<String>> =
+        hits.iter().map(|hit| columns.iter().map(|c| cell_text(&column_value(hit, c))).collect()).collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| rows.iter().map(|r| r[i].chars().count()).chain([c.chars().count()]).max().unwrap_or(0))
+        .collect();
+    let line = |cells: Vec<String>, plain: &[String]| {
+        let padded: Vec<String> = cells
+            .into_iter()
+            .zip(plain)
+            .zip(&widths)
+            .map(|((cell, plain), width)| format!("{}{}", cell, " ".repeat(width - plain.chars().count())))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.to_vec(), columns);
+    for (hit, row) in hits.iter().zip(&rows) {
+        let cells = match colored {
+            true => columns.iter().zip(row).map(|(c, text)| highlight(text, &column_terms(hit, c))).collect(),
+            false => row.clone(),
+        };
+        line(cells, row);
+    }
+}
+
+/// Rows under a header, in aligned columns.
+fn print_grid(header: &[String], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).chain([header[i].chars().count()]).max().unwrap_or(0))
+        .collect();
+    for row in [header].into_iter().chain(rows.iter().map(Vec::as_slice)) {
+        let padded: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        println!("{}", padded.join("  ").trim_end());
+    }
+}
+
+/// A metric value as a cell: whole numbers, such as a max timestamp or a count, without
+/// decimals.
+fn metric_text(value: &serde_json::Value) -> String {
+    match value.as_f64() {
+        Some(n) if n.fract() == 0.0 && n.abs() < 1e15 => format!("{}", n as i64),
+        _ => cell_text(value),
+    }
+}
+
+/// A metric's result as `(column, value)` pairs: `name` for single values, `name.min` and so
+/// on for stats, and the buckets' `key:doc_count` for a nested bucket aggregation.
+fn metric_cells(name: &str, result: &serde_json::Value) -> Vec<(String, String)> {
+    if let Some(buckets) = result.get("buckets") {
+        let buckets = bucket_list(buckets);
+        let text = buckets.iter().map(|(key, b)| format!("{}:{}", key, b["doc_count"])).collect::<Vec<_>>().join(", ");
+        return vec![(name.to_string(), cell_text(&serde_json::Value::String(text)))];
+    }
+    match result.as_object() {
+        Some(fields) if fields.len() == 1 && fields.contains_key("value") => vec![(name.to_string(), metric_text(&fields["value"]))],
+        Some(fields) => fields.iter().map(|(k, v)| (format!("{}.{}", name, k), metric_text(v))).collect(),
+        None => vec![(name.to_string(), metric_text(result))],
+    }
+}
+
+/// A bucket aggregation's buckets with their keys, whether listed or keyed by name.
+fn bucket_list(buckets: &serde_json::Value) -> Vec<(String, &serde_json::Value)> {
+    let key = |b: &serde_json::Value| cell_text(b.get("key_as_string").unwrap_or(&b["key"]));
+    match buckets {
+        serde_json::Value::Array(buckets) => buckets.iter().map(|b| (key(b), b)).collect(),
+        serde_json::Value::Object(buckets) => buckets.iter().map(|(k, b)| (k.clone(), b)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// One table per bucket aggregation: key, doc_count and a column per sub-aggregation. Metric
+/// aggregations share one name/value table.
+fn print_aggregations(results: &serde_json::Value) {
+    let Some(results) = results.as_object() else { return };
+    let mut metrics = Vec::new();
+    let mut first = true;
+    for (name, result) in results {
+        let Some(buckets) = result.get("buckets") else {
+            metrics.extend(metric_cells(name, result).into_iter().map(|(k, v)| vec![k, v]));
+            continue;
+        };
+        let buckets = bucket_list(buckets);
+        let mut header = vec!["key".to_string(), "doc_count".to_string()];
+        let mut rows = Vec::new();
+        for (key, bucket) in &buckets {
+            let mut row = vec![key.clone(), cell_text(&bucket["doc_count"])];
+            let subs = bucket.as_object().into_iter().flatten().filter(|(k, v)| v.is_object() && !matches!(k.as_str(), "key" | "key_as_string"));
+            for (column, value) in subs.flat_map(|(k, v)| metric_cells(k, v)) {
+                let i = match header.iter().position(|h| *h == column) {
+                    Some(i) => i,
+                    None => {
+                        header.push(column);
+                        header.len() - 1
+                    }
+                };
+                row.resize(row.len().max(i + 1), String::new());
+                row[i] = value;
+            }
+            rows.push(row);
+        }
+        rows.iter_mut().for_each(|row| row.resize(header.len(), String::new()));
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("{}", name);
+        print_grid(&header, &rows);
+    }
+    if !metrics.is_empty() {
+        if !first {
+            println!();
+        }
+        print_grid(&["name".to_string(), "value".to_string()], &metrics);
+    }
+}
+
+/// The width of a timeline bucket in seconds, and the offset aligning it: weeks start on
+/// Monday (1970-01-05) rather than on the epoch's Thursday.
+fn timeline_interval(interval: &str) -> Result<(u64, u64)> {
+    match interval {
+        "hour" => Ok((3600, 0)),
+        "day" => Ok((86_400, 0)),
+        "week" => Ok((7 * 86_400, 4 * 86_400)),
+        secs => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok((secs, 0)),
+            _ => anyhow::bail!("--interval must be hour, day, week or a number of seconds, got {}", interval),
+        },
+    }
+}
+
+/// A bucket's start as a date, with the time of day unless buckets are whole days.
+fn timeline_label(secs: i64, interval: u64) -> String {
+    let Ok(at) = OffsetDateTime::from_unix_timestamp(secs) else { return secs.to_string() };
+    let date = format!("{}-{:02}-{:02}", at.year(), at.month() as u8, at.day());
+    match interval % 86_400 {
+        0 => date,
+        _ => format!("{} {:02}:{:02}", date, at.hour(), at.minute()),
+    }
+}
+
+fn print_timeline(results: &serde_json::Value, interval: u64) {
+    let buckets: Vec<(i64, u64)> = results["timeline"]["buckets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|b| (b["key"].as_f64().unwrap_or_default() as i64, b["doc_count"].as_u64().unwrap_or_default()))
+        .collect();
+    let max = buckets.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+    let rows: Vec<Vec<String>> = buckets
+        .iter()
+        .map(|(key, count)| {
+            let bar = (*count as usize * TIMELINE_BAR).div_ceil(max as usize);
+            vec![timeline_label(*key, interval), count.to_string(), "#".repeat(bar)]
+        })
+        .collect();
+    print_grid(&["from", "matches", ""].map(String::from), &rows);
+}
+
+async fn run_command(client: &TantivyDemoClient, opts: &Opts, command: &Command) -> Result<()> {
+    let format = opts.format.unwrap_or(Format::Table);
+    if !matches!(format, Format::Table | Format::Json) {
+        anyhow::bail!("subcommands print --format table or json");
+    }
+    let q = opts.q.as_deref();
+    match command {
+        Command::Aggs { body, file } => {
+            let aggs = match (body, file) {
+                (Some(body), _) => body.clone(),
+                (None, Some(file)) => std::fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?,
+                (None, None) => unreachable!("clap requires --body or --file"),
+            };
+            let aggs: serde_json::Value = serde_json::from_str(&aggs).context("the aggregations aren't valid JSON")?;
+            let results = client.aggregate(q, &aggs).await.map_err(|e| anyhow::anyhow!("aggregation failed: {}", e))?;
+            match format {
+                Format::Table => print_aggregations(&results),
+                _ => println!("{}", serde_json::to_string_pretty(&results)?),
+            }
+        }
+        Command::Facets { fields, size } => {
+            let found = client
+                .search(q.unwrap_or("*").to_string())
+                .limit(1)
+                .facets(fields.clone(), *size)
+                .send_typed()
+                .await
+                .map_err(|e| anyhow::anyhow!("search failed: {}", e))?;
+            let facets = found.facets.unwrap_or_default();
+            if format == Format::Json {
+                println!("{}", serde_json::to_string_pretty(&facets)?);
+                return Ok(());
+            }
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                let rows: Vec<Vec<String>> = facets
+                    .get(field)
+                    .into_iter()
+                    .flatten()
+                    .map(|v| vec![cell_text(&serde_json::Value::String(v.value.clone())), v.count.to_string()])
+                    .collect();
+                print_grid(&[field.clone(), "count".to_string()], &rows);
+            }
+        }
+        Command::Doc { id, terms: false } => {
+            let found = client.document(id).await.map_err(|e| anyhow::anyhow!("lookup failed: {}", e))?;
+            let found = found.ok_or_else(|| anyhow::anyhow!("no post with id {}", id))?;
+            if format == Format::Json {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "tier": found.tier, "doc": found.doc }))?);
+                return Ok(());
+            }
+            println!("{} tier", found.tier);
+            let rows: Vec<Vec<String>> = found.doc.iter().map(|(field, value)| vec![field.clone(), cell_text(value)]).collect();
+            print_grid(&["field", "value"].map(String::from), &rows);
+        }
+        Command::Doc { id, terms: true } => {
+            let found = client.document_terms(id).await.map_err(|e| anyhow::anyhow!("lookup failed: {}", e))?;
+            let found = found.ok_or_else(|| anyhow::anyhow!("no post with id {}", id))?;
+            if format == Format::Json {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "tier": found.tier, "fields": found.fields }))?);
+                return Ok(());
+            }
+            println!("{} tier", found.tier);
+            for field in &found.fields {
+                println!();
+                println!("{}", field.field);
+                for value in &field.stored {
+                    println!("  stored: {}", cell_text(value));
+                }
+                let rows: Vec<Vec<String>> = field
+                    .terms
+                    .iter()
+                    .map(|t| {
+                        let positions = t.positions.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+                        let indexed = if t.indexed { "yes" } else { "NO" };
+                        // N-grams may start or end with a space, which would hide in a table
+                        let term = match t.term.trim() == t.term {
+                            true => t.term.clone(),
+                            false => format!("{:?}", t.term),
+                        };
+                        vec![cell_text(&serde_json::Value::String(term)), indexed.to_string(), t.term_freq.to_string(), cell_text(&serde_json::Value::String(positions)), t.doc_freq.to_string()]
+                    })
+                    .collect();
+                print_grid(&["term", "indexed", "freq", "positions", "docs"].map(String::from), &rows);
+            }
+        }
+        Command::Timeline { interval, field } => {
+            let (interval, offset) = timeline_interval(interval)?;
+            let aggs = serde_json::json!({
+                "timeline": { "histogram": { "field": field, "interval": interval, "offset": offset } }
+            });
+            let results = client.aggregate(q, &aggs).await.map_err(|e| anyhow::anyhow!("aggregation failed: {}", e))?;
+            match format {
+                Format::Table => print_timeline(&results, interval),
+                _ => println!("{}", serde_json::to_string_pretty(&results["timeline"]["buckets"])?),
+            }
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
-    let client = Client::builder().build()?;
+    let format = opts.format.unwrap_or(Format::Json);
+    if opts.highlight && (opts.command.is_some() || format != Format::Table) {
+        anyhow::bail!("--highlight only applies to searches with --format table");
+    }
+    let client = TantivyDemoClient::new(opts.endpoint.clone())
+        .with_retry(RetryPolicy { max_retries: opts.max_retries, ..RetryPolicy::default() });
+
+    if let Some(command) = &opts.command {
+        let done = run_command(&client, &opts, command).await;
+        if client.retry_stats().retries() > 0 {
+            eprintln!("Retries: {}", client.retry_stats());
+        }
+        return done;
+    }
 
-    let url = format!("{}/search", opts.endpoint);
-    let resp = client.get(url).query(&[("q", &opts.q), ("limit", &opts.limit.to_string())]).send().await?;
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        anyhow::bail!("search failed: {} - {}", status, text);
+    let found = client
+        .search(opts.q.clone().unwrap_or_else(|| "rust".to_string()))
+        .limit(opts.limit)
+        .matches(opts.highlight)
+        .send_typed()
+        .await;
+    if client.retry_stats().retries() > 0 {
+        eprintln!("Retries: {}", client.retry_stats());
+    }
+    let found = found.map_err(|e| anyhow::anyhow!("search failed: {}", e))?;
+    match format {
+        Format::Table => {
+            let columns = match opts.columns.is_empty() {
+                true => ["id", "score", "title"].map(String::from).to_vec(),
+                false => opts.columns.clone(),
+            };
+            print_table(&found.hits, &columns, opts.highlight);
+        }
+        Format::Json => {
+            let rows: Vec<_> = found.hits.iter().map(|hit| json_row(hit, &opts.columns)).collect();
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+        }
+        Format::Ndjson => {
+            for hit in &found.hits {
+                println!("{}", serde_json::to_string(&json_row(hit, &opts.columns))?);
+            }
+        }
+        Format::Ids => {
+            for id in found.hits.iter().filter_map(|hit| hit.id.as_deref()) {
+                println!("{}", id);
+            }
+        }
     }
-    let json: Value = resp.json().await?;
-    println!("{}", serde_json::to_string_pretty(&json)?);
     Ok(())
 }
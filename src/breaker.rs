@@ -0,0 +1,129 @@
+//! Circuit breakers around the outbound HTTP calls: the moderation webhook, each remote
+//! cluster and the metering endpoint. A breaker opens after `failure_threshold` calls in a row
+//! fail and then turns calls away without sending them for `cooldown`, after which one probe
+//! goes out (half-open): its success closes the breaker, its failure opens it again.
+//!
+//! While a breaker is open its caller falls back instead of waiting out timeouts: posts the
+//! webhook should moderate are dead-lettered for a retry from `/dlq`, searches skip the remote
+//! with an error on its leg, and usage records stay counted for the next emission. Every
+//! breaker is reported under `breakers` in `GET /stats`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerConfig {
+    /// Failed calls in a row that open a breaker
+    pub failure_threshold: u32,
+    /// How long an open breaker turns calls away before probing
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig { failure_threshold: 5, cooldown: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    /// A probe is out
+    HalfOpen,
+}
+
+struct Circuit {
+    state: BreakerState,
+    /// Failed calls in a row
+    failures: u32,
+    /// When the breaker opened or the last probe went out
+    since: Instant,
+}
+
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    circuit: Mutex<Circuit>,
+    /// Times the breaker opened
+    opened: AtomicU64,
+    /// Calls turned away while open
+    rejected: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            circuit: Mutex::new(Circuit { state: BreakerState::Closed, failures: 0, since: Instant::now() }),
+            opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn circuit(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.circuit().state
+    }
+
+    /// Whether a call may go out now. Past the cool-down one probe is let through; a probe
+    /// that never reports back (its request dropped) is replaced after another cool-down.
+    pub fn allow(&self) -> bool {
+        let mut circuit = self.circuit();
+        if circuit.state == BreakerState::Closed {
+            return true;
+        }
+        if circuit.since.elapsed() >= self.config.cooldown {
+            circuit.state = BreakerState::HalfOpen;
+            circuit.since = Instant::now();
+            return true;
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Records how a call let through by [`allow`](Self::allow) went.
+    pub fn record(&self, success: bool) {
+        let mut circuit = self.circuit();
+        if success {
+            circuit.state = BreakerState::Closed;
+            circuit.failures = 0;
+            return;
+        }
+        circuit.failures = circuit.failures.saturating_add(1);
+        let trips = match circuit.state {
+            BreakerState::Closed => circuit.failures >= self.config.failure_threshold,
+            BreakerState::HalfOpen => true,
+            // a call sent before the breaker opened
+            BreakerState::Open => false,
+        };
+        if trips {
+            circuit.state = BreakerState::Open;
+            circuit.since = Instant::now();
+            self.opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let circuit = self.circuit();
+        let retry_in = match circuit.state {
+            BreakerState::Closed => None,
+            _ => Some(self.config.cooldown.saturating_sub(circuit.since.elapsed()).as_millis() as u64),
+        };
+        serde_json::json!({
+            "state": circuit.state,
+            "consecutive_failures": circuit.failures,
+            "failure_threshold": self.config.failure_threshold,
+            "cooldown_ms": self.config.cooldown.as_millis() as u64,
+            "retry_in_ms": retry_in,
+            "opened": self.opened.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+}
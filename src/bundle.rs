@@ -0,0 +1,202 @@
+//! Configuration bundles: `GET /admin/export_config` gathers everything that configures a
+//! deployment rather than its documents into one JSON file: the schema options of the posts
+//! indexes, custom analyzers, managed index specs and aliases, saved objects (synonyms,
+//! templates, pins) and the live settings. `POST /admin/import_config` applies a bundle to another instance,
+//! so a configuration tried on staging can be promoted to production as is.
+//!
+//! Schema options and settings are tables in the `--config` file format (see
+//! [`settings`](crate::settings)). Schema options only take effect when the indexes are
+//! created, so importing reports where they differ instead of applying them.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analyzers::{check_name, AnalyzerSpec};
+use crate::error::{ServiceError, ServiceResult};
+use crate::indexes::{check_index_name, IndexSpec};
+use crate::settings::{changes, SettingChange};
+use crate::service::SearchService;
+use crate::storage::check_names;
+use crate::now_secs;
+
+/// What the `format` of a bundle says.
+pub const BUNDLE_FORMAT: &str = "tantivy-demo/config";
+
+/// Bumped when a bundle written now can't be read the same way by older servers.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Saved-object namespaces exported by default. Others, such as API keys, are deployment
+/// specific and only exported when asked for by name.
+pub const CONFIG_NAMESPACES: &[&str] = &["synonyms", "templates", "pins"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigBundle {
+    pub format: String,
+    pub version: u32,
+    #[serde(default)]
+    pub exported_at: i64,
+    /// Options shaping the posts indexes, by `--config` file name
+    #[serde(default)]
+    pub schema: toml::Table,
+    /// Custom analyzers
+    #[serde(default)]
+    pub analyzers: Vec<AnalyzerSpec>,
+    /// Managed indexes by name, their specs only
+    #[serde(default)]
+    pub indexes: BTreeMap<String, IndexSpec>,
+    /// Alias to index name
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Saved objects by namespace and key
+    #[serde(default)]
+    pub objects: BTreeMap<String, BTreeMap<String, serde_json::Value>>,
+    /// Live settings ([`HOT_SETTINGS`](crate::settings::HOT_SETTINGS)) by `--config` file name
+    #[serde(default)]
+    pub settings: toml::Table,
+}
+
+/// What importing a bundle did.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    pub analyzers_registered: Vec<String>,
+    /// Already registered with the same definition
+    pub analyzers_unchanged: Vec<String>,
+    pub indexes_created: Vec<String>,
+    /// Already there with the same spec
+    pub indexes_unchanged: Vec<String>,
+    pub aliases_set: Vec<String>,
+    /// Already pointing at the same index
+    pub aliases_unchanged: Vec<String>,
+    /// Saved objects written
+    pub objects: usize,
+    /// Live settings that changed
+    pub settings: Vec<SettingChange>,
+    /// Schema options that differ from this instance's, `old` being its own; they take effect
+    /// on indexes created with them
+    pub restart_required: Vec<SettingChange>,
+}
+
+impl SearchService {
+    /// The options the posts indexes were opened with that shape their schema, by `--config`
+    /// file name; unset ones are left out.
+    pub fn schema_options(&self) -> toml::Table {
+        let config = self.config();
+        let mut table = toml::Table::new();
+        table.insert("index_sort_create_at".to_string(), config.sort_by_create_at.into());
+        table.insert("fold_text".to_string(), config.fold_text.into());
+        if let Some(analyzer) = &config.body_analyzer {
+            table.insert("body_analyzer".to_string(), analyzer.clone().into());
+        }
+        if let Some(analyzer) = &config.features_analyzer {
+            table.insert("features_analyzer".to_string(), analyzer.clone().into());
+        }
+        if !config.nested_paths.is_empty() {
+            table.insert("nested_path".to_string(), config.nested_paths.clone().into());
+        }
+        if !config.path_features.is_empty() {
+            table.insert("path_feature".to_string(), config.path_features.clone().into());
+        }
+        table
+    }
+
+    /// A bundle of this instance's configuration, with the saved objects of `namespaces` and
+    /// the live `settings`, which the server keeps track of.
+    pub fn export_config(&self, namespaces: &[&str], settings: toml::Table) -> ServiceResult<ConfigBundle> {
+        let mut objects = BTreeMap::new();
+        for namespace in namespaces {
+            let saved = self.storage().list(namespace)?;
+            if !saved.is_empty() {
+                objects.insert(namespace.to_string(), saved);
+            }
+        }
+        Ok(ConfigBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            exported_at: now_secs(),
+            schema: self.schema_options(),
+            analyzers: self.analyzers.custom(),
+            indexes: self.indexes.list().iter().map(|index| (index.name.clone(), index.spec.clone())).collect(),
+            aliases: self.indexes.aliases(),
+            objects,
+            settings,
+        })
+    }
+
+    /// Registers the bundle's analyzers, creates its managed indexes, points its aliases and
+    /// writes its saved objects, after checking that none of them conflicts with what is here:
+    /// an analyzer, index or alias of the same name with another definition is a `Conflict`
+    /// and nothing is applied.
+    /// Saved objects replace those under the same keys. The bundle's settings are the
+    /// server's to apply.
+    pub fn import_config(&self, bundle: &ConfigBundle) -> ServiceResult<ImportReport> {
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(ServiceError::Invalid(format!("not a configuration bundle: format is {:?}, expected {:?}", bundle.format, BUNDLE_FORMAT)));
+        }
+        if bundle.version > BUNDLE_VERSION {
+            return Err(ServiceError::Invalid(format!("bundle version {} is newer than this server's {}", bundle.version, BUNDLE_VERSION)));
+        }
+        let mut report = ImportReport::default();
+        let custom = self.analyzers.custom();
+        for spec in &bundle.analyzers {
+            match custom.iter().find(|a| a.name == spec.name) {
+                Some(existing) if existing == spec => report.analyzers_unchanged.push(spec.name.clone()),
+                Some(_) => return Err(ServiceError::Conflict(format!("analyzer {} is registered here with another definition", spec.name))),
+                None => {
+                    check_name(&spec.name).map_err(ServiceError::Invalid)?;
+                    spec.build().map_err(|e| ServiceError::Invalid(format!("analyzer {}: {}", spec.name, e)))?;
+                }
+            }
+        }
+        // The bundle's own analyzers count as known, as they are registered first
+        let known = |name: &str| self.analyzers.is_known(name) || bundle.analyzers.iter().any(|a| a.name == name);
+        for (name, spec) in &bundle.indexes {
+            check_index_name(name)?;
+            spec.validate(&known).map_err(|e| ServiceError::Invalid(format!("index {}: {}", name, e)))?;
+            match self.indexes.get(name) {
+                Some(existing) if existing.spec == *spec => report.indexes_unchanged.push(name.clone()),
+                Some(_) => return Err(ServiceError::Conflict(format!("index {} exists here with another spec", name))),
+                None => {}
+            }
+        }
+        let aliases = self.indexes.aliases();
+        for (alias, index) in &bundle.aliases {
+            check_index_name(alias)?;
+            match aliases.get(alias) {
+                Some(existing) if existing == index => report.aliases_unchanged.push(alias.clone()),
+                Some(existing) => return Err(ServiceError::Conflict(format!("alias {} points at {} here", alias, existing))),
+                None if self.indexes.get(alias).is_some() || bundle.indexes.contains_key(alias) => {
+                    return Err(ServiceError::Conflict(format!("{} is an index; aliases can't share its name", alias)))
+                }
+                None if self.indexes.get(index).is_none() && !bundle.indexes.contains_key(index) => {
+                    return Err(ServiceError::Invalid(format!("alias {} points at {}, which is neither here nor in the bundle", alias, index)))
+                }
+                None => {}
+            }
+        }
+        for (namespace, saved) in &bundle.objects {
+            saved.keys().try_for_each(|key| check_names(namespace, Some(key)))?;
+        }
+
+        for spec in bundle.analyzers.iter().filter(|s| !report.analyzers_unchanged.contains(&s.name)) {
+            self.analyzers.register(spec.clone())?;
+            report.analyzers_registered.push(spec.name.clone());
+        }
+        for (name, spec) in bundle.indexes.iter().filter(|(name, _)| !report.indexes_unchanged.contains(name)) {
+            self.indexes.create(name, spec.clone(), &self.analyzers)?;
+            report.indexes_created.push(name.clone());
+        }
+        for (alias, index) in bundle.aliases.iter().filter(|(alias, _)| !report.aliases_unchanged.contains(alias)) {
+            self.indexes.set_alias(alias, index)?;
+            report.aliases_set.push(alias.clone());
+        }
+        for (namespace, saved) in &bundle.objects {
+            for (key, value) in saved {
+                self.storage().put(namespace, key, value.clone())?;
+                report.objects += 1;
+            }
+        }
+        report.restart_required = changes(&self.schema_options(), &bundle.schema);
+        Ok(report)
+    }
+}
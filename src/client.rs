@@ -0,0 +1,488 @@
+//! Typed HTTP client for a running `tantivy-demo` server.
+//!
+//! ```no_run
+//! use tantivy_demo::client::TantivyDemoClient;
+//!
+//! # async fn run() -> Result<(), tantivy_demo::client::ClientError> {
+//! let client = TantivyDemoClient::new("http://127.0.0.1:8080");
+//! let hits = client.search("features.lang:zh").limit(5).send().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests the server sheds under load (429 or 503) can be retried with a [`RetryPolicy`]:
+//! `TantivyDemoClient::new(url).with_retry(RetryPolicy::default())`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::aggs::FacetValue;
+use crate::batch::BatchOp;
+use crate::collector::TotalHits;
+use crate::envelope::V2_MEDIA_TYPE;
+use crate::schema::BlogPost;
+use crate::service::{FieldTerms, MatchedTerm};
+use crate::session::SESSION_HEADER;
+use crate::term_stats::TermStats;
+use crate::trace::{TraceContext, TRACEPARENT};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request never got a response (connect, timeout, decode)
+    Transport(reqwest::Error),
+    /// The server answered with a non-success status; `body` is its error message
+    Status { status: u16, body: String },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "request failed: {}", e),
+            ClientError::Status { status, body } => write!(f, "{} - {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Transport(e)
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Response of `POST /batch`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BatchResponse {
+    pub opstamp: u64,
+    pub operations: usize,
+}
+
+/// Response of `GET /doc`: a post's stored fields as plain JSON.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StoredDocument {
+    pub tier: String,
+    pub doc: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Response of `GET /doc/terms`: a post's term vectors.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DocumentTerms {
+    pub tier: String,
+    pub fields: Vec<FieldTerms>,
+}
+
+/// Response of [`SearchBuilder::send_typed`]: the v2 envelope.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TypedResults {
+    pub hits: Vec<TypedHit>,
+    pub total: Option<TotalHits>,
+    /// Set when the search asked for facets
+    pub facets: Option<BTreeMap<String, Vec<FacetValue>>>,
+}
+
+/// A hit of the v2 envelope, its stored fields as plain JSON.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TypedHit {
+    pub id: Option<String>,
+    pub score: f32,
+    pub tier: String,
+    pub fields: serde_json::Map<String, serde_json::Value>,
+    /// Set when the search asked for matches
+    pub matched: Option<TypedMatches>,
+    /// Set when the search deduplicated
+    pub duplicates_count: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TypedMatches {
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub terms: Vec<MatchedTerm>,
+}
+
+/// How requests answered 429 or 503 are retried: after the server's `Retry-After` when it
+/// sends one, otherwise after an exponential backoff with full jitter, both capped at
+/// `max_delay`. Requests that get no response at all aren't retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries per request; 0 disables retrying
+    pub max_retries: u32,
+    /// Backoff ceiling of the first retry, doubling with each further one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_retries: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_secs(10) }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy { max_retries: 0, ..RetryPolicy::default() }
+    }
+
+    /// The wait before retry number `attempt` (from 0) of a request answered `resp`.
+    fn delay(&self, attempt: u32, resp: &reqwest::Response) -> Duration {
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        match retry_after {
+            Some(wait) => wait.min(self.max_delay),
+            None => {
+                let ceiling = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+                ceiling.mul_f64(rand::thread_rng().gen::<f64>())
+            }
+        }
+    }
+}
+
+/// Counts of the retries made by a client and its clones.
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    retries: AtomicU64,
+    retried_requests: AtomicU64,
+    exhausted: AtomicU64,
+    waited_ms: AtomicU64,
+}
+
+impl RetryStats {
+    /// Retries sent, over all requests
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Requests retried at least once
+    pub fn retried_requests(&self) -> u64 {
+        self.retried_requests.load(Ordering::Relaxed)
+    }
+
+    /// Requests still shed after their last retry
+    pub fn exhausted(&self) -> u64 {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Time spent waiting between attempts, summed over requests
+    pub fn waited(&self) -> Duration {
+        Duration::from_millis(self.waited_ms.load(Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for RetryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "requests shed with 429/503: {}, retries: {}, given up: {}, backing off: {:.1}s",
+            self.retried_requests(),
+            self.retries(),
+            self.exhausted(),
+            self.waited().as_secs_f64()
+        )
+    }
+}
+
+/// Sends `request`, retrying as `policy` says while the server sheds it, and counts the
+/// retries in `stats`. Requests whose body can't be replayed are sent once.
+pub async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+    policy: &RetryPolicy,
+    stats: &RetryStats,
+) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let Some(retry) = request.try_clone().filter(|_| attempt < policy.max_retries) else {
+            let resp = request.send().await?;
+            if attempt > 0 && is_shed(resp.status()) {
+                stats.exhausted.fetch_add(1, Ordering::Relaxed);
+            }
+            return Ok(resp);
+        };
+        let resp = retry.send().await?;
+        if !is_shed(resp.status()) {
+            return Ok(resp);
+        }
+        let delay = policy.delay(attempt, &resp);
+        if attempt == 0 {
+            stats.retried_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.retries.fetch_add(1, Ordering::Relaxed);
+        stats.waited_ms.fetch_add(delay.as_millis() as u64, Ordering::Relaxed);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn is_shed(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+#[derive(Clone)]
+pub struct TantivyDemoClient {
+    http: reqwest::Client,
+    endpoint: String,
+    retry: RetryPolicy,
+    stats: Arc<RetryStats>,
+}
+
+impl TantivyDemoClient {
+    /// `endpoint` is the server base URL, e.g. `http://127.0.0.1:8080`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), endpoint)
+    }
+
+    /// Reuses a configured reqwest client (timeouts, proxies, TLS).
+    pub fn with_http_client(http: reqwest::Client, endpoint: impl Into<String>) -> Self {
+        let endpoint = endpoint.into().trim_end_matches('/').to_string();
+        TantivyDemoClient { http, endpoint, retry: RetryPolicy::none(), stats: Arc::default() }
+    }
+
+    /// Retries requests the server sheds with 429 or 503 as `policy` says.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Retries made so far by this client and its clones.
+    pub fn retry_stats(&self) -> &RetryStats {
+        &self.stats
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoint, path)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> ClientResult<reqwest::Response> {
+        Ok(send_with_retry(request, &self.retry, &self.stats).await?)
+    }
+
+    /// Queues one document, replacing the one with its id; it becomes searchable after the
+    /// server's next commit.
+    pub async fn index(&self, post: &BlogPost) -> ClientResult<()> {
+        let resp = self.send(self.http.post(self.url("/index")).json(post)).await?;
+        check(resp).await.map(drop)
+    }
+
+    /// Adds the document, failing with a 409 when one with its id exists.
+    pub async fn create(&self, post: &BlogPost) -> ClientResult<()> {
+        let resp = self.send(self.http.post(self.url("/index")).query(&[("op_type", "create")]).json(post)).await?;
+        check(resp).await.map(drop)
+    }
+
+    /// Replaces the document with the same id.
+    pub async fn update(&self, post: &BlogPost) -> ClientResult<()> {
+        let resp = self.send(self.http.post(self.url("/update")).json(post)).await?;
+        check(resp).await.map(drop)
+    }
+
+    pub async fn delete(&self, id: &str) -> ClientResult<()> {
+        let resp = self.send(self.http.delete(self.url("/delete")).query(&[("id", id)])).await?;
+        check(resp).await.map(drop)
+    }
+
+    /// Commits and waits for the new searcher, so every write acknowledged so far is searchable.
+    pub async fn refresh(&self) -> ClientResult<()> {
+        let resp = self.send(self.http.post(self.url("/refresh"))).await?;
+        check(resp).await.map(drop)
+    }
+
+    /// The stored fields of the committed post with `id`; `None` when there is none.
+    pub async fn document(&self, id: &str) -> ClientResult<Option<StoredDocument>> {
+        let resp = self.send(self.http.get(self.url("/doc")).query(&[("id", id)])).await?;
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            _ => json(resp).await.map(Some),
+        }
+    }
+
+    /// The term vectors of the committed post with `id`; `None` when there is none.
+    pub async fn document_terms(&self, id: &str) -> ClientResult<Option<DocumentTerms>> {
+        let resp = self.send(self.http.get(self.url("/doc/terms")).query(&[("id", id)])).await?;
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            _ => json(resp).await.map(Some),
+        }
+    }
+
+    /// BM25 inputs of `terms` in `field` of the hot tier (`GET /terms/stats`).
+    pub async fn term_stats(&self, field: &str, terms: &[&str]) -> ClientResult<TermStats> {
+        let resp = self.send(self.http.get(self.url("/terms/stats")).query(&[("field", field), ("terms", &terms.join(","))])).await?;
+        json(resp).await
+    }
+
+    /// Applies mixed operations all-or-nothing under one commit via `POST /batch`.
+    pub async fn bulk(&self, ops: &[BatchOp]) -> ClientResult<BatchResponse> {
+        let resp = self.send(self.http.post(self.url("/batch")).json(ops)).await?;
+        json(resp).await
+    }
+
+    /// Runs tantivy aggregations (`POST /search/aggs`) over the documents matching `q`, all
+    /// posts when `None`; returns the `aggregations` object, keyed like `aggs`.
+    pub async fn aggregate(&self, q: Option<&str>, aggs: &serde_json::Value) -> ClientResult<serde_json::Value> {
+        let body = serde_json::json!({ "q": q, "aggs": aggs });
+        let resp = self.send(self.http.post(self.url("/search/aggs")).json(&body)).await?;
+        let mut answer: serde_json::Value = json(resp).await?;
+        Ok(answer["aggregations"].take())
+    }
+
+    /// Starts a `GET /search` request; finish it with [`SearchBuilder::send`].
+    pub fn search(&self, q: impl Into<String>) -> SearchBuilder<'_> {
+        SearchBuilder {
+            client: self,
+            q: q.into(),
+            limit: None,
+            include_archive: false,
+            matches: false,
+            facets: Vec::new(),
+            facet_size: None,
+            dedupe_by: None,
+            timeout: None,
+            trace: None,
+            session: None,
+        }
+    }
+}
+
+pub struct SearchBuilder<'a> {
+    client: &'a TantivyDemoClient,
+    q: String,
+    limit: Option<usize>,
+    include_archive: bool,
+    matches: bool,
+    facets: Vec<String>,
+    facet_size: Option<usize>,
+    dedupe_by: Option<String>,
+    timeout: Option<Duration>,
+    trace: Option<TraceContext>,
+    session: Option<String>,
+}
+
+impl SearchBuilder<'_> {
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Federate with the archive tier; hits then carry a `_tier` key.
+    pub fn include_archive(mut self, include: bool) -> Self {
+        self.include_archive = include;
+        self
+    }
+
+    /// Report which of the query's terms each hit contains (`_matched_terms`, or
+    /// [`TypedHit::matched`]).
+    pub fn matches(mut self, matches: bool) -> Self {
+        self.matches = matches;
+        self
+    }
+
+    /// Count the matches per value of `fields` ([`TypedResults::facets`]), the `size` most
+    /// common values of each (the server's default when `None`).
+    pub fn facets(mut self, fields: Vec<String>, size: Option<usize>) -> Self {
+        self.facets = fields;
+        self.facet_size = size;
+        self
+    }
+
+    /// Collapse hits sharing a value of `field`, such as `_content_hash`; each kept hit carries
+    /// `_duplicates_count` ([`TypedHit::duplicates_count`]).
+    pub fn dedupe_by(mut self, field: impl Into<String>) -> Self {
+        self.dedupe_by = Some(field.into());
+        self
+    }
+
+    /// Asks the server to give up after `timeout` (sent as `X-Timeout-Ms`). A search that runs
+    /// out of time fails with status 504; its body holds the partial hits as JSON.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sends `span` as the `traceparent`, so the server's spans join the caller's trace.
+    pub fn trace(mut self, span: TraceContext) -> Self {
+        self.trace = Some(span);
+        self
+    }
+
+    /// Searches the snapshot `session` is pinned to (sent as `X-Search-Session`), so pages
+    /// fetched within a few minutes of each other don't reshuffle.
+    pub fn session(mut self, session: impl Into<String>) -> Self {
+        self.session = Some(session.into());
+        self
+    }
+
+    /// Hits as the server renders them: one object per document, keyed by field name.
+    pub async fn send(self) -> ClientResult<Vec<serde_json::Value>> {
+        json(self.client.send(self.request()).await?).await
+    }
+
+    /// Hits in the v2 envelope, their fields as plain JSON instead of debug strings.
+    pub async fn send_typed(self) -> ClientResult<TypedResults> {
+        json(self.client.send(self.request().header("Accept", V2_MEDIA_TYPE)).await?).await
+    }
+
+    fn request(&self) -> reqwest::RequestBuilder {
+        let mut params = vec![("q", self.q.clone())];
+        if let Some(limit) = self.limit {
+            params.push(("limit", limit.to_string()));
+        }
+        if self.include_archive {
+            params.push(("include_archive", "true".to_string()));
+        }
+        if self.matches {
+            params.push(("matches", "true".to_string()));
+        }
+        if !self.facets.is_empty() {
+            params.push(("facets", self.facets.join(",")));
+        }
+        if let Some(size) = self.facet_size {
+            params.push(("facet_size", size.to_string()));
+        }
+        if let Some(field) = &self.dedupe_by {
+            params.push(("dedupe_by", field.clone()));
+        }
+        let mut request = self.client.http.get(self.client.url("/search")).query(&params);
+        if let Some(timeout) = self.timeout {
+            request = request.header("X-Timeout-Ms", timeout.as_millis().to_string());
+        }
+        if let Some(span) = &self.trace {
+            request = request.header(TRACEPARENT, span.to_string());
+        }
+        if let Some(session) = &self.session {
+            request = request.header(SESSION_HEADER, session);
+        }
+        request
+    }
+}
+
+async fn check(resp: reqwest::Response) -> ClientResult<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(ClientError::Status { status: status.as_u16(), body })
+}
+
+async fn json<T: DeserializeOwned>(resp: reqwest::Response) -> ClientResult<T> {
+    Ok(check(resp).await?.json().await?)
+}
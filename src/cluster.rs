@@ -0,0 +1,156 @@
+//! Groups search hits by content similarity: TF-IDF vectors over the analyzed `title` and
+//! `body` tokens (n-grams with the default analyzers), clustered with spherical k-means.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tantivy::schema::Value;
+use tantivy::{Searcher, Term};
+
+use crate::error::ServiceResult;
+use crate::service::{SearchHit, SearchService};
+
+/// Upper bound on clusters per request.
+pub const MAX_CLUSTERS: usize = 10;
+const KMEANS_ROUNDS: usize = 10;
+const LABEL_TERMS: usize = 3;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HitCluster {
+    /// Terms weighing most in the cluster centroid relative to all hits
+    pub label: Vec<String>,
+    /// Positions of the member hits in the hit list, best-ranked first
+    pub hits: Vec<usize>,
+    /// Position of the hit closest to the centroid
+    pub representative: usize,
+}
+
+type Vector = HashMap<String, f32>;
+
+impl SearchService {
+    /// Splits `hits` into at most `k` clusters. Hits without any indexed text form no vector
+    /// and join the first cluster. Deterministic: seeds are the top hit and then, repeatedly,
+    /// the hit least similar to every seed so far.
+    pub fn cluster_hits(&self, hits: &[SearchHit], k: usize) -> ServiceResult<Vec<HitCluster>> {
+        let k = k.clamp(1, MAX_CLUSTERS).min(hits.len());
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let searcher = self.searcher();
+        let vectors = tf_idf(&searcher, hits)?;
+
+        let mut centroids: Vec<Vector> = vec![vectors[0].clone()];
+        while centroids.len() < k {
+            let farthest = (0..vectors.len())
+                .min_by(|&a, &b| {
+                    let near = |v: &Vector| centroids.iter().map(|c| cosine(v, c)).fold(f32::MIN, f32::max);
+                    near(&vectors[a]).total_cmp(&near(&vectors[b])).then(a.cmp(&b))
+                })
+                .unwrap();
+            centroids.push(vectors[farthest].clone());
+        }
+
+        let mut assignment = vec![0; vectors.len()];
+        for _ in 0..KMEANS_ROUNDS {
+            let next: Vec<usize> = vectors.iter().map(|v| nearest(v, &centroids)).collect();
+            let settled = next == assignment;
+            assignment = next;
+            centroids = (0..k).map(|c| centroid(vectors.iter().zip(&assignment).filter(|(_, &a)| a == c).map(|(v, _)| v))).collect();
+            if settled {
+                break;
+            }
+        }
+
+        // Labels favour terms that set a cluster apart from the hit list as a whole
+        let overall = centroid(vectors.iter());
+        let mut clusters = Vec::new();
+        for (c, center) in centroids.iter().enumerate() {
+            let members: Vec<usize> = (0..vectors.len()).filter(|&i| assignment[i] == c).collect();
+            let Some(&first) = members.first() else { continue };
+            let representative = members
+                .iter()
+                .copied()
+                .max_by(|&a, &b| cosine(&vectors[a], center).total_cmp(&cosine(&vectors[b], center)).then(b.cmp(&a)))
+                .unwrap_or(first);
+            // Longest grams read best and overlap least (`arc` over `ar` + `rc`)
+            let longest = center.keys().map(|t| t.chars().count()).max().unwrap_or(0);
+            let mut weights: Vec<(&String, f32)> = center
+                .iter()
+                .filter(|(t, _)| t.chars().count() == longest)
+                .map(|(t, w)| (t, w - overall.get(t).copied().unwrap_or(0.0)))
+                .collect();
+            weights.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+            let label = weights.into_iter().take(LABEL_TERMS).map(|(t, _)| t.clone()).collect();
+            clusters.push(HitCluster { label, hits: members, representative });
+        }
+        Ok(clusters)
+    }
+}
+
+/// Unit-length TF-IDF vector per hit, with IDF taken from the whole index.
+fn tf_idf(searcher: &Searcher, hits: &[SearchHit]) -> ServiceResult<Vec<Vector>> {
+    let index = searcher.index();
+    let schema = index.schema();
+    let num_docs = searcher.num_docs().max(1) as f32;
+    let mut idf_cache: HashMap<(String, String), f32> = HashMap::new();
+    let mut vectors = Vec::with_capacity(hits.len());
+    for hit in hits {
+        let mut vector = Vector::new();
+        for name in ["title", "body"] {
+            let field = schema.get_field(name)?;
+            let mut analyzer = index.tokenizer_for_field(field)?;
+            for value in hit.doc.get_all(field) {
+                let Some(text) = value.as_str() else { continue };
+                let mut tokens = analyzer.token_stream(text);
+                while tokens.advance() {
+                    let token = tokens.token().text.clone();
+                    // n-grams spanning a word boundary carry no topic
+                    if token.chars().any(|c| c.is_whitespace() || c.is_ascii_punctuation()) {
+                        continue;
+                    }
+                    let idf = match idf_cache.get(&(name.to_string(), token.clone())) {
+                        Some(idf) => *idf,
+                        None => {
+                            let df = searcher.doc_freq(&Term::from_field_text(field, &token))?.max(1) as f32;
+                            let idf = (num_docs / df).ln() + 1.0;
+                            idf_cache.insert((name.to_string(), token.clone()), idf);
+                            idf
+                        }
+                    };
+                    *vector.entry(token).or_default() += idf;
+                }
+            }
+        }
+        normalize(&mut vector);
+        vectors.push(vector);
+    }
+    Ok(vectors)
+}
+
+fn normalize(v: &mut Vector) {
+    let norm = v.values().map(|w| w * w).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.values_mut().for_each(|w| *w /= norm);
+    }
+}
+
+/// Cosine similarity of two unit vectors.
+fn cosine(a: &Vector, b: &Vector) -> f32 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    small.iter().filter_map(|(t, w)| large.get(t).map(|x| w * x)).sum()
+}
+
+fn nearest(v: &Vector, centroids: &[Vector]) -> usize {
+    (0..centroids.len()).max_by(|&a, &b| cosine(v, &centroids[a]).total_cmp(&cosine(v, &centroids[b])).then(b.cmp(&a))).unwrap_or(0)
+}
+
+fn centroid<'a>(members: impl Iterator<Item = &'a Vector>) -> Vector {
+    let mut sum = Vector::new();
+    for v in members {
+        for (t, w) in v {
+            *sum.entry(t.clone()).or_default() += w;
+        }
+    }
+    normalize(&mut sum);
+    sum
+}
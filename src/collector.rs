@@ -0,0 +1,318 @@
+//! Newest-first collection by `create_at` (or `_indexed_at`) that can stop early on a sorted
+//! index.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::columnar::Column;
+use tantivy::query::Weight;
+use tantivy::{DocAddress, DocId, DocSet, Score, SegmentOrdinal, SegmentReader, TERMINATED};
+
+/// How much of the index a newest-first scan touched.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanProfile {
+    pub segments: usize,
+    /// Matching live documents looked at
+    pub docs_scanned: u64,
+    /// Documents never visited because their segment stopped early
+    pub docs_skipped: u64,
+    pub early_terminated_segments: usize,
+}
+
+impl ScanProfile {
+    fn add(&mut self, other: &ScanProfile) {
+        self.segments += other.segments;
+        self.docs_scanned += other.docs_scanned;
+        self.docs_skipped += other.docs_skipped;
+        self.early_terminated_segments += other.early_terminated_segments;
+    }
+}
+
+/// Top `limit` documents by the i64 fast field `field` descending, as `(value, address)`.
+///
+/// With `early_terminate`, each segment stops after its first `limit` live matches. That is
+/// only correct when segments are stored newest-first by `field` (see
+/// [`SearchService::sorted_by_create_at`](crate::SearchService::sorted_by_create_at)).
+pub struct NewestFirst {
+    /// `create_at` or `_indexed_at`
+    pub field: &'static str,
+    pub limit: usize,
+    pub early_terminate: bool,
+}
+
+pub struct NewestFirstSegment {
+    segment_ord: SegmentOrdinal,
+    create_at: Column<i64>,
+    limit: usize,
+    // Min-heap of the newest hits so far; ties keep the lower doc id
+    top: BinaryHeap<Reverse<(i64, Reverse<DocId>)>>,
+    scanned: u64,
+}
+
+impl SegmentCollector for NewestFirstSegment {
+    type Fruit = (Vec<(i64, DocAddress)>, ScanProfile);
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        self.scanned += 1;
+        let create_at = self.create_at.first(doc).unwrap_or(i64::MIN);
+        self.top.push(Reverse((create_at, Reverse(doc))));
+        if self.top.len() > self.limit {
+            self.top.pop();
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let hits = self
+            .top
+            .into_iter()
+            .map(|Reverse((create_at, Reverse(doc)))| (create_at, DocAddress::new(self.segment_ord, doc)))
+            .collect();
+        let profile = ScanProfile { segments: 1, docs_scanned: self.scanned, ..ScanProfile::default() };
+        (hits, profile)
+    }
+}
+
+impl Collector for NewestFirst {
+    type Fruit = (Vec<(i64, DocAddress)>, ScanProfile);
+    type Child = NewestFirstSegment;
+
+    fn for_segment(&self, segment_ord: SegmentOrdinal, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(NewestFirstSegment {
+            segment_ord,
+            create_at: reader.fast_fields().i64(self.field)?,
+            limit: self.limit,
+            top: BinaryHeap::with_capacity(self.limit + 1),
+            scanned: 0,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, fruits: Vec<Self::Fruit>) -> tantivy::Result<Self::Fruit> {
+        let mut hits = Vec::new();
+        let mut profile = ScanProfile::default();
+        for (segment_hits, segment_profile) in fruits {
+            hits.extend(segment_hits);
+            profile.add(&segment_profile);
+        }
+        hits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        hits.truncate(self.limit);
+        Ok((hits, profile))
+    }
+
+    // Drives the scorer directly instead of `Weight::for_each` so the scan can stop mid-segment.
+    fn collect_segment(&self, weight: &dyn Weight, segment_ord: u32, reader: &SegmentReader) -> tantivy::Result<Self::Fruit> {
+        let mut child = self.for_segment(segment_ord, reader)?;
+        let alive = reader.alive_bitset();
+        let mut scorer = weight.scorer(reader, 1.0)?;
+        let mut doc = scorer.doc();
+        let mut stopped_at = None;
+        while doc != TERMINATED {
+            if alive.is_none_or(|a| a.is_alive(doc)) {
+                child.collect(doc, 0.0);
+                if self.early_terminate && child.top.len() >= self.limit {
+                    stopped_at = Some(doc);
+                    break;
+                }
+            }
+            doc = scorer.advance();
+        }
+        let (hits, mut profile) = child.harvest();
+        if let Some(last) = stopped_at {
+            profile.docs_skipped = u64::from(reader.max_doc() - last - 1);
+            profile.early_terminated_segments = 1;
+        }
+        Ok((hits, profile))
+    }
+}
+
+/// Top `limit` documents by score, like [`TopDocs`](tantivy::collector::TopDocs) but pruning
+/// harder: once `limit` hits are held, only documents scoring above `factor` times the
+/// current k-th best are considered. Term and boolean queries use that threshold to skip
+/// whole posting blocks (block-max WAND), so a `factor` above 1.0 trades recall of the
+/// lower-ranked hits for latency; 1.0 gives the exact top `limit`.
+pub struct ApproxTopDocs {
+    pub limit: usize,
+    pub factor: f32,
+}
+
+/// A scored hit ordered by score, then by ascending doc id.
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(Score, DocId);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(other.1.cmp(&self.1))
+    }
+}
+
+pub struct ApproxTopSegment {
+    segment_ord: SegmentOrdinal,
+    limit: usize,
+    // Min-heap of the best hits so far
+    top: BinaryHeap<Reverse<Scored>>,
+}
+
+impl ApproxTopSegment {
+    fn push(&mut self, doc: DocId, score: Score) {
+        self.top.push(Reverse(Scored(score, doc)));
+        if self.top.len() > self.limit {
+            self.top.pop();
+        }
+    }
+
+    /// Score a document must beat to matter, before applying the pruning factor.
+    fn kth_score(&self) -> Option<Score> {
+        (self.top.len() >= self.limit).then(|| self.top.peek().map_or(Score::MIN, |Reverse(s)| s.0))
+    }
+}
+
+impl SegmentCollector for ApproxTopSegment {
+    type Fruit = Vec<(Score, DocAddress)>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        self.push(doc, score);
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let mut hits: Vec<_> = self.top.into_iter().map(|Reverse(s)| s).collect();
+        hits.sort_by(|a, b| b.cmp(a));
+        hits.into_iter().map(|Scored(score, doc)| (score, DocAddress::new(self.segment_ord, doc))).collect()
+    }
+}
+
+impl Collector for ApproxTopDocs {
+    type Fruit = Vec<(Score, DocAddress)>;
+    type Child = ApproxTopSegment;
+
+    fn for_segment(&self, segment_ord: SegmentOrdinal, _reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(ApproxTopSegment { segment_ord, limit: self.limit, top: BinaryHeap::with_capacity(self.limit + 1) })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, fruits: Vec<Self::Fruit>) -> tantivy::Result<Self::Fruit> {
+        let mut hits: Vec<_> = fruits.into_iter().flatten().collect();
+        hits.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        hits.truncate(self.limit);
+        Ok(hits)
+    }
+
+    fn collect_segment(&self, weight: &dyn Weight, segment_ord: u32, reader: &SegmentReader) -> tantivy::Result<Self::Fruit> {
+        let mut child = self.for_segment(segment_ord, reader)?;
+        if self.limit == 0 {
+            return Ok(child.harvest());
+        }
+        let alive = reader.alive_bitset();
+        let factor = self.factor.max(1.0);
+        let mut threshold = Score::MIN;
+        weight.for_each_pruning(Score::MIN, reader, &mut |doc, score| {
+            if alive.is_none_or(|a| a.is_alive(doc)) {
+                child.push(doc, score);
+                // Scores can be negative under boosts; only inflate positive thresholds
+                threshold = child.kth_score().map_or(Score::MIN, |s| if s > 0.0 { s * factor } else { s });
+            }
+            threshold
+        })?;
+        Ok(child.harvest())
+    }
+}
+
+/// Whether a [`TotalHits`] count is exact or a lower bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relation {
+    Eq,
+    Gte,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TotalHits {
+    pub value: u64,
+    pub relation: Relation,
+}
+
+impl TotalHits {
+    /// Sum of two counts, capped at `up_to`.
+    pub fn merge(self, other: TotalHits, up_to: Option<u64>) -> TotalHits {
+        let value = self.value + other.value;
+        let relation = if self.relation == Relation::Gte || other.relation == Relation::Gte { Relation::Gte } else { Relation::Eq };
+        match up_to {
+            Some(cap) if value > cap => TotalHits { value: cap, relation: Relation::Gte },
+            _ => TotalHits { value, relation },
+        }
+    }
+}
+
+/// Counts live matches, stopping once `up_to` have been seen (exact when `None`). Reaching the
+/// cap reports it as a lower bound instead of scanning the rest of the index.
+pub struct CountUpTo {
+    pub up_to: Option<u64>,
+}
+
+pub struct CountUpToSegment(u64);
+
+impl SegmentCollector for CountUpToSegment {
+    type Fruit = TotalHits;
+
+    fn collect(&mut self, _doc: DocId, _score: Score) {
+        self.0 += 1;
+    }
+
+    fn harvest(self) -> TotalHits {
+        TotalHits { value: self.0, relation: Relation::Eq }
+    }
+}
+
+impl Collector for CountUpTo {
+    type Fruit = TotalHits;
+    type Child = CountUpToSegment;
+
+    fn for_segment(&self, _segment_ord: SegmentOrdinal, _reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(CountUpToSegment(0))
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, fruits: Vec<TotalHits>) -> tantivy::Result<TotalHits> {
+        let none = TotalHits { value: 0, relation: Relation::Eq };
+        Ok(fruits.into_iter().fold(none, |acc, t| acc.merge(t, self.up_to)))
+    }
+
+    fn collect_segment(&self, weight: &dyn Weight, _segment_ord: u32, reader: &SegmentReader) -> tantivy::Result<TotalHits> {
+        let Some(cap) = self.up_to else {
+            // Exact counts can use the weight's own (often posting-list based) counter
+            return Ok(TotalHits { value: u64::from(weight.count(reader)?), relation: Relation::Eq });
+        };
+        let alive = reader.alive_bitset();
+        let mut scorer = weight.scorer(reader, 1.0)?;
+        let mut doc = scorer.doc();
+        let mut count = 0;
+        while doc != TERMINATED {
+            if alive.is_none_or(|a| a.is_alive(doc)) {
+                count += 1;
+                if count > cap {
+                    return Ok(TotalHits { value: cap, relation: Relation::Gte });
+                }
+            }
+            doc = scorer.advance();
+        }
+        Ok(TotalHits { value: count, relation: Relation::Eq })
+    }
+}
@@ -0,0 +1,184 @@
+//! Comments on posts, kept in their own index and joined to posts at query time through
+//! `parent_id`. Flattening comments into the post body would lose which text belongs to which
+//! comment; joining keeps both sides searchable on their own fields.
+//!
+//! Joins resolve the matching side to an id set and filter the other side with it, so their
+//! cost grows with the number of matches on the inner side.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::query::{AllQuery, Query, QueryParser, TermSetQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, FAST, INDEXED, STORED, STRING};
+use tantivy::{IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term};
+
+use crate::error::ServiceResult;
+use crate::query::{parse_with_parser, QueryLimits};
+use crate::schema::open_index;
+
+/// Fields a query term without a field name is searched in.
+pub const DEFAULT_SEARCH_FIELDS: &[&str] = &["body", "author"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub id: String,
+    /// Id of the post this comment belongs to
+    pub parent_id: String,
+    pub body: String,
+    #[serde(default)]
+    pub author: String,
+    pub create_at: Option<i64>,
+}
+
+impl Comment {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id must not be empty".to_string());
+        }
+        if self.parent_id.trim().is_empty() {
+            return Err("parent_id must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CommentHit {
+    pub score: f32,
+    #[serde(flatten)]
+    pub comment: Comment,
+}
+
+pub fn comment_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+    let body_indexing = TextFieldIndexing::default()
+        .set_tokenizer("zh_ngram")
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    schema_builder.add_text_field("id", STRING | STORED);
+    schema_builder.add_text_field("parent_id", STRING | STORED);
+    schema_builder.add_text_field("body", TextOptions::default().set_indexing_options(body_indexing).set_stored());
+    schema_builder.add_text_field("author", STRING | STORED);
+    schema_builder.add_i64_field("create_at", INDEXED | STORED | FAST);
+    schema_builder.build()
+}
+
+fn to_document(schema: &Schema, comment: Comment) -> TantivyDocument {
+    let mut document = TantivyDocument::default();
+    document.add_text(schema.get_field("id").unwrap(), comment.id);
+    document.add_text(schema.get_field("parent_id").unwrap(), comment.parent_id);
+    document.add_text(schema.get_field("body").unwrap(), comment.body);
+    document.add_text(schema.get_field("author").unwrap(), comment.author);
+    if let Some(ts) = comment.create_at {
+        document.add_i64(schema.get_field("create_at").unwrap(), ts);
+    }
+    document
+}
+
+fn from_document(schema: &Schema, doc: &TantivyDocument) -> Comment {
+    let text = |name: &str| {
+        let field = schema.get_field(name).unwrap();
+        doc.get_first(field).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+    };
+    let create_at = doc.get_first(schema.get_field("create_at").unwrap()).and_then(|v| v.as_i64());
+    Comment { id: text("id"), parent_id: text("parent_id"), body: text("body"), author: text("author"), create_at }
+}
+
+/// Documents whose `field` holds one of `ids`.
+pub(crate) fn ids_query(field: Field, ids: &BTreeSet<String>) -> Box<dyn Query> {
+    Box::new(TermSetQuery::new(ids.iter().map(|id| Term::from_field_text(field, id))))
+}
+
+/// Stored values of `field` on every document of `searcher` matching `query`.
+pub(crate) fn matching_values(searcher: &Searcher, query: &dyn Query, field: Field) -> ServiceResult<BTreeSet<String>> {
+    let mut values = BTreeSet::new();
+    for addr in searcher.search(query, &DocSetCollector)? {
+        let doc: TantivyDocument = searcher.doc(addr)?;
+        if let Some(value) = doc.get_first(field).and_then(|v| v.as_str()) {
+            values.insert(value.to_string());
+        }
+    }
+    Ok(values)
+}
+
+/// The comments index. Like the hot index it is committed and its searcher swapped on every
+/// [`SearchService::refresh`](crate::SearchService::refresh).
+pub struct CommentStore {
+    pub writer: Mutex<IndexWriter>,
+    pub reader: IndexReader,
+    pub current_searcher: ArcSwap<Searcher>,
+}
+
+impl CommentStore {
+    pub fn open(path: &PathBuf, in_memory: bool) -> anyhow::Result<Self> {
+        let index = open_index(path, comment_schema(), IndexSettings::default(), in_memory)?;
+        let writer = index.writer(15_000_000)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let current_searcher = ArcSwap::from_pointee(reader.searcher());
+        Ok(CommentStore { writer: Mutex::new(writer), reader, current_searcher })
+    }
+
+    pub fn writer(&self) -> MutexGuard<'_, IndexWriter> {
+        match self.writer.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    pub fn searcher(&self) -> Arc<Searcher> {
+        self.current_searcher.load_full()
+    }
+
+    /// Commits, reloads and swaps in a fresh comments searcher.
+    pub fn refresh(&self) -> tantivy::Result<()> {
+        self.writer().commit()?;
+        self.reader.reload()?;
+        self.current_searcher.store(Arc::new(self.reader.searcher()));
+        Ok(())
+    }
+
+    /// Adds `comment`, replacing any comment with the same id.
+    pub fn upsert(&self, comment: Comment) -> tantivy::Result<u64> {
+        let writer = self.writer();
+        let schema = writer.index().schema();
+        writer.delete_term(Term::from_field_text(schema.get_field("id").unwrap(), &comment.id));
+        writer.add_document(to_document(&schema, comment))
+    }
+
+    pub fn delete(&self, id: &str) -> u64 {
+        let writer = self.writer();
+        let f_id = writer.index().schema().get_field("id").unwrap();
+        writer.delete_term(Term::from_field_text(f_id, id))
+    }
+
+    /// Parses `q` over the comment `body` and `author`; blank matches every comment.
+    pub fn parse_query(&self, searcher: &Searcher, q: &str, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+        if q.trim().is_empty() {
+            return Ok(Box::new(AllQuery));
+        }
+        let schema = searcher.index().schema();
+        let default_fields = DEFAULT_SEARCH_FIELDS.iter().map(|name| schema.get_field(name).unwrap()).collect();
+        parse_with_parser(&QueryParser::for_index(searcher.index(), default_fields), q, limits)
+    }
+
+    /// Ids of the posts having at least one comment matching `q`.
+    pub fn parent_ids(&self, q: &str, limits: &QueryLimits) -> ServiceResult<BTreeSet<String>> {
+        let searcher = self.searcher();
+        let query = self.parse_query(&searcher, q, limits)?;
+        matching_values(&searcher, query.as_ref(), searcher.index().schema().get_field("parent_id").unwrap())
+    }
+
+    /// Top `limit` comments matching `query`.
+    pub fn search(&self, searcher: &Searcher, query: &dyn Query, limit: usize) -> ServiceResult<Vec<CommentHit>> {
+        let schema = searcher.index().schema();
+        let mut hits = Vec::new();
+        for (score, addr) in searcher.search(query, &TopDocs::with_limit(limit))? {
+            let doc: TantivyDocument = searcher.doc(addr)?;
+            hits.push(CommentHit { score, comment: from_document(&schema, &doc) });
+        }
+        Ok(hits)
+    }
+}
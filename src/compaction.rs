@@ -0,0 +1,199 @@
+//! Compaction analysis: per segment of each posts tier, its size, deleted documents and term
+//! dictionary, and the merges worth running with what they'd save. `GET /admin/compaction`
+//! reports, `POST /admin/compaction` runs the recommended merges.
+//!
+//! Two things are recommended:
+//! - `expunge_deletes`: rewriting segments where deleted documents take at least
+//!   [`DELETED_RATIO_THRESHOLD`] of the space, which drops them; saves their share of the size
+//! - `merge_small`: merging [`SMALL_SEGMENTS_THRESHOLD`] or more segments under
+//!   [`SMALL_SEGMENT_BYTES`] into one, so searches visit fewer segments; their term
+//!   dictionaries mostly hold the same terms, so all but the largest are counted as saved
+//!
+//! Estimates come from the segments' space usage and are rough: deleted documents are taken to
+//! be as large as live ones.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+use tantivy::{IndexWriter, Searcher, SegmentId};
+
+use crate::error::ServiceResult;
+use crate::service::{SearchService, Tier};
+use crate::now_secs;
+
+/// Share of a segment's documents deleted before it is worth rewriting.
+pub const DELETED_RATIO_THRESHOLD: f64 = 0.2;
+
+/// Segments below this size count as small.
+pub const SMALL_SEGMENT_BYTES: u64 = 4 * 1024 * 1024;
+
+/// How many small segments make a merge worthwhile.
+pub const SMALL_SEGMENTS_THRESHOLD: usize = 5;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SegmentReport {
+    pub id: String,
+    /// Documents, deleted ones included
+    pub max_doc: u32,
+    pub deleted_docs: u32,
+    pub deleted_ratio: f64,
+    pub size_bytes: u64,
+    pub term_dict_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionAction {
+    ExpungeDeletes,
+    MergeSmall,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Recommendation {
+    pub action: CompactionAction,
+    pub segments: Vec<String>,
+    pub reason: String,
+    pub estimated_savings_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TierCompaction {
+    pub tier: Tier,
+    pub segments: Vec<SegmentReport>,
+    pub size_bytes: u64,
+    pub live_docs: u64,
+    pub deleted_docs: u64,
+    pub recommendations: Vec<Recommendation>,
+    pub estimated_savings_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CompactionReport {
+    pub generated_at: i64,
+    pub tiers: Vec<TierCompaction>,
+    pub estimated_savings_bytes: u64,
+}
+
+/// A recommended merge that was run, or why it wasn't.
+#[derive(Serialize, Debug, Clone)]
+pub struct AppliedMerge {
+    pub tier: Tier,
+    pub action: CompactionAction,
+    pub segments: Vec<String>,
+    pub merged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CompactionRun {
+    /// The analysis the merges were chosen from
+    pub before: CompactionReport,
+    pub merges: Vec<AppliedMerge>,
+    pub after: CompactionReport,
+}
+
+fn analyze_tier(tier: Tier, searcher: &Searcher) -> ServiceResult<TierCompaction> {
+    let mut segments = Vec::new();
+    for reader in searcher.segment_readers() {
+        let usage = reader.space_usage()?;
+        let (max_doc, deleted_docs) = (reader.max_doc(), reader.num_deleted_docs());
+        segments.push(SegmentReport {
+            id: reader.segment_id().uuid_string(),
+            max_doc,
+            deleted_docs,
+            deleted_ratio: if max_doc == 0 { 0.0 } else { f64::from(deleted_docs) / f64::from(max_doc) },
+            size_bytes: usage.total().get_bytes(),
+            term_dict_bytes: usage.termdict().total().get_bytes(),
+        });
+    }
+    // Largest first, as merges would leave them
+    segments.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then_with(|| a.id.cmp(&b.id)));
+
+    let mut recommendations = Vec::new();
+    let deleted: Vec<&SegmentReport> = segments.iter().filter(|s| s.deleted_docs > 0 && s.deleted_ratio >= DELETED_RATIO_THRESHOLD).collect();
+    if !deleted.is_empty() {
+        let docs: u32 = deleted.iter().map(|s| s.deleted_docs).sum();
+        recommendations.push(Recommendation {
+            action: CompactionAction::ExpungeDeletes,
+            segments: deleted.iter().map(|s| s.id.clone()).collect(),
+            reason: format!("{} deleted documents in {} segments at least {:.0}% deleted", docs, deleted.len(), DELETED_RATIO_THRESHOLD * 100.0),
+            estimated_savings_bytes: deleted.iter().map(|s| (s.size_bytes as f64 * s.deleted_ratio) as u64).sum(),
+        });
+    }
+    // Segments already rewritten for their deletes are left to that merge
+    let expunged: HashSet<&str> = deleted.iter().map(|s| s.id.as_str()).collect();
+    let small: Vec<&SegmentReport> =
+        segments.iter().filter(|s| s.size_bytes < SMALL_SEGMENT_BYTES && !expunged.contains(s.id.as_str())).collect();
+    if small.len() >= SMALL_SEGMENTS_THRESHOLD {
+        let dicts: u64 = small.iter().map(|s| s.term_dict_bytes).sum();
+        let largest = small.iter().map(|s| s.term_dict_bytes).max().unwrap_or(0);
+        recommendations.push(Recommendation {
+            action: CompactionAction::MergeSmall,
+            segments: small.iter().map(|s| s.id.clone()).collect(),
+            reason: format!("{} segments under {} bytes; searches visit each of them", small.len(), SMALL_SEGMENT_BYTES),
+            estimated_savings_bytes: dicts - largest,
+        });
+    }
+
+    Ok(TierCompaction {
+        tier,
+        size_bytes: segments.iter().map(|s| s.size_bytes).sum(),
+        live_docs: segments.iter().map(|s| u64::from(s.max_doc - s.deleted_docs)).sum(),
+        deleted_docs: segments.iter().map(|s| u64::from(s.deleted_docs)).sum(),
+        estimated_savings_bytes: recommendations.iter().map(|r| r.estimated_savings_bytes).sum(),
+        recommendations,
+        segments,
+    })
+}
+
+/// Runs `recommendation` on `writer`, waiting for the merge to finish.
+fn apply(writer: &mut IndexWriter, tier: Tier, recommendation: &Recommendation) -> AppliedMerge {
+    let ids: Vec<SegmentId> = recommendation.segments.iter().filter_map(|id| SegmentId::from_uuid_string(id).ok()).collect();
+    let result = writer.merge(&ids).wait();
+    AppliedMerge {
+        tier,
+        action: recommendation.action,
+        segments: recommendation.segments.clone(),
+        merged: result.is_ok(),
+        // Most often a background merge holding some of the segments
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+impl SearchService {
+    /// Analyzes the committed segments of the hot and archive tiers.
+    pub fn compaction_report(&self) -> ServiceResult<CompactionReport> {
+        let tiers = vec![
+            analyze_tier(Tier::Hot, &self.searcher())?,
+            analyze_tier(Tier::Archive, &self.archive().current_searcher.load())?,
+        ];
+        Ok(CompactionReport {
+            generated_at: now_secs(),
+            estimated_savings_bytes: tiers.iter().map(|t| t.estimated_savings_bytes).sum(),
+            tiers,
+        })
+    }
+
+    /// Runs every merge the report recommends, then swaps in searchers over the merged
+    /// segments and deletes the old segments' files.
+    pub fn compact(&self) -> ServiceResult<CompactionRun> {
+        self.check_writable()?;
+        let before = self.compaction_report()?;
+        let mut merges = Vec::new();
+        for tier in &before.tiers {
+            for recommendation in &tier.recommendations {
+                merges.push(match tier.tier {
+                    Tier::Hot => apply(&mut self.writer(), tier.tier, recommendation),
+                    Tier::Archive => apply(&mut self.archive().writer(), tier.tier, recommendation),
+                });
+            }
+        }
+        if merges.iter().any(|m| m.merged) {
+            self.refresh()?;
+            self.writer().garbage_collect_files().wait()?;
+            self.archive().writer().garbage_collect_files().wait()?;
+        }
+        Ok(CompactionRun { before, merges, after: self.compaction_report()? })
+    }
+}
@@ -0,0 +1,39 @@
+//! Conditional GETs of searches and documents: `/search` and `/doc` answer with an `ETag` and
+//! a 304 to an `If-None-Match` listing it, so CDNs and browsers can cache read-heavy public
+//! search pages and revalidate them for the price of a header.
+//!
+//! Tags are derived from the generations of the searchers an answer can be read from — the hot
+//! and archive tiers and the comments and authors indexes, or those of a pinned session — and a
+//! hash of the request, so any refresh, merge or swap makes every earlier tag stale. Searcher
+//! generations count up from zero in each process, so tags also carry the service's random
+//! instance id and never survive a restart.
+
+use crate::service::SearchService;
+use crate::session::Snapshot;
+
+/// Whether an `If-None-Match` header value lists `etag` (given without quotes), or is `*`.
+/// Weak tags compare like strong ones.
+pub fn etag_listed(if_none_match: &str, etag: &str) -> bool {
+    let mut listed = if_none_match.split(',').map(|tag| tag.trim().trim_start_matches("W/").trim_matches('"'));
+    listed.any(|tag| tag == "*" || tag == etag)
+}
+
+impl SearchService {
+    /// ETag, without quotes, of the answer to the read `request_key` stands for (the URI and
+    /// whatever headers change the answer) as the current searchers, or `snapshot`'s, give it.
+    pub fn read_etag(&self, request_key: &str, snapshot: Option<&Snapshot>) -> String {
+        let (hot, archive) = match snapshot {
+            Some(snapshot) => (snapshot.hot.generation().generation_id(), snapshot.archive.generation().generation_id()),
+            None => (self.searcher().generation().generation_id(), self.archive.current_searcher.load().generation().generation_id()),
+        };
+        let comments = self.comments.searcher().generation().generation_id();
+        let authors = self.authors.current_searcher.load().generation().generation_id();
+        let mut input = Vec::with_capacity(40 + request_key.len());
+        for n in [self.instance_id, hot, archive, comments, authors] {
+            input.extend_from_slice(&n.to_le_bytes());
+        }
+        input.extend_from_slice(request_key.as_bytes());
+        let digest = ring::digest::digest(&ring::digest::SHA256, &input);
+        digest.as_ref()[..16].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
@@ -0,0 +1,138 @@
+//! Collapsing hits that share a value: `dedupe_by=_content_hash` keeps the best-scoring post of
+//! each group of identical ones, as aggregated feeds index the same article from several
+//! sources, and reports how many others it stands for. Values are read from the field's fast
+//! column; posts without one are never collapsed.
+//!
+//! Every match is looked at, so a deduplicated search costs as much as a facet count.
+
+use std::collections::HashMap;
+
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::columnar::StrColumn;
+use tantivy::schema::{FieldType, Schema};
+use tantivy::{DocAddress, DocId, Score, SegmentOrdinal, SegmentReader};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::schema::{CONTENT_HASH_FIELD, RESTRICTED_FIELDS};
+
+/// Checks that hits can be collapsed by `field`: a string fast field.
+pub(crate) fn check(schema: &Schema, field: &str) -> ServiceResult<()> {
+    let entry = schema
+        .get_field(field)
+        .ok()
+        .filter(|_| !RESTRICTED_FIELDS.contains(&field))
+        .map(|f| schema.get_field_entry(f))
+        .ok_or_else(|| ServiceError::Invalid(format!("unknown field: {}", field)))?;
+    match entry.field_type() {
+        FieldType::Str(options) if options.is_fast() => Ok(()),
+        // Indexes created before the hash became a fast field
+        _ if field == CONTENT_HASH_FIELD => {
+            Err(ServiceError::Invalid(format!("{} isn't a fast field on this index; reindex it to dedupe by it", field)))
+        }
+        _ => Err(ServiceError::Invalid(format!("can't dedupe by {}: only string fast fields such as _content_hash dedupe", field))),
+    }
+}
+
+/// The best hit of a group and how many other matches share its value.
+#[derive(Debug, Clone)]
+pub(crate) struct Group {
+    pub score: Score,
+    pub address: DocAddress,
+    pub duplicates: u64,
+}
+
+impl Group {
+    fn absorb(&mut self, other: Group) {
+        if other.score > self.score || (other.score == self.score && other.address < self.address) {
+            (self.score, self.address) = (other.score, other.address);
+        }
+        self.duplicates += other.duplicates + 1;
+    }
+}
+
+fn add<K: std::hash::Hash + Eq>(groups: &mut HashMap<K, Group>, key: K, group: Group) {
+    match groups.get_mut(&key) {
+        Some(existing) => existing.absorb(group),
+        None => {
+            groups.insert(key, group);
+        }
+    }
+}
+
+/// Top `limit` groups of matches by the score of their best hit.
+pub(crate) struct DedupeTopDocs {
+    pub field: String,
+    pub limit: usize,
+}
+
+pub(crate) struct DedupeSegment {
+    segment_ord: SegmentOrdinal,
+    column: Option<StrColumn>,
+    // By term ordinal, which is only meaningful within the segment
+    groups: HashMap<u64, Group>,
+    // Matches without a value, each a group of its own
+    single: Vec<Group>,
+    limit: usize,
+}
+
+impl SegmentCollector for DedupeSegment {
+    type Fruit = (HashMap<String, Group>, Vec<Group>);
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let group = Group { score, address: DocAddress::new(self.segment_ord, doc), duplicates: 0 };
+        match self.column.as_ref().and_then(|c| c.term_ords(doc).next()) {
+            Some(ord) => add(&mut self.groups, ord, group),
+            None => self.single.push(group),
+        }
+    }
+
+    fn harvest(mut self) -> Self::Fruit {
+        let mut groups = HashMap::with_capacity(self.groups.len());
+        let mut value = String::new();
+        for (ord, group) in self.groups {
+            let found = self.column.as_ref().is_some_and(|c| c.ord_to_str(ord, &mut value).unwrap_or(false));
+            match found {
+                true => add(&mut groups, value.clone(), group),
+                false => self.single.push(group),
+            }
+        }
+        // Ungrouped hits can't gain duplicates from other segments, so only the best matter
+        self.single.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.address.cmp(&b.address)));
+        self.single.truncate(self.limit);
+        (groups, self.single)
+    }
+}
+
+impl Collector for DedupeTopDocs {
+    type Fruit = Vec<Group>;
+    type Child = DedupeSegment;
+
+    fn for_segment(&self, segment_ord: SegmentOrdinal, reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(DedupeSegment {
+            segment_ord,
+            column: reader.fast_fields().str(&self.field)?,
+            groups: HashMap::new(),
+            single: Vec::new(),
+            limit: self.limit,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, fruits: Vec<(HashMap<String, Group>, Vec<Group>)>) -> tantivy::Result<Vec<Group>> {
+        let mut groups: HashMap<String, Group> = HashMap::new();
+        let mut top = Vec::new();
+        for (segment_groups, single) in fruits {
+            for (value, group) in segment_groups {
+                add(&mut groups, value, group);
+            }
+            top.extend(single);
+        }
+        top.extend(groups.into_values());
+        top.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.address.cmp(&b.address)));
+        top.truncate(self.limit);
+        Ok(top)
+    }
+}
@@ -0,0 +1,188 @@
+//! Degradation mode: during an incident the expensive parts of search are switched off so the
+//! core of it stays up. While degraded, `/search` ignores clustering, query expansion, the
+//! shingle boost, matches, snippets and facets, and `/aggs/composite` and `/significant_terms` answer 503;
+//! every response touched says so with `X-Degraded: true`.
+//!
+//! The switch is flipped by hand (`POST /admin/degrade`) or, with an error budget configured,
+//! automatically: searches slower than the latency objective count against the budget, and a
+//! window of searches over it degrades the service until a window within it.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwapOption;
+use serde::Deserialize;
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::service::SearchService;
+
+/// Response header flagging a degraded answer.
+pub const DEGRADED_HEADER: &str = "X-Degraded";
+
+/// Searches a window needs before it can degrade the service; fewer say little about latency.
+const MIN_WINDOW_SEARCHES: u64 = 20;
+
+#[derive(Debug, Clone)]
+pub struct DegradeConfig {
+    /// Searches taking longer than this are slow
+    pub slow_after: Duration,
+    /// Share of slow searches a window may have, e.g. 0.05
+    pub error_budget: f64,
+    /// How long each window of searches is
+    pub window: Duration,
+}
+
+/// Who decides whether the service is degraded.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DegradeMode {
+    /// The error budget, if one is configured
+    Auto,
+    On,
+    Off,
+}
+
+impl DegradeMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            DegradeMode::Auto => "auto",
+            DegradeMode::On => "on",
+            DegradeMode::Off => "off",
+        }
+    }
+
+    fn from_u8(mode: u8) -> Self {
+        match mode {
+            1 => DegradeMode::On,
+            2 => DegradeMode::Off,
+            _ => DegradeMode::Auto,
+        }
+    }
+}
+
+struct Window {
+    started: Instant,
+    searches: u64,
+    slow: u64,
+}
+
+pub struct Degradation {
+    config: ArcSwapOption<DegradeConfig>,
+    mode: AtomicU8,
+    /// Whether the last full window spent more than the error budget
+    engaged: AtomicBool,
+    window: Mutex<Window>,
+    /// Windows that degraded the service
+    trips: AtomicU64,
+    /// Requests answered degraded
+    degraded_responses: AtomicU64,
+}
+
+impl Degradation {
+    pub fn new(config: Option<DegradeConfig>) -> Self {
+        Degradation {
+            config: ArcSwapOption::from(config.map(Arc::new)),
+            mode: AtomicU8::new(0),
+            engaged: AtomicBool::new(false),
+            window: Mutex::new(Window { started: Instant::now(), searches: 0, slow: 0 }),
+            trips: AtomicU64::new(0),
+            degraded_responses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> Option<DegradeConfig> {
+        self.config.load().as_deref().cloned()
+    }
+
+    /// Judges searches from now on against `config`; the current window carries on. Without
+    /// an error budget the service leaves automatic degradation.
+    pub fn set_config(&self, config: Option<DegradeConfig>) {
+        if config.is_none() && self.engaged.swap(false, Ordering::Relaxed) {
+            log::info!("error budget removed: leaving degradation mode");
+        }
+        self.config.store(config.map(Arc::new));
+    }
+
+    pub fn mode(&self) -> DegradeMode {
+        DegradeMode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    pub fn set_mode(&self, mode: DegradeMode) {
+        let value = match mode {
+            DegradeMode::Auto => 0,
+            DegradeMode::On => 1,
+            DegradeMode::Off => 2,
+        };
+        self.mode.store(value, Ordering::Relaxed);
+    }
+
+    /// Whether expensive features are off right now.
+    pub fn is_degraded(&self) -> bool {
+        match self.mode() {
+            DegradeMode::On => true,
+            DegradeMode::Off => false,
+            DegradeMode::Auto => self.engaged.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counts a request answered degraded.
+    pub fn count_degraded(&self) {
+        self.degraded_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a search against the error budget; the window it closes decides whether the
+    /// service stays degraded.
+    pub fn record(&self, latency: Duration) {
+        let Some(config) = self.config.load_full() else { return };
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.started.elapsed() >= config.window {
+            let over_budget =
+                window.searches >= MIN_WINDOW_SEARCHES && window.slow as f64 > window.searches as f64 * config.error_budget;
+            if over_budget && !self.engaged.swap(true, Ordering::Relaxed) {
+                self.trips.fetch_add(1, Ordering::Relaxed);
+                log::warn!("degrading search: {} of {} searches took over {:?}", window.slow, window.searches, config.slow_after);
+            } else if !over_budget && self.engaged.swap(false, Ordering::Relaxed) {
+                log::info!("search latency back within budget: leaving degradation mode");
+            }
+            *window = Window { started: Instant::now(), searches: 0, slow: 0 };
+        }
+        window.searches += 1;
+        if latency > config.slow_after {
+            window.slow += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::json!({
+            "mode": self.mode().as_str(),
+            "degraded": self.is_degraded(),
+            "error_budget": self.config.load().as_ref().map(|c| serde_json::json!({
+                "slow_after_ms": c.slow_after.as_millis() as u64,
+                "budget": c.error_budget,
+                "window_secs": c.window.as_secs_f64(),
+                "over_budget": self.engaged.load(Ordering::Relaxed),
+                "window_searches": window.searches,
+                "window_slow": window.slow,
+            })),
+            "trips": self.trips.load(Ordering::Relaxed),
+            "degraded_responses": self.degraded_responses.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl SearchService {
+    pub fn degradation(&self) -> &Degradation {
+        &self.degradation
+    }
+
+    /// Turns away a request for `feature` while degraded.
+    pub fn check_not_degraded(&self, feature: &str) -> ServiceResult<()> {
+        if !self.degradation.is_degraded() {
+            return Ok(());
+        }
+        self.degradation.count_degraded();
+        Err(ServiceError::Unavailable(format!("{} are disabled while the service is degraded; retry later", feature)))
+    }
+}
@@ -0,0 +1,194 @@
+//! Dead-letter queue for ingest payloads that failed validation or indexing, or that were
+//! not even valid JSON for their route.
+//!
+//! Entries stay persisted while they are retried and are only removed once their retry is
+//! applied, so a crash mid-retry leaves them queued.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::BatchOp;
+use crate::schema::BlogPost;
+use crate::now_secs;
+
+/// A failed ingest payload, kept as batch operations so it can be replayed verbatim.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub seq: u64,
+    pub source: String, // "index", "update" or "batch"
+    pub error: String,
+    pub failed_at: i64,
+    pub attempts: u32,
+    /// Empty for a `raw` entry
+    pub ops: Vec<BatchOp>,
+    /// A body that didn't deserialize; retries parse it again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawPayload>,
+}
+
+/// The body of an ingest request as it was sent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPayload {
+    pub body: String,
+    /// Tenant of the API key it was sent with, stamped on its posts when they parse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl DeadLetter {
+    /// The operations to replay: `ops`, or those of the `raw` body parsed as the payload of
+    /// the route `source` names.
+    pub fn replay_ops(&self) -> Result<Vec<BatchOp>, String> {
+        let Some(raw) = &self.raw else { return Ok(self.ops.clone()) };
+        let ops = match self.source.as_str() {
+            "index" => vec![BatchOp::Index { doc: serde_json::from_str(&raw.body).map_err(|e| e.to_string())? }],
+            "update" => vec![BatchOp::Update { doc: serde_json::from_str(&raw.body).map_err(|e| e.to_string())? }],
+            "batch" => serde_json::from_str(&raw.body).map_err(|e| e.to_string())?,
+            source => return Err(format!("no payload format for source {}", source)),
+        };
+        let owned = |mut doc: BlogPost| {
+            if raw.tenant.is_some() {
+                doc.tenant = raw.tenant.clone();
+            }
+            doc
+        };
+        Ok(ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Index { doc } => BatchOp::Index { doc: owned(doc) },
+                BatchOp::Update { doc } => BatchOp::Update { doc: owned(doc) },
+                delete => delete,
+            })
+            .collect())
+    }
+}
+
+/// Dead-letter store persisted as NDJSON next to the index.
+pub struct DeadLetterQueue {
+    /// Not persisted when `None`
+    path: Option<PathBuf>,
+    entries: Mutex<Vec<DeadLetter>>,
+    /// Entries checked out for a retry; locked after `entries`
+    retrying: Mutex<HashSet<u64>>,
+    next_seq: AtomicU64,
+}
+
+impl DeadLetterQueue {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        if path.exists() {
+            for line in std::fs::read_to_string(&path)?.lines() {
+                if !line.trim().is_empty() {
+                    entries.push(serde_json::from_str(line)?);
+                }
+            }
+        }
+        let next_seq = entries.iter().map(|e: &DeadLetter| e.seq).max().unwrap_or(0) + 1;
+        Ok(DeadLetterQueue { path: Some(path), entries: Mutex::new(entries), retrying: Mutex::default(), next_seq: AtomicU64::new(next_seq) })
+    }
+
+    /// An empty queue that is never written to disk.
+    pub fn in_memory() -> Self {
+        DeadLetterQueue { path: None, entries: Mutex::new(Vec::new()), retrying: Mutex::default(), next_seq: AtomicU64::new(1) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<DeadLetter>> {
+        match self.entries.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    pub fn push(&self, source: &str, error: impl ToString, ops: Vec<BatchOp>) {
+        self.append(source, error.to_string(), ops, None);
+    }
+
+    /// Queues a body of the route `source` that didn't deserialize.
+    pub fn push_raw(&self, source: &str, error: impl ToString, raw: RawPayload) {
+        self.append(source, error.to_string(), Vec::new(), Some(raw));
+    }
+
+    fn append(&self, source: &str, error: String, ops: Vec<BatchOp>, raw: Option<RawPayload>) {
+        let mut entries = self.lock();
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let entry = DeadLetter { seq, source: source.to_string(), error, failed_at: now_secs(), attempts: 0, ops, raw };
+        log::warn!("dead-lettered {} payload #{}: {}", entry.source, seq, entry.error);
+        if let Some(path) = &self.path {
+            let appended = serde_json::to_string(&entry).map_err(std::io::Error::from).and_then(|line| {
+                use std::io::Write;
+                let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", line)
+            });
+            if let Err(e) = appended {
+                log::error!("dlq write error: {}", e);
+            }
+        }
+        entries.push(entry);
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.lock().clone()
+    }
+
+    /// Removes the entry with `seq` (or every entry) and returns what was removed.
+    pub fn take(&self, seq: Option<u64>) -> Vec<DeadLetter> {
+        self.take_where(|e| seq.is_none_or(|s| e.seq == s))
+    }
+
+    /// Removes the entries `matches` picks and returns them.
+    pub fn take_where(&self, mut matches: impl FnMut(&DeadLetter) -> bool) -> Vec<DeadLetter> {
+        let mut entries = self.lock();
+        let (taken, kept) = entries.drain(..).partition(|e| matches(e));
+        *entries = kept;
+        self.persist(&entries);
+        taken
+    }
+
+    /// Copies of the entry with `seq` (or every entry) for a retry, leaving them queued.
+    /// Entries another retry has checked out are skipped until it [settles](Self::settle) them.
+    pub fn checkout(&self, seq: Option<u64>) -> Vec<DeadLetter> {
+        let entries = self.lock();
+        let mut retrying = self.retrying.lock().unwrap_or_else(|p| p.into_inner());
+        entries.iter().filter(|e| seq.is_none_or(|s| e.seq == s) && retrying.insert(e.seq)).cloned().collect()
+    }
+
+    /// Ends the retry of entry `seq`: removes it once applied, otherwise records the error and
+    /// bumps `attempts`. The entry may have been discarded in the meantime.
+    pub fn settle(&self, seq: u64, outcome: Result<(), String>) {
+        let mut entries = self.lock();
+        self.retrying.lock().unwrap_or_else(|p| p.into_inner()).remove(&seq);
+        let Some(at) = entries.iter().position(|e| e.seq == seq) else { return };
+        match outcome {
+            Ok(()) => {
+                entries.remove(at);
+            }
+            Err(error) => {
+                let entry = &mut entries[at];
+                entry.attempts += 1;
+                entry.error = error;
+                entry.failed_at = now_secs();
+            }
+        }
+        self.persist(&entries);
+    }
+
+    fn persist(&self, entries: &[DeadLetter]) {
+        let Some(path) = &self.path else { return };
+        let mut out = String::new();
+        for entry in entries {
+            if let Ok(line) = serde_json::to_string(entry) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        // Replaced through a rename, so a crash leaves either version whole
+        let tmp = path.with_extension("ndjson.tmp");
+        if let Err(e) = std::fs::write(&tmp, out).and_then(|()| std::fs::rename(&tmp, path)) {
+            log::error!("dlq write error: {}", e);
+        }
+    }
+}
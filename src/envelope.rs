@@ -0,0 +1,46 @@
+//! Versions of the `/search` response. Clients sending
+//! `Accept: application/vnd.tantivy-demo.v2+json` get the v2 envelope; every other client
+//! keeps the v1 format, a bare array of hits unless a count, clusters, expansion, profile or
+//! remotes wrap it.
+//!
+//! v2 is always an object, and counts matches by default:
+//!
+//! ```json
+//! {
+//!   "hits": [{"id": "a", "score": 1.3, "tier": "hot", "fields": {"title": "...", "create_at": 1700000000, "tags": ["rust"], "features": {"lang": "rust"}}}],
+//!   "total": {"value": 1, "relation": "eq"}
+//! }
+//! ```
+//!
+//! Stored values are plain JSON in both (see [`doc_to_json`]); what v1 adds as `_`-prefixed
+//! keys of the document (`_author`, `_matched_tags`, ...) sits next to
+//! `fields` as `author`, `matched.tags`, `matched.fields`, `matched.terms` and, for
+//! `_snippets`, `highlights`.
+
+use tantivy::schema::{Schema, Value};
+
+use crate::schema::doc_to_json;
+use crate::service::SearchHit;
+
+pub const V2_MEDIA_TYPE: &str = "application/vnd.tantivy-demo.v2+json";
+
+/// Matches a v2 search counts up to unless it sets `track_total_hits` itself.
+pub const V2_TOTAL_HITS: u64 = 10_000;
+
+/// Whether an `Accept` header asks for the v2 envelope.
+pub fn accepts_v2(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| range.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case(V2_MEDIA_TYPE))
+    })
+}
+
+/// A local hit in the v2 format, before any author or matches are added.
+pub fn v2_hit(schema: &Schema, hit: &SearchHit) -> serde_json::Value {
+    let id = schema.get_field("id").ok().and_then(|f| hit.doc.get_first(f)).and_then(|v| v.as_str());
+    serde_json::json!({
+        "id": id,
+        "score": hit.score,
+        "tier": hit.tier.as_str(),
+        "fields": doc_to_json(schema, &hit.doc),
+    })
+}
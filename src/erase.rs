@@ -0,0 +1,344 @@
+//! Erasure of everything referencing one person (GDPR "right to be forgotten").
+//!
+//! A post references the subject when its `author_id` is the subject or when one of the
+//! configured `erasure_paths` under `features` holds it. Comments match on `author`, author
+//! profiles on `id` and dead letters on any string value in their payload. Matches are deleted,
+//! or with [`ErasureAction::Scrub`] re-indexed without the referencing values, and every touched
+//! index is committed and merged down right away so the old data doesn't linger in segments
+//! that only carry a delete bitset. Merges are started under the writer's lock but waited for
+//! after it is released, so writes go on while the old segments are rewritten.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::DocSetCollector;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{FieldValue, IndexRecordOption, OwnedValue, Schema, Value};
+use tantivy::{FutureResult, IndexReader, IndexWriter, SegmentMeta, TantivyDocument, Term};
+
+use crate::comments::ids_query;
+use crate::error::{ServiceError, ServiceResult};
+use crate::journal::JournalOp;
+use crate::nested::{add_block, blocks, posts_only, WithChildrenQuery};
+use crate::service::SearchService;
+use crate::now_secs;
+use crate::schema::add_shingles;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErasureAction {
+    #[default]
+    Delete,
+    /// Keep the documents but drop the values referencing the subject. Author profiles are
+    /// deleted either way.
+    Scrub,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErasureRequest {
+    /// User identifier, e.g. an author id or email address
+    pub subject: String,
+    #[serde(default)]
+    pub action: ErasureAction,
+}
+
+/// What one index lost to an erasure.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ErasedIndex {
+    pub matched: usize,
+    pub ids: Vec<String>,
+    /// Whether the segments were merged down after the commit; `false` when a background merge
+    /// held them, in which case the data goes with that merge instead
+    pub purged: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ErasureReport {
+    pub subject: String,
+    pub action: ErasureAction,
+    pub erased_at: i64,
+    pub posts: ErasedIndex,
+    pub archive: ErasedIndex,
+    pub comments: ErasedIndex,
+    pub authors: ErasedIndex,
+    pub dead_letters: usize,
+}
+
+/// Whether the value at `path` of `value` is `subject` (or an array holding it). Arrays on the
+/// way are searched element by element.
+fn holds_at(value: &OwnedValue, path: &[&str], subject: &str) -> bool {
+    match (value, path) {
+        (OwnedValue::Array(items), _) => items.iter().any(|item| holds_at(item, path, subject)),
+        (OwnedValue::Object(entries), [key, rest @ ..]) => {
+            entries.iter().any(|(k, v)| k == key && holds_at(v, rest, subject))
+        }
+        (OwnedValue::Str(s), []) => s == subject,
+        (OwnedValue::I64(n), []) => subject.parse() == Ok(*n),
+        (OwnedValue::U64(n), []) => subject.parse() == Ok(*n),
+        _ => false,
+    }
+}
+
+/// Drops the value at `path` of `value` wherever it is `subject`.
+fn remove_at(value: &mut OwnedValue, path: &[&str], subject: &str) {
+    match (value, path) {
+        (OwnedValue::Array(items), _) => {
+            if path.is_empty() {
+                items.retain(|item| !holds_at(item, &[], subject));
+            }
+            items.iter_mut().for_each(|item| remove_at(item, path, subject));
+        }
+        (OwnedValue::Object(entries), [key, rest @ ..]) => {
+            if rest.is_empty() {
+                entries.retain(|k, v| !(k == key && holds_at(v, &[], subject)));
+            }
+            for (_, v) in entries.iter_mut().filter(|(k, _)| k == key) {
+                remove_at(v, rest, subject);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn json_mentions(value: &serde_json::Value, subject: &str) -> bool {
+    match value {
+        serde_json::Value::String(s) => s == subject,
+        serde_json::Value::Array(items) => items.iter().any(|v| json_mentions(v, subject)),
+        serde_json::Value::Object(entries) => entries.values().any(|v| json_mentions(v, subject)),
+        _ => false,
+    }
+}
+
+/// Where posts reference the subject: `author_id` and dotted paths under `features`.
+struct PostMatcher<'a> {
+    subject: &'a str,
+    paths: Vec<Vec<&'a str>>,
+}
+
+impl PostMatcher<'_> {
+    /// Posts that may reference the subject. JSON paths are matched as phrases of the analyzed
+    /// subject, so candidates are confirmed against the stored values with [`Self::references`].
+    fn candidates(&self, index: &tantivy::Index) -> ServiceResult<Box<dyn Query>> {
+        let schema = index.schema();
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if let Ok(f_author_id) = schema.get_field("author_id") {
+            let term = Term::from_field_text(f_author_id, self.subject);
+            clauses.push((Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+        }
+        let parser = QueryParser::for_index(index, vec![schema.get_field("features").unwrap()]);
+        let escaped = self.subject.replace('\\', "\\\\").replace('"', "\\\"");
+        for path in &self.paths {
+            let q = format!("features.{}:\"{}\"", path.join("."), escaped);
+            clauses.push((Occur::Should, parser.parse_query(&q)?));
+        }
+        Ok(posts_only(&schema, Box::new(BooleanQuery::new(clauses))))
+    }
+
+    fn references(&self, schema: &Schema, doc: &TantivyDocument) -> bool {
+        let by_author = schema
+            .get_field("author_id")
+            .is_ok_and(|f| doc.get_all(f).any(|v| v.as_str() == Some(self.subject)));
+        let f_features = schema.get_field("features").unwrap();
+        by_author || doc.get_all(f_features).any(|features| self.paths.iter().any(|p| holds_at(features, p, self.subject)))
+    }
+
+    /// `doc` without the referencing values. The unredacted body goes too, as it may name the
+    /// subject in ways no path captures.
+    fn scrub(&self, schema: &Schema, doc: TantivyDocument) -> TantivyDocument {
+        let f_author_id = schema.get_field("author_id").ok();
+        let f_original = schema.get_field("body_original").ok();
+        let f_features = schema.get_field("features").unwrap();
+        let mut kept = Vec::new();
+        for field_value in doc.field_values() {
+            let field = field_value.field();
+            if f_original == Some(field) || (f_author_id == Some(field) && field_value.value().as_str() == Some(self.subject)) {
+                continue;
+            }
+            let mut value = field_value.value().clone();
+            if field == f_features {
+                self.paths.iter().for_each(|p| remove_at(&mut value, p, self.subject));
+            }
+            kept.push(FieldValue::new(field, value));
+        }
+        TantivyDocument::from(kept)
+    }
+}
+
+/// A merge of all of an index's segments, started by [`purge`].
+type Purge = Option<FutureResult<Option<SegmentMeta>>>;
+
+/// Starts merging all segments into one so deleted documents are physically dropped, once
+/// committed. Wait for it with [`purged`] after releasing the writer.
+fn purge(writer: &mut IndexWriter) -> tantivy::Result<Purge> {
+    let segment_ids = writer.index().searchable_segment_ids()?;
+    Ok((!segment_ids.is_empty()).then(|| writer.merge(&segment_ids)))
+}
+
+/// Whether the merge `purge` started went through.
+fn purged(purge: Purge) -> bool {
+    purge.is_none_or(|merge| merge.wait().is_ok())
+}
+
+fn commit(writer: &mut IndexWriter) -> ServiceResult<()> {
+    writer.commit()?;
+    Ok(())
+}
+
+/// Erases matching posts, with their nested children, from one posts index, committing with
+/// `commit`, and starts purging them; `purged` is left for the caller to fill in.
+fn erase_posts(
+    writer: &mut IndexWriter,
+    reader: &IndexReader,
+    matcher: &PostMatcher,
+    action: ErasureAction,
+    commit: impl Fn(&mut IndexWriter) -> ServiceResult<()>,
+) -> ServiceResult<(ErasedIndex, Purge)> {
+    // Pending writes must be visible, or a post indexed just before the erasure survives it
+    commit(writer)?;
+    reader.reload()?;
+    let searcher = reader.searcher();
+    let schema = searcher.index().schema();
+    let f_id = schema.get_field("id").unwrap();
+
+    let mut ids = BTreeSet::new();
+    for addr in searcher.search(matcher.candidates(searcher.index())?.as_ref(), &DocSetCollector)? {
+        let doc: TantivyDocument = searcher.doc(addr)?;
+        if matcher.references(&schema, &doc) {
+            ids.extend(doc.get_first(f_id).and_then(|v| v.as_str()).map(str::to_string));
+        }
+    }
+    if ids.is_empty() {
+        return Ok((ErasedIndex::default(), None));
+    }
+
+    let mut scrubbed = Vec::new();
+    if action == ErasureAction::Scrub {
+        let with_children = WithChildrenQuery { parent: posts_only(&schema, ids_query(f_id, &ids)) };
+        for block in blocks(&searcher, searcher.search(&with_children, &DocSetCollector)?.into_iter().collect())? {
+            let docs = block.into_iter().map(|addr| searcher.doc(addr)).collect::<tantivy::Result<Vec<TantivyDocument>>>()?;
+            let mut docs = docs.into_iter().map(|doc| matcher.scrub(&schema, doc)).collect::<Vec<_>>();
+            docs.iter_mut().for_each(|doc| add_shingles(&schema, doc));
+            scrubbed.push(docs);
+        }
+    }
+    for id in &ids {
+        writer.delete_term(Term::from_field_text(f_id, id));
+    }
+    for block in scrubbed {
+        add_block(writer, block)?;
+    }
+    commit(writer)?;
+    Ok((ErasedIndex { matched: ids.len(), ids: ids.into_iter().collect(), purged: false }, purge(writer)?))
+}
+
+impl SearchService {
+    /// Deletes or scrubs everything referencing `req.subject` in the hot index, the archive
+    /// tier, the comments and authors indexes and the dead-letter queue, then merges the
+    /// touched indexes and swaps in fresh searchers before returning.
+    ///
+    /// The erasure is journaled for followers, and every earlier journal entry dropped with
+    /// the payloads it may hold: followers behind the erasure catch up from a new snapshot.
+    pub fn erase(&self, req: &ErasureRequest) -> ServiceResult<ErasureReport> {
+        self.check_writable()?;
+        self.erase_local(req, true)
+    }
+
+    /// [`erase`](Self::erase) on this node, journaled (when `journaled`) under the hot writer's
+    /// lock so followers replay it in the same order against the post writes around it.
+    pub(crate) fn erase_local(&self, req: &ErasureRequest, journaled: bool) -> ServiceResult<ErasureReport> {
+        let subject = req.subject.as_str();
+        if subject.trim().is_empty() {
+            return Err(ServiceError::Invalid("subject must not be empty".to_string()));
+        }
+        let matcher = PostMatcher {
+            subject,
+            paths: self.config().erasure_paths.iter().map(|p| p.split('.').collect()).collect(),
+        };
+
+        let (mut posts, purge_posts) = {
+            let mut writer = self.writer();
+            let erased = erase_posts(&mut writer, &self.reader.load(), &matcher, req.action, |w| self.commit_locked(w))?;
+            if let Some(journal) = self.journal.as_ref().filter(|_| journaled) {
+                let offset = journal.append(JournalOp::Erase { request: req.clone() });
+                journal.truncate_before(offset);
+            }
+            erased
+        };
+        posts.purged = purged(purge_posts);
+        let (mut archive, purge_archive) = erase_posts(&mut self.archive.writer(), &self.archive.reader.load(), &matcher, req.action, commit)?;
+        archive.purged = purged(purge_archive);
+
+        let (mut comments, purge_comments) = {
+            let mut writer = self.comments.writer();
+            writer.commit()?;
+            self.comments.reader.reload()?;
+            let searcher = self.comments.reader.searcher();
+            let schema = searcher.index().schema();
+            let f_author = schema.get_field("author").unwrap();
+            let by_author = TermQuery::new(Term::from_field_text(f_author, subject), IndexRecordOption::Basic);
+            let addrs = searcher.search(&by_author, &DocSetCollector)?;
+            let mut ids = Vec::new();
+            for addr in addrs {
+                let doc: TantivyDocument = searcher.doc(addr)?;
+                let id = doc.get_first(schema.get_field("id").unwrap()).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                writer.delete_term(Term::from_field_text(schema.get_field("id").unwrap(), &id));
+                if req.action == ErasureAction::Scrub {
+                    let kept = doc.field_values().iter().filter(|fv| fv.field() != f_author).cloned().collect::<Vec<_>>();
+                    writer.add_document(TantivyDocument::from(kept))?;
+                }
+                ids.push(id);
+            }
+            let purge = match ids.is_empty() {
+                true => None,
+                false => {
+                    writer.commit()?;
+                    purge(&mut writer)?
+                }
+            };
+            (ErasedIndex { matched: ids.len(), ids, purged: false }, purge)
+        };
+        comments.purged = purged(purge_comments);
+
+        let (mut authors, purge_authors) = {
+            let mut writer = self.authors.writer();
+            writer.commit()?;
+            self.authors.reader.reload()?;
+            let f_id = writer.index().schema().get_field("id").unwrap();
+            let by_id = TermQuery::new(Term::from_field_text(f_id, subject), IndexRecordOption::Basic);
+            let matched = self.authors.reader.searcher().search(&by_id, &DocSetCollector)?.len();
+            let mut erased = ErasedIndex { matched, ..ErasedIndex::default() };
+            let mut purge_authors = None;
+            if matched > 0 {
+                writer.delete_term(Term::from_field_text(f_id, subject));
+                erased.ids.push(subject.to_string());
+                writer.commit()?;
+                purge_authors = purge(&mut writer)?;
+            }
+            (erased, purge_authors)
+        };
+        authors.purged = purged(purge_authors);
+
+        let dropped = self.dlq.take_where(|entry| {
+            serde_json::to_value(&entry.ops).is_ok_and(|ops| json_mentions(&ops, subject))
+                || entry.raw.as_ref().is_some_and(|raw| raw.body.contains(subject))
+        });
+
+        // Swap out searchers still holding the pre-merge segments, then collect their files
+        self.refresh()?;
+        self.writer().garbage_collect_files().wait()?;
+        self.archive.writer().garbage_collect_files().wait()?;
+        self.comments.writer().garbage_collect_files().wait()?;
+        self.authors.writer().garbage_collect_files().wait()?;
+
+        Ok(ErasureReport {
+            subject: subject.to_string(),
+            action: req.action,
+            erased_at: now_secs(),
+            posts,
+            archive,
+            comments,
+            authors,
+            dead_letters: dropped.len(),
+        })
+    }
+}
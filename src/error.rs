@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Why a [`SearchService`](crate::SearchService) operation failed. The HTTP layer maps these
+/// to 400, 404, 409, 429, 503 and 500 respectively.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// The request itself is wrong: failed validation, bad query syntax, unknown field
+    Invalid(String),
+    /// No such post, or none of the caller's tenant
+    NotFound(String),
+    /// The write clashes with what is stored: a create for an id that exists
+    Conflict(String),
+    /// The caller's tenant is out of quota
+    QuotaExceeded(String),
+    /// The service can't take the request right now
+    Unavailable(String),
+    /// Index or I/O failure
+    Internal(String),
+}
+
+pub type ServiceResult<T> = Result<T, ServiceError>;
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::Invalid(msg)
+            | ServiceError::NotFound(msg)
+            | ServiceError::Conflict(msg)
+            | ServiceError::QuotaExceeded(msg)
+            | ServiceError::Unavailable(msg)
+            | ServiceError::Internal(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<tantivy::TantivyError> for ServiceError {
+    fn from(e: tantivy::TantivyError) -> Self {
+        ServiceError::Internal(e.to_string())
+    }
+}
+
+impl From<query_syntax::SyntaxError> for ServiceError {
+    fn from(e: query_syntax::SyntaxError) -> Self {
+        ServiceError::Invalid(e.0)
+    }
+}
+
+impl From<std::io::Error> for ServiceError {
+    fn from(e: std::io::Error) -> Self {
+        ServiceError::Internal(e.to_string())
+    }
+}
+
+impl From<tantivy::query::QueryParserError> for ServiceError {
+    fn from(e: tantivy::query::QueryParserError) -> Self {
+        ServiceError::Invalid(format!("invalid query: {}", e))
+    }
+}
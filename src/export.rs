@@ -0,0 +1,198 @@
+//! Column projection and flattening shared by the export formats and index diffs.
+//!
+//! Projected exports whose columns are all whole i64 or string fast fields (`create_at`,
+//! `status`, `tenant`, `_indexed_at`, `_content_hash`, ...) are read from the columnar store
+//! and never touch the doc store. Any other column needs the stored document: the doc store
+//! decompresses whole blocks, so projecting stored fields trims the output but saves no I/O.
+//! Values of multi-valued string fast fields come back sorted and deduplicated.
+
+use std::sync::Arc;
+
+use tantivy::collector::DocSetCollector;
+use tantivy::columnar::{Column, StrColumn};
+use tantivy::query::AllQuery;
+use tantivy::schema::{Field, FieldType, OwnedValue, Schema, Value};
+use tantivy::{DocAddress, Searcher, SegmentOrdinal, TantivyDocument};
+
+use crate::nested::{posts_only, NESTED_FIELD};
+use crate::schema::{INDEXED_AT_FIELD, RESTRICTED_FIELDS};
+
+/// Documents fetched from the doc store per export chunk.
+pub const EXPORT_CHUNK: usize = 256;
+
+/// A flattened export column: a schema field, or a dotted path inside a JSON field
+/// (`features.lang`).
+pub struct ExportColumn {
+    pub name: String,
+    pub field: Field,
+    pub path: Vec<String>,
+}
+
+/// Resolves the `fields` list (default: every stored field, in schema order).
+pub fn export_columns(schema: &Schema, fields: Option<&str>) -> Result<Vec<ExportColumn>, String> {
+    let Some(list) = fields.filter(|f| !f.trim().is_empty()) else {
+        return Ok(schema
+            .fields()
+            .filter(|(_, entry)| entry.is_stored() && entry.name() != NESTED_FIELD && !RESTRICTED_FIELDS.contains(&entry.name()))
+            .map(|(field, entry)| ExportColumn { name: entry.name().to_string(), field, path: Vec::new() })
+            .collect());
+    };
+    let mut columns = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let (base, path) = match name.split_once('.') {
+            Some((base, path)) => (base, path.split('.').map(str::to_string).collect()),
+            None => (name, Vec::new()),
+        };
+        let field = schema.get_field(base).map_err(|_| format!("unknown field: {}", name))?;
+        if !path.is_empty() && !matches!(schema.get_field_entry(field).field_type(), FieldType::JsonObject(_)) {
+            return Err(format!("{} is not a JSON field: {}", base, name));
+        }
+        columns.push(ExportColumn { name: name.to_string(), field, path });
+    }
+    Ok(columns)
+}
+
+/// A column read from its fast field.
+enum FastColumn {
+    I64(Column<i64>),
+    /// `None` in segments without a value for the field
+    Str(Option<StrColumn>),
+}
+
+/// The documents of an export: stored ones, or when every column is a whole i64 or string fast
+/// field, documents holding just those fields rebuilt from the columnar store.
+pub struct ExportDocs {
+    searcher: Arc<Searcher>,
+    /// Fields read from fast fields, when all columns are
+    fast: Option<Vec<(Field, bool)>>,
+    /// The fast columns of the segment read last
+    segment: Option<(SegmentOrdinal, Vec<FastColumn>)>,
+}
+
+impl ExportDocs {
+    pub fn new(searcher: Arc<Searcher>, columns: &[ExportColumn]) -> Self {
+        let schema = searcher.index().schema();
+        let fast: Option<Vec<(Field, bool)>> = columns
+            .iter()
+            .map(|c| {
+                let entry = schema.get_field_entry(c.field);
+                match entry.field_type() {
+                    _ if !c.path.is_empty() || !entry.is_fast() => None,
+                    FieldType::I64(_) => Some((c.field, true)),
+                    FieldType::Str(_) => Some((c.field, false)),
+                    _ => None,
+                }
+            })
+            .collect();
+        ExportDocs { searcher, fast: fast.filter(|fields| !fields.is_empty()), segment: None }
+    }
+
+    pub fn doc(&mut self, addr: DocAddress) -> tantivy::Result<TantivyDocument> {
+        let Some(fields) = &self.fast else { return self.searcher.doc(addr) };
+        let columns = match &mut self.segment {
+            Some((ord, columns)) if *ord == addr.segment_ord => columns,
+            segment => {
+                let schema = self.searcher.index().schema();
+                let reader = self.searcher.segment_reader(addr.segment_ord).fast_fields();
+                let mut columns = Vec::with_capacity(fields.len());
+                for (field, int) in fields {
+                    let name = schema.get_field_name(*field);
+                    columns.push(match int {
+                        true => FastColumn::I64(reader.i64(name)?),
+                        false => FastColumn::Str(reader.str(name)?),
+                    });
+                }
+                &mut segment.insert((addr.segment_ord, columns)).1
+            }
+        };
+        let mut doc = TantivyDocument::new();
+        for ((field, _), column) in fields.iter().zip(columns.iter()) {
+            match column {
+                FastColumn::I64(column) => column.values_for_doc(addr.doc_id).for_each(|v| doc.add_i64(*field, v)),
+                FastColumn::Str(Some(column)) => {
+                    for ord in column.term_ords(addr.doc_id) {
+                        let mut text = String::new();
+                        column.ord_to_str(ord, &mut text)?;
+                        doc.add_text(*field, text);
+                    }
+                }
+                FastColumn::Str(None) => {}
+            }
+        }
+        Ok(doc)
+    }
+}
+
+/// Typed value of one column: multi-valued fields become arrays, JSON paths are walked.
+pub fn column_value(doc: &TantivyDocument, column: &ExportColumn) -> serde_json::Value {
+    let values: Vec<&OwnedValue> = doc.get_all(column.field).collect();
+    if column.path.is_empty() {
+        return match values.as_slice() {
+            [] => serde_json::Value::Null,
+            [one] => serde_json::to_value(one).unwrap_or_default(),
+            many => serde_json::to_value(many).unwrap_or_default(),
+        };
+    }
+    let mut current = match values.first() {
+        Some(v) => *v,
+        None => return serde_json::Value::Null,
+    };
+    for segment in &column.path {
+        current = match current {
+            OwnedValue::Object(map) => match map.get(segment) {
+                Some(v) => v,
+                None => return serde_json::Value::Null,
+            },
+            _ => return serde_json::Value::Null,
+        };
+    }
+    serde_json::to_value(current).unwrap_or_default()
+}
+
+/// Flat text form of a column value: arrays are joined with `|`, objects are JSON-encoded.
+pub fn flat_cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => items.iter().map(flat_cell).collect::<Vec<_>>().join("|"),
+        other => other.to_string(),
+    }
+}
+
+pub fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// SHA-256 of a document's stored content, hex-encoded: every stored field's name and typed
+/// value in schema order, each length-prefixed. It depends on nothing but the content, so
+/// it compares equal across builds and processes. `_indexed_at` is left out, so a rebuild of
+/// the same posts compares equal.
+pub fn doc_content_hash(schema: &Schema, doc: &TantivyDocument) -> String {
+    let mut hasher = ring::digest::Context::new(&ring::digest::SHA256);
+    for (field, entry) in schema.fields().filter(|(_, e)| e.is_stored() && e.name() != INDEXED_AT_FIELD) {
+        let column = ExportColumn { name: entry.name().to_string(), field, path: Vec::new() };
+        for part in [column.name.clone(), column_value(doc, &column).to_string()] {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    hasher.finish().as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// id -> content hash for every live document of a searcher.
+pub fn content_hashes(searcher: &Searcher) -> tantivy::Result<std::collections::HashMap<String, String>> {
+    let schema = searcher.index().schema();
+    let f_id = schema.get_field("id").unwrap();
+    let mut hashes = std::collections::HashMap::new();
+    for addr in searcher.search(&*posts_only(&schema, Box::new(AllQuery)), &DocSetCollector)? {
+        let doc: TantivyDocument = searcher.doc(addr)?;
+        if let Some(id) = doc.get_first(f_id).and_then(|v| v.as_str()) {
+            hashes.insert(id.to_string(), doc_content_hash(&schema, &doc));
+        }
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,234 @@
+//! Cross-cluster search: remote deployments of this service (one per region, say) registered
+//! by name and searched alongside the local index with `indexes=<name>:posts`.
+//!
+//! Each remote leg is a `/search` request to the remote's endpoint, sent with the remote's own
+//! credentials and a child span of the search's trace. When the local search checks post ACLs
+//! the caller's own `Authorization` goes instead of the remote's `bearer_token`, so a remote
+//! verifying the same JWTs hides what the caller may not see. Remotes answer with `_score` on every
+//! hit, and the hits of every leg are merged by it. Scores from different clusters rest on
+//! different term statistics, so the merge favours whichever cluster holds fewer documents
+//! matching a term; it is a good interleaving, not a global ranking.
+//!
+//! Every remote has its own [circuit breaker](crate::breaker); a remote behind an open one is
+//! skipped, its leg answering with an error and no hits, until a probe gets through again.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::breaker::{BreakerConfig, CircuitBreaker};
+use crate::collector::TotalHits;
+use crate::envelope::V2_MEDIA_TYPE;
+use crate::service::SearchService;
+use crate::trace::{ShardTiming, TraceContext, TRACEPARENT};
+
+/// The only index a remote is searched for.
+pub const POSTS_INDEX: &str = "posts";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteCluster {
+    /// What `indexes=` refers to it by
+    pub name: String,
+    /// Base URL, e.g. `https://search.eu.example.com`
+    pub endpoint: String,
+    /// Sent as `X-Api-Key`, for remotes configured with tenants
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Sent as `Authorization: Bearer`, for remotes checking post ACLs
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+}
+
+/// Reads a JSON array of remote clusters.
+pub fn load_remotes(path: &PathBuf) -> anyhow::Result<Vec<RemoteCluster>> {
+    let remotes: Vec<RemoteCluster> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let mut names = std::collections::HashSet::new();
+    for remote in &remotes {
+        if remote.name.is_empty() || remote.name.contains([':', ',']) {
+            anyhow::bail!("remote cluster names must be non-empty without ':' or ',': {:?}", remote.name);
+        }
+        if !names.insert(remote.name.as_str()) {
+            anyhow::bail!("remote cluster {} is listed twice", remote.name);
+        }
+    }
+    Ok(remotes)
+}
+
+/// One remote leg of a search.
+pub struct RemoteSearch {
+    /// `/search` query parameters, passed through as given
+    pub params: Vec<(String, String)>,
+    pub deadline: Option<Instant>,
+    /// Span of the whole search; the leg runs under a child of it
+    pub trace: Option<TraceContext>,
+    /// Ask for the v2 envelope, whose hits carry `score` rather than `_score`
+    pub v2: bool,
+    pub authorization: RemoteAuthorization,
+}
+
+/// Whose `Authorization` a remote leg is sent with.
+#[derive(Debug, Clone)]
+pub enum RemoteAuthorization {
+    /// The remote's configured `bearer_token`, if any
+    Configured,
+    /// The caller's own header, none for anonymous callers; the remote then applies the
+    /// caller's groups rather than those of the remote's service account
+    Caller(Option<String>),
+}
+
+/// What one remote answered.
+#[derive(Debug, Clone)]
+pub struct RemoteHits {
+    pub cluster: String,
+    /// Hits as the remote renders them, each with `_score`
+    pub hits: Vec<serde_json::Value>,
+    pub total: Option<TotalHits>,
+    pub timing: ShardTiming,
+    /// Why the leg failed; its hits are left out
+    pub error: Option<String>,
+}
+
+/// How a remote leg went, as reported under `remotes` in the answer.
+#[derive(Serialize, Debug, Clone)]
+pub struct RemoteReport {
+    pub cluster: String,
+    pub hits: usize,
+    pub took_ms: f64,
+    pub timed_out: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&RemoteHits> for RemoteReport {
+    fn from(r: &RemoteHits) -> Self {
+        RemoteReport { cluster: r.cluster.clone(), hits: r.hits.len(), took_ms: r.timing.took_ms, timed_out: r.timing.timed_out, error: r.error.clone() }
+    }
+}
+
+/// The registered remotes and the HTTP client shared by their legs.
+pub struct Federation {
+    remotes: BTreeMap<String, RemoteCluster>,
+    breakers: BTreeMap<String, CircuitBreaker>,
+    http: reqwest::Client,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SearchAnswer {
+    Plain(Vec<serde_json::Value>),
+    Wrapped { hits: Vec<serde_json::Value>, total: Option<TotalHits> },
+}
+
+impl Federation {
+    /// Remote legs give up after `timeout`, or earlier at the search's deadline.
+    pub fn new(remotes: Vec<RemoteCluster>, timeout: Duration, breaker: BreakerConfig) -> Self {
+        let breakers = remotes.iter().map(|r| (r.name.clone(), CircuitBreaker::new(breaker))).collect();
+        let remotes = remotes.into_iter().map(|r| (r.name.clone(), r)).collect();
+        Federation { remotes, breakers, http: reqwest::Client::new(), timeout }
+    }
+
+    /// The breaker of each remote, by name.
+    pub fn breakers(&self) -> impl Iterator<Item = (&str, &CircuitBreaker)> {
+        self.breakers.iter().map(|(name, breaker)| (name.as_str(), breaker))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.remotes.keys().map(String::as_str)
+    }
+
+    async fn search_one(&self, remote: &RemoteCluster, req: &RemoteSearch) -> RemoteHits {
+        let (span, started) = (req.trace.as_ref().map(TraceContext::child), Instant::now());
+        let breaker = &self.breakers[&remote.name];
+        if !breaker.allow() {
+            let timing = ShardTiming::since(&format!("{}:{}", remote.name, POSTS_INDEX), span.as_ref(), started);
+            let error = Some("skipped: circuit open after repeated failures".to_string());
+            return RemoteHits { cluster: remote.name.clone(), hits: Vec::new(), total: None, timing, error };
+        }
+        let timeout = match req.deadline {
+            Some(d) => self.timeout.min(d.saturating_duration_since(started)),
+            None => self.timeout,
+        };
+        let mut request = self
+            .http
+            .get(format!("{}/search", remote.endpoint.trim_end_matches('/')))
+            .query(&req.params)
+            .query(&[("scores", "true")])
+            .timeout(timeout);
+        if req.v2 {
+            request = request.header(reqwest::header::ACCEPT, V2_MEDIA_TYPE);
+        }
+        if req.deadline.is_some() {
+            request = request.header("X-Timeout-Ms", timeout.as_millis().to_string());
+        }
+        if let Some(key) = &remote.api_key {
+            request = request.header("X-Api-Key", key);
+        }
+        match (&req.authorization, &remote.bearer_token) {
+            (RemoteAuthorization::Configured, Some(token)) => request = request.bearer_auth(token),
+            (RemoteAuthorization::Caller(Some(value)), _) => request = request.header(reqwest::header::AUTHORIZATION, value),
+            _ => {}
+        }
+        if let Some(span) = &span {
+            request = request.header(TRACEPARENT, span.to_string());
+        }
+        let answer = async {
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            // 504 still carries the hits found before the remote's deadline
+            let timed_out = status == reqwest::StatusCode::GATEWAY_TIMEOUT;
+            if !status.is_success() && !timed_out {
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("{}: {}", status, body.trim()));
+            }
+            let answer: SearchAnswer = response.json().await.map_err(|e| e.to_string())?;
+            Ok((answer, timed_out))
+        };
+        let mut timing = ShardTiming::since(&format!("{}:{}", remote.name, POSTS_INDEX), span.as_ref(), started);
+        let answer = answer.await;
+        breaker.record(answer.is_ok());
+        let (hits, total, error) = match answer {
+            Ok((SearchAnswer::Plain(hits), timed_out)) => {
+                timing.timed_out = timed_out;
+                (hits, None, None)
+            }
+            Ok((SearchAnswer::Wrapped { hits, total }, timed_out)) => {
+                timing.timed_out = timed_out;
+                (hits, total, None)
+            }
+            Err(e) => (Vec::new(), None, Some(e)),
+        };
+        timing.took_ms = started.elapsed().as_secs_f64() * 1000.0;
+        timing.hits = hits.len();
+        RemoteHits { cluster: remote.name.clone(), hits, total, timing, error }
+    }
+}
+
+impl SearchService {
+    /// Names of the registered remote clusters.
+    pub fn remote_clusters(&self) -> Vec<String> {
+        self.federation.iter().flat_map(|f| f.names().map(str::to_string)).collect()
+    }
+
+    /// Runs `req` on each of `clusters` concurrently. Unknown names and failing remotes come
+    /// back with an error instead of hits rather than failing the whole search.
+    pub async fn search_remotes(&self, clusters: &[String], req: &RemoteSearch) -> Vec<RemoteHits> {
+        let Some(federation) = &self.federation else {
+            return clusters.iter().map(|c| unknown_remote(c, req)).collect();
+        };
+        let legs = clusters.iter().map(|name| async move {
+            match federation.remotes.get(name) {
+                Some(remote) => federation.search_one(remote, req).await,
+                None => unknown_remote(name, req),
+            }
+        });
+        futures_util::future::join_all(legs).await
+    }
+}
+
+fn unknown_remote(name: &str, req: &RemoteSearch) -> RemoteHits {
+    let timing = ShardTiming::since(&format!("{}:{}", name, POSTS_INDEX), req.trace.as_ref(), Instant::now());
+    RemoteHits { cluster: name.to_string(), hits: Vec::new(), total: None, timing, error: Some(format!("unknown remote cluster: {}", name)) }
+}
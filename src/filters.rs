@@ -0,0 +1,776 @@
+//! Tokenizers, token filters and tokenizer wrappers tantivy doesn't ship, for the analyzer
+//! specs of [`analyzers`](crate::analyzers).
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+use serde::{Deserialize, Serialize};
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+
+/// Unicode NFKC normalization: full-width Latin letters and digits become ASCII (`ＲＵＳＴ` →
+/// `RUST`), half-width katakana full-width (`ｶﾀｶﾅ` → `カタカナ`), ligatures and other
+/// compatibility characters their plain form, and composed and decomposed accents one form, so
+/// text typed either way indexes to the same terms.
+#[derive(Clone)]
+pub struct NfkcFilter;
+
+impl TokenFilter for NfkcFilter {
+    type Tokenizer<T: Tokenizer> = NfkcTokenizer<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        NfkcTokenizer { tokenizer }
+    }
+}
+
+#[derive(Clone)]
+pub struct NfkcTokenizer<T> {
+    tokenizer: T,
+}
+
+impl<T: Tokenizer> Tokenizer for NfkcTokenizer<T> {
+    type TokenStream<'a> = NfkcTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        NfkcTokenStream { tail: self.tokenizer.token_stream(text) }
+    }
+}
+
+pub struct NfkcTokenStream<T> {
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for NfkcTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        // ASCII is already in NFKC
+        if !self.tail.token().text.is_ascii() {
+            if let Cow::Owned(normalized) = ComposingNormalizerBorrowed::new_nfkc().normalize(&self.tail.token().text) {
+                self.tail.token_mut().text = normalized;
+            }
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+/// What the analyzer does with emoji and pictographic symbols before tokenizing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiPolicy {
+    /// Drop them, so they neither become terms nor end up inside n-grams
+    Strip,
+    /// Index each emoji (a ZWJ sequence, flag or skin-toned emoji counts as one) as a token of
+    /// its own, and tokenize the text around it as if it weren't there
+    Keep,
+    /// Replace them with their names (`🎉` → `party_popper`), from the analyzer's `emoji_names`
+    /// or a built-in list; others become `emoji_<code points>`
+    Name,
+}
+
+/// Names for the most common emoji (without variation selectors).
+const EMOJI_NAMES: &[(&str, &str)] = &[
+    ("😀", "grinning_face"), ("😂", "face_with_tears_of_joy"), ("🤣", "rolling_on_the_floor_laughing"), ("😊", "smiling_face"),
+    ("😍", "heart_eyes"), ("😎", "sunglasses"), ("🤔", "thinking_face"), ("😅", "sweat_smile"), ("😭", "crying_face"),
+    ("😉", "winking_face"), ("🙂", "slightly_smiling_face"), ("🙃", "upside_down_face"), ("😱", "screaming_face"), ("🤯", "exploding_head"),
+    ("🥳", "partying_face"), ("😴", "sleeping_face"), ("🙏", "folded_hands"), ("👍", "thumbs_up"), ("👎", "thumbs_down"),
+    ("👏", "clapping_hands"), ("🙌", "raising_hands"), ("💪", "flexed_biceps"), ("👀", "eyes"), ("🎉", "party_popper"),
+    ("🎊", "confetti_ball"), ("✨", "sparkles"), ("🔥", "fire"), ("💯", "hundred_points"), ("🚀", "rocket"), ("⭐", "star"),
+    ("🌟", "glowing_star"), ("❤", "red_heart"), ("💔", "broken_heart"), ("💡", "light_bulb"), ("⚡", "high_voltage"),
+    ("✅", "check_mark"), ("❌", "cross_mark"), ("⚠", "warning"), ("❓", "question_mark"), ("❗", "exclamation_mark"),
+    ("🐛", "bug"), ("🦀", "crab"), ("🐍", "snake"), ("🐳", "whale"), ("🐧", "penguin"), ("☕", "coffee"), ("🍕", "pizza"),
+    ("📦", "package"), ("📚", "books"), ("📝", "memo"), ("📌", "pushpin"), ("🔒", "locked"), ("🔧", "wrench"), ("🔨", "hammer"),
+    ("🛠", "hammer_and_wrench"), ("⚙", "gear"), ("💻", "laptop"), ("🖥", "desktop_computer"), ("📱", "mobile_phone"),
+    ("🔍", "magnifying_glass"), ("📈", "chart_increasing"), ("📉", "chart_decreasing"), ("🎯", "direct_hit"), ("🏆", "trophy"),
+    ("🌍", "globe"), ("☀", "sun"), ("🌙", "moon"), ("🌈", "rainbow"), ("🎵", "musical_note"), ("👋", "waving_hand"),
+];
+
+/// Emoji and pictographic symbols: the emoji blocks, dingbats, miscellaneous symbols and
+/// technical pictographs, arrows and stars.
+fn is_pictograph(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x2300..=0x23FF | 0x2190..=0x21FF
+            | 0x3030 | 0x303D | 0x3297 | 0x3299 | 0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139)
+}
+
+/// Invisible characters that only modify an emoji: the zero-width joiner, variation
+/// selectors, the keycap mark and tag characters.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c as u32, 0x200D | 0xFE0E | 0xFE0F | 0x20E3 | 0xE0020..=0xE007F)
+}
+
+fn is_skin_tone(c: char) -> bool {
+    matches!(c as u32, 0x1F3FB..=0x1F3FF)
+}
+
+/// Flags are pairs of regional indicators.
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// Names of the HTML entities decoded besides numeric ones.
+const HTML_ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&"), ("lt", "<"), ("gt", ">"), ("quot", "\""), ("apos", "'"), ("nbsp", " "), ("shy", ""), ("copy", "©"),
+    ("reg", "®"), ("trade", "™"), ("hellip", "…"), ("mdash", "—"), ("ndash", "–"), ("lsquo", "‘"), ("rsquo", "’"),
+    ("ldquo", "“"), ("rdquo", "”"), ("laquo", "«"), ("raquo", "»"), ("bull", "•"), ("middot", "·"), ("deg", "°"),
+    ("euro", "€"), ("pound", "£"), ("yen", "¥"), ("cent", "¢"), ("sect", "§"), ("para", "¶"), ("times", "×"),
+    ("divide", "÷"), ("plusmn", "±"), ("agrave", "à"), ("aacute", "á"), ("acirc", "â"), ("auml", "ä"), ("ccedil", "ç"),
+    ("egrave", "è"), ("eacute", "é"), ("ecirc", "ê"), ("euml", "ë"), ("iacute", "í"), ("iuml", "ï"), ("ntilde", "ñ"),
+    ("oacute", "ó"), ("ocirc", "ô"), ("ouml", "ö"), ("uacute", "ú"), ("uuml", "ü"), ("szlig", "ß"),
+];
+
+/// One pre-tokenization rewrite of the text.
+#[derive(Clone)]
+pub enum CharFilter {
+    /// `&amp;`, `&#233;`, `&#xE9;` and the other entities of [`HTML_ENTITIES`] to their characters
+    HtmlEntities,
+    /// Every match of `regex` to `replacement`, which may refer to groups as `$1` or `${name}`
+    PatternReplace { regex: regex::Regex, replacement: String },
+    /// Occurrences of each key to its value, the longest key winning where several match
+    Mapping { mappings: Vec<(String, String)> },
+    /// Emoji handled as [`EmojiPolicy`] says; `names` go ahead of the built-in list
+    Emoji { policy: EmojiPolicy, names: BTreeMap<String, String> },
+    /// [`NfkcFilter`] over the text, for tokenizers that would split what it joins: n-grams
+    /// of half-width `ｶﾞ` or a decomposed `é` otherwise differ from those of `ガ` or `é`
+    Nfkc,
+}
+
+/// A span of the text a char filter replaced.
+struct Replacement {
+    start: usize,
+    end: usize,
+    text: String,
+    /// Emoji kept as a token of its own
+    token: Option<String>,
+}
+
+fn emoji_name(names: &BTreeMap<String, String>, emoji: &str) -> String {
+    if let Some(name) = names.get(emoji) {
+        return name.clone();
+    }
+    match EMOJI_NAMES.iter().find(|(e, _)| *e == emoji) {
+        Some((_, name)) => name.to_string(),
+        None => {
+            let points: Vec<String> = emoji.chars().filter(|c| *c != '\u{200D}').map(|c| format!("{:x}", c as u32)).collect();
+            format!("emoji_{}", points.join("_"))
+        }
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<String> {
+    match entity.strip_prefix('#') {
+        Some(code) => {
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code).map(String::from)
+        }
+        None => HTML_ENTITIES.iter().find(|(name, _)| *name == entity).map(|(_, c)| c.to_string()),
+    }
+}
+
+static ENTITY: once_cell::sync::Lazy<regex::Regex> =
+    once_cell::sync::Lazy::new(|| regex::Regex::new(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z][a-zA-Z0-9]{1,31});").expect("valid regex"));
+
+impl CharFilter {
+    /// The spans of `text` this filter replaces, in order and not overlapping.
+    fn replacements(&self, text: &str) -> Vec<Replacement> {
+        let mut out = Vec::new();
+        match self {
+            CharFilter::HtmlEntities => {
+                for caps in ENTITY.captures_iter(text) {
+                    let whole = caps.get(0).expect("group 0 always matches");
+                    if let Some(decoded) = decode_entity(&caps[1]) {
+                        out.push(Replacement { start: whole.start(), end: whole.end(), text: decoded, token: None });
+                    }
+                }
+            }
+            CharFilter::PatternReplace { regex, replacement } => {
+                for caps in regex.captures_iter(text) {
+                    let whole = caps.get(0).expect("group 0 always matches");
+                    let mut expanded = String::new();
+                    caps.expand(replacement, &mut expanded);
+                    out.push(Replacement { start: whole.start(), end: whole.end(), text: expanded, token: None });
+                }
+            }
+            CharFilter::Mapping { mappings } => {
+                let mut i = 0;
+                while i < text.len() {
+                    let rest = &text[i..];
+                    let found = mappings.iter().filter(|(from, _)| rest.starts_with(from.as_str())).max_by_key(|(from, _)| from.len());
+                    match found {
+                        Some((from, to)) => {
+                            out.push(Replacement { start: i, end: i + from.len(), text: to.clone(), token: None });
+                            i += from.len();
+                        }
+                        None => i += rest.chars().next().map_or(1, char::len_utf8),
+                    }
+                }
+            }
+            CharFilter::Emoji { policy, names } => {
+                let mut chars = text.char_indices().peekable();
+                while let Some((start, c)) = chars.next() {
+                    if !is_pictograph(c) && !is_emoji_modifier(c) {
+                        continue;
+                    }
+                    // The whole sequence: emoji joined by ZWJ, modifiers, skin tones and flag pairs
+                    let (mut end, mut prev, mut flag_halves) = (start + c.len_utf8(), c, is_regional_indicator(c) as usize);
+                    while let Some(&(i, next)) = chars.peek() {
+                        let flag_half = is_regional_indicator(next) && flag_halves % 2 == 1;
+                        let continues = is_emoji_modifier(next) || is_skin_tone(next) || flag_half || (prev == '\u{200D}' && is_pictograph(next));
+                        if !continues {
+                            break;
+                        }
+                        flag_halves += is_regional_indicator(next) as usize;
+                        (end, prev) = (i + next.len_utf8(), next);
+                        chars.next();
+                    }
+                    let sequence: String = text[start..end].chars().filter(|c| !matches!(*c as u32, 0xFE0E | 0xFE0F)).collect();
+                    // A lone modifier is just dropped
+                    let (replaced, token) = match (sequence.chars().any(is_pictograph), policy) {
+                        (false, _) => (String::new(), None),
+                        (true, EmojiPolicy::Strip) => (" ".to_string(), None),
+                        (true, EmojiPolicy::Keep) => (" ".to_string(), Some(sequence)),
+                        (true, EmojiPolicy::Name) => (format!(" {} ", emoji_name(names, &sequence)), None),
+                    };
+                    out.push(Replacement { start, end, text: replaced, token });
+                }
+            }
+            CharFilter::Nfkc => {
+                let nfkc = ComposingNormalizerBorrowed::new_nfkc();
+                let mut chars = text.char_indices().peekable();
+                while let Some((start, c)) = chars.next() {
+                    // Marks that compose with what precedes them join its span, so offsets
+                    // inside a replacement only collapse over one character and its marks
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(i, next)) = chars.peek().filter(|(_, next)| !next.is_ascii()) {
+                        let next_end = i + next.len_utf8();
+                        let apart = nfkc.normalize(&text[start..end]).into_owned() + &nfkc.normalize(&text[i..next_end]);
+                        if nfkc.normalize(&text[start..next_end]) == apart {
+                            break;
+                        }
+                        end = next_end;
+                        chars.next();
+                    }
+                    if let Cow::Owned(normalized) = nfkc.normalize(&text[start..end]) {
+                        out.push(Replacement { start, end, text: normalized, token: None });
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Where a char filter put a replaced span, to map token offsets back to its input.
+#[derive(Debug, Clone, Copy)]
+struct Replaced {
+    new_start: usize,
+    new_end: usize,
+    orig_start: usize,
+    orig_end: usize,
+}
+
+/// Maps byte offsets in a char filter's output back to its input; offsets inside a
+/// replacement map to the start or end of what it replaced.
+#[derive(Debug, Clone, Default)]
+struct OffsetMap {
+    replaced: Vec<Replaced>,
+}
+
+impl OffsetMap {
+    fn map(&self, offset: usize, end: bool) -> usize {
+        let before = self.replaced.partition_point(|r| r.new_end <= offset);
+        let shifted = match before {
+            0 => offset,
+            n => offset + self.replaced[n - 1].orig_end - self.replaced[n - 1].new_end,
+        };
+        match self.replaced.get(before) {
+            Some(r) if offset > r.new_start => match end {
+                true => r.orig_end,
+                false => r.orig_start,
+            },
+            _ => shifted,
+        }
+    }
+}
+
+/// Wraps a tokenizer to run char filters over the text first, in order. Token offsets point
+/// into the original text.
+#[derive(Clone)]
+pub struct CharFilteredTokenizer<T> {
+    tokenizer: T,
+    filters: Arc<Vec<CharFilter>>,
+    text: String,
+    /// One per filter that changed something, in the order they ran
+    maps: Vec<OffsetMap>,
+    /// Emoji kept as tokens, with offsets in the original text
+    kept: Vec<Token>,
+}
+
+impl<T: Tokenizer> CharFilteredTokenizer<T> {
+    pub fn new(tokenizer: T, filters: Vec<CharFilter>) -> Self {
+        CharFilteredTokenizer { tokenizer, filters: Arc::new(filters), text: String::new(), maps: Vec::new(), kept: Vec::new() }
+    }
+
+    fn rewrite(&mut self, text: &str) {
+        self.text.clear();
+        self.text.push_str(text);
+        self.maps.clear();
+        self.kept.clear();
+        for filter in self.filters.iter() {
+            let replacements = filter.replacements(&self.text);
+            if replacements.is_empty() {
+                continue;
+            }
+            let (mut out, mut map, mut copied) = (String::with_capacity(self.text.len()), OffsetMap::default(), 0);
+            for r in replacements {
+                out.push_str(&self.text[copied..r.start]);
+                copied = r.end;
+                if let Some(token) = r.token {
+                    let (from, to) = (original(&self.maps, r.start, false), original(&self.maps, r.end, true));
+                    self.kept.push(Token { offset_from: from, offset_to: to, text: token, ..Token::default() });
+                }
+                let new_start = out.len();
+                out.push_str(&r.text);
+                map.replaced.push(Replaced { new_start, new_end: out.len(), orig_start: r.start, orig_end: r.end });
+            }
+            out.push_str(&self.text[copied..]);
+            self.text = out;
+            self.maps.push(map);
+        }
+        self.kept.sort_by_key(|t| t.offset_from);
+    }
+}
+
+/// `offset` in the last filter's output, as an offset into the original text.
+fn original(maps: &[OffsetMap], offset: usize, end: bool) -> usize {
+    maps.iter().rev().fold(offset, |offset, map| map.map(offset, end))
+}
+
+impl<T: Tokenizer> Tokenizer for CharFilteredTokenizer<T> {
+    type TokenStream<'a> = CharFilteredTokenStream<'a, T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        self.rewrite(text);
+        let CharFilteredTokenizer { tokenizer, text, maps, kept, .. } = self;
+        CharFilteredTokenStream {
+            tail: tokenizer.token_stream(text),
+            maps,
+            kept,
+            pending: None,
+            tail_done: false,
+            token: Token::default(),
+            last_position: None,
+            shift: 0,
+        }
+    }
+}
+
+/// The wrapped tokenizer's tokens with their offsets mapped back, and kept emoji in between.
+/// An emoji takes the position after the token before it, and the tokens after it move up one
+/// so that it sits between its neighbours for phrase queries too.
+pub struct CharFilteredTokenStream<'a, T> {
+    tail: T,
+    maps: &'a [OffsetMap],
+    kept: &'a [Token],
+    pending: Option<Token>,
+    tail_done: bool,
+    token: Token,
+    last_position: Option<usize>,
+    /// Emoji emitted so far, added to the wrapped tokenizer's positions
+    shift: usize,
+}
+
+impl<T: TokenStream> TokenStream for CharFilteredTokenStream<'_, T> {
+    fn advance(&mut self) -> bool {
+        if self.pending.is_none() && !self.tail_done {
+            match self.tail.advance() {
+                true => {
+                    let mut token = self.tail.token().clone();
+                    token.offset_from = original(self.maps, token.offset_from, false);
+                    token.offset_to = original(self.maps, token.offset_to, true);
+                    self.pending = Some(token);
+                }
+                false => self.tail_done = true,
+            }
+        }
+        let kept_first = match (self.kept.first(), &self.pending) {
+            (Some(kept), Some(token)) => kept.offset_from < token.offset_from,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        self.token = match kept_first {
+            true => {
+                let (kept, rest) = self.kept.split_first().expect("checked above");
+                self.kept = rest;
+                self.shift += 1;
+                Token { position: self.last_position.map_or(0, |p| p + 1), ..kept.clone() }
+            }
+            false => match self.pending.take() {
+                Some(token) => Token { position: token.position + self.shift, ..token },
+                None => return false,
+            },
+        };
+        self.last_position = Some(self.last_position.map_or(self.token.position, |p| p.max(self.token.position)));
+        true
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+/// Word n-grams: each run of `min_size..=max_size` consecutive tokens joined by `separator`
+/// becomes a token too (`quick brown fox` → `quick brown`, `quick brown fox`, `brown fox`), so
+/// adjacent words can be matched as terms without positions. A shingle takes its first
+/// word's position and offsets from its first to its last word.
+#[derive(Clone)]
+pub struct ShingleFilter {
+    pub min_size: usize,
+    pub max_size: usize,
+    pub separator: String,
+    /// Keep the single words as well
+    pub output_unigrams: bool,
+}
+
+impl TokenFilter for ShingleFilter {
+    type Tokenizer<T: Tokenizer> = ShingleTokenizer<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        ShingleTokenizer { tokenizer, filter: self }
+    }
+}
+
+#[derive(Clone)]
+pub struct ShingleTokenizer<T> {
+    tokenizer: T,
+    filter: ShingleFilter,
+}
+
+impl<T: Tokenizer> Tokenizer for ShingleTokenizer<T> {
+    type TokenStream<'a> = BufferedTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut words = Vec::new();
+        let mut tail = self.tokenizer.token_stream(text);
+        while tail.advance() {
+            words.push(tail.token().clone());
+        }
+        let f = &self.filter;
+        let mut tokens = Vec::new();
+        for (i, word) in words.iter().enumerate() {
+            if f.output_unigrams {
+                tokens.push(word.clone());
+            }
+            for size in f.min_size..=f.max_size.min(words.len() - i) {
+                let run = &words[i..i + size];
+                let text = run.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(&f.separator);
+                tokens.push(Token { text, offset_to: run[size - 1].offset_to, position_length: size, ..word.clone() });
+            }
+        }
+        BufferedTokenStream::new(tokens)
+    }
+}
+
+/// Tokens produced up front, for tokenizers that need to look ahead.
+pub struct BufferedTokenStream {
+    tokens: std::vec::IntoIter<Token>,
+    token: Token,
+}
+
+impl BufferedTokenStream {
+    fn new(tokens: Vec<Token>) -> Self {
+        BufferedTokenStream { tokens: tokens.into_iter(), token: Token::default() }
+    }
+}
+
+impl TokenStream for BufferedTokenStream {
+    fn advance(&mut self) -> bool {
+        match self.tokens.next() {
+            Some(token) => {
+                self.token = token;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+/// Operators kept as tokens, longest first so `::` is one token rather than two `:`.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "<<", ">>",
+    "..", "<", ">", "=", "!", "&", "|", "+", "-", "*", "/", "%", "^", "~", "?", "@", "#", ":",
+];
+
+/// Longest generic type kept whole, in bytes.
+const MAX_GENERIC_LEN: usize = 64;
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Byte ranges of the words of an identifier: split at underscores and where the case turns
+/// upper (`IndexWriter` → `Index` `Writer`, `HTTPServer` → `HTTP` `Server`, `utf8Decoder` →
+/// `utf8` `Decoder`).
+fn identifier_words(identifier: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = identifier.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, &(at, c)) in chars.iter().enumerate() {
+        if c == '_' {
+            words.extend(start.take().map(|s| (s, at)));
+            continue;
+        }
+        let Some(from) = start else {
+            start = Some(at);
+            continue;
+        };
+        let prev = chars[i - 1].1;
+        let next_lower = chars.get(i + 1).is_some_and(|(_, n)| n.is_lowercase());
+        if c.is_uppercase() && (prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower)) {
+            words.push((from, at));
+            start = Some(at);
+        }
+    }
+    words.extend(start.map(|s| (s, identifier.len())));
+    words
+}
+
+/// Where the type arguments opening at `at` (`<T>`, `<K, V>`, `<Option<&str>>`) close.
+fn generic_end(text: &str, at: usize) -> Option<usize> {
+    if !text[at..].starts_with('<') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in text[at..].char_indices() {
+        if i > MAX_GENERIC_LEN {
+            return None;
+        }
+        match c {
+            '<' => depth += 1,
+            '>' if depth == 1 => return Some(at + i + 1),
+            '>' => depth -= 1,
+            c if is_identifier_char(c) || matches!(c, ' ' | ',' | ':' | '&' | '\'' | '(' | ')' | '[' | ']') => {}
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Tokenizes source code and prose about it: identifiers split into their words
+/// (`IndexWriter` → `Index` `Writer`, `max_doc_id` → `max` `doc` `id`) while the whole
+/// identifier is kept at the first word's position, generic types kept whole besides their
+/// parts (`Vec<T>`), and operators (`::`, `->`, `&&`, `<`) as tokens of their own. Brackets,
+/// quotes, dots and other punctuation only separate.
+#[derive(Clone, Default)]
+pub struct CodeTokenizer;
+
+impl Tokenizer for CodeTokenizer {
+    type TokenStream<'a> = BufferedTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        let mut push = |from: usize, to: usize, position: usize| {
+            tokens.push(Token { offset_from: from, offset_to: to, position, text: text[from..to].to_string(), position_length: 1 });
+        };
+        let mut at = 0;
+        while let Some(c) = text[at..].chars().next() {
+            if is_identifier_char(c) {
+                let end = text[at..].find(|c| !is_identifier_char(c)).map_or(text.len(), |n| at + n);
+                let words = identifier_words(&text[at..end]);
+                if words.len() > 1 {
+                    push(at, end, position);
+                }
+                if let Some(close) = generic_end(text, end).filter(|_| !words.is_empty()) {
+                    push(at, close, position);
+                }
+                for (from, to) in words {
+                    push(at + from, at + to, position);
+                    position += 1;
+                }
+                at = end;
+            } else if let Some(op) = OPERATORS.iter().find(|op| text[at..].starts_with(**op)) {
+                push(at, at + op.len(), position);
+                position += 1;
+                at += op.len();
+            } else {
+                at += c.len_utf8();
+            }
+        }
+        BufferedTokenStream::new(tokens)
+    }
+}
+
+/// Tokenizes a path into its ancestors and itself, all at position 0: `/docs/guide/intro.md` →
+/// `/docs`, `/docs/guide`, `/docs/guide/intro.md`, so a query analyzed the same way matches
+/// everything under a directory. A trailing delimiter adds nothing, and in a URL the scheme
+/// and host stay together as the first part (`https://example.com`, `https://example.com/docs`).
+#[derive(Clone)]
+pub struct PathHierarchyTokenizer {
+    pub delimiter: char,
+}
+
+impl Tokenizer for PathHierarchyTokenizer {
+    type TokenStream<'a> = BufferedTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        // Offsets count from the untrimmed text
+        let lead = text.len() - text.trim_start().len();
+        let text = text.trim();
+        // Ancestors end before a delimiter; in a URL the first one ends after the host
+        let root = match text.find("://") {
+            Some(scheme) => text[scheme + 3..].find(self.delimiter).map_or(text.len(), |n| scheme + 3 + n),
+            None => 0,
+        };
+        let mut ends: Vec<usize> = text[root..].match_indices(self.delimiter).map(|(at, _)| root + at).collect();
+        ends.push(text.len());
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut last = 0;
+        for end in ends {
+            // Skip the empty parts of leading, doubled and trailing delimiters
+            if end == 0 || text[last..end].trim_matches(self.delimiter).is_empty() {
+                last = end;
+                continue;
+            }
+            tokens.push(Token { offset_from: lead, offset_to: lead + end, position: 0, text: text[..end].to_string(), position_length: 1 });
+            last = end;
+        }
+        BufferedTokenStream::new(tokens)
+    }
+}
+
+/// Normalizes tokens that look like numbers or versions so differently formatted ones index to
+/// the same term: `007` → `7`, `1,000,000` → `1000000`, `v1.02.3` → `1.2.3`. Tokens of three
+/// or more dot-separated parts, or with a `v` prefix, are versions and every part loses its
+/// leading zeros; with two parts they are decimals and only the integer part does (`3.05`
+/// stays `3.05`). Other tokens pass through untouched.
+#[derive(Debug, Clone, Copy)]
+pub struct NumericFilter {
+    pub strip_leading_zeros: bool,
+    /// Drop thousands separators from `1,000`-style groups
+    pub strip_grouping: bool,
+    /// Drop trailing zero parts of versions (`1.2.0` → `1.2`) and trailing zeros of decimals
+    /// (`3.50` → `3.5`, `2.0` → `2`); off by default, since `1.10` is then `1.1`
+    pub trim_trailing_zeros: bool,
+}
+
+impl NumericFilter {
+    /// The normalized form of `text`, or `None` when it isn't a number or version.
+    fn normalize(&self, text: &str) -> Option<String> {
+        let (number, versioned) = match text.strip_prefix(['v', 'V']) {
+            Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => (rest, true),
+            _ => (text, false),
+        };
+        if !number.starts_with(|c: char| c.is_ascii_digit()) || !number.bytes().all(|b| b.is_ascii_digit() || b == b'.' || b == b',') {
+            return None;
+        }
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let grouped = integer.contains(',')
+            && integer.split(',').enumerate().all(|(i, g)| (1..=3).contains(&g.len()) && (i == 0 || g.len() == 3));
+        let number = match (grouped, self.strip_grouping) {
+            (true, true) => number.replace(',', ""),
+            (false, _) if integer.contains(',') => return None,
+            _ => number.to_string(),
+        };
+        if fraction.contains(',') || number.split('.').any(str::is_empty) {
+            return None;
+        }
+        let mut parts: Vec<String> = number.split('.').map(str::to_string).collect();
+        let version = versioned || parts.len() >= 3;
+        let unpadded = |part: &str| match part.trim_start_matches('0') {
+            "" => "0".to_string(),
+            rest => rest.to_string(),
+        };
+        if self.strip_leading_zeros {
+            let count = if version { parts.len() } else { 1 };
+            parts.iter_mut().take(count).for_each(|p| *p = unpadded(p));
+        }
+        if self.trim_trailing_zeros {
+            if version {
+                while parts.len() > 1 && parts.last().is_some_and(|p| p.bytes().all(|b| b == b'0')) {
+                    parts.pop();
+                }
+            } else if parts.len() == 2 {
+                let fraction = parts[1].trim_end_matches('0').to_string();
+                if fraction.is_empty() {
+                    parts.pop();
+                } else {
+                    parts[1] = fraction;
+                }
+            }
+        }
+        Some(parts.join("."))
+    }
+}
+
+impl TokenFilter for NumericFilter {
+    type Tokenizer<T: Tokenizer> = NumericTokenizer<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        NumericTokenizer { tokenizer, filter: self }
+    }
+}
+
+#[derive(Clone)]
+pub struct NumericTokenizer<T> {
+    tokenizer: T,
+    filter: NumericFilter,
+}
+
+impl<T: Tokenizer> Tokenizer for NumericTokenizer<T> {
+    type TokenStream<'a> = NumericTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        NumericTokenStream { tail: self.tokenizer.token_stream(text), filter: self.filter }
+    }
+}
+
+pub struct NumericTokenStream<T> {
+    tail: T,
+    filter: NumericFilter,
+}
+
+impl<T: TokenStream> TokenStream for NumericTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        if let Some(normalized) = self.filter.normalize(&self.tail.token().text) {
+            self.tail.token_mut().text = normalized;
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
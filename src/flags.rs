@@ -0,0 +1,107 @@
+//! Feature flags: experimental subsystems can ship switched off and be turned on per
+//! deployment, with `--feature-flag clustering=off` (or `feature_flag = [...]` in the config
+//! file) at startup and `POST /admin/flags` at runtime, without a rebuild. A request for a
+//! feature that is off is a 400 naming the flag.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::service::SearchService;
+
+/// A switch and whether it starts on.
+pub struct Flag {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: bool,
+}
+
+/// Every flag; new experimental subsystems register here, off by default until they settle.
+pub const FLAGS: &[Flag] = &[
+    Flag { name: "clustering", description: "cluster=k on /search groups hits by content similarity", default: true },
+    Flag { name: "expansion", description: "expand=true on /search adds the tags of the top hits to the query", default: true },
+    Flag { name: "significant_terms", description: "GET /significant_terms", default: true },
+    Flag { name: "federation", description: "indexes=<remote>:posts on /search queries remote clusters", default: true },
+];
+
+/// One flag as `/admin/flags` lists it.
+#[derive(Serialize, Debug, Clone)]
+pub struct FlagState {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub enabled: bool,
+    pub default: bool,
+}
+
+pub struct FeatureFlags {
+    enabled: Vec<AtomicBool>,
+}
+
+impl FeatureFlags {
+    /// Every flag at its default, then `overrides`; unknown names are an error.
+    pub fn new(overrides: &[(String, bool)]) -> ServiceResult<Self> {
+        let flags = FeatureFlags { enabled: FLAGS.iter().map(|f| AtomicBool::new(f.default)).collect() };
+        for (name, enabled) in overrides {
+            flags.set(name, *enabled)?;
+        }
+        Ok(flags)
+    }
+
+    /// Whether `name` is a flag at all.
+    pub fn check(name: &str) -> ServiceResult<()> {
+        Self::position(name).map(drop)
+    }
+
+    fn position(name: &str) -> ServiceResult<usize> {
+        FLAGS.iter().position(|f| f.name == name).ok_or_else(|| {
+            let known: Vec<&str> = FLAGS.iter().map(|f| f.name).collect();
+            ServiceError::Invalid(format!("unknown feature flag: {} (known: {})", name, known.join(", ")))
+        })
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        Self::position(name).is_ok_and(|i| self.enabled[i].load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, name: &str, enabled: bool) -> ServiceResult<()> {
+        let i = Self::position(name)?;
+        if self.enabled[i].swap(enabled, Ordering::Relaxed) != enabled {
+            log::info!("feature flag {} turned {}", name, if enabled { "on" } else { "off" });
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Vec<FlagState> {
+        FLAGS
+            .iter()
+            .zip(&self.enabled)
+            .map(|(f, enabled)| FlagState { name: f.name, description: f.description, enabled: enabled.load(Ordering::Relaxed), default: f.default })
+            .collect()
+    }
+}
+
+/// `name`, `name=on` or `name=off` (also `true`/`false`), as `--feature-flag` takes it.
+pub fn parse_flag(s: &str) -> ServiceResult<(String, bool)> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "on"));
+    let enabled = match value.trim() {
+        "on" | "true" => true,
+        "off" | "false" => false,
+        other => return Err(ServiceError::Invalid(format!("feature flag {} must be on or off, got {}", name.trim(), other))),
+    };
+    Ok((name.trim().to_string(), enabled))
+}
+
+impl SearchService {
+    pub fn flags(&self) -> &FeatureFlags {
+        &self.flags
+    }
+
+    /// Turns away a request for the feature behind `flag` while it is off.
+    pub fn check_flag(&self, flag: &str) -> ServiceResult<()> {
+        match self.flags.is_enabled(flag) {
+            true => Ok(()),
+            false => Err(ServiceError::Invalid(format!("{} is not enabled on this server (feature flag `{}`)", flag.replace('_', " "), flag))),
+        }
+    }
+}
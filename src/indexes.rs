@@ -0,0 +1,785 @@
+//! Managed indexes: besides the built-in posts, comments and authors indexes, clients can
+//! create indexes of their own with `PUT /indexes/{name}` and a schema, write JSON documents to
+//! them and search them under `/indexes/{name}/...`, and drop them again. Each has its own
+//! writer, reader and hot-swapped searcher, committed with the posts on every refresh.
+//!
+//! Every index lives in a directory of `indexes_path` named after it, next to its spec in
+//! [`SPEC_FILE`], and is opened again on startup. Managed indexes aren't journaled, so
+//! followers don't see them, and aren't scoped to tenants.
+//!
+//! Aliases name an index for clients: the `/indexes/{name}/...` routes take either, and
+//! repointing an alias with `POST /aliases` moves every request after it to the new index at
+//! once, so a reindex into `blog_v2` is cut over from `blog_v1` without clients changing URLs.
+//! They are kept in [`ALIASES_FILE`] under `indexes_path`.
+//!
+//! A [`SchemaFile`] given with `--schema-file` declares indexes and aliases up front, so a
+//! deployment serving another dataset than blog posts gets its schema from configuration: what
+//! it declares is created on startup, and an index already there must match its declaration.
+//! Its `[posts]` section ([`PostsSpec`]) shapes the posts index too.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{AllQuery, Query, QueryParser};
+use tantivy::schema::{
+    DateOptions, Field, FieldType, IndexRecordOption, JsonObjectOptions, NumericOptions, Schema, TextFieldIndexing, TextOptions,
+};
+use tantivy::{IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, TantivyError, Term};
+
+use crate::analyzers::AnalyzerRegistry;
+use crate::error::{ServiceError, ServiceResult};
+use crate::query::{parse_with_parser, QueryLimits};
+use crate::schema::{doc_to_json, open_index};
+use crate::service::SearchService;
+
+/// The spec of an index, next to its files.
+pub const SPEC_FILE: &str = "index_spec.json";
+
+/// The aliases, next to the index directories.
+pub const ALIASES_FILE: &str = "_aliases.json";
+
+/// Names taken by the built-in indexes.
+pub const RESERVED_NAMES: &[&str] = &["posts", "comments", "authors"];
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    /// Analyzed text, searched by default
+    Text,
+    /// One term per value, for ids, tags and exact matches
+    String,
+    I64,
+    F64,
+    Bool,
+    /// RFC 3339 timestamps
+    Date,
+    /// Objects, searched by dotted path (`attrs.color:red`)
+    Json,
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    #[serde(default = "yes")]
+    pub stored: bool,
+    #[serde(default = "yes")]
+    pub indexed: bool,
+    /// Column-oriented storage for sorting and aggregations
+    #[serde(default)]
+    pub fast: bool,
+    /// Of `text` and `json` fields; `default` when unset. Built-in or registered with
+    /// `POST /analyzers`
+    #[serde(default, alias = "tokenizer", skip_serializing_if = "Option::is_none")]
+    pub analyzer: Option<String>,
+}
+
+/// What `PUT /indexes/{name}` takes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IndexSpec {
+    pub fields: Vec<FieldSpec>,
+    /// An indexed `string` field identifying documents: writing a document with an id already
+    /// there replaces it, and documents can be deleted by it. Without one documents are only
+    /// ever added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_field: Option<String>,
+}
+
+impl IndexSpec {
+    /// Checks the spec, `known` telling the analyzers that can be named.
+    pub fn validate(&self, known: &dyn Fn(&str) -> bool) -> Result<(), String> {
+        if self.fields.is_empty() {
+            return Err("an index needs at least one field".to_string());
+        }
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            let valid = !field.name.is_empty()
+                && field.name.len() <= MAX_NAME_LEN
+                && !field.name.starts_with('_')
+                && field.name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+            if !valid {
+                return Err(format!("field names are 1 to {} of a-z, A-Z, 0-9 and _, not starting with _, got {:?}", MAX_NAME_LEN, field.name));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(format!("field {} is defined twice", field.name));
+            }
+            match (&field.analyzer, field.kind) {
+                (None, _) => {}
+                (Some(analyzer), FieldKind::Text | FieldKind::Json) if known(analyzer) => {}
+                (Some(analyzer), FieldKind::Text | FieldKind::Json) => {
+                    return Err(format!("field {}: unknown analyzer {}; see GET /analyzers", field.name, analyzer))
+                }
+                (Some(_), _) => return Err(format!("field {}: only text and json fields take an analyzer", field.name)),
+            }
+        }
+        if let Some(id_field) = &self.id_field {
+            match self.fields.iter().find(|f| f.name == *id_field) {
+                Some(f) if f.kind == FieldKind::String && f.indexed => {}
+                Some(_) => return Err(format!("id_field {} must be an indexed string field", id_field)),
+                None => return Err(format!("id_field {} is not a field", id_field)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn schema(&self) -> Schema {
+        let mut builder = Schema::builder();
+        for f in &self.fields {
+            let analyzer = f.analyzer.as_deref().unwrap_or("default");
+            match f.kind {
+                FieldKind::Text | FieldKind::String => {
+                    let (tokenizer, record) = match f.kind {
+                        FieldKind::Text => (analyzer, IndexRecordOption::WithFreqsAndPositions),
+                        _ => ("raw", IndexRecordOption::Basic),
+                    };
+                    let mut options = TextOptions::default();
+                    if f.indexed {
+                        options = options.set_indexing_options(TextFieldIndexing::default().set_tokenizer(tokenizer).set_index_option(record));
+                    }
+                    if f.stored {
+                        options = options.set_stored();
+                    }
+                    if f.fast {
+                        options = options.set_fast(None);
+                    }
+                    builder.add_text_field(&f.name, options);
+                }
+                FieldKind::I64 | FieldKind::F64 | FieldKind::Bool => {
+                    let mut options = NumericOptions::default();
+                    if f.indexed {
+                        options = options.set_indexed();
+                    }
+                    if f.stored {
+                        options = options.set_stored();
+                    }
+                    if f.fast {
+                        options = options.set_fast();
+                    }
+                    match f.kind {
+                        FieldKind::I64 => builder.add_i64_field(&f.name, options),
+                        FieldKind::F64 => builder.add_f64_field(&f.name, options),
+                        _ => builder.add_bool_field(&f.name, options),
+                    };
+                }
+                FieldKind::Date => {
+                    let mut options = DateOptions::default();
+                    if f.indexed {
+                        options = options.set_indexed();
+                    }
+                    if f.stored {
+                        options = options.set_stored();
+                    }
+                    if f.fast {
+                        options = options.set_fast();
+                    }
+                    builder.add_date_field(&f.name, options);
+                }
+                FieldKind::Json => {
+                    let mut options = JsonObjectOptions::default();
+                    if f.indexed {
+                        options = options.set_indexing_options(
+                            TextFieldIndexing::default().set_tokenizer(analyzer).set_index_option(IndexRecordOption::WithFreqsAndPositions),
+                        );
+                    }
+                    if f.stored {
+                        options = options.set_stored();
+                    }
+                    if f.fast {
+                        options = options.set_fast(None);
+                    }
+                    builder.add_json_field(&f.name, options);
+                }
+            }
+        }
+        builder.build()
+    }
+}
+
+/// Built-in posts fields a [`PostsSpec`] may re-declare, to pick their analyzer.
+pub const ANALYZED_POST_FIELDS: &[&str] = &["title", "body", "features"];
+
+/// The posts index's fields as a schema file declares them. `title`, `body` and `features` may
+/// be re-declared with the options they have to pick another analyzer; every other field is
+/// added to the built-in schema and filled from the same-named top-level key of each post's
+/// `features`, so posts can carry another dataset's typed fields (`sku`, a fast `price`).
+/// The rest of the built-in schema is what the posts routes are written against and stays.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PostsSpec {
+    #[serde(default)]
+    pub fields: Vec<FieldSpec>,
+}
+
+impl PostsSpec {
+    /// Checks the spec against the built-in posts schema, `known` telling the analyzers that
+    /// can be named.
+    pub fn validate(&self, builtin: &Schema, known: &dyn Fn(&str) -> bool) -> Result<(), String> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        IndexSpec { fields: self.fields.clone(), id_field: None }.validate(known)?;
+        for field in &self.fields {
+            let Ok(at) = builtin.get_field(&field.name) else { continue };
+            let entry = builtin.get_field_entry(at);
+            let same = ANALYZED_POST_FIELDS.contains(&field.name.as_str())
+                && matches_kind(entry.field_type(), field.kind)
+                && field.kind != FieldKind::String
+                && (entry.is_indexed(), entry.is_stored(), entry.is_fast()) == (field.indexed, field.stored, field.fast);
+            if !same {
+                return Err(format!(
+                    "field {} is built in; only {} can be re-declared, with their built-in type and flags, to pick an analyzer",
+                    field.name,
+                    ANALYZED_POST_FIELDS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The analyzer declared for the built-in `field`.
+    pub fn analyzer(&self, field: &str) -> Option<&str> {
+        self.fields.iter().find(|f| f.name == field).and_then(|f| f.analyzer.as_deref())
+    }
+
+    /// The fields added to the built-in ones.
+    pub fn extra_fields(&self) -> Vec<String> {
+        self.fields.iter().filter(|f| !ANALYZED_POST_FIELDS.contains(&f.name.as_str())).map(|f| f.name.clone()).collect()
+    }
+
+    /// `builtin` with the extra fields added.
+    pub fn extend(&self, builtin: Schema) -> Schema {
+        let extra = self.extra_fields();
+        if extra.is_empty() {
+            return builtin;
+        }
+        let mut builder = Schema::builder();
+        for (_, entry) in builtin.fields() {
+            builder.add_field(entry.clone());
+        }
+        let fields = self.fields.iter().filter(|f| extra.contains(&f.name)).cloned().collect();
+        for (_, entry) in (IndexSpec { fields, id_field: None }).schema().fields() {
+            builder.add_field(entry.clone());
+        }
+        builder.build()
+    }
+}
+
+/// Indexes and aliases declared by `--schema-file`, in TOML (`[indexes.products]` with
+/// `[[indexes.products.fields]]`) or JSON, and the posts index's fields.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaFile {
+    #[serde(default)]
+    pub indexes: BTreeMap<String, IndexSpec>,
+    /// Alias to index name
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub posts: PostsSpec,
+}
+
+/// Reads a schema file, TOML when its name ends in `.toml` and JSON otherwise.
+pub fn load_schema_file(path: &PathBuf) -> anyhow::Result<SchemaFile> {
+    let text = std::fs::read_to_string(path)?;
+    let file: SchemaFile = match path.extension().is_some_and(|e| e == "toml") {
+        true => toml::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?,
+        false => serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?,
+    };
+    for (alias, index) in &file.aliases {
+        if !file.indexes.contains_key(index) {
+            anyhow::bail!("{}: alias {} points at {}, which the file doesn't declare", path.display(), alias, index);
+        }
+    }
+    Ok(file)
+}
+
+/// Index and alias names are 1 to 64 of a-z, 0-9, `_` and `-`, starting with a letter or digit.
+pub fn check_index_name(name: &str) -> ServiceResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with(['_', '-'])
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+    if !valid {
+        return Err(ServiceError::Invalid(format!("index names are 1 to {} of a-z, 0-9, _ and -, not starting with _ or -, got {:?}", MAX_NAME_LEN, name)));
+    }
+    if RESERVED_NAMES.contains(&name) {
+        return Err(ServiceError::Invalid(format!("{} is a built-in index", name)));
+    }
+    Ok(())
+}
+
+/// An index as `GET /indexes` lists it.
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub spec: IndexSpec,
+    /// Committed documents
+    pub docs: u64,
+    pub segments: usize,
+    /// Aliases pointing at the index
+    pub aliases: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexHit {
+    pub score: f32,
+    /// Stored fields by name; fields holding several values are arrays
+    pub doc: serde_json::Value,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexSearchResults {
+    pub total: usize,
+    pub hits: Vec<IndexHit>,
+}
+
+pub struct ManagedIndex {
+    pub name: String,
+    pub spec: IndexSpec,
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+    current_searcher: ArcSwap<Searcher>,
+    committed_opstamp: AtomicU64,
+    /// `None` when kept in RAM
+    dir: Option<PathBuf>,
+}
+
+impl ManagedIndex {
+    fn open(name: &str, spec: IndexSpec, dir: Option<PathBuf>, analyzers: &AnalyzerRegistry) -> tantivy::Result<Self> {
+        let path = dir.clone().unwrap_or_default();
+        let index = open_index(&path, spec.schema(), IndexSettings::default(), dir.is_none())?;
+        analyzers.install_custom(index.tokenizers()).map_err(TantivyError::InvalidArgument)?;
+        let writer = index.writer(15_000_000)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let current_searcher = ArcSwap::from_pointee(reader.searcher());
+        Ok(ManagedIndex {
+            name: name.to_string(),
+            spec,
+            writer: Mutex::new(writer),
+            reader,
+            current_searcher,
+            committed_opstamp: AtomicU64::new(0),
+            dir,
+        })
+    }
+
+    pub fn writer(&self) -> MutexGuard<'_, IndexWriter> {
+        match self.writer.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    pub fn searcher(&self) -> Arc<Searcher> {
+        self.current_searcher.load_full()
+    }
+
+    /// Commits, reloads and swaps in a fresh searcher.
+    pub fn refresh(&self) -> tantivy::Result<()> {
+        let opstamp = self.writer().commit()?;
+        self.reader.reload()?;
+        self.current_searcher.store(Arc::new(self.reader.searcher()));
+        self.committed_opstamp.fetch_max(opstamp, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the live searcher shows the write stamped `opstamp`.
+    pub fn is_searchable(&self, opstamp: u64) -> bool {
+        self.committed_opstamp.load(Ordering::Acquire) >= opstamp
+    }
+
+    fn id_field(&self, schema: &Schema) -> Option<Field> {
+        self.spec.id_field.as_deref().and_then(|name| schema.get_field(name).ok())
+    }
+
+    /// Adds `docs`, each a JSON object of the spec's fields, replacing documents with the same
+    /// `id_field` value. Either all of them are valid and added or none is.
+    pub fn add(&self, docs: Vec<serde_json::Value>) -> ServiceResult<u64> {
+        let schema = self.reader.searcher().index().schema();
+        let mut parsed = Vec::with_capacity(docs.len());
+        for (i, doc) in docs.into_iter().enumerate() {
+            let invalid = |msg: String| ServiceError::Invalid(format!("document {}: {}", i, msg));
+            let serde_json::Value::Object(object) = doc else { return Err(invalid("expected a JSON object".to_string())) };
+            if let Some(unknown) = object.keys().find(|k| schema.get_field(k).is_err()) {
+                return Err(invalid(format!("{} is not a field of {}", unknown, self.name)));
+            }
+            let id = match &self.spec.id_field {
+                Some(id_field) => match object.get(id_field) {
+                    Some(serde_json::Value::String(id)) => Some(id.clone()),
+                    _ => return Err(invalid(format!("needs a string {}", id_field))),
+                },
+                None => None,
+            };
+            parsed.push((id, TantivyDocument::from_json_object(&schema, object).map_err(|e| invalid(e.to_string()))?));
+        }
+        let writer = self.writer();
+        let mut opstamp = 0;
+        for (id, doc) in parsed {
+            if let (Some(id), Some(f_id)) = (id, self.id_field(&schema)) {
+                writer.delete_term(Term::from_field_text(f_id, &id));
+            }
+            opstamp = writer.add_document(doc)?;
+        }
+        Ok(opstamp)
+    }
+
+    pub fn delete(&self, id: &str) -> ServiceResult<u64> {
+        let schema = self.reader.searcher().index().schema();
+        let f_id = self
+            .id_field(&schema)
+            .ok_or_else(|| ServiceError::Invalid(format!("{} has no id_field, so documents can't be deleted by id", self.name)))?;
+        Ok(self.writer().delete_term(Term::from_field_text(f_id, id)))
+    }
+
+    /// Parses `q` over the indexed text and json fields, or the string fields when there are
+    /// none; blank matches every document.
+    fn parse_query(&self, searcher: &Searcher, q: &str, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+        if q.trim().is_empty() {
+            return Ok(Box::new(AllQuery));
+        }
+        let schema = searcher.index().schema();
+        let default_fields: Vec<Field> = self.default_search_fields().iter().filter_map(|name| schema.get_field(name).ok()).collect();
+        parse_with_parser(&QueryParser::for_index(searcher.index(), default_fields), q, limits)
+    }
+
+    /// Fields a query term without a field name is searched in: the indexed text and json
+    /// fields, or the string ones when there are none.
+    pub fn default_search_fields(&self) -> Vec<String> {
+        let indexed = |kinds: &[FieldKind]| -> Vec<String> {
+            self.spec.fields.iter().filter(|f| f.indexed && kinds.contains(&f.kind)).map(|f| f.name.clone()).collect()
+        };
+        let fields = indexed(&[FieldKind::Text, FieldKind::Json]);
+        match fields.is_empty() {
+            true => indexed(&[FieldKind::String]),
+            false => fields,
+        }
+    }
+
+    pub fn search(&self, q: &str, limit: usize, limits: &QueryLimits) -> ServiceResult<IndexSearchResults> {
+        let searcher = self.searcher();
+        let query = self.parse_query(&searcher, q, limits)?;
+        let (total, top) = searcher.search(query.as_ref(), &(Count, TopDocs::with_limit(limit.max(1))))?;
+        let schema = searcher.index().schema();
+        let mut hits = Vec::with_capacity(top.len());
+        for (score, addr) in top.into_iter().take(limit) {
+            let doc: TantivyDocument = searcher.doc(addr)?;
+            hits.push(IndexHit { score, doc: doc_to_json(&schema, &doc) });
+        }
+        Ok(IndexSearchResults { total, hits })
+    }
+
+    pub fn info(&self) -> IndexInfo {
+        let searcher = self.searcher();
+        IndexInfo {
+            name: self.name.clone(),
+            spec: self.spec.clone(),
+            docs: searcher.num_docs(),
+            segments: searcher.segment_readers().len(),
+            aliases: Vec::new(),
+        }
+    }
+}
+
+/// Whether `field_type` is one the spec would have produced; spec files edited by hand are
+/// caught on startup.
+fn matches_kind(field_type: &FieldType, kind: FieldKind) -> bool {
+    matches!(
+        (field_type, kind),
+        (FieldType::Str(_), FieldKind::Text | FieldKind::String)
+            | (FieldType::I64(_), FieldKind::I64)
+            | (FieldType::F64(_), FieldKind::F64)
+            | (FieldType::Bool(_), FieldKind::Bool)
+            | (FieldType::Date(_), FieldKind::Date)
+            | (FieldType::JsonObject(_), FieldKind::Json)
+    )
+}
+
+pub struct IndexRegistry {
+    // Locked before `aliases` when both are
+    indexes: RwLock<BTreeMap<String, Arc<ManagedIndex>>>,
+    /// Alias to index name
+    aliases: RwLock<BTreeMap<String, String>>,
+    /// Where the indexes live; `None` keeps them in RAM
+    path: Option<PathBuf>,
+}
+
+impl IndexRegistry {
+    /// Opens every index under `path`, if it exists.
+    pub fn open(path: Option<PathBuf>, analyzers: &AnalyzerRegistry) -> anyhow::Result<Self> {
+        let mut indexes = BTreeMap::new();
+        if let Some(root) = path.as_deref().filter(|p| p.exists()) {
+            for entry in std::fs::read_dir(root)? {
+                let dir = entry?.path();
+                let spec_path = dir.join(SPEC_FILE);
+                let Some(name) = dir.file_name().and_then(|n| n.to_str()).map(str::to_string) else { continue };
+                if !spec_path.exists() {
+                    continue;
+                }
+                let spec: IndexSpec = serde_json::from_slice(&std::fs::read(&spec_path)?)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", spec_path.display(), e))?;
+                let index = ManagedIndex::open(&name, spec, Some(dir.clone()), analyzers)?;
+                let schema = index.reader.searcher().index().schema();
+                for field in &index.spec.fields {
+                    let matches = schema.get_field(&field.name).is_ok_and(|f| matches_kind(schema.get_field_entry(f).field_type(), field.kind));
+                    if !matches {
+                        anyhow::bail!("{}: field {} doesn't match the index in {}", spec_path.display(), field.name, dir.display());
+                    }
+                }
+                indexes.insert(name, Arc::new(index));
+            }
+        }
+        let mut aliases: BTreeMap<String, String> = BTreeMap::new();
+        if let Some(aliases_path) = path.as_ref().map(|root| root.join(ALIASES_FILE)).filter(|p| p.exists()) {
+            aliases = serde_json::from_slice(&std::fs::read(&aliases_path)?).map_err(|e| anyhow::anyhow!("{}: {}", aliases_path.display(), e))?;
+            aliases.retain(|alias, index| {
+                let found = indexes.contains_key(index);
+                if !found {
+                    log::warn!("{}: alias {} points at missing index {}; dropped", aliases_path.display(), alias, index);
+                }
+                found
+            });
+        }
+        Ok(IndexRegistry { indexes: RwLock::new(indexes), aliases: RwLock::new(aliases), path })
+    }
+
+    /// Creates the indexes and aliases `file` declares that aren't there yet. An index already
+    /// there with another spec fails startup, as its documents were indexed by the old one:
+    /// declare the new spec under another name and move the alias once it is filled. An alias
+    /// moved at runtime is left where it points.
+    pub fn declare(&self, file: &SchemaFile, analyzers: &AnalyzerRegistry) -> anyhow::Result<()> {
+        for (name, spec) in &file.indexes {
+            match self.get(name) {
+                Some(existing) if existing.spec == *spec => {}
+                Some(_) => anyhow::bail!("the schema file declares index {} with another spec than the existing one; declare it under a new name", name),
+                None => {
+                    self.create(name, spec.clone(), analyzers).map_err(|e| anyhow::anyhow!("schema file, index {}: {}", name, e))?;
+                }
+            }
+        }
+        let aliases = self.aliases();
+        for (alias, index) in &file.aliases {
+            match aliases.get(alias) {
+                Some(current) if current == index => {}
+                Some(current) => log::warn!("alias {} points at {}, not {} as the schema file has it; left as is", alias, current, index),
+                None => {
+                    self.set_alias(alias, index).map_err(|e| anyhow::anyhow!("schema file, alias {}: {}", alias, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The index named `name`, aliases left aside.
+    pub fn get(&self, name: &str) -> Option<Arc<ManagedIndex>> {
+        self.indexes.read().unwrap_or_else(|p| p.into_inner()).get(name).cloned()
+    }
+
+    /// The index `name` stands for: the index of that name, or the one the alias points at.
+    pub fn resolve(&self, name: &str) -> Option<Arc<ManagedIndex>> {
+        let target = self.aliases.read().unwrap_or_else(|p| p.into_inner()).get(name).cloned();
+        self.get(target.as_deref().unwrap_or(name))
+    }
+
+    /// `index` as `GET /indexes` lists it.
+    pub fn describe(&self, index: &ManagedIndex) -> IndexInfo {
+        let mut info = index.info();
+        let aliases = self.aliases.read().unwrap_or_else(|p| p.into_inner());
+        info.aliases = aliases.iter().filter(|(_, target)| **target == index.name).map(|(alias, _)| alias.clone()).collect();
+        info
+    }
+
+    /// Every alias and the index it points at.
+    pub fn aliases(&self) -> BTreeMap<String, String> {
+        self.aliases.read().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    /// Points `alias` at `index`, creating it or moving it off the index it pointed at; which
+    /// that was, if any. Requests resolving the alias afterwards get `index`.
+    pub fn set_alias(&self, alias: &str, index: &str) -> ServiceResult<Option<String>> {
+        check_index_name(alias)?;
+        let indexes = self.indexes.read().unwrap_or_else(|p| p.into_inner());
+        if indexes.contains_key(alias) {
+            return Err(ServiceError::Conflict(format!("{} is an index; aliases can't share its name", alias)));
+        }
+        if !indexes.contains_key(index) {
+            return Err(ServiceError::Invalid(format!("no index named {}", index)));
+        }
+        let mut aliases = self.aliases.write().unwrap_or_else(|p| p.into_inner());
+        let mut updated = aliases.clone();
+        let previous = updated.insert(alias.to_string(), index.to_string());
+        self.persist_aliases(&updated)?;
+        *aliases = updated;
+        log::info!("alias {} now points at {}", alias, index);
+        Ok(previous)
+    }
+
+    /// Removes `alias`; the index it pointed at, or `None` when there was no such alias.
+    pub fn remove_alias(&self, alias: &str) -> ServiceResult<Option<String>> {
+        let mut aliases = self.aliases.write().unwrap_or_else(|p| p.into_inner());
+        let mut updated = aliases.clone();
+        let Some(previous) = updated.remove(alias) else { return Ok(None) };
+        self.persist_aliases(&updated)?;
+        *aliases = updated;
+        Ok(Some(previous))
+    }
+
+    fn persist_aliases(&self, aliases: &BTreeMap<String, String>) -> ServiceResult<()> {
+        let Some(root) = &self.path else { return Ok(()) };
+        std::fs::create_dir_all(root)?;
+        let json = serde_json::to_vec_pretty(aliases).map_err(|e| ServiceError::Internal(e.to_string()))?;
+        Ok(write_atomically(&root.join(ALIASES_FILE), &json)?)
+    }
+
+    /// Every index, by name.
+    pub fn list(&self) -> Vec<Arc<ManagedIndex>> {
+        self.indexes.read().unwrap_or_else(|p| p.into_inner()).values().cloned().collect()
+    }
+
+    /// Creates the index `name`; a `Conflict` when there already is one.
+    pub fn create(&self, name: &str, spec: IndexSpec, analyzers: &AnalyzerRegistry) -> ServiceResult<Arc<ManagedIndex>> {
+        check_index_name(name)?;
+        spec.validate(&|name| analyzers.is_known(name)).map_err(ServiceError::Invalid)?;
+        let mut indexes = self.indexes.write().unwrap_or_else(|p| p.into_inner());
+        if indexes.contains_key(name) {
+            return Err(ServiceError::Conflict(format!("index {} already exists", name)));
+        }
+        if let Some(target) = self.aliases.read().unwrap_or_else(|p| p.into_inner()).get(name) {
+            return Err(ServiceError::Conflict(format!("{} is an alias of {}; indexes can't share its name", name, target)));
+        }
+        let dir = self.path.as_ref().map(|root| root.join(name));
+        if let Some(dir) = dir.as_deref().filter(|d| d.exists()) {
+            return Err(ServiceError::Conflict(format!("{} already exists; remove it or pick another name", dir.display())));
+        }
+        let index = match ManagedIndex::open(name, spec, dir.clone(), analyzers) {
+            Ok(index) => Arc::new(index),
+            Err(e) => {
+                remove_dir(dir.as_deref());
+                return Err(e.into());
+            }
+        };
+        if let Some(dir) = &dir {
+            let written = serde_json::to_vec_pretty(&index.spec)
+                .map_err(|e| ServiceError::Internal(e.to_string()))
+                .and_then(|json| write_atomically(&dir.join(SPEC_FILE), &json).map_err(ServiceError::from));
+            if let Err(e) = written {
+                drop(index);
+                remove_dir(Some(dir));
+                return Err(e);
+            }
+        }
+        indexes.insert(name.to_string(), Arc::clone(&index));
+        log::info!("created index {}", name);
+        Ok(index)
+    }
+
+    /// Drops the index `name` and deletes its files; `false` when there is no such index.
+    /// Requests already holding it finish against the dropped index. An index aliases point at
+    /// is a `Conflict`: move or remove them first, so no alias is left dangling.
+    pub fn drop_index(&self, name: &str) -> ServiceResult<bool> {
+        let mut indexes = self.indexes.write().unwrap_or_else(|p| p.into_inner());
+        let aliases = self.aliases.read().unwrap_or_else(|p| p.into_inner());
+        if let Some(target) = aliases.get(name) {
+            return Err(ServiceError::Invalid(format!("{} is an alias of {}; remove the alias or drop {}", name, target, target)));
+        }
+        let pointing: Vec<&str> = aliases.iter().filter(|(_, target)| *target == name).map(|(alias, _)| alias.as_str()).collect();
+        if !pointing.is_empty() {
+            return Err(ServiceError::Conflict(format!("aliases {} point at {}; move or remove them first", pointing.join(", "), name)));
+        }
+        drop(aliases);
+        let removed = indexes.remove(name);
+        drop(indexes);
+        let Some(index) = removed else { return Ok(false) };
+        let dir = index.dir.clone();
+        drop(index);
+        remove_dir(dir.as_deref());
+        log::info!("dropped index {}", name);
+        Ok(true)
+    }
+
+    /// Commits every index and swaps in fresh searchers.
+    pub fn refresh(&self) -> tantivy::Result<()> {
+        for index in self.list() {
+            index.refresh()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_atomically(path: &Path, json: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn remove_dir(dir: Option<&Path>) {
+    if let Some(dir) = dir {
+        if let Err(e) = std::fs::remove_dir_all(dir) {
+            log::warn!("removing {}: {}", dir.display(), e);
+        }
+    }
+}
+
+impl SearchService {
+    pub fn indexes(&self) -> &IndexRegistry {
+        &self.indexes
+    }
+
+    pub fn create_index(&self, name: &str, spec: IndexSpec) -> ServiceResult<IndexInfo> {
+        let index = self.indexes.create(name, spec, &self.analyzers)?;
+        Ok(self.indexes.describe(&index))
+    }
+
+    /// Adds `docs` to `index` (see [`ManagedIndex::add`]).
+    pub fn index_into(&self, index: &ManagedIndex, docs: Vec<serde_json::Value>) -> ServiceResult<u64> {
+        let opstamp = index.add(docs)?;
+        if self.config().sync_commits {
+            index.refresh()?;
+        }
+        Ok(opstamp)
+    }
+
+    pub fn delete_from(&self, index: &ManagedIndex, id: &str) -> ServiceResult<u64> {
+        let opstamp = index.delete(id)?;
+        if self.config().sync_commits {
+            index.refresh()?;
+        }
+        Ok(opstamp)
+    }
+
+    pub fn search_index(&self, index: &ManagedIndex, q: &str, limit: usize) -> ServiceResult<IndexSearchResults> {
+        index.search(q, limit, &self.query_limits())
+    }
+
+    /// Waits up to `max_wait` for a refresh to make the write to `index` stamped `opstamp`
+    /// searchable, then refreshes `index` itself.
+    pub async fn wait_for_index_refresh(&self, index: &ManagedIndex, opstamp: u64, max_wait: Duration) -> ServiceResult<()> {
+        let mut commits = self.subscribe_commits();
+        let waited = tokio::time::timeout(max_wait, async {
+            while !index.is_searchable(opstamp) {
+                if commits.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+        match waited.await {
+            Ok(()) if index.is_searchable(opstamp) => Ok(()),
+            _ => Ok(index.refresh()?),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Journal of accepted post operations, read by follower nodes that apply them to their own
+//! indexes (logical replication, see [`replica`](crate::replica)) instead of copying segments.
+//!
+//! Every acknowledged index, update, delete, batch and erasure, and every retention run that
+//! deleted or archived posts, is appended under the next offset. Followers only see entries a commit has covered, so they never get ahead of what
+//! the leader would keep after a crash. The journal lives in memory: a restart starts a new
+//! journal under a fresh `journal_id`, and followers that notice the change, or that fell
+//! behind the oldest retained entry, catch up from a snapshot plus the offset it was taken at.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::DocSetCollector;
+use tantivy::query::AllQuery;
+use tantivy::{DocAddress, Searcher};
+use tokio::sync::watch;
+
+use crate::batch::BatchOp;
+use crate::erase::ErasureRequest;
+use crate::error::{ServiceError, ServiceResult};
+use crate::nested::posts_only;
+use crate::schema::BlogPost;
+use crate::service::{SearchService, Tier};
+use crate::now_secs;
+
+/// What a journal entry replays.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum JournalOp {
+    /// Post writes, as accepted (after moderation, before redaction)
+    Batch { ops: Vec<BatchOp> },
+    Erase { request: ErasureRequest },
+    /// Posts an `archive` retention rule moved from the hot index into the archive tier, as
+    /// they were moved (retention deletes journal as a batch of deletes)
+    Archive { posts: Vec<BlogPost> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub offset: u64,
+    pub recorded_at: i64,
+    #[serde(flatten)]
+    pub op: JournalOp,
+}
+
+/// First line of `/replication/snapshot`; the posts follow one per line, as [`SnapshotPost`]s.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotHeader {
+    pub journal_id: String,
+    /// Every entry up to this offset is reflected in the posts
+    pub offset: u64,
+    pub posts: u64,
+}
+
+/// A post of `/replication/snapshot` with the tier it is in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotPost {
+    pub tier: Tier,
+    pub post: BlogPost,
+}
+
+/// Each tier with its searcher and the addresses of its posts, in doc order.
+pub type SnapshotTiers = Vec<(Tier, Arc<Searcher>, Vec<DocAddress>)>;
+
+/// Why entries from an offset can't be served; the reader has to start over from a snapshot.
+#[derive(Serialize, Debug, Clone)]
+pub struct JournalGap {
+    pub journal_id: String,
+    /// Oldest entry still held
+    pub first_offset: u64,
+    pub committed_offset: u64,
+}
+
+struct JournalLog {
+    entries: VecDeque<JournalEntry>,
+    next_offset: u64,
+}
+
+pub struct Journal {
+    id: String,
+    max_entries: usize,
+    log: Mutex<JournalLog>,
+    /// Highest offset covered by a commit
+    committed: watch::Sender<u64>,
+}
+
+impl Journal {
+    /// An empty journal keeping the latest `max_entries` entries.
+    pub fn new(max_entries: usize) -> Self {
+        Journal {
+            id: format!("{:016x}", rand::random::<u64>()),
+            max_entries: max_entries.max(1),
+            log: Mutex::new(JournalLog { entries: VecDeque::new(), next_offset: 1 }),
+            committed: watch::channel(0).0,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JournalLog> {
+        self.log.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Appends `op` and returns its offset.
+    pub(crate) fn append(&self, op: JournalOp) -> u64 {
+        let mut log = self.lock();
+        let offset = log.next_offset;
+        log.next_offset += 1;
+        log.entries.push_back(JournalEntry { offset, recorded_at: now_secs(), op });
+        while log.entries.len() > self.max_entries {
+            log.entries.pop_front();
+        }
+        offset
+    }
+
+    /// Offset of the latest entry, 0 when there is none.
+    pub fn last_offset(&self) -> u64 {
+        self.lock().next_offset - 1
+    }
+
+    pub fn committed_offset(&self) -> u64 {
+        *self.committed.borrow()
+    }
+
+    /// Marks entries up to `offset` as covered by a commit.
+    pub(crate) fn publish_committed(&self, offset: u64) {
+        self.committed.send_if_modified(|committed| {
+            let advanced = offset > *committed;
+            *committed = (*committed).max(offset);
+            advanced
+        });
+    }
+
+    /// Receiver whose value is the committed offset.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.committed.subscribe()
+    }
+
+    /// Waits up to `timeout` for `offset` to be committed.
+    pub async fn wait_committed(&self, offset: u64, timeout: Duration) {
+        let mut committed = self.subscribe();
+        let _ = tokio::time::timeout(timeout, committed.wait_for(|c| *c >= offset)).await;
+    }
+
+    /// Drops every entry before `offset`; readers still behind it need a new snapshot.
+    pub(crate) fn truncate_before(&self, offset: u64) {
+        let mut log = self.lock();
+        while log.entries.front().is_some_and(|e| e.offset < offset) {
+            log.entries.pop_front();
+        }
+    }
+
+    /// Up to `limit` committed entries starting at offset `from`, or the gap when entries
+    /// from there are gone or were never written by this journal.
+    pub fn read(&self, from: u64, limit: usize) -> Result<Vec<JournalEntry>, JournalGap> {
+        let committed = self.committed_offset();
+        let log = self.lock();
+        let first_offset = log.entries.front().map_or(log.next_offset, |e| e.offset);
+        if from < first_offset || from > committed + 1 {
+            return Err(JournalGap { journal_id: self.id.clone(), first_offset, committed_offset: committed });
+        }
+        let skip = (from - first_offset) as usize;
+        Ok(log.entries.iter().skip(skip).take_while(|e| e.offset <= committed).take(limit).cloned().collect())
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let log = self.lock();
+        serde_json::json!({
+            "journal_id": self.id,
+            "entries": log.entries.len(),
+            "max_entries": self.max_entries,
+            "first_offset": log.entries.front().map_or(log.next_offset, |e| e.offset),
+            "last_offset": log.next_offset - 1,
+            "committed_offset": self.committed_offset(),
+        })
+    }
+}
+
+impl SearchService {
+    /// A starting point for a new follower: the committed journal offset and the posts of both
+    /// tiers as of a searcher taken after it. Entries past the offset may already show in the
+    /// posts; followers apply entries idempotently, so replaying them is harmless.
+    pub fn replication_snapshot(&self) -> ServiceResult<(SnapshotHeader, SnapshotTiers)> {
+        let Some(journal) = &self.journal else {
+            return Err(ServiceError::Invalid("this node keeps no replication journal".to_string()));
+        };
+        self.refresh()?;
+        let offset = journal.committed_offset();
+        let mut tiers = Vec::new();
+        let mut posts = 0;
+        for (tier, searcher) in [(Tier::Hot, self.current_searcher.load_full()), (Tier::Archive, self.archive.current_searcher.load_full())] {
+            let schema = searcher.index().schema();
+            let mut addrs: Vec<DocAddress> = searcher.search(posts_only(&schema, Box::new(AllQuery)).as_ref(), &DocSetCollector)?.into_iter().collect();
+            addrs.sort();
+            posts += addrs.len() as u64;
+            tiers.push((tier, searcher, addrs));
+        }
+        Ok((SnapshotHeader { journal_id: journal.id().to_string(), offset, posts }, tiers))
+    }
+}
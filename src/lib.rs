@@ -0,0 +1,93 @@
+//! The search engine behind the `tantivy-demo` server, usable in-process.
+//!
+//! [`SearchService`] owns the index, its writer and the hot-swapped searcher together with the
+//! archive tier, retention, dead-letter queue, shadow index and replication journal built
+//! around them. The actix server in `main.rs` is a thin HTTP layer over it:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use tantivy_demo::{SearchRequest, SearchService, ServiceConfig};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let service = Arc::new(SearchService::open(ServiceConfig::default())?);
+//! service.spawn_background_tasks();
+//! let req = SearchRequest { q: "rust".into(), limit: 10, ..SearchRequest::default() };
+//! let hits = service.search(&req)?.hits;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod admission;
+pub mod analyzers;
+pub mod aggs;
+pub mod archive;
+pub mod auth;
+pub mod authors;
+pub mod batch;
+pub mod breaker;
+pub mod bundle;
+pub mod client;
+pub mod cluster;
+pub mod collector;
+pub mod comments;
+pub mod compaction;
+pub mod conditional;
+pub mod dedupe;
+pub mod degrade;
+pub mod dlq;
+pub mod erase;
+pub mod envelope;
+pub mod error;
+pub mod export;
+pub mod federation;
+pub mod filters;
+pub mod flags;
+pub mod indexes;
+pub mod journal;
+pub mod metadata;
+pub mod metering;
+pub mod migrations;
+pub mod moderation;
+pub mod nested;
+pub mod pacing;
+pub mod paging;
+pub mod patch;
+#[cfg(feature = "parquet")]
+pub mod parquet_export;
+pub mod protected;
+pub mod query;
+pub mod raft;
+pub mod redact;
+pub mod reindex;
+pub mod replica;
+pub mod retention;
+pub mod schema;
+pub mod schema_info;
+pub mod seed;
+pub mod service;
+pub mod session;
+pub mod settings;
+pub mod shadow;
+pub mod snippets;
+pub mod sort;
+pub mod stats;
+pub mod storage;
+pub mod tags;
+pub mod term_stats;
+pub mod test_utils;
+pub mod trace;
+pub mod usage;
+
+pub use authors::Author;
+pub use batch::BatchOp;
+pub use comments::Comment;
+pub use error::{ServiceError, ServiceResult};
+pub use query::MinimumShouldMatch;
+pub use schema::BlogPost;
+pub use service::{AnalyzedToken, IndexDiff, PlannedTerm, QueryExpansion, QueryPlan, RetryReport, SearchHit, SearchRequest, SearchResults, SearchService, ServiceConfig, Tier, TrackTotalHits};
+
+pub(crate) fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
@@ -1,275 +1,3411 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-
-use actix_web::{get, post, delete, web, App, HttpResponse, HttpServer, Responder};
-use arc_swap::ArcSwap;
-use serde::{Deserialize, Serialize};
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::schema::{Schema, STORED, STRING, TEXT, OwnedValue, TextOptions, TextFieldIndexing, IndexRecordOption};
-use tantivy::tokenizer::{TextAnalyzer, LowerCaser, WhitespaceTokenizer, NgramTokenizer};
-use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term};
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlogPost {
-    pub id: String,
-    pub title: String,
-    pub body: String,
-    pub tags: Vec<String>,
-    pub create_at: Option<i64>,
-    pub status: String,
-    pub features: serde_json::Value,
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::JsonPayloadError;
+use actix_web::http::{header, Method};
+use actix_web::{get, post, put, patch, delete, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder};
+use clap::Parser;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tantivy::schema::Value;
+use tantivy::{DocAddress, Searcher, TantivyDocument};
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+
+use tantivy_demo::admission::{AdmissionConfig, OverBudget};
+use tantivy_demo::analyzers::AnalyzerSpec;
+use tantivy_demo::aggs::CompositeRequest;
+use tantivy_demo::auth::{AdminKey, JwtVerifier, ADMIN_KEY_HEADER};
+use tantivy_demo::breaker::BreakerConfig;
+use tantivy_demo::bundle::{ConfigBundle, CONFIG_NAMESPACES};
+use tantivy_demo::batch::OpType;
+use tantivy_demo::dlq::RawPayload;
+use tantivy_demo::degrade::{DegradeConfig, DegradeMode, DEGRADED_HEADER};
+use tantivy_demo::envelope::{accepts_v2, v2_hit, V2_MEDIA_TYPE, V2_TOTAL_HITS};
+use tantivy_demo::erase::ErasureRequest;
+use tantivy_demo::metering::MeteringConfig;
+use tantivy_demo::moderation::{self, ModerationConfig};
+use tantivy_demo::patch::PostPatch;
+use tantivy_demo::flags::{parse_flag, FeatureFlags};
+use tantivy_demo::indexes::{load_schema_file, IndexSpec, ManagedIndex, SchemaFile};
+use tantivy_demo::federation::{load_remotes, RemoteAuthorization, RemoteReport, RemoteSearch, POSTS_INDEX};
+use tantivy_demo::export::{column_value, csv_escape, export_columns, flat_cell, ExportDocs, EXPORT_CHUNK};
+use tantivy_demo::journal::SnapshotPost;
+#[cfg(feature = "parquet")]
+use tantivy_demo::parquet_export;
+use tantivy_demo::nested::NestedQuery;
+use tantivy_demo::pacing::CommitPacing;
+use tantivy_demo::paging::SearchAfter;
+use tantivy_demo::query::QueryLimits;
+use tantivy_demo::protected::load_protected_terms;
+use tantivy_demo::redact::RedactionConfig;
+use tantivy_demo::reindex::ReindexRequest;
+use tantivy_demo::metadata::MetadataCommand;
+use tantivy_demo::raft::{AppendRequest, RaftConfig, RaftNode, VoteRequest, CLUSTER_SECRET_HEADER};
+use tantivy_demo::replica::{FollowerConfig, REPLICATION_KEY_HEADER};
+use tantivy_demo::retention::load_rules;
+use tantivy_demo::schema::{content_hash, doc_to_json, flatten_features, from_document, CONTENT_HASH_FIELD};
+use tantivy_demo::seed::SeedStatus;
+use tantivy_demo::tags::TagMatcher;
+use tantivy_demo::conditional::etag_listed;
+use tantivy_demo::session::{SessionPinning, GENERATION_HEADER, SESSION_COOKIE, SESSION_HEADER};
+use tantivy_demo::settings::{self, ReloadStatus};
+use tantivy_demo::snippets::{SnippetBoundary, SnippetOptions};
+use tantivy_demo::sort::SortBy;
+use tantivy_demo::trace::{TraceContext, TRACEPARENT};
+use tantivy_demo::usage::load_tenants;
+use tantivy_demo::{Author, BatchOp, BlogPost, Comment, MinimumShouldMatch, SearchHit, SearchRequest, SearchResults, SearchService, ServiceConfig, ServiceError, Tier, TrackTotalHits};
+
+/// Largest JSON body accepted, actix's default.
+const JSON_LIMIT_BYTES: usize = 2 * 1024 * 1024;
+/// Upper bound on documents pushed per commit to one `/latest/stream` subscriber.
+const LATEST_STREAM_MAX_EVENTS: usize = 100;
+// How long `?refresh=wait_for` waits for the commit loop before refreshing itself
+const REFRESH_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "tantivy-demo", about = "Run the search service", args_override_self = true)]
+pub struct ServerOpts {
+    /// TOML file of these options by long name (`max_query_clauses = 2048`), applied before the
+    /// command line; watched for changes, see /admin/settings
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Port to listen on (127.0.0.1)
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// Actix worker threads (defaults to the number of cores)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub workers: Option<usize>,
+
+    /// Simultaneous /search requests; further ones wait (defaults to 2x cores)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_concurrent_searches: Option<usize>,
+
+    /// Estimated cost (postings read, see /debug/query) above which /search, /aggs/composite
+    /// and /significant_terms queries count as expensive; enables admission control
+    #[arg(long)]
+    pub query_cost_budget: Option<u64>,
+
+    /// Costed queries in flight from which expensive ones are rejected or queued (defaults to
+    /// --max-concurrent-searches)
+    #[arg(long)]
+    pub admission_busy_at: Option<usize>,
+
+    /// What happens to expensive queries while busy: reject (503) or queue (one at a time)
+    #[arg(long, default_value = "reject")]
+    pub over_budget: String,
+
+    /// Milliseconds a queued expensive query waits for its turn before a 503
+    #[arg(long, default_value_t = 5000)]
+    pub admission_queue_ms: u64,
+
+    /// Searches slower than this many milliseconds spend the error budget; enables automatic
+    /// degradation mode (POST /admin/degrade switches it by hand regardless)
+    #[arg(long)]
+    pub degrade_slow_ms: Option<u64>,
+
+    /// Share of searches per window that may be slow before the service degrades
+    #[arg(long, default_value_t = 0.05)]
+    pub degrade_error_budget: f64,
+
+    /// Seconds per window of searches judged against the error budget
+    #[arg(long, default_value_t = 60)]
+    pub degrade_window_secs: u64,
+
+    /// Simultaneous /update, /delete and /batch requests; further ones wait.
+    /// /index is already serialized through the micro-batch queue.
+    #[arg(long, default_value_t = 4, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_concurrent_writes: usize,
+
+    /// Commit and reload before every write responds instead of in the background, so
+    /// acknowledged writes are searchable; slower, meant for test suites
+    #[arg(long)]
+    pub sync_commits: bool,
+
+    /// Commit pending writes on this fixed interval instead of adapting to the write rate
+    #[arg(long)]
+    pub commit_interval_ms: Option<u64>,
+
+    /// Commit interval while writes trickle in
+    #[arg(long, default_value_t = 250)]
+    pub commit_min_ms: u64,
+
+    /// Commit interval under heavy ingest, and while idle
+    #[arg(long, default_value_t = 10000)]
+    pub commit_max_ms: u64,
+
+    /// Post writes per second at which the commit interval reaches --commit-max-ms
+    #[arg(long, default_value_t = 1000.0)]
+    pub commit_heavy_writes_per_sec: f64,
+
+    /// Target for a post write to become searchable after it is acknowledged; `/stats`
+    /// reports the share of writes within it
+    #[arg(long, default_value_t = 5000)]
+    pub nrt_slo_ms: u64,
+
+    /// Directory of NDJSON/JSON post files loaded on startup when the index is empty;
+    /// `/readyz` answers 503 with the progress until it is done
+    #[arg(long)]
+    pub seed_dir: Option<PathBuf>,
+
+    /// JSON file with retention rules; the retention job only runs when this is set
+    #[arg(long)]
+    pub retention_rules: Option<PathBuf>,
+
+    /// Seconds between scheduled retention runs
+    #[arg(long, default_value_t = 3600)]
+    pub retention_interval_secs: u64,
+
+    /// Only report what scheduled retention runs would remove
+    #[arg(long)]
+    pub retention_dry_run: bool,
+
+    /// Directory of the archive (cold tier) index; point it at slower storage if available
+    #[arg(long, default_value = ".tantivy_archive")]
+    pub archive_path: PathBuf,
+
+    /// Directory of the comments index
+    #[arg(long, default_value = ".tantivy_comments")]
+    pub comments_path: PathBuf,
+
+    /// Directory of the authors index used by `enrich=authors`
+    #[arg(long, default_value = ".tantivy_authors")]
+    pub authors_path: PathBuf,
+
+    /// JSON file the analyzers registered with `POST /analyzers` are kept in
+    #[arg(long, default_value = ".tantivy_analyzers.json")]
+    pub analyzers_path: PathBuf,
+
+    /// Directory of the indexes created with `PUT /indexes/{name}`, one directory each
+    #[arg(long, default_value = ".tantivy_indexes")]
+    pub indexes_path: PathBuf,
+
+    /// TOML or JSON file declaring managed indexes (fields, types, stored/fast flags, analyzer
+    /// per field) and aliases, created on startup when missing
+    #[arg(long)]
+    pub schema_file: Option<PathBuf>,
+
+    /// Directory of the saved-object store, one JSON file per namespace
+    #[arg(long, default_value = ".tantivy_objects")]
+    pub storage_path: PathBuf,
+
+    /// Directory of index snapshots /admin/diff may compare by name (copied backups, rebuilds)
+    #[arg(long, default_value = ".tantivy_snapshots")]
+    pub snapshots_path: PathBuf,
+
+    /// JSON manifest of the format versions the analyzers, dead-letter, raft and replica files
+    /// are in; older ones are migrated on startup
+    #[arg(long, default_value = ".tantivy_formats.json")]
+    pub formats_path: PathBuf,
+
+    /// Index directory (e.g. a new schema build) that sampled searches are shadowed against
+    #[arg(long)]
+    pub shadow_index: Option<PathBuf>,
+
+    /// Percentage of /search requests also run against --shadow-index
+    #[arg(long, default_value_t = 10.0)]
+    pub shadow_sample_pct: f64,
+
+    /// Queries with more term/phrase/range clauses than this are rejected with 400
+    #[arg(long, default_value_t = 1024)]
+    pub max_query_clauses: usize,
+
+    /// Queries that analyze into more index terms than this (e.g. very long n-gram phrases) are rejected with 400
+    #[arg(long, default_value_t = 4096)]
+    pub max_query_terms: usize,
+
+    /// Seconds a session opting in with `X-Search-Session` or a `search_session` cookie keeps
+    /// searching the snapshot it first saw; 0 turns pinning off
+    #[arg(long, default_value_t = 300)]
+    pub session_pin_secs: u64,
+
+    /// Sessions pinned at once at most; further ones search the current snapshot
+    #[arg(long, default_value_t = 10_000)]
+    pub max_pinned_sessions: usize,
+
+    /// Upper bound in milliseconds for a client's `X-Timeout-Ms` search deadline
+    #[arg(long, default_value_t = 30_000)]
+    pub max_search_timeout_ms: u64,
+
+    /// Create a new index with segments sorted newest-first by create_at (speeds up /latest);
+    /// has no effect on an existing index
+    #[arg(long)]
+    pub index_sort_create_at: bool,
+
+    /// Create a new index whose title and body fold accents and full-/half-width forms
+    /// (`zh_ngram_folded`), so `café` finds `cafe`; has no effect on an existing index
+    #[arg(long)]
+    pub fold_text: bool,
+
+    /// Built-in analyzer for `body` in a new index, e.g. `code` for programming content
+    /// (camelCase/snake_case identifiers split, operators and generics kept); no effect on an
+    /// existing index
+    #[arg(long)]
+    pub body_analyzer: Option<String>,
+
+    /// Built-in analyzer for the string values of `features`, nested snippets included, in a
+    /// new index (default: `default`)
+    #[arg(long)]
+    pub features_analyzer: Option<String>,
+
+    /// `features` key whose objects are also indexed as nested documents, e.g. `snippet`
+    /// (repeatable); the index must be new or built with nested support, and unsorted
+    #[arg(long = "nested-path")]
+    pub nested_paths: Vec<String>,
+
+    /// Redact `email` and/or `phone` numbers from post bodies before indexing (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pub redact: Vec<String>,
+
+    /// Extra regular expression to redact from post bodies (repeatable)
+    #[arg(long = "redact-pattern")]
+    pub redact_patterns: Vec<String>,
+
+    /// Keep unredacted bodies in the stored-only `body_original` field
+    #[arg(long)]
+    pub redact_keep_original: bool,
+
+    /// JSON array of terms such as `Vec<T>` also indexed verbatim in `keywords`, where queries
+    /// for them match exactly instead of through the text analyzers
+    #[arg(long)]
+    pub protected_terms: Option<PathBuf>,
+
+    /// Dotted `features` key holding a file path or URL, e.g. `source.url` (repeatable); also
+    /// indexed in `paths`, where `paths.source.url:"/docs/guide/"` matches everything under it
+    #[arg(long = "path-feature")]
+    pub path_features: Vec<String>,
+
+    /// HS256 secret for `Authorization: Bearer` JWTs; enables per-document ACLs on /search
+    #[arg(long)]
+    pub jwt_secret: Option<String>,
+
+    /// JWT claim listing the caller's groups
+    #[arg(long, default_value = "groups")]
+    pub jwt_groups_claim: String,
+
+    /// Key callers of /admin/* send as `X-Admin-Key`
+    #[arg(long)]
+    pub admin_key: Option<String>,
+
+    /// JWT group whose members may call /admin/*; needs --jwt-secret
+    #[arg(long)]
+    pub jwt_admin_group: Option<String>,
+
+    /// Dotted path under `features` holding user identifiers, e.g. `reviewer.email`, matched
+    /// by /admin/erase besides `author_id` (repeatable)
+    #[arg(long = "erase-path")]
+    pub erasure_paths: Vec<String>,
+
+    /// JSON file with moderation rules (regexes that reject, flag or tag posts before indexing)
+    #[arg(long)]
+    pub moderation_rules: Option<PathBuf>,
+
+    /// URL every post is POSTed to before indexing; answers `{"action": "allow|reject|flag|tag"}`
+    #[arg(long)]
+    pub moderation_webhook: Option<String>,
+
+    /// Milliseconds to wait for the moderation webhook; posts are refused with 503 past it
+    #[arg(long, default_value_t = 2000)]
+    pub moderation_timeout_ms: u64,
+
+    /// Failed calls in a row after which the moderation webhook, a remote cluster or the
+    /// metering endpoint is given up on for --breaker-cooldown-ms
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub breaker_failures: u32,
+
+    /// Milliseconds an open circuit breaker turns calls away before letting a probe through
+    #[arg(long, default_value_t = 30000)]
+    pub breaker_cooldown_ms: u64,
+
+    /// JSON file with tenants (name, API keys, optional max_docs); requests then need an
+    /// `X-Api-Key` and are accounted per tenant
+    #[arg(long)]
+    pub tenants: Option<PathBuf>,
+
+    /// JSON file with remote clusters (name, endpoint, optional api_key / bearer_token) that
+    /// `/search` can federate with through `indexes=<name>:posts`
+    #[arg(long)]
+    pub remote_clusters: Option<PathBuf>,
+
+    /// Milliseconds a remote leg of a search may take, unless the search's deadline is sooner
+    #[arg(long, default_value_t = 10000)]
+    pub remote_timeout_ms: u64,
+
+    /// NDJSON file per-tenant usage records are appended to (needs --tenants)
+    #[arg(long)]
+    pub metering_file: Option<PathBuf>,
+
+    /// URL per-tenant usage records are POSTed to as NDJSON (needs --tenants)
+    #[arg(long)]
+    pub metering_url: Option<String>,
+
+    /// Seconds between usage record emissions
+    #[arg(long, default_value_t = 300)]
+    pub metering_interval_secs: u64,
+
+    /// Journal accepted post writes so followers can replicate them from /replication/*
+    #[arg(long)]
+    pub journal: bool,
+
+    /// Journal entries kept in memory; followers further behind reload a snapshot
+    #[arg(long, default_value_t = 100_000)]
+    pub journal_max_entries: usize,
+
+    /// Key followers send as `X-Replication-Key` to read /replication/journal and
+    /// /replication/snapshot; give the leader and its followers the same one. Without it those
+    /// routes want admin credentials
+    #[arg(long)]
+    pub replication_key: Option<String>,
+
+    /// Leader URL to follow, e.g. `http://10.0.0.1:8080`; post writes are then refused and
+    /// come from the leader's journal instead
+    #[arg(long)]
+    pub follow: Option<String>,
+
+    /// File keeping the follower's applied journal position across restarts
+    #[arg(long, default_value = ".tantivy_replica.json")]
+    pub replica_state: PathBuf,
+
+    /// Milliseconds a follower's journal request waits on the leader for new entries
+    #[arg(long, default_value_t = 10_000)]
+    pub replica_poll_ms: u64,
+
+    /// Journal entries a follower may be behind before /replication/status answers 503
+    #[arg(long, default_value_t = 1000)]
+    pub replica_max_lag_entries: u64,
+
+    /// Seconds a follower may go without catching up with, or reaching, its leader before
+    /// /replication/status answers 503
+    #[arg(long, default_value_t = 30)]
+    pub replica_max_lag_secs: u64,
+
+    /// This node's id in the cluster metadata raft group; enables /cluster/*
+    #[arg(long)]
+    pub cluster_node_id: Option<String>,
+
+    /// Founding member as `id=url`, this node included (repeatable, the same list on every
+    /// founding node); leave out to join an existing cluster through its `add_member`
+    #[arg(long = "cluster-member")]
+    pub cluster_members: Vec<String>,
+
+    /// Secret every cluster member is started with; raft calls carry it as `X-Cluster-Secret`
+    /// and /cluster/raft/* and `POST /cluster/metadata` refuse requests without it. Required
+    /// with --cluster-node-id
+    #[arg(long)]
+    pub cluster_secret: Option<String>,
+
+    /// File keeping this node's raft term, vote and metadata log
+    #[arg(long, default_value = ".tantivy_raft.json")]
+    pub cluster_state: PathBuf,
+
+    /// Milliseconds without a metadata leader before a node calls an election (randomized up
+    /// to twice this); leaders send heartbeats five times as often
+    #[arg(long, default_value_t = 1000)]
+    pub cluster_election_ms: u64,
+
+    /// Switch an experimental feature on or off, as `name=on` or `name=off` (repeatable);
+    /// GET /admin/flags lists them
+    #[arg(long = "feature-flag")]
+    pub feature_flags: Vec<String>,
+
+    /// Reindex posts indexes created by an older version (e.g. with `create_at` neither
+    /// indexed nor fast) on startup, instead of only warning about them
+    #[arg(long)]
+    pub upgrade_schema: bool,
+}
+
+
+pub struct AppState {
+    pub service: Arc<SearchService>,
+    pub search_limit: Semaphore, // caps concurrent searches
+    pub write_limit: Semaphore,  // caps concurrent writes
+    pub max_search_timeout_ms: AtomicU64,
+    pub config_file: Option<Mutex<ConfigFile>>,
+    pub jwt: Option<JwtVerifier>,
+    pub admin_key: Option<AdminKey>,
+    /// JWT group allowed on /admin/*
+    pub admin_group: Option<String>,
+    /// Key followers read /replication/journal and /replication/snapshot with
+    pub replication_key: Option<AdminKey>,
+    pub seed: Option<Arc<SeedStatus>>,
+}
+
+/// The `--config` file, as last applied.
+pub struct ConfigFile {
+    /// The command line, parsed again over each version of the file
+    cli_args: Vec<String>,
+    /// As read at startup; restart-only options are compared against it
+    startup: toml::Table,
+    current: toml::Table,
+    status: ReloadStatus,
+}
+
+impl AppState {
+    async fn acquire_search(&self) -> SemaphorePermit<'_> {
+        self.search_limit.acquire().await.expect("search semaphore is never closed")
+    }
+
+    async fn acquire_write(&self) -> SemaphorePermit<'_> {
+        self.write_limit.acquire().await.expect("write semaphore is never closed")
+    }
+}
+
+fn error_response(e: ServiceError) -> HttpResponse {
+    match e {
+        ServiceError::Invalid(msg) => HttpResponse::BadRequest().body(msg),
+        ServiceError::NotFound(msg) => HttpResponse::NotFound().body(msg),
+        ServiceError::Conflict(msg) => HttpResponse::Conflict().body(msg),
+        ServiceError::QuotaExceeded(msg) => HttpResponse::TooManyRequests().body(msg),
+        ServiceError::Unavailable(msg) => HttpResponse::ServiceUnavailable().body(msg),
+        ServiceError::Internal(msg) => HttpResponse::InternalServerError().body(msg),
+    }
+}
+
+/// The caller's tenant when tenants are configured, from its `X-Api-Key`; a missing or unknown
+/// key is a 401.
+fn caller_tenant(req: &HttpRequest, state: &AppState) -> Result<Option<String>, HttpResponse> {
+    let usage = state.service.usage();
+    if !usage.enabled() {
+        return Ok(None);
+    }
+    let key = req.headers().get("X-Api-Key").and_then(|v| v.to_str().ok());
+    match key.and_then(|k| usage.tenant_for_key(k.trim())) {
+        Some(tenant) => Ok(Some(tenant.to_string())),
+        None => Err(HttpResponse::Unauthorized().body("a valid X-Api-Key is required")),
+    }
+}
+
+/// Stamps `post` with the caller's tenant, replacing whatever the client sent.
+fn owned_by(mut post: BlogPost, tenant: &Option<String>) -> BlogPost {
+    if tenant.is_some() {
+        post.tenant = tenant.clone();
+    }
+    post
+}
+
+/// `If-None-Match` on a write: a 412 when it lists the `_content_hash` of the committed post
+/// with the same id (`*`: any committed version), so pipelines can skip unchanged posts. Posts
+/// of other tenants, or hidden from the caller's groups, count as absent, so the answer tells
+/// nothing about them.
+fn if_none_match(req: &HttpRequest, state: &AppState, post: &BlogPost, tenant: &Option<String>) -> Result<(), HttpResponse> {
+    let Some(value) = req.headers().get("If-None-Match") else { return Ok(()) };
+    let Ok(value) = value.to_str() else { return Err(HttpResponse::BadRequest().body("If-None-Match must be ASCII")) };
+    let groups = caller_groups(req, state)?;
+    let stored = match state.service.stored_content_hash(&post.id, tenant, groups.as_deref()) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(error_response(e)),
+    };
+    match etag_listed(value, &stored) {
+        true => Err(HttpResponse::PreconditionFailed()
+            .insert_header(("ETag", format!("\"{}\"", stored)))
+            .json(serde_json::json!({ "id": post.id, "status": "unchanged", "content_hash": stored }))),
+        false => Ok(()),
+    }
+}
+
+/// Headers that change what a read answers, besides its URI.
+const READ_VARY: &str = "Accept, Authorization, X-Api-Key, X-Search-Session";
+
+/// What a conditional read is keyed by: its URI and the headers (and session cookie) that
+/// change the answer.
+fn read_key(req: &HttpRequest) -> String {
+    let mut key = req.uri().to_string();
+    for name in READ_VARY.split(", ") {
+        if let Some(value) = req.headers().get(name).and_then(|v| v.to_str().ok()) {
+            key.push_str(&format!("\n{}: {}", name, value));
+        }
+    }
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        key.push_str(&format!("\n{}={}", SESSION_COOKIE, cookie.value()));
+    }
+    key
+}
+
+/// `resp` as a cacheable read tagged `etag`: caches may keep it but revalidate it on every
+/// use, and only the caller's own cache when it came with credentials.
+fn cacheable(mut resp: HttpResponse, credentials: bool, etag: &str) -> HttpResponse {
+    let cache_control = if credentials { "private, no-cache" } else { "no-cache" };
+    if let Ok(value) = header::HeaderValue::from_str(&format!("\"{}\"", etag)) {
+        resp.headers_mut().insert(header::ETAG, value);
+    }
+    resp.headers_mut().insert(header::CACHE_CONTROL, header::HeaderValue::from_static(cache_control));
+    resp.headers_mut().insert(header::VARY, header::HeaderValue::from_static(READ_VARY));
+    resp
+}
+
+/// Whether the caller sent credentials an answer may depend on.
+fn has_credentials(req: &HttpRequest) -> bool {
+    req.headers().contains_key(header::AUTHORIZATION) || req.headers().contains_key("X-Api-Key")
+}
+
+/// 304 when the read's `If-None-Match` lists `etag`.
+fn not_modified(req: &HttpRequest, etag: &str) -> Option<HttpResponse> {
+    let value = req.headers().get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok())?;
+    etag_listed(value, etag).then(|| cacheable(HttpResponse::NotModified().finish(), has_credentials(req), etag))
+}
+
+/// Acknowledges a write with the post's content hash as `ETag`.
+fn written(post_hash: String, body: &str) -> HttpResponse {
+    HttpResponse::Ok().insert_header(("ETag", format!("\"{}\"", post_hash))).json(body)
+}
+
+/// `?refresh=` on post writes: `true` makes the write searchable before answering, `wait_for`
+/// waits for the commit loop to, and `false` (the default) answers right away.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Refresh {
+    True,
+    #[default]
+    False,
+    WaitFor,
+}
+
+#[derive(Deserialize)]
+struct RefreshParams {
+    #[serde(default)]
+    refresh: Refresh,
+}
+
+/// Waits as `refresh` asks for the write stamped `opstamp` to become searchable.
+async fn refreshed(state: &AppState, refresh: Refresh, opstamp: u64) -> Result<(), HttpResponse> {
+    let result = match refresh {
+        Refresh::False => return Ok(()),
+        _ if state.service.is_searchable(opstamp) => return Ok(()),
+        Refresh::True => state.service.refresh(),
+        Refresh::WaitFor => state.service.wait_for_refresh(opstamp, REFRESH_WAIT).await,
+    };
+    result.map_err(error_response)
+}
+
+#[derive(Deserialize)]
+pub struct IndexParams {
+    #[serde(default)]
+    op_type: OpType,
+    #[serde(default)]
+    refresh: Refresh,
+}
+
+#[post("/index")]
+async fn add_document(req: HttpRequest, params: web::Query<IndexParams>, data: web::Json<BlogPost>, state: web::Data<AppState>) -> impl Responder {
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = if_none_match(&req, &state, &data, &tenant) {
+        return resp;
+    }
+    let hash = content_hash(&data);
+    match state.service.index_document(owned_by(data.into_inner(), &tenant), params.op_type).await {
+        Ok(opstamp) => match refreshed(&state, params.refresh, opstamp).await {
+            Ok(()) => written(hash, "queued"),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    search_after: Option<String>,
+    include_archive: Option<bool>,
+    approximate: Option<f32>,
+    track_total_hits: Option<String>,
+    cluster: Option<usize>,
+    expand: Option<bool>,
+    minimum_should_match: Option<String>,
+    shingle_boost: Option<f32>,
+    tags_all: Option<String>,
+    tags_any: Option<String>,
+    has_child: Option<String>,
+    nested: Option<String>,
+    enrich: Option<String>,
+    flatten_features: Option<bool>,
+    matches: Option<bool>,
+    snippets: Option<bool>,
+    snippet_chars: Option<usize>,
+    snippet_fragments: Option<usize>,
+    snippet_separator: Option<String>,
+    snippet_boundary: Option<SnippetBoundary>,
+    snippet_pre_tag: Option<String>,
+    snippet_post_tag: Option<String>,
+    facets: Option<String>,
+    facet_size: Option<usize>,
+    sort: Option<String>,
+    dedupe_by: Option<String>,
+    profile: Option<bool>,
+    indexes: Option<String>,
+    scores: Option<bool>,
+}
+
+impl SearchQuery {
+    /// What a remote leg is sent: the parameters that shape its own search.
+    fn remote_params(&self) -> Vec<(String, String)> {
+        let optional = [
+            ("limit", self.limit.map(|v| v.to_string())),
+            ("include_archive", self.include_archive.map(|v| v.to_string())),
+            ("approximate", self.approximate.map(|v| v.to_string())),
+            ("track_total_hits", self.track_total_hits.clone()),
+            ("minimum_should_match", self.minimum_should_match.clone()),
+            ("shingle_boost", self.shingle_boost.map(|v| v.to_string())),
+            ("tags_all", self.tags_all.clone()),
+            ("tags_any", self.tags_any.clone()),
+            ("flatten_features", self.flatten_features.map(|v| v.to_string())),
+            ("matches", self.matches.map(|v| v.to_string())),
+            ("snippets", self.snippets.map(|v| v.to_string())),
+            ("snippet_chars", self.snippet_chars.map(|v| v.to_string())),
+            ("snippet_fragments", self.snippet_fragments.map(|v| v.to_string())),
+            ("snippet_separator", self.snippet_separator.clone()),
+            ("snippet_boundary", self.snippet_boundary.map(|v| format!("{:?}", v).to_lowercase())),
+            ("snippet_pre_tag", self.snippet_pre_tag.clone()),
+            ("snippet_post_tag", self.snippet_post_tag.clone()),
+            ("has_child", self.has_child.clone()),
+            ("nested", self.nested.clone()),
+        ];
+        let mut params = vec![("q".to_string(), self.q.clone())];
+        params.extend(optional.into_iter().filter_map(|(k, v)| v.map(|v| (k.to_string(), v))));
+        params
+    }
+}
+
+/// A comma-separated list such as `tags_all=rust,search`.
+fn split_list(value: Option<&str>) -> Vec<String> {
+    value.unwrap_or("").split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect()
+}
+
+/// `shingle_boost` must be a positive number.
+fn check_shingle_boost(boost: Option<f32>) -> Result<(), HttpResponse> {
+    match boost {
+        Some(b) if !(b.is_finite() && b > 0.0) => Err(HttpResponse::BadRequest().body("shingle_boost must be a positive number")),
+        _ => Ok(()),
+    }
+}
+
+/// `indexes=posts,eu:posts`: whether the local posts are searched, and the remote clusters to
+/// search too. Only the local posts when absent.
+fn parse_indexes(value: Option<&str>, remotes: &[String]) -> Result<(bool, Vec<String>), HttpResponse> {
+    let Some(value) = value else { return Ok((true, Vec::new())) };
+    let (mut local, mut clusters) = (false, Vec::new());
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let (cluster, index) = match name.split_once(':') {
+            Some((cluster, index)) => (Some(cluster), index),
+            None => (None, name),
+        };
+        if index != POSTS_INDEX {
+            return Err(HttpResponse::BadRequest().body(format!("only the {} index can be searched, got {}", POSTS_INDEX, name)));
+        }
+        match cluster {
+            None => local = true,
+            Some(c) if remotes.iter().any(|r| r == c) => {
+                if !clusters.iter().any(|known| known == c) {
+                    clusters.push(c.to_string());
+                }
+            }
+            Some(c) => return Err(HttpResponse::BadRequest().body(format!("unknown remote cluster: {}", c))),
+        }
+    }
+    if !local && clusters.is_empty() {
+        return Err(HttpResponse::BadRequest().body("indexes must name at least one index"));
+    }
+    Ok((local, clusters))
+}
+
+/// `track_total_hits=true` counts exactly, `=N` counts up to N and `=false` (or absent) skips
+/// counting.
+fn parse_track_total_hits(value: Option<&str>) -> Result<Option<TrackTotalHits>, HttpResponse> {
+    match value {
+        None | Some("false") => Ok(None),
+        Some("true") => Ok(Some(TrackTotalHits::Exact)),
+        Some(n) => n.parse().map(|n| Some(TrackTotalHits::UpTo(n))).map_err(|_| {
+            HttpResponse::BadRequest().body("track_total_hits must be true, false or a number of hits")
+        }),
+    }
+}
+
+/// `nested=snippet:features.snippet.lang:rust AND features.snippet.text:unsafe`
+fn parse_nested(value: Option<&str>) -> Result<Option<NestedQuery>, HttpResponse> {
+    match value.map(|n| n.split_once(':')) {
+        None => Ok(None),
+        Some(Some((path, q))) if !path.trim().is_empty() => Ok(Some(NestedQuery { path: path.trim().to_string(), q: q.to_string() })),
+        Some(_) => Err(HttpResponse::BadRequest().body("nested must look like <path>:<query>")),
+    }
+}
+
+/// Deadline from the caller's `X-Timeout-Ms` header, capped at the server's maximum.
+fn request_deadline(req: &HttpRequest, started: Instant, cap: Duration) -> Result<Option<Instant>, HttpResponse> {
+    let Some(value) = req.headers().get("X-Timeout-Ms") else { return Ok(None) };
+    match value.to_str().ok().and_then(|v| v.trim().parse::<u64>().ok()) {
+        Some(ms) => Ok(Some(started + Duration::from_millis(ms).min(cap))),
+        None => Err(HttpResponse::BadRequest().body("X-Timeout-Ms must be a whole number of milliseconds")),
+    }
+}
+
+/// Span this request runs under: a child of the caller's `traceparent`, or a new trace when
+/// it sent none (or a malformed one).
+fn request_span(req: &HttpRequest) -> TraceContext {
+    let parent = req.headers().get(TRACEPARENT).and_then(|v| v.to_str().ok()).and_then(TraceContext::parse);
+    parent.map_or_else(TraceContext::root, |p| p.child())
+}
+
+/// `resp` with the span it was produced under, for callers correlating their traces.
+fn traced(mut resp: HttpResponse, span: &TraceContext) -> HttpResponse {
+    if let Ok(value) = span.to_string().parse() {
+        resp.headers_mut().insert(actix_web::http::header::HeaderName::from_static(TRACEPARENT), value);
+    }
+    resp
+}
+
+/// The session a caller opted into pinning with, from `X-Search-Session` or, for browsers, the
+/// `search_session` cookie.
+fn request_session(req: &HttpRequest) -> Option<String> {
+    match req.headers().get(SESSION_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(session) => Some(session.trim().to_string()),
+        None => req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()),
+    }
+}
+
+/// The caller's groups when ACLs are enabled: from a valid bearer token, or none (public posts
+/// only) without one. A bad token is a 401 rather than a silently narrower result.
+fn caller_groups(req: &HttpRequest, state: &AppState) -> Result<Option<Vec<String>>, HttpResponse> {
+    let Some(jwt) = &state.jwt else { return Ok(None) };
+    let Some(value) = req.headers().get("Authorization") else { return Ok(Some(Vec::new())) };
+    let token = value.to_str().ok().and_then(|v| v.strip_prefix("Bearer "));
+    match token.map(|t| jwt.groups(t.trim())) {
+        Some(Ok(groups)) => Ok(Some(groups)),
+        Some(Err(e)) => Err(HttpResponse::Unauthorized().body(e)),
+        None => Err(HttpResponse::Unauthorized().body("Authorization must be a Bearer token")),
+    }
+}
+
+/// Whether the caller may use /admin/*: it sends the `--admin-key` as `X-Admin-Key`, or a
+/// bearer token in the `--jwt-admin-group`. A missing or wrong credential is a 401, a valid
+/// token outside the group a 403. With neither configured the admin routes are open, unless
+/// tenants or JWTs are, since then the server isn't meant to be open to anyone.
+fn caller_admin(req: &HttpRequest, state: &AppState) -> Result<(), HttpResponse> {
+    if state.admin_key.is_none() && state.admin_group.is_none() {
+        return match state.jwt.is_some() || state.service.usage().enabled() {
+            true => Err(HttpResponse::Forbidden().body("admin endpoints need --admin-key or --jwt-admin-group")),
+            false => Ok(()),
+        };
+    }
+    if let Some(sent) = req.headers().get(ADMIN_KEY_HEADER) {
+        return match (&state.admin_key, sent.to_str()) {
+            (Some(key), Ok(sent)) if key.matches(sent.trim()) => Ok(()),
+            _ => Err(HttpResponse::Unauthorized().body("invalid X-Admin-Key")),
+        };
+    }
+    if let (Some(group), true) = (&state.admin_group, req.headers().contains_key("Authorization")) {
+        return match caller_groups(req, state)? {
+            Some(groups) if groups.contains(group) => Ok(()),
+            _ => Err(HttpResponse::Forbidden().body(format!("the token's groups don't include {}", group))),
+        };
+    }
+    Err(HttpResponse::Unauthorized().body("admin endpoints need an X-Admin-Key or an admin bearer token"))
+}
+
+/// Whether the caller may read the replication journal and snapshot, which hold every post
+/// unfiltered and unredacted: it sends the `--replication-key` as `X-Replication-Key`, or passes
+/// [`caller_admin`].
+fn caller_replica(req: &HttpRequest, state: &AppState) -> Result<(), HttpResponse> {
+    match (req.headers().get(REPLICATION_KEY_HEADER), &state.replication_key) {
+        (Some(sent), Some(key)) => match sent.to_str() {
+            Ok(sent) if key.matches(sent.trim()) => Ok(()),
+            _ => Err(HttpResponse::Unauthorized().body("invalid X-Replication-Key")),
+        },
+        (Some(_), None) => Err(HttpResponse::Unauthorized().body("this node has no --replication-key")),
+        (None, _) => caller_admin(req, state),
+    }
+}
+
+/// Every answer, errors included, carries the span it ran under (see [`traced`]).
+#[get("/search")]
+async fn search_document(req: HttpRequest, info: web::Query<SearchQuery>, state: web::Data<AppState>) -> impl Responder {
+    let span = request_span(&req);
+    let resp = search_response(req, info, state, &span).await;
+    traced(resp, &span)
+}
+
+async fn search_response(req: HttpRequest, info: web::Query<SearchQuery>, state: web::Data<AppState>, span: &TraceContext) -> HttpResponse {
+    let started = Instant::now();
+    let max_timeout = Duration::from_millis(state.max_search_timeout_ms.load(Ordering::Relaxed));
+    let deadline = match request_deadline(&req, started, max_timeout) {
+        Ok(d) => d,
+        Err(resp) => return resp,
+    };
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let v2 = accepts_v2(req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok()));
+    let search_after = match info.search_after.as_deref().map(SearchAfter::decode).transpose() {
+        Ok(s) => s,
+        Err(e) => return error_response(e),
+    };
+    let paged = info.offset.is_some() || search_after.is_some();
+    let track_total_hits = match parse_track_total_hits(info.track_total_hits.as_deref()) {
+        // v2 and paged answers count matches unless told otherwise
+        Ok(None) if (v2 || paged) && info.track_total_hits.is_none() => Some(TrackTotalHits::UpTo(V2_TOTAL_HITS)),
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let minimum_should_match = match info.minimum_should_match.as_deref().map(str::parse::<MinimumShouldMatch>).transpose() {
+        Ok(m) => m,
+        Err(e) => return error_response(e.into()),
+    };
+    let nested = match parse_nested(info.nested.as_deref()) {
+        Ok(n) => n,
+        Err(resp) => return resp,
+    };
+    let sort = match info.sort.as_deref().filter(|s| !s.trim().is_empty()).map(str::parse::<SortBy>).transpose() {
+        Ok(s) => s,
+        Err(e) => return error_response(e),
+    };
+    if let Err(resp) = check_shingle_boost(info.shingle_boost) {
+        return resp;
+    }
+    // `enrich=authors` adds each hit's author profile as `_author`
+    let mut enrich_authors = false;
+    for name in info.enrich.as_deref().unwrap_or("").split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match name {
+            "authors" => enrich_authors = true,
+            other => return HttpResponse::BadRequest().body(format!("unknown enrich value: {}", other)),
+        }
+    }
+    let (local, remotes) = match parse_indexes(info.indexes.as_deref(), &state.service.remote_clusters()) {
+        Ok(i) => i,
+        Err(resp) => return resp,
+    };
+    let facets = split_list(info.facets.as_deref());
+    // Clustering, expansion and author lookups need the stored documents of every hit, facets
+    // every match; remote hits are merged by score
+    let dedupe_by = info.dedupe_by.clone().filter(|f| !f.trim().is_empty());
+    if !remotes.is_empty() && (info.cluster.is_some() || info.expand == Some(true) || enrich_authors || !facets.is_empty() || sort.is_some() || dedupe_by.is_some()) {
+        return HttpResponse::BadRequest().body("cluster, expand, enrich, facets, sort and dedupe_by aren't supported with remote indexes");
+    }
+    let flagged = [(info.cluster.is_some(), "clustering"), (info.expand == Some(true), "expansion"), (!remotes.is_empty(), "federation")];
+    if let Some(Err(e)) = flagged.iter().filter(|(asked, _)| *asked).map(|(_, flag)| state.service.check_flag(flag)).find(Result::is_err) {
+        return error_response(e);
+    }
+    // Cursors and offsets point into the local searchers' ranking
+    if !remotes.is_empty() && paged {
+        return HttpResponse::BadRequest().body("offset and search_after aren't supported with remote indexes");
+    }
+    // With a count, facets, clusters, expansion, profile or remotes requested, hits come wrapped as
+    // {"hits": [...], "total": {"value", "relation"}, "facets": {...}, "clusters": [...], "expansion": {...}, "profile": {...}, "remotes": [...]}
+    // v2 and paged answers always are (see `envelope`), paged ones with the `next_search_after` cursor
+    let profile = info.profile.unwrap_or(false);
+    let wrapped = v2 || paged || track_total_hits.is_some() || !facets.is_empty() || info.cluster.is_some() || info.expand == Some(true) || profile || !remotes.is_empty();
+    let scores = info.scores.unwrap_or(false) || !remotes.is_empty();
+    // Degraded searches skip clustering, expansion, the shingle boost, matches, snippets and
+    // facets, keeping the response's shape
+    let degraded = state.service.degradation().is_degraded();
+    let snippets = info.snippets.unwrap_or(false).then(|| {
+        let defaults = SnippetOptions::default();
+        SnippetOptions {
+            max_chars: info.snippet_chars.unwrap_or(defaults.max_chars),
+            fragments: info.snippet_fragments.unwrap_or(defaults.fragments),
+            separator: info.snippet_separator.clone().unwrap_or(defaults.separator),
+            boundary: info.snippet_boundary.unwrap_or(defaults.boundary),
+            pre_tag: info.snippet_pre_tag.clone().unwrap_or(defaults.pre_tag),
+            post_tag: info.snippet_post_tag.clone().unwrap_or(defaults.post_tag),
+        }
+    });
+    let envelope = |results: Vec<serde_json::Value>, extras: Vec<(&str, serde_json::Value)>| {
+        if !wrapped {
+            return serde_json::Value::from(results);
+        }
+        let mut body = serde_json::json!({ "hits": results });
+        for (key, value) in extras {
+            body[key] = value;
+        }
+        body
+    };
+    let include_archive = info.include_archive.unwrap_or(false);
+    let http_req = req;
+    let req = SearchRequest {
+        q: info.q.clone(),
+        limit: info.limit.unwrap_or(10),
+        offset: info.offset.unwrap_or(0),
+        search_after,
+        include_archive,
+        deadline,
+        track_total_hits,
+        approximate: info.approximate,
+        expand: info.expand.unwrap_or(false) && !degraded,
+        minimum_should_match,
+        has_child: info.has_child.clone(),
+        nested,
+        groups,
+        tenant,
+        shingle_boost: info.shingle_boost.filter(|_| !degraded),
+        tags_all: split_list(info.tags_all.as_deref()),
+        tags_any: split_list(info.tags_any.as_deref()),
+        matches: info.matches.unwrap_or(false) && !degraded,
+        snippets: snippets.filter(|_| !degraded),
+        facets: if degraded { Vec::new() } else { facets },
+        facet_size: info.facet_size,
+        sort,
+        dedupe_by,
+        trace: Some(span.clone()),
+        snapshot: request_session(&http_req).and_then(|session| state.service.session_snapshot(&session)),
+    };
+    let generation = req.snapshot.as_ref().map(|s| s.generation);
+    // Answers of the local searchers can be revalidated; remote and degraded ones can't
+    let etag = (remotes.is_empty() && !degraded).then(|| state.service.read_etag(&read_key(&http_req), req.snapshot.as_ref()));
+    // Answers to pinned sessions say which searcher generation they came from, and v2 hits
+    // come as the v2 media type
+    let finish = |mut resp: HttpResponse| {
+        if let Some(generation) = generation {
+            if let Ok(name) = actix_web::http::header::HeaderName::from_bytes(GENERATION_HEADER.as_bytes()) {
+                resp.headers_mut().insert(name, generation.into());
+            }
+        }
+        let json = resp.headers().get(header::CONTENT_TYPE).is_some_and(|t| t == "application/json");
+        if v2 && json {
+            resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(V2_MEDIA_TYPE));
+        }
+        resp.headers_mut().insert(header::VARY, header::HeaderValue::from_static(READ_VARY));
+        if degraded {
+            state.service.degradation().count_degraded();
+            resp = flag_degraded(resp);
+        }
+        resp
+    };
+    if let Some(resp) = etag.as_deref().and_then(|etag| not_modified(&http_req, etag)) {
+        return finish(resp);
+    }
+    // Expensive local searches may be turned away or queued while the node is busy
+    let _admitted = match local {
+        true => match state.service.admit_query(|| state.service.estimate_search_cost(&req)).await {
+            Ok(admitted) => Some(admitted),
+            Err(e) => return finish(error_response(e)),
+        },
+        false => None,
+    };
+    // Waiting for a search slot counts against the deadline too
+    let _permit = match deadline {
+        Some(d) => match tokio::time::timeout_at(d.into(), state.acquire_search()).await {
+            Ok(permit) => permit,
+            Err(_) => return finish(partial_response(envelope(Vec::new(), Vec::new()))),
+        },
+        None => state.acquire_search().await,
+    };
+    // Remote legs run while the local index is searched
+    let remote_legs = (!remotes.is_empty()).then(|| {
+        let service = Arc::clone(&state.service);
+        // With ACLs on, remotes check the caller's token rather than the configured one
+        let authorization = match state.jwt {
+            Some(_) => RemoteAuthorization::Caller(http_req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).map(str::to_string)),
+            None => RemoteAuthorization::Configured,
+        };
+        let remote = RemoteSearch { params: info.remote_params(), deadline, trace: Some(span.clone()), v2, authorization };
+        tokio::spawn(async move { service.search_remotes(&remotes, &remote).await })
+    });
+    let mut found = match local {
+        true => match state.service.search(&req) {
+            Ok(r) => r,
+            Err(e) => return error_response(e),
+        },
+        false => SearchResults::default(),
+    };
+    let schema = state.service.schema();
+    let authors = match enrich_authors {
+        true => match state.service.hit_authors(&found.hits) {
+            Ok(authors) => authors,
+            Err(e) => return error_response(e),
+        },
+        false => Default::default(),
+    };
+    let f_author_id = schema.get_field("author_id").ok();
+    // Hits say which of their tags matched when the query or a tag filter looks at tags
+    let mut tag_matcher = match state.service.tag_matcher(&req) {
+        Ok(matcher) => Some(matcher).filter(|m| !m.is_empty()),
+        Err(e) => return error_response(e),
+    };
+    let mut results: Vec<serde_json::Value> = found
+        .hits
+        .iter()
+        .map(|hit| {
+            if v2 {
+                return v2_result(&schema, hit, &mut tag_matcher, enrich_authors.then_some(&authors));
+            }
+            let mut doc = doc_to_json(&schema, &hit.doc);
+            if info.flatten_features == Some(true) {
+                flatten_features(&mut doc);
+            }
+            if include_archive {
+                doc["_tier"] = serde_json::Value::from(hit.tier.as_str());
+            }
+            if enrich_authors {
+                let author_id = f_author_id.and_then(|f| hit.doc.get_first(f)).and_then(|v| v.as_str());
+                doc["_author"] = serde_json::json!(author_id.and_then(|id| authors.get(id)));
+            }
+            if scores {
+                doc["_score"] = serde_json::json!(hit.score);
+            }
+            if let Some(matcher) = tag_matcher.as_mut() {
+                doc["_matched_tags"] = serde_json::json!(matcher.matched(&hit.doc));
+            }
+            if let Some(matches) = &hit.matches {
+                doc["_matched_fields"] = serde_json::json!(matches.fields);
+                doc["_matched_terms"] = serde_json::json!(matches.terms);
+            }
+            if let Some(snippets) = &hit.snippets {
+                doc["_snippets"] = serde_json::json!(snippets);
+            }
+            if let Some(duplicates) = hit.duplicates {
+                doc["_duplicates_count"] = serde_json::json!(duplicates);
+            }
+            doc
+        })
+        .collect();
+    let mut extras = Vec::new();
+    if let Some(legs) = remote_legs {
+        let legs = match legs.await {
+            Ok(legs) => legs,
+            Err(e) => return error_response(ServiceError::Internal(e.to_string())),
+        };
+        let cap = track_total_hits.and_then(TrackTotalHits::cap);
+        for leg in &legs {
+            found.timed_out |= leg.timing.timed_out;
+            found.shards.push(leg.timing.clone());
+            if let Some(total) = leg.total {
+                found.total = Some(found.total.map_or(total, |t| t.merge(total, cap)));
+            }
+            results.extend(leg.hits.iter().cloned().map(|mut hit| {
+                hit[if v2 { "cluster" } else { "_cluster" }] = serde_json::Value::from(leg.cluster.as_str());
+                hit
+            }));
+        }
+        let score = |hit: &serde_json::Value| hit[if v2 { "score" } else { "_score" }].as_f64().unwrap_or(0.0);
+        results.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        results.truncate(req.limit);
+        extras.push(("remotes", serde_json::json!(legs.iter().map(RemoteReport::from).collect::<Vec<_>>())));
+        if !local && legs.iter().all(|leg| leg.error.is_some()) {
+            return finish(HttpResponse::BadGateway().json(envelope(results, extras)));
+        }
+    }
+    if track_total_hits.is_some() {
+        extras.push(("total", serde_json::json!(found.total)));
+    }
+    if let Some(facets) = &found.facets {
+        extras.push(("facets", serde_json::json!(facets)));
+    }
+    if paged {
+        // A short page is the last one; cursors follow the score ranking, so sorted and
+        // deduplicated pages have none
+        let last = found.hits.last().filter(|_| found.hits.len() == req.limit && req.sort.is_none() && req.dedupe_by.is_none());
+        let next = last.map(|hit| SearchAfter::after(hit, found.tier_best).encode());
+        extras.push(("next_search_after", serde_json::json!(next)));
+    }
+    if let Some(expansion) = &found.expansion {
+        extras.push(("expansion", serde_json::json!(expansion)));
+    }
+    if !found.timed_out {
+        if let Some(k) = info.cluster.filter(|_| !degraded) {
+            match state.service.cluster_hits(&found.hits, k) {
+                Ok(clusters) => extras.push(("clusters", serde_json::json!(clusters))),
+                Err(e) => return error_response(e),
+            }
+        }
+    }
+    if degraded {
+        extras.push(("degraded", serde_json::Value::Bool(true)));
+    }
+    if profile {
+        extras.push(("profile", serde_json::json!({
+            "trace_id": span.trace_id,
+            "span_id": span.span_id,
+            "took_ms": started.elapsed().as_secs_f64() * 1000.0,
+            "shards": found.shards,
+        })));
+    }
+    if local {
+        state.service.degradation().record(started.elapsed());
+    }
+    match (found.timed_out, etag) {
+        (true, _) => finish(partial_response(envelope(results, extras))),
+        (false, Some(etag)) => finish(cacheable(HttpResponse::Ok().json(envelope(results, extras)), has_credentials(&http_req), &etag)),
+        (false, None) => finish(HttpResponse::Ok().json(envelope(results, extras))),
+    }
+}
+
+/// A local hit in the v2 envelope, with its author and what it matched.
+fn v2_result(
+    schema: &tantivy::schema::Schema,
+    hit: &SearchHit,
+    tag_matcher: &mut Option<TagMatcher>,
+    authors: Option<&HashMap<String, Author>>,
+) -> serde_json::Value {
+    let mut result = v2_hit(schema, hit);
+    if let Some(authors) = authors {
+        let author_id = schema.get_field("author_id").ok().and_then(|f| hit.doc.get_first(f)).and_then(|v| v.as_str());
+        result["author"] = serde_json::json!(author_id.and_then(|id| authors.get(id)));
+    }
+    let mut matched = serde_json::Map::new();
+    if let Some(matcher) = tag_matcher.as_mut() {
+        matched.insert("tags".to_string(), serde_json::json!(matcher.matched(&hit.doc)));
+    }
+    if let Some(matches) = &hit.matches {
+        matched.insert("fields".to_string(), serde_json::json!(matches.fields));
+        matched.insert("terms".to_string(), serde_json::json!(matches.terms));
+    }
+    if !matched.is_empty() {
+        result["matched"] = serde_json::Value::Object(matched);
+    }
+    if let Some(snippets) = &hit.snippets {
+        result["highlights"] = serde_json::json!(snippets);
+    }
+    if let Some(duplicates) = hit.duplicates {
+        result["duplicates_count"] = serde_json::json!(duplicates);
+    }
+    result
+}
+
+/// Marks `resp` as answered in degradation mode.
+fn flag_degraded(mut resp: HttpResponse) -> HttpResponse {
+    if let Ok(name) = header::HeaderName::from_bytes(DEGRADED_HEADER.as_bytes()) {
+        resp.headers_mut().insert(name, header::HeaderValue::from_static("true"));
+    }
+    resp
+}
+
+/// 504 carrying whatever hits were ready when the deadline passed.
+fn partial_response(body: serde_json::Value) -> HttpResponse {
+    HttpResponse::GatewayTimeout().insert_header(("X-Partial-Results", "true")).json(body)
+}
+
+#[derive(Deserialize)]
+struct LatestQuery {
+    limit: Option<usize>,
+    tags: Option<String>,
+    optimize: Option<String>,
+    profile: Option<bool>,
+    flatten_features: Option<bool>,
+}
+
+impl LatestQuery {
+    fn tags(&self) -> Vec<String> {
+        self.tags
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+}
+
+#[get("/latest")]
+async fn latest_documents(req: HttpRequest, info: web::Query<LatestQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let early_terminate = match info.optimize.as_deref() {
+        None => false,
+        Some("early_terminate") => true,
+        Some(other) => return HttpResponse::BadRequest().body(format!("unknown optimize value: {}", other)),
+    };
+    let _permit = state.acquire_search().await;
+    match state.service.latest_profiled(&info.tags(), None, info.limit.unwrap_or(20), early_terminate, groups.as_deref()) {
+        Ok((hits, profile)) => {
+            let schema = state.service.schema();
+            let render = |doc: &TantivyDocument| {
+                let mut rendered = doc_to_json(&schema, doc);
+                if info.flatten_features == Some(true) {
+                    flatten_features(&mut rendered);
+                }
+                rendered
+            };
+            let results: Vec<serde_json::Value> = hits.iter().map(|(_, doc)| render(doc)).collect();
+            if info.profile.unwrap_or(false) {
+                return HttpResponse::Ok().json(serde_json::json!({
+                    "hits": results,
+                    "profile": { "index_sorted": state.service.sorted_by_create_at(), "scan": profile },
+                }));
+            }
+            HttpResponse::Ok().json(results)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+fn doc_id(schema: &tantivy::schema::Schema, doc: &TantivyDocument) -> String {
+    let f_id = schema.get_field("id").unwrap();
+    doc.get_first(f_id).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+/// Id and content hash of a stored post: which version of it a stream sent.
+fn doc_version(schema: &tantivy::schema::Schema, doc: &TantivyDocument) -> (String, String) {
+    let hash = schema.get_field(CONTENT_HASH_FIELD).ok().and_then(|f| doc.get_first(f)).and_then(|v| v.as_str());
+    (doc_id(schema, doc), hash.unwrap_or_default().to_string())
+}
+
+/// Server-sent events: after each commit, pushes matching documents committed since anything
+/// sent so far (`event: post`), or a keep-alive comment when nothing new matched. Documents
+/// are followed by `_indexed_at`, which the writer stamps in commit order, so a post shows up
+/// whatever its `create_at`; an update is pushed again as a new version.
+#[get("/latest/stream")]
+async fn latest_stream(req: HttpRequest, info: web::Query<LatestQuery>, state: web::Data<AppState>) -> impl Responder {
+    struct Cursor {
+        service: Arc<SearchService>,
+        commits: watch::Receiver<u64>,
+        tags: Vec<String>,
+        groups: Option<Vec<String>>,
+        high_water: i64,
+        /// Versions sent that were indexed at `high_water`, when later ones may still come
+        seen_at_high_water: std::collections::HashSet<(String, String)>,
+    }
+
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    // Start from what is already searchable; documents indexed in the newest second count as sent
+    let tags = info.tags();
+    let schema = state.service.schema();
+    let mut high_water = i64::MIN;
+    let mut seen_at_high_water = std::collections::HashSet::new();
+    let hits = state.service.indexed_since(&tags, None, LATEST_STREAM_MAX_EVENTS, groups.as_deref()).unwrap_or_default();
+    if let Some((newest, _)) = hits.first() {
+        high_water = *newest;
+        for (_, doc) in hits.iter().filter(|(ts, _)| *ts == high_water) {
+            seen_at_high_water.insert(doc_version(&schema, doc));
+        }
+    }
+    let cursor = Cursor {
+        commits: state.service.subscribe_commits(),
+        service: state.service.clone(),
+        tags,
+        groups,
+        high_water,
+        seen_at_high_water,
+    };
+
+    let stream = futures_util::stream::unfold(cursor, move |mut c| {
+        let schema = schema.clone();
+        async move {
+            c.commits.changed().await.ok()?;
+            let hits = c.service.indexed_since(&c.tags, Some(c.high_water), LATEST_STREAM_MAX_EVENTS, c.groups.as_deref()).unwrap_or_default();
+
+            let mut out = String::new();
+            // Oldest first, so events arrive in commit order
+            for (indexed_at, doc) in hits.iter().rev() {
+                if *indexed_at > c.high_water {
+                    c.high_water = *indexed_at;
+                    c.seen_at_high_water.clear();
+                }
+                if !c.seen_at_high_water.insert(doc_version(&schema, doc)) {
+                    continue;
+                }
+                out.push_str(&format!("event: post\ndata: {}\n\n", doc_to_json(&schema, doc)));
+            }
+            if out.is_empty() {
+                out.push_str(": keep-alive\n\n");
+            }
+            Some((Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(out)), c))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+#[derive(Deserialize)]
+struct ExportQuery { q: Option<String>, fields: Option<String>, format: Option<String> }
+
+/// Exports stored documents from one searcher snapshot. `q` filters (default: all documents),
+/// `fields` is a comma-separated projection (default: every stored field) and `format` is
+/// `ndjson` (default), `csv` or `parquet` (needs the `parquet` feature). CSV and Parquet
+/// flatten columns and accept JSON paths such as `features.lang`.
+#[get("/export")]
+async fn export_documents(req: HttpRequest, info: web::Query<ExportQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let schema = state.service.schema();
+    let columns = match export_columns(&schema, info.fields.as_deref()) {
+        Ok(c) => c,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let format = info.format.as_deref().unwrap_or("ndjson");
+    if !matches!(format, "ndjson" | "csv" | "parquet") {
+        return HttpResponse::BadRequest().body(format!("unknown export format: {}", format));
+    }
+    if format == "ndjson" && columns.iter().any(|c| !c.path.is_empty()) {
+        return HttpResponse::BadRequest().body("JSON paths in fields are only supported for csv and parquet");
+    }
+    let (searcher, addrs) = match state.service.export_snapshot(info.q.as_deref(), groups.as_deref()) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return error_response(e),
+    };
+
+    if format == "parquet" {
+        #[cfg(feature = "parquet")]
+        return match parquet_export::write_parquet(searcher, &addrs, &columns) {
+            Ok(bytes) => HttpResponse::Ok().content_type("application/vnd.apache.parquet").body(bytes),
+            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        };
+        #[cfg(not(feature = "parquet"))]
+        return HttpResponse::BadRequest().body("parquet export needs the `parquet` feature");
+    }
+
+    let csv = format == "csv";
+    let header = if csv {
+        let names: Vec<String> = columns.iter().map(|c| csv_escape(&c.name)).collect();
+        Some(Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(names.join(",") + "\n")))
+    } else {
+        None
+    };
+    let projected = info.fields.as_deref().is_some_and(|f| !f.trim().is_empty());
+    let chunks: Vec<Vec<_>> = addrs.chunks(EXPORT_CHUNK).map(|c| c.to_vec()).collect();
+    let mut docs = ExportDocs::new(searcher, &columns);
+    let rows = futures_util::stream::iter(chunks).map(move |chunk| {
+        let mut out = String::new();
+        for addr in chunk {
+            let doc = docs.doc(addr).map_err(actix_web::error::ErrorInternalServerError)?;
+            if csv {
+                let cells: Vec<String> =
+                    columns.iter().map(|c| csv_escape(&flat_cell(&column_value(&doc, c)))).collect();
+                out.push_str(&cells.join(","));
+            } else {
+                let mut line = doc_to_json(&schema, &doc);
+                if let (true, serde_json::Value::Object(map)) = (projected, &mut line) {
+                    map.retain(|name, _| columns.iter().any(|c| &c.name == name));
+                }
+                out.push_str(&line.to_string());
+            }
+            out.push('\n');
+        }
+        Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(out))
+    });
+    let stream = futures_util::stream::iter(header).chain(rows);
+
+    let content_type = if csv { "text/csv" } else { "application/x-ndjson" };
+    HttpResponse::Ok().content_type(content_type).streaming(stream)
+}
+
+#[derive(Deserialize)]
+struct JournalQuery {
+    from: u64,
+    limit: Option<usize>,
+    wait_ms: Option<u64>,
+}
+
+/// Committed journal entries from offset `from` as NDJSON (`{"offset", "recorded_at", "kind",
+/// ...}`), at most `limit` (default 1000). With `wait_ms`, waits up to that long (capped at
+/// 30s) for an entry when there is none yet. `X-Journal-Id` and `X-Journal-Committed` tell the
+/// journal and its committed offset; 410 means the entries are gone and the caller has to load
+/// a new snapshot. Only for callers [`caller_replica`] lets through.
+#[get("/replication/journal")]
+async fn replication_journal(req: HttpRequest, info: web::Query<JournalQuery>, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = caller_replica(&req, &state) {
+        return resp;
+    }
+    let Some(journal) = state.service.journal() else {
+        return HttpResponse::NotFound().body("this node keeps no replication journal (start it with --journal)");
+    };
+    let limit = info.limit.unwrap_or(1000).clamp(1, 10_000);
+    let mut read = journal.read(info.from, limit);
+    if let (Ok(entries), Some(wait_ms)) = (&read, info.wait_ms) {
+        if entries.is_empty() && wait_ms > 0 {
+            journal.wait_committed(info.from, Duration::from_millis(wait_ms.min(30_000))).await;
+            read = journal.read(info.from, limit);
+        }
+    }
+    let entries = match read {
+        Ok(entries) => entries,
+        Err(gap) => return HttpResponse::Gone().insert_header(("X-Journal-Id", gap.journal_id.clone())).json(gap),
+    };
+    let mut body = String::new();
+    for entry in &entries {
+        match serde_json::to_string(entry) {
+            Ok(line) => body.push_str(&line),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        }
+        body.push('\n');
+    }
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(("X-Journal-Id", journal.id().to_string()))
+        .insert_header(("X-Journal-Committed", journal.committed_offset().to_string()))
+        .body(body)
+}
+
+/// Every post of both tiers as NDJSON, after a `{"journal_id", "offset", "posts"}` header
+/// line; a follower loads it and then reads the journal from `offset + 1`. Like the journal, only
+/// for callers [`caller_replica`] lets through.
+#[get("/replication/snapshot")]
+async fn replication_snapshot(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = caller_replica(&req, &state) {
+        return resp;
+    }
+    let (header, tiers) = match state.service.replication_snapshot() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return error_response(e),
+    };
+    let header = match serde_json::to_string(&header) {
+        Ok(line) => line + "\n",
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let chunks: Vec<(Tier, Arc<Searcher>, Vec<DocAddress>)> = tiers
+        .into_iter()
+        .flat_map(|(tier, searcher, addrs)| addrs.chunks(EXPORT_CHUNK).map(|c| (tier, Arc::clone(&searcher), c.to_vec())).collect::<Vec<_>>())
+        .collect();
+    let posts = futures_util::stream::iter(chunks).map(|(tier, searcher, chunk)| {
+        let schema = searcher.index().schema();
+        let mut out = String::new();
+        for addr in chunk {
+            let doc: TantivyDocument = searcher.doc(addr).map_err(actix_web::error::ErrorInternalServerError)?;
+            let Some(post) = from_document(&schema, &doc) else { continue };
+            out.push_str(&serde_json::to_string(&SnapshotPost { tier, post }).map_err(actix_web::error::ErrorInternalServerError)?);
+            out.push('\n');
+        }
+        Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(out))
+    });
+    let stream = futures_util::stream::iter([Ok(actix_web::web::Bytes::from(header))]).chain(posts);
+    HttpResponse::Ok().content_type("application/x-ndjson").streaming(stream)
+}
+
+/// A follower's applied position, lag behind the leader and last sync error; 503 when it is
+/// stale, for load balancer health checks.
+#[get("/replication/status")]
+async fn replication_status(state: web::Data<AppState>) -> impl Responder {
+    match state.service.replication_status() {
+        Some(status) if status.stale => HttpResponse::ServiceUnavailable().json(status),
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body("this node is not a follower (start it with --follow)"),
+    }
+}
+
+/// This node's view of the cluster metadata: raft role, term and leader, and the committed
+/// members, primary, aliases and shard assignments. Served locally, so a node cut off from the
+/// leader may answer with an older state.
+#[get("/cluster/metadata")]
+async fn cluster_metadata(state: web::Data<AppState>) -> impl Responder {
+    match state.service.raft() {
+        Some(raft) => HttpResponse::Ok().json(raft.status()),
+        None => HttpResponse::NotFound().body("this node is not part of a cluster (start it with --cluster-node-id)"),
+    }
+}
+
+/// Commits one metadata change (`{"op": "set_primary", "node": "n2"}`, `add_member`,
+/// `remove_member`, `set_alias`, `remove_alias`, `assign_shard`, `unassign_shard`) and returns
+/// the metadata with it applied. Only the raft leader takes changes; others answer 503 naming it.
+/// Callers send the cluster secret, like the raft calls.
+#[post("/cluster/metadata")]
+async fn change_cluster_metadata(req: HttpRequest, data: web::Json<MetadataCommand>, state: web::Data<AppState>) -> impl Responder {
+    let Some(raft) = state.service.raft() else {
+        return HttpResponse::NotFound().body("this node is not part of a cluster (start it with --cluster-node-id)");
+    };
+    if let Err(resp) = cluster_peer(&req, raft) {
+        return resp;
+    }
+    match raft.propose(data.into_inner()).await {
+        Ok(metadata) => HttpResponse::Ok().json(metadata),
+        Err(e) => error_response(e),
+    }
+}
+
+/// A 401 unless the request carries the cluster secret.
+fn cluster_peer(req: &HttpRequest, raft: &RaftNode) -> Result<(), HttpResponse> {
+    match raft.authorizes(req.headers().get(CLUSTER_SECRET_HEADER).and_then(|v| v.to_str().ok())) {
+        true => Ok(()),
+        false => Err(HttpResponse::Unauthorized().body("a valid X-Cluster-Secret is required")),
+    }
+}
+
+#[post("/cluster/raft/vote")]
+async fn raft_vote(req: HttpRequest, data: web::Json<VoteRequest>, state: web::Data<AppState>) -> impl Responder {
+    let Some(raft) = state.service.raft() else { return HttpResponse::NotFound().finish() };
+    if let Err(resp) = cluster_peer(&req, raft) {
+        return resp;
+    }
+    match raft.handle_vote(&data) {
+        Ok(vote) => HttpResponse::Ok().json(vote),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/cluster/raft/append")]
+async fn raft_append(req: HttpRequest, data: web::Json<AppendRequest>, state: web::Data<AppState>) -> impl Responder {
+    let Some(raft) = state.service.raft() else { return HttpResponse::NotFound().finish() };
+    if let Err(resp) = cluster_peer(&req, raft) {
+        return resp;
+    }
+    match raft.handle_append(data.into_inner()) {
+        Ok(appended) => HttpResponse::Ok().json(appended),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct DiffQuery { base: String, target: String, limit: Option<usize> }
+
+/// Compares two indexes by id and content hash: `added` ids exist only in `target`, `removed`
+/// only in `base`, `changed` in both with different stored content. Id lists are capped by
+/// `limit` (default 100) while counts are exact.
+#[get("/admin/diff")]
+async fn diff_indexes(info: web::Query<DiffQuery>, state: web::Data<AppState>) -> impl Responder {
+    let diff = match state.service.diff(&info.base, &info.target) {
+        Ok(d) => d,
+        Err(e) => return error_response(e),
+    };
+    let limit = info.limit.unwrap_or(100);
+    let section = |ids: &[String]| serde_json::json!({ "count": ids.len(), "ids": &ids[..ids.len().min(limit)] });
+    HttpResponse::Ok().json(serde_json::json!({
+        "base": { "source": info.base, "docs": diff.base_docs },
+        "target": { "source": info.target, "docs": diff.target_docs },
+        "added": section(&diff.added),
+        "removed": section(&diff.removed),
+        "changed": section(&diff.changed),
+    }))
+}
+
+/// Deletes (or with `"action": "scrub"` strips) everything referencing `subject` across the
+/// hot and archive indexes, comments, authors and the DLQ, then commits and merges so the data
+/// is gone from disk: `{"subject": "u-42"}` returns an erasure report.
+#[post("/admin/erase")]
+async fn erase_subject(data: web::Json<ErasureRequest>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.erase(&data) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Segment sizes, deleted documents and term dictionaries of both tiers, with the merges worth
+/// running and their estimated savings.
+#[get("/admin/compaction")]
+async fn compaction_report(state: web::Data<AppState>) -> impl Responder {
+    match state.service.compaction_report() {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Runs the recommended merges: `{"before", "merges", "after"}`.
+#[post("/admin/compaction")]
+async fn compact_index(state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.compact() {
+        Ok(run) => HttpResponse::Ok().json(run),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Rebuilds the posts indexes with other analyzers and swaps them in:
+/// `{"title_analyzer": "zh_ngram_3"}` returns the analyzers and how many posts were copied.
+/// Post writes get 503 until it returns; the copy runs on the blocking pool so they can be told.
+#[post("/reindex")]
+async fn reindex_posts(data: web::Json<ReindexRequest>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    let service = Arc::clone(&state.service);
+    match web::block(move || service.reindex(&data)).await {
+        Ok(Ok(report)) => HttpResponse::Ok().json(report),
+        Ok(Err(e)) => error_response(e),
+        Err(e) => error_response(ServiceError::Internal(e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct ObjectQuery { namespace: Option<String>, key: Option<String> }
+
+impl ObjectQuery {
+    fn target(&self) -> Result<(&str, &str), HttpResponse> {
+        match (self.namespace.as_deref(), self.key.as_deref()) {
+            (Some(namespace), Some(key)) => Ok((namespace, key)),
+            _ => Err(HttpResponse::BadRequest().body("namespace and key are required")),
+        }
+    }
+}
+
+/// Saved objects: the namespaces in use, every object of `namespace`, or with `key` one object
+/// (404 when missing).
+#[get("/admin/objects")]
+async fn get_objects(info: web::Query<ObjectQuery>, state: web::Data<AppState>) -> impl Responder {
+    let storage = state.service.storage();
+    let found = match (info.namespace.as_deref(), info.key.as_deref()) {
+        (None, _) => storage.namespaces().map(|n| Some(serde_json::json!(n))),
+        (Some(namespace), None) => storage.list(namespace).map(|objects| Some(serde_json::json!(objects))),
+        (Some(namespace), Some(key)) => storage.get(namespace, key),
+    };
+    match found {
+        Ok(Some(value)) => HttpResponse::Ok().json(value),
+        Ok(None) => HttpResponse::NotFound().body("no such object"),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Stores the JSON body as the object `namespace`/`key`, replacing any previous one.
+#[post("/admin/objects")]
+async fn put_object(info: web::Query<ObjectQuery>, body: web::Json<serde_json::Value>, state: web::Data<AppState>) -> impl Responder {
+    let (namespace, key) = match info.target() {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    match state.service.storage().put(namespace, key, body.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "namespace": namespace, "key": key })),
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/admin/objects")]
+async fn delete_object(info: web::Query<ObjectQuery>, state: web::Data<AppState>) -> impl Responder {
+    let (namespace, key) = match info.target() {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    match state.service.storage().delete(namespace, key) {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({ "deleted": true })),
+        Ok(false) => HttpResponse::NotFound().body("no such object"),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct DegradeBody { mode: DegradeMode }
+
+/// Degradation mode's state: whether it is on, who decides, and the error budget's window.
+#[get("/admin/degrade")]
+async fn degrade_status(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.service.degradation().snapshot())
+}
+
+/// Switches degradation mode by hand: `{"mode": "on"}` or `"off"` until set back to `"auto"`,
+/// where the error budget decides.
+#[post("/admin/degrade")]
+async fn set_degrade(body: web::Json<DegradeBody>, state: web::Data<AppState>) -> impl Responder {
+    state.service.degradation().set_mode(body.mode);
+    HttpResponse::Ok().json(state.service.degradation().snapshot())
+}
+
+/// Feature flags: `[{"name", "description", "enabled", "default"}]`.
+#[get("/admin/flags")]
+async fn list_flags(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.service.flags().snapshot())
+}
+
+/// Switches feature flags until restart: `{"clustering": false}`. Unknown names change nothing.
+#[post("/admin/flags")]
+async fn set_flags(body: web::Json<BTreeMap<String, bool>>, state: web::Data<AppState>) -> impl Responder {
+    let flags = state.service.flags();
+    if let Err(e) = body.keys().try_for_each(|name| FeatureFlags::check(name)) {
+        return error_response(e);
+    }
+    for (name, enabled) in body.iter() {
+        flags.set(name, *enabled).expect("checked above");
+    }
+    HttpResponse::Ok().json(flags.snapshot())
+}
+
+#[derive(Deserialize)]
+struct CompositeQuery { q: Option<String>, sources: String, size: Option<usize>, after: Option<String> }
+
+/// Pages through every combination of `sources` values (comma-separated fields) on matching
+/// documents: `{"buckets": [{"key": {"tags": "rust", "status": "published"}, "doc_count": 3}],
+/// "after_key": {...}}`. Pass `after_key` back as `after` (JSON) for the next page.
+#[get("/aggs/composite")]
+async fn composite_aggs(req: HttpRequest, info: web::Query<CompositeQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let sources: Vec<String> =
+        info.sources.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    let after = match info.after.as_deref().map(serde_json::from_str::<serde_json::Map<String, serde_json::Value>>) {
+        None => None,
+        Some(Ok(after)) => {
+            match sources.iter().map(|s| after.get(s).and_then(|v| v.as_str()).map(str::to_string)).collect() {
+                Some(key) => Some(key),
+                None => return HttpResponse::BadRequest().body("after must hold a string for every source"),
+            }
+        }
+        Some(Err(e)) => return HttpResponse::BadRequest().body(format!("after is not a JSON object: {}", e)),
+    };
+    if let Err(e) = state.service.check_not_degraded("aggregations") {
+        return flag_degraded(error_response(e));
+    }
+    let composite = CompositeRequest { q: info.q.clone(), sources: sources.clone(), size: info.size.unwrap_or(10), after, groups };
+    let _admitted = match state.service.admit_query(|| state.service.estimate_composite_cost(&composite)).await {
+        Ok(admitted) => admitted,
+        Err(e) => return error_response(e),
+    };
+    let _permit = state.acquire_search().await;
+    let page = match state.service.composite(&composite) {
+        Ok(p) => p,
+        Err(e) => return error_response(e),
+    };
+    let keyed = |key: &[String]| -> serde_json::Map<String, serde_json::Value> {
+        sources.iter().cloned().zip(key.iter().map(|v| serde_json::Value::from(v.as_str()))).collect()
+    };
+    let buckets: Vec<serde_json::Value> = page
+        .buckets
+        .iter()
+        .map(|b| serde_json::json!({ "key": keyed(&b.key), "doc_count": b.doc_count }))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({
+        "buckets": buckets,
+        "after_key": page.after_key.as_deref().map(keyed),
+    }))
+}
+
+#[derive(Deserialize)]
+struct AggsBody { q: Option<String>, aggs: tantivy::aggregation::agg_req::Aggregations }
+
+/// Tantivy aggregations over the documents matching `q` (all posts without it):
+/// `{"q": "rust", "aggs": {"by_status": {"terms": {"field": "status"}}, "score": {"stats":
+/// {"field": "features.score"}}}}` answers `{"aggregations": {"by_status": {"buckets": [...]},
+/// "score": {"count": 3, "min": ...}}}`.
+#[post("/search/aggs")]
+async fn search_aggs(req: HttpRequest, body: web::Json<AggsBody>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = state.service.check_not_degraded("aggregations") {
+        return flag_degraded(error_response(e));
+    }
+    let AggsBody { q, aggs } = body.into_inner();
+    let _admitted = match state.service.admit_query(|| state.service.estimate_aggregation_cost(q.as_deref(), &aggs)).await {
+        Ok(admitted) => admitted,
+        Err(e) => return error_response(e),
+    };
+    let _permit = state.acquire_search().await;
+    match state.service.aggregate(q.as_deref(), aggs, groups.as_deref()) {
+        Ok(results) => HttpResponse::Ok().json(serde_json::json!({ "aggregations": results })),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct SignificantTermsQuery { q: String, field: Option<String>, size: Option<usize>, min_doc_count: Option<u64> }
+
+/// Terms of `field` (default `tags`) unusually frequent in the documents matching `q`
+/// compared to the whole index.
+#[get("/significant_terms")]
+async fn significant_terms(req: HttpRequest, info: web::Query<SignificantTermsQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let field = info.field.as_deref().unwrap_or("tags");
+    if let Err(e) = state.service.check_flag("significant_terms") {
+        return error_response(e);
+    }
+    if let Err(e) = state.service.check_not_degraded("aggregations") {
+        return flag_degraded(error_response(e));
+    }
+    let _admitted = match state.service.admit_query(|| state.service.estimate_significant_terms_cost(&info.q, field)).await {
+        Ok(admitted) => admitted,
+        Err(e) => return error_response(e),
+    };
+    let _permit = state.acquire_search().await;
+    match state.service.significant_terms(&info.q, field, info.size.unwrap_or(10), info.min_doc_count.unwrap_or(3), groups.as_deref()) {
+        Ok(terms) => HttpResponse::Ok().json(terms),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/update")]
+async fn update_document(req: HttpRequest, params: web::Query<RefreshParams>, data: web::Json<BlogPost>, state: web::Data<AppState>) -> impl Responder {
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = if_none_match(&req, &state, &data, &tenant) {
+        return resp;
+    }
+    let hash = content_hash(&data);
+    let permit = state.acquire_write().await;
+    let updated = state.service.update_document(owned_by(data.into_inner(), &tenant)).await;
+    drop(permit);
+    match updated {
+        Ok(opstamp) => match refreshed(&state, params.refresh, opstamp).await {
+            Ok(()) => written(hash, "updated"),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+/// Changes some fields of a committed post, see [`PostPatch`].
+#[patch("/update/{id}")]
+async fn patch_document(req: HttpRequest, id: web::Path<String>, params: web::Query<RefreshParams>, data: web::Json<PostPatch>, state: web::Data<AppState>) -> impl Responder {
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let permit = state.acquire_write().await;
+    let patched = state.service.patch_document(&id, &data, &tenant).await;
+    drop(permit);
+    match patched {
+        Ok(Some((post, opstamp))) => match refreshed(&state, params.refresh, opstamp).await {
+            Ok(()) => written(content_hash(&post), "updated"),
+            Err(resp) => resp,
+        },
+        Ok(None) => HttpResponse::NotFound().body(format!("no post with id {}", id)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    id: String,
+    #[serde(default)]
+    refresh: Refresh,
+}
+
+/// Deletes a post from both tiers; with tenants configured, only one of the caller's (404 otherwise).
+#[delete("/delete")]
+async fn delete_document(req: HttpRequest, info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let permit = state.acquire_write().await;
+    let deleted = state.service.delete_document(&info.id, &tenant);
+    drop(permit);
+    match deleted {
+        Ok(opstamp) => match refreshed(&state, info.refresh, opstamp).await {
+            Ok(()) => HttpResponse::Ok().json("deleted"),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+/// Commits and swaps in the searchers before answering, so every write acknowledged so far is
+/// searchable.
+#[post("/refresh")]
+async fn refresh_index(state: web::Data<AppState>) -> impl Responder {
+    match state.service.refresh() {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "generation": *state.service.subscribe_commits().borrow() })),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Applies every operation under one writer lock and one commit, all-or-nothing.
+#[post("/batch")]
+async fn batch_documents(req: HttpRequest, params: web::Query<RefreshParams>, data: web::Json<Vec<BatchOp>>, state: web::Data<AppState>) -> impl Responder {
+    let tenant = match caller_tenant(&req, &state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let permit = state.acquire_write().await;
+    let ops: Vec<BatchOp> = data
+        .into_inner()
+        .into_iter()
+        .map(|op| match op {
+            BatchOp::Index { doc } => BatchOp::Index { doc: owned_by(doc, &tenant) },
+            BatchOp::Update { doc } => BatchOp::Update { doc: owned_by(doc, &tenant) },
+            delete => delete,
+        })
+        .collect();
+    let count = ops.len();
+    let applied = state.service.apply_batch(ops, &tenant).await;
+    drop(permit);
+    match applied {
+        Ok(opstamp) => match refreshed(&state, params.refresh, opstamp).await {
+            Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "opstamp": opstamp, "operations": count })),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/comments")]
+async fn add_comment(data: web::Json<Comment>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.index_comment(data.into_inner()) {
+        Ok(_) => HttpResponse::Ok().json("indexed"),
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/comments")]
+async fn delete_comment(info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.delete_comment(&info.id) {
+        Ok(_) => HttpResponse::Ok().json("deleted"),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/authors")]
+async fn add_author(data: web::Json<Author>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.index_author(data.into_inner()) {
+        Ok(_) => HttpResponse::Ok().json("indexed"),
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/authors")]
+async fn delete_author(info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.delete_author(&info.id) {
+        Ok(_) => HttpResponse::Ok().json("deleted"),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct CommentSearchQuery { q: Option<String>, has_parent: Option<String>, limit: Option<usize> }
+
+/// Comments matching `q` (all when omitted); `has_parent` keeps those whose post matches it.
+#[get("/comments/search")]
+async fn search_comments(info: web::Query<CommentSearchQuery>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_search().await;
+    let q = info.q.as_deref().unwrap_or("");
+    match state.service.search_comments(q, info.has_parent.as_deref(), info.limit.unwrap_or(10)) {
+        Ok(hits) => HttpResponse::Ok().json(hits),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The managed index `name` or the alias `name` points at, or a 404.
+fn managed_index(state: &AppState, name: &str) -> Result<Arc<ManagedIndex>, HttpResponse> {
+    state.service.indexes().resolve(name).ok_or_else(|| HttpResponse::NotFound().body(format!("no index named {}", name)))
+}
+
+/// Waits as `refresh` asks for the write to `index` stamped `opstamp` to become searchable.
+async fn index_refreshed(state: &AppState, index: &ManagedIndex, refresh: Refresh, opstamp: u64) -> Result<(), HttpResponse> {
+    let result = match refresh {
+        Refresh::False => return Ok(()),
+        _ if index.is_searchable(opstamp) => return Ok(()),
+        Refresh::True => index.refresh().map_err(Into::into),
+        Refresh::WaitFor => state.service.wait_for_index_refresh(index, opstamp, REFRESH_WAIT).await,
+    };
+    result.map_err(error_response)
+}
+
+#[get("/indexes")]
+async fn list_indexes(state: web::Data<AppState>) -> impl Responder {
+    let registry = state.service.indexes();
+    let indexes: Vec<_> = registry.list().iter().map(|index| registry.describe(index)).collect();
+    HttpResponse::Ok().json(indexes)
+}
+
+/// Creates an index with the schema of the body, e.g. `{"fields": [{"name": "sku", "type":
+/// "string"}, {"name": "title", "type": "text"}], "id_field": "sku"}`; a 409 if it exists.
+#[put("/indexes/{name}")]
+async fn create_index(name: web::Path<String>, spec: web::Json<IndexSpec>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.create_index(&name, spec.into_inner()) {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/indexes/{name}")]
+async fn get_index(name: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
+    match managed_index(&state, &name) {
+        Ok(index) => HttpResponse::Ok().json(state.service.indexes().describe(&index)),
+        Err(resp) => resp,
+    }
+}
+
+/// Drops an index and deletes its files; aliases must not point at it.
+#[delete("/indexes/{name}")]
+async fn drop_index(name: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    match state.service.indexes().drop_index(&name) {
+        Ok(true) => HttpResponse::Ok().json("dropped"),
+        Ok(false) => HttpResponse::NotFound().body(format!("no index named {}", name)),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Adds one document, or an array of them, to an index; documents with the `id_field` value
+/// of one already there replace it.
+#[post("/indexes/{name}/docs")]
+async fn add_index_documents(name: web::Path<String>, params: web::Query<RefreshParams>, body: web::Json<serde_json::Value>, state: web::Data<AppState>) -> impl Responder {
+    let index = match managed_index(&state, &name) {
+        Ok(index) => index,
+        Err(resp) => return resp,
+    };
+    let docs = match body.into_inner() {
+        serde_json::Value::Array(docs) => docs,
+        doc => vec![doc],
+    };
+    let count = docs.len();
+    let permit = state.acquire_write().await;
+    let added = state.service.index_into(&index, docs);
+    drop(permit);
+    match added {
+        Ok(opstamp) => match index_refreshed(&state, &index, params.refresh, opstamp).await {
+            Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "opstamp": opstamp, "documents": count })),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+/// Deletes the document of an index with this `id_field` value.
+#[delete("/indexes/{name}/docs/{id}")]
+async fn delete_index_document(path: web::Path<(String, String)>, params: web::Query<RefreshParams>, state: web::Data<AppState>) -> impl Responder {
+    let (name, id) = path.into_inner();
+    let index = match managed_index(&state, &name) {
+        Ok(index) => index,
+        Err(resp) => return resp,
+    };
+    let permit = state.acquire_write().await;
+    let deleted = state.service.delete_from(&index, &id);
+    drop(permit);
+    match deleted {
+        Ok(opstamp) => match index_refreshed(&state, &index, params.refresh, opstamp).await {
+            Ok(()) => HttpResponse::Ok().json("deleted"),
+            Err(resp) => resp,
+        },
+        Err(e) => error_response(e),
+    }
+}
+
+/// Every alias and the index it points at: `{"blog": "blog_v2"}`.
+#[get("/aliases")]
+async fn list_aliases(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.service.indexes().aliases())
+}
+
+#[derive(Deserialize)]
+struct AliasBody { alias: String, index: String }
+
+/// Points an alias at an index, creating it or moving it in one step:
+/// `{"alias": "blog", "index": "blog_v2"}`. Answers with the index it pointed at before.
+#[post("/aliases")]
+async fn set_alias(body: web::Json<AliasBody>, state: web::Data<AppState>) -> impl Responder {
+    match state.service.indexes().set_alias(&body.alias, &body.index) {
+        Ok(previous) => HttpResponse::Ok().json(serde_json::json!({ "alias": body.alias, "index": body.index, "previous": previous })),
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/aliases/{alias}")]
+async fn remove_alias(alias: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
+    match state.service.indexes().remove_alias(&alias) {
+        Ok(Some(index)) => HttpResponse::Ok().json(serde_json::json!({ "alias": *alias, "previous": index })),
+        Ok(None) => HttpResponse::NotFound().body(format!("no alias named {}", alias)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexSearchQuery { q: Option<String>, limit: Option<usize> }
+
+/// Documents of an index matching `q` (all when omitted), parsed over its text and json
+/// fields: `{"total", "hits": [{"score", "doc"}]}`.
+#[get("/indexes/{name}/search")]
+async fn search_managed_index(name: web::Path<String>, info: web::Query<IndexSearchQuery>, state: web::Data<AppState>) -> impl Responder {
+    let index = match managed_index(&state, &name) {
+        Ok(index) => index,
+        Err(resp) => return resp,
+    };
+    let _permit = state.acquire_search().await;
+    match state.service.search_index(&index, info.q.as_deref().unwrap_or(""), info.limit.unwrap_or(10)) {
+        Ok(results) => HttpResponse::Ok().json(results),
+        Err(e) => error_response(e),
+    }
 }
 
-pub struct AppState {
-    pub writer: Arc<Mutex<IndexWriter>>,     // protected for add and commit
-    pub reader: IndexReader,                  // used to get new searchers
-    pub current_searcher: Arc<ArcSwap<Searcher>>, // hot-swapped searcher
-}
-
-fn create_schema() -> Schema {
-    let mut schema_builder = Schema::builder();
-
-    // Per-field analyzers via TextOptions
-    let zh_indexing = TextFieldIndexing::default()
-        .set_tokenizer("zh_ngram")
-        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
-    let zh_text = TextOptions::default()
-        .set_indexing_options(zh_indexing)
-        .set_stored();
-
-    let tags_indexing = TextFieldIndexing::default()
-        .set_tokenizer("whitespace_lc")
-        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
-    let tags_text = TextOptions::default()
-        .set_indexing_options(tags_indexing)
-        .set_stored();
-
-    schema_builder.add_text_field("id", STRING | STORED);
-    schema_builder.add_text_field("title", zh_text.clone());
-    schema_builder.add_text_field("body", zh_text);
-    schema_builder.add_text_field("tags", tags_text);
-    schema_builder.add_i64_field("create_at", STORED);
-    schema_builder.add_text_field("status", STRING | STORED);
-    schema_builder.add_json_field("features", TEXT | STORED);
-    schema_builder.build()
-}
-
-fn to_document(schema: &Schema, post: BlogPost) -> TantivyDocument {
-    let mut document = TantivyDocument::default();
-    let f_id = schema.get_field("id").unwrap();
-    let f_title = schema.get_field("title").unwrap();
-    let f_body = schema.get_field("body").unwrap();
-    let f_tags = schema.get_field("tags").unwrap();
-    let f_create_at = schema.get_field("create_at").unwrap();
-    let f_status = schema.get_field("status").unwrap();
-    let f_features = schema.get_field("features").unwrap();
+#[derive(Deserialize)]
+struct DlqQuery { seq: Option<u64> }
+
+#[get("/dlq")]
+async fn list_dead_letters(state: web::Data<AppState>) -> impl Responder {
+    let entries = state.service.dead_letters().list();
+    HttpResponse::Ok().json(serde_json::json!({ "count": entries.len(), "entries": entries }))
+}
+
+/// Replays dead-lettered payloads (all, or just `?seq=N`). Each entry is re-validated and
+/// applied as its own batch, leaving the queue once committed; entries that fail again stay
+/// queued with `attempts` bumped.
+#[post("/dlq/retry")]
+async fn retry_dead_letters(info: web::Query<DlqQuery>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    HttpResponse::Ok().json(state.service.retry_dead_letters(info.seq).await)
+}
+
+/// Routes whose bodies are dead-lettered raw when they don't deserialize.
+const INGEST_ROUTES: [&str; 3] = ["/index", "/update", "/batch"];
+
+/// The body of a POST to one of `INGEST_ROUTES`, kept by [`KeepIngestBodies`].
+struct RawBody(web::Bytes);
+
+/// Middleware buffering the bodies of POSTs to `INGEST_ROUTES` as a [`RawBody`] extension,
+/// up to the JSON limit, so [`dead_letter_bad_json`] can queue what didn't deserialize.
+struct KeepIngestBodies;
+
+impl<S, B> Transform<S, ServiceRequest> for KeepIngestBodies
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = KeepIngestBodiesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, ()>>;
 
-    document.add_text(f_id, post.id);
-    document.add_text(f_title, post.title);
-    document.add_text(f_body, post.body);
-    for tag in post.tags.into_iter() {
-        document.add_text(f_tags, tag);
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(KeepIngestBodiesMiddleware { service: Rc::new(service) }))
     }
-    if let Some(ts) = post.create_at {
-        document.add_i64(f_create_at, ts);
+}
+
+struct KeepIngestBodiesMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for KeepIngestBodiesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            if req.method() == Method::POST && INGEST_ROUTES.contains(&req.path()) {
+                let mut payload = req.take_payload();
+                let mut body = web::BytesMut::new();
+                // Past the limit the JSON extractor refuses the body anyway
+                while body.len() <= JSON_LIMIT_BYTES {
+                    match payload.next().await {
+                        Some(chunk) => body.extend_from_slice(&chunk?),
+                        None => break,
+                    }
+                }
+                let body = body.freeze();
+                req.extensions_mut().insert(RawBody(body.clone()));
+                req.set_payload(Payload::from(body));
+            }
+            service.call(req).await
+        })
     }
-    document.add_text(f_status, post.status);
-    let ov = OwnedValue::from(post.features);
-    match ov {
-        OwnedValue::Object(map) => {
-            document.add_object(f_features, map);
-        }
-        other => {
-            // Wrap non-object into an object under key "value" for JSON field
-            let mut map = std::collections::BTreeMap::new();
-            map.insert("value".to_string(), other);
-            document.add_object(f_features, map);
+}
+
+/// Whether a request is for admins only: everything under /admin/, the dead-letter queue,
+/// which holds raw payloads of every tenant, rebuilding the posts indexes, running retention,
+/// which deletes or archives posts, creating, dropping or writing into managed indexes, and
+/// moving or removing the aliases live traffic reads through, and registering analyzers.
+fn admin_only(method: &Method, path: &str) -> bool {
+    let write = method != Method::GET && method != Method::HEAD;
+    match path {
+        "/dlq" | "/reindex" | "/retention/run" => true,
+        "/aliases" | "/analyzers" => write,
+        _ => {
+            path.starts_with("/admin/")
+                || path.starts_with("/dlq/")
+                || (write && (path.starts_with("/indexes/") || path.starts_with("/aliases/")))
         }
     }
+}
+
+/// Middleware refusing [`admin_only`] requests that [`caller_admin`] doesn't let through.
+struct RequireAdmin;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireAdmin
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequireAdminMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireAdminMiddleware { service: Rc::new(service) }))
+    }
+}
+
+struct RequireAdminMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireAdminMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            if admin_only(req.method(), req.path()) {
+                if let Some(state) = req.app_data::<web::Data<AppState>>() {
+                    if let Err(resp) = caller_admin(req.request(), state) {
+                        return Err(actix_web::error::InternalError::from_response("admin access refused", resp).into());
+                    }
+                }
+            }
+            service.call(req).await
+        })
+    }
+}
 
-    document
+/// `JsonConfig` error handler: ingest bodies that aren't JSON or don't fit their route's
+/// payload are dead-lettered as sent (source `index`, `update` or `batch`), then refused
+/// with the extractor's usual 400.
+fn dead_letter_bad_json(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let deserialize = matches!(err, JsonPayloadError::Deserialize(_) | JsonPayloadError::ContentType);
+    let raw = req.extensions().get::<RawBody>().map(|raw| raw.0.clone());
+    if let (true, Some(raw), Some(state)) = (deserialize, raw, req.app_data::<web::Data<AppState>>()) {
+        // Callers without a valid key don't get to fill the queue
+        if let Ok(tenant) = caller_tenant(req, state) {
+            let body = String::from_utf8_lossy(&raw).into_owned();
+            state.service.dead_letters().push_raw(req.path().trim_start_matches('/'), &err, RawPayload { body, tenant });
+        }
+    }
+    err.into()
 }
 
-pub fn index_post(writer: &mut IndexWriter, schema: &Schema, post: BlogPost) -> tantivy::Result<u64> {
-    let doc = to_document(schema, post);
-    writer.add_document(doc)
+#[delete("/dlq")]
+async fn discard_dead_letters(info: web::Query<DlqQuery>, state: web::Data<AppState>) -> impl Responder {
+    let discarded = state.service.dead_letters().take(info.seq).len();
+    HttpResponse::Ok().json(serde_json::json!({ "discarded": discarded }))
 }
 
-#[post("/index")]
-async fn add_document(data: web::Json<BlogPost>, state: web::Data<AppState>) -> impl Responder {
-    let mut writer = match state.writer.lock() {
-        Ok(g) => g,
-        Err(poison) => poison.into_inner(),
+/// Moderation rules, webhook, outcome counts and the most recent non-`allow` outcomes.
+#[get("/moderation")]
+async fn moderation_status(state: web::Data<AppState>) -> impl Responder {
+    let Some(moderator) = state.service.moderator() else {
+        return HttpResponse::Ok().json(serde_json::json!({ "enabled": false }));
     };
-    let schema = writer.index().schema();
-    match index_post(&mut writer, &schema, data.into_inner()) {
-        Ok(_) => HttpResponse::Ok().json("queued"),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    HttpResponse::Ok().json(serde_json::json!({
+        "enabled": true,
+        "rules": moderator.rules(),
+        "webhook": moderator.webhook(),
+        "counts": moderator.counts(),
+        "recent": moderator.recent(),
+    }))
+}
+
+/// Per-tenant post counts, storage share, searches and ingested volume.
+#[get("/admin/usage")]
+async fn tenant_usage(state: web::Data<AppState>) -> impl Responder {
+    if !state.service.usage().enabled() {
+        return HttpResponse::Ok().json(serde_json::json!({ "enabled": false }));
+    }
+    match state.service.usage_report() {
+        Ok(tenants) => HttpResponse::Ok().json(serde_json::json!({ "enabled": true, "tenants": tenants })),
+        Err(e) => error_response(e),
     }
 }
 
 #[derive(Deserialize)]
-pub struct SearchQuery { q: String, limit: Option<usize> }
+struct RetentionRunQuery { dry_run: Option<bool> }
 
-#[get("/search")]
-async fn search_document(info: web::Query<SearchQuery>, state: web::Data<AppState>) -> impl Responder {
-    let guard = state.current_searcher.load();
-    let searcher: &Searcher = &guard;
-
-    let index = searcher.index();
-    let schema = index.schema();
-    let default_fields = vec![
-        schema.get_field("title").unwrap(),
-        schema.get_field("body").unwrap(),
-        schema.get_field("tags").unwrap(),
-        schema.get_field("features").unwrap(),
-    ];
-    let parser = QueryParser::for_index(index, default_fields);
-    let query = match parser.parse_query(&info.q) {
-        Ok(q) => q,
-        Err(e) => return HttpResponse::BadRequest().body(format!("invalid query: {}", e)),
-    };
-    let limit = info.limit.unwrap_or(10);
-    let top_docs = match searcher.search(&query, &TopDocs::with_limit(limit)) {
-        Ok(d) => d,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+#[get("/retention")]
+async fn retention_status(state: web::Data<AppState>) -> impl Responder {
+    let retention = state.service.retention();
+    HttpResponse::Ok().json(serde_json::json!({
+        "rules": retention.rules,
+        "dry_run": retention.dry_run,
+        "last_report": retention.last_report(),
+    }))
+}
+
+/// Runs retention immediately; `?dry_run=true` reports matches without deleting anything.
+#[post("/retention/run")]
+async fn retention_run(info: web::Query<RetentionRunQuery>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    let dry_run = info.dry_run.unwrap_or(state.service.retention().dry_run);
+    match state.service.run_retention(dry_run) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct AnalyzeQuery { field: Option<String>, analyzer: Option<String>, text: String }
+
+/// Tokens `text` becomes when indexed into `field`, or run through the registered `analyzer`:
+/// `[{"text", "position", "offset_from", "offset_to"}]`.
+#[get("/analyze")]
+async fn analyze_text(info: web::Query<AnalyzeQuery>, state: web::Data<AppState>) -> impl Responder {
+    let tokens = match (&info.field, &info.analyzer) {
+        (Some(field), None) => state.service.analyze(field, &info.text),
+        (None, Some(analyzer)) => state.service.analyze_with(analyzer, &info.text),
+        _ => return HttpResponse::BadRequest().body("give either field or analyzer"),
     };
+    match tokens {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(e) => error_response(e),
+    }
+}
 
-    let mut results: Vec<serde_json::Value> = Vec::new();
-    for (_score, addr) in top_docs {
-        let doc: TantivyDocument = match searcher.doc::<TantivyDocument>(addr) {
-            Ok(d) => d,
-            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
-        };
-        results.push(doc_to_named_debug(&schema, &doc));
+#[derive(Deserialize)]
+struct SchemaQuery {
+    index: Option<String>,
+}
+
+/// The live schema of every index, or of `?index=`, with the registered analyzers:
+/// `{"indexes": [{"index", "fields", "default_search_fields", "sort_by"}], "analyzers"}`.
+#[get("/schema")]
+async fn schema_info(info: web::Query<SchemaQuery>, state: web::Data<AppState>) -> impl Responder {
+    match state.service.schema_info(info.index.as_deref()) {
+        Some(schema) => HttpResponse::Ok().json(schema),
+        None => HttpResponse::NotFound().body(format!("no index named {}", info.index.as_deref().unwrap_or_default())),
     }
+}
 
-    HttpResponse::Ok().json(results)
+/// Every registered analyzer: `[{"name", "tokenizer", "filters", "builtin", "fields"}]`.
+#[get("/analyzers")]
+async fn list_analyzers(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.service.analyzers())
 }
 
-#[post("/update")]
-async fn update_document(data: web::Json<BlogPost>, state: web::Data<AppState>) -> impl Responder {
-    let mut writer = match state.writer.lock() {
+/// Registers a custom analyzer on every index, e.g.
+/// `{"name": "en_text", "tokenizer": {"type": "simple"}, "filters": [{"type": "lowercase"}]}`.
+#[post("/analyzers")]
+async fn register_analyzer(spec: web::Json<AnalyzerSpec>, state: web::Data<AppState>) -> impl Responder {
+    let spec = spec.into_inner();
+    let name = spec.name.clone();
+    match state.service.register_analyzer(spec) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "name": name, "status": "registered" })),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The `/search` parameters that shape the query, as a JSON body.
+#[derive(Deserialize)]
+struct DebugQueryBody {
+    q: String,
+    #[serde(default)]
+    include_archive: bool,
+    minimum_should_match: Option<String>,
+    shingle_boost: Option<f32>,
+    #[serde(default)]
+    tags_all: Vec<String>,
+    #[serde(default)]
+    tags_any: Vec<String>,
+    has_child: Option<String>,
+    nested: Option<String>,
+}
+
+/// Explains a search before running it: the resolved query tree, the fields and terms it looks
+/// up with their document frequencies, and their sum as an estimated cost. The caller's token
+/// narrows it to their groups like `/search` does.
+#[post("/debug/query")]
+async fn debug_query(req: HttpRequest, body: web::Json<DebugQueryBody>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
         Ok(g) => g,
-        Err(poison) => poison.into_inner(),
+        Err(resp) => return resp,
     };
-    let schema = writer.index().schema();
-    let f_id = schema.get_field("id").unwrap();
+    let minimum_should_match = match body.minimum_should_match.as_deref().map(str::parse::<MinimumShouldMatch>).transpose() {
+        Ok(m) => m,
+        Err(e) => return error_response(e.into()),
+    };
+    let nested = match parse_nested(body.nested.as_deref()) {
+        Ok(n) => n,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = check_shingle_boost(body.shingle_boost) {
+        return resp;
+    }
+    let req = SearchRequest {
+        q: body.q.clone(),
+        include_archive: body.include_archive,
+        minimum_should_match,
+        shingle_boost: body.shingle_boost,
+        tags_all: body.tags_all.clone(),
+        tags_any: body.tags_any.clone(),
+        has_child: body.has_child.clone(),
+        nested,
+        groups,
+        ..Default::default()
+    };
+    match state.service.explain_query(&req) {
+        Ok(plan) => HttpResponse::Ok().json(plan),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Stored fields of one post as JSON, from whichever tier holds it:
+/// `{"tier": "hot", "doc": {"id": "1", "tags": ["rust"], ...}}`. Restricted fields are left out,
+/// and a post the caller's groups may not see is a 404 like a missing one.
+#[get("/doc")]
+async fn get_document(req: HttpRequest, info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    let etag = state.service.read_etag(&read_key(&req), None);
+    if let Some(resp) = not_modified(&req, &etag) {
+        return resp;
+    }
+    let (tier, doc) = match state.service.document(&info.id, groups.as_deref()) {
+        Ok(Some(found)) => found,
+        Ok(None) => return HttpResponse::NotFound().body(format!("no post with id {}", info.id)),
+        Err(e) => return error_response(e),
+    };
+    let schema = state.service.schema();
+    let columns = export_columns(&schema, None).expect("default columns always resolve");
+    let fields: serde_json::Map<String, serde_json::Value> =
+        columns.iter().map(|c| (c.name.clone(), column_value(&doc, c))).filter(|(_, v)| !v.is_null()).collect();
+    cacheable(HttpResponse::Ok().json(serde_json::json!({ "tier": tier.as_str(), "doc": fields })), has_credentials(&req), &etag)
+}
 
-    // delete existing by id, then add
-    writer.delete_term(Term::from_field_text(f_id, &data.id));
-    match index_post(&mut writer, &schema, data.into_inner()) {
-        Ok(_) => HttpResponse::Ok().json("updated"),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+/// Term vectors of one post, for debugging why it doesn't match:
+/// `{"tier": "hot", "fields": [{"field", "stored", "terms": [{"term", "indexed", "term_freq", "positions", "doc_freq"}]}]}`.
+/// Restricted fields are left out; a post hidden from the caller's groups is a 404.
+#[get("/doc/terms")]
+async fn document_terms(req: HttpRequest, info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
+    let groups = match caller_groups(&req, &state) {
+        Ok(g) => g,
+        Err(resp) => return resp,
+    };
+    match state.service.document_terms(&info.id, groups.as_deref()) {
+        Ok(Some((tier, fields))) => HttpResponse::Ok().json(serde_json::json!({ "tier": tier.as_str(), "fields": fields })),
+        Ok(None) => HttpResponse::NotFound().body(format!("no post with id {}", info.id)),
+        Err(e) => error_response(e),
     }
 }
 
 #[derive(Deserialize)]
-struct DeleteQuery { id: String }
+struct TermStatsQuery {
+    field: String,
+    /// Comma-separated
+    terms: String,
+    #[serde(default)]
+    tier: Option<Tier>,
+}
 
-#[delete("/delete")]
-async fn delete_document(info: web::Query<DeleteQuery>, state: web::Data<AppState>) -> impl Responder {
-    let mut writer = match state.writer.lock() {
-        Ok(g) => g,
-        Err(poison) => poison.into_inner(),
+/// BM25 inputs of some terms in one field: `{"field", "tier", "num_docs", "avg_field_len",
+/// "terms": [{"term", "token", "doc_freq", "total_term_freq", "idf"}]}`.
+#[get("/terms/stats")]
+async fn term_stats(info: web::Query<TermStatsQuery>, state: web::Data<AppState>) -> impl Responder {
+    let terms: Vec<String> = info.terms.split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::to_string).collect();
+    match state.service.term_stats(&info.field, &terms, info.tier.unwrap_or(Tier::Hot)) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The admin UI: search with highlighting, document inspector, stats and analyzer testing,
+/// all against this server's API.
+#[cfg(feature = "ui")]
+#[get("/ui")]
+async fn admin_ui() -> impl Responder {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(include_str!("../ui/index.html"))
+}
+
+/// Readiness: 200 once the `--seed-dir` load (if any) has finished or was skipped, 503 while it
+/// is loading or after it failed. `{"ready": bool, "seed": {"state", "files_done", "docs_loaded", ...}}`
+#[get("/readyz")]
+async fn readyz(state: web::Data<AppState>) -> impl Responder {
+    let Some(seed) = &state.seed else { return HttpResponse::Ok().json(serde_json::json!({ "ready": true })) };
+    let body = serde_json::json!({ "ready": seed.finished(), "seed": seed.progress() });
+    match seed.finished() {
+        true => HttpResponse::Ok().json(body),
+        false => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
+
+#[get("/stats")]
+async fn stats(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.service.stats())
+}
+
+/// Prometheus scrape endpoint.
+#[get("/metrics")]
+async fn metrics(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(state.service.metrics())
+}
+
+/// The [`settings::HOT_SETTINGS`] in effect, by `--config` file name; unset ones are left out.
+fn live_settings(state: &AppState) -> toml::Table {
+    let service = &state.service;
+    let limits = service.query_limits();
+    let mut table = toml::Table::new();
+    let mut set = |name: &str, value: toml::Value| {
+        table.insert(name.to_string(), value);
     };
-    let schema = writer.index().schema();
-    let f_id = schema.get_field("id").unwrap();
-    writer.delete_term(Term::from_field_text(f_id, &info.id));
-    HttpResponse::Ok().json("deleted")
+    set("max_query_clauses", (limits.max_clauses as i64).into());
+    set("max_query_terms", (limits.max_terms as i64).into());
+    set("max_search_timeout_ms", (state.max_search_timeout_ms.load(Ordering::Relaxed) as i64).into());
+    if let Some(admission) = service.admission().map(|a| a.config()) {
+        set("query_cost_budget", (admission.budget as i64).into());
+        set("admission_busy_at", (admission.busy_at as i64).into());
+        set("over_budget", match admission.over_budget {
+            OverBudget::Reject => "reject",
+            OverBudget::Queue => "queue",
+        }.into());
+        set("admission_queue_ms", (admission.queue_timeout.as_millis() as i64).into());
+    }
+    if let Some(degrade) = service.degradation().config() {
+        set("degrade_slow_ms", (degrade.slow_after.as_millis() as i64).into());
+        set("degrade_error_budget", degrade.error_budget.into());
+        set("degrade_window_secs", (degrade.window.as_secs() as i64).into());
+    }
+    let flags: Vec<String> = service.flags().snapshot().iter().map(|f| format!("{}={}", f.name, if f.enabled { "on" } else { "off" })).collect();
+    set("feature_flag", flags.into());
+    table
+}
+
+#[derive(Deserialize)]
+struct ExportConfigQuery {
+    /// Comma-separated saved-object namespaces; [`CONFIG_NAMESPACES`] when unset
+    namespaces: Option<String>,
 }
 
-fn doc_to_named_debug(schema: &Schema, doc: &TantivyDocument) -> serde_json::Value {
-    let mut obj = serde_json::Map::new();
-    for fv in doc.field_values() {
-        let name = schema.get_field_entry(fv.field()).name().to_string();
-        obj.insert(name, serde_json::Value::String(format!("{:?}", fv.value())));
+/// This instance's configuration as one bundle file, see [`ConfigBundle`].
+#[get("/admin/export_config")]
+async fn export_config(info: web::Query<ExportConfigQuery>, state: web::Data<AppState>) -> impl Responder {
+    let namespaces: Vec<&str> = match &info.namespaces {
+        Some(names) => names.split(',').map(str::trim).filter(|n| !n.is_empty()).collect(),
+        None => CONFIG_NAMESPACES.to_vec(),
+    };
+    match state.service.export_config(&namespaces, live_settings(&state)) {
+        Ok(bundle) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"tantivy-demo-config.json\""))
+            .json(bundle),
+        Err(e) => error_response(e),
     }
-    serde_json::Value::Object(obj)
 }
 
-#[actix_web::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+/// Applies a bundle from `/admin/export_config`: analyzers, managed indexes, saved objects and
+/// live settings, checked first so a conflicting bundle (409) or bad settings (400) change
+/// nothing. Reports what was applied and the schema options that differ.
+#[post("/admin/import_config")]
+async fn import_config(bundle: web::Json<ConfigBundle>, state: web::Data<AppState>) -> impl Responder {
+    let _permit = state.acquire_write().await;
+    let opts = settings::config_args(&bundle.settings).and_then(|args| {
+        if let Some(name) = bundle.settings.keys().find(|k| !settings::HOT_SETTINGS.contains(&k.replace('-', "_").as_str())) {
+            anyhow::bail!("{} is not a live setting", name);
+        }
+        let opts = ServerOpts::try_parse_from(std::iter::once("tantivy-demo".to_string()).chain(args))
+            .map_err(|e| anyhow::anyhow!("{}", e.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ")))?;
+        if admission_config(&opts)?.is_some() != state.service.admission().is_some() {
+            anyhow::bail!("query_cost_budget turns admission control on or off only at startup; restart to apply");
+        }
+        Ok(opts)
+    });
+    let opts = match opts {
+        // A bundle without settings leaves them alone rather than resetting them
+        Ok(opts) => (!bundle.settings.is_empty()).then_some(opts),
+        Err(e) => return HttpResponse::BadRequest().body(format!("settings: {:#}", e)),
+    };
+    let before = live_settings(&state);
+    let mut report = match state.service.import_config(&bundle) {
+        Ok(report) => report,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = opts.map_or(Ok(()), |opts| apply_live_settings(&state, &opts)) {
+        return HttpResponse::InternalServerError().body(format!("settings: {:#}", e));
+    }
+    report.settings = settings::changes(&before, &live_settings(&state));
+    HttpResponse::Ok().json(report)
+}
 
-    // Build schema and index in a temp dir (RAM directory is also possible). Use project-local path.
-    let schema = create_schema();
+/// Settings of the running service and the `--config` file's reload state.
+#[get("/admin/settings")]
+async fn admin_settings(state: web::Data<AppState>) -> impl Responder {
+    let service = &state.service;
+    let limits = service.query_limits();
+    let admission = service.admission().map(|a| a.config());
+    let degrade = service.degradation().config();
+    let settings = serde_json::json!({
+        "max_query_clauses": limits.max_clauses,
+        "max_query_terms": limits.max_terms,
+        "max_search_timeout_ms": state.max_search_timeout_ms.load(Ordering::Relaxed),
+        "query_cost_budget": admission.as_ref().map(|a| a.budget),
+        "admission_busy_at": admission.as_ref().map(|a| a.busy_at),
+        "over_budget": admission.as_ref().map(|a| match a.over_budget {
+            OverBudget::Reject => "reject",
+            OverBudget::Queue => "queue",
+        }),
+        "admission_queue_ms": admission.as_ref().map(|a| a.queue_timeout.as_millis() as u64),
+        "degrade_slow_ms": degrade.as_ref().map(|d| d.slow_after.as_millis() as u64),
+        "degrade_error_budget": degrade.as_ref().map(|d| d.error_budget),
+        "degrade_window_secs": degrade.as_ref().map(|d| d.window.as_secs()),
+    });
+    let file = state.config_file.as_ref().map(|f| f.lock().unwrap_or_else(|e| e.into_inner()).status.clone());
+    let warnings: Vec<String> = file
+        .iter()
+        .flat_map(|f| &f.restart_required)
+        .map(|c| format!("{} changed in {} since startup; restart to apply", c.name, file.as_ref().map(|f| f.path.display().to_string()).unwrap_or_default()))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "settings": settings, "config": file, "warnings": warnings }))
+}
 
-    let mut index_path = PathBuf::from(".tantivy_idx");
-    // Create or open index
-    let index = if index_path.exists() {
-        Index::open_in_dir(&index_path)?
-    } else {
-        std::fs::create_dir_all(&index_path)?;
-        Index::create_in_dir(&index_path, schema.clone())?
+/// The options from the command line over the `--config` file `table`.
+fn parse_with_config(table: &toml::Table, cli_args: &[String]) -> anyhow::Result<ServerOpts> {
+    let mut args = cli_args[..1].to_vec();
+    args.extend(settings::config_args(table)?);
+    args.extend(cli_args[1..].iter().cloned());
+    ServerOpts::try_parse_from(args).map_err(|e| {
+        let message = e.to_string();
+        anyhow::anyhow!("{}", message.lines().next().unwrap_or_default().trim_start_matches("error: "))
+    })
+}
+
+fn max_searches(opts: &ServerOpts) -> usize {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    opts.max_concurrent_searches.unwrap_or(cores * 2)
+}
+
+fn admission_config(opts: &ServerOpts) -> anyhow::Result<Option<AdmissionConfig>> {
+    let over_budget = match opts.over_budget.as_str() {
+        "reject" => OverBudget::Reject,
+        "queue" => OverBudget::Queue,
+        other => anyhow::bail!("unknown --over-budget value: {} (expected reject or queue)", other),
     };
+    Ok(opts.query_cost_budget.map(|budget| AdmissionConfig {
+        budget,
+        busy_at: opts.admission_busy_at.unwrap_or(max_searches(opts)).max(1),
+        over_budget,
+        queue_timeout: Duration::from_millis(opts.admission_queue_ms),
+    }))
+}
 
-    // Register custom analyzers (no external deps):
-    // - zh_ngram: character bigram/trigram for CJK-friendly search
-    // - whitespace_lc: whitespace + lowercasing for tags
-    {
-        let zh = TextAnalyzer::builder(NgramTokenizer::new(2, 3, false).unwrap())
-            .filter(LowerCaser)
-            .build();
-        index.tokenizers().register("zh_ngram", zh);
+fn degrade_config(opts: &ServerOpts) -> anyhow::Result<Option<DegradeConfig>> {
+    if !(0.0..1.0).contains(&opts.degrade_error_budget) {
+        anyhow::bail!("--degrade-error-budget must be at least 0 and below 1");
+    }
+    Ok(opts.degrade_slow_ms.map(|ms| DegradeConfig {
+        slow_after: Duration::from_millis(ms),
+        error_budget: opts.degrade_error_budget,
+        window: Duration::from_secs(opts.degrade_window_secs.max(1)),
+    }))
+}
+
+/// Applies the [`settings::HOT_SETTINGS`] of `opts` to the running service, all or none.
+fn apply_live_settings(state: &AppState, opts: &ServerOpts) -> anyhow::Result<()> {
+    let admission = admission_config(opts)?;
+    let degrade = degrade_config(opts)?;
+    let flags: Vec<(String, bool)> = opts.feature_flags.iter().map(|f| parse_flag(f)).collect::<Result<_, _>>()?;
+    flags.iter().try_for_each(|(name, _)| FeatureFlags::check(name))?;
+    match (state.service.admission(), admission) {
+        (Some(current), Some(config)) => current.set_config(config),
+        (None, None) => {}
+        _ => anyhow::bail!("query_cost_budget turns admission control on or off only at startup; restart to apply"),
+    }
+    state.service.degradation().set_config(degrade);
+    // Only the flags the file or command line name, so ones switched by hand stay as they are
+    for (name, enabled) in &flags {
+        state.service.flags().set(name, *enabled)?;
+    }
+    state.service.set_query_limits(QueryLimits { max_clauses: opts.max_query_clauses, max_terms: opts.max_query_terms });
+    state.max_search_timeout_ms.store(opts.max_search_timeout_ms, Ordering::Relaxed);
+    Ok(())
+}
 
-        let tags_analyzer = TextAnalyzer::builder(WhitespaceTokenizer::default())
-            .filter(LowerCaser)
-            .build();
-        index.tokenizers().register("whitespace_lc", tags_analyzer);
+/// Rereads the `--config` file after a change: applies its live settings, logs what changed
+/// and notes the restart-only options that did.
+fn reload_config(state: &AppState) {
+    let Some(file) = &state.config_file else { return };
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    let path = file.status.path.clone();
+    let applied = settings::read_config(&path).and_then(|table| {
+        let opts = parse_with_config(&table, &file.cli_args)?;
+        Ok((table, opts))
+    });
+    let (table, opts) = match applied {
+        Ok(applied) => applied,
+        Err(e) => {
+            eprintln!("{}: not reloaded: {:#}", path.display(), e);
+            file.status.last_error = Some(format!("{:#}", e));
+            return;
+        }
+    };
+    let changes = settings::changes(&file.current, &table);
+    if changes.is_empty() {
+        return;
+    }
+    if let Err(e) = apply_live_settings(state, &opts) {
+        eprintln!("{}: not reloaded: {:#}", path.display(), e);
+        file.status.last_error = Some(format!("{:#}", e));
+        return;
+    }
+    for change in &changes {
+        let (old, new) = (change.old.as_deref().unwrap_or("unset"), change.new.as_deref().unwrap_or("unset"));
+        match change.live {
+            true => eprintln!("{}: {} changed from {} to {}", path.display(), change.name, old, new),
+            false => eprintln!("{}: {} changed from {} to {}; restart to apply", path.display(), change.name, old, new),
+        }
     }
+    let since_startup = settings::changes(&file.startup, &table);
+    file.status.reloaded(since_startup);
+    file.current = table;
+}
 
-    // Create writer with 50MB heap
-    let writer = index.writer(50_000_000)?;
-    let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
-    let searcher = reader.searcher();
+/// Every route of the server, with the JSON limits of its bodies.
+fn routes(cfg: &mut web::ServiceConfig) {
+    #[cfg(feature = "ui")]
+    cfg.service(admin_ui);
+    cfg.app_data(web::JsonConfig::default().limit(JSON_LIMIT_BYTES).error_handler(dead_letter_bad_json))
+        .service(add_document)
+        .service(update_document)
+        .service(patch_document)
+        .service(delete_document)
+        .service(refresh_index)
+        .service(batch_documents)
+        .service(add_comment)
+        .service(delete_comment)
+        .service(search_comments)
+        .service(list_indexes)
+        .service(create_index)
+        .service(get_index)
+        .service(drop_index)
+        .service(add_index_documents)
+        .service(delete_index_document)
+        .service(search_managed_index)
+        .service(list_aliases)
+        .service(set_alias)
+        .service(remove_alias)
+        .service(add_author)
+        .service(delete_author)
+        .service(stats)
+        .service(metrics)
+        .service(readyz)
+        .service(analyze_text)
+        .service(list_analyzers)
+        .service(schema_info)
+        .service(register_analyzer)
+        .service(debug_query)
+        .service(get_document)
+        .service(document_terms)
+        .service(term_stats)
+        .service(moderation_status)
+        .service(retention_status)
+        .service(retention_run)
+        .service(list_dead_letters)
+        .service(retry_dead_letters)
+        .service(discard_dead_letters)
+        .service(search_document)
+        .service(latest_documents)
+        .service(composite_aggs)
+        .service(search_aggs)
+        .service(significant_terms)
+        .service(latest_stream)
+        .service(export_documents)
+        .service(diff_indexes)
+        .service(degrade_status)
+        .service(set_degrade)
+        .service(admin_settings)
+        .service(export_config)
+        .service(import_config)
+        .service(list_flags)
+        .service(set_flags)
+        .service(get_objects)
+        .service(put_object)
+        .service(delete_object)
+        .service(erase_subject)
+        .service(compaction_report)
+        .service(compact_index)
+        .service(reindex_posts)
+        .service(tenant_usage)
+        .service(replication_journal)
+        .service(replication_snapshot)
+        .service(replication_status)
+        .service(cluster_metadata)
+        .service(change_cluster_metadata)
+        .service(raft_vote)
+        .service(raft_append);
+}
 
-    let state = web::Data::new(AppState {
-        writer: Arc::new(Mutex::new(writer)),
-        reader,
-        current_searcher: Arc::new(ArcSwap::new(Arc::new(searcher))),
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    let cli_args: Vec<String> = std::env::args().collect();
+    let opts = ServerOpts::parse();
+    let (opts, config_file) = match &opts.config {
+        Some(path) => {
+            let table = settings::read_config(path)?;
+            let opts = parse_with_config(&table, &cli_args).map_err(|e| e.context(format!("applying {}", path.display())))?;
+            let status = ReloadStatus { path: path.clone(), ..ReloadStatus::default() };
+            (opts, Some(ConfigFile { cli_args, startup: table.clone(), current: table, status }))
+        }
+        None => (opts, None),
+    };
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let workers = opts.workers.unwrap_or(cores);
+    let max_searches = max_searches(&opts);
+
+    let admission = admission_config(&opts)?;
+    let degrade = degrade_config(&opts)?;
+
+    let mut redaction = RedactionConfig {
+        patterns: opts.redact_patterns.clone(),
+        keep_original: opts.redact_keep_original,
+        ..RedactionConfig::default()
+    };
+    for kind in &opts.redact {
+        match kind.trim() {
+            "email" => redaction.emails = true,
+            "phone" => redaction.phones = true,
+            other => anyhow::bail!("unknown --redact value: {} (expected email or phone)", other),
+        }
+    }
+    let moderation = ModerationConfig {
+        rules: match &opts.moderation_rules {
+            Some(path) => moderation::load_rules(path)?,
+            None => Vec::new(),
+        },
+        webhook: opts.moderation_webhook.clone(),
+        webhook_timeout: Duration::from_millis(opts.moderation_timeout_ms),
+    };
+    let moderating = !moderation.rules.is_empty() || moderation.webhook.is_some();
+    let metering = (opts.metering_file.is_some() || opts.metering_url.is_some()).then(|| MeteringConfig {
+        path: opts.metering_file.clone(),
+        url: opts.metering_url.clone(),
+        interval: Duration::from_secs(opts.metering_interval_secs),
     });
+    if metering.is_some() && opts.tenants.is_none() {
+        anyhow::bail!("--metering-file and --metering-url need --tenants");
+    }
+    if opts.jwt_admin_group.is_some() && opts.jwt_secret.is_none() {
+        anyhow::bail!("--jwt-admin-group needs --jwt-secret");
+    }
+    let cluster = match &opts.cluster_node_id {
+        Some(node_id) => {
+            let mut members = BTreeMap::new();
+            for member in &opts.cluster_members {
+                let Some((id, url)) = member.split_once('=') else {
+                    anyhow::bail!("--cluster-member must be id=url: {}", member);
+                };
+                members.insert(id.trim().to_string(), url.trim().to_string());
+            }
+            if !members.is_empty() && !members.contains_key(node_id) {
+                anyhow::bail!("--cluster-member must list this node ({}) too", node_id);
+            }
+            let Some(secret) = opts.cluster_secret.clone() else {
+                anyhow::bail!("--cluster-node-id needs --cluster-secret");
+            };
+            let election_timeout = Duration::from_millis(opts.cluster_election_ms.max(50));
+            Some(RaftConfig {
+                node_id: node_id.clone(),
+                members,
+                state_path: Some(opts.cluster_state.clone()),
+                secret: Some(secret),
+                election_timeout,
+                heartbeat: election_timeout / 5,
+            })
+        }
+        None if !opts.cluster_members.is_empty() || opts.cluster_secret.is_some() => {
+            anyhow::bail!("--cluster-member and --cluster-secret need --cluster-node-id")
+        }
+        None => None,
+    };
+    let redacting = redaction.emails || redaction.phones || !redaction.patterns.is_empty();
 
-    // Background task to periodically commit and refresh searcher
-    {
-        let state_clone = state.clone();
-        actix_web::rt::spawn(async move {
-            loop {
-                actix_web::rt::time::sleep(Duration::from_secs(3)).await;
-                // commit
-                if let Ok(mut w) = state_clone.writer.lock() {
-                    if let Err(e) = w.commit() {
-                        eprintln!("commit error: {}", e);
-                        continue;
-                    }
-                }
-                // reload reader and swap searcher
-                if let Err(e) = state_clone.reader.reload() {
-                    eprintln!("reader reload error: {}", e);
-                    continue;
-                }
-                let new_searcher = state_clone.reader.searcher();
-                state_clone
-                    .current_searcher
-                    .store(Arc::new(new_searcher));
+    let config = ServiceConfig {
+        sync_commits: opts.sync_commits,
+        commit_pacing: match opts.commit_interval_ms {
+            Some(ms) => CommitPacing::Fixed(Duration::from_millis(ms.max(1))),
+            None => CommitPacing::Adaptive {
+                min: Duration::from_millis(opts.commit_min_ms.max(1)),
+                max: Duration::from_millis(opts.commit_max_ms.max(opts.commit_min_ms).max(1)),
+                heavy_writes_per_sec: opts.commit_heavy_writes_per_sec,
+            },
+        },
+        nrt_slo: Duration::from_millis(opts.nrt_slo_ms),
+        archive_path: opts.archive_path.clone(),
+        comments_path: opts.comments_path.clone(),
+        authors_path: opts.authors_path.clone(),
+        analyzers_path: opts.analyzers_path.clone(),
+        indexes_path: opts.indexes_path.clone(),
+        schema_file: match &opts.schema_file {
+            Some(path) => load_schema_file(path)?,
+            None => SchemaFile::default(),
+        },
+        storage_path: opts.storage_path.clone(),
+        snapshots_path: opts.snapshots_path.clone(),
+        formats_path: opts.formats_path.clone(),
+        retention_rules: match &opts.retention_rules {
+            Some(path) => load_rules(path)?,
+            None => Vec::new(),
+        },
+        retention_interval: Duration::from_secs(opts.retention_interval_secs),
+        retention_dry_run: opts.retention_dry_run,
+        shadow_index: opts.shadow_index.clone(),
+        shadow_sample_pct: opts.shadow_sample_pct,
+        sort_by_create_at: opts.index_sort_create_at,
+        fold_text: opts.fold_text,
+        body_analyzer: opts.body_analyzer.clone(),
+        features_analyzer: opts.features_analyzer.clone(),
+        nested_paths: opts.nested_paths.clone(),
+        redaction: redacting.then_some(redaction),
+        path_features: opts.path_features.clone(),
+        protected_terms: match &opts.protected_terms {
+            Some(path) => load_protected_terms(path)?,
+            None => Vec::new(),
+        },
+        erasure_paths: opts.erasure_paths.clone(),
+        moderation: moderating.then_some(moderation),
+        breaker: BreakerConfig { failure_threshold: opts.breaker_failures, cooldown: Duration::from_millis(opts.breaker_cooldown_ms) },
+        tenants: match &opts.tenants {
+            Some(path) => load_tenants(path)?,
+            None => Vec::new(),
+        },
+        metering,
+        remote_clusters: match &opts.remote_clusters {
+            Some(path) => load_remotes(path)?,
+            None => Vec::new(),
+        },
+        remote_timeout: Duration::from_millis(opts.remote_timeout_ms),
+        admission,
+        degrade,
+        feature_flags: opts.feature_flags.iter().map(|f| parse_flag(f)).collect::<Result<_, _>>()?,
+        upgrade_schema: opts.upgrade_schema,
+        session_pinning: (opts.session_pin_secs > 0).then(|| SessionPinning {
+            ttl: Duration::from_secs(opts.session_pin_secs),
+            max_sessions: opts.max_pinned_sessions,
+        }),
+        journal_max_entries: opts.journal.then_some(opts.journal_max_entries),
+        follow: opts.follow.clone().map(|leader| FollowerConfig {
+            leader,
+            key: opts.replication_key.clone(),
+            state_path: Some(opts.replica_state.clone()),
+            poll_wait: Duration::from_millis(opts.replica_poll_ms),
+            max_lag_entries: opts.replica_max_lag_entries,
+            max_lag: Duration::from_secs(opts.replica_max_lag_secs),
+        }),
+        cluster,
+        query_limits: QueryLimits { max_clauses: opts.max_query_clauses, max_terms: opts.max_query_terms },
+        ..ServiceConfig::default()
+    };
+    let service = Arc::new(SearchService::open(config)?);
+    // Micro-batching for /index, the commit + searcher swap loop and scheduled retention
+    service.spawn_background_tasks();
+    let seed = opts.seed_dir.clone().map(|dir| {
+        let status = Arc::new(SeedStatus::new(dir.clone()));
+        let (service, progress) = (Arc::clone(&service), Arc::clone(&status));
+        tokio::spawn(async move {
+            match service.load_seed_dir(&dir, &progress).await {
+                Ok(loaded) => println!("seed load from {}: {} posts", dir.display(), loaded),
+                Err(e) => eprintln!("seed load from {} failed: {}", dir.display(), e),
             }
         });
+        status
+    });
+
+    let state = web::Data::new(AppState {
+        service,
+        search_limit: Semaphore::new(max_searches),
+        write_limit: Semaphore::new(opts.max_concurrent_writes),
+        max_search_timeout_ms: AtomicU64::new(opts.max_search_timeout_ms),
+        config_file: config_file.map(Mutex::new),
+        jwt: opts.jwt_secret.as_ref().map(|s| JwtVerifier::new(s.as_bytes(), opts.jwt_groups_claim.clone())),
+        admin_key: opts.admin_key.as_deref().map(AdminKey::new),
+        admin_group: opts.jwt_admin_group.clone(),
+        replication_key: opts.replication_key.as_deref().map(AdminKey::new),
+        seed,
+    });
+    if state.admin_key.is_none() && state.admin_group.is_none() && state.jwt.is_none() && !state.service.usage().enabled() {
+        eprintln!("warning: /admin/* is open to anyone; set --admin-key or --jwt-admin-group to protect it");
     }
 
-    println!("Server running at http://127.0.0.1:8080");
-    HttpServer::new(move || {
-        App::new()
-            .app_data(state.clone())
-            .service(add_document)
-            .service(update_document)
-            .service(delete_document)
-            .service(search_document)
-    })
-    .bind(("127.0.0.1", 8080))?
+    let _config_watcher = match &opts.config {
+        Some(path) => {
+            let state = state.clone();
+            Some(settings::watch(path, move || reload_config(&state))?)
+        }
+        None => None,
+    };
+
+    println!(
+        "Server running at http://127.0.0.1:{} ({} workers, {} searches / {} writes in flight)",
+        opts.port, workers, max_searches, opts.max_concurrent_writes
+    );
+    HttpServer::new(move || App::new().app_data(state.clone()).wrap(RequireAdmin).wrap(KeepIngestBodies).configure(routes))
+    .workers(workers)
+    .bind(("127.0.0.1", opts.port))?
     .run()
     .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use actix_web::test;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use tantivy_demo::federation::RemoteCluster;
+    use tantivy_demo::retention::{RetentionAction, RetentionRule};
+    use tantivy_demo::service::ServiceConfig;
+    use tantivy_demo::test_utils::post;
+    use tantivy_demo::usage::TenantConfig;
+
+    const JWT_SECRET: &str = "test secret";
+    const ADMIN_KEY: &str = "admin key";
+
+    /// Server state over a RAM service, with JWTs checked against `JWT_SECRET` when `jwt`.
+    fn state(config: ServiceConfig, jwt: bool, admin_key: bool, admin_group: Option<&str>) -> web::Data<AppState> {
+        let config = ServiceConfig { in_memory: true, sync_commits: true, writer_heap_bytes: 15_000_000, ..config };
+        web::Data::new(AppState {
+            service: Arc::new(SearchService::open(config).unwrap()),
+            search_limit: Semaphore::new(4),
+            write_limit: Semaphore::new(4),
+            max_search_timeout_ms: AtomicU64::new(10_000),
+            config_file: None,
+            jwt: jwt.then(|| JwtVerifier::new(JWT_SECRET.as_bytes(), "groups")),
+            admin_key: admin_key.then(|| AdminKey::new(ADMIN_KEY)),
+            admin_group: admin_group.map(str::to_string),
+            replication_key: None,
+            seed: None,
+        })
+    }
+
+    /// A bearer token for `groups`, signed with `secret`.
+    fn token(secret: &str, groups: &[&str]) -> String {
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(serde_json::json!({ "groups": groups }).to_string());
+        let signed = format!("{}.{}", header, claims);
+        let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes()), signed.as_bytes());
+        format!("Bearer {}.{}", signed, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Status of `GET /admin/flags` with `headers`.
+    async fn admin_status(state: web::Data<AppState>, headers: &[(&str, String)]) -> u16 {
+        guarded_status(state, test::TestRequest::get().uri("/admin/flags"), headers).await
+    }
+
+    /// Status of `req` with `headers`, through [`RequireAdmin`].
+    async fn guarded_status(state: web::Data<AppState>, mut req: test::TestRequest, headers: &[(&str, String)]) -> u16 {
+        let app = test::init_service(App::new().app_data(state).wrap(RequireAdmin).configure(routes)).await;
+        for (name, value) in headers {
+            req = req.insert_header((*name, value.as_str()));
+        }
+        // Refusals come back as errors carrying their response
+        match test::try_call_service(&app, req.to_request()).await {
+            Ok(resp) => resp.status().as_u16(),
+            Err(e) => e.error_response().status().as_u16(),
+        }
+    }
+
+    /// Status and body of `GET uri` with `headers`.
+    async fn get(state: web::Data<AppState>, uri: &str, headers: &[(&str, String)]) -> (u16, String) {
+        let app = test::init_service(App::new().app_data(state).configure(routes)).await;
+        let mut req = test::TestRequest::get().uri(uri);
+        for (name, value) in headers {
+            req = req.insert_header((*name, value.as_str()));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let status = resp.status().as_u16();
+        (status, String::from_utf8_lossy(&test::read_body(resp).await).into_owned())
+    }
+
+    #[actix_web::test]
+    async fn posts_reads_want_a_valid_token_and_hide_restricted_posts_outside_its_groups() {
+        let state = state(ServiceConfig::default(), true, false, None);
+        let restricted = BlogPost { allowed_groups: vec!["eng".to_string()], ..post("restricted", "Closed", "rust plans") };
+        let ops = vec![BatchOp::Index { doc: post("public", "Open", "rust for all") }, BatchOp::Index { doc: restricted }];
+        state.service.apply_batch(ops, &None).await.unwrap();
+
+        let eng = [("Authorization", token(JWT_SECRET, &["eng"]))];
+        let sales = [("Authorization", token(JWT_SECRET, &["sales"]))];
+        let forged = [("Authorization", token("wrong secret", &["eng"]))];
+        for uri in ["/search?q=body:rust", "/latest", "/export", "/doc?id=restricted", "/doc/terms?id=restricted"] {
+            let (status, body) = get(state.clone(), uri, &eng).await;
+            assert_eq!(status, 200, "{} as eng: {}", uri, body);
+            assert!(body.contains("restricted"), "{} as eng: {}", uri, body);
+            assert_eq!(get(state.clone(), uri, &forged).await.0, 401, "{} with a forged token", uri);
+            for headers in [&sales[..], &[]] {
+                let (status, body) = get(state.clone(), uri, headers).await;
+                match uri.starts_with("/doc") {
+                    true => assert_eq!(status, 404, "{} with {:?}: {}", uri, headers, body),
+                    false => assert!(status == 200 && !body.contains("restricted"), "{} with {:?}: {}", uri, headers, body),
+                }
+            }
+        }
+    }
+
+    /// Serves `state` on a free local port and returns its URL.
+    fn serve(state: web::Data<AppState>) -> String {
+        let server = HttpServer::new(move || App::new().app_data(state.clone()).configure(routes)).workers(1).bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        url
+    }
+
+    #[actix_web::test]
+    async fn the_moderation_webhooks_breaker_trips_and_recovers() {
+        let (failing, calls) = (Arc::new(AtomicBool::new(true)), Arc::new(AtomicU64::new(0)));
+        let (webhook_failing, webhook_calls) = (Arc::clone(&failing), Arc::clone(&calls));
+        let webhook = HttpServer::new(move || {
+            let (failing, calls) = (Arc::clone(&webhook_failing), Arc::clone(&webhook_calls));
+            App::new().route("/", web::post().to(move || {
+                calls.fetch_add(1, Ordering::Relaxed);
+                let failing = failing.load(Ordering::Relaxed);
+                async move {
+                    match failing {
+                        true => HttpResponse::InternalServerError().finish(),
+                        false => HttpResponse::Ok().json(serde_json::json!({ "action": "allow" })),
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/", webhook.addrs()[0]);
+        actix_web::rt::spawn(webhook.run());
+
+        let moderation = ModerationConfig { webhook: Some(url), webhook_timeout: Duration::from_secs(5), ..ModerationConfig::default() };
+        let breaker = BreakerConfig { failure_threshold: 2, cooldown: Duration::from_millis(200) };
+        let moderated = state(ServiceConfig { moderation: Some(moderation), breaker, ..ServiceConfig::default() }, false, false, None);
+        let service = &moderated.service;
+        let state_of = || service.stats()["breakers"]["moderation_webhook"]["state"].as_str().unwrap_or_default().to_string();
+
+        for id in ["1", "2"] {
+            assert!(service.index_document(post(id, "Moderated", "rust"), OpType::Upsert).await.is_err());
+        }
+        assert_eq!(state_of(), "open");
+        // A dropped connection fails like a 500 without reaching the stub, so count from here
+        let tripped = calls.load(Ordering::Relaxed);
+        // Open: turned away without a call, and dead-lettered like the failed ones
+        assert!(service.index_document(post("3", "Moderated", "rust"), OpType::Upsert).await.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), tripped);
+        assert_eq!(service.dead_letters().list().len(), 3);
+
+        // Past the cool-down one probe goes out, and its success closes the breaker
+        failing.store(false, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        service.index_document(post("4", "Moderated", "rust"), OpType::Upsert).await.unwrap();
+        assert_eq!((state_of().as_str(), calls.load(Ordering::Relaxed)), ("closed", tripped + 1));
+    }
+
+    #[actix_web::test]
+    async fn remote_legs_search_with_the_callers_token_rather_than_the_configured_one() {
+        let remote = state(ServiceConfig::default(), true, false, None);
+        let restricted = BlogPost { allowed_groups: vec!["eng".to_string()], ..post("restricted", "Closed", "rust plans") };
+        let ops = vec![BatchOp::Index { doc: post("public", "Open", "rust for all") }, BatchOp::Index { doc: restricted }];
+        remote.service.apply_batch(ops, &None).await.unwrap();
+        let service_account = token(JWT_SECRET, &["eng"]).trim_start_matches("Bearer ").to_string();
+        let eu = RemoteCluster { name: "eu".to_string(), endpoint: serve(remote), api_key: None, bearer_token: Some(service_account) };
+        let local = state(ServiceConfig { remote_clusters: vec![eu], ..ServiceConfig::default() }, true, false, None);
+
+        let uri = "/search?q=body:rust&indexes=eu:posts";
+        let (status, body) = get(local.clone(), uri, &[("Authorization", token(JWT_SECRET, &["eng"]))]).await;
+        assert!(status == 200 && body.contains("restricted") && body.contains("public"), "as eng: {}", body);
+        for headers in [&[("Authorization", token(JWT_SECRET, &["sales"]))][..], &[]] {
+            let (status, body) = get(local.clone(), uri, headers).await;
+            assert!(status == 200 && body.contains("public") && !body.contains("restricted"), "with {:?}: {}", headers, body);
+        }
+    }
+
+    /// Status and body of `POST /index` of `post` with `If-None-Match: *` and `headers`.
+    async fn index_unless_present(state: web::Data<AppState>, post: &BlogPost, headers: &[(&str, String)]) -> (u16, String) {
+        let app = test::init_service(App::new().app_data(state).configure(routes)).await;
+        let mut req = test::TestRequest::post().uri("/index").insert_header(("If-None-Match", "*")).set_json(post);
+        for (name, value) in headers {
+            req = req.insert_header((*name, value.as_str()));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let status = resp.status().as_u16();
+        (status, String::from_utf8_lossy(&test::read_body(resp).await).into_owned())
+    }
+
+    #[actix_web::test]
+    async fn if_none_match_treats_posts_the_caller_may_not_see_as_absent() {
+        let grouped = state(ServiceConfig::default(), true, false, None);
+        let restricted = BlogPost { allowed_groups: vec!["eng".to_string()], ..post("restricted", "Closed", "rust plans") };
+        grouped.service.apply_batch(vec![BatchOp::Index { doc: restricted.clone() }], &None).await.unwrap();
+        let (status, body) = index_unless_present(grouped.clone(), &restricted, &[("Authorization", token(JWT_SECRET, &["eng"]))]).await;
+        assert!(status == 412 && body.contains("content_hash"), "as eng: {}", body);
+        let (status, body) = index_unless_present(grouped.clone(), &restricted, &[("Authorization", token(JWT_SECRET, &["sales"]))]).await;
+        assert!(status != 412 && !body.contains("content_hash"), "as sales: {} {}", status, body);
+
+        let tenants = ["a", "b"].map(|t| TenantConfig { name: t.to_string(), api_keys: vec![format!("k-{}", t)], max_docs: None }).to_vec();
+        let tenanted = state(ServiceConfig { tenants, ..ServiceConfig::default() }, false, false, None);
+        let owned = BlogPost { tenant: Some("b".to_string()), ..post("owned", "Mine", "rust plans") };
+        tenanted.service.apply_batch(vec![BatchOp::Index { doc: owned.clone() }], &None).await.unwrap();
+        let (status, body) = index_unless_present(tenanted.clone(), &owned, &[("X-Api-Key", "k-b".to_string())]).await;
+        assert!(status == 412 && body.contains("content_hash"), "as b: {}", body);
+        let (status, body) = index_unless_present(tenanted.clone(), &owned, &[("X-Api-Key", "k-a".to_string())]).await;
+        assert!(status != 412 && !body.contains("content_hash"), "as a: {} {}", status, body);
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_want_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        assert_eq!(admin_status(keyed(), &[]).await, 401);
+        assert_eq!(admin_status(keyed(), &[(ADMIN_KEY_HEADER, "guess".to_string())]).await, 401);
+        assert_eq!(admin_status(keyed(), &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn dead_letter_routes_want_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let admin = [(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())];
+        for (method, uri) in [(Method::GET, "/dlq"), (Method::POST, "/dlq/retry"), (Method::DELETE, "/dlq")] {
+            let req = || test::TestRequest::default().method(method.clone()).uri(uri);
+            assert_eq!(guarded_status(keyed(), req(), &[]).await, 401, "{} {}", method, uri);
+            assert_eq!(guarded_status(keyed(), req(), &admin).await, 200, "{} {}", method, uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn managed_index_changes_want_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let spec = serde_json::json!({ "fields": [{ "name": "sku", "type": "string" }], "id_field": "sku" });
+        let requests = [
+            test::TestRequest::put().uri("/indexes/products").set_json(spec),
+            test::TestRequest::post().uri("/indexes/products/docs").set_json(serde_json::json!({ "sku": "a" })),
+            test::TestRequest::delete().uri("/indexes/products/docs/a"),
+            test::TestRequest::delete().uri("/indexes/products"),
+        ];
+        for req in requests {
+            assert_eq!(guarded_status(keyed(), req, &[]).await, 401);
+        }
+        assert_eq!(guarded_status(keyed(), test::TestRequest::get().uri("/indexes"), &[]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn alias_changes_want_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let set = test::TestRequest::post().uri("/aliases").set_json(serde_json::json!({ "alias": "blog", "index": "posts" }));
+        assert_eq!(guarded_status(keyed(), set, &[]).await, 401);
+        assert_eq!(guarded_status(keyed(), test::TestRequest::delete().uri("/aliases/blog"), &[]).await, 401);
+        assert_eq!(guarded_status(keyed(), test::TestRequest::get().uri("/aliases"), &[]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn registering_analyzers_wants_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let spec = serde_json::json!({ "name": "en_text", "tokenizer": { "type": "simple" }, "filters": [{ "type": "lowercase" }] });
+        let req = || test::TestRequest::post().uri("/analyzers").set_json(&spec);
+        assert_eq!(guarded_status(keyed(), req(), &[]).await, 401);
+        assert_eq!(guarded_status(keyed(), req(), &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await, 200);
+        assert_eq!(guarded_status(keyed(), test::TestRequest::get().uri("/analyzers"), &[]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn running_retention_wants_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let req = || test::TestRequest::post().uri("/retention/run?dry_run=true");
+        assert_eq!(guarded_status(keyed(), req(), &[]).await, 401);
+        assert_eq!(guarded_status(keyed(), req(), &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn reindexing_wants_the_admin_key() {
+        let keyed = || state(ServiceConfig::default(), false, true, None);
+        let req = || test::TestRequest::post().uri("/reindex").set_json(serde_json::json!({}));
+        assert_eq!(guarded_status(keyed(), req(), &[]).await, 401);
+        assert_eq!(guarded_status(keyed(), req(), &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn cluster_routes_want_the_cluster_secret() {
+        let raft = RaftConfig {
+            node_id: "n1".to_string(),
+            members: BTreeMap::from([("n1".to_string(), "http://127.0.0.1:1".to_string())]),
+            state_path: None,
+            secret: Some("cluster secret".to_string()),
+            election_timeout: Duration::from_secs(60),
+            heartbeat: Duration::from_secs(12),
+        };
+        let clustered = || state(ServiceConfig { cluster: Some(raft.clone()), ..ServiceConfig::default() }, false, false, None);
+        let vote = serde_json::json!({ "term": 1, "candidate": "n2", "last_log_index": 0, "last_log_term": 0 });
+        let append = serde_json::json!({ "term": 1, "leader": "n2", "prev_log_index": 0, "prev_log_term": 0, "entries": [], "leader_commit": 0 });
+        let requests = || {
+            [
+                test::TestRequest::post().uri("/cluster/raft/vote").set_json(&vote),
+                test::TestRequest::post().uri("/cluster/raft/append").set_json(&append),
+                test::TestRequest::post().uri("/cluster/metadata").set_json(serde_json::json!({ "op": "set_primary", "node": "n1" })),
+            ]
+        };
+        for req in requests() {
+            assert_eq!(guarded_status(clustered(), req, &[]).await, 401);
+        }
+        for req in requests() {
+            assert_eq!(guarded_status(clustered(), req, &[(CLUSTER_SECRET_HEADER, "guess".to_string())]).await, 401);
+        }
+        // Metadata changes need a leader, which a node without its election loop never becomes
+        for req in requests().into_iter().take(2) {
+            assert_eq!(guarded_status(clustered(), req, &[(CLUSTER_SECRET_HEADER, "cluster secret".to_string())]).await, 200);
+        }
+    }
+
+    #[actix_web::test]
+    async fn replication_reads_want_the_replication_or_admin_key() {
+        let journaled = || state(ServiceConfig { journal_max_entries: Some(100), ..ServiceConfig::default() }, false, true, None);
+        for uri in ["/replication/snapshot", "/replication/journal?from=1"] {
+            assert_eq!(get(journaled(), uri, &[]).await.0, 401, "{}", uri);
+            assert_eq!(get(journaled(), uri, &[(REPLICATION_KEY_HEADER, "guess".to_string())]).await.0, 401, "{}", uri);
+            assert_eq!(get(journaled(), uri, &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await.0, 200, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_follower_loads_a_snapshots_archived_posts_into_its_archive() {
+        let archive_old = RetentionRule { name: "old".to_string(), query: "*".to_string(), older_than_days: 1, action: RetentionAction::Archive };
+        let leader = state(ServiceConfig { journal_max_entries: Some(100), retention_rules: vec![archive_old], ..ServiceConfig::default() }, false, false, None);
+        let old = BlogPost { create_at: Some(1_000_000), ..post("old", "Old", "rust then") };
+        leader.service.apply_batch(vec![BatchOp::Index { doc: old }, BatchOp::Index { doc: post("new", "New", "rust now") }], &None).await.unwrap();
+        leader.service.run_retention(false).unwrap();
+
+        let follow = FollowerConfig {
+            leader: serve(leader),
+            key: None,
+            state_path: None,
+            poll_wait: Duration::from_millis(10),
+            max_lag_entries: 100,
+            max_lag: Duration::from_secs(60),
+        };
+        let config = ServiceConfig { in_memory: true, sync_commits: true, writer_heap_bytes: 15_000_000, follow: Some(follow), ..ServiceConfig::default() };
+        let follower = SearchService::open(config).unwrap();
+        assert_eq!(follower.sync_replica().await.unwrap(), 2);
+        let f_id = follower.schema().get_field("id").unwrap();
+        let tiers = |include_archive| {
+            let req = SearchRequest { q: "rust".to_string(), limit: 10, include_archive, ..SearchRequest::default() };
+            let hits = follower.search(&req).unwrap().hits;
+            hits.iter().map(|hit| (hit.doc.get_first(f_id).and_then(|v| v.as_str()).unwrap_or_default().to_string(), hit.tier)).collect::<Vec<_>>()
+        };
+        assert_eq!(tiers(false), [("new".to_string(), Tier::Hot)]);
+        let mut all = tiers(true);
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all, [("new".to_string(), Tier::Hot), ("old".to_string(), Tier::Archive)]);
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_want_a_token_in_the_admin_group() {
+        let grouped = || state(ServiceConfig::default(), true, false, Some("ops"));
+        assert_eq!(admin_status(grouped(), &[]).await, 401);
+        assert_eq!(admin_status(grouped(), &[("Authorization", token("wrong secret", &["ops"]))]).await, 401);
+        assert_eq!(admin_status(grouped(), &[("Authorization", token(JWT_SECRET, &["staff"]))]).await, 403);
+        assert_eq!(admin_status(grouped(), &[("Authorization", token(JWT_SECRET, &["staff", "ops"]))]).await, 200);
+        // The key doesn't stand in for a group when no key is configured
+        assert_eq!(admin_status(grouped(), &[(ADMIN_KEY_HEADER, ADMIN_KEY.to_string())]).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_are_closed_when_auth_is_on_but_admin_is_not_configured() {
+        let tenants = vec![TenantConfig { name: "a".to_string(), api_keys: vec!["k-a".to_string()], max_docs: None }];
+        assert_eq!(admin_status(state(ServiceConfig { tenants, ..ServiceConfig::default() }, false, false, None), &[]).await, 403);
+        assert_eq!(admin_status(state(ServiceConfig::default(), true, false, None), &[]).await, 403);
+        assert_eq!(admin_status(state(ServiceConfig::default(), false, false, None), &[]).await, 200);
+    }
+}
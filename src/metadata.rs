@@ -0,0 +1,110 @@
+//! Cluster metadata kept consistent across nodes by the [`raft`](crate::raft) log: the member
+//! nodes, which of them is primary, alias definitions and shard assignments. Every change is
+//! a [`MetadataCommand`] that becomes part of the state once a majority of members has
+//! stored it, so nodes agree on it without hand-edited config on each of them.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// A change to the cluster metadata, replicated through the raft log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MetadataCommand {
+    /// Appended by every new raft leader so entries of earlier terms get committed
+    Noop,
+    /// Adds a node, or changes its URL; it votes as soon as the entry is in the log
+    AddMember { node: String, url: String },
+    RemoveMember { node: String },
+    SetPrimary { node: String },
+    SetAlias { alias: String, index: String },
+    RemoveAlias { alias: String },
+    /// Nodes holding `shard`, the first being its primary
+    AssignShard { shard: u32, nodes: Vec<String> },
+    UnassignShard { shard: u32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClusterMetadata {
+    /// Node id -> base URL
+    pub members: BTreeMap<String, String>,
+    pub primary: Option<String>,
+    /// Alias -> index name
+    pub aliases: BTreeMap<String, String>,
+    /// Shard -> nodes holding it, primary first
+    pub shards: BTreeMap<u32, Vec<String>>,
+}
+
+impl ClusterMetadata {
+    /// Why `command` can't apply to this state, if it can't. Checked before a command is
+    /// proposed; applying never fails, so every node ends up with the same state.
+    pub fn check(&self, command: &MetadataCommand) -> Result<(), String> {
+        let member = |node: &str| match self.members.contains_key(node) {
+            true => Ok(()),
+            false => Err(format!("unknown node: {}", node)),
+        };
+        match command {
+            MetadataCommand::Noop => Ok(()),
+            MetadataCommand::AddMember { node, url } if node.trim().is_empty() || url.trim().is_empty() => {
+                Err("node and url must not be empty".to_string())
+            }
+            MetadataCommand::AddMember { .. } => Ok(()),
+            MetadataCommand::RemoveMember { node } => {
+                member(node)?;
+                if self.members.len() == 1 {
+                    return Err("can't remove the last member".to_string());
+                }
+                Ok(())
+            }
+            MetadataCommand::SetPrimary { node } => member(node),
+            MetadataCommand::SetAlias { alias, index } if alias.trim().is_empty() || index.trim().is_empty() => {
+                Err("alias and index must not be empty".to_string())
+            }
+            MetadataCommand::SetAlias { .. } => Ok(()),
+            MetadataCommand::RemoveAlias { alias } => match self.aliases.contains_key(alias) {
+                true => Ok(()),
+                false => Err(format!("unknown alias: {}", alias)),
+            },
+            MetadataCommand::AssignShard { nodes, .. } if nodes.is_empty() => Err("a shard needs at least one node".to_string()),
+            MetadataCommand::AssignShard { nodes, .. } => nodes.iter().try_for_each(|n| member(n)),
+            MetadataCommand::UnassignShard { .. } => Ok(()),
+        }
+    }
+
+    pub fn apply(&mut self, command: &MetadataCommand) {
+        match command {
+            MetadataCommand::Noop => {}
+            MetadataCommand::AddMember { node, url } => {
+                self.members.insert(node.clone(), url.clone());
+            }
+            MetadataCommand::RemoveMember { node } => {
+                self.members.remove(node);
+                if self.primary.as_ref() == Some(node) {
+                    self.primary = None;
+                }
+                for nodes in self.shards.values_mut() {
+                    nodes.retain(|n| n != node);
+                }
+                self.shards.retain(|_, nodes| !nodes.is_empty());
+            }
+            MetadataCommand::SetPrimary { node } => self.primary = Some(node.clone()),
+            MetadataCommand::SetAlias { alias, index } => {
+                self.aliases.insert(alias.clone(), index.clone());
+            }
+            MetadataCommand::RemoveAlias { alias } => {
+                self.aliases.remove(alias);
+            }
+            MetadataCommand::AssignShard { shard, nodes } => {
+                self.shards.insert(*shard, nodes.clone());
+            }
+            MetadataCommand::UnassignShard { shard } => {
+                self.shards.remove(shard);
+            }
+        }
+    }
+
+    /// URL of the primary node, when one is set.
+    pub fn primary_url(&self) -> Option<&str> {
+        self.primary.as_ref().and_then(|p| self.members.get(p)).map(String::as_str)
+    }
+}
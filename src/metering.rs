@@ -0,0 +1,147 @@
+//! Periodic per-tenant usage records for charging costs back to the teams using the service.
+//! Every `interval` one [`MeteringRecord`] per tenant is appended as NDJSON to a file and/or
+//! POSTed to an HTTP endpoint (e.g. an object-store upload URL or a Kafka REST proxy).
+//!
+//! Counters cover the period since the last emission all sinks accepted. When a sink fails,
+//! the next emission covers the longer period under the same `record_id`, so consumers keep the
+//! last record per `record_id`.
+//!
+//! The endpoint sits behind a [circuit breaker](crate::breaker); while it is open emissions
+//! fail without POSTing, and the period keeps growing until one gets through.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::breaker::{BreakerConfig, CircuitBreaker};
+use crate::service::SearchService;
+use crate::usage::TenantUsage;
+use crate::now_secs;
+
+/// Bumped whenever a field of [`MeteringRecord`] changes meaning or goes away.
+pub const METERING_SCHEMA_VERSION: u32 = 1;
+
+/// One tenant's usage over `[period_start, period_end)`, in seconds since the epoch.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MeteringRecord {
+    pub schema_version: u32,
+    /// `<tenant>:<period_start>`
+    pub record_id: String,
+    pub tenant: String,
+    pub period_start: i64,
+    pub period_end: i64,
+    /// Searches during the period
+    pub searches: u64,
+    /// Posts accepted during the period, updates included
+    pub docs_indexed: u64,
+    /// JSON size of the posts accepted during the period
+    pub bytes_indexed: u64,
+    /// Committed posts at `period_end`
+    pub docs: u64,
+    /// The tenant's share of the index size at `period_end`
+    pub bytes_stored: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MeteringConfig {
+    /// NDJSON file records are appended to
+    pub path: Option<PathBuf>,
+    /// Endpoint every batch of records is POSTed to as NDJSON
+    pub url: Option<String>,
+    pub interval: Duration,
+}
+
+/// Counters as of the last accepted emission: period start and per tenant
+/// `(searches, docs_indexed, bytes_indexed)`.
+type Checkpoint = (i64, HashMap<String, (u64, u64, u64)>);
+
+pub struct Meter {
+    config: MeteringConfig,
+    http: reqwest::Client,
+    breaker: CircuitBreaker,
+    last: Mutex<Checkpoint>,
+}
+
+impl Meter {
+    pub fn new(config: MeteringConfig, breaker: BreakerConfig) -> Self {
+        Meter { config, http: reqwest::Client::new(), breaker: CircuitBreaker::new(breaker), last: Mutex::new((now_secs(), HashMap::new())) }
+    }
+
+    /// The breaker of the endpoint, when records are POSTed.
+    pub fn breaker(&self) -> Option<&CircuitBreaker> {
+        self.config.url.as_ref().map(|_| &self.breaker)
+    }
+
+    pub fn config(&self) -> &MeteringConfig {
+        &self.config
+    }
+
+    /// Records for the period ending `now`, and the checkpoint to [`commit`](Self::commit)
+    /// once they are emitted.
+    fn records(&self, usage: &[TenantUsage], now: i64) -> (Vec<MeteringRecord>, Checkpoint) {
+        let last = self.last.lock().unwrap_or_else(|p| p.into_inner());
+        let (period_start, seen) = &*last;
+        let mut counters = HashMap::new();
+        let records = usage
+            .iter()
+            .map(|u| {
+                let (searches, docs_indexed, bytes_indexed) = seen.get(&u.tenant).copied().unwrap_or_default();
+                counters.insert(u.tenant.clone(), (u.searches, u.docs_indexed, u.bytes_indexed));
+                MeteringRecord {
+                    schema_version: METERING_SCHEMA_VERSION,
+                    record_id: format!("{}:{}", u.tenant, period_start),
+                    tenant: u.tenant.clone(),
+                    period_start: *period_start,
+                    period_end: now,
+                    searches: u.searches.saturating_sub(searches),
+                    docs_indexed: u.docs_indexed.saturating_sub(docs_indexed),
+                    bytes_indexed: u.bytes_indexed.saturating_sub(bytes_indexed),
+                    docs: u.docs,
+                    bytes_stored: u.storage_bytes,
+                }
+            })
+            .collect();
+        (records, (now, counters))
+    }
+
+    fn commit(&self, checkpoint: Checkpoint) {
+        *self.last.lock().unwrap_or_else(|p| p.into_inner()) = checkpoint;
+    }
+
+    async fn emit(&self, records: &[MeteringRecord]) -> anyhow::Result<()> {
+        let mut ndjson = String::new();
+        for record in records {
+            ndjson.push_str(&serde_json::to_string(record)?);
+            ndjson.push('\n');
+        }
+        if let Some(path) = &self.config.path {
+            let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+            file.write_all(ndjson.as_bytes())?;
+        }
+        if let Some(url) = &self.config.url {
+            if !self.breaker.allow() {
+                anyhow::bail!("metering endpoint: circuit open after repeated failures");
+            }
+            let sent = async { self.http.post(url).header("content-type", "application/x-ndjson").body(ndjson).send().await?.error_for_status() }.await;
+            self.breaker.record(sent.is_ok());
+            sent?;
+        }
+        Ok(())
+    }
+}
+
+impl SearchService {
+    /// Emits one record per tenant for the period since the last accepted emission and
+    /// returns how many were written. A no-op without metering configured.
+    pub async fn emit_metering(&self) -> anyhow::Result<usize> {
+        let Some(meter) = &self.meter else { return Ok(0) };
+        let (records, checkpoint) = meter.records(&self.usage_report()?, now_secs());
+        meter.emit(&records).await?;
+        meter.commit(checkpoint);
+        Ok(records.len())
+    }
+}
@@ -0,0 +1,113 @@
+//! Versioned formats for what the service keeps on disk besides tantivy's segments: the
+//! custom analyzers, the dead-letter queue, the raft state and a follower's position.
+//!
+//! The version each of these files was last written in is recorded in a small manifest
+//! (`.tantivy_formats.json`). On startup, before anything is opened, every file behind the
+//! format this binary writes is brought up to date one migration at a time: the original is
+//! kept next to it as `<file>.v<from>.bak`, the migrated file replaces it atomically, and the
+//! manifest is updated after each step, so an interrupted upgrade resumes where it stopped.
+//! A file recorded in a newer format than this binary knows stops startup instead of being
+//! misread.
+//!
+//! Files from before the manifest existed are taken to be in version 1. Changing a format
+//! means bumping its version in [`CURRENT_VERSIONS`] and adding the [`Migration`] from the
+//! previous one to [`MIGRATIONS`].
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// Format each store is written in by this binary.
+pub const CURRENT_VERSIONS: &[(&str, u32)] = &[("analyzers", 1), ("dead_letters", 1), ("raft_state", 1), ("replica_position", 1)];
+
+/// Rewrites a store's file from one format version to the next.
+pub struct Migration {
+    pub store: &'static str,
+    /// The version migrated from, to `from + 1`
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(String) -> anyhow::Result<String>,
+}
+
+/// Every migration there is, oldest first.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// A file kept in one of the versioned formats.
+pub struct Store {
+    pub name: &'static str,
+    pub path: PathBuf,
+}
+
+/// One migration run on startup.
+#[derive(Serialize, Debug, Clone)]
+pub struct AppliedMigration {
+    pub store: String,
+    pub from: u32,
+    pub to: u32,
+    pub description: String,
+}
+
+fn current_version(store: &str) -> anyhow::Result<u32> {
+    match CURRENT_VERSIONS.iter().find(|(name, _)| *name == store) {
+        Some((_, version)) => Ok(*version),
+        None => anyhow::bail!("no format version for store {}", store),
+    }
+}
+
+fn write_atomically(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Brings every store to the format this binary writes and records it in the manifest at
+/// `manifest`, returning the migrations run.
+pub fn migrate(manifest: &Path, stores: &[Store]) -> anyhow::Result<Vec<AppliedMigration>> {
+    let mut versions: BTreeMap<String, u32> = match manifest.exists() {
+        true => serde_json::from_str(&std::fs::read_to_string(manifest)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", manifest.display(), e))?,
+        false => BTreeMap::new(),
+    };
+    let mut applied = Vec::new();
+    for store in stores {
+        let current = current_version(store.name)?;
+        if !store.path.exists() {
+            // Written from scratch in the current format
+            versions.insert(store.name.to_string(), current);
+            continue;
+        }
+        let mut version = versions.get(store.name).copied().unwrap_or(1);
+        if version > current {
+            anyhow::bail!(
+                "{} is in format version {} of {}, newer than this binary's {}; upgrade the binary",
+                store.path.display(), version, store.name, current
+            );
+        }
+        while version < current {
+            let Some(migration) = MIGRATIONS.iter().find(|m| m.store == store.name && m.from == version) else {
+                anyhow::bail!("no migration for {} from format version {}", store.name, version);
+            };
+            let contents = std::fs::read_to_string(&store.path)?;
+            let backup = PathBuf::from(format!("{}.v{}.bak", store.path.display(), version));
+            std::fs::write(&backup, &contents)?;
+            let migrated = (migration.apply)(contents)
+                .map_err(|e| anyhow::anyhow!("migrating {} from version {}: {}", store.path.display(), version, e))?;
+            write_atomically(&store.path, &migrated)?;
+            version += 1;
+            versions.insert(store.name.to_string(), version);
+            write_atomically(manifest, &serde_json::to_string_pretty(&versions)?)?;
+            log::info!("{}: migrated {} to format version {}: {}", store.path.display(), store.name, version, migration.description);
+            applied.push(AppliedMigration {
+                store: store.name.to_string(),
+                from: version - 1,
+                to: version,
+                description: migration.description.to_string(),
+            });
+        }
+        versions.insert(store.name.to_string(), version);
+    }
+    write_atomically(manifest, &serde_json::to_string_pretty(&versions)?)?;
+    Ok(applied)
+}
@@ -0,0 +1,253 @@
+//! Content moderation of posts before they are indexed: regex rules and an optional webhook
+//! decide whether a post is rejected, quarantined with `status=flagged` or tagged.
+//!
+//! Rejected posts are dead-lettered with source `moderation`, so they show up in `/dlq` and
+//! are moderated again when retried. Every non-`allow` outcome is also kept in a short audit
+//! log.
+//!
+//! The webhook sits behind a [circuit breaker](crate::breaker): while it is open, posts are
+//! refused with 503 and dead-lettered straight away instead of waiting for the timeout.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::breaker::{BreakerConfig, CircuitBreaker};
+use crate::schema::BlogPost;
+use crate::now_secs;
+
+/// Moderation outcomes kept for `/moderation`.
+const AUDIT_LOG_LEN: usize = 100;
+
+/// Status given to quarantined posts.
+pub const FLAGGED_STATUS: &str = "flagged";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationAction {
+    Allow,
+    Reject,
+    /// Index with `status=flagged`
+    Flag,
+    /// Index with extra tags
+    Tag,
+}
+
+/// A rule: posts where `pattern` matches one of `fields` are subject to `action`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModerationRule {
+    pub name: String,
+    pub pattern: String,
+    /// Any of `title`, `body` and `tags`
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>,
+    pub action: ModerationAction,
+    /// Tags added by `tag` rules
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_fields() -> Vec<String> {
+    vec!["title".to_string(), "body".to_string()]
+}
+
+/// Reads a JSON array of rules.
+pub fn load_rules(path: &PathBuf) -> anyhow::Result<Vec<ModerationRule>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModerationConfig {
+    pub rules: Vec<ModerationRule>,
+    /// Receives each post as JSON and answers with a [`WebhookVerdict`]
+    pub webhook: Option<String>,
+    pub webhook_timeout: Duration,
+}
+
+/// Webhook response body: `{"action": "flag", "reason": "spam score 0.97"}`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookVerdict {
+    pub action: ModerationAction,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModerationOutcome {
+    pub id: String,
+    pub at: i64,
+    pub action: ModerationAction,
+    /// Names of the matching rules, plus `webhook` or its reason
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ModerationError {
+    Rejected(String),
+    /// The webhook couldn't be reached, answered garbage or is behind an open breaker; the
+    /// post isn't indexed
+    Unavailable(String),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct ModerationCounts {
+    pub checked: u64,
+    pub rejected: u64,
+    pub flagged: u64,
+    pub tagged: u64,
+}
+
+pub struct Moderator {
+    rules: Vec<(Regex, ModerationRule)>,
+    webhook: Option<(reqwest::Client, String, CircuitBreaker)>,
+    audit: Mutex<VecDeque<ModerationOutcome>>,
+    checked: AtomicU64,
+    rejected: AtomicU64,
+    flagged: AtomicU64,
+    tagged: AtomicU64,
+}
+
+impl Moderator {
+    /// Compiles the rules; invalid patterns and unknown fields are errors.
+    pub fn new(config: &ModerationConfig, breaker: BreakerConfig) -> anyhow::Result<Self> {
+        let mut rules = Vec::new();
+        for rule in &config.rules {
+            if let Some(field) = rule.fields.iter().find(|f| !matches!(f.as_str(), "title" | "body" | "tags")) {
+                anyhow::bail!("moderation rule {}: unknown field {}", rule.name, field);
+            }
+            let pattern = Regex::new(&rule.pattern).map_err(|e| anyhow::anyhow!("moderation rule {}: {}", rule.name, e))?;
+            rules.push((pattern, rule.clone()));
+        }
+        let webhook = match &config.webhook {
+            Some(url) => Some((reqwest::Client::builder().timeout(config.webhook_timeout).build()?, url.clone(), CircuitBreaker::new(breaker))),
+            None => None,
+        };
+        Ok(Moderator {
+            rules,
+            webhook,
+            audit: Mutex::new(VecDeque::with_capacity(AUDIT_LOG_LEN)),
+            checked: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            flagged: AtomicU64::new(0),
+            tagged: AtomicU64::new(0),
+        })
+    }
+
+    pub fn rules(&self) -> Vec<ModerationRule> {
+        self.rules.iter().map(|(_, rule)| rule.clone()).collect()
+    }
+
+    pub fn webhook(&self) -> Option<&str> {
+        self.webhook.as_ref().map(|(_, url, _)| url.as_str())
+    }
+
+    pub fn webhook_breaker(&self) -> Option<&CircuitBreaker> {
+        self.webhook.as_ref().map(|(_, _, breaker)| breaker)
+    }
+
+    pub fn counts(&self) -> ModerationCounts {
+        ModerationCounts {
+            checked: self.checked.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            flagged: self.flagged.load(Ordering::Relaxed),
+            tagged: self.tagged.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Most recent outcomes other than `allow`, oldest first.
+    pub fn recent(&self) -> Vec<ModerationOutcome> {
+        self.audit.lock().unwrap_or_else(|p| p.into_inner()).iter().cloned().collect()
+    }
+
+    async fn ask_webhook(&self, post: &BlogPost) -> Result<Option<WebhookVerdict>, ModerationError> {
+        let Some((http, url, breaker)) = &self.webhook else { return Ok(None) };
+        if !breaker.allow() {
+            return Err(ModerationError::Unavailable("moderation webhook: circuit open after repeated failures".to_string()));
+        }
+        let verdict: Result<WebhookVerdict, reqwest::Error> = async { http.post(url).json(post).send().await?.error_for_status()?.json().await }.await;
+        breaker.record(verdict.is_ok());
+        verdict.map(Some).map_err(|e| ModerationError::Unavailable(format!("moderation webhook: {}", e)))
+    }
+
+    /// Runs the rules, then the webhook, and applies the strictest outcome: `reject` over
+    /// `flag` over `tag`. Tags from every matching `tag` verdict are added.
+    pub async fn moderate(&self, mut post: BlogPost) -> Result<BlogPost, ModerationError> {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        let mut action = ModerationAction::Allow;
+        let mut reasons = Vec::new();
+        let mut tags = Vec::new();
+        let mut apply = |verdict: ModerationAction, reason: String, extra: &[String]| {
+            if verdict == ModerationAction::Allow {
+                return;
+            }
+            if verdict == ModerationAction::Tag {
+                tags.extend(extra.iter().cloned());
+            }
+            action = strictest(action, verdict);
+            reasons.push(reason);
+        };
+        for (pattern, rule) in &self.rules {
+            let hit = rule.fields.iter().any(|field| match field.as_str() {
+                "title" => pattern.is_match(&post.title),
+                "body" => pattern.is_match(&post.body),
+                _ => post.tags.iter().any(|t| pattern.is_match(t)),
+            });
+            if hit {
+                apply(rule.action, rule.name.clone(), &rule.tags);
+            }
+        }
+        if let Some(verdict) = self.ask_webhook(&post).await? {
+            apply(verdict.action, verdict.reason.unwrap_or_else(|| "webhook".to_string()), &verdict.tags);
+        }
+
+        match action {
+            ModerationAction::Allow => return Ok(post),
+            ModerationAction::Reject => self.rejected.fetch_add(1, Ordering::Relaxed),
+            ModerationAction::Flag => self.flagged.fetch_add(1, Ordering::Relaxed),
+            ModerationAction::Tag => self.tagged.fetch_add(1, Ordering::Relaxed),
+        };
+        tags.retain(|t| !post.tags.contains(t));
+        tags.sort();
+        tags.dedup();
+        let outcome = ModerationOutcome { id: post.id.clone(), at: now_secs(), action, reasons, tags: tags.clone() };
+        let message = outcome.reasons.join(", ");
+        {
+            let mut audit = self.audit.lock().unwrap_or_else(|p| p.into_inner());
+            if audit.len() == AUDIT_LOG_LEN {
+                audit.pop_front();
+            }
+            audit.push_back(outcome);
+        }
+        if action == ModerationAction::Reject {
+            return Err(ModerationError::Rejected(message));
+        }
+        if action == ModerationAction::Flag {
+            post.status = FLAGGED_STATUS.to_string();
+        }
+        post.tags.extend(tags);
+        Ok(post)
+    }
+}
+
+fn strictest(a: ModerationAction, b: ModerationAction) -> ModerationAction {
+    let rank = |action| match action {
+        ModerationAction::Allow => 0,
+        ModerationAction::Tag => 1,
+        ModerationAction::Flag => 2,
+        ModerationAction::Reject => 3,
+    };
+    if rank(b) > rank(a) {
+        b
+    } else {
+        a
+    }
+}
@@ -0,0 +1,327 @@
+//! Nested objects indexed as doc blocks. For every configured nested path (e.g. `snippet`),
+//! each object of the post's `features.snippet` array is also indexed as a child document right
+//! before its post, in the same segment:
+//!
+//! ```text
+//! [child snippet #0] [child snippet #1] [post]
+//! ```
+//!
+//! A child carries the post's `id` (so deleting or updating a post by id replaces the whole
+//! block), its path in `_nested` and the object alone under `features.<path>`. Queries on one
+//! child therefore only see fields of the same object, which the flattened `features` of the
+//! post can't tell apart. [`ToParentQuery`] maps matching children to the post that follows
+//! them; every other query is restricted to posts with [`posts_only`].
+//!
+//! Block order only holds on an unsorted index, so nested paths can't be combined with
+//! `sort_by_create_at`.
+
+use tantivy::columnar::Column;
+use tantivy::indexer::UserOperation;
+use tantivy::query::{BooleanQuery, EmptyScorer, EnableScoring, ExistsQuery, Explanation, Occur, Query, Scorer, TermQuery, Weight};
+use tantivy::schema::{IndexRecordOption, Schema};
+use tantivy::{DocAddress, DocId, DocSet, IndexWriter, Score, Searcher, SegmentReader, TantivyDocument, TantivyError, Term, TERMINATED};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::schema::BlogPost;
+
+/// Posts with a child under `path` matching `q` (see
+/// [`parse_nested_query`](crate::query::parse_nested_query)).
+#[derive(Debug, Clone)]
+pub struct NestedQuery {
+    pub path: String,
+    pub q: String,
+}
+
+/// Marks child documents with the nested path they were taken from.
+pub const NESTED_FIELD: &str = "_nested";
+
+/// Restricts `query` to posts, leaving out nested children. A no-op on indexes created before
+/// nested documents existed.
+pub fn posts_only(schema: &Schema, query: Box<dyn Query>) -> Box<dyn Query> {
+    if schema.get_field(NESTED_FIELD).is_err() {
+        return query;
+    }
+    let children: Box<dyn Query> = Box::new(ExistsQuery::new_exists_query(NESTED_FIELD.to_string()));
+    Box::new(BooleanQuery::new(vec![(Occur::Must, query), (Occur::MustNot, children)]))
+}
+
+/// Fails when `schema` can't hold nested documents.
+pub fn check_nested_schema(schema: &Schema) -> ServiceResult<()> {
+    match schema.get_field(NESTED_FIELD) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ServiceError::Invalid(format!(
+            "index was created without the {} field; rebuild it to use nested paths",
+            NESTED_FIELD
+        ))),
+    }
+}
+
+/// One child document per object found at `features.<path>` for each of `paths`. Non-object
+/// entries are skipped; a single object counts as a one-element array.
+pub fn nested_documents(schema: &Schema, post: &BlogPost, paths: &[String]) -> Vec<TantivyDocument> {
+    let Ok(f_nested) = schema.get_field(NESTED_FIELD) else { return Vec::new() };
+    let f_id = schema.get_field("id").unwrap();
+    let f_features = schema.get_field("features").unwrap();
+    let mut children = Vec::new();
+    for path in paths {
+        let objects = match post.features.get(path) {
+            Some(serde_json::Value::Array(items)) => items.iter().collect(),
+            Some(object @ serde_json::Value::Object(_)) => vec![object],
+            _ => Vec::new(),
+        };
+        for object in objects.into_iter().filter(|o| o.is_object()) {
+            let mut child = TantivyDocument::default();
+            child.add_text(f_id, &post.id);
+            child.add_text(f_nested, path);
+            let mut features = std::collections::BTreeMap::new();
+            features.insert(path.clone(), tantivy::schema::OwnedValue::from(object.clone()));
+            child.add_object(f_features, features);
+            children.push(child);
+        }
+    }
+    children
+}
+
+/// Adds documents that must stay contiguous, children first and the post last.
+pub fn add_block(writer: &IndexWriter, block: Vec<TantivyDocument>) -> tantivy::Result<u64> {
+    if block.len() == 1 {
+        return writer.add_document(block.into_iter().next().unwrap());
+    }
+    writer.run(block.into_iter().map(UserOperation::Add))
+}
+
+/// Splits addresses in doc order into doc blocks, each closed by its post.
+pub fn blocks(searcher: &Searcher, mut addrs: Vec<DocAddress>) -> tantivy::Result<Vec<Vec<DocAddress>>> {
+    addrs.sort();
+    let marks = searcher.segment_readers().iter().map(ChildMarks::open).collect::<tantivy::Result<Vec<_>>>()?;
+    let mut blocks = Vec::new();
+    let mut current = Vec::new();
+    for addr in addrs {
+        current.push(addr);
+        if !marks[addr.segment_ord as usize].is_child(addr.doc_id) {
+            blocks.push(std::mem::take(&mut current));
+        }
+    }
+    Ok(blocks)
+}
+
+/// Children under `path` matching `child`.
+pub fn child_query(schema: &Schema, path: &str, child: Box<dyn Query>) -> ServiceResult<Box<dyn Query>> {
+    check_nested_schema(schema)?;
+    let f_nested = schema.get_field(NESTED_FIELD).unwrap();
+    let on_path: Box<dyn Query> = Box::new(TermQuery::new(Term::from_field_text(f_nested, path), IndexRecordOption::Basic));
+    Ok(Box::new(BooleanQuery::new(vec![(Occur::Must, child), (Occur::Must, on_path)])))
+}
+
+/// Whether each doc of a segment is a nested child.
+struct ChildMarks(Option<Column<u64>>);
+
+impl ChildMarks {
+    fn open(reader: &SegmentReader) -> tantivy::Result<Self> {
+        Ok(ChildMarks(reader.fast_fields().str(NESTED_FIELD)?.map(|c| c.ords().clone())))
+    }
+
+    fn is_child(&self, doc: DocId) -> bool {
+        self.0.as_ref().is_some_and(|c| c.first(doc).is_some())
+    }
+
+    /// The post closing the block `doc` belongs to.
+    fn parent_of(&self, mut doc: DocId, max_doc: DocId) -> DocId {
+        while doc < max_doc && self.is_child(doc) {
+            doc += 1;
+        }
+        if doc < max_doc {
+            doc
+        } else {
+            TERMINATED
+        }
+    }
+
+    /// First doc of the block closed by `parent`.
+    fn block_start(&self, parent: DocId) -> DocId {
+        let mut start = parent;
+        while start > 0 && self.is_child(start - 1) {
+            start -= 1;
+        }
+        start
+    }
+}
+
+/// Posts with at least one nested child matching `child` (see [`child_query`]), scored by the
+/// best matching child.
+#[derive(Debug)]
+pub struct ToParentQuery {
+    pub child: Box<dyn Query>,
+}
+
+impl Clone for ToParentQuery {
+    fn clone(&self) -> Self {
+        ToParentQuery { child: self.child.box_clone() }
+    }
+}
+
+impl Query for ToParentQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(ToParentWeight { child: self.child.weight(enable_scoring)? }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        self.child.query_terms(visitor);
+    }
+}
+
+struct ToParentWeight {
+    child: Box<dyn Weight>,
+}
+
+impl Weight for ToParentWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let marks = ChildMarks::open(reader)?;
+        if marks.0.is_none() {
+            return Ok(Box::new(EmptyScorer));
+        }
+        let child = self.child.scorer(reader, boost)?;
+        let mut scorer = ToParentScorer { child, marks, max_doc: reader.max_doc(), doc: 0, score: 0.0 };
+        scorer.advance();
+        Ok(Box::new(scorer))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!("Document #({}) does not match", doc)));
+        }
+        Ok(Explanation::new("best matching nested child", scorer.score()))
+    }
+}
+
+struct ToParentScorer {
+    child: Box<dyn Scorer>,
+    marks: ChildMarks,
+    max_doc: DocId,
+    doc: DocId,
+    score: Score,
+}
+
+impl DocSet for ToParentScorer {
+    fn advance(&mut self) -> DocId {
+        let first = self.child.doc();
+        if first == TERMINATED {
+            self.doc = TERMINATED;
+            return TERMINATED;
+        }
+        let parent = self.marks.parent_of(first, self.max_doc);
+        // Every other matching child of the same block only adds to the score
+        self.score = Score::MIN;
+        while self.child.doc() < parent {
+            self.score = self.score.max(self.child.score());
+            self.child.advance();
+        }
+        self.doc = parent;
+        parent
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.child.size_hint()
+    }
+}
+
+impl Scorer for ToParentScorer {
+    fn score(&mut self) -> Score {
+        self.score
+    }
+}
+
+/// Posts matching `parent` together with all their nested children, for operations that
+/// must move or remove whole blocks (retention).
+#[derive(Debug)]
+pub struct WithChildrenQuery {
+    pub parent: Box<dyn Query>,
+}
+
+impl Clone for WithChildrenQuery {
+    fn clone(&self) -> Self {
+        WithChildrenQuery { parent: self.parent.box_clone() }
+    }
+}
+
+impl Query for WithChildrenQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(WithChildrenWeight { parent: self.parent.weight(enable_scoring)? }))
+    }
+}
+
+struct WithChildrenWeight {
+    parent: Box<dyn Weight>,
+}
+
+impl Weight for WithChildrenWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let marks = ChildMarks::open(reader)?;
+        let parent = self.parent.scorer(reader, boost)?;
+        if marks.0.is_none() {
+            return Ok(parent);
+        }
+        let mut scorer = WithChildrenScorer { parent, marks, doc: 0, block_end: 0 };
+        scorer.start_block();
+        Ok(Box::new(scorer))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!("Document #({}) does not match", doc)));
+        }
+        Ok(Explanation::new("post or nested child of a matching post", 1.0))
+    }
+}
+
+struct WithChildrenScorer {
+    parent: Box<dyn Scorer>,
+    marks: ChildMarks,
+    doc: DocId,
+    block_end: DocId,
+}
+
+impl WithChildrenScorer {
+    fn start_block(&mut self) -> DocId {
+        self.block_end = self.parent.doc();
+        self.doc = match self.block_end {
+            TERMINATED => TERMINATED,
+            parent => self.marks.block_start(parent),
+        };
+        self.doc
+    }
+}
+
+impl DocSet for WithChildrenScorer {
+    fn advance(&mut self) -> DocId {
+        if self.doc == TERMINATED {
+            return TERMINATED;
+        }
+        if self.doc < self.block_end {
+            self.doc += 1;
+            return self.doc;
+        }
+        self.parent.advance();
+        self.start_block()
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.parent.size_hint()
+    }
+}
+
+impl Scorer for WithChildrenScorer {
+    fn score(&mut self) -> Score {
+        1.0
+    }
+}
@@ -0,0 +1,91 @@
+//! When the background commit loop commits: on a fixed interval, or adapting to the write
+//! rate so a trickle of writes becomes searchable within a fraction of a second while heavy
+//! ingest is folded into fewer, larger commits.
+
+use std::time::{Duration, Instant};
+
+/// Share of the time a commit may take: the interval never drops below this many times the
+/// last commit's duration.
+const COMMIT_COST_FACTOR: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommitPacing {
+    Fixed(Duration),
+    /// `min` while writes trickle in, growing linearly with the write rate up to `max` at
+    /// `heavy_writes_per_sec`; without new post writes the loop still commits every `max`
+    /// for the comments and authors indexes and the shadow reload
+    Adaptive { min: Duration, max: Duration, heavy_writes_per_sec: f64 },
+}
+
+impl Default for CommitPacing {
+    fn default() -> Self {
+        CommitPacing::Adaptive { min: Duration::from_millis(250), max: Duration::from_secs(10), heavy_writes_per_sec: 1000.0 }
+    }
+}
+
+/// State of the commit loop: when it last committed and the write rate seen so far.
+pub(crate) struct Pacer {
+    pacing: CommitPacing,
+    last_commit: Instant,
+    acked_at_commit: u64,
+    /// Smoothed writes per second over previous commit intervals
+    rate: f64,
+}
+
+impl Pacer {
+    pub fn new(pacing: CommitPacing, acked: u64) -> Self {
+        Pacer { pacing, last_commit: Instant::now(), acked_at_commit: acked, rate: 0.0 }
+    }
+
+    /// How long the loop sleeps between looks at the write rate.
+    pub fn tick(&self) -> Duration {
+        match self.pacing {
+            CommitPacing::Fixed(interval) => interval,
+            CommitPacing::Adaptive { min, .. } => min,
+        }
+    }
+
+    /// Writes per second: the smoothed rate blended with the one since the last commit, so a
+    /// burst stretches the interval before its first commit.
+    pub fn rate(&self, acked: u64) -> f64 {
+        let elapsed = self.last_commit.elapsed().as_secs_f64();
+        let current = match elapsed > 0.0 {
+            true => acked.saturating_sub(self.acked_at_commit) as f64 / elapsed,
+            false => 0.0,
+        };
+        (self.rate + current) / 2.0
+    }
+
+    /// The interval the next commit aims for, given the last commit took `commit_cost`.
+    pub fn interval(&self, acked: u64, commit_cost: Duration) -> Duration {
+        match self.pacing {
+            CommitPacing::Fixed(interval) => interval,
+            CommitPacing::Adaptive { min, max, heavy_writes_per_sec } => {
+                let load = (self.rate(acked) / heavy_writes_per_sec.max(1.0)).min(1.0);
+                let by_rate = min + (max.saturating_sub(min)).mul_f64(load);
+                by_rate.max(commit_cost * COMMIT_COST_FACTOR).clamp(min, max.max(min))
+            }
+        }
+    }
+
+    /// Whether to commit now.
+    pub fn due(&self, acked: u64, commit_cost: Duration) -> bool {
+        let elapsed = self.last_commit.elapsed();
+        match self.pacing {
+            CommitPacing::Fixed(interval) => elapsed >= interval,
+            CommitPacing::Adaptive { max, .. } if acked == self.acked_at_commit => elapsed >= max,
+            CommitPacing::Adaptive { .. } => elapsed >= self.interval(acked, commit_cost),
+        }
+    }
+
+    /// Notes a commit covering `acked` writes in total.
+    pub fn committed(&mut self, acked: u64) {
+        let elapsed = self.last_commit.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            let rate = acked.saturating_sub(self.acked_at_commit) as f64 / elapsed;
+            self.rate = (self.rate + rate) / 2.0;
+        }
+        self.last_commit = Instant::now();
+        self.acked_at_commit = acked;
+    }
+}
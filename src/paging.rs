@@ -0,0 +1,105 @@
+//! Paging through search results. `offset` skips the first hits of the ranking, which costs
+//! collecting all of them again on every page, so offsets stop at [`MAX_RESULT_WINDOW`]. A
+//! `search_after` cursor instead picks up right after the last hit of the previous page,
+//! however deep: hits are ranked by score, then hot before archive, then by document address,
+//! and the cursor is the last hit's place in that order. A search taking in the archive ranks
+//! by score relative to each tier's best (see [`TierBest`]), so its cursors carry those bests
+//! and later pages rank by them rather than by the best hits left.
+//!
+//! Document addresses only hold within one searcher, so a cursor continues the same ranking
+//! only while the searcher it came from is searched: pin the session (see
+//! [`session`](crate::session)) to page through one consistent view. Without it, a refresh
+//! between pages may make a page repeat or skip hits.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tantivy::{DocAddress, Score};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::service::{SearchHit, Tier};
+
+/// Deepest `offset + limit` a search may reach; `search_after` goes deeper.
+pub const MAX_RESULT_WINDOW: usize = 10_000;
+
+/// Where the previous page ended.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SearchAfter {
+    pub score: Score,
+    pub tier: Tier,
+    pub segment: u32,
+    pub doc: u32,
+    /// Set when the page merged both tiers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best: Option<TierBest>,
+}
+
+/// Each tier's best score in a search merging the hot tier and the archive, which ranks hits
+/// by their score divided by their tier's best.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TierBest {
+    pub hot: Score,
+    pub archive: Score,
+}
+
+impl TierBest {
+    /// The best score of each tier among `hits`; 0 for a tier without any.
+    pub fn of(hits: &[SearchHit]) -> Self {
+        let best = |tier| hits.iter().filter(|h| h.tier == tier).map(|h| h.score).fold(0.0f32, f32::max);
+        TierBest { hot: best(Tier::Hot), archive: best(Tier::Archive) }
+    }
+
+    /// `score` of a hit of `tier` relative to the tier's best.
+    pub fn relative(&self, score: Score, tier: Tier) -> Score {
+        let best = match tier {
+            Tier::Hot => self.hot,
+            Tier::Archive => self.archive,
+        };
+        if best > 0.0 { score / best } else { 0.0 }
+    }
+
+    fn is_finite(&self) -> bool {
+        self.hot.is_finite() && self.archive.is_finite()
+    }
+}
+
+fn tier_rank(tier: Tier) -> u8 {
+    match tier {
+        Tier::Hot => 0,
+        Tier::Archive => 1,
+    }
+}
+
+impl SearchAfter {
+    /// The cursor continuing after `hit`, of a page ranked by `best` when it merged both tiers.
+    pub fn after(hit: &SearchHit, best: Option<TierBest>) -> Self {
+        SearchAfter { score: hit.score, tier: hit.tier, segment: hit.address.segment_ord, doc: hit.address.doc_id, best }
+    }
+
+    /// The opaque form clients pass back as `search_after`.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> ServiceResult<Self> {
+        URL_SAFE_NO_PAD
+            .decode(cursor.trim())
+            .ok()
+            .and_then(|json| serde_json::from_slice::<SearchAfter>(&json).ok())
+            .filter(|after| after.score.is_finite() && after.best.is_none_or(|best| best.is_finite()))
+            .ok_or_else(|| ServiceError::Invalid("search_after is not a cursor returned by /search".to_string()))
+    }
+
+    /// Whether a hit of `tier` at `address` scored `score` ranks after the cursor, by relative
+    /// score when the cursor carries the tiers' bests.
+    pub fn admits(&self, score: Score, tier: Tier, address: DocAddress) -> bool {
+        let (score, last) = match &self.best {
+            Some(best) => (best.relative(score, tier), best.relative(self.score, self.tier)),
+            None => (score, self.score),
+        };
+        if score != last {
+            return score < last;
+        }
+        (tier_rank(tier), address.segment_ord, address.doc_id) > (tier_rank(self.tier), self.segment, self.doc)
+    }
+}
@@ -0,0 +1,66 @@
+//! Parquet encoding for `/export?format=parquet`, behind the `parquet` feature.
+//!
+//! Columns are flattened like the CSV export: plain i64 fields become `Int64` columns,
+//! everything else (text, multi-valued fields, JSON paths) becomes nullable `Utf8`.
+
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Int64Builder, StringBuilder};
+use arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use tantivy::schema::FieldType;
+use tantivy::{DocAddress, Searcher};
+
+use crate::export::{column_value, flat_cell, ExportColumn, ExportDocs, EXPORT_CHUNK};
+
+enum ColumnBuilder {
+    Int(Int64Builder),
+    Text(StringBuilder),
+}
+
+pub fn write_parquet(searcher: Arc<Searcher>, addrs: &[DocAddress], columns: &[ExportColumn]) -> anyhow::Result<Vec<u8>> {
+    let schema = searcher.index().schema();
+    let mut docs = ExportDocs::new(searcher, columns);
+    let is_int: Vec<bool> = columns
+        .iter()
+        .map(|c| c.path.is_empty() && matches!(schema.get_field_entry(c.field).field_type(), FieldType::I64(_)))
+        .collect();
+    let arrow_schema = Arc::new(ArrowSchema::new(
+        columns
+            .iter()
+            .zip(&is_int)
+            .map(|(c, int)| ArrowField::new(&c.name, if *int { DataType::Int64 } else { DataType::Utf8 }, true))
+            .collect::<Vec<_>>(),
+    ));
+
+    let mut out = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut out, arrow_schema.clone(), None)?;
+    for chunk in addrs.chunks(EXPORT_CHUNK) {
+        let mut builders: Vec<ColumnBuilder> = is_int
+            .iter()
+            .map(|int| if *int { ColumnBuilder::Int(Int64Builder::new()) } else { ColumnBuilder::Text(StringBuilder::new()) })
+            .collect();
+        for addr in chunk {
+            let doc = docs.doc(*addr)?;
+            for (column, builder) in columns.iter().zip(builders.iter_mut()) {
+                let value = column_value(&doc, column);
+                match builder {
+                    ColumnBuilder::Int(b) => b.append_option(value.as_i64()),
+                    ColumnBuilder::Text(b) if value.is_null() => b.append_null(),
+                    ColumnBuilder::Text(b) => b.append_value(flat_cell(&value)),
+                }
+            }
+        }
+        let arrays: Vec<ArrayRef> = builders
+            .into_iter()
+            .map(|b| match b {
+                ColumnBuilder::Int(mut b) => Arc::new(b.finish()) as ArrayRef,
+                ColumnBuilder::Text(mut b) => Arc::new(b.finish()) as ArrayRef,
+            })
+            .collect();
+        writer.write(&RecordBatch::try_new(arrow_schema.clone(), arrays)?)?;
+    }
+    writer.close()?;
+    Ok(out)
+}
@@ -0,0 +1,95 @@
+//! Partial updates: `PATCH /update/{id}` with just the fields to change. The committed post is
+//! rebuilt from its stored fields, the patch merged in and the result re-indexed as an update,
+//! so clients flipping a `status` or adding a tag don't need the full post at hand.
+
+use serde::{Deserialize, Deserializer};
+
+use crate::schema::BlogPost;
+
+/// The fields to change; absent ones keep their stored value. `id` and `tenant` can't be
+/// patched.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PostPatch {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    /// Replaces the tags, before `add_tags` and `remove_tags` apply
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+    /// `null` clears it
+    #[serde(default, deserialize_with = "present")]
+    pub create_at: Option<Option<i64>>,
+    pub status: Option<String>,
+    /// Merged into the stored features as a JSON merge patch (RFC 7386): objects merge key by
+    /// key, `null` removes a key, anything else replaces the value
+    pub features: Option<serde_json::Value>,
+    /// `null` clears it
+    #[serde(default, deserialize_with = "present")]
+    pub author_id: Option<Option<String>>,
+    pub allowed_groups: Option<Vec<String>>,
+}
+
+/// Tells a `null` field (`Some(None)`) from an absent one (`None`).
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl PostPatch {
+    /// `post` with the patch applied.
+    pub fn apply(&self, mut post: BlogPost) -> BlogPost {
+        if let Some(title) = &self.title {
+            post.title = title.clone();
+        }
+        if let Some(body) = &self.body {
+            post.body = body.clone();
+        }
+        if let Some(tags) = &self.tags {
+            post.tags = tags.clone();
+        }
+        for tag in &self.add_tags {
+            if !post.tags.contains(tag) {
+                post.tags.push(tag.clone());
+            }
+        }
+        post.tags.retain(|tag| !self.remove_tags.contains(tag));
+        if let Some(create_at) = self.create_at {
+            post.create_at = create_at;
+        }
+        if let Some(status) = &self.status {
+            post.status = status.clone();
+        }
+        if let Some(features) = &self.features {
+            merge_patch(&mut post.features, features);
+        }
+        if let Some(author_id) = &self.author_id {
+            post.author_id = author_id.clone();
+        }
+        if let Some(groups) = &self.allowed_groups {
+            post.allowed_groups = groups.clone();
+        }
+        post
+    }
+}
+
+/// Applies `patch` to `target` as RFC 7386 describes.
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(changes) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    let object = target.as_object_mut().unwrap();
+    for (key, value) in changes {
+        match value {
+            serde_json::Value::Null => {
+                object.remove(key);
+            }
+            value => merge_patch(object.entry(key.clone()).or_insert(serde_json::Value::Null), value),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Protected terms: product names and code identifiers such as `Vec<T>` or `IndexWriter` that
+//! the text analyzers would mangle into n-grams or stems. Each one a post's title or body
+//! contains is also indexed verbatim in the `keywords` field, and a query literal that is a
+//! keyword of the tier searched matches there exactly (see [`query`](crate::query)).
+//!
+//! Protected terms are matched case-sensitively, and only as whole words: `Vec` is not found
+//! in `Vector`.
+
+use std::path::PathBuf;
+
+use tantivy::schema::{Schema, Value};
+use tantivy::TantivyDocument;
+
+use crate::schema::KEYWORDS_FIELD;
+
+/// Longest protected term accepted, in bytes.
+const MAX_TERM_LEN: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProtectedTerms {
+    terms: Vec<String>,
+}
+
+/// Reads a JSON array of protected terms.
+pub fn load_protected_terms(path: &PathBuf) -> anyhow::Result<Vec<String>> {
+    let terms: Vec<String> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    for term in &terms {
+        if term.trim().is_empty() || term.trim() != term || term.len() > MAX_TERM_LEN {
+            anyhow::bail!("protected terms must be 1 to {} bytes without surrounding whitespace: {:?}", MAX_TERM_LEN, term);
+        }
+    }
+    Ok(terms)
+}
+
+/// Whether `c` continues a word, so a match ending or starting next to it is only part of one.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl ProtectedTerms {
+    pub fn new(mut terms: Vec<String>) -> Self {
+        terms.sort();
+        terms.dedup();
+        ProtectedTerms { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The protected terms `text` contains as whole words.
+    pub fn find<'a>(&'a self, text: &str) -> Vec<&'a str> {
+        let mut found = Vec::new();
+        for term in &self.terms {
+            let whole = text.match_indices(term.as_str()).any(|(start, m)| {
+                let end = start + m.len();
+                // Boundaries only matter where the term itself starts or ends with a word char
+                let first = term.chars().next().is_some_and(is_word_char);
+                let last = term.chars().next_back().is_some_and(is_word_char);
+                let joined_before = first && text[..start].chars().next_back().is_some_and(is_word_char);
+                let joined_after = last && text[end..].chars().next().is_some_and(is_word_char);
+                !joined_before && !joined_after
+            });
+            if whole {
+                found.push(term.as_str());
+            }
+        }
+        found
+    }
+
+    /// Adds the protected terms of the document's title and body to its keywords. Indexes
+    /// created before keywords existed have no field to fill.
+    pub fn add_keywords(&self, schema: &Schema, document: &mut TantivyDocument) {
+        let Ok(f_keywords) = schema.get_field(KEYWORDS_FIELD) else { return };
+        let text = |name: &str| {
+            let field = schema.get_field(name).ok()?;
+            document.get_first(field).and_then(|v| v.as_str()).map(str::to_string)
+        };
+        let texts: Vec<String> = [text("title"), text("body")].into_iter().flatten().collect();
+        let mut keywords: Vec<String> = Vec::new();
+        for text in &texts {
+            for term in self.find(text) {
+                if !keywords.iter().any(|k| k == term) {
+                    keywords.push(term.to_string());
+                }
+            }
+        }
+        for keyword in keywords {
+            document.add_text(f_keywords, keyword);
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! User query translation: tantivy's query grammar plus the forms its parser rejects or
+//! silently mismatches on JSON fields.
+//!
+//! - `field:(a OR "b c")` groups distribute the field over their terms (any field)
+//! - `features.score:[1 TO 5]`, `features.score:>3`, `features.lang:[a TO m]` ranges on JSON
+//!   paths; numeric bounds match integer and float values alike
+//! - `create_at:[2024-01-01 TO 2024-02-01}`, `create_at:>=2024-01-31T12:00:00Z` ranges on
+//!   timestamp fields take dates as well as unix seconds
+//! - `features.score:3.0` numeric literals on JSON paths match both encodings
+//! - `features.lang:IN [zh jp]` sets on JSON paths
+//! - an optional [`MinimumShouldMatch`] over the top-level optional clauses
+//! - an unscoped word or phrase that is a protected term indexed in `keywords` (see
+//!   [`protected`](crate::protected)) matches there verbatim instead of through the analyzers
+//! - an optional boost for posts containing the query's words next to each other (see
+//!   [`with_shingle_boost`])
+//!
+//! Everything else goes through [`default_query_parser`] unchanged. The text-only part, syntax,
+//! field groups, clause limits and `minimum_should_match`, lives in the `query_syntax` crate,
+//! which frontends can run as WASM to validate queries exactly as this does.
+
+use std::collections::HashSet;
+use std::ops::Bound;
+
+use tantivy::json_utils::JsonTermWriter;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, EnableScoring, Explanation, Occur, Query, QueryParser, RangeQuery, Scorer,
+    TermQuery, Weight,
+};
+use tantivy::query_grammar::{Delimiter, UserInputAst, UserInputBound, UserInputLeaf, UserInputLiteral};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema, Type};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::OffsetDateTime;
+use tantivy::{f64_to_u64, i64_to_u64, DocId, DocSet, Score, Searcher, SegmentReader, TantivyError, Term, TERMINATED};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::nested::{child_query, posts_only, ToParentQuery};
+use crate::schema::{default_query_parser, INDEXED_AT_FIELD, KEYWORDS_FIELD, SHINGLES_FIELD};
+
+pub use query_syntax::{MinimumShouldMatch, QueryLimits};
+pub(crate) use query_syntax::QueryShape;
+
+/// i64 fields holding unix seconds, whose range bounds may also be dates.
+const TIMESTAMP_FIELDS: &[&str] = &["create_at", INDEXED_AT_FIELD];
+
+/// Parses `q` against the default search fields of `searcher`'s index. Matches posts only,
+/// never their nested children.
+pub fn parse_query(searcher: &Searcher, q: &str, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+    parse_query_with(searcher, q, limits, None)
+}
+
+/// [`parse_query`], additionally requiring `minimum_should_match` of the query's top-level
+/// optional clauses (e.g. the words of `rust tantivy search`) to match. Each clause already
+/// spans all default fields, so a word counts once wherever it matched.
+pub fn parse_query_with(
+    searcher: &Searcher,
+    q: &str,
+    limits: &QueryLimits,
+    minimum_should_match: Option<MinimumShouldMatch>,
+) -> ServiceResult<Box<dyn Query>> {
+    let ast = parse_ast(q, limits)?;
+    let query = match minimum_should_match {
+        Some(msm) => build_min_should_match(searcher, ast, msm)?,
+        None => build(searcher, ast)?,
+    };
+    Ok(posts_only(&searcher.index().schema(), check_terms(query, limits)?))
+}
+
+/// `query`, scoring posts up by `boost` for each run of two or three of `q`'s words they
+/// contain in that order, looked up as terms of the shingles field rather than as phrases.
+/// Only unscoped, non-prefix words outside negated clauses count; indexes without the field
+/// get `query` back unchanged.
+pub fn with_shingle_boost(
+    searcher: &Searcher,
+    q: &str,
+    limits: &QueryLimits,
+    query: Box<dyn Query>,
+    boost: f32,
+) -> ServiceResult<Box<dyn Query>> {
+    fn words(ast: &UserInputAst, out: &mut Vec<String>) {
+        match ast {
+            UserInputAst::Clause(clauses) => {
+                clauses.iter().filter(|(occur, _)| *occur != Some(Occur::MustNot)).for_each(|(_, sub)| words(sub, out))
+            }
+            UserInputAst::Boost(inner, _) => words(inner, out),
+            UserInputAst::Leaf(leaf) => {
+                if let UserInputLeaf::Literal(literal) = leaf.as_ref() {
+                    if literal.field_name.is_none() && !literal.prefix {
+                        out.push(literal.phrase.clone());
+                    }
+                }
+            }
+        }
+    }
+    let index = searcher.index();
+    let Ok(field) = index.schema().get_field(SHINGLES_FIELD) else { return Ok(query) };
+    let mut literals = Vec::new();
+    words(&parse_ast(q, limits)?, &mut literals);
+    let text = literals.join(" ");
+    let mut analyzer = index.tokenizer_for_field(field)?;
+    let mut stream = analyzer.token_stream(&text);
+    let mut terms: Vec<Term> = Vec::new();
+    while stream.advance() {
+        let term = Term::from_field_text(field, &stream.token().text);
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    if terms.is_empty() {
+        return Ok(query);
+    }
+    let shingles: Vec<(Occur, Box<dyn Query>)> = terms
+        .into_iter()
+        .map(|term| (Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)) as Box<dyn Query>))
+        .collect();
+    let boosted = BoostQuery::new(Box::new(BooleanQuery::new(shingles)), boost);
+    check_terms(Box::new(BooleanQuery::new(vec![(Occur::Must, query), (Occur::Should, Box::new(boosted))])), limits)
+}
+
+/// Posts with a child under the nested `path` matching `q` on its own, e.g. `snippet` with
+/// `features.snippet.lang:rust AND features.snippet.text:unsafe`.
+pub fn parse_nested_query(searcher: &Searcher, path: &str, q: &str, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+    let child = check_terms(build(searcher, parse_ast(q, limits)?)?, limits)?;
+    let child = child_query(&searcher.index().schema(), path, child)?;
+    Ok(Box::new(ToParentQuery { child }))
+}
+
+/// Parses `q` with a plain `parser`, for indexes other than the posts schema (which has no
+/// JSON paths to translate), under the same field groups and complexity limits.
+pub fn parse_with_parser(parser: &QueryParser, q: &str, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+    let ast = parse_ast(q, limits)?;
+    check_terms(parser.build_query_from_user_input_ast(ast)?, limits)
+}
+
+fn parse_ast(q: &str, limits: &QueryLimits) -> ServiceResult<UserInputAst> {
+    Ok(query_syntax::parse(q, limits)?)
+}
+
+fn check_terms(query: Box<dyn Query>, limits: &QueryLimits) -> ServiceResult<Box<dyn Query>> {
+    let mut terms = 0;
+    query.query_terms(&mut |_, _| terms += 1);
+    if terms > limits.max_terms {
+        return Err(ServiceError::Invalid(format!(
+            "query too complex: expands to {} terms (limit {}); shorten phrases or scope them to one field",
+            terms, limits.max_terms
+        )));
+    }
+    Ok(query)
+}
+
+fn build(searcher: &Searcher, ast: UserInputAst) -> ServiceResult<Box<dyn Query>> {
+    let translator = Translator {
+        searcher,
+        schema: searcher.index().schema(),
+        parser: default_query_parser(searcher.index()),
+    };
+    if !translator.needs_translation(&ast) {
+        return Ok(translator.parser.build_query_from_user_input_ast(ast)?);
+    }
+    let mut query = translator.convert(ast)?;
+    if let Some(clauses) = query.downcast_ref::<BooleanQuery>().map(|b| b.clauses()) {
+        // A purely negative query matches nothing; like the stock parser, exclude from everything
+        if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+            let mut clauses: Vec<_> = clauses.iter().map(|(o, q)| (*o, q.box_clone())).collect();
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+            query = Box::new(BooleanQuery::new(clauses));
+        }
+    }
+    Ok(query)
+}
+
+fn build_min_should_match(searcher: &Searcher, ast: UserInputAst, msm: MinimumShouldMatch) -> ServiceResult<Box<dyn Query>> {
+    let UserInputAst::Clause(clauses) = ast else { return build(searcher, ast) };
+    let is_optional = |occur: &Option<Occur>| matches!(occur, None | Some(Occur::Should));
+    let optional = clauses.iter().filter(|(occur, _)| is_optional(occur)).count();
+    let minimum = msm.resolve(optional);
+    if minimum <= 1 || optional < 2 {
+        return build(searcher, UserInputAst::Clause(clauses));
+    }
+    let mut should = Vec::with_capacity(optional);
+    let mut rest = Vec::new();
+    for (occur, sub) in clauses {
+        match occur {
+            None | Some(Occur::Should) => should.push(build(searcher, sub)?),
+            Some(occur) => rest.push((occur, build(searcher, sub)?)),
+        }
+    }
+    rest.push((Occur::Must, Box::new(MinShouldMatchQuery { clauses: should, minimum })));
+    Ok(Box::new(BooleanQuery::new(rest)))
+}
+
+/// The [`QueryShape`] of `q`, under the same syntax and clause limits as parsing it.
+pub(crate) fn query_shape(q: &str, limits: &QueryLimits) -> ServiceResult<QueryShape> {
+    Ok(query_syntax::shape(q, limits)?)
+}
+
+/// A JSON field plus the path inside it, e.g. `features` + `score`.
+struct JsonPath {
+    field: Field,
+    full: String,
+    path: String,
+    expand_dots: bool,
+}
+
+struct Translator<'a> {
+    searcher: &'a Searcher,
+    schema: Schema,
+    parser: QueryParser,
+}
+
+impl Translator<'_> {
+    /// `Some` when `name` is `<json field>.<path>`; errors on empty path segments.
+    fn json_path(&self, name: &str) -> ServiceResult<Option<JsonPath>> {
+        let Some((base, path)) = name.split_once('.') else { return Ok(None) };
+        let Ok(field) = self.schema.get_field(base) else { return Ok(None) };
+        let FieldType::JsonObject(options) = self.schema.get_field_entry(field).field_type() else {
+            return Ok(None);
+        };
+        if path.split('.').any(str::is_empty) {
+            return Err(ServiceError::Invalid(format!("invalid JSON path `{}`: empty segment", name)));
+        }
+        Ok(Some(JsonPath {
+            field,
+            full: name.to_string(),
+            path: path.to_string(),
+            expand_dots: options.is_expand_dots_enabled(),
+        }))
+    }
+
+    /// The `keywords` term for an unscoped literal that some post of this tier holds as a
+    /// protected term.
+    fn keyword(&self, lit: &UserInputLiteral) -> Option<Term> {
+        if lit.field_name.is_some() || lit.prefix {
+            return None;
+        }
+        let field = self.schema.get_field(KEYWORDS_FIELD).ok()?;
+        let term = Term::from_field_text(field, &lit.phrase);
+        matches!(self.searcher.doc_freq(&term), Ok(n) if n > 0).then_some(term)
+    }
+
+    fn targets_json(&self, field: Option<&String>) -> bool {
+        field.is_some_and(|f| !matches!(self.json_path(f), Ok(None)))
+    }
+
+    fn targets_timestamp(&self, field: Option<&String>) -> bool {
+        field.is_some_and(|f| TIMESTAMP_FIELDS.contains(&f.as_str()) && self.schema.get_field(f).is_ok())
+    }
+
+    fn needs_translation(&self, ast: &UserInputAst) -> bool {
+        match ast {
+            UserInputAst::Clause(clauses) => clauses.iter().any(|(_, sub)| self.needs_translation(sub)),
+            UserInputAst::Boost(inner, _) => self.needs_translation(inner),
+            UserInputAst::Leaf(leaf) => match leaf.as_ref() {
+                UserInputLeaf::Range { field, .. } => self.targets_json(field.as_ref()) || self.targets_timestamp(field.as_ref()),
+                UserInputLeaf::Set { field, .. } => self.targets_json(field.as_ref()),
+                UserInputLeaf::Literal(lit) => {
+                    (lit.delimiter == Delimiter::None && self.targets_json(lit.field_name.as_ref())) || self.keyword(lit).is_some()
+                }
+                UserInputLeaf::All | UserInputLeaf::Exists { .. } => false,
+            },
+        }
+    }
+
+    fn convert(&self, ast: UserInputAst) -> ServiceResult<Box<dyn Query>> {
+        if !self.needs_translation(&ast) {
+            return Ok(self.parser.build_query_from_user_input_ast(ast)?);
+        }
+        match ast {
+            UserInputAst::Clause(clauses) => {
+                let mut subqueries = Vec::with_capacity(clauses.len());
+                for (occur, sub) in clauses {
+                    subqueries.push((occur.unwrap_or(Occur::Should), self.convert(sub)?));
+                }
+                Ok(Box::new(BooleanQuery::new(subqueries)))
+            }
+            UserInputAst::Boost(inner, boost) => Ok(Box::new(BoostQuery::new(self.convert(*inner)?, boost as f32))),
+            UserInputAst::Leaf(leaf) => self.convert_leaf(*leaf),
+        }
+    }
+
+    fn convert_leaf(&self, leaf: UserInputLeaf) -> ServiceResult<Box<dyn Query>> {
+        match leaf {
+            UserInputLeaf::Range { field: Some(name), lower, upper } if self.targets_timestamp(Some(&name)) => {
+                timestamp_range(&name, lower, upper)
+            }
+            UserInputLeaf::Range { field: Some(name), lower, upper } => {
+                let path = self.json_path(&name)?.expect("checked by needs_translation");
+                self.range(&path, lower, upper)
+            }
+            UserInputLeaf::Set { field: Some(name), elements } => {
+                let path = self.json_path(&name)?.expect("checked by needs_translation");
+                let mut any = Vec::with_capacity(elements.len());
+                for element in elements {
+                    any.push((Occur::Should, self.literal(&path, &element)?));
+                }
+                Ok(Box::new(BooleanQuery::new(any)))
+            }
+            UserInputLeaf::Literal(lit) => {
+                if let Some(term) = self.keyword(&lit) {
+                    return Ok(Box::new(TermQuery::new(term, IndexRecordOption::Basic)));
+                }
+                let name = lit.field_name.clone().expect("checked by needs_translation");
+                let path = self.json_path(&name)?.expect("checked by needs_translation");
+                if lit.prefix {
+                    return Ok(self.parser.build_query_from_user_input_ast(UserInputAst::Leaf(Box::new(UserInputLeaf::Literal(lit))))?);
+                }
+                self.literal(&path, &lit.phrase)
+            }
+            other => Ok(self.parser.build_query_from_user_input_ast(UserInputAst::Leaf(Box::new(other)))?),
+        }
+    }
+
+    /// Equality on a JSON path. Numbers match integer and float encodings; text goes through
+    /// the field's analyzer like any other literal.
+    fn literal(&self, path: &JsonPath, value: &str) -> ServiceResult<Box<dyn Query>> {
+        let types = self.path_types(path)?;
+        let text = || -> ServiceResult<Box<dyn Query>> {
+            let literal = UserInputLiteral {
+                field_name: Some(path.full.clone()),
+                phrase: value.to_string(),
+                delimiter: Delimiter::None,
+                slop: 0,
+                prefix: false,
+            };
+            Ok(self.parser.build_query_from_user_input_ast(UserInputAst::Leaf(Box::new(UserInputLeaf::Literal(literal))))?)
+        };
+        let Ok(number) = value.parse::<f64>() else {
+            if !types.is_empty() && types.iter().all(|t| is_numeric(*t)) {
+                return Err(ServiceError::Invalid(format!(
+                    "{} holds numbers; `{}` is not a number",
+                    path.full, value
+                )));
+            }
+            return text();
+        };
+        let mut any: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Should, text()?)];
+        if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+            let term = json_term(path, Type::I64, &i64_to_u64(number as i64).to_be_bytes());
+            any.push((Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+        }
+        let term = json_term(path, Type::F64, &f64_to_u64(number).to_be_bytes());
+        any.push((Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+        Ok(Box::new(BooleanQuery::new(any)))
+    }
+
+    fn range(&self, path: &JsonPath, lower: UserInputBound, upper: UserInputBound) -> ServiceResult<Box<dyn Query>> {
+        let (lower, upper) = (to_bound(lower), to_bound(upper));
+        let numeric = |b: &Bound<String>| match b {
+            Bound::Included(v) | Bound::Excluded(v) => Some(v.parse::<f64>().is_ok()),
+            Bound::Unbounded => None,
+        };
+        let types = self.path_types(path)?;
+        let holds = |numeric: bool| types.is_empty() || types.iter().any(|t| is_numeric(*t) == numeric);
+        match (numeric(&lower), numeric(&upper)) {
+            (Some(true), Some(false)) | (Some(false), Some(true)) => Err(ServiceError::Invalid(format!(
+                "{}: range bounds must both be numbers or both be text",
+                path.full
+            ))),
+            (None, None) => Err(ServiceError::Invalid(format!("{}: range needs at least one bound", path.full))),
+            (Some(true), _) | (_, Some(true)) if !holds(true) => Err(ServiceError::Invalid(format!(
+                "{} holds text, not numbers; use text bounds such as [a TO m]",
+                path.full
+            ))),
+            (Some(true), _) | (_, Some(true)) => {
+                let parse = |b: Bound<String>| map_bound(b, |v| v.parse::<f64>().unwrap());
+                Ok(numeric_range(path, parse(lower), parse(upper)))
+            }
+            _ if !holds(false) => Err(ServiceError::Invalid(format!(
+                "{} holds numbers; use numeric bounds such as [1 TO 5]",
+                path.full
+            ))),
+            _ => Ok(text_range(path, lower, upper)),
+        }
+    }
+
+    /// Value types indexed under a JSON path across all segments.
+    fn path_types(&self, path: &JsonPath) -> ServiceResult<HashSet<Type>> {
+        let mut types = HashSet::new();
+        for typ in [Type::Str, Type::I64, Type::U64, Type::F64, Type::Bool, Type::Date] {
+            let prefix = json_term(path, typ, &[]);
+            let prefix = prefix.serialized_value_bytes();
+            let mut end = prefix.to_vec();
+            *end.last_mut().unwrap() += 1;
+            for segment in self.searcher.segment_readers() {
+                let inverted = segment.inverted_index(path.field)?;
+                let mut stream = inverted.terms().range().ge(prefix).lt(&end).into_stream()?;
+                if stream.advance() {
+                    types.insert(typ);
+                    break;
+                }
+            }
+        }
+        Ok(types)
+    }
+}
+
+fn is_numeric(typ: Type) -> bool {
+    matches!(typ, Type::I64 | Type::U64 | Type::F64)
+}
+
+fn to_bound(bound: UserInputBound) -> Bound<String> {
+    match bound {
+        UserInputBound::Inclusive(v) if v == "*" => Bound::Unbounded,
+        UserInputBound::Inclusive(v) => Bound::Included(v),
+        UserInputBound::Exclusive(v) => Bound::Excluded(v),
+        UserInputBound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn map_bound<T, U>(bound: Bound<T>, f: impl Fn(T) -> U) -> Bound<U> {
+    match bound {
+        Bound::Included(v) => Bound::Included(f(v)),
+        Bound::Excluded(v) => Bound::Excluded(f(v)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// A typed term under a JSON path; `value` is the encoded value (empty for a type prefix).
+fn json_term(path: &JsonPath, typ: Type, value: &[u8]) -> Term {
+    let mut term = Term::with_capacity(path.path.len() + 2 + value.len());
+    JsonTermWriter::from_field_and_json_path(path.field, &path.path, path.expand_dots, &mut term)
+        .close_path_and_set_type(typ);
+    term.append_bytes(value);
+    term
+}
+
+fn json_range(path: &JsonPath, lower: Bound<Term>, upper: Bound<Term>) -> Box<dyn Query> {
+    let field_name = path.full.split('.').next().unwrap().to_string();
+    Box::new(RangeQuery::new_term_bounds(field_name, Type::Json, &lower, &upper))
+}
+
+/// Integer and float values in `[lower, upper]`; each type is searched over its own term
+/// range, with unbounded sides clamped to that type's extremes.
+fn numeric_range(path: &JsonPath, lower: Bound<f64>, upper: Bound<f64>) -> Box<dyn Query> {
+    let int_lower = match lower {
+        Bound::Included(v) => v.ceil(),
+        Bound::Excluded(v) if v.fract() == 0.0 => v + 1.0,
+        Bound::Excluded(v) => v.ceil(),
+        Bound::Unbounded => i64::MIN as f64,
+    };
+    let int_upper = match upper {
+        Bound::Included(v) => v.floor(),
+        Bound::Excluded(v) if v.fract() == 0.0 => v - 1.0,
+        Bound::Excluded(v) => v.floor(),
+        Bound::Unbounded => i64::MAX as f64,
+    };
+    let int = |v: f64| json_term(path, Type::I64, &i64_to_u64(v as i64).to_be_bytes());
+    let float = |v: f64| json_term(path, Type::F64, &f64_to_u64(v).to_be_bytes());
+    let float_bound = |b: Bound<f64>, open: f64| match b {
+        Bound::Included(v) => Bound::Included(float(v)),
+        Bound::Excluded(v) => Bound::Excluded(float(v)),
+        Bound::Unbounded => Bound::Included(float(open)),
+    };
+    Box::new(BooleanQuery::new(vec![
+        (Occur::Should, json_range(path, Bound::Included(int(int_lower)), Bound::Included(int(int_upper)))),
+        (
+            Occur::Should,
+            json_range(path, float_bound(lower, f64::NEG_INFINITY), float_bound(upper, f64::INFINITY)),
+        ),
+    ]))
+}
+
+/// Seconds in `[lower, upper]` on a timestamp field. Bounds are unix seconds or dates:
+/// `2024-01-31` (midnight UTC) or RFC 3339 (`2024-01-31T12:00:00Z`, `2024-01-31T21:00:00+09:00`).
+fn timestamp_range(name: &str, lower: UserInputBound, upper: UserInputBound) -> ServiceResult<Box<dyn Query>> {
+    let seconds = |bound: Bound<String>| -> ServiceResult<Bound<i64>> {
+        let parse = |v: &str| {
+            v.parse::<i64>().ok().or_else(|| {
+                let v = if v.len() == 10 { format!("{}T00:00:00Z", v) } else { v.to_string() };
+                OffsetDateTime::parse(&v, &Rfc3339).ok().map(|t| t.unix_timestamp())
+            })
+        };
+        match bound {
+            Bound::Included(v) | Bound::Excluded(v) if parse(&v).is_none() => Err(ServiceError::Invalid(format!(
+                "{}: range bounds must be unix seconds or dates such as 2024-01-31 or 2024-01-31T12:00:00Z, got {}",
+                name, v
+            ))),
+            bound => Ok(map_bound(bound, |v| parse(&v).unwrap())),
+        }
+    };
+    let (lower, upper) = (seconds(to_bound(lower))?, seconds(to_bound(upper))?);
+    if matches!((&lower, &upper), (Bound::Unbounded, Bound::Unbounded)) {
+        return Err(ServiceError::Invalid(format!("{}: range needs at least one bound", name)));
+    }
+    Ok(Box::new(RangeQuery::new_i64_bounds(name.to_string(), lower, upper)))
+}
+
+/// Lexicographic range over the path's (lowercased) text tokens.
+fn text_range(path: &JsonPath, lower: Bound<String>, upper: Bound<String>) -> Box<dyn Query> {
+    let text = |v: String| json_term(path, Type::Str, v.to_lowercase().as_bytes());
+    let lower = match lower {
+        Bound::Unbounded => Bound::Included(json_term(path, Type::Str, &[])),
+        bound => map_bound(bound, text),
+    };
+    let upper = match upper {
+        Bound::Unbounded => Bound::Excluded(json_term(path, Type::Str, &[0xff])),
+        bound => map_bound(bound, text),
+    };
+    json_range(path, lower, upper)
+}
+
+/// Matches documents hit by at least `minimum` of `clauses`, scored by the sum of the
+/// matching clauses (tantivy 0.22's `BooleanQuery` has no such threshold).
+#[derive(Debug)]
+struct MinShouldMatchQuery {
+    clauses: Vec<Box<dyn Query>>,
+    minimum: usize,
+}
+
+impl Clone for MinShouldMatchQuery {
+    fn clone(&self) -> Self {
+        MinShouldMatchQuery { clauses: self.clauses.iter().map(|q| q.box_clone()).collect(), minimum: self.minimum }
+    }
+}
+
+impl Query for MinShouldMatchQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let weights = self.clauses.iter().map(|q| q.weight(enable_scoring)).collect::<tantivy::Result<_>>()?;
+        Ok(Box::new(MinShouldMatchWeight { weights, minimum: self.minimum }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        for clause in &self.clauses {
+            clause.query_terms(visitor);
+        }
+    }
+}
+
+struct MinShouldMatchWeight {
+    weights: Vec<Box<dyn Weight>>,
+    minimum: usize,
+}
+
+impl Weight for MinShouldMatchWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let scorers = self.weights.iter().map(|w| w.scorer(reader, boost)).collect::<tantivy::Result<_>>()?;
+        let mut scorer = MinShouldMatchScorer { scorers, minimum: self.minimum, doc: 0, score: 0.0 };
+        scorer.next_match();
+        Ok(Box::new(scorer))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!("Document #({}) does not match", doc)));
+        }
+        let mut explanation =
+            Explanation::new_with_string(format!("at least {} of {} clauses, sum of", self.minimum, self.weights.len()), scorer.score());
+        for weight in &self.weights {
+            if let Ok(detail) = weight.explain(reader, doc) {
+                explanation.add_detail(detail);
+            }
+        }
+        Ok(explanation)
+    }
+}
+
+struct MinShouldMatchScorer {
+    scorers: Vec<Box<dyn Scorer>>,
+    minimum: usize,
+    doc: DocId,
+    score: Score,
+}
+
+impl MinShouldMatchScorer {
+    /// Moves to the smallest doc, at or after the sub-scorers' positions, that enough of them
+    /// share.
+    fn next_match(&mut self) -> DocId {
+        loop {
+            let doc = self.scorers.iter().map(|s| s.doc()).min().unwrap_or(TERMINATED);
+            if doc == TERMINATED {
+                self.doc = TERMINATED;
+                return TERMINATED;
+            }
+            let on_doc = self.scorers.iter().filter(|s| s.doc() == doc).count();
+            if on_doc >= self.minimum {
+                self.doc = doc;
+                self.score = self.scorers.iter_mut().filter(|s| s.doc() == doc).map(|s| s.score()).sum();
+                return doc;
+            }
+            self.advance_on(doc);
+        }
+    }
+
+    fn advance_on(&mut self, doc: DocId) {
+        for scorer in self.scorers.iter_mut().filter(|s| s.doc() == doc) {
+            scorer.advance();
+        }
+    }
+}
+
+impl DocSet for MinShouldMatchScorer {
+    fn advance(&mut self) -> DocId {
+        self.advance_on(self.doc);
+        self.next_match()
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.scorers.iter().map(|s| s.size_hint()).max().unwrap_or(0)
+    }
+}
+
+impl Scorer for MinShouldMatchScorer {
+    fn score(&mut self) -> Score {
+        self.score
+    }
+}
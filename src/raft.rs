@@ -0,0 +1,590 @@
+//! A small Raft implementation replicating the [`metadata`](crate::metadata) log between the
+//! nodes of a cluster over HTTP (`/cluster/raft/vote` and `/cluster/raft/append`).
+//!
+//! It covers leader election, log replication and commitment as in the Raft paper, with the
+//! term, vote and log persisted to a JSON file before any reply depends on them. Membership
+//! changes go one node at a time through the log and take effect as soon as a node stores
+//! them. There is no log compaction: metadata changes are rare, so the log stays small.
+//!
+//! A fresh cluster is bootstrapped by starting every founding node with the same member list;
+//! the first leader writes it to the log. A node joining later starts with no members, keeps
+//! quiet until the leader has added it (`add_member`) and then learns everything from the log.
+//!
+//! Members share a secret, sent as [`CLUSTER_SECRET_HEADER`] on every raft call; the cluster
+//! routes refuse callers without it, so votes, appends and metadata changes can't be forged by
+//! whoever reaches a node's port.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Notify};
+
+use crate::auth::AdminKey;
+use crate::error::{ServiceError, ServiceResult};
+use crate::metadata::{ClusterMetadata, MetadataCommand};
+use crate::storage::write_durably;
+
+/// Entries sent per append request.
+const MAX_APPEND_ENTRIES: usize = 100;
+
+/// How long a proposal waits to be committed.
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header carrying the cluster secret.
+pub const CLUSTER_SECRET_HEADER: &str = "X-Cluster-Secret";
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub node_id: String,
+    /// Founding members (node id -> base URL), this node included and the same on every
+    /// founding node; empty when joining an existing cluster
+    pub members: BTreeMap<String, String>,
+    /// JSON file holding the term, vote and log; not persisted when `None`
+    pub state_path: Option<PathBuf>,
+    /// Shared by every member; raft calls carry it and the cluster routes want it. `None`
+    /// leaves them open, for clusters on a private network only
+    pub secret: Option<String>,
+    /// Followers start an election after hearing nothing from a leader for between this and
+    /// twice this long
+    pub election_timeout: Duration,
+    /// How often a leader sends (possibly empty) appends
+    pub heartbeat: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub term: u64,
+    pub command: MetadataCommand,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct PersistentState {
+    current_term: u64,
+    voted_for: Option<String>,
+    log: Vec<LogEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate: String,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteResponse {
+    pub term: u64,
+    pub granted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppendRequest {
+    pub term: u64,
+    pub leader: String,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<LogEntry>,
+    pub leader_commit: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppendResponse {
+    pub term: u64,
+    pub success: bool,
+    /// Last index known to match the leader's log (a hint for the next attempt on failure)
+    pub match_index: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// What `/cluster/metadata` reports.
+#[derive(Serialize, Debug, Clone)]
+pub struct RaftStatus {
+    pub node_id: String,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<String>,
+    pub leader_url: Option<String>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub log_entries: u64,
+    /// Voting members as of the latest log entry, committed or not
+    pub voters: BTreeMap<String, String>,
+    /// Committed metadata
+    pub metadata: ClusterMetadata,
+}
+
+struct RaftState {
+    persistent: PersistentState,
+    role: Role,
+    leader: Option<String>,
+    commit_index: u64,
+    last_applied: u64,
+    metadata: ClusterMetadata,
+    election_deadline: Instant,
+    /// When an append from the current leader last arrived
+    leader_contact: Option<Instant>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+}
+
+impl RaftState {
+    fn last_index(&self) -> u64 {
+        self.persistent.log.len() as u64
+    }
+
+    /// Term of the entry at 1-based `index`, 0 before the first.
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            i => self.persistent.log.get(i as usize - 1).map_or(0, |e| e.term),
+        }
+    }
+
+    fn apply_committed(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let command = self.persistent.log[self.last_applied as usize - 1].command.clone();
+            self.metadata.apply(&command);
+        }
+    }
+}
+
+pub struct RaftNode {
+    config: RaftConfig,
+    secret: Option<AdminKey>,
+    http: reqwest::Client,
+    state: Mutex<RaftState>,
+    /// Last applied index
+    applied: watch::Sender<u64>,
+    replicate_now: Notify,
+}
+
+impl RaftNode {
+    /// Restores the term, vote and log from `state_path`, if it exists. Entries are applied
+    /// again as the leader's commits are learnt.
+    pub fn open(config: RaftConfig) -> anyhow::Result<Self> {
+        let persistent = match &config.state_path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => PersistentState::default(),
+        };
+        let state = RaftState {
+            persistent,
+            role: Role::Follower,
+            leader: None,
+            commit_index: 0,
+            last_applied: 0,
+            metadata: ClusterMetadata::default(),
+            election_deadline: Instant::now(),
+            leader_contact: None,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+        };
+        let secret = config.secret.as_deref().map(AdminKey::new);
+        let node = RaftNode { config, secret, http: reqwest::Client::new(), state: Mutex::new(state), applied: watch::channel(0).0, replicate_now: Notify::new() };
+        node.reset_election_deadline(&mut node.lock());
+        Ok(node)
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.config.node_id
+    }
+
+    /// Whether `sent` is the cluster secret, compared in constant time.
+    pub fn authorizes(&self, sent: Option<&str>) -> bool {
+        match (&self.secret, sent) {
+            (None, _) => true,
+            (Some(secret), Some(sent)) => secret.matches(sent.trim()),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RaftState> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn reset_election_deadline(&self, state: &mut RaftState) {
+        let base = self.config.election_timeout;
+        state.election_deadline = Instant::now() + rand::thread_rng().gen_range(base..base * 2);
+    }
+
+    fn persist(&self, state: &RaftState) -> ServiceResult<()> {
+        let Some(path) = &self.config.state_path else { return Ok(()) };
+        let json = serde_json::to_string(&state.persistent).map_err(|e| ServiceError::Internal(e.to_string()))?;
+        write_durably(path, json.as_bytes())?;
+        Ok(())
+    }
+
+    /// Voting members: the founding list with every membership change in the log applied,
+    /// committed or not.
+    fn voters(&self, state: &RaftState) -> BTreeMap<String, String> {
+        let mut voters = self.config.members.clone();
+        for entry in &state.persistent.log {
+            match &entry.command {
+                MetadataCommand::AddMember { node, url } => {
+                    voters.insert(node.clone(), url.clone());
+                }
+                MetadataCommand::RemoveMember { node } => {
+                    voters.remove(node);
+                }
+                _ => {}
+            }
+        }
+        voters
+    }
+
+    /// Nodes the leader sends appends to: the voters, and removed nodes that haven't stored
+    /// their removal yet, so they learn of it and stop calling elections.
+    fn replication_targets(&self, state: &RaftState) -> BTreeMap<String, String> {
+        let mut targets = self.voters(state);
+        let mut urls = self.config.members.clone();
+        for (index, entry) in (1..).zip(&state.persistent.log) {
+            match &entry.command {
+                MetadataCommand::AddMember { node, url } => {
+                    urls.insert(node.clone(), url.clone());
+                }
+                MetadataCommand::RemoveMember { node } if state.match_index.get(node).copied().unwrap_or(0) < index => {
+                    if let Some(url) = urls.get(node) {
+                        targets.insert(node.clone(), url.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        targets
+    }
+
+    /// Moves to `term` as a follower, forgetting the vote of an older term.
+    fn step_down(&self, state: &mut RaftState, term: u64) -> ServiceResult<()> {
+        if term > state.persistent.current_term {
+            state.persistent.current_term = term;
+            state.persistent.voted_for = None;
+            self.persist(state)?;
+        }
+        if state.role != Role::Follower {
+            state.role = Role::Follower;
+            self.reset_election_deadline(state);
+        }
+        Ok(())
+    }
+
+    pub fn handle_vote(&self, req: &VoteRequest) -> ServiceResult<VoteResponse> {
+        let mut state = self.lock();
+        // A node that stopped hearing from a live leader (e.g. one just removed) must not
+        // depose it: votes are only considered once the leader has been silent for a timeout
+        let leader_alive = state.role == Role::Leader || state.leader_contact.is_some_and(|t| t.elapsed() < self.config.election_timeout);
+        if leader_alive {
+            return Ok(VoteResponse { term: state.persistent.current_term, granted: false });
+        }
+        if req.term > state.persistent.current_term {
+            self.step_down(&mut state, req.term)?;
+        }
+        let up_to_date = (req.last_log_term, req.last_log_index) >= (state.term_at(state.last_index()), state.last_index());
+        let free = state.persistent.voted_for.as_ref().is_none_or(|v| v == &req.candidate);
+        let granted = req.term == state.persistent.current_term && free && up_to_date;
+        if granted {
+            state.persistent.voted_for = Some(req.candidate.clone());
+            self.persist(&state)?;
+            self.reset_election_deadline(&mut state);
+        }
+        Ok(VoteResponse { term: state.persistent.current_term, granted })
+    }
+
+    pub fn handle_append(&self, req: AppendRequest) -> ServiceResult<AppendResponse> {
+        let mut state = self.lock();
+        let term = state.persistent.current_term;
+        if req.term < term {
+            return Ok(AppendResponse { term, success: false, match_index: 0 });
+        }
+        if req.term > term || state.role != Role::Follower {
+            self.step_down(&mut state, req.term)?;
+        }
+        state.leader = Some(req.leader.clone());
+        state.leader_contact = Some(Instant::now());
+        self.reset_election_deadline(&mut state);
+        let term = state.persistent.current_term;
+
+        if req.prev_log_index > state.last_index() || state.term_at(req.prev_log_index) != req.prev_log_term {
+            let hint = req.prev_log_index.saturating_sub(1).min(state.last_index());
+            return Ok(AppendResponse { term, success: false, match_index: hint });
+        }
+        let match_index = req.prev_log_index + req.entries.len() as u64;
+        let mut changed = false;
+        for (index, entry) in (req.prev_log_index + 1..).zip(req.entries) {
+            if index <= state.last_index() {
+                if state.term_at(index) == entry.term {
+                    continue;
+                }
+                // A conflicting entry was never committed; drop it and everything after it
+                state.persistent.log.truncate(index as usize - 1);
+            }
+            state.persistent.log.push(entry);
+            changed = true;
+        }
+        if changed {
+            self.persist(&state)?;
+        }
+        if req.leader_commit > state.commit_index {
+            state.commit_index = req.leader_commit.min(match_index);
+            state.apply_committed();
+            self.applied.send_replace(state.last_applied);
+        }
+        Ok(AppendResponse { term, success: true, match_index })
+    }
+
+    /// Appends `command` as the leader and waits until it is committed, returning the
+    /// metadata with it applied. Other nodes answer `Unavailable` naming the leader.
+    pub async fn propose(&self, command: MetadataCommand) -> ServiceResult<ClusterMetadata> {
+        let (index, term) = {
+            let mut state = self.lock();
+            if state.role != Role::Leader {
+                let leader = state.leader.clone();
+                let url = leader.as_ref().and_then(|l| self.voters(&state).get(l).cloned());
+                return Err(ServiceError::Unavailable(match (leader, url) {
+                    (Some(leader), Some(url)) => format!("not the metadata leader; send changes to {} at {}", leader, url),
+                    _ => "no metadata leader elected yet; retry shortly".to_string(),
+                }));
+            }
+            // Checked against the state including entries not committed yet
+            let mut pending = state.metadata.clone();
+            for entry in &state.persistent.log[state.last_applied as usize..] {
+                pending.apply(&entry.command);
+            }
+            pending.check(&command).map_err(ServiceError::Invalid)?;
+            let term = state.persistent.current_term;
+            state.persistent.log.push(LogEntry { term, command });
+            self.persist(&state)?;
+            self.advance_commit(&mut state);
+            (state.last_index(), term)
+        };
+        self.replicate_now.notify_one();
+
+        let mut applied = self.applied.subscribe();
+        if tokio::time::timeout(PROPOSE_TIMEOUT, applied.wait_for(|a| *a >= index)).await.is_err() {
+            return Err(ServiceError::Unavailable("change not committed in time: no majority reachable".to_string()));
+        }
+        let state = self.lock();
+        match state.term_at(index) == term {
+            true => Ok(state.metadata.clone()),
+            false => Err(ServiceError::Unavailable("leadership changed before the change committed; retry".to_string())),
+        }
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        let state = self.lock();
+        let voters = self.voters(&state);
+        RaftStatus {
+            node_id: self.config.node_id.clone(),
+            role: state.role,
+            term: state.persistent.current_term,
+            leader: state.leader.clone(),
+            leader_url: state.leader.as_ref().and_then(|l| voters.get(l).cloned()),
+            commit_index: state.commit_index,
+            last_applied: state.last_applied,
+            log_entries: state.last_index(),
+            voters,
+            metadata: state.metadata.clone(),
+        }
+    }
+
+    /// Committed metadata.
+    pub fn metadata(&self) -> ClusterMetadata {
+        self.lock().metadata.clone()
+    }
+
+    /// Commits the latest entry of the current term a majority of voters stores.
+    fn advance_commit(&self, state: &mut RaftState) {
+        let voters = self.voters(state);
+        let term = state.persistent.current_term;
+        for index in (state.commit_index + 1..=state.last_index()).rev() {
+            if state.term_at(index) != term {
+                break;
+            }
+            let stored = voters
+                .keys()
+                .filter(|node| match *node == &self.config.node_id {
+                    true => true,
+                    false => state.match_index.get(*node).is_some_and(|m| *m >= index),
+                })
+                .count();
+            if stored * 2 > voters.len() {
+                state.commit_index = index;
+                break;
+            }
+        }
+        state.apply_committed();
+        self.applied.send_replace(state.last_applied);
+        if !voters.contains_key(&self.config.node_id) && state.role == Role::Leader && state.last_applied >= state.last_index() {
+            // Removed from the cluster: hand over once the removal is committed
+            state.role = Role::Follower;
+            state.leader = None;
+        }
+    }
+
+    fn become_leader(&self, state: &mut RaftState) -> ServiceResult<()> {
+        state.role = Role::Leader;
+        state.leader = Some(self.config.node_id.clone());
+        let next = state.last_index() + 1;
+        state.next_index = self.voters(state).into_keys().map(|n| (n, next)).collect();
+        state.match_index.clear();
+        let term = state.persistent.current_term;
+        if state.persistent.log.is_empty() {
+            for (node, url) in self.config.members.clone() {
+                state.persistent.log.push(LogEntry { term, command: MetadataCommand::AddMember { node, url } });
+            }
+        } else {
+            state.persistent.log.push(LogEntry { term, command: MetadataCommand::Noop });
+        }
+        self.persist(state)?;
+        self.advance_commit(state);
+        log::info!("metadata: {} is leader for term {}", self.config.node_id, term);
+        Ok(())
+    }
+
+    async fn campaign(&self) -> ServiceResult<()> {
+        let (req, peers) = {
+            let mut state = self.lock();
+            state.persistent.current_term += 1;
+            state.persistent.voted_for = Some(self.config.node_id.clone());
+            state.role = Role::Candidate;
+            state.leader = None;
+            self.persist(&state)?;
+            self.reset_election_deadline(&mut state);
+            let req = VoteRequest {
+                term: state.persistent.current_term,
+                candidate: self.config.node_id.clone(),
+                last_log_index: state.last_index(),
+                last_log_term: state.term_at(state.last_index()),
+            };
+            let peers: Vec<String> = self.voters(&state).into_iter().filter(|(n, _)| n != &self.config.node_id).map(|(_, url)| url).collect();
+            (req, peers)
+        };
+        let votes = futures_util::future::join_all(peers.iter().map(|url| self.call::<_, VoteResponse>(url, "/cluster/raft/vote", &req))).await;
+
+        let mut state = self.lock();
+        if state.persistent.current_term != req.term || state.role != Role::Candidate {
+            return Ok(());
+        }
+        let mut granted = 1;
+        for vote in votes.into_iter().flatten() {
+            if vote.term > state.persistent.current_term {
+                return self.step_down(&mut state, vote.term);
+            }
+            granted += vote.granted as usize;
+        }
+        if granted * 2 > self.voters(&state).len() {
+            self.become_leader(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// One round of appends to every other node, handling the answers once all are in.
+    async fn replicate(&self) -> ServiceResult<()> {
+        let requests: Vec<(String, String, AppendRequest)> = {
+            let mut state = self.lock();
+            if state.role != Role::Leader {
+                return Ok(());
+            }
+            let last = state.last_index();
+            let mut requests = Vec::new();
+            for (node, url) in self.replication_targets(&state) {
+                if node == self.config.node_id {
+                    continue;
+                }
+                let next = *state.next_index.entry(node.clone()).or_insert(last + 1);
+                let prev = next.saturating_sub(1);
+                let end = (prev as usize + MAX_APPEND_ENTRIES).min(last as usize);
+                requests.push((
+                    node,
+                    url,
+                    AppendRequest {
+                        term: state.persistent.current_term,
+                        leader: self.config.node_id.clone(),
+                        prev_log_index: prev,
+                        prev_log_term: state.term_at(prev),
+                        entries: state.persistent.log[prev as usize..end].to_vec(),
+                        leader_commit: state.commit_index,
+                    },
+                ));
+            }
+            requests
+        };
+        let answers = futures_util::future::join_all(
+            requests.iter().map(|(_, url, req)| self.call::<_, AppendResponse>(url, "/cluster/raft/append", req)),
+        )
+        .await;
+
+        let mut state = self.lock();
+        for ((node, _, req), answer) in requests.into_iter().zip(answers) {
+            let Ok(answer) = answer else { continue };
+            if answer.term > state.persistent.current_term {
+                return self.step_down(&mut state, answer.term);
+            }
+            if state.role != Role::Leader || state.persistent.current_term != req.term {
+                return Ok(());
+            }
+            if answer.success {
+                let matched = state.match_index.entry(node.clone()).or_insert(0);
+                *matched = (*matched).max(answer.match_index);
+                let next = *matched + 1;
+                state.next_index.insert(node, next);
+            } else {
+                let next = (answer.match_index + 1).min(req.prev_log_index).max(1);
+                state.next_index.insert(node, next);
+            }
+        }
+        self.advance_commit(&mut state);
+        Ok(())
+    }
+
+    async fn call<Req: Serialize, Resp: serde::de::DeserializeOwned>(&self, url: &str, path: &str, req: &Req) -> anyhow::Result<Resp> {
+        let url = format!("{}{}", url.trim_end_matches('/'), path);
+        let timeout = self.config.heartbeat.max(Duration::from_millis(200));
+        let mut request = self.http.post(url).json(req).timeout(timeout);
+        if let Some(secret) = &self.config.secret {
+            request = request.header(CLUSTER_SECRET_HEADER, secret);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+
+    /// Elections and heartbeats until the process exits.
+    pub(crate) async fn run(self: Arc<Self>) {
+        loop {
+            let (role, campaign) = {
+                let state = self.lock();
+                let member = self.voters(&state).contains_key(&self.config.node_id);
+                (state.role, member && Instant::now() >= state.election_deadline)
+            };
+            let result = match role {
+                Role::Leader => {
+                    let replicated = self.replicate().await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.config.heartbeat) => {}
+                        _ = self.replicate_now.notified() => {}
+                    }
+                    replicated
+                }
+                _ if campaign => self.campaign().await,
+                _ => {
+                    tokio::time::sleep(self.config.heartbeat / 2).await;
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                log::error!("metadata raft error: {}", e);
+                tokio::time::sleep(self.config.heartbeat).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Ingest-time redaction of personal data from post bodies: email addresses, phone numbers and
+//! custom patterns are replaced before the body is analyzed and stored, so they can't be found
+//! by search or read back from the doc store.
+//!
+//! With `keep_original` the unredacted body goes to the stored-only `body_original` field
+//! instead; it is never indexed and is left out of search, `/latest` and default exports.
+
+use regex::Regex;
+
+const EMAIL: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}";
+// Optional country code, then at least eight digits in groups split by spaces, dots, dashes
+// or an area code in parentheses
+const PHONE: &str = r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)[\s.-]?|\d{2,4}[\s.-])?\d{3,4}[\s.-]?\d{4}\b";
+
+/// What to redact; see [`Redactor`].
+#[derive(Debug, Clone, Default)]
+pub struct RedactionConfig {
+    pub emails: bool,
+    pub phones: bool,
+    /// Extra regular expressions, replaced with `[redacted]`
+    pub patterns: Vec<String>,
+    /// Keep the unredacted body in the restricted `body_original` field
+    pub keep_original: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    rules: Vec<(Regex, &'static str)>,
+    pub keep_original: bool,
+}
+
+impl Redactor {
+    /// Compiles the configured patterns; an invalid custom pattern is an error.
+    pub fn new(config: &RedactionConfig) -> Result<Self, regex::Error> {
+        let mut rules = Vec::new();
+        if config.emails {
+            rules.push((Regex::new(EMAIL)?, "[email]"));
+        }
+        if config.phones {
+            rules.push((Regex::new(PHONE)?, "[phone]"));
+        }
+        for pattern in &config.patterns {
+            rules.push((Regex::new(pattern)?, "[redacted]"));
+        }
+        Ok(Redactor { rules, keep_original: config.keep_original })
+    }
+
+    /// `text` with every match replaced, or `None` when nothing matched.
+    pub fn redact(&self, text: &str) -> Option<String> {
+        let mut redacted = None;
+        for (pattern, replacement) in &self.rules {
+            let current = redacted.as_deref().unwrap_or(text);
+            if pattern.is_match(current) {
+                redacted = Some(pattern.replace_all(current, *replacement).into_owned());
+            }
+        }
+        redacted
+    }
+}
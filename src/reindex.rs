@@ -0,0 +1,322 @@
+//! Rebuilding the posts tiers with other analyzers: `POST /reindex` streams every stored post
+//! of the hot and archive indexes into new indexes created with the analyzers asked for, then
+//! swaps the writers and readers over to them. Changing the n-gram sizes of `title` is
+//! registering an analyzer with them (`POST /analyzers`) and reindexing with it, instead of
+//! deleting the index and replaying the source data.
+//!
+//! Posts are rebuilt from their stored fields through this instance's ingest pipeline, keeping
+//! their `_indexed_at`. Post writes are refused with 503 while the copy runs, and merges held
+//! back; a write that got past the check before it started is noticed from the committed
+//! segments when swapping, and the reindex is given up as a conflict with the indexes left as
+//! they were. On disk the new
+//! indexes are built in `<path>.reindex` next to the old ones and renamed into their place.
+//!
+//! Reindexing is also how indexes created by older versions pick up schema changes, such as
+//! `create_at` becoming indexed and fast: the fields they hold differently are reported at
+//! startup and in `/stats`, and `upgrade_schema` reindexes them while opening.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::DocSetCollector;
+use tantivy::indexer::{LogMergePolicy, NoMergePolicy};
+use tantivy::query::AllQuery;
+use tantivy::schema::{FieldType, Schema, Value};
+use tantivy::{DocAddress, Index, IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, SegmentId, TantivyDocument};
+
+use crate::archive::{self, ArchiveTier};
+use crate::error::{ServiceError, ServiceResult};
+use crate::indexes::PostsSpec;
+use crate::nested::posts_only;
+use crate::schema::{from_document, index_post_at, open_index, posts_schema, INDEXED_AT_FIELD};
+use crate::service::{index_settings, SearchService};
+use crate::now_secs;
+
+/// Body of `POST /reindex`; analyzers left out stay as they are.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ReindexRequest {
+    #[serde(default)]
+    pub title_analyzer: Option<String>,
+    #[serde(default)]
+    pub body_analyzer: Option<String>,
+    #[serde(default)]
+    pub features_analyzer: Option<String>,
+}
+
+/// What a reindex did.
+#[derive(Serialize, Debug, Clone)]
+pub struct ReindexReport {
+    pub title_analyzer: String,
+    pub body_analyzer: String,
+    pub features_analyzer: String,
+    /// Posts copied into the new hot index
+    pub posts: u64,
+    /// Posts copied into the new archive
+    pub archived: u64,
+    pub took_ms: u64,
+}
+
+/// Clears the reindexing flag however the reindex ends.
+struct Running<'a>(&'a AtomicBool);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// The analyzer of the text or JSON field `name`.
+fn field_analyzer(schema: &Schema, name: &str) -> Option<String> {
+    let entry = schema.get_field_entry(schema.get_field(name).ok()?);
+    let indexing = match entry.field_type() {
+        FieldType::Str(options) => options.get_indexing_options(),
+        FieldType::JsonObject(options) => options.get_text_indexing_options(),
+        _ => None,
+    };
+    indexing.map(|indexing| indexing.tokenizer().to_string())
+}
+
+/// Fields of `expected` that `existing` lacks or holds other than indexed, stored or fast as
+/// `expected` has them; analyzers aside.
+fn outdated_fields(existing: &Schema, expected: &Schema) -> Vec<String> {
+    expected
+        .fields()
+        .filter(|(field, entry)| match existing.get_field(entry.name()) {
+            Ok(at) => {
+                let (old, new) = (existing.get_field_entry(at), expected.get_field_entry(*field));
+                (old.is_indexed(), old.is_stored(), old.is_fast()) != (new.is_indexed(), new.is_stored(), new.is_fast())
+            }
+            Err(_) => true,
+        })
+        .map(|(_, entry)| entry.name().to_string())
+        .collect()
+}
+
+/// The posts schema a reindex would build for `existing`, keeping its analyzers and adding
+/// the fields in `posts`.
+fn current_schema(existing: &Schema, posts: &PostsSpec) -> Schema {
+    let analyzer = |field| field_analyzer(existing, field).unwrap_or_else(|| "default".to_string());
+    posts.extend(posts_schema(&analyzer("title"), &analyzer("body"), &analyzer("features")))
+}
+
+/// `path` with `.<suffix>` appended to its last component.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// The committed segments of `writer`'s index with their documents and deletes.
+fn committed_segments(writer: &IndexWriter) -> tantivy::Result<Vec<(SegmentId, u32, u32)>> {
+    let mut segments: Vec<_> = writer.index().searchable_segment_metas()?.iter().map(|m| (m.id(), m.max_doc(), m.num_deleted_docs())).collect();
+    segments.sort();
+    Ok(segments)
+}
+
+fn open_reader(index: &Index) -> tantivy::Result<IndexReader> {
+    index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()
+}
+
+/// Replaces the index behind `writer` with `built`, renaming `staged` to `path` first when it
+/// was built on disk, and returns the index the new writer writes to.
+fn swap_index(writer: &mut IndexWriter, built: Index, staged: Option<&Path>, path: &Path, heap_bytes: usize) -> ServiceResult<Index> {
+    // The old writer has to stop before its directory moves; the caller holds the lock, so the
+    // placeholder never sees a write
+    let placeholder = Index::create_in_ram(built.schema()).writer_with_num_threads(1, 15_000_000)?;
+    std::mem::replace(writer, placeholder).wait_merging_threads()?;
+    let index = match staged {
+        None => built,
+        Some(staged) => {
+            let old = sibling(path, "old");
+            if old.exists() {
+                std::fs::remove_dir_all(&old)?;
+            }
+            std::fs::rename(path, &old)?;
+            std::fs::rename(staged, path)?;
+            // Searchers still over the old segments keep their open files
+            std::fs::remove_dir_all(&old)?;
+            let mut index = open_index(&path.to_path_buf(), built.schema(), built.settings().clone(), false)?;
+            index.set_tokenizers(built.tokenizers().clone());
+            index
+        }
+    };
+    *writer = index.writer(heap_bytes)?;
+    Ok(index)
+}
+
+impl SearchService {
+    /// Fields the hot or archive index holds differently from this version's posts schema
+    /// and the schema file's additions, as `<tier>.<field>`; empty once they are reindexed.
+    pub fn outdated_fields(&self) -> Vec<String> {
+        let posts = &self.config().schema_file.posts;
+        let tiers = [("hot", self.current_searcher.load_full()), ("archive", self.archive.current_searcher.load_full())];
+        tiers
+            .iter()
+            .flat_map(|(tier, searcher)| {
+                let schema = searcher.index().schema();
+                outdated_fields(&schema, &current_schema(&schema, posts)).into_iter().map(move |field| format!("{}.{}", tier, field))
+            })
+            .collect()
+    }
+
+    /// Reports outdated fields on startup, and reindexes them when `upgrade_schema` is set.
+    pub(crate) fn check_schema(&self) -> ServiceResult<()> {
+        let outdated = self.outdated_fields();
+        if outdated.is_empty() {
+            return Ok(());
+        }
+        if !self.config().upgrade_schema {
+            log::warn!(
+                "posts indexes predate this version's schema or the schema file ({}): range queries on create_at, /latest, \
+                 retention and queries on the fields the schema file adds may fail; restart with --upgrade-schema or POST /reindex to rebuild them",
+                outdated.join(", ")
+            );
+            return Ok(());
+        }
+        let report = self.reindex(&ReindexRequest::default())?;
+        log::info!("upgraded the posts schema ({}): reindexed {} posts and {} archived in {} ms", outdated.join(", "), report.posts, report.archived, report.took_ms);
+        Ok(())
+    }
+
+    /// Refuses post writes while a reindex runs.
+    pub(crate) fn check_not_reindexing(&self) -> ServiceResult<()> {
+        match self.reindexing.load(Ordering::Acquire) {
+            true => Err(ServiceError::Unavailable("posts are being reindexed; retry once POST /reindex returns".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    /// Copies the posts `searcher` shows into a new index of `schema`, at `path` or in RAM,
+    /// and returns it with the number of posts copied.
+    fn copy_posts(&self, searcher: &Searcher, schema: &Schema, settings: IndexSettings, path: Option<&Path>) -> ServiceResult<(Index, u64)> {
+        if let Some(path) = path.filter(|path| path.exists()) {
+            // left over by a reindex that failed
+            std::fs::remove_dir_all(path)?;
+        }
+        let in_memory = path.is_none();
+        let mut index = open_index(&path.map(Path::to_path_buf).unwrap_or_default(), schema.clone(), settings, in_memory)?;
+        // Custom analyzers registered later reach the new index too
+        index.set_tokenizers(searcher.index().tokenizers().clone());
+        let mut writer: IndexWriter = index.writer(self.config().writer_heap_bytes)?;
+
+        let old = searcher.index().schema();
+        let f_indexed_at = old.get_field(INDEXED_AT_FIELD).ok();
+        let mut addrs: Vec<DocAddress> = searcher.search(posts_only(&old, Box::new(AllQuery)).as_ref(), &DocSetCollector)?.into_iter().collect();
+        addrs.sort();
+        let mut copied = 0;
+        for addr in addrs {
+            let doc: TantivyDocument = searcher.doc(addr)?;
+            let Some(post) = from_document(&old, &doc) else { continue };
+            let indexed_at = f_indexed_at.and_then(|f| doc.get_first(f)).and_then(|v| v.as_i64()).unwrap_or_else(now_secs);
+            index_post_at(&mut writer, schema, &self.pipeline, post, indexed_at)?;
+            copied += 1;
+        }
+        writer.commit()?;
+        writer.wait_merging_threads()?;
+        Ok((index, copied))
+    }
+
+    /// Rebuilds the hot and archive tiers with the analyzers of `req` and swaps them in.
+    /// `Conflict` when another reindex runs or a post was written during the copy.
+    pub fn reindex(&self, req: &ReindexRequest) -> ServiceResult<ReindexReport> {
+        self.check_writable()?;
+        let current = self.schema();
+        let analyzer = |asked: &Option<String>, field: &str| match asked {
+            Some(name) if self.analyzers.is_known(name) => Ok(name.clone()),
+            Some(name) => Err(ServiceError::Invalid(format!("unknown analyzer for {}: {}; see GET /analyzers", field, name))),
+            None => field_analyzer(&current, field).ok_or_else(|| ServiceError::Internal(format!("{} is not an analyzed field", field))),
+        };
+        let title_analyzer = analyzer(&req.title_analyzer, "title")?;
+        let body_analyzer = analyzer(&req.body_analyzer, "body")?;
+        let features_analyzer = analyzer(&req.features_analyzer, "features")?;
+        let schema = self.config().schema_file.posts.extend(posts_schema(&title_analyzer, &body_analyzer, &features_analyzer));
+
+        if self.reindexing.swap(true, Ordering::AcqRel) {
+            return Err(ServiceError::Conflict("a reindex is already running".to_string()));
+        }
+        let _running = Running(&self.reindexing);
+        let started = Instant::now();
+        let config = self.config();
+        let staged = |path: &PathBuf| (!config.in_memory).then(|| sibling(path, "reindex"));
+        let staged = [staged(&config.index_path), staged(&config.archive_path)];
+
+        // Merges would change the segments `rebuild` compares
+        for writer in [self.writer(), self.archive.writer()] {
+            writer.set_merge_policy(Box::new(NoMergePolicy));
+        }
+        let rebuilt = self.rebuild(&schema, &staged);
+        for writer in [self.writer(), self.archive.writer()] {
+            writer.set_merge_policy(Box::<LogMergePolicy>::default());
+        }
+        let (posts, archived) = match rebuilt {
+            Ok(copied) => copied,
+            Err(e) => {
+                for path in staged.iter().flatten().filter(|path| path.exists()) {
+                    let _ = std::fs::remove_dir_all(path);
+                }
+                return Err(e);
+            }
+        };
+        Ok(ReindexReport {
+            title_analyzer,
+            body_analyzer,
+            features_analyzer,
+            posts,
+            archived,
+            took_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Copies both tiers into indexes of `schema`, built at the `staged` paths of the hot and
+    /// archive tiers or in RAM, and swaps them in. Returns the posts copied per tier.
+    fn rebuild(&self, schema: &Schema, [hot_staged, archive_staged]: &[Option<PathBuf>; 2]) -> ServiceResult<(u64, u64)> {
+        // `refresh` leaves both writers alone by now, so only writes change their segments
+        let (hot, hot_segments) = {
+            let mut writer = self.writer();
+            writer.commit()?;
+            let reader = self.reader.load();
+            reader.reload()?;
+            (reader.searcher(), committed_segments(&writer)?)
+        };
+        let (archive, archive_segments) = {
+            let mut writer = self.archive.writer();
+            writer.commit()?;
+            let reader = self.archive.reader.load();
+            reader.reload()?;
+            (reader.searcher(), committed_segments(&writer)?)
+        };
+
+        let config = self.config();
+        let (hot_index, posts) = self.copy_posts(&hot, schema, index_settings(config), hot_staged.as_deref())?;
+        let (archive_index, archived) = self.copy_posts(&archive, schema, ArchiveTier::settings(), archive_staged.as_deref())?;
+
+        // Hot before archive, as deletes take them
+        let mut writer = self.writer();
+        let mut archive_writer = self.archive.writer();
+        writer.commit()?;
+        archive_writer.commit()?;
+        if committed_segments(&writer)? != hot_segments || committed_segments(&archive_writer)? != archive_segments {
+            return Err(ServiceError::Conflict("posts were written during the reindex; run it again".to_string()));
+        }
+        let hot_index = swap_index(&mut writer, hot_index, hot_staged.as_deref(), &config.index_path, config.writer_heap_bytes)?;
+        let archive_index = swap_index(&mut archive_writer, archive_index, archive_staged.as_deref(), &config.archive_path, archive::WRITER_HEAP_BYTES)?;
+
+        let reader = open_reader(&hot_index)?;
+        self.current_searcher.store(Arc::new(reader.searcher()));
+        self.reader.store(Arc::new(reader));
+        // Opstamps start over with the new writer
+        self.committed_opstamp.store(writer.commit_opstamp(), Ordering::Release);
+        let reader = open_reader(&archive_index)?;
+        self.archive.current_searcher.store(Arc::new(reader.searcher()));
+        self.archive.reader.store(Arc::new(reader));
+        drop((writer, archive_writer));
+        self.commits.send_modify(|generation| *generation += 1);
+        Ok((posts, archived))
+    }
+}
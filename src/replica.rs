@@ -0,0 +1,378 @@
+//! Follower side of journal replication: a node started with a leader URL pulls the leader's
+//! [`journal`](crate::journal) and applies the entries to its own indexes, which clients can
+//! only search.
+//!
+//! A follower without a position (first start, a leader restart, or fallen behind the oldest
+//! entry the leader holds) downloads `/replication/snapshot` — every post of the leader with
+//! its tier, plus the journal offset it covers — replaces its posts with it, then long-polls
+//! `/replication/journal` from that offset. Index operations are applied as updates, so an
+//! entry applied twice (the snapshot may already hold the first few after its offset, and a
+//! crash between applying and saving the position replays them) leaves the same posts.
+//!
+//! The journal and snapshot carry every post unfiltered, so the leader only serves them to
+//! callers sending its replication key as [`REPLICATION_KEY_HEADER`] (or admin credentials);
+//! followers send [`FollowerConfig::key`].
+//!
+//! `GET /replication/status` tells how far behind the leader a follower is and answers 503
+//! once it is stale — further behind than [`FollowerConfig::max_lag_entries`] or
+//! [`FollowerConfig::max_lag`], or without a position yet — so load balancers can take it out
+//! of rotation until it catches up.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tantivy::{IndexWriter, Term};
+
+use crate::batch::BatchOp;
+use crate::error::ServiceResult;
+use crate::journal::{JournalEntry, JournalOp, SnapshotHeader, SnapshotPost};
+use crate::schema::{index_post, IngestPipeline};
+use crate::service::{SearchService, Tier};
+use crate::now_secs;
+
+/// Journal entries fetched per request.
+const JOURNAL_BATCH: usize = 1000;
+
+/// Pause after a failed round before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Header carrying the replication key.
+pub const REPLICATION_KEY_HEADER: &str = "X-Replication-Key";
+
+#[derive(Debug, Clone)]
+pub struct FollowerConfig {
+    /// Base URL of the leader, e.g. `http://10.0.0.1:8080`
+    pub leader: String,
+    /// The leader's replication key, sent as [`REPLICATION_KEY_HEADER`]
+    pub key: Option<String>,
+    /// JSON file keeping the applied journal position across restarts; not persisted when `None`
+    pub state_path: Option<PathBuf>,
+    /// How long one journal request waits on the leader for new entries
+    pub poll_wait: Duration,
+    /// Journal entries the follower may be behind before it counts as stale
+    pub max_lag_entries: u64,
+    /// How long the follower may go without catching up, or without reaching the leader,
+    /// before it counts as stale
+    pub max_lag: Duration,
+}
+
+/// The last journal entry applied, in the journal the leader identified by `journal_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicaPosition {
+    pub journal_id: String,
+    pub offset: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReplicaStatus {
+    pub leader: String,
+    pub position: Option<ReplicaPosition>,
+    /// Committed offset the leader reported last
+    pub leader_offset: u64,
+    pub snapshots_loaded: u64,
+    pub entries_applied: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sync_at: Option<i64>,
+    /// Last round that reached the leader and applied what it got
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success_at: Option<i64>,
+    /// Last round that ended with nothing left to apply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caught_up_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Response of `GET /replication/status`.
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicationStatus {
+    #[serde(flatten)]
+    pub replica: ReplicaStatus,
+    /// Opstamp of the local commit the searcher shows
+    pub applied_opstamp: u64,
+    /// Generation of the local searcher, bumped by every swap
+    pub searcher_generation: u64,
+    /// Journal entries the leader has committed that aren't applied here
+    pub lag_entries: u64,
+    /// Seconds since the follower was last caught up; 0 when it is
+    pub lag_secs: i64,
+    /// Why the follower is stale; empty when it isn't
+    pub stale_reasons: Vec<String>,
+    pub stale: bool,
+}
+
+pub struct Replica {
+    config: FollowerConfig,
+    http: reqwest::Client,
+    status: Mutex<ReplicaStatus>,
+    started_at: i64,
+}
+
+impl Replica {
+    /// Resumes from the position saved in `state_path`, if any.
+    pub fn open(config: FollowerConfig) -> anyhow::Result<Self> {
+        let position = match &config.state_path {
+            Some(path) if path.exists() => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+            _ => None,
+        };
+        let status = ReplicaStatus { leader: config.leader.clone(), position, ..ReplicaStatus::default() };
+        Ok(Replica { config, http: reqwest::Client::new(), status: Mutex::new(status), started_at: now_secs() })
+    }
+
+    pub fn config(&self) -> &FollowerConfig {
+        &self.config
+    }
+
+    pub fn status(&self) -> ReplicaStatus {
+        self.status.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ReplicaStatus)) {
+        f(&mut self.status.lock().unwrap_or_else(|p| p.into_inner()));
+    }
+
+    /// A GET of `path` on the leader, with the replication key.
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.get(format!("{}{}", self.config.leader.trim_end_matches('/'), path));
+        match &self.config.key {
+            Some(key) => request.header(REPLICATION_KEY_HEADER, key),
+            None => request,
+        }
+    }
+
+    /// Records `position` as applied, persisting it first.
+    fn advance(&self, position: Option<ReplicaPosition>) -> anyhow::Result<()> {
+        if let Some(path) = &self.config.state_path {
+            match &position {
+                Some(position) => std::fs::write(path, serde_json::to_string(position)?)?,
+                None if path.exists() => std::fs::remove_file(path)?,
+                None => {}
+            }
+        }
+        self.update(|s| s.position = position);
+        Ok(())
+    }
+
+    /// Where a snapshot is downloaded before it is applied.
+    fn spool_path(&self) -> PathBuf {
+        match &self.config.state_path {
+            Some(path) => path.with_extension("snapshot.ndjson"),
+            None => std::env::temp_dir().join(format!("tantivy-demo-snapshot-{}.ndjson", std::process::id())),
+        }
+    }
+}
+
+/// Replaces every post with the snapshot `lines`, each in the tier it has on the leader,
+/// committing both tiers only once all of them are added.
+fn load_snapshot_posts(
+    writer: &mut IndexWriter,
+    archive: &mut IndexWriter,
+    pipeline: &IngestPipeline,
+    lines: impl Iterator<Item = std::io::Result<String>>,
+) -> anyhow::Result<u64> {
+    let (schema, archive_schema) = (writer.index().schema(), archive.index().schema());
+    writer.delete_all_documents()?;
+    archive.delete_all_documents()?;
+    let mut loaded = 0;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line)? {
+            SnapshotPost { tier: Tier::Hot, post } => index_post(writer, &schema, pipeline, post)?,
+            SnapshotPost { tier: Tier::Archive, post } => index_post(archive, &archive_schema, pipeline, post)?,
+        };
+        loaded += 1;
+    }
+    writer.commit()?;
+    archive.commit()?;
+    Ok(loaded)
+}
+
+impl SearchService {
+    /// One replication round: loads a snapshot when there is no position yet, otherwise
+    /// applies the journal entries the leader has after it, waiting up to `poll_wait` for
+    /// some. Returns how many posts or entries were applied. A no-op unless following.
+    pub async fn sync_replica(&self) -> anyhow::Result<u64> {
+        let Some(replica) = &self.replica else { return Ok(0) };
+        let result = match replica.status().position {
+            Some(position) => self.pull_journal(replica, position).await,
+            None => self.pull_snapshot(replica).await,
+        };
+        replica.update(|s| {
+            let now = now_secs();
+            s.last_sync_at = Some(now);
+            s.last_error = result.as_ref().err().map(|e| e.to_string());
+            if result.is_ok() {
+                s.last_success_at = Some(now);
+                if s.position.as_ref().is_some_and(|p| p.offset >= s.leader_offset) {
+                    s.caught_up_at = Some(now);
+                }
+            }
+        });
+        result
+    }
+
+    /// How far behind its leader this follower is, and whether that makes it stale; `None`
+    /// unless following.
+    pub fn replication_status(&self) -> Option<ReplicationStatus> {
+        let replica = self.replica.as_ref()?;
+        let status = replica.status();
+        let now = now_secs();
+        let lag_entries = status.leader_offset.saturating_sub(status.position.as_ref().map_or(0, |p| p.offset));
+        let lag_secs = match (&status.position, lag_entries) {
+            (Some(_), 0) => 0,
+            _ => now - status.caught_up_at.unwrap_or(replica.started_at),
+        };
+        let max_lag = replica.config.max_lag.as_secs() as i64;
+        let mut stale_reasons = Vec::new();
+        if status.position.is_none() {
+            stale_reasons.push("no snapshot loaded yet".to_string());
+        }
+        if lag_entries > replica.config.max_lag_entries {
+            stale_reasons.push(format!("{} entries behind, over {}", lag_entries, replica.config.max_lag_entries));
+        }
+        if lag_secs > max_lag {
+            stale_reasons.push(format!("not caught up for {}s, over {}s", lag_secs, max_lag));
+        }
+        let unreachable = now - status.last_success_at.unwrap_or(replica.started_at);
+        if unreachable > max_lag {
+            stale_reasons.push(format!("no successful sync for {}s, over {}s", unreachable, max_lag));
+        }
+        Some(ReplicationStatus {
+            replica: status,
+            applied_opstamp: self.committed_opstamp(),
+            searcher_generation: self.searcher().generation().generation_id(),
+            lag_entries,
+            lag_secs,
+            stale: !stale_reasons.is_empty(),
+            stale_reasons,
+        })
+    }
+
+    async fn pull_snapshot(&self, replica: &Replica) -> anyhow::Result<u64> {
+        let mut response = replica.get("/replication/snapshot").send().await?.error_for_status()?;
+        let spool = replica.spool_path();
+        let mut file = std::fs::File::create(&spool)?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk)?;
+        }
+        drop(file);
+
+        let mut lines = std::io::BufReader::new(std::fs::File::open(&spool)?).lines();
+        let header: SnapshotHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => anyhow::bail!("empty snapshot"),
+        };
+        let loaded = {
+            let mut writer = self.writer();
+            let mut archive = self.archive.writer();
+            let loaded = load_snapshot_posts(&mut writer, &mut archive, &self.pipeline, lines);
+            if loaded.is_err() {
+                if let Err(rb) = writer.rollback().and_then(|_| archive.rollback()) {
+                    log::error!("snapshot rollback error: {}", rb);
+                }
+            }
+            loaded
+        };
+        std::fs::remove_file(&spool)?;
+        let loaded = loaded?;
+        self.refresh()?;
+        log::info!("replica loaded a snapshot of {} posts at offset {} of journal {}", loaded, header.offset, header.journal_id);
+        replica.advance(Some(ReplicaPosition { journal_id: header.journal_id, offset: header.offset }))?;
+        replica.update(|s| {
+            s.snapshots_loaded += 1;
+            s.leader_offset = s.leader_offset.max(header.offset);
+        });
+        Ok(loaded)
+    }
+
+    async fn pull_journal(&self, replica: &Replica, position: ReplicaPosition) -> anyhow::Result<u64> {
+        let wait = replica.config.poll_wait;
+        let response = replica
+            .get("/replication/journal")
+            .query(&[("from", position.offset + 1), ("limit", JOURNAL_BATCH as u64), ("wait_ms", wait.as_millis() as u64)])
+            .timeout(wait + Duration::from_secs(30))
+            .send()
+            .await?;
+        let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let journal_id = header("x-journal-id");
+        if response.status() == reqwest::StatusCode::GONE || journal_id.as_deref() != Some(position.journal_id.as_str()) {
+            // The leader restarted or dropped the entries after our position: start over
+            log::warn!("replica position {:?} is gone on the leader; loading a new snapshot", position);
+            replica.advance(None)?;
+            return Ok(0);
+        }
+        let leader_offset = header("x-journal-committed").and_then(|v| v.parse().ok()).unwrap_or(0);
+        let body = response.error_for_status()?.text().await?;
+        let entries = body
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<JournalEntry>, _>>()?;
+        replica.update(|s| s.leader_offset = leader_offset);
+        let Some(last) = entries.last().map(|e| e.offset) else { return Ok(0) };
+        let applied = entries.len() as u64;
+        self.apply_journal(entries)?;
+        replica.advance(Some(ReplicaPosition { journal_id: position.journal_id, offset: last }))?;
+        replica.update(|s| s.entries_applied += applied);
+        Ok(applied)
+    }
+
+    /// Applies journal entries in order and makes them searchable. Index operations replace
+    /// a post with the same id in either tier, deletes reach both tiers and archived posts
+    /// move from the hot index into the archive, like on the leader.
+    pub(crate) fn apply_journal(&self, entries: Vec<JournalEntry>) -> ServiceResult<()> {
+        for entry in entries {
+            match entry.op {
+                JournalOp::Batch { ops } => {
+                    let mut writer = self.writer();
+                    let schema = writer.index().schema();
+                    let f_id = schema.get_field("id").unwrap();
+                    for op in ops {
+                        match op {
+                            BatchOp::Index { doc } | BatchOp::Update { doc } => {
+                                self.archive.writer().delete_term(Term::from_field_text(f_id, &doc.id));
+                                writer.delete_term(Term::from_field_text(f_id, &doc.id));
+                                index_post(&mut writer, &schema, &self.pipeline, doc)?;
+                            }
+                            BatchOp::Delete { id } => {
+                                self.archive.writer().delete_term(Term::from_field_text(f_id, &id));
+                                writer.delete_term(Term::from_field_text(f_id, &id));
+                            }
+                        }
+                    }
+                }
+                JournalOp::Erase { request } => {
+                    self.erase_local(&request, false)?;
+                }
+                JournalOp::Archive { posts } => {
+                    let writer = self.writer();
+                    let mut archive = self.archive.writer();
+                    let schema = archive.index().schema();
+                    let f_id = schema.get_field("id").unwrap();
+                    for post in posts {
+                        writer.delete_term(Term::from_field_text(f_id, &post.id));
+                        archive.delete_term(Term::from_field_text(f_id, &post.id));
+                        index_post(&mut archive, &schema, &self.pipeline, post)?;
+                    }
+                }
+            }
+        }
+        self.refresh()
+    }
+
+    /// Replication rounds until the process exits, pausing after failures.
+    pub(crate) async fn run_follower(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.sync_replica().await {
+                log::error!("replication error: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Rule-driven deletion and archiving of old documents.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::query::{BooleanQuery, Occur, Query, RangeQuery};
+use tantivy::schema::Value;
+use tantivy::{Searcher, TantivyDocument, Term};
+
+use crate::batch::BatchOp;
+use crate::error::{ServiceError, ServiceResult};
+use crate::journal::JournalOp;
+use crate::nested::posts_only;
+use crate::query::parse_query;
+use crate::schema::from_document;
+use crate::service::SearchService;
+use crate::now_secs;
+
+const RETENTION_SAMPLE_IDS: usize = 5;
+
+/// A retention rule: documents matching `query` whose `create_at` is older than
+/// `older_than_days` are subject to `action`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionRule {
+    pub name: String,
+    #[serde(default = "match_all")]
+    pub query: String,
+    pub older_than_days: i64,
+    #[serde(default)]
+    pub action: RetentionAction,
+}
+
+fn match_all() -> String {
+    "*".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    #[default]
+    Delete,
+    /// Copy matches into the archive tier, then delete them from the hot index
+    Archive,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RetentionOutcome {
+    pub rule: String,
+    pub action: RetentionAction,
+    pub cutoff: i64,
+    pub matched: usize,
+    pub sample_ids: Vec<String>,
+    pub applied: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RetentionReport {
+    pub ran_at: i64,
+    pub dry_run: bool,
+    pub outcomes: Vec<RetentionOutcome>,
+}
+
+pub struct Retention {
+    pub rules: Vec<RetentionRule>,
+    pub dry_run: bool,
+    pub last_report: Mutex<Option<RetentionReport>>,
+}
+
+impl Retention {
+    pub fn new(rules: Vec<RetentionRule>, dry_run: bool) -> Self {
+        Retention { rules, dry_run, last_report: Mutex::new(None) }
+    }
+
+    pub fn last_report(&self) -> Option<RetentionReport> {
+        self.last_report.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+}
+
+/// Reads a JSON array of rules.
+pub fn load_rules(path: &PathBuf) -> anyhow::Result<Vec<RetentionRule>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+impl SearchService {
+    /// Evaluates every retention rule against the current searcher and, unless `dry_run`,
+    /// queues deletes of the posts each rule matches there; the deletes become visible after
+    /// the next commit. `archive` rules first copy and commit matches into the archive tier.
+    pub fn run_retention(&self, dry_run: bool) -> ServiceResult<RetentionReport> {
+        if !dry_run {
+            self.check_not_reindexing()?;
+        }
+        let guard = self.current_searcher.load();
+        let searcher: &Searcher = &guard;
+        let schema = searcher.index().schema();
+        let f_id = schema.get_field("id").unwrap();
+        let now = now_secs();
+
+        let mut outcomes = Vec::new();
+        for rule in &self.retention.rules {
+            let cutoff = now - rule.older_than_days * 86_400;
+            let selector = parse_query(searcher, &rule.query, &self.query_limits()).map_err(|e| match e {
+                ServiceError::Invalid(msg) => ServiceError::Invalid(format!("rule {}: {}", rule.name, msg)),
+                other => other,
+            })?;
+            let older = RangeQuery::new_i64_bounds(
+                "create_at".to_string(),
+                std::ops::Bound::Unbounded,
+                std::ops::Bound::Excluded(cutoff),
+            );
+            let query = BooleanQuery::new(vec![(Occur::Must, selector), (Occur::Must, Box::new(older))]);
+
+            let (matched, top) = searcher.search(&query, &(Count, TopDocs::with_limit(RETENTION_SAMPLE_IDS)))?;
+            let mut sample_ids = Vec::new();
+            for (_score, addr) in top {
+                let doc: TantivyDocument = searcher.doc(addr)?;
+                if let Some(id) = doc.get_first(f_id).and_then(|v| v.as_str()) {
+                    sample_ids.push(id.to_string());
+                }
+            }
+
+            let applied = !dry_run && matched > 0;
+            if applied {
+                self.apply_rule(searcher, rule.action, &query)?;
+            }
+            outcomes.push(RetentionOutcome {
+                rule: rule.name.clone(),
+                action: rule.action,
+                cutoff,
+                matched,
+                sample_ids,
+                applied,
+            });
+        }
+
+        let report = RetentionReport { ran_at: now, dry_run, outcomes };
+        *self.retention.last_report.lock().unwrap_or_else(|p| p.into_inner()) = Some(report.clone());
+        Ok(report)
+    }
+
+    /// Deletes or archives the posts `query` matches in `searcher` by id, with their nested
+    /// children, and journals that under the writer's lock like any other post write. Posts
+    /// written since `searcher` are left alone on the leader as on followers replaying the ids.
+    fn apply_rule(&self, searcher: &Searcher, action: RetentionAction, query: &dyn Query) -> ServiceResult<()> {
+        let schema = searcher.index().schema();
+        let f_id = schema.get_field("id").unwrap();
+        let mut posts = Vec::new();
+        for addr in searcher.search(posts_only(&schema, query.box_clone()).as_ref(), &DocSetCollector)? {
+            posts.extend(from_document(&schema, &searcher.doc(addr)?));
+        }
+        let writer = self.writer();
+        if action == RetentionAction::Archive {
+            self.archive.archive_matches(searcher, query)?;
+        }
+        for post in &posts {
+            writer.delete_term(Term::from_field_text(f_id, &post.id));
+        }
+        self.journal_write(&writer, || match action {
+            RetentionAction::Delete => JournalOp::Batch { ops: posts.into_iter().map(|post| BatchOp::Delete { id: post.id }).collect() },
+            RetentionAction::Archive => JournalOp::Archive { posts },
+        });
+        Ok(())
+    }
+}
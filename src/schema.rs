@@ -0,0 +1,425 @@
+//! Blog post schema, analyzers and document conversion.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use tantivy::query::QueryParser;
+use tantivy::schema::{JsonObjectOptions, Schema, FAST, INDEXED, STORED, STRING, OwnedValue, TextOptions, Value, TextFieldIndexing, IndexRecordOption};
+use tantivy::{Index, IndexSettings, IndexWriter, TantivyDocument};
+
+use crate::analyzers::builtin_analyzers;
+use crate::nested::{add_block, nested_documents, NESTED_FIELD};
+use crate::protected::ProtectedTerms;
+use crate::redact::Redactor;
+use crate::now_secs;
+
+/// When a post was last written, in seconds since the epoch; set by [`index_post`].
+pub const INDEXED_AT_FIELD: &str = "_indexed_at";
+/// [`content_hash`] of a post as it was last written; set by [`index_post`].
+pub const CONTENT_HASH_FIELD: &str = "_content_hash";
+/// Protected terms found in title and body, verbatim (see [`protected`](crate::protected)).
+pub const KEYWORDS_FIELD: &str = "keywords";
+/// Copies of the `features` values listed as path features, analyzed as paths; filled by
+/// [`index_post`].
+pub const PATHS_FIELD: &str = "paths";
+/// Word shingles of title and body; filled by [`add_shingles`].
+pub const SHINGLES_FIELD: &str = "shingles";
+
+/// Stored fields kept out of search results and default exports.
+pub const RESTRICTED_FIELDS: &[&str] = &["body_original", KEYWORDS_FIELD, PATHS_FIELD];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlogPost {
+    pub id: String,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub create_at: Option<i64>,
+    pub status: String,
+    pub features: serde_json::Value,
+    /// Id in the `authors` index, used to decorate hits with the author's profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<String>,
+    /// Groups allowed to see the post; empty means public (see [`auth`](crate::auth))
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_groups: Vec<String>,
+    /// Tenant owning the post; set by the server from the caller's API key (see
+    /// [`usage`](crate::usage))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// Identifies a post's content: hex SHA-256 of its compact JSON (fields in declaration order,
+/// `features` keys sorted, absent optional fields left out) without `tenant`, which the server
+/// stamps, and with an empty `id`, so the same content posted under two ids hashes the same
+/// (see [`dedupe`](crate::dedupe)). Taken before redaction, so it covers what the client sent.
+pub fn content_hash(post: &BlogPost) -> String {
+    let content = BlogPost { id: String::new(), tenant: None, ..post.clone() };
+    let json = serde_json::to_vec(&content).expect("posts always serialize");
+    ring::digest::digest(&ring::digest::SHA256, &json).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl BlogPost {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub fn create_schema() -> Schema {
+    posts_schema("zh_ngram", "zh_ngram", "default")
+}
+
+/// The posts schema with `title`, `body` and the string values of `features` (nested snippets
+/// included) analyzed by the given analyzers.
+pub fn posts_schema(title_analyzer: &str, body_analyzer: &str, features_analyzer: &str) -> Schema {
+    let mut schema_builder = Schema::builder();
+
+    // Per-field analyzers via TextOptions
+    let text = |analyzer: &str| {
+        let indexing = TextFieldIndexing::default()
+            .set_tokenizer(analyzer)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        TextOptions::default().set_indexing_options(indexing).set_stored()
+    };
+
+    let tags_indexing = TextFieldIndexing::default()
+        .set_tokenizer("whitespace_lc")
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    let tags_text = TextOptions::default()
+        .set_indexing_options(tags_indexing)
+        .set_stored();
+
+    schema_builder.add_text_field("id", STRING | STORED);
+    schema_builder.add_text_field("title", text(title_analyzer));
+    schema_builder.add_text_field("body", text(body_analyzer));
+    schema_builder.add_text_field("tags", tags_text);
+    schema_builder.add_i64_field("create_at", INDEXED | STORED | FAST);
+    schema_builder.add_text_field("status", STRING | STORED | FAST);
+    let features_indexing = TextFieldIndexing::default()
+        .set_tokenizer(features_analyzer)
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    schema_builder.add_json_field("features", JsonObjectOptions::default().set_indexing_options(features_indexing).set_stored().set_fast(None));
+    schema_builder.add_text_field("author_id", STRING | STORED);
+    schema_builder.add_text_field("allowed_groups", STRING | STORED | FAST);
+    schema_builder.add_text_field("tenant", STRING | STORED | FAST);
+    schema_builder.add_i64_field(INDEXED_AT_FIELD, INDEXED | STORED | FAST);
+    // Fast for `dedupe_by` (see `dedupe`)
+    schema_builder.add_text_field(CONTENT_HASH_FIELD, STRING | STORED | FAST);
+    // Unredacted body when redaction keeps originals; stored only (see `redact`)
+    schema_builder.add_text_field("body_original", STORED);
+    // Set only on nested child documents (see `nested`); stored so archived copies keep it
+    schema_builder.add_text_field(NESTED_FIELD, STRING | STORED | FAST);
+    // Protected terms; stored so archived and scrubbed copies keep them, but kept out of results
+    schema_builder.add_text_field(KEYWORDS_FIELD, STRING | STORED);
+    // File paths and URLs from `features`, each matched by its ancestors too; stored like keywords
+    let paths_indexing = TextFieldIndexing::default()
+        .set_tokenizer("path_hierarchy")
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    schema_builder.add_json_field(PATHS_FIELD, JsonObjectOptions::default().set_indexing_options(paths_indexing).set_stored());
+    // Word shingles of title and body for `shingle_boost`; unstored, see `add_shingles`
+    let shingles_indexing = TextFieldIndexing::default()
+        .set_tokenizer("shingles")
+        .set_index_option(IndexRecordOption::WithFreqs);
+    schema_builder.add_text_field(SHINGLES_FIELD, TextOptions::default().set_indexing_options(shingles_indexing));
+    schema_builder.build()
+}
+
+pub fn to_document(schema: &Schema, post: BlogPost) -> TantivyDocument {
+    let mut document = TantivyDocument::default();
+    let f_id = schema.get_field("id").unwrap();
+    let f_title = schema.get_field("title").unwrap();
+    let f_body = schema.get_field("body").unwrap();
+    let f_tags = schema.get_field("tags").unwrap();
+    let f_create_at = schema.get_field("create_at").unwrap();
+    let f_status = schema.get_field("status").unwrap();
+    let f_features = schema.get_field("features").unwrap();
+
+    document.add_text(f_id, post.id);
+    document.add_text(f_title, post.title);
+    document.add_text(f_body, post.body);
+    for tag in post.tags.into_iter() {
+        document.add_text(f_tags, tag);
+    }
+    if let Some(ts) = post.create_at {
+        document.add_i64(f_create_at, ts);
+    }
+    document.add_text(f_status, post.status);
+    // Indexes created before authors existed have no field to hold it
+    if let (Some(author_id), Ok(f_author_id)) = (post.author_id, schema.get_field("author_id")) {
+        document.add_text(f_author_id, author_id);
+    }
+    if let Ok(f_groups) = schema.get_field("allowed_groups") {
+        for group in post.allowed_groups {
+            document.add_text(f_groups, group);
+        }
+    }
+    if let (Some(tenant), Ok(f_tenant)) = (post.tenant, schema.get_field("tenant")) {
+        document.add_text(f_tenant, tenant);
+    }
+    let ov = OwnedValue::from(post.features);
+    match ov {
+        OwnedValue::Object(map) => {
+            document.add_object(f_features, map);
+        }
+        other => {
+            // Wrap non-object into an object under key "value" for JSON field
+            let mut map = std::collections::BTreeMap::new();
+            map.insert("value".to_string(), other);
+            document.add_object(f_features, map);
+        }
+    }
+
+    add_shingles(schema, &mut document);
+    document
+}
+
+/// Fills the shingles field from the document's title and body. The field isn't stored, so
+/// documents copied from their stored values (archiving, scrubbing) go through this again.
+/// Indexes created before shingles existed have no field to fill.
+pub fn add_shingles(schema: &Schema, document: &mut TantivyDocument) {
+    let Ok(f_shingles) = schema.get_field(SHINGLES_FIELD) else { return };
+    let text = |name: &str| {
+        let field = schema.get_field(name).ok()?;
+        document.get_first(field).and_then(|v| v.as_str()).map(str::to_string)
+    };
+    // Separately, so no shingle spans the end of the title and the start of the body
+    let texts: Vec<String> = [text("title"), text("body")].into_iter().flatten().collect();
+    for text in texts {
+        document.add_text(f_shingles, text);
+    }
+}
+
+/// Rebuilds the post a stored document was indexed from, with the unredacted body when it was
+/// kept. `None` for documents without an id.
+pub fn from_document(schema: &Schema, doc: &TantivyDocument) -> Option<BlogPost> {
+    let text = |name: &str| schema.get_field(name).ok().and_then(|f| doc.get_first(f)).and_then(|v| v.as_str()).map(str::to_string);
+    let texts = |name: &str| match schema.get_field(name) {
+        Ok(f) => doc.get_all(f).filter_map(|v| v.as_str()).map(str::to_string).collect(),
+        Err(_) => Vec::new(),
+    };
+    let features = schema
+        .get_field("features")
+        .ok()
+        .and_then(|f| doc.get_first(f))
+        .and_then(|v| serde_json::to_value(v).ok())
+        .unwrap_or_else(|| serde_json::json!({}));
+    Some(BlogPost {
+        id: text("id")?,
+        title: text("title").unwrap_or_default(),
+        body: text("body_original").or_else(|| text("body")).unwrap_or_default(),
+        tags: texts("tags"),
+        create_at: schema.get_field("create_at").ok().and_then(|f| doc.get_first(f)).and_then(|v| v.as_i64()),
+        status: text("status").unwrap_or_default(),
+        features,
+        author_id: text("author_id"),
+        allowed_groups: texts("allowed_groups"),
+        tenant: text("tenant"),
+    })
+}
+
+/// Processing applied to every post on its way into the index, whichever route it came from.
+#[derive(Debug, Clone, Default)]
+pub struct IngestPipeline {
+    /// `features` keys indexed as nested child documents (see [`nested`](crate::nested))
+    pub nested_paths: Vec<String>,
+    pub redactor: Option<Redactor>,
+    /// Terms also indexed verbatim in `keywords` (see [`protected`](crate::protected))
+    pub protected_terms: Option<ProtectedTerms>,
+    /// Dotted paths under `features` holding file paths or URLs, copied into `paths`
+    pub path_features: Vec<String>,
+    /// Fields the schema file adds to the posts schema, filled from the same-named keys of
+    /// `features` (see [`PostsSpec`](crate::indexes::PostsSpec))
+    pub extra_fields: Vec<String>,
+}
+
+impl IngestPipeline {
+    /// The values `post` has for the extra fields, as a document of `schema`; `Err` when one
+    /// doesn't fit its field's type. Fields an index created before them lacks are left out.
+    pub fn extra_values(&self, schema: &Schema, post: &BlogPost) -> Result<TantivyDocument, String> {
+        let values: serde_json::Map<String, serde_json::Value> = self
+            .extra_fields
+            .iter()
+            .filter_map(|name| Some((name.clone(), post.features.get(name).filter(|v| !v.is_null())?.clone())))
+            .collect();
+        TantivyDocument::from_json_object(schema, values).map_err(|e| format!("features: {}", e))
+    }
+}
+
+/// The string values (or arrays of them) at `keys` of `features`, nested the same way.
+fn path_values(features: &serde_json::Value, keys: &[String]) -> serde_json::Map<String, serde_json::Value> {
+    let mut paths = serde_json::Map::new();
+    for key in keys {
+        let value = key.split('.').try_fold(features, |v, segment| v.get(segment));
+        let value = match value {
+            Some(v @ serde_json::Value::String(_)) => v.clone(),
+            Some(serde_json::Value::Array(items)) => items.iter().filter(|i| i.is_string()).cloned().collect(),
+            _ => continue,
+        };
+        let mut segments: Vec<&str> = key.split('.').collect();
+        let leaf = segments.pop().unwrap_or_default();
+        let mut object = &mut paths;
+        for segment in segments {
+            let entry = object.entry(segment.to_string()).or_insert_with(|| serde_json::Value::Object(Default::default()));
+            // A key listed both on its own and as a parent keeps only its children
+            if !entry.is_object() {
+                *entry = serde_json::Value::Object(Default::default());
+            }
+            object = entry.as_object_mut().unwrap();
+        }
+        object.insert(leaf.to_string(), value);
+    }
+    paths
+}
+
+/// Adds `post` after running it through `pipeline`: the body is redacted, and nested objects
+/// become child documents preceding the post. The post gets `_indexed_at`, `_content_hash`,
+/// its protected terms as keywords and its path features on indexes that have the fields.
+pub fn index_post(writer: &mut IndexWriter, schema: &Schema, pipeline: &IngestPipeline, post: BlogPost) -> tantivy::Result<u64> {
+    index_post_at(writer, schema, pipeline, post, indexed_at_now())
+}
+
+/// The latest `_indexed_at` handed out.
+static LAST_INDEXED_AT: AtomicI64 = AtomicI64::new(i64::MIN);
+
+/// Now, or the last stamp if the clock went back: writers stamp posts while locked, so stamps
+/// follow the order posts are committed in, which `/latest/stream` relies on.
+fn indexed_at_now() -> i64 {
+    let now = now_secs();
+    LAST_INDEXED_AT.fetch_max(now, Ordering::Relaxed).max(now)
+}
+
+/// [`index_post`] with `_indexed_at` set to `indexed_at`, for posts copied rather than written.
+pub fn index_post_at(writer: &mut IndexWriter, schema: &Schema, pipeline: &IngestPipeline, post: BlogPost, indexed_at: i64) -> tantivy::Result<u64> {
+    add_block(writer, post_block_at(schema, pipeline, post, indexed_at)?)
+}
+
+/// The documents [`index_post`] adds for `post`, its nested children first, without adding
+/// them; callers applying several writes at once build them all before touching the writer.
+pub fn post_block(schema: &Schema, pipeline: &IngestPipeline, post: BlogPost) -> tantivy::Result<Vec<TantivyDocument>> {
+    post_block_at(schema, pipeline, post, indexed_at_now())
+}
+
+fn post_block_at(schema: &Schema, pipeline: &IngestPipeline, mut post: BlogPost, indexed_at: i64) -> tantivy::Result<Vec<TantivyDocument>> {
+    let hash = content_hash(&post);
+    let mut original = None;
+    if let Some(redacted) = pipeline.redactor.as_ref().and_then(|r| r.redact(&post.body)) {
+        let body = std::mem::replace(&mut post.body, redacted);
+        original = pipeline.redactor.as_ref().is_some_and(|r| r.keep_original).then_some(body);
+    }
+    let mut block = nested_documents(schema, &post, &pipeline.nested_paths);
+    let paths = path_values(&post.features, &pipeline.path_features);
+    let extra = pipeline.extra_values(schema, &post).map_err(tantivy::TantivyError::InvalidArgument)?;
+    let mut doc = to_document(schema, post);
+    for value in extra.into_iter() {
+        doc.add_field_value(value.field, value.value);
+    }
+    if let (Some(body), Ok(f_original)) = (original, schema.get_field("body_original")) {
+        doc.add_text(f_original, body);
+    }
+    if let (Ok(f_indexed_at), Ok(f_hash)) = (schema.get_field(INDEXED_AT_FIELD), schema.get_field(CONTENT_HASH_FIELD)) {
+        doc.add_i64(f_indexed_at, indexed_at);
+        doc.add_text(f_hash, hash);
+    }
+    if let Some(protected) = &pipeline.protected_terms {
+        protected.add_keywords(schema, &mut doc);
+    }
+    if let (false, Ok(f_paths)) = (paths.is_empty(), schema.get_field(PATHS_FIELD)) {
+        let paths = paths.into_iter().map(|(k, v)| (k, OwnedValue::from(v))).collect();
+        doc.add_object(f_paths, paths);
+    }
+    block.push(doc);
+    Ok(block)
+}
+
+/// Fields a query term without a field name is searched in.
+pub const DEFAULT_SEARCH_FIELDS: &[&str] = &["title", "body", "tags", "features"];
+
+/// Query parser over the [`DEFAULT_SEARCH_FIELDS`].
+pub fn default_query_parser(index: &Index) -> QueryParser {
+    let schema = index.schema();
+    let default_fields = DEFAULT_SEARCH_FIELDS.iter().map(|name| schema.get_field(name).unwrap()).collect();
+    QueryParser::for_index(index, default_fields)
+}
+
+/// Fields rendered as JSON arrays of plain strings, however many values they hold; `tags` is
+/// there even when empty.
+pub const MULTI_VALUED_FIELDS: &[&str] = &["tags", "allowed_groups"];
+
+/// Stored fields by name as plain JSON, in the shape of [`BlogPost`]: strings, numbers and
+/// `features` as an object. Fields of [`MULTI_VALUED_FIELDS`] are always arrays, `tags` even
+/// when empty; any other field only when it holds several values.
+pub fn doc_to_json(schema: &Schema, doc: &TantivyDocument) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    if schema.get_field("tags").is_ok() {
+        obj.insert("tags".to_string(), serde_json::json!([]));
+    }
+    for fv in doc.field_values() {
+        let name = schema.get_field_entry(fv.field()).name().to_string();
+        if RESTRICTED_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        let value = serde_json::to_value(fv.value()).unwrap_or(serde_json::Value::Null);
+        match obj.get_mut(&name) {
+            Some(serde_json::Value::Array(values)) => values.push(value),
+            Some(first) => *first = serde_json::json!([first.take(), value]),
+            None if MULTI_VALUED_FIELDS.contains(&name.as_str()) => {
+                obj.insert(name, serde_json::json!([value]));
+            }
+            None => {
+                obj.insert(name, value);
+            }
+        }
+    }
+    serde_json::Value::Object(obj)
+}
+
+/// Replaces `features` in a rendered document (see [`doc_to_json`]) with each of its leaves as
+/// a top-level dotted key (`"features.source.lang": "rust"`). Arrays are leaves.
+pub fn flatten_features(rendered: &mut serde_json::Value) {
+    fn leaves(prefix: String, value: serde_json::Value, out: &mut serde_json::Map<String, serde_json::Value>) {
+        match value {
+            serde_json::Value::Object(map) => map.into_iter().for_each(|(k, v)| leaves(format!("{}.{}", prefix, k), v, out)),
+            leaf => {
+                out.insert(prefix, leaf);
+            }
+        }
+    }
+    let Some(obj) = rendered.as_object_mut() else { return };
+    let features = obj.remove("features").unwrap_or_else(|| serde_json::json!({}));
+    leaves("features".to_string(), features, obj);
+}
+
+/// Creates or opens an index directory and registers the custom analyzers on it.
+pub fn open_or_create_index(path: &PathBuf, schema: Schema, settings: IndexSettings) -> tantivy::Result<Index> {
+    let index = if path.exists() {
+        Index::open_in_dir(path)?
+    } else {
+        std::fs::create_dir_all(path)?;
+        Index::builder().schema(schema).settings(settings).create_in_dir(path)?
+    };
+    register_analyzers(&index);
+    Ok(index)
+}
+
+/// [`open_or_create_index`], or a fresh index kept in RAM when `in_memory` (nothing touches
+/// `path` then).
+pub fn open_index(path: &PathBuf, schema: Schema, settings: IndexSettings, in_memory: bool) -> tantivy::Result<Index> {
+    if !in_memory {
+        return open_or_create_index(path, schema, settings);
+    }
+    let index = Index::builder().schema(schema).settings(settings).create_in_ram()?;
+    register_analyzers(&index);
+    Ok(index)
+}
+
+// Register the built-in analyzers (see `analyzers`); custom ones are added by the service
+pub fn register_analyzers(index: &Index) {
+    for spec in builtin_analyzers() {
+        let analyzer = spec.build().expect("built-in analyzers are valid");
+        index.tokenizers().register(&spec.name, analyzer);
+    }
+}
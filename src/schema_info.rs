@@ -0,0 +1,130 @@
+//! Schema introspection: `GET /schema` describes the live schema of every index — each field's
+//! type, analyzer and stored/indexed/fast flags, the fields searched by default, the index sort
+//! — together with the registered analyzers, so clients and dashboards can discover what is
+//! queryable without reading the code.
+//!
+//! Fields kept for internal use ([`RESTRICTED_FIELDS`]) are left out, as queries can't name
+//! them.
+
+use serde::Serialize;
+use tantivy::schema::{FieldEntry, FieldType, IndexRecordOption, Schema};
+use tantivy::Index;
+
+use crate::analyzers::AnalyzerInfo;
+use crate::schema::RESTRICTED_FIELDS;
+use crate::service::{sorted_by_create_at, SearchService};
+
+/// One field as `GET /schema` describes it.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldInfo {
+    pub name: String,
+    /// `text` (analyzed), `string` (one term per value), `i64`, `u64`, `f64`, `bool`, `date`,
+    /// `json`, `facet`, `bytes` or `ip`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub stored: bool,
+    pub indexed: bool,
+    pub fast: bool,
+    /// Analyzer of indexed text and json fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analyzer: Option<String>,
+    /// Whether positions are indexed, which phrase queries need; text and json fields only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub positions: Option<bool>,
+}
+
+/// One index as `GET /schema` describes it.
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexSchema {
+    pub index: String,
+    pub fields: Vec<FieldInfo>,
+    /// Fields a query term without a field name is searched in
+    pub default_search_fields: Vec<String>,
+    /// `<field> asc|desc` when documents are stored sorted by a fast field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_by: Option<String>,
+}
+
+/// Response of `GET /schema`.
+#[derive(Serialize, Debug, Clone)]
+pub struct SchemaInfo {
+    pub indexes: Vec<IndexSchema>,
+    pub analyzers: Vec<AnalyzerInfo>,
+}
+
+fn field_info(entry: &FieldEntry) -> FieldInfo {
+    let text = |indexing: Option<&tantivy::schema::TextFieldIndexing>| match indexing {
+        Some(indexing) => (Some(indexing.tokenizer().to_string()), Some(indexing.index_option() == IndexRecordOption::WithFreqsAndPositions)),
+        None => (None, None),
+    };
+    let (kind, (analyzer, positions)) = match entry.field_type() {
+        // `raw` keeps each value one term, as the `STRING` flag does
+        FieldType::Str(options) => match options.get_indexing_options() {
+            Some(indexing) if indexing.tokenizer() == "raw" => ("string", (None, None)),
+            None if options.is_fast() => ("string", (None, None)),
+            indexing => ("text", text(indexing)),
+        },
+        FieldType::JsonObject(options) => ("json", text(options.get_text_indexing_options())),
+        FieldType::I64(_) => ("i64", (None, None)),
+        FieldType::U64(_) => ("u64", (None, None)),
+        FieldType::F64(_) => ("f64", (None, None)),
+        FieldType::Bool(_) => ("bool", (None, None)),
+        FieldType::Date(_) => ("date", (None, None)),
+        FieldType::Facet(_) => ("facet", (None, None)),
+        FieldType::Bytes(_) => ("bytes", (None, None)),
+        FieldType::IpAddr(_) => ("ip", (None, None)),
+    };
+    FieldInfo {
+        name: entry.name().to_string(),
+        kind,
+        stored: entry.is_stored(),
+        indexed: entry.is_indexed(),
+        fast: entry.is_fast(),
+        analyzer,
+        positions,
+    }
+}
+
+fn index_schema(name: &str, index: &Index, default_search_fields: Vec<String>) -> IndexSchema {
+    let schema: Schema = index.schema();
+    // The only index sort the service creates
+    let sort_by = sorted_by_create_at(index).then(|| "create_at desc".to_string());
+    IndexSchema {
+        index: name.to_string(),
+        fields: schema.fields().filter(|(_, entry)| !RESTRICTED_FIELDS.contains(&entry.name())).map(|(_, entry)| field_info(entry)).collect(),
+        default_search_fields,
+        sort_by,
+    }
+}
+
+impl SearchService {
+    /// The live schema of the posts, comments and authors indexes and of every managed index,
+    /// or only of `index` (a managed index or alias, or a built-in one), with the analyzers;
+    /// `None` when there is no such index.
+    pub fn schema_info(&self, index: Option<&str>) -> Option<SchemaInfo> {
+        let names = |fields: &[&str]| fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let mut indexes = Vec::new();
+        let wanted = |name: &str| index.is_none_or(|i| i == name);
+        if wanted("posts") {
+            indexes.push(index_schema("posts", self.searcher().index(), names(crate::schema::DEFAULT_SEARCH_FIELDS)));
+        }
+        if wanted("comments") {
+            indexes.push(index_schema("comments", self.comments.searcher().index(), names(crate::comments::DEFAULT_SEARCH_FIELDS)));
+        }
+        if wanted("authors") {
+            // Only read by id to enrich hits, never searched
+            indexes.push(index_schema("authors", self.authors.current_searcher.load().index(), Vec::new()));
+        }
+        let managed = match index {
+            None => self.indexes.list(),
+            Some(name) => self.indexes.resolve(name).into_iter().collect(),
+        };
+        for managed in managed {
+            indexes.push(index_schema(&managed.name, managed.searcher().index(), managed.default_search_fields()));
+        }
+        if indexes.is_empty() {
+            return None;
+        }
+        Some(SchemaInfo { indexes, analyzers: self.analyzers() })
+    }
+}
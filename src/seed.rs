@@ -0,0 +1,149 @@
+//! Bulk-loading a fresh index from a directory of post files at startup, so demo and staging
+//! environments come up with the same data every time. Files are loaded in name order: `.ndjson`
+//! and `.jsonl` hold one post per line, `.json` a post or an array of posts.
+//!
+//! Loading is skipped when the index already holds posts. Progress is kept in a [`SeedStatus`]
+//! that `/readyz` reports.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::batch::BatchOp;
+use crate::schema::BlogPost;
+use crate::service::SearchService;
+use crate::now_secs;
+
+/// Posts applied per batch (and commit).
+const SEED_BATCH: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SeedState {
+    #[default]
+    Pending,
+    Loading,
+    Done,
+    /// The index wasn't empty
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SeedProgress {
+    pub state: SeedState,
+    pub dir: PathBuf,
+    pub files_total: usize,
+    pub files_done: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_file: Option<PathBuf>,
+    pub docs_loaded: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Shared view of a seed load, updated as it goes.
+pub struct SeedStatus {
+    progress: Mutex<SeedProgress>,
+}
+
+impl SeedStatus {
+    pub fn new(dir: PathBuf) -> Self {
+        SeedStatus { progress: Mutex::new(SeedProgress { dir, ..SeedProgress::default() }) }
+    }
+
+    pub fn progress(&self) -> SeedProgress {
+        self.progress.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    /// Whether loading has ended, successfully or because there was nothing to do.
+    pub fn finished(&self) -> bool {
+        matches!(self.progress().state, SeedState::Done | SeedState::Skipped)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SeedProgress)) {
+        f(&mut self.progress.lock().unwrap_or_else(|p| p.into_inner()));
+    }
+}
+
+/// The post files in `dir`, in name order.
+fn seed_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let seedable = path.extension().and_then(|e| e.to_str()).is_some_and(|e| matches!(e, "ndjson" | "jsonl" | "json"));
+        if path.is_file() && seedable {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_posts(path: &Path) -> anyhow::Result<Vec<BlogPost>> {
+    let text = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|e| e == "json") {
+        let value: serde_json::Value = serde_json::from_str(&text)?;
+        return Ok(match value {
+            serde_json::Value::Array(_) => serde_json::from_value(value)?,
+            post => vec![serde_json::from_value(post)?],
+        });
+    }
+    let mut posts = Vec::new();
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        posts.push(serde_json::from_str(line).map_err(|e| anyhow::anyhow!("line {}: {}", n + 1, e))?);
+    }
+    Ok(posts)
+}
+
+impl SearchService {
+    /// Loads every post file in `dir` when the hot and archive indexes are both empty,
+    /// recording progress in `status`. Posts go through [`apply_batch`](Self::apply_batch), so
+    /// moderation and quotas apply; the first unreadable file or failed batch stops the load.
+    pub async fn load_seed_dir(&self, dir: &Path, status: &SeedStatus) -> anyhow::Result<u64> {
+        let result = self.seed_from(dir, status).await;
+        status.update(|p| {
+            p.current_file = None;
+            p.finished_at = Some(now_secs());
+            if let Err(e) = &result {
+                p.state = SeedState::Failed;
+                p.error = Some(e.to_string());
+            }
+        });
+        result
+    }
+
+    async fn seed_from(&self, dir: &Path, status: &SeedStatus) -> anyhow::Result<u64> {
+        status.update(|p| p.started_at = Some(now_secs()));
+        let existing = self.searcher().num_docs() + self.archive.current_searcher.load().num_docs();
+        if existing > 0 {
+            status.update(|p| p.state = SeedState::Skipped);
+            return Ok(0);
+        }
+        let files = seed_files(dir).map_err(|e| anyhow::anyhow!("{}: {}", dir.display(), e))?;
+        status.update(|p| {
+            p.state = SeedState::Loading;
+            p.files_total = files.len();
+        });
+        let mut loaded = 0;
+        for file in files {
+            status.update(|p| p.current_file = Some(file.clone()));
+            let posts = read_posts(&file).map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+            for chunk in posts.chunks(SEED_BATCH) {
+                let ops = chunk.iter().map(|doc| BatchOp::Index { doc: doc.clone() }).collect();
+                self.apply_batch(ops, &None).await.map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+                loaded += chunk.len() as u64;
+                status.update(|p| p.docs_loaded = loaded);
+            }
+            status.update(|p| p.files_done += 1);
+        }
+        self.refresh()?;
+        status.update(|p| p.state = SeedState::Done);
+        Ok(loaded)
+    }
+}
@@ -0,0 +1,2376 @@
+//! The embeddable engine: index lifecycle, document operations and search.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::DerefMut;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Collector, Count, DocSetCollector, TopDocs};
+use tantivy::query::{AllQuery, BooleanQuery, BoostQuery, EnableScoring, Occur, Query, RangeQuery, TermQuery};
+use tantivy::json_utils::JsonTermWriter;
+use tantivy::postings::Postings;
+use tantivy::schema::{FieldType, IndexRecordOption, Schema, Type, Value, ValueBytes, JSON_END_OF_PATH};
+use tantivy::tokenizer::{TextAnalyzer, TokenStream};
+use tantivy::{
+    DocAddress, DocId, DocSet, Index, IndexReader, IndexSettings, IndexWriter, Order, ReloadPolicy, Score, Searcher, SegmentId,
+    SegmentReader, TantivyDocument, Term,
+};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::admission::{Admission, AdmissionConfig, QueryCost};
+use crate::aggs::{count_facets, top_facets, FacetCounts, FacetValue, DEFAULT_FACET_SIZE, MAX_FACET_SIZE};
+use crate::dedupe::DedupeTopDocs;
+use crate::degrade::{DegradeConfig, Degradation};
+use crate::flags::FeatureFlags;
+use crate::indexes::{IndexRegistry, SchemaFile};
+use crate::migrations::{migrate, AppliedMigration, Store};
+use crate::paging::{SearchAfter, TierBest, MAX_RESULT_WINDOW};
+use crate::storage::{FileStorage, MemoryStorage, Storage};
+use crate::analyzers::{builtin_analyzers, AnalyzerRegistry};
+use crate::archive::ArchiveTier;
+use crate::auth::visible_to;
+use crate::breaker::BreakerConfig;
+use crate::authors::{Author, AuthorStore};
+use crate::batch::{self, validate_batch, BatchOp, OpType};
+use crate::comments::{ids_query, matching_values, Comment, CommentHit, CommentStore};
+use crate::collector::{ApproxTopDocs, CountUpTo, NewestFirst, ScanProfile, TotalHits};
+use crate::dlq::DeadLetterQueue;
+use crate::error::{ServiceError, ServiceResult};
+use crate::metering::{Meter, MeteringConfig};
+use crate::moderation::{ModerationConfig, ModerationError, Moderator};
+use crate::export::content_hashes;
+use crate::federation::{Federation, RemoteCluster};
+use crate::pacing::{CommitPacing, Pacer};
+use crate::patch::PostPatch;
+use crate::session::{SessionPinning, SessionPins, Snapshot};
+use crate::nested::{check_nested_schema, posts_only, NestedQuery};
+use crate::query::{parse_nested_query, parse_query, parse_query_with, with_shingle_boost, MinimumShouldMatch, QueryLimits};
+use crate::journal::{Journal, JournalOp};
+use crate::raft::{RaftConfig, RaftNode};
+use crate::replica::{FollowerConfig, Replica};
+use crate::retention::{Retention, RetentionRule};
+use crate::protected::ProtectedTerms;
+use crate::redact::{RedactionConfig, Redactor};
+use crate::schema::{from_document, index_post, posts_schema, open_index, BlogPost, IngestPipeline, CONTENT_HASH_FIELD, INDEXED_AT_FIELD, PATHS_FIELD, RESTRICTED_FIELDS};
+use crate::shadow::{hit_ids, ShadowIndex};
+use crate::snippets::{SnippetOptions, Snippets};
+use crate::sort::SortBy;
+use crate::stats::Stats;
+use crate::tags::tags_filter;
+use crate::trace::{ShardTiming, TraceContext};
+use crate::usage::{TenantConfig, TenantUsage, TenantWrite, Usage};
+
+/// Upper bound on how many queued `index` documents are added per writer lock acquisition.
+pub const MAX_INDEX_BATCH: usize = 256;
+
+/// Weight of the terms `expand` adds relative to the user's query.
+const EXPANSION_BOOST: f32 = 0.3;
+
+/// Times a patch is merged again after another write to its post landed while it was moderated.
+const PATCH_ATTEMPTS: usize = 5;
+
+type IndexRequest = (BlogPost, OpType, oneshot::Sender<ServiceResult<u64>>);
+
+/// A hot-index write not yet live: the post with the id afterwards (`None` once deleted) and
+/// the write's opstamp.
+type WrittenId = (Option<BlogPost>, u64);
+
+/// Where a [`SearchService`] keeps its data and how its background jobs behave.
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub index_path: PathBuf,
+    /// Directory of the archive (cold tier) index
+    pub archive_path: PathBuf,
+    /// Directory of the comments index, joined to posts by `parent_id`
+    pub comments_path: PathBuf,
+    /// Directory of the authors index used to enrich hits
+    pub authors_path: PathBuf,
+    /// NDJSON file backing the dead-letter queue
+    pub dlq_path: PathBuf,
+    /// Where analyzers registered at runtime are persisted (see [`analyzers`](crate::analyzers))
+    pub analyzers_path: PathBuf,
+    /// Directory of the managed indexes, one directory each (see [`indexes`](crate::indexes))
+    pub indexes_path: PathBuf,
+    /// Managed indexes and aliases created on startup when missing
+    pub schema_file: SchemaFile,
+    /// Directory of the saved-object store (see [`storage`](crate::storage))
+    pub storage_path: PathBuf,
+    /// Index directories `/admin/diff` may open by name (see [`diff`](SearchService::diff))
+    pub snapshots_path: PathBuf,
+    /// Manifest of the format versions the files besides the indexes are in (see
+    /// [`migrations`](crate::migrations))
+    pub formats_path: PathBuf,
+    /// Index writer heap budget in bytes
+    pub writer_heap_bytes: usize,
+    /// When pending writes are committed and the searcher swapped (see [`pacing`](crate::pacing))
+    pub commit_pacing: CommitPacing,
+    /// Commit and swap in a fresh searcher before every write returns, instead of as
+    /// `commit_pacing` has it: acknowledged writes are searchable, at the cost of a commit each
+    pub sync_commits: bool,
+    /// Target for the time from a post write being acknowledged to it being searchable,
+    /// reported against in `/stats`
+    pub nrt_slo: Duration,
+    pub retention_rules: Vec<RetentionRule>,
+    pub retention_interval: Duration,
+    pub retention_dry_run: bool,
+    /// Index directory that sampled searches are shadowed against
+    pub shadow_index: Option<PathBuf>,
+    pub shadow_sample_pct: f64,
+    /// Complexity caps applied to every search, export and retention query
+    pub query_limits: QueryLimits,
+    /// Create the hot index with segments sorted newest-first by `create_at`. Only applies
+    /// when the index is created; an existing index keeps the sort it was built with.
+    pub sort_by_create_at: bool,
+    /// Create the hot and archive indexes with `title` and `body` analyzed by `zh_ngram_folded`,
+    /// which folds width, compatibility forms and accents (see [`analyzers`](crate::analyzers)).
+    /// Only applies when they are created
+    pub fold_text: bool,
+    /// Built-in analyzer for `body` when the hot and archive indexes are created, e.g. `code`;
+    /// the title's analyzer when `None`
+    pub body_analyzer: Option<String>,
+    /// Built-in analyzer for the string values of `features` (nested snippets included) when
+    /// the indexes are created; `default` when `None`
+    pub features_analyzer: Option<String>,
+    /// `features` keys whose objects are also indexed as nested child documents (see
+    /// [`nested`](crate::nested)); needs an unsorted index created with the `_nested` field
+    pub nested_paths: Vec<String>,
+    /// Redact personal data from post bodies before indexing
+    pub redaction: Option<RedactionConfig>,
+    /// Terms also indexed verbatim, and matched exactly, in `keywords` (see
+    /// [`protected`](crate::protected)); none when empty
+    pub protected_terms: Vec<String>,
+    /// Dotted paths under `features` holding file paths or URLs, also indexed in `paths` with
+    /// each of their ancestors; the index must have the field
+    pub path_features: Vec<String>,
+    /// Dotted paths under `features` holding user identifiers, matched by erasure requests
+    pub erasure_paths: Vec<String>,
+    /// Rules and webhook every post passes before it is indexed
+    pub moderation: Option<ModerationConfig>,
+    /// When the moderation webhook, remote clusters and metering endpoint are given up on for
+    /// a while (see [`breaker`](crate::breaker))
+    pub breaker: BreakerConfig,
+    /// API-key tenants whose usage is accounted and whose quotas are enforced
+    pub tenants: Vec<TenantConfig>,
+    /// Where and how often per-tenant usage records are emitted
+    pub metering: Option<MeteringConfig>,
+    /// Journal accepted post writes for followers, keeping the latest this many (see
+    /// [`journal`](crate::journal))
+    pub journal_max_entries: Option<usize>,
+    /// Follow a leader's journal instead of taking post writes (see [`replica`](crate::replica))
+    pub follow: Option<FollowerConfig>,
+    /// Take part in the raft group keeping the cluster metadata (see [`metadata`](crate::metadata))
+    pub cluster: Option<RaftConfig>,
+    /// Other deployments searchable with `indexes=<name>:posts` (see [`federation`](crate::federation))
+    pub remote_clusters: Vec<RemoteCluster>,
+    /// How long a remote leg of a search may take at most
+    pub remote_timeout: Duration,
+    /// Turn away or queue expensive queries while busy (see [`admission`](crate::admission))
+    pub admission: Option<AdmissionConfig>,
+    /// Pin opted-in sessions to the searchers they first saw (see [`session`](crate::session))
+    pub session_pinning: Option<SessionPinning>,
+    /// Degrade automatically once searches run over this latency error budget (see
+    /// [`degrade`](crate::degrade)); degrading by hand works regardless
+    pub degrade: Option<DegradeConfig>,
+    /// Feature flags switched away from their defaults (see [`flags`](crate::flags))
+    pub feature_flags: Vec<(String, bool)>,
+    /// Reindex posts indexes created with an older schema while opening (see
+    /// [`reindex`](crate::reindex)); otherwise they are only reported
+    pub upgrade_schema: bool,
+    /// Keep the hot, archive, comments, authors and managed indexes in RAM and the dead-letter queue
+    /// unpersisted, ignoring the paths above; everything is lost on drop. Meant for tests
+    pub in_memory: bool,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        ServiceConfig {
+            index_path: PathBuf::from(".tantivy_idx"),
+            archive_path: PathBuf::from(".tantivy_archive"),
+            comments_path: PathBuf::from(".tantivy_comments"),
+            authors_path: PathBuf::from(".tantivy_authors"),
+            dlq_path: PathBuf::from(".tantivy_dlq.ndjson"),
+            analyzers_path: PathBuf::from(".tantivy_analyzers.json"),
+            indexes_path: PathBuf::from(".tantivy_indexes"),
+            schema_file: SchemaFile::default(),
+            storage_path: PathBuf::from(".tantivy_objects"),
+            snapshots_path: PathBuf::from(".tantivy_snapshots"),
+            formats_path: PathBuf::from(".tantivy_formats.json"),
+            writer_heap_bytes: 50_000_000,
+            commit_pacing: CommitPacing::default(),
+            sync_commits: false,
+            nrt_slo: Duration::from_secs(5),
+            retention_rules: Vec::new(),
+            retention_interval: Duration::from_secs(3600),
+            retention_dry_run: false,
+            shadow_index: None,
+            shadow_sample_pct: 10.0,
+            query_limits: QueryLimits::default(),
+            sort_by_create_at: false,
+            fold_text: false,
+            body_analyzer: None,
+            features_analyzer: None,
+            nested_paths: Vec::new(),
+            redaction: None,
+            protected_terms: Vec::new(),
+            path_features: Vec::new(),
+            erasure_paths: Vec::new(),
+            moderation: None,
+            breaker: BreakerConfig::default(),
+            tenants: Vec::new(),
+            metering: None,
+            journal_max_entries: None,
+            follow: None,
+            cluster: None,
+            remote_clusters: Vec::new(),
+            remote_timeout: Duration::from_secs(10),
+            admission: None,
+            session_pinning: Some(SessionPinning { ttl: Duration::from_secs(300), max_sessions: 10_000 }),
+            degrade: None,
+            feature_flags: Vec::new(),
+            upgrade_schema: false,
+            in_memory: false,
+        }
+    }
+}
+
+/// The search engine without any transport: owns the index writer, the hot-swapped searcher
+/// and the subsystems around them. Writes become searchable after the next [`refresh`], which
+/// [`spawn_background_tasks`] runs as `commit_pacing` has it, or before they return with
+/// `sync_commits`.
+///
+/// [`refresh`]: SearchService::refresh
+/// [`spawn_background_tasks`]: SearchService::spawn_background_tasks
+pub struct SearchService {
+    pub(crate) writer: Mutex<IndexWriter>,              // protected for add and commit
+    pub(crate) reader: ArcSwap<IndexReader>,            // used to get new searchers; swapped by reindexing
+    pub(crate) current_searcher: ArcSwap<Searcher>,     // hot-swapped searcher
+    index_queue: mpsc::Sender<IndexRequest>,            // `index` requests, drained in micro-batches
+    index_queue_rx: Mutex<Option<mpsc::Receiver<IndexRequest>>>,
+    batcher_running: AtomicBool,
+    written_ids: Mutex<HashMap<String, WrittenId>>,     // hot ids written since the live searcher's commit
+    pub(crate) commits: watch::Sender<u64>,             // bumped after every searcher swap
+    pub(crate) committed_opstamp: AtomicU64,            // of the commit the live searcher shows
+    pub(crate) dlq: DeadLetterQueue,                    // failed ingest payloads
+    pub(crate) retention: Retention,
+    pub(crate) archive: ArchiveTier,
+    pub(crate) comments: CommentStore,
+    pub(crate) authors: AuthorStore,
+    pub(crate) shadow: Option<Arc<ShadowIndex>>,
+    pub(crate) stats: Stats,
+    pub(crate) moderator: Option<Moderator>,
+    pub(crate) usage: Usage,
+    pub(crate) meter: Option<Meter>,
+    pub(crate) journal: Option<Journal>,
+    pub(crate) replica: Option<Replica>,
+    pub(crate) raft: Option<Arc<RaftNode>>,
+    pub(crate) federation: Option<Federation>,
+    pub(crate) admission: Option<Admission>,
+    pub(crate) session_pins: Option<SessionPins>,
+    pub(crate) degradation: Degradation,
+    pub(crate) flags: FeatureFlags,
+    /// Migrations run on startup
+    pub(crate) migrations: Vec<AppliedMigration>,
+    pub(crate) storage: Box<dyn Storage>,
+    pub(crate) pipeline: IngestPipeline,
+    pub(crate) analyzers: AnalyzerRegistry,
+    pub(crate) indexes: IndexRegistry,
+    /// Set while [`reindex`](SearchService::reindex) rebuilds the posts tiers
+    pub(crate) reindexing: AtomicBool,
+    /// Random per process, so read validators handed out before a restart never match (see
+    /// [`conditional`](crate::conditional))
+    pub(crate) instance_id: u64,
+    /// Starts as `config.query_limits`; see [`set_query_limits`](SearchService::set_query_limits)
+    query_limits: ArcSwap<QueryLimits>,
+    config: ServiceConfig,
+}
+
+/// Which tier a hit came from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Hot,
+    Archive,
+}
+
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Hot => "hot",
+            Tier::Archive => "archive",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchRequest {
+    pub q: String,
+    pub limit: usize,
+    /// Skip this many top hits (see [`paging`](crate::paging))
+    pub offset: usize,
+    /// Only hits ranking after this cursor, from the last hit of the previous page
+    pub search_after: Option<SearchAfter>,
+    /// Also search the archive tier and merge both hit lists by their scores relative to each
+    /// tier's best hit
+    pub include_archive: bool,
+    /// Stop at this instant and return whatever was collected and fetched so far
+    pub deadline: Option<Instant>,
+    /// Count matching documents, exactly or up to a threshold; not counted when `None`
+    pub track_total_hits: Option<TrackTotalHits>,
+    /// Approximate top-k with this pruning factor (>= 1.0, see [`ApproxTopDocs`]); exact when `None`
+    pub approximate: Option<f32>,
+    /// Add the tags most characteristic of the matches to the query (see
+    /// [`expansion_terms`](SearchService::expansion_terms))
+    pub expand: bool,
+    /// How many of the query's top-level optional clauses a hit must match; any one when `None`
+    pub minimum_should_match: Option<MinimumShouldMatch>,
+    /// Only posts with at least one comment matching this query (see [`comments`](crate::comments))
+    pub has_child: Option<String>,
+    /// Only posts with a nested child matching on its own (see [`nested`](crate::nested))
+    pub nested: Option<NestedQuery>,
+    /// The caller's groups: restricted posts outside them are hidden. No filtering when `None`
+    pub groups: Option<Vec<String>>,
+    /// Tenant the search is accounted to
+    pub tenant: Option<String>,
+    /// Span the search runs under; each fan-out leg gets a child span (see [`trace`](crate::trace))
+    pub trace: Option<TraceContext>,
+    /// Searchers to use instead of the current ones, e.g. those a session is pinned to (see
+    /// [`session`](crate::session))
+    pub snapshot: Option<Snapshot>,
+    /// Score posts up by this much for each pair or triple of the query's words they contain
+    /// in order (see [`with_shingle_boost`]); no boost when `None`
+    pub shingle_boost: Option<f32>,
+    /// Only posts with every one of these tags (see [`tags`](crate::tags))
+    pub tags_all: Vec<String>,
+    /// Only posts with at least one of these tags
+    pub tags_any: Vec<String>,
+    /// Report the fields and terms each hit matched (see [`HitMatches`])
+    pub matches: bool,
+    /// Cut highlighted fragments of each hit's title and body (see [`snippets`](crate::snippets))
+    pub snippets: Option<SnippetOptions>,
+    /// Count the matches per value of these fields (see [`count_facets`])
+    pub facets: Vec<String>,
+    /// Values listed per facet; [`DEFAULT_FACET_SIZE`] when `None`
+    pub facet_size: Option<usize>,
+    /// Rank hits by this field instead of by score (see [`sort`](crate::sort))
+    pub sort: Option<SortBy>,
+    /// Collapse hits sharing a value of this field into the best of them (see
+    /// [`dedupe`](crate::dedupe))
+    pub dedupe_by: Option<String>,
+}
+
+pub struct SearchHit {
+    pub score: f32,
+    pub tier: Tier,
+    /// Where the hit is within its tier's searcher
+    pub address: DocAddress,
+    pub doc: TantivyDocument,
+    /// Set when the request asked for matches
+    pub matches: Option<HitMatches>,
+    /// Highlighted fragments by field, when the request asked for snippets
+    pub snippets: Option<BTreeMap<String, String>>,
+    /// The hit's value of the field the request sorts by, if it has one
+    pub sort_value: Option<i64>,
+    /// How many other matches the hit stands for, when the request dedupes
+    pub duplicates: Option<u64>,
+}
+
+/// One term of the query a hit contains.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchedTerm {
+    pub field: String,
+    /// The indexed value; `<path>:<value>` for JSON fields
+    pub term: String,
+}
+
+/// Which of the query's terms a hit contains, and in which fields, read from the postings.
+/// Only the terms the query looks up as such count: those that prefixes, fuzzy and range
+/// clauses expand to at search time don't, and a phrase's terms count one by one.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HitMatches {
+    /// Fields with at least one matched term, in the order the terms first matched
+    pub fields: Vec<String>,
+    pub terms: Vec<MatchedTerm>,
+}
+
+/// One token as a field's analyzer produces it at index time.
+#[derive(Serialize, Debug, Clone)]
+pub struct AnalyzedToken {
+    pub text: String,
+    pub position: usize,
+    pub offset_from: usize,
+    pub offset_to: usize,
+}
+
+/// How one field of a post was indexed: its stored values, and the terms the field's analyzer
+/// makes of them checked against the index.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldTerms {
+    pub field: String,
+    pub stored: Vec<serde_json::Value>,
+    pub terms: Vec<DocTerm>,
+}
+
+/// One term of a post's field, as a term vector entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocTerm {
+    /// The indexed value; `<path>:<value>` for JSON fields
+    pub term: String,
+    /// Whether the post is in the term's postings. `false` means a search for the term misses
+    /// the post, typically because the analyzer changed since it was indexed
+    pub indexed: bool,
+    /// Occurrences in the field, 0 when not indexed
+    pub term_freq: u32,
+    /// Token positions of the occurrences, empty when the field doesn't record them
+    pub positions: Vec<u32>,
+    /// Documents of the post's tier containing the term
+    pub doc_freq: u64,
+}
+
+/// One term a query looks up, and how many documents contain it.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedTerm {
+    pub field: String,
+    /// The indexed value; `<path>:<value>` for JSON fields
+    pub term: String,
+    /// Whether positions are read too (phrases)
+    pub positions: bool,
+    /// Documents containing the term in the tiers searched
+    pub doc_freq: u64,
+}
+
+/// What a search resolves to, without running it; see [`explain_query`](SearchService::explain_query).
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryPlan {
+    /// The resolved tantivy query tree
+    pub query: String,
+    /// Fields of the terms looked up, sorted
+    pub fields: Vec<String>,
+    pub terms: Vec<PlannedTerm>,
+    /// Sum of the terms' document frequencies, roughly the postings the search reads. Range,
+    /// wildcard and fuzzy clauses expand at search time and aren't counted
+    pub estimated_cost: u64,
+    /// What admission control charges the query, prefixes and ranges included (see
+    /// [`admission`](crate::admission))
+    pub cost: QueryCost,
+    /// Documents in the tiers searched
+    pub num_docs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackTotalHits {
+    /// Stop counting at this many matches and report a lower bound
+    UpTo(u64),
+    Exact,
+}
+
+impl TrackTotalHits {
+    /// Where counting stops, `None` for exact counts.
+    pub fn cap(self) -> Option<u64> {
+        match self {
+            TrackTotalHits::UpTo(n) => Some(n),
+            TrackTotalHits::Exact => None,
+        }
+    }
+}
+
+/// What `expand` added to a query.
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryExpansion {
+    pub terms: Vec<String>,
+    /// The query actually run
+    pub query: String,
+}
+
+#[derive(Default)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub expansion: Option<QueryExpansion>,
+    /// Present when the request asked to track total hits and counting finished in time
+    pub total: Option<TotalHits>,
+    /// Present when the request asked for facets and counting finished in time
+    pub facets: Option<BTreeMap<String, Vec<FacetValue>>>,
+    /// The deadline passed before every segment was searched or every hit fetched
+    pub timed_out: bool,
+    /// Timing of each leg the search fanned out to, in the order they ran
+    pub shards: Vec<ShardTiming>,
+    /// The tiers' bests the hits were ranked by, when the search merged both
+    pub tier_best: Option<TierBest>,
+}
+
+/// One tier's share of a search.
+pub(crate) struct TierHits {
+    pub hits: Vec<(f32, TantivyDocument)>,
+    /// Per hit
+    pub addresses: Vec<DocAddress>,
+    /// Per hit, when the request asked for matches
+    pub matches: Vec<HitMatches>,
+    /// Per hit, when the request asked for snippets
+    pub snippets: Vec<BTreeMap<String, String>>,
+    /// Per hit, when the request sorts by a field
+    pub sort_values: Vec<Option<i64>>,
+    /// Per hit, when the request dedupes
+    pub duplicates: Vec<u64>,
+    pub total: Option<TotalHits>,
+    /// Empty unless the request asked for facets
+    pub facets: FacetCounts,
+    pub timed_out: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RetryReport {
+    pub retried: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Ids present in only one of two indexes, or in both with different stored content. Lists
+/// are sorted.
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexDiff {
+    pub base_docs: usize,
+    pub target_docs: usize,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl SearchService {
+    /// Opens (or creates) the hot and archive indexes, the dead-letter queue and the optional
+    /// shadow index. Call [`spawn_background_tasks`](Self::spawn_background_tasks) afterwards
+    /// to get batching, periodic commits and scheduled retention.
+    pub fn open(config: ServiceConfig) -> anyhow::Result<Self> {
+        let storage: Box<dyn Storage> = match config.in_memory {
+            true => Box::new(MemoryStorage::default()),
+            false => Box::new(FileStorage::open(&config.storage_path)?),
+        };
+        Self::open_with_storage(config, storage)
+    }
+
+    /// Like [`open`](Self::open), keeping saved objects in `storage` instead of
+    /// `storage_path` (see [`storage`](crate::storage)).
+    pub fn open_with_storage(config: ServiceConfig, storage: Box<dyn Storage>) -> anyhow::Result<Self> {
+        let migrations = match config.in_memory {
+            true => Vec::new(),
+            false => migrate(&config.formats_path, &persisted_stores(&config))?,
+        };
+        let text_analyzer = match config.fold_text {
+            true => "zh_ngram_folded",
+            false => "zh_ngram",
+        };
+        let body_analyzer = config.body_analyzer.as_deref().unwrap_or(text_analyzer);
+        let features_analyzer = config.features_analyzer.as_deref().unwrap_or("default");
+        for name in [body_analyzer, features_analyzer] {
+            if !builtin_analyzers().iter().any(|a| a.name == name) {
+                anyhow::bail!("{} is not a built-in analyzer; see GET /analyzers", name);
+            }
+        }
+        // The schema file's analyzers win over the flags'; custom ones aren't loaded yet
+        let posts = &config.schema_file.posts;
+        posts
+            .validate(&posts_schema(text_analyzer, body_analyzer, features_analyzer), &|name| builtin_analyzers().iter().any(|a| a.name == name))
+            .map_err(|e| anyhow::anyhow!("schema file, posts: {}", e))?;
+        let schema = posts.extend(posts_schema(
+            posts.analyzer("title").unwrap_or(text_analyzer),
+            posts.analyzer("body").unwrap_or(body_analyzer),
+            posts.analyzer("features").unwrap_or(features_analyzer),
+        ));
+        let index = open_index(&config.index_path, schema.clone(), index_settings(&config), config.in_memory)?;
+        if config.sort_by_create_at && !sorted_by_create_at(&index) {
+            log::warn!(
+                "{}: existing index is not sorted by create_at; rebuild it to enable the index sort",
+                config.index_path.display()
+            );
+        }
+        if !config.nested_paths.is_empty() {
+            check_nested_schema(&index.schema())?;
+            if sorted_by_create_at(&index) {
+                anyhow::bail!("nested paths need an index without sort_by_field: sorting breaks doc blocks");
+            }
+        }
+        if !config.path_features.is_empty() && index.schema().get_field(PATHS_FIELD).is_err() {
+            anyhow::bail!("path features need an index created with the `{}` field; rebuild it", PATHS_FIELD);
+        }
+        let writer = index.writer(config.writer_heap_bytes)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let searcher = reader.searcher();
+
+        let dlq = match config.in_memory {
+            true => DeadLetterQueue::in_memory(),
+            false => DeadLetterQueue::open(config.dlq_path.clone())?,
+        };
+        let retention = Retention::new(config.retention_rules.clone(), config.retention_dry_run);
+        let archive = ArchiveTier::open(&config.archive_path, index.schema(), config.in_memory)?;
+        let comments = CommentStore::open(&config.comments_path, config.in_memory)?;
+        let authors = AuthorStore::open(&config.authors_path, config.in_memory)?;
+        let shadow = match &config.shadow_index {
+            Some(path) => Some(Arc::new(ShadowIndex::open(path, config.shadow_sample_pct)?)),
+            None => None,
+        };
+
+        let pipeline = IngestPipeline {
+            nested_paths: config.nested_paths.clone(),
+            redactor: config.redaction.as_ref().map(Redactor::new).transpose()?,
+            protected_terms: (!config.protected_terms.is_empty()).then(|| ProtectedTerms::new(config.protected_terms.clone())),
+            path_features: config.path_features.clone(),
+            extra_fields: config.schema_file.posts.extra_fields(),
+        };
+
+        let moderator = config.moderation.as_ref().map(|m| Moderator::new(m, config.breaker)).transpose()?;
+        if config.follow.is_some() && config.journal_max_entries.is_some() {
+            anyhow::bail!("a follower can't keep a journal of its own: it applies the leader's");
+        }
+        let replica = config.follow.clone().map(Replica::open).transpose()?;
+        let raft = config.cluster.clone().map(RaftNode::open).transpose()?.map(Arc::new);
+        let mut managers = vec![index.tokenizers().clone(), archive.reader.load().searcher().index().tokenizers().clone()];
+        managers.push(comments.reader.searcher().index().tokenizers().clone());
+        managers.push(authors.reader.searcher().index().tokenizers().clone());
+        managers.extend(shadow.iter().map(|s| s.reader.searcher().index().tokenizers().clone()));
+        let analyzers = AnalyzerRegistry::open((!config.in_memory).then(|| config.analyzers_path.clone()), managers)?;
+        let indexes = IndexRegistry::open((!config.in_memory).then(|| config.indexes_path.clone()), &analyzers)?;
+        indexes.declare(&config.schema_file, &analyzers)?;
+        let federation = (!config.remote_clusters.is_empty()).then(|| Federation::new(config.remote_clusters.clone(), config.remote_timeout, config.breaker));
+
+        let (index_queue, index_queue_rx) = mpsc::channel(MAX_INDEX_BATCH * 4);
+        let service = SearchService {
+            writer: Mutex::new(writer),
+            reader: ArcSwap::from_pointee(reader),
+            current_searcher: ArcSwap::from_pointee(searcher),
+            index_queue,
+            index_queue_rx: Mutex::new(Some(index_queue_rx)),
+            batcher_running: AtomicBool::new(false),
+            written_ids: Mutex::new(HashMap::new()),
+            commits: watch::channel(0).0,
+            committed_opstamp: AtomicU64::new(0),
+            dlq,
+            retention,
+            archive,
+            comments,
+            authors,
+            shadow,
+            stats: Stats::new(config.nrt_slo),
+            moderator,
+            usage: Usage::new(config.tenants.clone()),
+            meter: config.metering.clone().map(|m| Meter::new(m, config.breaker)),
+            journal: config.journal_max_entries.map(Journal::new),
+            replica,
+            raft,
+            federation,
+            admission: config.admission.clone().map(Admission::new),
+            session_pins: config.session_pinning.clone().map(SessionPins::new),
+            degradation: Degradation::new(config.degrade.clone()),
+            flags: FeatureFlags::new(&config.feature_flags)?,
+            query_limits: ArcSwap::from_pointee(config.query_limits),
+            migrations,
+            storage,
+            pipeline,
+            analyzers,
+            indexes,
+            reindexing: AtomicBool::new(false),
+            instance_id: rand::random(),
+            config,
+        };
+        service.check_schema()?;
+        Ok(service)
+    }
+
+    /// Spawns the `index` micro-batcher, the commit loop (unless `sync_commits`) and, when
+    /// configured, the retention, metering, replication and cluster metadata jobs on the
+    /// current tokio runtime.
+    /// Calling it twice is a no-op.
+    pub fn spawn_background_tasks(self: &Arc<Self>) {
+        let Some(queue) = self.index_queue_rx.lock().unwrap_or_else(|p| p.into_inner()).take() else {
+            return;
+        };
+        self.batcher_running.store(true, Ordering::Release);
+        tokio::spawn(Arc::clone(self).run_index_batcher(queue));
+
+        // Periodically commit and refresh the searcher
+        if !self.config.sync_commits {
+            let service = Arc::clone(self);
+            tokio::spawn(async move {
+                let mut pacer = Pacer::new(service.config.commit_pacing, service.stats.visibility.acknowledged_total());
+                loop {
+                    tokio::time::sleep(pacer.tick()).await;
+                    let acked = service.stats.visibility.acknowledged_total();
+                    let cost = Duration::from_micros(service.stats.commit_last_total_us.load(Ordering::Relaxed));
+                    service.stats.record_pacing(pacer.interval(acked, cost), pacer.rate(acked));
+                    if !pacer.due(acked, cost) {
+                        continue;
+                    }
+                    if let Err(e) = service.refresh() {
+                        log::error!("refresh error: {}", e);
+                    }
+                    pacer.committed(acked);
+                }
+            });
+        }
+
+        // Scheduled retention job
+        if !self.retention.rules.is_empty() {
+            let service = Arc::clone(self);
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(service.config.retention_interval).await;
+                    match service.run_retention(service.retention.dry_run) {
+                        Ok(report) => {
+                            for o in &report.outcomes {
+                                log::info!(
+                                    "retention rule {}: {} matched (applied: {})",
+                                    o.rule, o.matched, o.applied
+                                );
+                            }
+                        }
+                        Err(e) => log::error!("retention error: {}", e),
+                    }
+                }
+            });
+        }
+
+        // Per-tenant usage records
+        if let Some(meter) = &self.meter {
+            let service = Arc::clone(self);
+            let interval = meter.config().interval;
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    if let Err(e) = service.emit_metering().await {
+                        log::error!("metering error (the next emission covers this period): {}", e);
+                    }
+                }
+            });
+        }
+
+        // Pulling the leader's journal
+        if self.replica.is_some() {
+            tokio::spawn(Arc::clone(self).run_follower());
+        }
+
+        // Metadata elections and heartbeats
+        if let Some(raft) = &self.raft {
+            tokio::spawn(Arc::clone(raft).run());
+        }
+    }
+
+    /// Drains queued `index` requests, adding up to `MAX_INDEX_BATCH` documents per writer lock
+    /// so concurrent clients don't contend on the mutex one document at a time.
+    async fn run_index_batcher(self: Arc<Self>, mut queue: mpsc::Receiver<IndexRequest>) {
+        let mut batch: Vec<IndexRequest> = Vec::with_capacity(MAX_INDEX_BATCH);
+        while let Some(first) = queue.recv().await {
+            batch.push(first);
+            while batch.len() < MAX_INDEX_BATCH {
+                match queue.try_recv() {
+                    Ok(req) => batch.push(req),
+                    Err(_) => break,
+                }
+            }
+
+            let mut writer = self.writer();
+            self.stats.record_index_batch(batch.len());
+            for (post, op_type, reply) in batch.drain(..) {
+                let _ = reply.send(self.write_post(&mut writer, post, op_type));
+            }
+        }
+    }
+
+    pub(crate) fn writer(&self) -> MutexGuard<'_, IndexWriter> {
+        match self.writer.lock() {
+            Ok(g) => g,
+            Err(poison) => poison.into_inner(),
+        }
+    }
+
+    pub fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// The caps queries are parsed under; `config().query_limits` is only their starting value.
+    pub fn query_limits(&self) -> QueryLimits {
+        **self.query_limits.load()
+    }
+
+    /// Changes the caps for queries parsed from now on.
+    pub fn set_query_limits(&self, limits: QueryLimits) {
+        self.query_limits.store(Arc::new(limits));
+    }
+
+    pub fn index(&self) -> Index {
+        self.reader.load().searcher().index().clone()
+    }
+
+    /// Whether segments are stored newest-first by `create_at`, so newest-first scans can stop
+    /// after the first matches of each segment.
+    pub fn sorted_by_create_at(&self) -> bool {
+        sorted_by_create_at(self.reader.load().searcher().index())
+    }
+
+    pub fn schema(&self) -> Schema {
+        self.current_searcher.load().index().schema()
+    }
+
+    /// The searcher of the last refresh; held snapshots stay consistent across later swaps.
+    pub fn searcher(&self) -> Arc<Searcher> {
+        self.current_searcher.load_full()
+    }
+
+    pub fn archive(&self) -> &ArchiveTier {
+        &self.archive
+    }
+
+    pub fn comments(&self) -> &CommentStore {
+        &self.comments
+    }
+
+    pub fn authors(&self) -> &AuthorStore {
+        &self.authors
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetterQueue {
+        &self.dlq
+    }
+
+    pub fn moderator(&self) -> Option<&Moderator> {
+        self.moderator.as_ref()
+    }
+
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub fn replica(&self) -> Option<&Replica> {
+        self.replica.as_ref()
+    }
+
+    /// This node's member of the cluster metadata raft group.
+    pub fn raft(&self) -> Option<&RaftNode> {
+        self.raft.as_deref()
+    }
+
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
+
+    /// Receiver whose value is bumped after every searcher swap.
+    pub fn subscribe_commits(&self) -> watch::Receiver<u64> {
+        self.commits.subscribe()
+    }
+
+    /// Commits pending writes as a prepared commit, reloads the reader and swaps in the new
+    /// searcher, then does the same for the managed indexes, the archive tier and the comments and authors indexes and picks up new commits
+    /// of the shadow index.
+    /// While a reindex runs the posts tiers are left to it.
+    pub fn refresh(&self) -> ServiceResult<()> {
+        self.refresh_hot()?;
+        // before the bump, so `wait_for` writes to managed indexes see it
+        self.indexes.refresh()?;
+        self.commits.send_modify(|generation| *generation += 1);
+
+        // the archive tier only sees retention moves and deletes
+        if !self.reindexing.load(Ordering::Acquire) {
+            self.archive.refresh()?;
+        }
+        self.comments.refresh()?;
+        self.authors.refresh()?;
+        // the shadow index is written elsewhere
+        if let Some(shadow) = &self.shadow {
+            shadow.reader.reload()?;
+        }
+        Ok(())
+    }
+
+    /// The hot tier's part of [`refresh`](Self::refresh).
+    fn refresh_hot(&self) -> ServiceResult<()> {
+        let started = Instant::now();
+        let writer = self.writer();
+        // A reindex commits the writer it copies alone, so it can tell whether a write
+        // slipped in; checked under the lock it takes to start
+        if self.reindexing.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        self.commit_hot(started, writer)
+    }
+
+    /// Commits the hot writer like [`refresh`](Self::refresh) does, for a caller already
+    /// holding its lock, which stays held until the commit is searchable.
+    pub(crate) fn commit_locked(&self, writer: &mut IndexWriter) -> ServiceResult<()> {
+        self.commit_hot(Instant::now(), writer)
+    }
+
+    /// Commits the hot writer behind `writer`, released (when it is the lock guard) once the
+    /// commit is prepared, then swaps in a searcher showing it.
+    fn commit_hot(&self, started: Instant, mut writer: impl DerefMut<Target = IndexWriter>) -> ServiceResult<()> {
+        let (committed, pending, acks, journaled) = {
+            let pending = self.usage.pending_snapshot();
+            let acks = self.stats.visibility.take_pending();
+            // Entries journaled so far were written before this commit, so it covers them
+            let journaled = self.journal.as_ref().map(Journal::last_offset);
+            // Only flushing the indexing threads' segments needs the writer; publishing them
+            // (meta update, segment bookkeeping) runs while writes go on
+            (writer.prepare_commit()?.commit_future(), pending, acks, journaled)
+        };
+        drop(writer);
+        let locked = started.elapsed();
+        let opstamp = committed.wait()?;
+        self.stats.record_commit(locked, started.elapsed());
+        if let (Some(journal), Some(offset)) = (&self.journal, journaled) {
+            journal.publish_committed(offset);
+        }
+        let reader = self.reader.load();
+        reader.reload()?;
+        self.current_searcher.store(Arc::new(reader.searcher()));
+        self.committed_opstamp.fetch_max(opstamp, Ordering::Release);
+        self.written_ids.lock().unwrap_or_else(|p| p.into_inner()).retain(|_, (_, stamp)| *stamp > opstamp);
+        self.stats.visibility.observe(acks);
+        self.usage.settle(pending);
+        Ok(())
+    }
+
+    /// Opstamp of the commit the live searcher shows.
+    pub fn committed_opstamp(&self) -> u64 {
+        self.committed_opstamp.load(Ordering::Acquire)
+    }
+
+    /// Whether the live searcher shows the write stamped `opstamp`.
+    pub fn is_searchable(&self, opstamp: u64) -> bool {
+        self.committed_opstamp.load(Ordering::Acquire) >= opstamp
+    }
+
+    /// Waits for a commit of the background loop to make the write stamped `opstamp`
+    /// searchable, refreshing after `max_wait` if none has by then.
+    pub async fn wait_for_refresh(&self, opstamp: u64, max_wait: Duration) -> ServiceResult<()> {
+        let mut commits = self.commits.subscribe();
+        let waited = tokio::time::timeout(max_wait, async {
+            while !self.is_searchable(opstamp) {
+                if commits.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+        match waited.await {
+            Ok(()) if self.is_searchable(opstamp) => Ok(()),
+            _ => self.refresh(),
+        }
+    }
+
+    /// With `sync_commits`, makes a write visible before it is acknowledged.
+    fn commit_write(&self) -> ServiceResult<()> {
+        match self.config.sync_commits {
+            true => self.refresh(),
+            false => Ok(()),
+        }
+    }
+
+    /// Appends the operation `op` builds to the journal, when there is one. Takes the writer
+    /// so the append happens under its lock, in the order the writes were applied.
+    pub(crate) fn journal_write(&self, _writer: &IndexWriter, op: impl FnOnce() -> JournalOp) {
+        if let Some(journal) = &self.journal {
+            journal.append(op());
+        }
+    }
+
+    /// Whether a post has `id`, counting hot-index writes the live searcher doesn't show yet.
+    /// Checked under the writer lock, so no write to the id lands between this and the create.
+    fn id_taken(&self, id: &str) -> ServiceResult<bool> {
+        if let Some((post, _)) = self.written_ids.lock().unwrap_or_else(|p| p.into_inner()).get(id) {
+            return Ok(post.is_some());
+        }
+        Ok(self.locate(id)?.is_some())
+    }
+
+    /// `NotFound` when tenants are configured and a post with `id`, committed or not, belongs
+    /// to another tenant than `tenant`, so a write to it isn't a takeover. Writes without a
+    /// tenant (seeding, dead-letter retries) aren't checked. Call it under the writer lock.
+    fn check_owner(&self, id: &str, tenant: &Option<String>) -> ServiceResult<()> {
+        let Some(tenant) = tenant.as_ref().filter(|_| self.usage.enabled()) else { return Ok(()) };
+        let written = self.written_ids.lock().unwrap_or_else(|p| p.into_inner()).get(id).cloned();
+        let owner = match written {
+            Some((post, _)) => post.map(|p| p.tenant),
+            None => match self.locate(id)? {
+                Some((_, searcher, addr)) => from_document(&searcher.index().schema(), &searcher.doc(addr)?).map(|p| p.tenant),
+                None => None,
+            },
+        };
+        match owner {
+            Some(owner) if owner.as_ref() != Some(tenant) => Err(ServiceError::NotFound(format!("no post with id {}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The post with `id` as the next commit will show it, with its tier: the last hot-index
+    /// write to it while the live searcher doesn't show that yet, otherwise the committed post.
+    /// `None` when there is none, or it isn't `tenant`'s when given.
+    fn current_post(&self, id: &str, tenant: &Option<String>) -> ServiceResult<Option<(Tier, BlogPost)>> {
+        let written = self.written_ids.lock().unwrap_or_else(|p| p.into_inner()).get(id).cloned();
+        let found = match written {
+            Some((post, _)) => post.map(|p| (Tier::Hot, p)),
+            None => match self.locate(id)? {
+                Some((tier, searcher, addr)) => from_document(&searcher.index().schema(), &searcher.doc(addr)?).map(|p| (tier, p)),
+                None => None,
+            },
+        };
+        Ok(found.filter(|(_, post)| tenant.is_none() || post.tenant == *tenant))
+    }
+
+    /// Records hot-index writes stamped `opstamp` for [`id_taken`](Self::id_taken),
+    /// [`check_owner`](Self::check_owner) and [`current_post`](Self::current_post): each id with the post it holds after the write,
+    /// `None` once deleted. Entries go once a commit past them is live.
+    fn note_written<'a>(&self, ids: impl IntoIterator<Item = (&'a str, Option<&'a BlogPost>)>, opstamp: u64) {
+        let mut written = self.written_ids.lock().unwrap_or_else(|p| p.into_inner());
+        for (id, post) in ids {
+            written.insert(id.to_string(), (post.cloned(), opstamp));
+        }
+    }
+
+    /// Deletes `ids` from the archive tier, for hot-index writes that replace or delete the posts
+    /// with them: ids are unique across both tiers. Called with the writer lock held.
+    fn drop_archived<'a>(&self, ids: impl IntoIterator<Item = &'a str>) {
+        let f_id = self.schema().get_field("id").unwrap();
+        let archive = self.archive.writer();
+        for id in ids {
+            archive.delete_term(Term::from_field_text(f_id, id));
+        }
+    }
+
+    /// Adds `post` with the writer lock held as `writer`, refusing an `op_type=create` whose id
+    /// is taken and any write over another tenant's post, and journals it.
+    fn write_post(&self, writer: &mut IndexWriter, post: BlogPost, op_type: OpType) -> ServiceResult<u64> {
+        self.check_owner(&post.id, &post.tenant)?;
+        if op_type == OpType::Create && self.id_taken(&post.id)? {
+            return Err(ServiceError::Conflict(format!("a post with id {} already exists", post.id)));
+        }
+        let schema = writer.index().schema();
+        let opstamp = add_post(writer, &schema, &self.pipeline, post.clone(), op_type)?;
+        // A create found no post with the id in either tier
+        if op_type == OpType::Upsert {
+            self.drop_archived([post.id.as_str()]);
+        }
+        self.note_written([(post.id.as_str(), Some(&post))], opstamp);
+        self.journal_write(writer, || post_write(post, op_type));
+        Ok(opstamp)
+    }
+
+    /// [`BlogPost::validate`], and that the post's values for the schema file's extra fields
+    /// fit their types.
+    fn validate_post(&self, post: &BlogPost) -> Result<(), String> {
+        post.validate()?;
+        match self.pipeline.extra_fields.is_empty() {
+            true => Ok(()),
+            false => self.pipeline.extra_values(&self.schema(), post).map(|_| ()),
+        }
+    }
+
+    /// [`validate_batch`], with every post checked by [`validate_post`](Self::validate_post).
+    fn validate_ops(&self, ops: &[BatchOp]) -> Result<(), String> {
+        validate_batch(ops)?;
+        for (pos, op) in ops.iter().enumerate() {
+            if let BatchOp::Index { doc } | BatchOp::Update { doc } = op {
+                self.validate_post(doc).map_err(|e| format!("operation {}: {}", pos, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Followers only take post writes from their leader's journal, and nobody while a
+    /// reindex runs.
+    pub(crate) fn check_writable(&self) -> ServiceResult<()> {
+        self.check_not_reindexing()?;
+        match &self.replica {
+            Some(replica) => Err(ServiceError::Invalid(format!(
+                "read-only follower of {}; send writes to the leader",
+                replica.config().leader
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `post` through moderation when it is configured; rejected posts are `Invalid`.
+    async fn moderate(&self, post: BlogPost) -> ServiceResult<BlogPost> {
+        let Some(moderator) = &self.moderator else { return Ok(post) };
+        moderator.moderate(post).await.map_err(|e| match e {
+            ModerationError::Rejected(reasons) => ServiceError::Invalid(format!("rejected by moderation: {}", reasons)),
+            ModerationError::Unavailable(msg) => ServiceError::Unavailable(msg),
+        })
+    }
+
+    /// Committed posts of `tenant` in both tiers.
+    fn tenant_docs(&self, tenant: &str) -> ServiceResult<u64> {
+        let mut docs = 0;
+        for searcher in [self.current_searcher.load_full(), self.archive.current_searcher.load_full()] {
+            let schema = searcher.index().schema();
+            let Ok(f_tenant) = schema.get_field("tenant") else { continue };
+            let owned = TermQuery::new(Term::from_field_text(f_tenant, tenant), IndexRecordOption::Basic);
+            docs += searcher.search(posts_only(&schema, Box::new(owned)).as_ref(), &Count)? as u64;
+        }
+        Ok(docs)
+    }
+
+    /// What writing `posts` (each flagged whether it is an update) adds per tenant, or
+    /// `QuotaExceeded` when it would take a tenant past its `max_docs`. Concurrent writes are
+    /// checked against the same counts, so a tenant can overshoot by what is in flight.
+    fn admit<'a>(&self, posts: impl IntoIterator<Item = (&'a BlogPost, bool)>) -> ServiceResult<HashMap<String, TenantWrite>> {
+        let mut writes: HashMap<String, TenantWrite> = HashMap::new();
+        if !self.usage.enabled() {
+            return Ok(writes);
+        }
+        let searcher = self.current_searcher.load();
+        let f_id = searcher.index().schema().get_field("id").unwrap();
+        for (post, update) in posts {
+            let Some(tenant) = &post.tenant else { continue };
+            let write = writes.entry(tenant.clone()).or_default();
+            write.docs += 1;
+            write.bytes += serde_json::to_vec(post).map_or(0, |json| json.len() as u64);
+            let existing = TermQuery::new(Term::from_field_text(f_id, &post.id), IndexRecordOption::Basic);
+            if !(update && searcher.search(&existing, &Count)? > 0) {
+                write.new_docs += 1;
+            }
+        }
+        for (tenant, write) in &writes {
+            let Some(max_docs) = self.usage.max_docs(tenant) else { continue };
+            let docs = self.tenant_docs(tenant)? + self.usage.pending(tenant);
+            if docs + write.new_docs > max_docs {
+                return Err(ServiceError::QuotaExceeded(format!(
+                    "tenant {} has {} of its {} posts; {} more would exceed the quota",
+                    tenant, docs, max_docs, write.new_docs
+                )));
+            }
+        }
+        Ok(writes)
+    }
+
+    fn account(&self, writes: HashMap<String, TenantWrite>) {
+        for (tenant, write) in writes {
+            self.usage.record_indexed(&tenant, &write);
+        }
+    }
+
+    /// Usage of every configured tenant. Storage is each tiers' size split by post count.
+    pub fn usage_report(&self) -> ServiceResult<Vec<TenantUsage>> {
+        let mut committed: HashMap<String, (u64, u64)> = HashMap::new();
+        for searcher in [self.current_searcher.load_full(), self.archive.current_searcher.load_full()] {
+            let schema = searcher.index().schema();
+            let Ok(f_tenant) = schema.get_field("tenant") else { continue };
+            let bytes = searcher.space_usage()?.total().get_bytes();
+            let posts = searcher.search(posts_only(&schema, Box::new(AllQuery)).as_ref(), &Count)? as u64;
+            for tenant in self.usage.tenants() {
+                let owned = TermQuery::new(Term::from_field_text(f_tenant, &tenant.name), IndexRecordOption::Basic);
+                let docs = searcher.search(posts_only(&schema, Box::new(owned)).as_ref(), &Count)? as u64;
+                let entry = committed.entry(tenant.name.clone()).or_default();
+                entry.0 += docs;
+                entry.1 += (bytes * docs).checked_div(posts).unwrap_or(0);
+            }
+        }
+        Ok(self.usage.report(&committed))
+    }
+
+    /// Moderates the posts of every index and update operation of a batch.
+    async fn moderate_batch(&self, ops: Vec<BatchOp>) -> ServiceResult<Vec<BatchOp>> {
+        let mut moderated = Vec::with_capacity(ops.len());
+        for op in ops {
+            moderated.push(match op {
+                BatchOp::Index { doc } => BatchOp::Index { doc: self.moderate(doc).await? },
+                BatchOp::Update { doc } => BatchOp::Update { doc: self.moderate(doc).await? },
+                delete => delete,
+            });
+        }
+        Ok(moderated)
+    }
+
+    /// Adds a document, replacing the one with the same id unless `op_type` is `create`, which
+    /// is a conflict when a post has the id in either tier, committed or not. With background tasks
+    /// running it goes through the micro-batch queue, otherwise it is added directly. Failures
+    /// and moderation rejects are dead-lettered.
+    pub async fn index_document(&self, post: BlogPost, op_type: OpType) -> ServiceResult<u64> {
+        self.check_writable()?;
+        if let Err(e) = self.validate_post(&post) {
+            self.dlq.push("index", &e, vec![BatchOp::Index { doc: post }]);
+            return Err(ServiceError::Invalid(e));
+        }
+        let post = match self.moderate(post.clone()).await {
+            Ok(moderated) => moderated,
+            Err(e) => {
+                self.dlq.push("moderation", &e, vec![BatchOp::Index { doc: post }]);
+                return Err(e);
+            }
+        };
+        let writes = self.admit([(&post, op_type == OpType::Upsert)])?;
+        let result = if self.batcher_running.load(Ordering::Acquire) {
+            let (tx, rx) = oneshot::channel();
+            if self.index_queue.send((post.clone(), op_type, tx)).await.is_err() {
+                return Err(ServiceError::Unavailable("index queue closed".to_string()));
+            }
+            match rx.await {
+                Ok(result) => result,
+                Err(_) => return Err(ServiceError::Internal("index batch dropped".to_string())),
+            }
+        } else {
+            self.write_post(&mut self.writer(), post.clone(), op_type)
+        };
+        let opstamp = match result {
+            Ok(opstamp) => opstamp,
+            Err(e @ (ServiceError::Conflict(_) | ServiceError::NotFound(_))) => return Err(e),
+            Err(e) => {
+                self.dlq.push("index", &e, vec![BatchOp::Index { doc: post }]);
+                return Err(e);
+            }
+        };
+        self.account(writes);
+        self.stats.visibility.acknowledged(1);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// `post` validated and moderated for an update; failures and rejects are dead-lettered.
+    async fn checked_update(&self, post: BlogPost) -> ServiceResult<BlogPost> {
+        if let Err(e) = self.validate_post(&post) {
+            self.dlq.push("update", &e, vec![BatchOp::Update { doc: post }]);
+            return Err(ServiceError::Invalid(e));
+        }
+        match self.moderate(post.clone()).await {
+            Ok(moderated) => Ok(moderated),
+            Err(e) => {
+                self.dlq.push("moderation", &e, vec![BatchOp::Update { doc: post }]);
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the document with the same id; `NotFound` when that is another tenant's than
+    /// `post.tenant`. Failures and moderation rejects are dead-lettered.
+    pub async fn update_document(&self, post: BlogPost) -> ServiceResult<u64> {
+        self.check_writable()?;
+        let post = self.checked_update(post).await?;
+        let writes = self.admit([(&post, true)])?;
+        let opstamp = {
+            let mut writer = self.writer();
+            self.check_owner(&post.id, &post.tenant)?;
+            let schema = writer.index().schema();
+            let f_id = schema.get_field("id").unwrap();
+
+            // delete existing by id, then add
+            writer.delete_term(Term::from_field_text(f_id, &post.id));
+            match index_post(&mut writer, &schema, &self.pipeline, post.clone()) {
+                Ok(opstamp) => {
+                    self.drop_archived([post.id.as_str()]);
+                    self.note_written([(post.id.as_str(), Some(&post))], opstamp);
+                    self.journal_write(&writer, || post_write(post, OpType::Upsert));
+                    opstamp
+                }
+                Err(e) => {
+                    self.dlq.push("update", &e, vec![BatchOp::Update { doc: post }]);
+                    return Err(ServiceError::from(e));
+                }
+            }
+        };
+        self.account(writes);
+        self.stats.visibility.acknowledged(1);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Merges `patch` into the post with `id`, counting writes not yet searchable, and
+    /// re-indexes it as an update, returning the post as written and the opstamp. `None` when
+    /// there is no such post, or none of `tenant`'s when given. An archived post moves back to
+    /// the hot index, journaled as a delete from both tiers and an update.
+    ///
+    /// The merged post is moderated before the writer lock is taken; under it the post is read
+    /// again, and when another write changed it in the meantime the patch is merged anew, so
+    /// concurrent patches don't lose each other's changes.
+    pub async fn patch_document(&self, id: &str, patch: &PostPatch, tenant: &Option<String>) -> ServiceResult<Option<(BlogPost, u64)>> {
+        self.check_writable()?;
+        for _ in 0..PATCH_ATTEMPTS {
+            let Some((_, base)) = self.current_post(id, tenant)? else { return Ok(None) };
+            let post = self.checked_update(patch.apply(base.clone())).await?;
+            let writes = self.admit([(&post, true)])?;
+            let opstamp = {
+                let mut writer = self.writer();
+                let Some((tier, current)) = self.current_post(id, tenant)? else { return Ok(None) };
+                if serde_json::to_value(&current).ok() != serde_json::to_value(&base).ok() {
+                    continue;
+                }
+                let schema = writer.index().schema();
+                let f_id = schema.get_field("id").unwrap();
+                writer.delete_term(Term::from_field_text(f_id, id));
+                let opstamp = match index_post(&mut writer, &schema, &self.pipeline, post.clone()) {
+                    Ok(opstamp) => opstamp,
+                    Err(e) => {
+                        self.dlq.push("update", &e, vec![BatchOp::Update { doc: post }]);
+                        return Err(ServiceError::from(e));
+                    }
+                };
+                let mut ops = vec![BatchOp::Update { doc: post.clone() }];
+                if tier == Tier::Archive {
+                    self.archive.writer().delete_term(Term::from_field_text(f_id, id));
+                    ops.insert(0, BatchOp::Delete { id: id.to_string() });
+                }
+                self.note_written([(id, Some(&post))], opstamp);
+                self.journal_write(&writer, || JournalOp::Batch { ops });
+                opstamp
+            };
+            self.account(writes);
+            self.stats.visibility.acknowledged(1);
+            self.commit_write()?;
+            return Ok(Some((post, opstamp)));
+        }
+        Err(ServiceError::Conflict(format!("post {} kept changing while it was patched", id)))
+    }
+
+    /// Deletes by id from the hot index and the archive tier; `NotFound` for a post of another
+    /// tenant than `tenant` (see [`check_owner`](Self::check_owner)).
+    pub fn delete_document(&self, id: &str, tenant: &Option<String>) -> ServiceResult<u64> {
+        self.check_writable()?;
+        let opstamp = {
+            let writer = self.writer();
+            self.check_owner(id, tenant)?;
+            let f_id = writer.index().schema().get_field("id").unwrap();
+            // Archived copies share the id key, so a delete removes the document from both tiers
+            self.drop_archived([id]);
+            let opstamp = writer.delete_term(Term::from_field_text(f_id, id));
+            self.note_written([(id, None)], opstamp);
+            self.journal_write(&writer, || JournalOp::Batch { ops: vec![BatchOp::Delete { id: id.to_string() }] });
+            opstamp
+        };
+        self.stats.visibility.acknowledged(1);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Adds a comment, replacing one with the same id. The parent post doesn't have to exist
+    /// yet; a comment only joins once a post with its `parent_id` is searchable.
+    pub fn index_comment(&self, comment: Comment) -> ServiceResult<u64> {
+        comment.validate().map_err(ServiceError::Invalid)?;
+        let opstamp = self.comments.upsert(comment)?;
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Deletes a comment by id. Deleting a post leaves its comments in place; they simply stop
+    /// matching `has_parent` queries.
+    pub fn delete_comment(&self, id: &str) -> ServiceResult<u64> {
+        let opstamp = self.comments.delete(id);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Adds or replaces an author profile.
+    pub fn index_author(&self, author: Author) -> ServiceResult<u64> {
+        author.validate().map_err(ServiceError::Invalid)?;
+        let opstamp = self.authors.upsert(author)?;
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    pub fn delete_author(&self, id: &str) -> ServiceResult<u64> {
+        let opstamp = self.authors.delete(id);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Profiles of the authors of `hits`, keyed by author id, fetched in one lookup. Hits
+    /// without an `author_id`, or whose author is unknown, have no entry.
+    pub fn hit_authors(&self, hits: &[SearchHit]) -> ServiceResult<HashMap<String, Author>> {
+        let Ok(f_author_id) = self.schema().get_field("author_id") else { return Ok(HashMap::new()) };
+        let ids = hits
+            .iter()
+            .filter_map(|hit| hit.doc.get_first(f_author_id).and_then(|v| v.as_str()).map(str::to_string))
+            .collect();
+        self.authors.lookup(&ids)
+    }
+
+    /// Comments matching `q` (all when blank), optionally only those whose parent post
+    /// matches `has_parent` in the hot index.
+    pub fn search_comments(&self, q: &str, has_parent: Option<&str>, limit: usize) -> ServiceResult<Vec<CommentHit>> {
+        let limits = self.query_limits();
+        let searcher = self.comments.searcher();
+        let mut query = self.comments.parse_query(&searcher, q, &limits)?;
+        if let Some(parent_q) = has_parent {
+            let posts = self.current_searcher.load();
+            let f_id = posts.index().schema().get_field("id").unwrap();
+            let parents = matching_values(&posts, parse_query(&posts, parent_q, &limits)?.as_ref(), f_id)?;
+            let f_parent = searcher.index().schema().get_field("parent_id").unwrap();
+            query = filtered(query, ids_query(f_parent, &parents).as_ref());
+        }
+        self.comments.search(&searcher, query.as_ref(), limit)
+    }
+
+    /// Applies every operation under one writer lock, all-or-nothing, and the next
+    /// [`refresh`](Self::refresh) makes all of it searchable at once; one post rejected by
+    /// moderation rejects the batch, and so does writing or deleting a post of another tenant
+    /// than `tenant`.
+    pub async fn apply_batch(&self, ops: Vec<BatchOp>, tenant: &Option<String>) -> ServiceResult<u64> {
+        self.check_writable()?;
+        if let Err(e) = self.validate_ops(&ops) {
+            self.dlq.push("batch", &e, ops);
+            return Err(ServiceError::Invalid(e));
+        }
+        let ops = match self.moderate_batch(ops.clone()).await {
+            Ok(moderated) => moderated,
+            Err(e) => {
+                self.dlq.push("moderation", &e, ops);
+                return Err(e);
+            }
+        };
+        let writes = self.admit(batch_posts(&ops))?;
+        let count = ops.len();
+        let applied = {
+            let writer = self.writer();
+            for (id, _) in batch_ids(&ops) {
+                self.check_owner(id, tenant)?;
+            }
+            let applied = batch::apply_batch(&writer, &writer.index().schema(), &self.pipeline, ops.clone());
+            if let Ok(opstamp) = applied {
+                self.drop_archived(batch_ids(&ops).map(|(id, _)| id));
+                self.note_written(batch_ids(&ops), opstamp);
+                self.journal_write(&writer, || JournalOp::Batch { ops: ops.clone() });
+            }
+            applied
+        };
+        let opstamp = match applied {
+            Ok(opstamp) => opstamp,
+            Err(e) => {
+                self.dlq.push("batch", &e, ops);
+                return Err(ServiceError::Internal(format!("batch not applied: {}", e)));
+            }
+        };
+        self.account(writes);
+        self.stats.visibility.acknowledged(count);
+        self.commit_write()?;
+        Ok(opstamp)
+    }
+
+    /// Replays dead-lettered payloads (all, or just `seq`). Each entry is re-validated,
+    /// moderated again and applied as its own batch, and only leaves the queue once that is
+    /// committed; entries that fail again stay queued with `attempts` bumped.
+    pub async fn retry_dead_letters(&self, seq: Option<u64>) -> RetryReport {
+        let entries = self.dlq.checkout(seq);
+        let retried = entries.len();
+        let mut still_failing = 0;
+        for entry in entries {
+            let result = match entry.replay_ops().and_then(|ops| self.validate_ops(&ops).map(|()| ops)) {
+                Ok(ops) => match self.moderate_batch(ops).await {
+                    Ok(ops) => self.admit(batch_posts(&ops)).map_err(|e| e.to_string()).and_then(|writes| {
+                        let writer = self.writer();
+                        let schema = writer.index().schema();
+                        let opstamp = batch::apply_batch(&writer, &schema, &self.pipeline, ops.clone()).map_err(|e| e.to_string())?;
+                        self.drop_archived(batch_ids(&ops).map(|(id, _)| id));
+                        self.note_written(batch_ids(&ops), opstamp);
+                        self.journal_write(&writer, || JournalOp::Batch { ops });
+                        drop(writer);
+                        self.account(writes);
+                        Ok(())
+                    }),
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(e) => Err(e),
+            };
+            still_failing += result.is_err() as usize;
+            self.dlq.settle(entry.seq, result);
+        }
+        if let Err(e) = self.commit_write() {
+            log::error!("refresh error after dead-letter retry: {}", e);
+        }
+        RetryReport { retried, succeeded: retried - still_failing, failed: still_failing }
+    }
+
+    /// Relevance-ranked search over the hot index (ranked by a field with `req.sort`),
+    /// optionally federated with the archive tier.
+    /// A sample of requests is also replayed against the shadow index, off the calling thread
+    /// when a tokio runtime is available. Past `req.deadline` the hits found so far are
+    /// returned with `timed_out` set.
+    pub fn search(&self, req: &SearchRequest) -> ServiceResult<SearchResults> {
+        if let Some(factor) = req.approximate.filter(|f| !(f.is_finite() && *f >= 1.0)) {
+            return Err(ServiceError::Invalid(format!("approximate must be a number >= 1.0, got {}", factor)));
+        }
+        if req.search_after.is_some() && (req.offset > 0 || req.approximate.is_some()) {
+            return Err(ServiceError::Invalid("search_after can't be combined with offset or approximate".to_string()));
+        }
+        if let Some(options) = &req.snippets {
+            options.validate()?;
+        }
+        if let Some(sort) = &req.sort {
+            if req.search_after.is_some() || req.approximate.is_some() {
+                return Err(ServiceError::Invalid("sort can't be combined with search_after or approximate; page with offset".to_string()));
+            }
+            sort.check(&self.schema())?;
+        }
+        if let Some(field) = &req.dedupe_by {
+            if req.sort.is_some() || req.search_after.is_some() || req.approximate.is_some() {
+                return Err(ServiceError::Invalid("dedupe_by can't be combined with sort, search_after or approximate; page with offset".to_string()));
+            }
+            crate::dedupe::check(&self.schema(), field)?;
+        }
+        if req.facet_size.is_some_and(|size| size == 0 || size > MAX_FACET_SIZE) {
+            return Err(ServiceError::Invalid(format!("facet_size must be between 1 and {}", MAX_FACET_SIZE)));
+        }
+        if req.offset + req.limit > MAX_RESULT_WINDOW {
+            return Err(ServiceError::Invalid(format!(
+                "offset + limit must be at most {}; page deeper with search_after",
+                MAX_RESULT_WINDOW
+            )));
+        }
+        let (expansion, expanded);
+        let req = match req.expand {
+            false => {
+                expansion = None;
+                req
+            }
+            true => {
+                let terms = self.expansion_terms(&req.q, req.groups.as_deref())?;
+                let q = expand_query(&req.q, &terms);
+                expanded = SearchRequest { q: q.clone(), expand: false, ..req.clone() };
+                expansion = Some(QueryExpansion { terms, query: q });
+                &expanded
+            }
+        };
+        if let Some(tenant) = &req.tenant {
+            self.usage.record_search(tenant);
+        }
+        let searcher = match &req.snapshot {
+            Some(snapshot) => Arc::clone(&snapshot.hot),
+            None => self.current_searcher.load_full(),
+        };
+        let limits = self.query_limits();
+        let children = self.search_filter(&searcher, req, &limits)?;
+        let mut shards = Vec::new();
+        let (span, started) = (req.trace.as_ref().map(TraceContext::child), Instant::now());
+        // Both tiers' hits up to the offset are needed to merge them, otherwise the hot tier
+        // skips them before fetching
+        let skip = if req.include_archive { 0 } else { req.offset };
+        let mut hot = search_tier(&searcher, req, &limits, children.as_deref(), Tier::Hot, skip)?;
+        shards.push(ShardTiming { segments: searcher.segment_readers().len(), hits: hot.hits.len(), timed_out: hot.timed_out, ..ShardTiming::since("hot", span.as_ref(), started) });
+        let (mut total, mut timed_out) = (hot.total, hot.timed_out);
+        let mut facets = std::mem::take(&mut hot.facets);
+        // Shadow replays compare the plain query; joins and ACLs may not hold on its data
+        let sampled = |s: &&Arc<ShadowIndex>| !timed_out && children.is_none() && rand::random::<f64>() < s.sample_rate;
+        if let Some(shadow) = self.shadow.as_ref().filter(sampled) {
+            let live_ids = hit_ids(&searcher.index().schema(), &hot.hits);
+            let (shadow, q, limit) = (Arc::clone(shadow), req.q.clone(), req.limit);
+            match tokio::runtime::Handle::try_current() {
+                Ok(rt) => drop(rt.spawn_blocking(move || shadow.compare(&q, limit, &limits, &live_ids))),
+                Err(_) => shadow.compare(&q, limit, &limits, &live_ids),
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = tier_hits(hot, Tier::Hot);
+        let mut tier_best = None;
+        if req.include_archive && !timed_out {
+            let archive = match &req.snapshot {
+                Some(snapshot) => Arc::clone(&snapshot.archive),
+                None => self.archive.current_searcher.load_full(),
+            };
+            let (span, started) = (req.trace.as_ref().map(TraceContext::child), Instant::now());
+            let archived = search_tier(&archive, req, &limits, children.as_deref(), Tier::Archive, 0)?;
+            shards.push(ShardTiming { segments: archive.segment_readers().len(), hits: archived.hits.len(), timed_out: archived.timed_out, ..ShardTiming::since("archive", span.as_ref(), started) });
+            timed_out = archived.timed_out;
+            let cap = req.track_total_hits.and_then(TrackTotalHits::cap);
+            total = total.zip(archived.total).map(|(hot, archive)| hot.merge(archive, cap));
+            for (field, values) in &archived.facets {
+                let counts = facets.entry(field.clone()).or_default();
+                values.iter().for_each(|(value, count)| *counts.entry(value.clone()).or_default() += count);
+            }
+            hits.extend(tier_hits(archived, Tier::Archive));
+            // Stable, so equal scores stay hot first and in address order
+            match &req.sort {
+                Some(sort) => hits.sort_by(|a, b| sort.compare((a.sort_value, a.score), (b.sort_value, b.score))),
+                None => {
+                    // Pages after the first keep the first page's bests, which their hits no longer hold
+                    let best = req.search_after.and_then(|after| after.best).unwrap_or_else(|| TierBest::of(&hits));
+                    by_tier_relative_score(&mut hits, &best);
+                    tier_best = Some(best);
+                }
+            }
+            if let Some(field) = &req.dedupe_by {
+                hits = dedupe_across_tiers(&searcher.index().schema(), field, hits);
+            }
+        }
+        // The hot tier kept the hits before the offset for the merge, even when it timed out
+        if req.include_archive {
+            hits.drain(..req.offset.min(hits.len()));
+            hits.truncate(req.limit);
+        }
+        let facets = (!req.facets.is_empty() && !timed_out).then(|| top_facets(facets, req.facet_size.unwrap_or(DEFAULT_FACET_SIZE)));
+        Ok(SearchResults { hits, expansion, total, facets, timed_out, shards, tier_best })
+    }
+
+    /// The join and ACL restrictions of `req`, if any. Both tiers share the posts schema, so one
+    /// filter serves both.
+    fn search_filter(&self, searcher: &Searcher, req: &SearchRequest, limits: &QueryLimits) -> ServiceResult<Option<Box<dyn Query>>> {
+        let mut filters: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if let Some(child_q) = &req.has_child {
+            let f_id = searcher.index().schema().get_field("id").unwrap();
+            filters.push((Occur::Must, ids_query(f_id, &self.comments.parent_ids(child_q, limits)?)));
+        }
+        if let Some(nested) = &req.nested {
+            filters.push((Occur::Must, parse_nested_query(searcher, &nested.path, &nested.q, limits)?));
+        }
+        if let Some(groups) = &req.groups {
+            filters.push((Occur::Must, visible_to(&searcher.index().schema(), groups)));
+        }
+        if let Some(tags) = tags_filter(searcher, req)? {
+            filters.push((Occur::Must, tags));
+        }
+        Ok((!filters.is_empty()).then(|| Box::new(BooleanQuery::new(filters)) as _))
+    }
+
+    /// The ACL restriction of a posts read that isn't a search (latest, export, aggregations):
+    /// [`search_filter`](Self::search_filter) of a request carrying only `groups`.
+    pub(crate) fn groups_filter(&self, searcher: &Searcher, groups: Option<&[String]>) -> ServiceResult<Option<Box<dyn Query>>> {
+        let req = SearchRequest { groups: groups.map(<[String]>::to_vec), ..SearchRequest::default() };
+        self.search_filter(searcher, &req, &self.query_limits())
+    }
+
+    /// Resolves `req` the way [`search`](Self::search) would and reports the query tree, the
+    /// terms it looks up and their document frequencies, without running it. Only `q`,
+    /// `include_archive`, `minimum_should_match`, `shingle_boost`, `has_child`, `nested`,
+    /// `groups`, `tags_all` and `tags_any` matter.
+    pub fn explain_query(&self, req: &SearchRequest) -> ServiceResult<QueryPlan> {
+        let searcher = self.current_searcher.load_full();
+        let limits = self.query_limits();
+        let mut query = parse_query_with(&searcher, &req.q, &limits, req.minimum_should_match)?;
+        if let Some(boost) = req.shingle_boost {
+            query = with_shingle_boost(&searcher, &req.q, &limits, query, boost)?;
+        }
+        if let Some(filter) = self.search_filter(&searcher, req, &limits)? {
+            query = filtered(query, filter.as_ref());
+        }
+        let mut lookups: Vec<(Term, bool)> = Vec::new();
+        query.query_terms(&mut |term, positions| match lookups.iter_mut().find(|(t, _)| t == term) {
+            Some(seen) => seen.1 |= positions,
+            None => lookups.push((term.clone(), positions)),
+        });
+
+        let mut searchers = vec![searcher];
+        if req.include_archive {
+            searchers.push(self.archive.current_searcher.load_full());
+        }
+        let schema = self.schema();
+        let mut terms = Vec::with_capacity(lookups.len());
+        for (term, positions) in lookups {
+            let mut doc_freq = 0;
+            for searcher in &searchers {
+                doc_freq += searcher.doc_freq(&term)?;
+            }
+            let field = schema.get_field_name(term.field()).to_string();
+            terms.push(PlannedTerm { field, term: term_text(&term), positions, doc_freq });
+        }
+        let mut fields: Vec<String> = terms.iter().map(|t| t.field.clone()).collect();
+        fields.sort();
+        fields.dedup();
+        Ok(QueryPlan {
+            query: format!("{:#?}", query),
+            fields,
+            estimated_cost: terms.iter().map(|t| t.doc_freq).sum(),
+            cost: self.estimate_search_cost(req)?,
+            terms,
+            num_docs: searchers.iter().map(|s| s.num_docs()).sum(),
+        })
+    }
+
+    /// Newest-first documents carrying any of `tags` (all documents if empty), optionally only
+    /// those with `create_at >= since`, hiding restricted posts outside `groups` (see
+    /// [`SearchRequest::groups`]). No relevance scoring.
+    pub fn latest(
+        &self,
+        tags: &[String],
+        since: Option<i64>,
+        limit: usize,
+        groups: Option<&[String]>,
+    ) -> ServiceResult<Vec<(i64, TantivyDocument)>> {
+        Ok(self.latest_profiled(tags, since, limit, false, groups)?.0)
+    }
+
+    /// [`latest`](Self::latest) plus how much of the index it scanned. With `early_terminate`
+    /// on an index sorted by `create_at`, each segment stops after its first `limit` matches;
+    /// on an unsorted index the flag is ignored.
+    pub fn latest_profiled(
+        &self,
+        tags: &[String],
+        since: Option<i64>,
+        limit: usize,
+        early_terminate: bool,
+        groups: Option<&[String]>,
+    ) -> ServiceResult<(Vec<(i64, TantivyDocument)>, ScanProfile)> {
+        let searcher = self.current_searcher.load();
+        let mut query = latest_query(&searcher.index().schema(), "create_at", tags, since);
+        if let Some(filter) = self.groups_filter(&searcher, groups)? {
+            query = filtered(query, filter.as_ref());
+        }
+        let collector = NewestFirst { field: "create_at", limit, early_terminate: early_terminate && sorted_by_create_at(searcher.index()) };
+        Ok(latest_hits(&searcher, query.as_ref(), &collector)?)
+    }
+
+    /// Up to `limit` committed posts with one of `tags` (any post when empty) indexed at or
+    /// after `since`, most recently indexed first, as `(_indexed_at, document)`. `_indexed_at`
+    /// is stamped while the writer is locked and never goes back, so unlike `create_at` it
+    /// follows the order posts were committed in. Restricted posts outside `groups` are hidden.
+    pub fn indexed_since(
+        &self,
+        tags: &[String],
+        since: Option<i64>,
+        limit: usize,
+        groups: Option<&[String]>,
+    ) -> ServiceResult<Vec<(i64, TantivyDocument)>> {
+        let searcher = self.current_searcher.load();
+        let mut query = latest_query(&searcher.index().schema(), INDEXED_AT_FIELD, tags, since);
+        if let Some(filter) = self.groups_filter(&searcher, groups)? {
+            query = filtered(query, filter.as_ref());
+        }
+        let collector = NewestFirst { field: INDEXED_AT_FIELD, limit, early_terminate: false };
+        Ok(latest_hits(&searcher, query.as_ref(), &collector)?.0)
+    }
+
+    /// A searcher snapshot plus the addresses of documents matching `q` (all documents when
+    /// `None` or blank), in doc store order so fetching them decompresses blocks sequentially.
+    /// Restricted posts outside `groups` are left out.
+    pub fn export_snapshot(&self, q: Option<&str>, groups: Option<&[String]>) -> ServiceResult<(Arc<Searcher>, Vec<DocAddress>)> {
+        let searcher = self.current_searcher.load_full();
+        let mut query = match q.filter(|q| !q.trim().is_empty()) {
+            None => posts_only(&searcher.index().schema(), Box::new(AllQuery)),
+            Some(q) => parse_query(&searcher, q, &self.query_limits())?,
+        };
+        if let Some(filter) = self.groups_filter(&searcher, groups)? {
+            query = filtered(query, filter.as_ref());
+        }
+        let mut addrs: Vec<_> = searcher.search(query.as_ref(), &DocSetCollector)?.into_iter().collect();
+        addrs.sort();
+        Ok((searcher, addrs))
+    }
+
+    /// Compares two indexes by id and content hash. Each side is `live` (the current hot
+    /// searcher) or the name of an index directory under `snapshots_path` (a copied backup, a
+    /// rebuild, ...), opened read-only.
+    pub fn diff(&self, base: &str, target: &str) -> ServiceResult<IndexDiff> {
+        let base = content_hashes(&*self.diff_side(base)?)?;
+        let target = content_hashes(&*self.diff_side(target)?)?;
+
+        let mut added: Vec<String> = target.keys().filter(|id| !base.contains_key(*id)).cloned().collect();
+        let mut removed: Vec<String> = base.keys().filter(|id| !target.contains_key(*id)).cloned().collect();
+        let mut changed: Vec<String> = base
+            .iter()
+            .filter(|(id, hash)| target.get(*id).is_some_and(|h| h != *hash))
+            .map(|(id, _)| id.clone())
+            .collect();
+        added.sort();
+        removed.sort();
+        changed.sort();
+        Ok(IndexDiff { base_docs: base.len(), target_docs: target.len(), added, removed, changed })
+    }
+
+    /// The directory of the snapshot `name`. Names are a single path component, and the
+    /// directory has to resolve to one inside `snapshots_path`, symlinks followed.
+    fn snapshot_dir(&self, name: &str) -> ServiceResult<PathBuf> {
+        let named = !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !named {
+            return Err(ServiceError::Invalid(format!("{} is not a snapshot name; use `live` or a directory name under the snapshots path", name)));
+        }
+        let unknown = || ServiceError::Invalid(format!("no snapshot named {}", name));
+        let root = std::fs::canonicalize(&self.config.snapshots_path).map_err(|_| unknown())?;
+        let dir = std::fs::canonicalize(root.join(name)).map_err(|_| unknown())?;
+        if self.config.in_memory || !dir.starts_with(&root) || !dir.is_dir() {
+            return Err(unknown());
+        }
+        Ok(dir)
+    }
+
+    fn diff_side(&self, side: &str) -> ServiceResult<Arc<Searcher>> {
+        if side == "live" {
+            return Ok(self.current_searcher.load_full());
+        }
+        let invalid = |e: tantivy::TantivyError| ServiceError::Invalid(format!("{}: {}", side, e));
+        let index = Index::open_in_dir(self.snapshot_dir(side)?).map_err(invalid)?;
+        let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into().map_err(invalid)?;
+        Ok(Arc::new(reader.searcher()))
+    }
+
+    /// The post with `id` from the hot index, or else from the archive tier. `None` too when
+    /// it is restricted to groups outside `groups` (see [`SearchRequest::groups`]).
+    pub fn document(&self, id: &str, groups: Option<&[String]>) -> ServiceResult<Option<(Tier, TantivyDocument)>> {
+        match self.locate_visible(id, groups)? {
+            Some((tier, searcher, addr)) => Ok(Some((tier, searcher.doc(addr)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// The committed post with `id`: its tier, and its address in that tier's searcher.
+    fn locate(&self, id: &str) -> ServiceResult<Option<(Tier, Arc<Searcher>, DocAddress)>> {
+        for (tier, searcher) in [(Tier::Hot, self.searcher()), (Tier::Archive, self.archive.current_searcher.load_full())] {
+            let schema = searcher.index().schema();
+            let by_id = TermQuery::new(Term::from_field_text(schema.get_field("id").unwrap(), id), IndexRecordOption::Basic);
+            let top = searcher.search(posts_only(&schema, Box::new(by_id)).as_ref(), &TopDocs::with_limit(1))?;
+            if let Some((_, addr)) = top.first() {
+                return Ok(Some((tier, searcher.clone(), *addr)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// [`locate`](Self::locate), but `None` for a post `groups` may not see.
+    fn locate_visible(&self, id: &str, groups: Option<&[String]>) -> ServiceResult<Option<(Tier, Arc<Searcher>, DocAddress)>> {
+        let Some((tier, searcher, addr)) = self.locate(id)? else { return Ok(None) };
+        let Some(filter) = self.groups_filter(&searcher, groups)? else { return Ok(Some((tier, searcher, addr))) };
+        let weight = filter.weight(EnableScoring::disabled_from_searcher(&searcher))?;
+        let mut scorer = weight.scorer(searcher.segment_reader(addr.segment_ord), 1.0)?;
+        Ok((scorer.seek(addr.doc_id) == addr.doc_id).then_some((tier, searcher, addr)))
+    }
+
+    /// The term vectors of the committed post with `id`: per indexed and stored field, its
+    /// stored values and the terms its analyzer makes of them, each checked against the
+    /// postings of the post's tier. Restricted fields are left out, and so are the numbers and
+    /// booleans inside JSON fields. `None` for a post hidden from `groups`, as with
+    /// [`document`](Self::document).
+    pub fn document_terms(&self, id: &str, groups: Option<&[String]>) -> ServiceResult<Option<(Tier, Vec<FieldTerms>)>> {
+        let Some((tier, searcher, addr)) = self.locate_visible(id, groups)? else { return Ok(None) };
+        let doc: TantivyDocument = searcher.doc(addr)?;
+        let segment = searcher.segment_reader(addr.segment_ord);
+        let index = searcher.index();
+        let schema = index.schema();
+        let mut fields = Vec::new();
+        for (field, entry) in schema.fields() {
+            if !entry.is_indexed() || RESTRICTED_FIELDS.contains(&entry.name()) {
+                continue;
+            }
+            let stored: Vec<serde_json::Value> = doc.get_all(field).map(|v| serde_json::to_value(v).unwrap_or_default()).collect();
+            if stored.is_empty() {
+                continue;
+            }
+            let mut terms: Vec<Term> = Vec::new();
+            let mut push = |term: Term| {
+                if !terms.contains(&term) {
+                    terms.push(term);
+                }
+            };
+            match entry.field_type() {
+                FieldType::Str(_) => {
+                    let mut analyzer = index.tokenizer_for_field(field)?;
+                    for text in stored.iter().filter_map(|v| v.as_str()) {
+                        analyzer.token_stream(text).process(&mut |token| push(Term::from_field_text(field, &token.text)));
+                    }
+                }
+                FieldType::I64(_) => stored.iter().filter_map(|v| v.as_i64()).for_each(|v| push(Term::from_field_i64(field, v))),
+                FieldType::JsonObject(options) => {
+                    let mut analyzer = index.tokenizer_for_field(field)?;
+                    let mut leaves = Vec::new();
+                    stored.iter().for_each(|v| json_string_leaves(v, String::new(), &mut leaves));
+                    for (path, text) in leaves {
+                        analyzer.token_stream(&text).process(&mut |token| {
+                            let mut term = Term::with_capacity(path.len() + 2 + token.text.len());
+                            JsonTermWriter::from_field_and_json_path(field, &path, options.is_expand_dots_enabled(), &mut term)
+                                .close_path_and_set_type(Type::Str);
+                            term.append_bytes(token.text.as_bytes());
+                            push(term);
+                        });
+                    }
+                }
+                _ => continue,
+            }
+            let mut doc_terms = Vec::new();
+            for term in &terms {
+                let mut doc_term =
+                    DocTerm { term: term_text(term), indexed: false, term_freq: 0, positions: Vec::new(), doc_freq: searcher.doc_freq(term)? };
+                let postings = segment.inverted_index(field)?.read_postings(term, IndexRecordOption::WithFreqsAndPositions)?;
+                if let Some(mut postings) = postings {
+                    // Postings start at their first document, and seeking never goes backwards
+                    if postings.doc() <= addr.doc_id && postings.seek(addr.doc_id) == addr.doc_id {
+                        doc_term.indexed = true;
+                        doc_term.term_freq = postings.term_freq();
+                        postings.positions(&mut doc_term.positions);
+                    }
+                }
+                doc_terms.push(doc_term);
+            }
+            fields.push(FieldTerms { field: entry.name().to_string(), stored, terms: doc_terms });
+        }
+        Ok(Some((tier, fields)))
+    }
+
+    /// `_content_hash` of the committed post with `id`, from whichever tier holds it. `None`
+    /// when there is no such post, it was written before the field existed, or the caller may
+    /// not see it: it belongs to another tenant than `tenant` or is hidden from `groups`.
+    pub fn stored_content_hash(&self, id: &str, tenant: &Option<String>, groups: Option<&[String]>) -> ServiceResult<Option<String>> {
+        match self.check_owner(id, tenant) {
+            Err(ServiceError::NotFound(_)) => return Ok(None),
+            owned => owned?,
+        }
+        let Some((_, doc)) = self.document(id, groups)? else { return Ok(None) };
+        let Ok(f_hash) = self.schema().get_field(CONTENT_HASH_FIELD) else { return Ok(None) };
+        Ok(doc.get_first(f_hash).and_then(|v| v.as_str()).map(str::to_string))
+    }
+
+    /// Runs `text` through the analyzer `field` is indexed with.
+    pub fn analyze(&self, field: &str, text: &str) -> ServiceResult<Vec<AnalyzedToken>> {
+        let index = self.index();
+        let f = index.schema().get_field(field).map_err(|_| ServiceError::Invalid(format!("unknown field: {}", field)))?;
+        let mut analyzer = index.tokenizer_for_field(f).map_err(|e| ServiceError::Invalid(e.to_string()))?;
+        Ok(analyzed_tokens(&mut analyzer, text))
+    }
+
+    /// Runs `text` through the registered analyzer `name`, built-in or custom.
+    pub fn analyze_with(&self, name: &str, text: &str) -> ServiceResult<Vec<AnalyzedToken>> {
+        let mut analyzer = self.index().tokenizers().get(name).ok_or_else(|| ServiceError::Invalid(format!("unknown analyzer: {}", name)))?;
+        Ok(analyzed_tokens(&mut analyzer, text))
+    }
+
+    /// Ingest counters and the write-to-searchable latency histogram in the Prometheus text
+    /// format.
+    pub fn metrics(&self) -> String {
+        self.stats.prometheus()
+    }
+
+    /// Ingest batching counters, write-to-searchable latency, plus shadow comparison totals
+    /// when a shadow index is set.
+    pub fn stats(&self) -> serde_json::Value {
+        let mut snapshot = self.stats.snapshot();
+        if let Some(journal) = &self.journal {
+            snapshot["journal"] = journal.snapshot();
+        }
+        if let Some(replica) = &self.replica {
+            snapshot["replica"] = serde_json::to_value(replica.status()).unwrap_or_default();
+        }
+        if let Some(shadow) = &self.shadow {
+            snapshot["shadow"] = shadow.snapshot();
+        }
+        if let Some(admission) = &self.admission {
+            snapshot["admission"] = admission.snapshot();
+        }
+        if let Some(pins) = &self.session_pins {
+            snapshot["session_pins"] = pins.snapshot();
+        }
+        snapshot["degradation"] = self.degradation.snapshot();
+        let mut breakers = serde_json::Map::new();
+        if let Some(breaker) = self.moderator.as_ref().and_then(Moderator::webhook_breaker) {
+            breakers.insert("moderation_webhook".to_string(), breaker.snapshot());
+        }
+        for (name, breaker) in self.federation.iter().flat_map(Federation::breakers) {
+            breakers.insert(format!("remote:{}", name), breaker.snapshot());
+        }
+        if let Some(breaker) = self.meter.as_ref().and_then(Meter::breaker) {
+            breakers.insert("metering".to_string(), breaker.snapshot());
+        }
+        if !breakers.is_empty() {
+            snapshot["breakers"] = serde_json::Value::Object(breakers);
+        }
+        if !self.migrations.is_empty() {
+            snapshot["migrations"] = serde_json::json!(self.migrations);
+        }
+        let outdated = self.outdated_fields();
+        if !outdated.is_empty() {
+            snapshot["outdated_fields"] = serde_json::json!(outdated);
+        }
+        snapshot
+    }
+}
+
+/// The files of `config` kept in versioned formats.
+fn persisted_stores(config: &ServiceConfig) -> Vec<Store> {
+    let mut stores = vec![
+        Store { name: "analyzers", path: config.analyzers_path.clone() },
+        Store { name: "dead_letters", path: config.dlq_path.clone() },
+    ];
+    if let Some(path) = config.cluster.as_ref().and_then(|c| c.state_path.clone()) {
+        stores.push(Store { name: "raft_state", path });
+    }
+    if let Some(path) = config.follow.as_ref().and_then(|f| f.state_path.clone()) {
+        stores.push(Store { name: "replica_position", path });
+    }
+    stores
+}
+
+/// The tokens `analyzer` turns `text` into.
+fn analyzed_tokens(analyzer: &mut TextAnalyzer, text: &str) -> Vec<AnalyzedToken> {
+    let mut tokens = Vec::new();
+    analyzer.token_stream(text).process(&mut |token| {
+        tokens.push(AnalyzedToken {
+            text: token.text.clone(),
+            position: token.position,
+            offset_from: token.offset_from,
+            offset_to: token.offset_to,
+        })
+    });
+    tokens
+}
+
+/// The ids `ops` write, each with the post it holds afterwards (`None` once deleted).
+fn batch_ids(ops: &[BatchOp]) -> impl Iterator<Item = (&str, Option<&BlogPost>)> {
+    ops.iter().map(|op| match op {
+        BatchOp::Index { doc } | BatchOp::Update { doc } => (doc.id.as_str(), Some(doc)),
+        BatchOp::Delete { id } => (id.as_str(), None),
+    })
+}
+
+/// The journal entry for a post written with `op_type`; an upsert replays as the update it is.
+fn post_write(post: BlogPost, op_type: OpType) -> JournalOp {
+    let op = match op_type {
+        OpType::Create => BatchOp::Index { doc: post },
+        OpType::Upsert => BatchOp::Update { doc: post },
+    };
+    JournalOp::Batch { ops: vec![op] }
+}
+
+/// Adds `post`, an upsert first deleting the post with its id.
+fn add_post(writer: &mut IndexWriter, schema: &Schema, pipeline: &IngestPipeline, post: BlogPost, op_type: OpType) -> tantivy::Result<u64> {
+    if op_type == OpType::Upsert {
+        writer.delete_term(Term::from_field_text(schema.get_field("id").unwrap(), &post.id));
+    }
+    index_post(writer, schema, pipeline, post)
+}
+
+/// The posts of a batch's index and update operations, each flagged as an update since both
+/// replace a post with the same id.
+fn batch_posts(ops: &[BatchOp]) -> impl Iterator<Item = (&BlogPost, bool)> {
+    ops.iter().filter_map(|op| match op {
+        BatchOp::Index { doc } | BatchOp::Update { doc } => Some((doc, true)),
+        BatchOp::Delete { .. } => None,
+    })
+}
+
+/// Runs `req.q` against one tier, restricted to documents matching `filter` when given,
+/// returning scored stored documents and, when requested, the match count. With a deadline, segments are searched one at a time and the deadline is
+/// checked between segments and between document fetches; `timed_out` reports whether it cut
+/// the work short.
+///
+/// The top `req.offset + req.limit` hits are collected (those after `req.search_after`,
+/// otherwise), and the first `skip` of them dropped before fetching.
+pub(crate) fn search_tier(
+    searcher: &Searcher,
+    req: &SearchRequest,
+    limits: &QueryLimits,
+    filter: Option<&dyn Query>,
+    tier: Tier,
+    skip: usize,
+) -> ServiceResult<TierHits> {
+    let expired = || req.deadline.is_some_and(|d| Instant::now() >= d);
+    let mut query = parse_query_with(searcher, &req.q, limits, req.minimum_should_match)?;
+    if let Some(boost) = req.shingle_boost {
+        query = with_shingle_boost(searcher, &req.q, limits, query, boost)?;
+    }
+    // Matches are reported for the query's own terms, not the filter's
+    let mut terms: Vec<Term> = Vec::new();
+    if req.matches {
+        query.query_terms(&mut |term, _| {
+            if !terms.contains(term) {
+                terms.push(term.clone());
+            }
+        });
+    }
+    let snippets = match &req.snippets {
+        Some(options) => Some(Snippets::create(searcher, query.as_ref(), options)?),
+        None => None,
+    };
+    if let Some(filter) = filter {
+        query = filtered(query, filter);
+    }
+    let window = req.offset + req.limit;
+    let mut sort_values = Vec::new();
+    let mut duplicates = Vec::new();
+    let (top_docs, mut timed_out) = match (&req.sort, req.search_after, req.approximate) {
+        _ if req.dedupe_by.is_some() => {
+            let collector = DedupeTopDocs { field: req.dedupe_by.clone().unwrap_or_default(), limit: window };
+            let (groups, timed_out) = collect_until(searcher, query.as_ref(), &collector, req.deadline)?;
+            let top_docs = groups
+                .into_iter()
+                .map(|group| {
+                    duplicates.push(group.duplicates);
+                    (group.score, group.address)
+                })
+                .collect();
+            (top_docs, timed_out)
+        }
+        (Some(sort), _, _) => {
+            let (ranked, timed_out) = collect_until(searcher, query.as_ref(), &sort.top_docs(window), req.deadline)?;
+            let top_docs = ranked
+                .into_iter()
+                .map(|(rank, addr)| {
+                    sort_values.push(sort.value(&rank));
+                    (rank.1, addr)
+                })
+                .collect();
+            (top_docs, timed_out)
+        }
+        (None, Some(after), _) => {
+            let ords: HashMap<SegmentId, u32> =
+                searcher.segment_readers().iter().enumerate().map(|(ord, s)| (s.segment_id(), ord as u32)).collect();
+            // Hits up to the cursor sink below every real score and are dropped below
+            let collector = TopDocs::with_limit(req.limit).tweak_score(move |segment: &SegmentReader| {
+                let ord = ords.get(&segment.segment_id()).copied().unwrap_or_default();
+                move |doc: DocId, score: Score| match after.admits(score, tier, DocAddress::new(ord, doc)) {
+                    true => score,
+                    false => Score::NEG_INFINITY,
+                }
+            });
+            let (top_docs, timed_out) = collect_until(searcher, query.as_ref(), &collector, req.deadline)?;
+            (top_docs.into_iter().filter(|(score, _)| *score != Score::NEG_INFINITY).collect(), timed_out)
+        }
+        (None, None, None) => collect_until(searcher, query.as_ref(), &TopDocs::with_limit(window), req.deadline)?,
+        (None, None, Some(factor)) => {
+            let collector = ApproxTopDocs { limit: window, factor };
+            collect_until(searcher, query.as_ref(), &collector, req.deadline)?
+        }
+    };
+
+    // Counted in a second pass so the top-k pass keeps its block skipping
+    let mut total = None;
+    if let Some(track) = req.track_total_hits.filter(|_| !timed_out) {
+        let (count, count_timed_out) =
+            collect_until(searcher, query.as_ref(), &CountUpTo { up_to: track.cap() }, req.deadline)?;
+        timed_out = count_timed_out;
+        total = (!timed_out).then_some(count);
+    }
+    let mut facets = FacetCounts::new();
+    if !req.facets.is_empty() && !timed_out {
+        let (matched, facets_timed_out) = collect_until(searcher, query.as_ref(), &DocSetCollector, req.deadline)?;
+        timed_out = facets_timed_out;
+        if !timed_out {
+            count_facets(searcher, &matched, &req.facets, &mut facets)?;
+        }
+    }
+
+    let mut hits = Vec::with_capacity(top_docs.len());
+    let (mut addresses, mut matches, mut fragments, mut values, mut counts) = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (i, (score, addr)) in top_docs.into_iter().enumerate().skip(skip) {
+        if expired() {
+            timed_out = true;
+            break;
+        }
+        let doc = searcher.doc::<TantivyDocument>(addr)?;
+        if let Some(snippets) = &snippets {
+            fragments.push(snippets.render(&doc));
+        }
+        hits.push((score, doc));
+        addresses.push(addr);
+        if let Some(value) = sort_values.get(i) {
+            values.push(*value);
+        }
+        if let Some(count) = duplicates.get(i) {
+            counts.push(*count);
+        }
+        if req.matches {
+            matches.push(hit_matches(searcher, addr, &terms)?);
+        }
+    }
+    Ok(TierHits { hits, addresses, matches, snippets: fragments, sort_values: values, duplicates: counts, total, facets, timed_out })
+}
+
+/// Sorts hits of both tiers by their score divided by the `best` score of their tier, best
+/// first. Each tier is scored with its own term statistics, so a term rare in the small hot
+/// tier scores far higher there than the same match in the archive; relative scores put each
+/// tier's best hit level at 1.0 and interleave the rest by how close they come to it. Hits
+/// keep their raw scores, so a merged page isn't ordered by `score`.
+fn by_tier_relative_score(hits: &mut [SearchHit], best: &TierBest) {
+    hits.sort_by(|a, b| best.relative(b.score, b.tier).total_cmp(&best.relative(a.score, a.tier)));
+}
+
+fn tier_hits(tier_hits: TierHits, tier: Tier) -> Vec<SearchHit> {
+    let (mut matches, mut snippets) = (tier_hits.matches.into_iter(), tier_hits.snippets.into_iter());
+    let (mut sort_values, mut duplicates) = (tier_hits.sort_values.into_iter(), tier_hits.duplicates.into_iter());
+    let hits = tier_hits.hits.into_iter().zip(tier_hits.addresses);
+    hits.map(|((score, doc), address)| SearchHit {
+        score,
+        tier,
+        address,
+        doc,
+        matches: matches.next(),
+        snippets: snippets.next(),
+        sort_value: sort_values.next().flatten(),
+        duplicates: duplicates.next(),
+    })
+    .collect()
+}
+
+/// Folds each hit into an earlier one with the same stored value of `field`, from the other
+/// tier, adding up their duplicates.
+fn dedupe_across_tiers(schema: &Schema, field: &str, hits: Vec<SearchHit>) -> Vec<SearchHit> {
+    let Ok(f) = schema.get_field(field) else { return hits };
+    let mut kept: Vec<SearchHit> = Vec::with_capacity(hits.len());
+    let mut by_value: HashMap<String, usize> = HashMap::new();
+    for hit in hits {
+        let Some(value) = hit.doc.get_first(f).and_then(|v| v.as_str()).map(str::to_string) else {
+            kept.push(hit);
+            continue;
+        };
+        match by_value.get(&value) {
+            Some(&i) => kept[i].duplicates = Some(kept[i].duplicates.unwrap_or(0) + hit.duplicates.unwrap_or(0) + 1),
+            None => {
+                by_value.insert(value, kept.len());
+                kept.push(hit);
+            }
+        }
+    }
+    kept
+}
+
+/// The `terms` document `addr` contains, looked up in its segment's postings.
+fn hit_matches(searcher: &Searcher, addr: DocAddress, terms: &[Term]) -> ServiceResult<HitMatches> {
+    let segment = searcher.segment_reader(addr.segment_ord);
+    let schema = searcher.schema();
+    let mut matches = HitMatches::default();
+    for term in terms {
+        let Some(mut postings) = segment.inverted_index(term.field())?.read_postings(term, IndexRecordOption::Basic)? else {
+            continue;
+        };
+        // Postings start at their first document, and seeking never goes backwards
+        if postings.doc() > addr.doc_id || postings.seek(addr.doc_id) != addr.doc_id {
+            continue;
+        }
+        let field = schema.get_field_name(term.field()).to_string();
+        if !matches.fields.contains(&field) {
+            matches.fields.push(field.clone());
+        }
+        matches.terms.push(MatchedTerm { field, term: term_text(term) });
+    }
+    Ok(matches)
+}
+
+/// The string leaves of a JSON value with their dotted paths below `prefix`.
+fn json_string_leaves(value: &serde_json::Value, prefix: String, out: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::String(s) if !prefix.is_empty() => out.push((prefix, s.clone())),
+        serde_json::Value::Array(values) => values.iter().for_each(|v| json_string_leaves(v, prefix.clone(), out)),
+        serde_json::Value::Object(map) => {
+            for (key, v) in map {
+                let path = match prefix.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", prefix, key),
+                };
+                json_string_leaves(v, path, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A term's value as text; JSON terms as `<path>:<value>`.
+pub(crate) fn term_text(term: &Term) -> String {
+    let value = term.value();
+    if value.typ() != Type::Json {
+        return value_text(&value);
+    }
+    let bytes = term.serialized_value_bytes();
+    let Some(end) = bytes.iter().position(|b| *b == JSON_END_OF_PATH) else { return format!("{:?}", term) };
+    let path = String::from_utf8_lossy(&bytes[..end]).replace('\u{1}', ".");
+    format!("{}:{}", path, value_text(&ValueBytes::wrap(&bytes[end + 1..])))
+}
+
+fn value_text(value: &ValueBytes<&[u8]>) -> String {
+    let text = match value.typ() {
+        Type::Str => value.as_str().map(str::to_string),
+        Type::U64 => value.as_u64().map(|v| v.to_string()),
+        Type::I64 => value.as_i64().map(|v| v.to_string()),
+        Type::F64 => value.as_f64().map(|v| v.to_string()),
+        Type::Bool => value.as_bool().map(|v| v.to_string()),
+        Type::Date => value.as_date().map(|v| format!("{:?}", v)),
+        Type::Facet => value.as_facet().map(|v| v.to_string()),
+        _ => None,
+    };
+    text.unwrap_or_else(|| format!("{:?}", value.typ()))
+}
+
+/// `query` restricted to documents matching `filter`, which doesn't contribute to the score.
+pub(crate) fn filtered(query: Box<dyn Query>, filter: &dyn Query) -> Box<dyn Query> {
+    let filter: Box<dyn Query> = Box::new(BoostQuery::new(filter.box_clone(), 0.0));
+    Box::new(BooleanQuery::new(vec![(Occur::Must, query), (Occur::Must, filter)]))
+}
+
+fn collect_until<C: Collector>(
+    searcher: &Searcher,
+    query: &dyn Query,
+    collector: &C,
+    deadline: Option<Instant>,
+) -> ServiceResult<(C::Fruit, bool)> {
+    let Some(deadline) = deadline else { return Ok((searcher.search(query, collector)?, false)) };
+    let scoring = match collector.requires_scoring() {
+        true => EnableScoring::enabled_from_searcher(searcher),
+        false => EnableScoring::disabled_from_searcher(searcher),
+    };
+    let weight = query.weight(scoring)?;
+    let mut fruits = Vec::new();
+    let mut timed_out = false;
+    for (ord, segment) in searcher.segment_readers().iter().enumerate() {
+        if Instant::now() >= deadline {
+            timed_out = true;
+            break;
+        }
+        fruits.push(collector.collect_segment(weight.as_ref(), ord as u32, segment)?);
+    }
+    Ok((collector.merge_fruits(fruits)?, timed_out))
+}
+
+/// `q` OR any of the expansion tags, the latter down-weighted so original matches still rank
+/// first.
+fn expand_query(q: &str, terms: &[String]) -> String {
+    if terms.is_empty() {
+        return q.to_string();
+    }
+    let tags: Vec<String> = terms.iter().map(|t| format!("tags:\"{}\"", t)).collect();
+    format!("({}) OR ({})^{}", q, tags.join(" OR "), EXPANSION_BOOST)
+}
+
+// Index sorting is deprecated in tantivy 0.22 and slated for removal upstream; keep its use
+// confined to these two functions so dropping it is a local change.
+#[allow(deprecated)]
+pub(crate) fn index_settings(config: &ServiceConfig) -> IndexSettings {
+    IndexSettings {
+        sort_by_field: config
+            .sort_by_create_at
+            .then(|| tantivy::IndexSortByField { field: "create_at".to_string(), order: Order::Desc }),
+        ..IndexSettings::default()
+    }
+}
+
+#[allow(deprecated)]
+pub(crate) fn sorted_by_create_at(index: &Index) -> bool {
+    index
+        .settings()
+        .sort_by_field
+        .as_ref()
+        .is_some_and(|sort| sort.field == "create_at" && sort.order == Order::Desc)
+}
+
+/// Documents carrying any of `tags` (all documents if empty), optionally only those with
+/// `create_at >= since`.
+fn latest_query(schema: &Schema, field: &str, tags: &[String], since: Option<i64>) -> Box<dyn Query> {
+    let f_tags = schema.get_field("tags").unwrap();
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    if !tags.is_empty() {
+        let any_tag: Vec<(Occur, Box<dyn Query>)> = tags
+            .iter()
+            .map(|t| {
+                let term = Term::from_field_text(f_tags, t);
+                (Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>)
+            })
+            .collect();
+        clauses.push((Occur::Must, Box::new(BooleanQuery::new(any_tag))));
+    }
+    if let Some(since) = since {
+        let range = RangeQuery::new_i64_bounds(
+            field.to_string(),
+            std::ops::Bound::Included(since),
+            std::ops::Bound::Unbounded,
+        );
+        clauses.push((Occur::Must, Box::new(range)));
+    }
+    if clauses.is_empty() {
+        posts_only(schema, Box::new(AllQuery))
+    } else {
+        posts_only(schema, Box::new(BooleanQuery::new(clauses)))
+    }
+}
+
+/// Newest-first hits ordered by the collector's fast field.
+fn latest_hits(
+    searcher: &Searcher,
+    query: &dyn Query,
+    collector: &NewestFirst,
+) -> tantivy::Result<(Vec<(i64, TantivyDocument)>, ScanProfile)> {
+    let (top, profile) = searcher.search(query, collector)?;
+    let mut hits = Vec::with_capacity(top.len());
+    for (create_at, addr) in top {
+        hits.push((create_at, searcher.doc(addr)?));
+    }
+    Ok((hits, profile))
+}
@@ -0,0 +1,127 @@
+//! Sticky snapshots: a browsing session that opts in (an `X-Search-Session` header or a
+//! `search_session` cookie holding any id the client picks) is pinned to the searchers it first
+//! saw for a few minutes, so paging through results doesn't reshuffle them when background
+//! commits swap in new searchers.
+//!
+//! A pin holds its searchers, and with them their segments, until it expires, so the pinned
+//! sessions are bounded; past the bound new sessions just see the current searchers.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tantivy::Searcher;
+
+use crate::service::SearchService;
+
+/// Header naming the caller's session.
+pub const SESSION_HEADER: &str = "X-Search-Session";
+
+/// Cookie naming the caller's session, for browsers.
+pub const SESSION_COOKIE: &str = "search_session";
+
+/// Response header with the generation of the searchers a pinned search ran on.
+pub const GENERATION_HEADER: &str = "X-Search-Generation";
+
+/// Session ids longer than this aren't pinned.
+const MAX_SESSION_LEN: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct SessionPinning {
+    /// How long a session stays on the searchers it first saw
+    pub ttl: Duration,
+    /// Sessions pinned at once at most
+    pub max_sessions: usize,
+}
+
+/// The searchers of both tiers as of one refresh.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Generation of the hot searcher, as reported in `X-Search-Generation`
+    pub generation: u64,
+    pub hot: Arc<Searcher>,
+    pub archive: Arc<Searcher>,
+}
+
+struct Pin {
+    snapshot: Snapshot,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct PinCounters {
+    pinned: AtomicU64,
+    reused: AtomicU64,
+    /// New sessions left unpinned because `max_sessions` were pinned
+    full: AtomicU64,
+}
+
+pub struct SessionPins {
+    config: SessionPinning,
+    pins: Mutex<HashMap<String, Pin>>,
+    counters: PinCounters,
+}
+
+impl SessionPins {
+    pub fn new(config: SessionPinning) -> Self {
+        SessionPins { config, pins: Mutex::new(HashMap::new()), counters: PinCounters::default() }
+    }
+
+    /// The snapshot `session` is pinned to, pinning it to `current` if it has none (or its pin
+    /// expired). `None` when the session can't be pinned.
+    fn pin(&self, session: &str, current: impl FnOnce() -> Snapshot) -> Option<Snapshot> {
+        if session.is_empty() || session.len() > MAX_SESSION_LEN {
+            return None;
+        }
+        let now = Instant::now();
+        let mut pins = self.pins.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(pin) = pins.get(session).filter(|p| p.expires > now) {
+            self.counters.reused.fetch_add(1, Ordering::Relaxed);
+            return Some(pin.snapshot.clone());
+        }
+        // Expired pins let go of their searchers whenever a session is (re)pinned
+        pins.retain(|_, p| p.expires > now);
+        if pins.len() >= self.config.max_sessions {
+            self.counters.full.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let snapshot = current();
+        pins.insert(session.to_string(), Pin { snapshot: snapshot.clone(), expires: now + self.config.ttl });
+        self.counters.pinned.fetch_add(1, Ordering::Relaxed);
+        Some(snapshot)
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let now = Instant::now();
+        let pins = self.pins.lock().unwrap_or_else(|p| p.into_inner());
+        let live: Vec<&Pin> = pins.values().filter(|p| p.expires > now).collect();
+        let mut generations: Vec<u64> = live.iter().map(|p| p.snapshot.generation).collect();
+        generations.sort_unstable();
+        generations.dedup();
+        let c = &self.counters;
+        serde_json::json!({
+            "ttl_secs": self.config.ttl.as_secs(),
+            "max_sessions": self.config.max_sessions,
+            "sessions": live.len(),
+            "generations": generations,
+            "pinned": c.pinned.load(Ordering::Relaxed),
+            "reused": c.reused.load(Ordering::Relaxed),
+            "full": c.full.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl SearchService {
+    /// The current searchers of both tiers.
+    pub fn current_snapshot(&self) -> Snapshot {
+        let hot = self.current_searcher.load_full();
+        Snapshot { generation: hot.generation().generation_id(), hot, archive: self.archive.current_searcher.load_full() }
+    }
+
+    /// The searchers `session` is pinned to; `None` when pinning is off or the session can't be
+    /// pinned, in which case it searches the current ones.
+    pub fn session_snapshot(&self, session: &str) -> Option<Snapshot> {
+        self.session_pins.as_ref()?.pin(session, || self.current_snapshot())
+    }
+}
@@ -0,0 +1,146 @@
+//! The server's `--config` file: a TOML table of its command-line options by long name
+//! (`max_query_clauses = 2048`, `nested_path = ["snippet"]`), applied before the command line,
+//! which wins where both set an option.
+//!
+//! The file is watched while the server runs. Changes to the [`HOT_SETTINGS`] take effect on
+//! the next query; every other option only takes effect on restart, and `/admin/settings`
+//! lists those that changed since startup.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+
+/// Options applied to the running service when the file changes.
+pub const HOT_SETTINGS: &[&str] = &[
+    "max_query_clauses",
+    "max_query_terms",
+    "max_search_timeout_ms",
+    "query_cost_budget",
+    "admission_busy_at",
+    "over_budget",
+    "admission_queue_ms",
+    "degrade_slow_ms",
+    "degrade_error_budget",
+    "degrade_window_secs",
+    "feature_flag",
+];
+
+/// How long to wait after a change for more, as editors save in several steps.
+const SETTLE: Duration = Duration::from_millis(200);
+
+pub fn read_config(path: &Path) -> anyhow::Result<toml::Table> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    text.parse::<toml::Table>().with_context(|| format!("parsing {}", path.display()))
+}
+
+/// The file's options as command-line arguments, `--name=value` each; arrays repeat the option.
+pub fn config_args(table: &toml::Table) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        if name == "config" {
+            anyhow::bail!("config: a config file can't name another one");
+        }
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => args.push(format!("--{}", name)),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(s) => args.push(format!("--{}={}", name, s)),
+                toml::Value::Integer(n) => args.push(format!("--{}={}", name, n)),
+                toml::Value::Float(n) => args.push(format!("--{}={}", name, n)),
+                _ => anyhow::bail!("{}: expected a string, number, boolean or an array of them", key),
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// An option whose value differs between two versions of the file.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    pub name: String,
+    /// `None` where the file doesn't set it
+    pub old: Option<String>,
+    pub new: Option<String>,
+    /// Whether the running service picks it up, see [`HOT_SETTINGS`]
+    pub live: bool,
+}
+
+/// The options that differ between `old` and `new`, in name order.
+pub fn changes(old: &toml::Table, new: &toml::Table) -> Vec<SettingChange> {
+    let normalized = |table: &toml::Table| -> std::collections::BTreeMap<String, String> {
+        table.iter().map(|(k, v)| (k.replace('-', "_"), v.to_string())).collect()
+    };
+    let (old, new) = (normalized(old), normalized(new));
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| old.get(*name) != new.get(*name))
+        .map(|name| SettingChange {
+            name: name.clone(),
+            old: old.get(name).cloned(),
+            new: new.get(name).cloned(),
+            live: HOT_SETTINGS.contains(&name.as_str()),
+        })
+        .collect()
+}
+
+/// What `/admin/settings` reports about the file.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReloadStatus {
+    pub path: PathBuf,
+    /// Changes applied since startup
+    pub reloads: u64,
+    pub last_reload_at: Option<i64>,
+    /// Why the last change wasn't applied, until one is
+    pub last_error: Option<String>,
+    /// Options changed since startup that take effect on restart
+    pub restart_required: Vec<SettingChange>,
+}
+
+impl ReloadStatus {
+    /// Records an applied change, `restart_required` being the file's changes since startup.
+    pub fn reloaded(&mut self, restart_required: Vec<SettingChange>) {
+        self.reloads += 1;
+        self.last_reload_at = Some(crate::now_secs());
+        self.last_error = None;
+        self.restart_required = restart_required.into_iter().filter(|c| !c.live).collect();
+    }
+}
+
+/// Calls `on_change` on a thread of its own whenever `path` is written, created or replaced,
+/// once things settle. Watching stops when the returned watcher is dropped.
+pub fn watch(path: &Path, on_change: impl Fn() + Send + 'static) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel();
+    let name = path.file_name().map(|n| n.to_os_string());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if (event.kind.is_modify() || event.kind.is_create()) && event.paths.iter().any(|p| p.file_name() == name.as_deref()) {
+            let _ = tx.send(());
+        }
+    })?;
+    // The directory rather than the file, which editors and config management replace
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    std::thread::spawn(move || {
+        while rx.recv().is_ok() {
+            std::thread::sleep(SETTLE);
+            while rx.try_recv().is_ok() {}
+            on_change();
+        }
+    });
+    Ok(watcher)
+}
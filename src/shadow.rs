@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tantivy::schema::{Schema, Value};
+use tantivy::{Index, IndexReader, ReloadPolicy, TantivyDocument};
+
+use crate::schema::register_analyzers;
+use crate::query::QueryLimits;
+use crate::service::{search_tier, SearchRequest, Tier};
+
+/// Canary target: a second index that a sample of live queries is replayed against so result
+/// differences can be measured before cutting over to it. It is opened read-only and
+/// reloaded with the hot index. Comparisons are counted for `/stats` and logged at debug
+/// level.
+pub struct ShadowIndex {
+    pub reader: IndexReader,
+    pub sample_rate: f64,
+    pub totals: Mutex<ShadowTotals>,
+}
+
+#[derive(Default, Serialize)]
+pub struct ShadowTotals {
+    pub queries: u64,
+    pub errors: u64,
+    pub overlap_sum: f64,
+    pub rank_correlation_sum: f64,
+    pub rank_correlation_samples: u64,
+}
+
+impl ShadowIndex {
+    pub fn open(path: &PathBuf, sample_pct: f64) -> anyhow::Result<Self> {
+        let index = Index::open_in_dir(path)?;
+        register_analyzers(&index);
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        Ok(ShadowIndex {
+            reader,
+            sample_rate: (sample_pct / 100.0).clamp(0.0, 1.0),
+            totals: Mutex::new(ShadowTotals::default()),
+        })
+    }
+
+    /// Replays one query against the shadow index and records how its top-k compares.
+    pub fn compare(&self, q: &str, limit: usize, limits: &QueryLimits, live: &[String]) {
+        let searcher = self.reader.searcher();
+        let req = SearchRequest { q: q.to_string(), limit, ..SearchRequest::default() };
+        let hits = match search_tier(&searcher, &req, limits, None, Tier::Hot, 0) {
+            Ok(tier) => tier.hits,
+            Err(_) => {
+                self.record(None);
+                log::warn!("shadow q={:?}: query failed on shadow index", q);
+                return;
+            }
+        };
+        let shadow_ids = hit_ids(&searcher.index().schema(), &hits);
+        let overlap = overlap_at_k(live, &shadow_ids);
+        let tau = rank_correlation(live, &shadow_ids);
+        self.record(Some((overlap, tau)));
+        // Averages are in `/stats`; each comparison only with RUST_LOG=tantivy_demo::shadow=debug
+        log::debug!(
+            "shadow q={:?} k={} overlap@k={:.2} rank_correlation={}",
+            q,
+            limit,
+            overlap,
+            tau.map(|t| format!("{:.2}", t)).unwrap_or_else(|| "n/a".to_string())
+        );
+    }
+
+    fn record(&self, outcome: Option<(f64, Option<f64>)>) {
+        let mut totals = self.totals.lock().unwrap_or_else(|p| p.into_inner());
+        match outcome {
+            None => totals.errors += 1,
+            Some((overlap, tau)) => {
+                totals.queries += 1;
+                totals.overlap_sum += overlap;
+                if let Some(tau) = tau {
+                    totals.rank_correlation_sum += tau;
+                    totals.rank_correlation_samples += 1;
+                }
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let totals = self.totals.lock().unwrap_or_else(|p| p.into_inner());
+        let mean = |sum: f64, n: u64| if n == 0 { None } else { Some(sum / n as f64) };
+        serde_json::json!({
+            "queries": totals.queries,
+            "errors": totals.errors,
+            "avg_overlap_at_k": mean(totals.overlap_sum, totals.queries),
+            "avg_rank_correlation": mean(totals.rank_correlation_sum, totals.rank_correlation_samples),
+        })
+    }
+}
+
+/// Fraction of the top-k live ids also returned by the shadow index.
+fn overlap_at_k(live: &[String], shadow: &[String]) -> f64 {
+    if live.is_empty() {
+        return if shadow.is_empty() { 1.0 } else { 0.0 };
+    }
+    let shadow: std::collections::HashSet<&String> = shadow.iter().collect();
+    live.iter().filter(|id| shadow.contains(id)).count() as f64 / live.len() as f64
+}
+
+/// Kendall's tau over the ids both rankings share; `None` with fewer than two shared ids.
+fn rank_correlation(live: &[String], shadow: &[String]) -> Option<f64> {
+    let shadow_rank: std::collections::HashMap<&String, usize> = shadow.iter().enumerate().map(|(r, id)| (id, r)).collect();
+    let shared: Vec<usize> = live.iter().filter_map(|id| shadow_rank.get(id).copied()).collect();
+    if shared.len() < 2 {
+        return None;
+    }
+    let mut concordant = 0i64;
+    let mut discordant = 0i64;
+    for i in 0..shared.len() {
+        for j in i + 1..shared.len() {
+            if shared[i] < shared[j] {
+                concordant += 1;
+            } else {
+                discordant += 1;
+            }
+        }
+    }
+    Some((concordant - discordant) as f64 / (concordant + discordant) as f64)
+}
+
+pub(crate) fn hit_ids(schema: &Schema, hits: &[(f32, TantivyDocument)]) -> Vec<String> {
+    let f_id = schema.get_field("id").unwrap();
+    hits.iter()
+        .filter_map(|(_, doc)| doc.get_first(f_id).and_then(|v| v.as_str()).map(str::to_string))
+        .collect()
+}
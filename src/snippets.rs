@@ -0,0 +1,304 @@
+//! Highlighted fragments of a hit's `title` and `body`, cut around the query's terms. The
+//! query's terms are looked for in the tokens each field's analyzer makes of the stored text,
+//! and fragments holding more of them, rarer terms weighing more (as tantivy's
+//! [`SnippetGenerator`](tantivy::snippet::SnippetGenerator) weighs them), are picked first.
+//! With the n-gram analyzers the matched n-grams overlap, so a word shows up as one highlight.
+//!
+//! Lengths are counted in characters rather than bytes, so a CJK fragment is as long as a
+//! Latin one, and fragment edges snap to [`SnippetBoundary`]: Latin words aren't cut, while CJK
+//! text, written without spaces, is cut between characters, at punctuation when there is some
+//! near the edge.
+//!
+//! Fragments are HTML-escaped; the highlight tags and the separator are inserted as given, so
+//! callers rendering into HTML should only pass ones they trust.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use serde::Deserialize;
+use tantivy::query::Query;
+use tantivy::schema::{Field, Value};
+use tantivy::tokenizer::TextAnalyzer;
+use tantivy::{Score, Searcher, TantivyDocument};
+
+use crate::error::{ServiceError, ServiceResult};
+
+/// Fields snippets are cut from.
+pub const SNIPPET_FIELDS: &[&str] = &["title", "body"];
+
+/// Longest fragment a request may ask for, in characters.
+pub const MAX_SNIPPET_CHARS: usize = 1000;
+
+/// Most fragments a request may ask for per field.
+pub const MAX_SNIPPET_FRAGMENTS: usize = 10;
+
+/// How far from a CJK edge `auto` looks for punctuation to cut at, in characters.
+const CJK_SNAP_CHARS: usize = 8;
+
+/// Where fragments start and end.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SnippetBoundary {
+    /// Between words in text with spaces; anywhere between CJK characters, at punctuation within
+    /// a few characters of the edge if there is some
+    #[default]
+    Auto,
+    /// After a sentence end (`.`, `!`, `?`, `;`, `。`, `！`, `？`, `；` or a line break) inside
+    /// the fragment, as `auto` where there is none
+    Sentence,
+    /// At the character count, wherever that falls
+    Char,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnippetOptions {
+    /// Longest fragment, in characters
+    pub max_chars: usize,
+    /// Most fragments per field, in text order
+    pub fragments: usize,
+    /// Put between the fragments of a field
+    pub separator: String,
+    pub boundary: SnippetBoundary,
+    pub pre_tag: String,
+    pub post_tag: String,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        SnippetOptions {
+            max_chars: 150,
+            fragments: 1,
+            separator: " … ".to_string(),
+            boundary: SnippetBoundary::Auto,
+            pre_tag: "<b>".to_string(),
+            post_tag: "</b>".to_string(),
+        }
+    }
+}
+
+impl SnippetOptions {
+    pub fn validate(&self) -> ServiceResult<()> {
+        if self.max_chars == 0 || self.max_chars > MAX_SNIPPET_CHARS {
+            return Err(ServiceError::Invalid(format!("snippet_chars must be between 1 and {}", MAX_SNIPPET_CHARS)));
+        }
+        if self.fragments == 0 || self.fragments > MAX_SNIPPET_FRAGMENTS {
+            return Err(ServiceError::Invalid(format!("snippet_fragments must be between 1 and {}", MAX_SNIPPET_FRAGMENTS)));
+        }
+        Ok(())
+    }
+}
+
+/// The query's terms in one field, by weight, and the field's analyzer.
+struct FieldTerms {
+    name: &'static str,
+    field: Field,
+    terms: BTreeMap<String, Score>,
+    analyzer: TextAnalyzer,
+}
+
+/// Snippet generation for one query on one searcher.
+pub(crate) struct Snippets {
+    fields: Vec<FieldTerms>,
+    options: SnippetOptions,
+}
+
+/// Matched terms next to or overlapping each other, highlighted as one.
+struct Run {
+    /// In characters
+    chars: Range<usize>,
+    terms: BTreeMap<String, Score>,
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F)
+}
+
+fn is_sentence_end(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | ';' | '\n' | '。' | '！' | '？' | '；')
+}
+
+/// Whether a fragment may start right after or end right at `c`.
+fn is_break(c: char, boundary: SnippetBoundary) -> bool {
+    match boundary {
+        SnippetBoundary::Sentence => is_sentence_end(c),
+        _ => !c.is_alphanumeric(),
+    }
+}
+
+/// Moves `start` forward, no further than `core`, so the fragment doesn't begin mid-word.
+fn snap_start(chars: &[char], start: usize, core: usize, boundary: SnippetBoundary) -> usize {
+    if start == 0 || boundary == SnippetBoundary::Char || is_break(chars[start - 1], boundary) {
+        return start;
+    }
+    let cjk = is_cjk(chars[start - 1]) || is_cjk(chars[start]);
+    let limit = match (boundary, cjk) {
+        (SnippetBoundary::Auto, true) => core.min(start + CJK_SNAP_CHARS),
+        _ => core,
+    };
+    match (start + 1..=limit).find(|&i| is_break(chars[i - 1], boundary)) {
+        Some(i) => i,
+        None if boundary == SnippetBoundary::Sentence => snap_start(chars, start, core, SnippetBoundary::Auto),
+        None => start,
+    }
+}
+
+/// Moves `end` back, no further than `core`, so the fragment doesn't end mid-word; a
+/// punctuation mark it ends at is kept.
+fn snap_end(chars: &[char], end: usize, core: usize, boundary: SnippetBoundary) -> usize {
+    if end == chars.len() || boundary == SnippetBoundary::Char || is_break(chars[end - 1], boundary) {
+        return end;
+    }
+    let cjk = is_cjk(chars[end - 1]) || is_cjk(chars[end]);
+    if boundary == SnippetBoundary::Auto && !cjk && !chars[end].is_alphanumeric() {
+        return end;
+    }
+    let limit = match (boundary, cjk) {
+        (SnippetBoundary::Auto, true) => core.max(end.saturating_sub(CJK_SNAP_CHARS)),
+        _ => core,
+    };
+    match (limit..end).rev().find(|&i| is_break(chars[i], boundary)) {
+        Some(i) => i + 1,
+        None if boundary == SnippetBoundary::Sentence => snap_end(chars, end, core, SnippetBoundary::Auto),
+        None => end,
+    }
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+}
+
+impl Snippets {
+    pub fn create(searcher: &Searcher, query: &dyn Query, options: &SnippetOptions) -> ServiceResult<Self> {
+        let schema = searcher.schema();
+        let mut fields = Vec::new();
+        for name in SNIPPET_FIELDS {
+            let Ok(field) = schema.get_field(name) else { continue };
+            let mut query_terms = Vec::new();
+            query.query_terms(&mut |term, _| {
+                if term.field() == field && !query_terms.contains(term) {
+                    query_terms.push(term.clone());
+                }
+            });
+            let mut terms = BTreeMap::new();
+            for term in query_terms {
+                let Some(text) = term.value().as_str().map(str::to_string) else { continue };
+                let doc_freq = searcher.doc_freq(&term)?;
+                if doc_freq > 0 {
+                    terms.insert(text, 1.0 / (1.0 + doc_freq as Score));
+                }
+            }
+            let analyzer = searcher.index().tokenizer_for_field(field)?;
+            fields.push(FieldTerms { name, field, terms, analyzer });
+        }
+        Ok(Snippets { fields, options: options.clone() })
+    }
+
+    /// The highlighted fragments of `doc` by field; fields the query doesn't match are left out.
+    pub fn render(&self, doc: &TantivyDocument) -> BTreeMap<String, String> {
+        let mut snippets = BTreeMap::new();
+        for field in self.fields.iter().filter(|f| !f.terms.is_empty()) {
+            let values: Vec<&str> = doc.get_all(field.field).filter_map(|v| v.as_str()).collect();
+            let text = values.join(" ");
+            let fragments = self.fragments(field, text.trim());
+            if !fragments.is_empty() {
+                snippets.insert(field.name.to_string(), fragments.join(&self.options.separator));
+            }
+        }
+        snippets
+    }
+
+    /// Highlighted, HTML-escaped fragments of `text` in text order.
+    fn fragments(&self, field: &FieldTerms, text: &str) -> Vec<String> {
+        let options = &self.options;
+        let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+        let char_at = |byte: usize| offsets.binary_search(&byte).unwrap_or_else(|i| i);
+        let chars: Vec<char> = text.chars().collect();
+
+        let mut matches = Vec::new();
+        field.analyzer.clone().token_stream(text).process(&mut |token| {
+            if let Some(&score) = field.terms.get(&token.text.to_lowercase()) {
+                matches.push((char_at(token.offset_from)..char_at(token.offset_to), token.text.to_lowercase(), score));
+            }
+        });
+        matches.sort_by_key(|(chars, _, _)| (chars.start, chars.end));
+        let mut runs: Vec<Run> = Vec::new();
+        for (range, term, score) in matches {
+            match runs.last_mut() {
+                Some(run) if range.start <= run.chars.end => {
+                    run.chars.end = run.chars.end.max(range.end);
+                    run.terms.insert(term, score);
+                }
+                _ => runs.push(Run { chars: range, terms: BTreeMap::from([(term, score)]) }),
+            }
+        }
+
+        // From each run, as many of the following ones as fit, scored by the distinct terms
+        let mut candidates: Vec<(Range<usize>, Score)> = Vec::new();
+        for (i, first) in runs.iter().enumerate() {
+            let mut terms: BTreeMap<&str, Score> = BTreeMap::new();
+            let mut end = first.chars.end;
+            for (j, run) in runs[i..].iter().enumerate() {
+                if j > 0 && run.chars.end - first.chars.start > options.max_chars {
+                    break;
+                }
+                terms.extend(run.terms.iter().map(|(t, s)| (t.as_str(), *s)));
+                end = run.chars.end.min(first.chars.start + options.max_chars);
+            }
+            candidates.push((first.chars.start..end, terms.values().sum()));
+        }
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.start.cmp(&b.0.start)));
+        let mut cores: Vec<Range<usize>> = Vec::new();
+        for (core, _) in candidates {
+            if cores.len() == options.fragments {
+                break;
+            }
+            if !cores.iter().any(|c| c.start < core.end && core.start < c.end) {
+                cores.push(core);
+            }
+        }
+        cores.sort_by_key(|c| c.start);
+
+        let mut fragments = Vec::new();
+        let mut taken = 0;
+        for (k, core) in cores.iter().enumerate() {
+            // Context around the matches, split evenly between both sides where there is text
+            let next = cores.get(k + 1).map_or(chars.len(), |c| c.start);
+            let room = options.max_chars - core.len();
+            let before = (room / 2).min(core.start - taken);
+            let after = (room - before).min(next - core.end);
+            let before = (room - after).min(core.start - taken);
+            let mut start = snap_start(&chars, core.start - before, core.start, options.boundary);
+            let mut end = snap_end(&chars, core.end + after, core.end, options.boundary);
+            while start < core.start && chars[start].is_whitespace() {
+                start += 1;
+            }
+            while end > core.end && chars[end - 1].is_whitespace() {
+                end -= 1;
+            }
+            taken = end;
+
+            let mut html = String::new();
+            let mut at = start;
+            for run in runs.iter().filter(|r| r.chars.start < end && start < r.chars.end) {
+                let (from, to) = (run.chars.start.max(start), run.chars.end.min(end));
+                escape_html(&text[offsets[at]..offsets[from]], &mut html);
+                html.push_str(&options.pre_tag);
+                escape_html(&text[offsets[from]..offsets[to]], &mut html);
+                html.push_str(&options.post_tag);
+                at = to;
+            }
+            escape_html(&text[offsets[at]..offsets[end]], &mut html);
+            fragments.push(html);
+        }
+        fragments
+    }
+}
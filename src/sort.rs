@@ -0,0 +1,87 @@
+//! Ordering hits by a field instead of by score: `sort=create_at:desc`. Hits are ranked by
+//! the field's value, read from its fast column, with the score breaking ties; documents
+//! without a value come last in either order.
+//!
+//! Only i64 fast fields sort (`create_at`, `_indexed_at`).
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use tantivy::collector::TopDocs;
+use tantivy::schema::{FieldType, Schema};
+use tantivy::{DocId, Order, Score, SegmentReader};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::schema::RESTRICTED_FIELDS;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortBy {
+    pub field: String,
+    pub order: Order,
+}
+
+impl FromStr for SortBy {
+    type Err = ServiceError;
+
+    /// `<field>`, `<field>:desc` (the default) or `<field>:asc`.
+    fn from_str(s: &str) -> ServiceResult<Self> {
+        let (field, order) = match s.trim().rsplit_once(':') {
+            Some((field, "desc")) => (field, Order::Desc),
+            Some((field, "asc")) => (field, Order::Asc),
+            Some(_) => return Err(ServiceError::Invalid(format!("sort must be like create_at:desc or create_at:asc, got {}", s))),
+            None => (s.trim(), Order::Desc),
+        };
+        Ok(SortBy { field: field.trim().to_string(), order })
+    }
+}
+
+/// Where a hit ranks: higher first. `!value` turns ascending order into descending without
+/// overflowing; a missing value ranks below every present one.
+pub(crate) type SortRank = (Option<i64>, Score);
+
+impl SortBy {
+    /// Checks that the field can be sorted by.
+    pub(crate) fn check(&self, schema: &Schema) -> ServiceResult<()> {
+        let field = schema
+            .get_field(&self.field)
+            .ok()
+            .filter(|_| !RESTRICTED_FIELDS.contains(&self.field.as_str()))
+            .ok_or_else(|| ServiceError::Invalid(format!("unknown field: {}", self.field)))?;
+        match schema.get_field_entry(field).field_type() {
+            FieldType::I64(options) if options.is_fast() => Ok(()),
+            _ => Err(ServiceError::Invalid(format!("can't sort by {}: only i64 fast fields such as create_at sort", self.field))),
+        }
+    }
+
+    fn rank(&self, value: Option<i64>, score: Score) -> SortRank {
+        let value = match self.order {
+            Order::Desc => value,
+            Order::Asc => value.map(|v| !v),
+        };
+        (value, score)
+    }
+
+    /// The top `limit` hits in this order, each with its rank.
+    pub(crate) fn top_docs(&self, limit: usize) -> impl tantivy::collector::Collector<Fruit = Vec<(SortRank, tantivy::DocAddress)>> {
+        let sort = self.clone();
+        TopDocs::with_limit(limit).tweak_score(move |segment: &SegmentReader| {
+            let column = segment.fast_fields().i64(&sort.field).ok();
+            let sort = sort.clone();
+            move |doc: DocId, score: Score| sort.rank(column.as_ref().and_then(|c| c.first(doc)), score)
+        })
+    }
+
+    /// The value of the sort field behind `rank`.
+    pub(crate) fn value(&self, rank: &SortRank) -> Option<i64> {
+        match self.order {
+            Order::Desc => rank.0,
+            Order::Asc => rank.0.map(|v| !v),
+        }
+    }
+
+    /// Orders two hits, by value and then score, as the collector ranks them.
+    pub(crate) fn compare(&self, a: (Option<i64>, Score), b: (Option<i64>, Score)) -> Ordering {
+        let (a, b) = (self.rank(a.0, a.1), self.rank(b.0, b.1));
+        b.0.cmp(&a.0).then_with(|| b.1.total_cmp(&a.1))
+    }
+}
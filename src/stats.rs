@@ -0,0 +1,258 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the write-to-searchable latency buckets, in milliseconds.
+const VISIBILITY_BUCKETS_MS: [u64; 12] = [10, 50, 100, 250, 500, 1000, 2000, 3000, 5000, 10_000, 30_000, 60_000];
+
+/// Acknowledged writes remembered per searcher swap; beyond it a uniform sample is kept.
+const MAX_PENDING_ACKS: usize = 4096;
+
+pub struct Stats {
+    pub index_batches: AtomicU64,
+    pub index_batched_docs: AtomicU64,
+    pub index_last_batch_size: AtomicU64,
+    pub index_max_batch_size: AtomicU64,
+    pub commits: AtomicU64,
+    /// Microseconds the hot writer was locked by the last commit, and the whole commit
+    pub commit_last_locked_us: AtomicU64,
+    pub commit_last_total_us: AtomicU64,
+    pub commit_max_locked_us: AtomicU64,
+    /// Interval the commit loop currently aims for, and the write rate it is based on
+    /// (`f64` bits, writes per second)
+    pub commit_interval_us: AtomicU64,
+    pub write_rate: AtomicU64,
+    pub visibility: VisibilityLatency,
+}
+
+impl Stats {
+    pub fn new(nrt_slo: Duration) -> Self {
+        Stats {
+            index_batches: AtomicU64::new(0),
+            index_batched_docs: AtomicU64::new(0),
+            index_last_batch_size: AtomicU64::new(0),
+            index_max_batch_size: AtomicU64::new(0),
+            commits: AtomicU64::new(0),
+            commit_last_locked_us: AtomicU64::new(0),
+            commit_last_total_us: AtomicU64::new(0),
+            commit_max_locked_us: AtomicU64::new(0),
+            commit_interval_us: AtomicU64::new(0),
+            write_rate: AtomicU64::new(0),
+            visibility: VisibilityLatency::new(nrt_slo),
+        }
+    }
+
+    pub(crate) fn record_index_batch(&self, size: usize) {
+        let size = size as u64;
+        self.index_batches.fetch_add(1, Ordering::Relaxed);
+        self.index_batched_docs.fetch_add(size, Ordering::Relaxed);
+        self.index_last_batch_size.store(size, Ordering::Relaxed);
+        self.index_max_batch_size.fetch_max(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_commit(&self, locked: Duration, total: Duration) {
+        let locked = locked.as_micros() as u64;
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        self.commit_last_locked_us.store(locked, Ordering::Relaxed);
+        self.commit_last_total_us.store(total.as_micros() as u64, Ordering::Relaxed);
+        self.commit_max_locked_us.fetch_max(locked, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_pacing(&self, interval: Duration, writes_per_sec: f64) {
+        self.commit_interval_us.store(interval.as_micros() as u64, Ordering::Relaxed);
+        self.write_rate.store(writes_per_sec.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let ms = |us: &AtomicU64| us.load(Ordering::Relaxed) as f64 / 1000.0;
+        let batches = self.index_batches.load(Ordering::Relaxed);
+        let docs = self.index_batched_docs.load(Ordering::Relaxed);
+        let avg = if batches == 0 { 0.0 } else { docs as f64 / batches as f64 };
+        serde_json::json!({
+            "index_batches": {
+                "count": batches,
+                "docs": docs,
+                "avg_size": avg,
+                "last_size": self.index_last_batch_size.load(Ordering::Relaxed),
+                "max_size": self.index_max_batch_size.load(Ordering::Relaxed),
+            },
+            "commits": {
+                "count": self.commits.load(Ordering::Relaxed),
+                "last_locked_ms": ms(&self.commit_last_locked_us),
+                "last_total_ms": ms(&self.commit_last_total_us),
+                "max_locked_ms": ms(&self.commit_max_locked_us),
+                "interval_ms": ms(&self.commit_interval_us),
+                "writes_per_sec": f64::from_bits(self.write_rate.load(Ordering::Relaxed)),
+            },
+            "nrt_visibility": self.visibility.snapshot(),
+        })
+    }
+
+    /// The counters in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP tantivy_demo_index_batches_total Micro-batches drained from the /index queue\n");
+        out.push_str("# TYPE tantivy_demo_index_batches_total counter\n");
+        out.push_str(&format!("tantivy_demo_index_batches_total {}\n", self.index_batches.load(Ordering::Relaxed)));
+        out.push_str("# HELP tantivy_demo_index_batched_docs_total Documents added through /index micro-batches\n");
+        out.push_str("# TYPE tantivy_demo_index_batched_docs_total counter\n");
+        out.push_str(&format!("tantivy_demo_index_batched_docs_total {}\n", self.index_batched_docs.load(Ordering::Relaxed)));
+        out.push_str("# HELP tantivy_demo_commit_interval_seconds Interval the background commit loop currently aims for\n");
+        out.push_str("# TYPE tantivy_demo_commit_interval_seconds gauge\n");
+        out.push_str(&format!("tantivy_demo_commit_interval_seconds {}\n", self.commit_interval_us.load(Ordering::Relaxed) as f64 / 1e6));
+        self.visibility.prometheus(&mut out);
+        out
+    }
+}
+
+/// Distribution of the time from a write being acknowledged to the searcher swap that makes
+/// it searchable. Acknowledgements are timestamped as they happen; each swap times the ones
+/// that were pending when its commit started.
+pub struct VisibilityLatency {
+    slo: Duration,
+    /// Acknowledgement times not yet covered by a commit, and how many there were in total
+    pending: Mutex<(Vec<Instant>, u64)>,
+    /// Every write acknowledged so far
+    acknowledged: AtomicU64,
+    /// Per bucket of `VISIBILITY_BUCKETS_MS`, plus one for slower writes
+    buckets: [AtomicU64; VISIBILITY_BUCKETS_MS.len() + 1],
+    count: AtomicU64,
+    within_slo: AtomicU64,
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl VisibilityLatency {
+    pub fn new(slo: Duration) -> Self {
+        VisibilityLatency {
+            slo,
+            pending: Mutex::new((Vec::new(), 0)),
+            acknowledged: AtomicU64::new(0),
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            within_slo: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (Vec<Instant>, u64)> {
+        self.pending.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Notes `writes` acknowledged writes.
+    pub(crate) fn acknowledged(&self, writes: usize) {
+        self.acknowledged.fetch_add(writes as u64, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut pending = self.lock();
+        let (acks, seen) = &mut *pending;
+        for _ in 0..writes {
+            *seen += 1;
+            if acks.len() < MAX_PENDING_ACKS {
+                acks.push(now);
+            } else {
+                // Reservoir sampling keeps every acknowledgement equally likely to be timed
+                let slot = rand::random::<u64>() % *seen;
+                if let Some(ack) = acks.get_mut(slot as usize) {
+                    *ack = now;
+                }
+            }
+        }
+    }
+
+    /// Writes acknowledged since startup.
+    pub(crate) fn acknowledged_total(&self) -> u64 {
+        self.acknowledged.load(Ordering::Relaxed)
+    }
+
+    /// The pending acknowledgements, to [`observe`](Self::observe) once the commit that covers
+    /// them is searchable. Taken before the commit starts.
+    pub(crate) fn take_pending(&self) -> Vec<Instant> {
+        let mut pending = self.lock();
+        pending.1 = 0;
+        std::mem::take(&mut pending.0)
+    }
+
+    pub(crate) fn observe(&self, acks: Vec<Instant>) {
+        let now = Instant::now();
+        for ack in acks {
+            let latency = now.duration_since(ack);
+            let ms = latency.as_millis() as u64;
+            let bucket = VISIBILITY_BUCKETS_MS.iter().position(|le| ms <= *le).unwrap_or(VISIBILITY_BUCKETS_MS.len());
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            self.count.fetch_add(1, Ordering::Relaxed);
+            if latency <= self.slo {
+                self.within_slo.fetch_add(1, Ordering::Relaxed);
+            }
+            self.sum_us.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+            self.max_us.fetch_max(latency.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Upper bound of the bucket holding quantile `q`, capped at the slowest write seen.
+    fn quantile_ms(&self, counts: &[u64], q: f64) -> f64 {
+        let total: u64 = counts.iter().sum();
+        let max_ms = self.max_us.load(Ordering::Relaxed) as f64 / 1000.0;
+        let rank = (q * total as f64).ceil() as u64;
+        let mut cumulative = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank.max(1) {
+                return VISIBILITY_BUCKETS_MS.get(bucket).map_or(max_ms, |le| (*le as f64).min(max_ms));
+            }
+        }
+        max_ms
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let count = self.count.load(Ordering::Relaxed);
+        let (pending, oldest_pending_ms) = {
+            let pending = self.lock();
+            let oldest = pending.0.iter().min().map(|ack| ack.elapsed().as_millis() as u64);
+            (pending.1, oldest)
+        };
+        let mut cumulative = 0;
+        let buckets: Vec<serde_json::Value> = VISIBILITY_BUCKETS_MS
+            .iter()
+            .zip(&counts)
+            .map(|(le, n)| {
+                cumulative += n;
+                serde_json::json!({ "le_ms": le, "count": cumulative })
+            })
+            .collect();
+        serde_json::json!({
+            "count": count,
+            "mean_ms": (self.sum_us.load(Ordering::Relaxed) as f64 / 1000.0) / count.max(1) as f64,
+            "p50_ms": self.quantile_ms(&counts, 0.5),
+            "p90_ms": self.quantile_ms(&counts, 0.9),
+            "p99_ms": self.quantile_ms(&counts, 0.99),
+            "max_ms": self.max_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            "slo_ms": self.slo.as_millis() as u64,
+            "within_slo": match count {
+                0 => 1.0,
+                n => self.within_slo.load(Ordering::Relaxed) as f64 / n as f64,
+            },
+            "pending": pending,
+            "oldest_pending_ms": oldest_pending_ms,
+            "buckets": buckets,
+        })
+    }
+
+    fn prometheus(&self, out: &mut String) {
+        out.push_str("# HELP tantivy_demo_nrt_visibility_seconds Time from a write being acknowledged to it being searchable\n");
+        out.push_str("# TYPE tantivy_demo_nrt_visibility_seconds histogram\n");
+        let mut cumulative = 0;
+        for (le, bucket) in VISIBILITY_BUCKETS_MS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            out.push_str(&format!("tantivy_demo_nrt_visibility_seconds_bucket{{le=\"{}\"}} {}\n", *le as f64 / 1000.0, cumulative));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        out.push_str(&format!("tantivy_demo_nrt_visibility_seconds_bucket{{le=\"+Inf\"}} {}\n", count));
+        out.push_str(&format!("tantivy_demo_nrt_visibility_seconds_sum {}\n", self.sum_us.load(Ordering::Relaxed) as f64 / 1e6));
+        out.push_str(&format!("tantivy_demo_nrt_visibility_seconds_count {}\n", count));
+        out.push_str("# HELP tantivy_demo_nrt_visibility_within_slo_total Writes searchable within the NRT SLO\n");
+        out.push_str("# TYPE tantivy_demo_nrt_visibility_within_slo_total counter\n");
+        out.push_str(&format!("tantivy_demo_nrt_visibility_within_slo_total {}\n", self.within_slo.load(Ordering::Relaxed)));
+    }
+}
@@ -0,0 +1,218 @@
+//! Saved objects: state that belongs to the service rather than to an index, such as pinned
+//! results, query templates, synonym sets, API keys or scroll contexts. Objects are JSON
+//! values under a key in a namespace, one namespace per kind of object.
+//!
+//! Features reach the store through the [`Storage`] trait, so the backend can be swapped
+//! without touching them. Two ship: [`FileStorage`], a directory with one JSON file per
+//! namespace rewritten atomically on every change, meant for the modest object counts these
+//! features keep; and [`MemoryStorage`], for in-memory services and tests.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::service::SearchService;
+
+/// Longest namespace or key accepted, in bytes.
+const MAX_NAME_LEN: usize = 128;
+
+type Objects = BTreeMap<String, BTreeMap<String, serde_json::Value>>;
+
+/// A store of JSON objects by namespace and key.
+pub trait Storage: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> ServiceResult<Option<serde_json::Value>>;
+
+    /// Stores `value` under `key`, replacing what was there.
+    fn put(&self, namespace: &str, key: &str, value: serde_json::Value) -> ServiceResult<()>;
+
+    /// Removes `key`; whether it was there.
+    fn delete(&self, namespace: &str, key: &str) -> ServiceResult<bool>;
+
+    /// Every object of `namespace`, by key.
+    fn list(&self, namespace: &str) -> ServiceResult<BTreeMap<String, serde_json::Value>>;
+
+    /// Namespaces holding at least one object.
+    fn namespaces(&self) -> ServiceResult<Vec<String>>;
+}
+
+impl dyn Storage {
+    /// The object under `key` as a `T`.
+    pub fn get_as<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> ServiceResult<Option<T>> {
+        match self.get(namespace, key)? {
+            Some(value) => serde_json::from_value(value).map(Some).map_err(|e| {
+                ServiceError::Internal(format!("saved object {}/{} is malformed: {}", namespace, key, e))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_as<T: Serialize>(&self, namespace: &str, key: &str, value: &T) -> ServiceResult<()> {
+        let value = serde_json::to_value(value).map_err(|e| ServiceError::Internal(e.to_string()))?;
+        self.put(namespace, key, value)
+    }
+}
+
+/// Namespaces name files, so they keep to lowercase letters, digits, `_` and `-`; keys may be
+/// anything non-empty.
+pub(crate) fn check_names(namespace: &str, key: Option<&str>) -> ServiceResult<()> {
+    let valid = |n: &str| n.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if namespace.is_empty() || namespace.len() > MAX_NAME_LEN || !valid(namespace) {
+        return Err(ServiceError::Invalid(format!(
+            "namespaces are 1 to {} lowercase letters, digits, '_' or '-': {:?}",
+            MAX_NAME_LEN, namespace
+        )));
+    }
+    if let Some(key) = key.filter(|k| k.is_empty() || k.len() > MAX_NAME_LEN) {
+        return Err(ServiceError::Invalid(format!("keys are 1 to {} bytes: {:?}", MAX_NAME_LEN, key)));
+    }
+    Ok(())
+}
+
+fn read<'a>(objects: &'a RwLock<Objects>) -> std::sync::RwLockReadGuard<'a, Objects> {
+    objects.read().unwrap_or_else(|p| p.into_inner())
+}
+
+fn write<'a>(objects: &'a RwLock<Objects>) -> std::sync::RwLockWriteGuard<'a, Objects> {
+    objects.write().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Objects kept in memory only; everything is lost on drop.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: RwLock<Objects>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &str) -> ServiceResult<Option<serde_json::Value>> {
+        Ok(read(&self.objects).get(namespace).and_then(|ns| ns.get(key)).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: serde_json::Value) -> ServiceResult<()> {
+        check_names(namespace, Some(key))?;
+        write(&self.objects).entry(namespace.to_string()).or_default().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> ServiceResult<bool> {
+        let mut objects = write(&self.objects);
+        let removed = objects.get_mut(namespace).and_then(|ns| ns.remove(key)).is_some();
+        objects.retain(|_, ns| !ns.is_empty());
+        Ok(removed)
+    }
+
+    fn list(&self, namespace: &str) -> ServiceResult<BTreeMap<String, serde_json::Value>> {
+        Ok(read(&self.objects).get(namespace).cloned().unwrap_or_default())
+    }
+
+    fn namespaces(&self) -> ServiceResult<Vec<String>> {
+        Ok(read(&self.objects).keys().cloned().collect())
+    }
+}
+
+/// Objects in a directory, `<namespace>.json` holding a JSON object of each namespace's
+/// objects by key. Everything is read on open and served from memory.
+pub struct FileStorage {
+    dir: PathBuf,
+    objects: RwLock<Objects>,
+}
+
+impl FileStorage {
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut objects = Objects::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(namespace) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            if path.extension().and_then(|e| e.to_str()) != Some("json") || check_names(namespace, None).is_err() {
+                continue;
+            }
+            let ns: BTreeMap<String, serde_json::Value> = serde_json::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            if !ns.is_empty() {
+                objects.insert(namespace.to_string(), ns);
+            }
+        }
+        Ok(FileStorage { dir: dir.to_path_buf(), objects: RwLock::new(objects) })
+    }
+
+    /// Rewrites the file of `namespace`, or removes it once the namespace is empty.
+    fn persist(&self, objects: &Objects, namespace: &str) -> ServiceResult<()> {
+        let path = self.dir.join(format!("{}.json", namespace));
+        match objects.get(namespace) {
+            Some(ns) => {
+                let json = serde_json::to_string_pretty(ns).map_err(|e| ServiceError::Internal(e.to_string()))?;
+                write_durably(&path, json.as_bytes())?;
+            }
+            None if path.exists() => std::fs::remove_file(&path)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `path` with `contents` through a sibling temporary file, synced before the rename
+/// and its directory after, so a crash leaves either the old or the new contents on disk.
+pub(crate) fn write_durably(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+impl Storage for FileStorage {
+    fn get(&self, namespace: &str, key: &str) -> ServiceResult<Option<serde_json::Value>> {
+        Ok(read(&self.objects).get(namespace).and_then(|ns| ns.get(key)).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: serde_json::Value) -> ServiceResult<()> {
+        check_names(namespace, Some(key))?;
+        let mut objects = write(&self.objects);
+        let previous = objects.entry(namespace.to_string()).or_default().insert(key.to_string(), value);
+        if let Err(e) = self.persist(&objects, namespace) {
+            // Keep memory in step with the file
+            let ns = objects.get_mut(namespace).expect("just inserted");
+            match previous {
+                Some(previous) => ns.insert(key.to_string(), previous),
+                None => ns.remove(key),
+            };
+            objects.retain(|_, ns| !ns.is_empty());
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> ServiceResult<bool> {
+        let mut objects = write(&self.objects);
+        let Some(removed) = objects.get_mut(namespace).and_then(|ns| ns.remove(key)) else { return Ok(false) };
+        objects.retain(|_, ns| !ns.is_empty());
+        if let Err(e) = self.persist(&objects, namespace) {
+            objects.entry(namespace.to_string()).or_default().insert(key.to_string(), removed);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    fn list(&self, namespace: &str) -> ServiceResult<BTreeMap<String, serde_json::Value>> {
+        Ok(read(&self.objects).get(namespace).cloned().unwrap_or_default())
+    }
+
+    fn namespaces(&self) -> ServiceResult<Vec<String>> {
+        Ok(read(&self.objects).keys().cloned().collect())
+    }
+}
+
+impl SearchService {
+    /// The saved-object store.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+}
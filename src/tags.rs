@@ -0,0 +1,104 @@
+//! Tag filters and matched tags for `/search`: `tags_all=rust,search` keeps posts with every
+//! listed tag, `tags_any=rust,go` posts with at least one, and each hit reports which of its
+//! tags the query or the filters matched.
+//!
+//! Tags are compared as the `tags` analyzer indexes them (whitespace split, lowercased), so
+//! `Rust` matches `rust` and a tag of several words must match them in order.
+
+use std::collections::HashSet;
+
+use tantivy::query::{BooleanQuery, Occur, PhraseQuery, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Value};
+use tantivy::tokenizer::{TextAnalyzer, TokenStream};
+use tantivy::{Searcher, TantivyDocument, Term};
+
+use crate::error::ServiceResult;
+use crate::query::parse_query_with;
+use crate::service::{SearchRequest, SearchService};
+
+fn tokens(analyzer: &mut TextAnalyzer, tag: &str) -> Vec<String> {
+    let mut stream = analyzer.token_stream(tag);
+    let mut tokens = Vec::new();
+    while stream.advance() {
+        tokens.push(stream.token().text.clone());
+    }
+    tokens
+}
+
+/// Posts tagged `tag`; `None` for a tag with no words.
+fn tag_query(f_tags: Field, analyzer: &mut TextAnalyzer, tag: &str) -> Option<Box<dyn Query>> {
+    let mut terms: Vec<Term> = tokens(analyzer, tag).iter().map(|t| Term::from_field_text(f_tags, t)).collect();
+    match terms.len() {
+        0 => None,
+        1 => Some(Box::new(TermQuery::new(terms.remove(0), IndexRecordOption::Basic))),
+        _ => Some(Box::new(PhraseQuery::new(terms))),
+    }
+}
+
+/// The `tags_all` and `tags_any` filters of `req` on `searcher`'s index, if it sets any.
+pub(crate) fn tags_filter(searcher: &Searcher, req: &SearchRequest) -> ServiceResult<Option<Box<dyn Query>>> {
+    if req.tags_all.is_empty() && req.tags_any.is_empty() {
+        return Ok(None);
+    }
+    let f_tags = searcher.index().schema().get_field("tags").unwrap();
+    let mut analyzer = searcher.index().tokenizer_for_field(f_tags)?;
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for tag in &req.tags_all {
+        clauses.extend(tag_query(f_tags, &mut analyzer, tag).map(|q| (Occur::Must, q)));
+    }
+    let any: Vec<(Occur, Box<dyn Query>)> =
+        req.tags_any.iter().filter_map(|tag| tag_query(f_tags, &mut analyzer, tag)).map(|q| (Occur::Should, q)).collect();
+    if !any.is_empty() {
+        clauses.push((Occur::Must, Box::new(BooleanQuery::new(any))));
+    }
+    Ok((!clauses.is_empty()).then(|| Box::new(BooleanQuery::new(clauses)) as _))
+}
+
+/// Tells which tags of a hit a search matched: those indexed with a term the query looks up
+/// in `tags`, or one of its tag filters names.
+pub struct TagMatcher {
+    f_tags: Field,
+    analyzer: TextAnalyzer,
+    terms: HashSet<String>,
+}
+
+impl TagMatcher {
+    /// Whether the search looks at tags at all; hits report no matched tags otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The tags of `doc` the search matched, in the post's order.
+    pub fn matched(&mut self, doc: &TantivyDocument) -> Vec<String> {
+        let tags: Vec<&str> = doc.get_all(self.f_tags).filter_map(|v| v.as_str()).collect();
+        let mut matched = Vec::new();
+        for tag in tags {
+            if tokens(&mut self.analyzer, tag).iter().any(|t| self.terms.contains(t)) {
+                matched.push(tag.to_string());
+            }
+        }
+        matched
+    }
+}
+
+impl SearchService {
+    /// A [`TagMatcher`] for the hits of `req`.
+    pub fn tag_matcher(&self, req: &SearchRequest) -> ServiceResult<TagMatcher> {
+        let searcher = self.searcher();
+        let f_tags = self.schema().get_field("tags").unwrap();
+        let mut analyzer = searcher.index().tokenizer_for_field(f_tags)?;
+        let mut terms = HashSet::new();
+        if !req.q.trim().is_empty() {
+            let query = parse_query_with(&searcher, &req.q, &self.query_limits(), req.minimum_should_match)?;
+            query.query_terms(&mut |term, _| {
+                if term.field() == f_tags {
+                    terms.extend(term.value().as_str().map(str::to_string));
+                }
+            });
+        }
+        for tag in req.tags_all.iter().chain(&req.tags_any) {
+            terms.extend(tokens(&mut analyzer, tag));
+        }
+        Ok(TagMatcher { f_tags, analyzer, terms })
+    }
+}
@@ -0,0 +1,134 @@
+//! Term statistics for relevance tuning: `GET /terms/stats?field=body&terms=rust,search` reports
+//! the inputs BM25 scores each term with in one tier — its document frequency, total term
+//! frequency and IDF, with the tier's document count and the field's average length — without
+//! dumping the index.
+//!
+//! Terms go through the field's analyzer first, so a term the analyzer splits (n-grams, say)
+//! is reported once per indexed token. Like scoring, the counts include deleted documents
+//! until their segments are merged.
+
+use serde::{Deserialize, Serialize};
+use tantivy::query::Bm25StatisticsProvider;
+use tantivy::json_utils::JsonTermWriter;
+use tantivy::postings::Postings;
+use tantivy::schema::{FieldType, IndexRecordOption, Type};
+use tantivy::{DocSet, Searcher, Term, TERMINATED};
+
+use crate::error::{ServiceError, ServiceResult};
+use crate::schema::RESTRICTED_FIELDS;
+use crate::service::{term_text, SearchService, Tier};
+
+/// How many terms one request may ask about.
+pub const MAX_TERMS: usize = 100;
+
+/// One indexed token of a term asked about.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermStat {
+    /// As asked for
+    pub term: String,
+    /// What the analyzer made of it; `<path>:<value>` for JSON fields
+    pub token: String,
+    /// Documents containing the token
+    pub doc_freq: u64,
+    /// Occurrences in all documents; `doc_freq` for fields indexed without frequencies
+    pub total_term_freq: u64,
+    /// `ln(1 + (num_docs - doc_freq + 0.5) / (doc_freq + 0.5))`, as BM25 weighs it
+    pub idf: f32,
+}
+
+/// Response of `GET /terms/stats`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermStats {
+    pub field: String,
+    pub tier: Tier,
+    /// Documents of the tier, the N of the IDF
+    pub num_docs: u64,
+    /// Tokens per document in the field on average, BM25's length normalization pivot
+    pub avg_field_len: f32,
+    pub terms: Vec<TermStat>,
+}
+
+fn idf(doc_freq: u64, num_docs: u64) -> f32 {
+    let x = (num_docs.saturating_sub(doc_freq) as f32 + 0.5) / (doc_freq as f32 + 0.5);
+    (1.0 + x).ln()
+}
+
+/// Occurrences of `term` summed over every segment's postings.
+fn total_term_freq(searcher: &Searcher, term: &Term) -> ServiceResult<u64> {
+    let mut total = 0;
+    for segment in searcher.segment_readers() {
+        let Some(mut postings) = segment.inverted_index(term.field())?.read_postings(term, IndexRecordOption::WithFreqs)? else { continue };
+        while postings.doc() != TERMINATED {
+            total += u64::from(postings.term_freq());
+            postings.advance();
+        }
+    }
+    Ok(total)
+}
+
+impl SearchService {
+    /// Statistics of `terms` in `field` of `tier`. `field` is a text or string field, or a path
+    /// inside a JSON one (`features.lang`).
+    pub fn term_stats(&self, field: &str, terms: &[String], tier: Tier) -> ServiceResult<TermStats> {
+        if terms.len() > MAX_TERMS {
+            return Err(ServiceError::Invalid(format!("at most {} terms per request, got {}", MAX_TERMS, terms.len())));
+        }
+        let searcher = match tier {
+            Tier::Hot => self.searcher(),
+            Tier::Archive => self.archive().current_searcher.load_full(),
+        };
+        let index = searcher.index();
+        let schema = index.schema();
+        let (f, path) = schema
+            .find_field(field)
+            .filter(|(f, _)| !RESTRICTED_FIELDS.contains(&schema.get_field_name(*f)))
+            .ok_or_else(|| ServiceError::Invalid(format!("unknown field: {}", field)))?;
+        let entry = schema.get_field_entry(f);
+        let json = match entry.field_type() {
+            FieldType::Str(options) if options.get_indexing_options().is_some() && path.is_empty() => None,
+            FieldType::JsonObject(options) if options.get_text_indexing_options().is_some() => match path.is_empty() {
+                true => return Err(ServiceError::Invalid(format!("{} is a JSON field; name a path inside it, e.g. {}.lang", field, field))),
+                false => Some(options.is_expand_dots_enabled()),
+            },
+            _ => return Err(ServiceError::Invalid(format!("{} isn't an indexed text, string or JSON field", field))),
+        };
+        let mut analyzer = index.tokenizer_for_field(f)?;
+        let num_docs = searcher.total_num_docs()?;
+        let mut stats = Vec::new();
+        for asked in terms {
+            let mut tokens: Vec<Term> = Vec::new();
+            analyzer.token_stream(asked).process(&mut |token| {
+                let term = match json {
+                    None => Term::from_field_text(f, &token.text),
+                    Some(expand_dots) => {
+                        let mut term = Term::with_capacity(path.len() + 2 + token.text.len());
+                        JsonTermWriter::from_field_and_json_path(f, path, expand_dots, &mut term).close_path_and_set_type(Type::Str);
+                        term.append_bytes(token.text.as_bytes());
+                        term
+                    }
+                };
+                if !tokens.contains(&term) {
+                    tokens.push(term);
+                }
+            });
+            for term in tokens {
+                let doc_freq = searcher.doc_freq(&term)?;
+                stats.push(TermStat {
+                    term: asked.clone(),
+                    token: term_text(&term),
+                    doc_freq,
+                    total_term_freq: total_term_freq(&searcher, &term)?,
+                    idf: idf(doc_freq, num_docs),
+                });
+            }
+        }
+        let total_tokens = searcher.total_num_tokens(f)?;
+        Ok(TermStats {
+            field: field.to_string(),
+            tier,
+            num_docs,
+            avg_field_len: if num_docs == 0 { 0.0 } else { total_tokens as f32 / num_docs as f32 },
+            terms: stats,
+        })
+    }
+}
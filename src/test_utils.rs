@@ -0,0 +1,128 @@
+//! An in-process harness for integration tests of apps embedding the service: every index is
+//! kept in RAM and every write commits before returning (`sync_commits`), so a test sees its
+//! writes immediately, without sleeps, temp directories or a server port.
+//!
+//! ```
+//! use tantivy_demo::test_utils::{post, TestService};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! # tokio::runtime::Runtime::new()?.block_on(async {
+//! let svc = TestService::new()?;
+//! svc.seed([post("1", "Fast search", "tantivy in rust"), post("2", "Slow search", "grep")]).await?;
+//! svc.assert_hits("body:rust", &["1"]);
+//! # Ok(())
+//! # })
+//! # }
+//! ```
+//!
+//! Title and body are analyzed into 2- and 3-grams (`zh_ngram`), so a word matches the posts
+//! containing it but a fragment does too: `body:ru` finds post 1 above as well.
+
+use std::sync::Arc;
+
+use tantivy::schema::Value;
+
+use crate::batch::BatchOp;
+use crate::error::ServiceResult;
+use crate::journal::JournalEntry;
+use crate::schema::BlogPost;
+use crate::service::{SearchRequest, SearchService, ServiceConfig};
+
+/// Hits fetched by the search helpers.
+const MAX_HITS: usize = 1000;
+
+/// A published post with no tags, features or author.
+pub fn post(id: &str, title: &str, body: &str) -> BlogPost {
+    BlogPost {
+        id: id.to_string(),
+        title: title.to_string(),
+        body: body.to_string(),
+        tags: Vec::new(),
+        create_at: None,
+        status: "published".to_string(),
+        features: serde_json::json!({}),
+        author_id: None,
+        allowed_groups: Vec::new(),
+        tenant: None,
+    }
+}
+
+/// A [`SearchService`] on RAM indexes with synchronous commits and no background tasks: writes,
+/// through the helpers here or directly on [`service`](Self::service), are searchable when
+/// they return.
+pub struct TestService {
+    service: Arc<SearchService>,
+}
+
+impl TestService {
+    /// A service with the default configuration.
+    pub fn new() -> anyhow::Result<Self> {
+        Self::with_config(ServiceConfig::default())
+    }
+
+    /// A service with `config`, kept in memory whatever its paths say.
+    pub fn with_config(config: ServiceConfig) -> anyhow::Result<Self> {
+        let config = ServiceConfig { in_memory: true, sync_commits: true, writer_heap_bytes: 15_000_000, ..config };
+        Ok(TestService { service: Arc::new(SearchService::open(config)?) })
+    }
+
+    pub fn service(&self) -> &Arc<SearchService> {
+        &self.service
+    }
+
+    /// Indexes `posts`, as one batch so they cost a single commit.
+    pub async fn seed(&self, posts: impl IntoIterator<Item = BlogPost>) -> ServiceResult<()> {
+        let ops = posts.into_iter().map(|doc| BatchOp::Index { doc }).collect();
+        self.service.apply_batch(ops, &None).await.map(drop)
+    }
+
+    /// Replaces the post with the same id.
+    pub async fn update(&self, post: BlogPost) -> ServiceResult<()> {
+        self.service.update_document(post).await.map(drop)
+    }
+
+    pub fn delete(&self, id: &str) -> ServiceResult<()> {
+        self.service.delete_document(id, &None).map(drop)
+    }
+
+    /// Applies `entries`, read from a leader's journal, the way a follower replicating it does.
+    pub fn replay(&self, entries: Vec<JournalEntry>) -> ServiceResult<()> {
+        self.service.apply_journal(entries)
+    }
+
+    /// Ids of the posts matching `q`, best first.
+    pub fn search_ids(&self, q: &str) -> ServiceResult<Vec<String>> {
+        self.search_ids_with(&SearchRequest { q: q.to_string(), limit: MAX_HITS, ..SearchRequest::default() })
+    }
+
+    /// Ids of the posts matching `req`, best first.
+    pub fn search_ids_with(&self, req: &SearchRequest) -> ServiceResult<Vec<String>> {
+        let f_id = self.service.schema().get_field("id").unwrap();
+        let hits = self.service.search(req)?.hits;
+        Ok(hits.iter().filter_map(|hit| hit.doc.get_first(f_id).and_then(|v| v.as_str()).map(str::to_string)).collect())
+    }
+
+    /// Panics unless `q` matches exactly the posts `expected`, in that order.
+    #[track_caller]
+    pub fn assert_hits(&self, q: &str, expected: &[&str]) {
+        let ids = self.search_ids(q).unwrap_or_else(|e| panic!("search {:?} failed: {}", q, e));
+        assert_eq!(ids, expected, "hits of {:?}", q);
+    }
+
+    /// Panics unless `q` matches exactly the posts `expected`, in any order.
+    #[track_caller]
+    pub fn assert_hits_unordered(&self, q: &str, expected: &[&str]) {
+        let mut ids = self.search_ids(q).unwrap_or_else(|e| panic!("search {:?} failed: {}", q, e));
+        let mut expected = expected.to_vec();
+        ids.sort();
+        expected.sort();
+        assert_eq!(ids, expected, "hits of {:?}", q);
+    }
+
+    /// Panics unless `q` matches `count` posts.
+    #[track_caller]
+    pub fn assert_count(&self, q: &str, count: usize) {
+        let ids = self.search_ids(q).unwrap_or_else(|e| panic!("search {:?} failed: {}", q, e));
+        assert_eq!(ids.len(), count, "hits of {:?}: {:?}", q, ids);
+    }
+}
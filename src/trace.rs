@@ -0,0 +1,95 @@
+//! Trace context for searches that fan out, in the W3C `traceparent` format
+//! (`00-<trace id>-<span id>-<flags>`).
+//!
+//! The server continues the caller's trace (or starts one) for every search, and each leg of
+//! the fan-out — a tier today, a remote cluster or shard once searches reach other nodes —
+//! runs under a child span whose context is what a child request carries. `profile=true` on
+//! `/search` reports the legs with their span ids and timings, so a slow one stands out and
+//! can be found in the child's own logs.
+
+use std::fmt;
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Header carrying the context on requests and responses.
+pub const TRACEPARENT: &str = "traceparent";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits, shared by every span of the trace
+    pub trace_id: String,
+    /// 16 lowercase hex digits identifying this span
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+fn random_hex(bytes: usize) -> String {
+    (0..bytes).map(|_| format!("{:02x}", rand::random::<u8>())).collect()
+}
+
+fn is_hex_id(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) && s.bytes().any(|b| b != b'0')
+}
+
+impl TraceContext {
+    /// A new trace.
+    pub fn root() -> Self {
+        TraceContext { trace_id: random_hex(16), span_id: random_hex(8), sampled: true }
+    }
+
+    /// Reads a `traceparent` value; `None` when it is malformed, in which case the receiver
+    /// starts a new trace as the spec asks.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        // Later versions may append fields; version 00 has exactly four
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if !is_hex_id(trace_id, 32) || !is_hex_id(span_id, 16) || flags.len() != 2 {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceContext { trace_id: trace_id.to_string(), span_id: span_id.to_string(), sampled: flags & 1 == 1 })
+    }
+
+    /// A span in the same trace whose parent is this one.
+    pub fn child(&self) -> Self {
+        TraceContext { span_id: random_hex(8), ..self.clone() }
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", self.trace_id, self.span_id, self.sampled as u8)
+    }
+}
+
+/// One leg of a search fan-out, as reported in the `profile` section.
+#[derive(Serialize, Debug, Clone)]
+pub struct ShardTiming {
+    /// What the leg searched: `hot`, `archive`, or a remote name
+    pub shard: String,
+    /// Span the leg ran under, when the search was traced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span_id: Option<String>,
+    pub took_ms: f64,
+    /// Segments searched (0 for remote legs)
+    pub segments: usize,
+    pub hits: usize,
+    pub timed_out: bool,
+}
+
+impl ShardTiming {
+    pub(crate) fn since(shard: &str, span: Option<&TraceContext>, started: Instant) -> Self {
+        ShardTiming {
+            shard: shard.to_string(),
+            span_id: span.map(|s| s.span_id.clone()),
+            took_ms: started.elapsed().as_secs_f64() * 1000.0,
+            segments: 0,
+            hits: 0,
+            timed_out: false,
+        }
+    }
+}
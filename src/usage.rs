@@ -0,0 +1,156 @@
+//! Per-tenant usage accounting and document quotas. Tenants are identified by API key; their
+//! posts carry the tenant in the `tenant` field, so document counts come straight from the
+//! index while searches and ingested bytes are counted in memory since startup.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TenantConfig {
+    pub name: String,
+    /// Keys sent as `X-Api-Key`
+    #[serde(skip_serializing)]
+    pub api_keys: Vec<String>,
+    /// Writes that would take the tenant past this many posts are rejected
+    #[serde(default)]
+    pub max_docs: Option<u64>,
+}
+
+/// Reads a JSON array of tenants.
+pub fn load_tenants(path: &PathBuf) -> anyhow::Result<Vec<TenantConfig>> {
+    let tenants: Vec<TenantConfig> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let mut owners = HashMap::new();
+    for tenant in &tenants {
+        for key in &tenant.api_keys {
+            if let Some(other) = owners.insert(key.as_str(), tenant.name.as_str()) {
+                anyhow::bail!("API key shared by tenants {} and {}", other, tenant.name);
+            }
+        }
+    }
+    Ok(tenants)
+}
+
+/// What one write adds to a tenant's usage.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TenantWrite {
+    pub docs: u64,
+    /// Posts that don't replace a committed one
+    pub new_docs: u64,
+    /// Size of the posts as sent (JSON)
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    searches: u64,
+    docs_indexed: u64,
+    bytes_indexed: u64,
+    /// Posts accepted since the last commit, not yet in the committed counts
+    pending_docs: u64,
+}
+
+/// One tenant's usage as reported by `/admin/usage`.
+#[derive(Serialize, Debug, Clone)]
+pub struct TenantUsage {
+    pub tenant: String,
+    /// Committed posts in the hot and archive tiers
+    pub docs: u64,
+    pub pending_docs: u64,
+    pub max_docs: Option<u64>,
+    /// The tenant's share of the index size, apportioned by post count
+    pub storage_bytes: u64,
+    pub searches: u64,
+    pub docs_indexed: u64,
+    /// Size of the posts as sent (JSON)
+    pub bytes_indexed: u64,
+}
+
+pub struct Usage {
+    tenants: Vec<TenantConfig>,
+    counters: Mutex<HashMap<String, Counters>>,
+}
+
+impl Usage {
+    pub fn new(tenants: Vec<TenantConfig>) -> Self {
+        Usage { tenants, counters: Mutex::new(HashMap::new()) }
+    }
+
+    /// Whether tenants are configured; without them nothing is accounted.
+    pub fn enabled(&self) -> bool {
+        !self.tenants.is_empty()
+    }
+
+    pub fn tenants(&self) -> &[TenantConfig] {
+        &self.tenants
+    }
+
+    pub fn tenant_for_key(&self, key: &str) -> Option<&str> {
+        self.tenants.iter().find(|t| t.api_keys.iter().any(|k| k == key)).map(|t| t.name.as_str())
+    }
+
+    pub fn max_docs(&self, tenant: &str) -> Option<u64> {
+        self.tenants.iter().find(|t| t.name == tenant).and_then(|t| t.max_docs)
+    }
+
+    fn update(&self, tenant: &str, f: impl FnOnce(&mut Counters)) {
+        let mut counters = self.counters.lock().unwrap_or_else(|p| p.into_inner());
+        f(counters.entry(tenant.to_string()).or_default());
+    }
+
+    pub(crate) fn record_search(&self, tenant: &str) {
+        self.update(tenant, |c| c.searches += 1);
+    }
+
+    pub(crate) fn record_indexed(&self, tenant: &str, write: &TenantWrite) {
+        self.update(tenant, |c| {
+            c.docs_indexed += write.docs;
+            c.pending_docs += write.new_docs;
+            c.bytes_indexed += write.bytes;
+        });
+    }
+
+    pub(crate) fn pending(&self, tenant: &str) -> u64 {
+        let counters = self.counters.lock().unwrap_or_else(|p| p.into_inner());
+        counters.get(tenant).map_or(0, |c| c.pending_docs)
+    }
+
+    /// Pending counts right before a commit, to [`settle`](Self::settle) once it is visible.
+    pub(crate) fn pending_snapshot(&self) -> HashMap<String, u64> {
+        let counters = self.counters.lock().unwrap_or_else(|p| p.into_inner());
+        counters.iter().map(|(tenant, c)| (tenant.clone(), c.pending_docs)).collect()
+    }
+
+    pub(crate) fn settle(&self, committed: HashMap<String, u64>) {
+        let mut counters = self.counters.lock().unwrap_or_else(|p| p.into_inner());
+        for (tenant, docs) in committed {
+            if let Some(c) = counters.get_mut(&tenant) {
+                c.pending_docs = c.pending_docs.saturating_sub(docs);
+            }
+        }
+    }
+
+    /// Usage of every configured tenant, given committed post counts and storage shares.
+    pub(crate) fn report(&self, committed: &HashMap<String, (u64, u64)>) -> Vec<TenantUsage> {
+        let counters = self.counters.lock().unwrap_or_else(|p| p.into_inner());
+        self.tenants
+            .iter()
+            .map(|tenant| {
+                let c = counters.get(&tenant.name).copied().unwrap_or_default();
+                let (docs, storage_bytes) = committed.get(&tenant.name).copied().unwrap_or_default();
+                TenantUsage {
+                    tenant: tenant.name.clone(),
+                    docs,
+                    pending_docs: c.pending_docs,
+                    max_docs: tenant.max_docs,
+                    storage_bytes,
+                    searches: c.searches,
+                    docs_indexed: c.docs_indexed,
+                    bytes_indexed: c.bytes_indexed,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,166 @@
+//! Tokens of the built-in analyzers and of the tokenizers and filters behind them.
+
+use tantivy::tokenizer::{TextAnalyzer, Token};
+use std::collections::BTreeMap;
+use tantivy_demo::analyzers::{builtin_analyzers, AnalyzerRegistry, AnalyzerSpec, CharFilterSpec, FilterSpec, TokenizerSpec};
+use tantivy_demo::filters::EmojiPolicy;
+
+fn builtin(name: &str) -> TextAnalyzer {
+    builtin_analyzers().into_iter().find(|spec| spec.name == name).unwrap().build().unwrap()
+}
+
+fn tokens(analyzer: &mut TextAnalyzer, text: &str) -> Vec<Token> {
+    let mut stream = analyzer.token_stream(text);
+    let mut tokens = Vec::new();
+    stream.process(&mut |token| tokens.push(token.clone()));
+    tokens
+}
+
+fn texts(analyzer: &mut TextAnalyzer, text: &str) -> Vec<String> {
+    tokens(analyzer, text).into_iter().map(|token| token.text).collect()
+}
+
+#[test]
+fn folded_ngrams_match_across_widths_and_accent_forms() {
+    let mut folded = builtin("zh_ngram_folded");
+    // Half-width katakana with voicing marks, which NFKC joins into one character each
+    assert_eq!(texts(&mut folded, "ｶﾞｲﾄﾞ"), texts(&mut folded, "ガイド"));
+    assert_eq!(texts(&mut folded, "ＲＵＳＴ"), texts(&mut folded, "rust"));
+    assert_eq!(texts(&mut folded, "cafe\u{301}"), texts(&mut folded, "café"));
+    assert_eq!(texts(&mut folded, "café"), texts(&mut folded, "cafe"));
+
+    // Offsets still point into the text as written
+    let text = "ｶﾞｲﾄﾞ";
+    for token in tokens(&mut folded, text) {
+        assert!(text.is_char_boundary(token.offset_from) && text.is_char_boundary(token.offset_to), "{:?}", token);
+    }
+    let first = &tokens(&mut folded, text)[0];
+    assert_eq!((first.text.as_str(), &text[first.offset_from..first.offset_to]), ("ガイ", "ｶﾞｲ"));
+}
+
+#[test]
+fn path_hierarchy_offsets_point_past_leading_whitespace() {
+    let mut paths = builtin("path_hierarchy");
+    let text = "  /docs/guide/intro.md ";
+    let found: Vec<(String, &str)> = tokens(&mut paths, text).into_iter().map(|t| (t.text, &text[t.offset_from..t.offset_to])).collect();
+    let ancestors = ["/docs", "/docs/guide", "/docs/guide/intro.md"];
+    assert_eq!(found, ancestors.map(|path| (path.to_string(), path)));
+}
+
+#[test]
+fn kept_emoji_sit_between_their_neighbours() {
+    let spec = AnalyzerSpec {
+        name: "emoji".to_string(),
+        char_filters: Vec::new(),
+        tokenizer: TokenizerSpec::Simple,
+        filters: Vec::new(),
+        emoji: Some(EmojiPolicy::Keep),
+        emoji_names: BTreeMap::new(),
+    };
+    let mut kept = spec.build().unwrap();
+    let positions = |analyzer: &mut TextAnalyzer, text: &str| -> Vec<(String, usize)> {
+        tokens(analyzer, text).into_iter().map(|token| (token.text, token.position)).collect()
+    };
+    // `rust crab` must not look adjacent to a phrase query
+    let found = positions(&mut kept, "rust 🦀 crab 🎉🎉 party");
+    let expected = [("rust", 0), ("🦀", 1), ("crab", 2), ("🎉", 3), ("🎉", 4), ("party", 5)];
+    assert_eq!(found, expected.map(|(text, position)| (text.to_string(), position)));
+    assert_eq!(positions(&mut kept, "🦀 rust"), [("🦀".to_string(), 0), ("rust".to_string(), 1)]);
+}
+
+#[test]
+fn registered_analyzers_survive_a_reopen() {
+    let dir = std::env::temp_dir().join(format!("tantivy-demo-analyzers-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("analyzers.json");
+    let spec = AnalyzerSpec {
+        name: "lower_words".to_string(),
+        char_filters: Vec::new(),
+        tokenizer: TokenizerSpec::Whitespace,
+        filters: vec![FilterSpec::Lowercase],
+        emoji: None,
+        emoji_names: BTreeMap::new(),
+    };
+    AnalyzerRegistry::open(Some(path.clone()), Vec::new()).unwrap().register(spec.clone()).unwrap();
+    // Written through a temporary file that the rename consumed
+    assert!(!path.with_extension("tmp").exists());
+
+    let reopened = AnalyzerRegistry::open(Some(path), Vec::new()).unwrap();
+    assert_eq!(reopened.custom(), [spec]);
+    let manager = tantivy::tokenizer::TokenizerManager::default();
+    reopened.install_custom(&manager).unwrap();
+    assert_eq!(texts(&mut manager.get("lower_words").unwrap(), "Rust Search"), ["rust", "search"]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn positioned(analyzer: &mut TextAnalyzer, text: &str) -> Vec<(String, usize)> {
+    tokens(analyzer, text).into_iter().map(|token| (token.text, token.position)).collect()
+}
+
+#[test]
+fn code_splits_identifiers_and_keeps_them_whole() {
+    let mut code = builtin("code");
+    let found = positioned(&mut code, "IndexWriter::new(max_doc_id) -> Vec<T>");
+    let expected = [
+        ("indexwriter", 0),
+        ("index", 0),
+        ("writer", 1),
+        ("::", 2),
+        ("new", 3),
+        ("max_doc_id", 4),
+        ("max", 4),
+        ("doc", 5),
+        ("id", 6),
+        ("->", 7),
+        ("vec<t>", 8),
+        ("vec", 8),
+        ("<", 9),
+        ("t", 10),
+        (">", 11),
+    ];
+    assert_eq!(found, expected.map(|(text, position)| (text.to_string(), position)));
+    assert_eq!(texts(&mut code, "HTTPServer utf8Decoder"), ["httpserver", "http", "server", "utf8decoder", "utf8", "decoder"]);
+}
+
+#[test]
+fn numeric_normalizes_numbers_and_versions() {
+    let mut numeric = builtin("numeric");
+    assert_eq!(texts(&mut numeric, "007 1,000,000 v1.02.3 3.05 release"), ["7", "1000000", "1.2.3", "3.05", "release"]);
+    assert_eq!(texts(&mut numeric, "v1.2.3"), texts(&mut numeric, "1.02.03"));
+}
+
+#[test]
+fn shingles_join_neighbouring_words() {
+    let mut shingles = builtin("shingles");
+    let found = positioned(&mut shingles, "Quick brown fox jumps");
+    let expected = [("quick brown", 0), ("quick brown fox", 0), ("brown fox", 1), ("brown fox jumps", 1), ("fox jumps", 2)];
+    assert_eq!(found, expected.map(|(text, position)| (text.to_string(), position)));
+    assert!(texts(&mut shingles, "alone").is_empty());
+}
+
+#[test]
+fn char_filters_rewrite_the_text_but_not_the_offsets() {
+    let spec = AnalyzerSpec {
+        name: "filtered".to_string(),
+        char_filters: vec![
+            CharFilterSpec::HtmlEntities,
+            CharFilterSpec::Mapping { mappings: BTreeMap::from([("C++".to_string(), "cpp".to_string()), ("C".to_string(), "c".to_string())]) },
+            CharFilterSpec::PatternReplace { pattern: r"(\d+)%".to_string(), replacement: "$1 percent".to_string() },
+        ],
+        tokenizer: TokenizerSpec::Whitespace,
+        filters: vec![FilterSpec::Lowercase],
+        emoji: Some(EmojiPolicy::Name),
+        emoji_names: BTreeMap::from([("🦀".to_string(), "rustacean".to_string())]),
+    };
+    let mut filtered = spec.build().unwrap();
+    let text = "caf&eacute; C++ 50% 🦀 🎉";
+    let found: Vec<(String, &str)> = tokens(&mut filtered, text).into_iter().map(|t| (t.text, &text[t.offset_from..t.offset_to])).collect();
+    let expected = [("café", "caf&eacute;"), ("cpp", "C++"), ("50", "50%"), ("percent", "50%"), ("rustacean", "🦀"), ("party_popper", "🎉")];
+    assert_eq!(found, expected.map(|(token, original)| (token.to_string(), original)));
+
+    let stripped = AnalyzerSpec { emoji: Some(EmojiPolicy::Strip), emoji_names: BTreeMap::new(), ..spec.clone() };
+    // A stripped emoji still separates the words around it
+    assert_eq!(texts(&mut stripped.build().unwrap(), "rust🦀crab 🎉"), ["rust", "crab"]);
+    // Names only apply when emoji are named
+    assert!(AnalyzerSpec { emoji: Some(EmojiPolicy::Keep), ..spec }.build().is_err());
+}
@@ -0,0 +1,45 @@
+//! The test harness itself: writes through its helpers are searchable when they return.
+
+use tantivy_demo::service::ServiceConfig;
+use tantivy_demo::test_utils::{post, TestService};
+
+async fn seeded() -> TestService {
+    let svc = TestService::new().unwrap();
+    svc.seed([post("1", "Fast search", "tantivy in rust"), post("2", "Slow search", "grep")]).await.unwrap();
+    svc
+}
+
+#[tokio::test]
+async fn seeded_posts_are_searchable() {
+    let svc = seeded().await;
+    svc.assert_hits("body:rust", &["1"]);
+    svc.assert_hits_unordered("search", &["1", "2"]);
+    svc.assert_count("title:slow", 1);
+}
+
+#[tokio::test]
+async fn updates_and_deletes_are_searchable() {
+    let svc = seeded().await;
+    svc.update(post("1", "Fast search", "grep in rust")).await.unwrap();
+    svc.assert_hits_unordered("body:grep", &["1", "2"]);
+    svc.delete("2").unwrap();
+    svc.assert_hits("body:grep", &["1"]);
+    svc.assert_count("search", 1);
+}
+
+#[tokio::test]
+async fn ngrams_match_word_fragments() {
+    let svc = seeded().await;
+    svc.assert_hits("body:ru", &["1"]);
+    svc.assert_count("body:rustacean", 0);
+}
+
+#[tokio::test]
+async fn indexes_stay_in_memory_whatever_the_config_says() {
+    let dir = std::env::temp_dir().join(format!("tantivy-demo-harness-{}", std::process::id()));
+    let config = ServiceConfig { index_path: dir.join("idx"), archive_path: dir.join("archive"), ..ServiceConfig::default() };
+    let svc = TestService::with_config(config).unwrap();
+    svc.seed([post("1", "Fast search", "tantivy in rust")]).await.unwrap();
+    svc.assert_hits("body:rust", &["1"]);
+    assert!(!dir.exists());
+}
@@ -0,0 +1,114 @@
+//! The metadata raft group: appends and votes as a follower handles them, and a one-node
+//! cluster electing itself and committing changes.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tantivy_demo::metadata::MetadataCommand;
+use tantivy_demo::raft::{AppendRequest, LogEntry, RaftConfig, RaftNode, Role, VoteRequest};
+use tantivy_demo::service::{SearchService, ServiceConfig};
+
+fn config(node: &str, members: &[&str], election_timeout: Duration) -> RaftConfig {
+    RaftConfig {
+        node_id: node.to_string(),
+        members: members.iter().map(|n| (n.to_string(), format!("http://{}.invalid", n))).collect(),
+        state_path: None,
+        secret: None,
+        election_timeout,
+        heartbeat: election_timeout / 4,
+    }
+}
+
+fn add(term: u64, node: &str) -> LogEntry {
+    LogEntry { term, command: MetadataCommand::AddMember { node: node.to_string(), url: format!("http://{}.invalid", node) } }
+}
+
+fn append(term: u64, prev: (u64, u64), entries: Vec<LogEntry>, leader_commit: u64) -> AppendRequest {
+    AppendRequest { term, leader: "n1".to_string(), prev_log_index: prev.0, prev_log_term: prev.1, entries, leader_commit }
+}
+
+fn vote(term: u64, candidate: &str, last: (u64, u64)) -> VoteRequest {
+    VoteRequest { term, candidate: candidate.to_string(), last_log_index: last.0, last_log_term: last.1 }
+}
+
+#[test]
+fn a_follower_applies_only_what_the_leader_committed() {
+    let node = RaftNode::open(config("n2", &[], Duration::from_secs(60))).unwrap();
+    let stored = node.handle_append(append(1, (0, 0), vec![add(1, "n1"), add(1, "n2")], 0)).unwrap();
+    assert!(stored.success);
+    assert_eq!((stored.match_index, node.status().log_entries), (2, 2));
+    assert!(node.metadata().members.is_empty());
+    // Entries become voters as soon as they are stored, committed or not
+    assert_eq!(node.status().voters.keys().collect::<Vec<_>>(), ["n1", "n2"]);
+
+    node.handle_append(append(1, (2, 1), Vec::new(), 1)).unwrap();
+    assert_eq!(node.metadata().members.keys().collect::<Vec<_>>(), ["n1"]);
+    assert_eq!((node.status().commit_index, node.status().leader.as_deref()), (1, Some("n1")));
+
+    // An older leader is turned away, and so is one whose log doesn't match
+    assert!(!node.handle_append(append(0, (2, 1), Vec::new(), 2)).unwrap().success);
+    let gap = node.handle_append(append(1, (5, 1), vec![add(1, "n3")], 2)).unwrap();
+    assert!(!gap.success && gap.match_index <= 2, "{:?}", gap);
+}
+
+#[test]
+fn a_new_leaders_entries_replace_the_uncommitted_ones_they_conflict_with() {
+    let node = RaftNode::open(config("n2", &[], Duration::from_secs(60))).unwrap();
+    node.handle_append(append(1, (0, 0), vec![add(1, "n1"), add(1, "n2"), add(1, "n3")], 1)).unwrap();
+
+    let primary = LogEntry { term: 2, command: MetadataCommand::SetPrimary { node: "n1".to_string() } };
+    let replaced = node.handle_append(append(2, (1, 1), vec![primary], 2)).unwrap();
+    assert!(replaced.success);
+    let status = node.status();
+    assert_eq!((status.term, status.log_entries, status.commit_index), (2, 2, 2));
+    assert_eq!(status.voters.keys().collect::<Vec<_>>(), ["n1"]);
+    assert_eq!(node.metadata().primary.as_deref(), Some("n1"));
+}
+
+#[test]
+fn votes_go_to_one_candidate_per_term_with_a_log_at_least_as_recent() {
+    let node = RaftNode::open(config("n3", &[], Duration::from_secs(60))).unwrap();
+    assert!(node.handle_vote(&vote(1, "n1", (0, 0))).unwrap().granted);
+    assert!(!node.handle_vote(&vote(1, "n2", (0, 0))).unwrap().granted);
+    // Asking again, e.g. after a lost answer, gets the same vote
+    assert!(node.handle_vote(&vote(1, "n1", (0, 0))).unwrap().granted);
+    let next_term = node.handle_vote(&vote(2, "n2", (0, 0))).unwrap();
+    assert!(next_term.granted);
+    assert_eq!(next_term.term, 2);
+
+    let timeout = Duration::from_millis(50);
+    let node = RaftNode::open(config("n3", &[], timeout)).unwrap();
+    node.handle_append(append(1, (0, 0), vec![add(1, "n1")], 0)).unwrap();
+    // The leader was just heard from, so nobody gets to depose it
+    assert!(!node.handle_vote(&vote(2, "n2", (1, 1))).unwrap().granted);
+    std::thread::sleep(timeout * 2);
+    assert!(!node.handle_vote(&vote(2, "n2", (0, 0))).unwrap().granted);
+    assert!(node.handle_vote(&vote(3, "n2", (1, 1))).unwrap().granted);
+}
+
+#[tokio::test]
+async fn a_one_node_cluster_elects_itself_and_commits_changes() {
+    let dir = std::env::temp_dir().join(format!("tantivy-demo-raft-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let raft = RaftConfig { state_path: Some(dir.join("raft.json")), ..config("n1", &["n1"], Duration::from_millis(40)) };
+    let svc = Arc::new(SearchService::open(ServiceConfig { in_memory: true, cluster: Some(raft.clone()), ..ServiceConfig::default() }).unwrap());
+    svc.spawn_background_tasks();
+    let node = svc.raft().unwrap();
+
+    let started = Instant::now();
+    while node.status().role != Role::Leader {
+        assert!(started.elapsed() < Duration::from_secs(5), "no leader: {:?}", node.status());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let metadata = node.propose(MetadataCommand::SetPrimary { node: "n1".to_string() }).await.unwrap();
+    assert_eq!(metadata.primary.as_deref(), Some("n1"));
+    assert_eq!(metadata.members.keys().collect::<Vec<_>>(), ["n1"]);
+    let unknown = node.propose(MetadataCommand::SetPrimary { node: "n9".to_string() }).await;
+    assert!(unknown.is_err());
+
+    // The term and log survive a restart; commits are learnt again from the next leader
+    let status = node.status();
+    let reopened = RaftNode::open(raft).unwrap().status();
+    assert_eq!((reopened.term, reopened.log_entries, reopened.commit_index), (status.term, status.log_entries, 0));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,162 @@
+//! Read paths over the posts: newest-first listing, deduplicated, approximate and counted
+//! searches, facets, and the aggregations.
+
+use tantivy::aggregation::agg_req::Aggregations;
+use tantivy::schema::Value;
+use tantivy::TantivyDocument;
+use tantivy_demo::aggs::CompositeRequest;
+use tantivy_demo::collector::{Relation, TotalHits};
+use tantivy_demo::schema::BlogPost;
+use tantivy_demo::service::{SearchRequest, SearchService, ServiceConfig, TrackTotalHits};
+use tantivy_demo::test_utils::{post, TestService};
+
+fn id_of(svc: &SearchService, doc: &TantivyDocument) -> String {
+    let f_id = svc.schema().get_field("id").unwrap();
+    doc.get_first(f_id).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+fn dated(id: &str, create_at: i64, tags: &[&str]) -> BlogPost {
+    BlogPost { create_at: Some(create_at), tags: tags.iter().map(|t| t.to_string()).collect(), ..post(id, "Dated", "rust news") }
+}
+
+fn search(q: &str, limit: usize) -> SearchRequest {
+    SearchRequest { q: q.to_string(), limit, ..SearchRequest::default() }
+}
+
+#[tokio::test]
+async fn latest_lists_newest_first_and_stops_early_on_a_sorted_index() {
+    let svc = TestService::with_config(ServiceConfig { sort_by_create_at: true, ..ServiceConfig::default() }).unwrap();
+    // Several commits, so several segments, each out of order as written
+    for chunk in [[7, 3, 12, 1], [9, 15, 5, 11], [2, 14, 8, 4]] {
+        svc.seed(chunk.map(|at| dated(&format!("p{:02}", at), at, &[if at % 2 == 0 { "even" } else { "odd" }]))).await.unwrap();
+    }
+    let svc = svc.service();
+    assert!(svc.sorted_by_create_at());
+    let ids = |hits: Vec<(i64, TantivyDocument)>| hits.iter().map(|(_, doc)| id_of(svc, doc)).collect::<Vec<_>>();
+
+    let (early, profile) = svc.latest_profiled(&[], None, 3, true, None).unwrap();
+    assert_eq!(ids(early), ["p15", "p14", "p12"]);
+    assert!(profile.early_terminated_segments > 0 && profile.docs_skipped > 0, "{:?}", profile);
+    let (full, profile) = svc.latest_profiled(&[], None, 3, false, None).unwrap();
+    assert_eq!(ids(full), ["p15", "p14", "p12"]);
+    assert_eq!((profile.early_terminated_segments, profile.docs_scanned), (0, 12));
+
+    assert_eq!(ids(svc.latest(&["odd".to_string()], Some(5), 10, None).unwrap()), ["p15", "p11", "p09", "p07", "p05"]);
+}
+
+#[tokio::test]
+async fn dedupe_keeps_the_best_post_of_each_group_of_copies() {
+    let svc = TestService::new().unwrap();
+    let copy = |id: &str| post(id, "Syndicated", "rust release notes");
+    svc.seed([copy("a"), copy("b"), post("c", "Original", "rust release notes and more"), copy("d")]).await.unwrap();
+    let svc = svc.service();
+
+    let req = SearchRequest { dedupe_by: Some("_content_hash".to_string()), ..search("body:rust", 10) };
+    let found = svc.search(&req).unwrap();
+    let mut groups: Vec<(String, Option<u64>)> = found.hits.iter().map(|hit| (id_of(svc, &hit.doc), hit.duplicates)).collect();
+    groups.sort();
+    // Equal scores keep the first copy written
+    assert_eq!(groups, [("a".to_string(), Some(2)), ("c".to_string(), Some(0))]);
+
+    let unknown = SearchRequest { dedupe_by: Some("body".to_string()), ..search("body:rust", 10) };
+    assert!(svc.search(&unknown).is_err());
+}
+
+#[tokio::test]
+async fn approximate_searches_and_capped_counts() {
+    let svc = TestService::new().unwrap();
+    // Scores spread by how often the word repeats
+    svc.seed((0..40).map(|i| post(&format!("p{:02}", i), "Scored", &format!("{} filler", "rust ".repeat(1 + i % 7))))).await.unwrap();
+    let ids = |req: &SearchRequest| svc.search_ids_with(req).unwrap();
+
+    let exact = ids(&search("body:rust", 5));
+    assert_eq!(ids(&SearchRequest { approximate: Some(1.0), ..search("body:rust", 5) }), exact);
+    // Pruning harder trades recall: nothing scores four times the fifth best of the first five
+    // matches collected, so those are kept
+    let pruned = ids(&SearchRequest { approximate: Some(4.0), ..search("body:rust", 5) });
+    assert_eq!(pruned, ["p04", "p03", "p02", "p01", "p00"]);
+
+    let total = |track| svc.service().search(&SearchRequest { track_total_hits: Some(track), ..search("body:rust", 5) }).unwrap().total.unwrap();
+    let capped = total(TrackTotalHits::UpTo(10));
+    assert_eq!((capped.value, capped.relation), (10, Relation::Gte));
+    let counted = total(TrackTotalHits::Exact);
+    assert_eq!((counted.value, counted.relation), (40, Relation::Eq));
+    let roomy = total(TrackTotalHits::UpTo(100));
+    assert_eq!((roomy.value, roomy.relation), (40, Relation::Eq));
+
+    // Shards' counts add up, and stay a lower bound once one of them was
+    let merged = capped.merge(TotalHits { value: 3, relation: Relation::Eq }, None);
+    assert_eq!((merged.value, merged.relation), (13, Relation::Gte));
+    let merged = counted.merge(counted, Some(50));
+    assert_eq!((merged.value, merged.relation), (50, Relation::Gte));
+}
+
+#[tokio::test]
+async fn composite_pages_through_every_bucket_once() {
+    let svc = TestService::new().unwrap();
+    let tagged = [("1", &["rust", "search"][..]), ("2", &["rust"]), ("3", &["go", "search"]), ("4", &["rust", "search"]), ("5", &[])];
+    svc.seed(tagged.map(|(id, tags)| dated(id, 1, tags))).await.unwrap();
+    let svc = svc.service();
+
+    let request = |size, after| CompositeRequest { q: None, sources: vec!["tags".to_string(), "status".to_string()], size, after, groups: None };
+    let everything = svc.composite(&request(100, None)).unwrap();
+    assert!(everything.after_key.is_none());
+    let buckets: Vec<(String, u64)> = everything.buckets.iter().map(|b| (b.key.join(","), b.doc_count)).collect();
+    let expected = [("go,published", 1), ("rust,published", 3), ("search,published", 3)];
+    assert_eq!(buckets, expected.map(|(key, count)| (key.to_string(), count)));
+
+    let mut paged = Vec::new();
+    let mut after = None;
+    loop {
+        let page = svc.composite(&request(2, after)).unwrap();
+        paged.extend(page.buckets.into_iter().map(|b| (b.key.join(","), b.doc_count)));
+        match page.after_key {
+            Some(key) => after = Some(key),
+            None => break,
+        }
+    }
+    assert_eq!(paged, buckets);
+    assert!(svc.composite(&request(2, Some(vec!["rust".to_string()]))).is_err());
+}
+
+#[tokio::test]
+async fn facets_and_aggregations_count_the_matches() {
+    let svc = TestService::new().unwrap();
+    let posts = [dated("1", 100, &["rust"]), dated("2", 200, &["rust", "search"]), dated("3", 300, &["search"]), BlogPost { status: "draft".to_string(), ..dated("4", 400, &["rust"]) }];
+    svc.seed(posts).await.unwrap();
+    let svc = svc.service();
+
+    let req = SearchRequest { facets: vec!["tags".to_string()], facet_size: Some(1), ..search("body:rust", 10) };
+    let facets = svc.search(&req).unwrap().facets.unwrap();
+    let tags: Vec<(String, u64)> = facets["tags"].iter().map(|v| (v.value.clone(), v.count)).collect();
+    assert_eq!(tags, [("rust".to_string(), 3)]);
+
+    let aggs: Aggregations = serde_json::from_value(serde_json::json!({
+        "statuses": { "terms": { "field": "status" } },
+        "dates": { "stats": { "field": "create_at" } },
+    }))
+    .unwrap();
+    let results = serde_json::to_value(svc.aggregate(Some("tags:rust"), aggs, None).unwrap()).unwrap();
+    let statuses: Vec<(&str, u64)> =
+        results["statuses"]["buckets"].as_array().unwrap().iter().map(|b| (b["key"].as_str().unwrap(), b["doc_count"].as_u64().unwrap())).collect();
+    assert_eq!(statuses, [("published", 2), ("draft", 1)]);
+    assert_eq!((results["dates"]["count"].as_u64(), results["dates"]["max"].as_f64()), (Some(3), Some(400.0)));
+
+    let body: Aggregations = serde_json::from_value(serde_json::json!({ "words": { "terms": { "field": "body" } } })).unwrap();
+    assert!(svc.aggregate(None, body, None).is_err());
+}
+
+#[tokio::test]
+async fn significant_terms_rank_what_the_matches_have_in_common() {
+    let svc = TestService::new().unwrap();
+    let mut posts: Vec<BlogPost> = (0..6).map(|i| BlogPost { body: "async runtime".to_string(), ..dated(&format!("m{}", i), 1, &["tokio", "common"]) }).collect();
+    posts.push(BlogPost { body: "async runtime".to_string(), ..dated("m6", 1, &["rare"]) });
+    posts.extend((0..14).map(|i| dated(&format!("o{}", i), 1, &["common"])));
+    svc.seed(posts).await.unwrap();
+
+    let significant = svc.service().significant_terms("body:async", "tags", 10, 2, None).unwrap();
+    assert_eq!((significant.doc_count, significant.bg_count), (7, 21));
+    // `common` is as frequent everywhere, and `rare` is on too few matches to judge
+    let terms: Vec<(&str, u64, u64)> = significant.terms.iter().map(|t| (t.term.as_str(), t.doc_count, t.bg_count)).collect();
+    assert_eq!(terms, [("tokio", 6, 6)]);
+}
@@ -0,0 +1,424 @@
+//! Write paths of the service: batches, `op_type=create`, patches, writes over archived
+//! posts, tenant ownership, paging and journal replay; and the ACL on every posts read path.
+
+use std::time::Instant;
+
+use tantivy::schema::Value;
+use tantivy::{DocAddress, IndexReader, IndexSettings, IndexWriter, ReloadPolicy, TantivyDocument};
+use tantivy_demo::aggs::CompositeRequest;
+use tantivy_demo::batch::{apply_batch, BatchOp, OpType};
+use tantivy_demo::error::ServiceError;
+use tantivy_demo::indexes::{FieldKind, FieldSpec, PostsSpec, SchemaFile};
+use tantivy_demo::journal::JournalOp;
+use tantivy_demo::paging::SearchAfter;
+use tantivy_demo::patch::PostPatch;
+use tantivy_demo::retention::{RetentionAction, RetentionRule};
+use tantivy_demo::schema::{index_post, open_index, posts_schema, BlogPost, IngestPipeline};
+use tantivy_demo::service::{SearchRequest, SearchService, ServiceConfig};
+use tantivy_demo::test_utils::{post, TestService};
+use tantivy_demo::usage::TenantConfig;
+
+/// Posts carry a typed `price` taken from `features`, so a post can fail late, while indexed.
+fn priced() -> PostsSpec {
+    let price = FieldSpec { name: "price".to_string(), kind: FieldKind::I64, stored: true, indexed: true, fast: true, analyzer: None };
+    PostsSpec { fields: vec![price] }
+}
+
+fn with_price(id: &str, price: serde_json::Value) -> BlogPost {
+    BlogPost { features: serde_json::json!({ "price": price }), ..post(id, "Priced", "for sale") }
+}
+
+/// A RAM service whose writes wait for the next refresh, like the server's.
+fn deferred() -> SearchService {
+    let config = ServiceConfig { in_memory: true, sync_commits: false, writer_heap_bytes: 15_000_000, ..ServiceConfig::default() };
+    SearchService::open(config).unwrap()
+}
+
+/// A RAM service with tenants `a` and `b`.
+fn tenanted(sync_commits: bool) -> SearchService {
+    let tenant = |name: &str| TenantConfig { name: name.to_string(), api_keys: vec![format!("k-{}", name)], max_docs: None };
+    let config = ServiceConfig {
+        in_memory: true,
+        sync_commits,
+        writer_heap_bytes: 15_000_000,
+        tenants: vec![tenant("a"), tenant("b")],
+        ..ServiceConfig::default()
+    };
+    SearchService::open(config).unwrap()
+}
+
+fn owned(id: &str, tenant: &str, body: &str) -> BlogPost {
+    BlogPost { tenant: Some(tenant.to_string()), ..post(id, "Owned", body) }
+}
+
+fn tenant(name: &str) -> Option<String> {
+    Some(name.to_string())
+}
+
+fn ids(svc: &SearchService, q: &str) -> Vec<String> {
+    let f_id = svc.schema().get_field("id").unwrap();
+    let req = SearchRequest { q: q.to_string(), limit: 100, ..SearchRequest::default() };
+    svc.search(&req).unwrap().hits.iter().filter_map(|hit| hit.doc.get_first(f_id).and_then(|v| v.as_str()).map(str::to_string)).collect()
+}
+
+#[tokio::test]
+async fn a_batch_with_an_invalid_post_writes_none_of_it() {
+    let config = ServiceConfig { schema_file: SchemaFile { posts: priced(), ..SchemaFile::default() }, ..ServiceConfig::default() };
+    let svc = TestService::with_config(config).unwrap();
+    let ops = vec![BatchOp::Index { doc: with_price("1", 10.into()) }, BatchOp::Index { doc: with_price("2", "cheap".into()) }];
+    let err = svc.service().apply_batch(ops, &None).await.unwrap_err();
+    assert!(matches!(err, ServiceError::Invalid(ref msg) if msg.starts_with("operation 1:")), "{}", err);
+    svc.assert_count("for", 0);
+    let dead = svc.service().dead_letters().list();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].ops.len(), 2);
+}
+
+#[tokio::test]
+async fn a_batch_index_op_replaces_a_post_with_the_same_id() {
+    for sync_commits in [true, false] {
+        let config = ServiceConfig { in_memory: true, sync_commits, writer_heap_bytes: 15_000_000, ..ServiceConfig::default() };
+        let svc = SearchService::open(config).unwrap();
+        svc.index_document(post("1", "First", "one"), OpType::Upsert).await.unwrap();
+        svc.refresh().unwrap();
+        let ops = vec![BatchOp::Index { doc: post("1", "Second", "two") }, BatchOp::Index { doc: post("1", "Third", "three") }];
+        svc.apply_batch(ops, &None).await.unwrap();
+        svc.refresh().unwrap();
+        assert_eq!(ids(&svc, "id:1"), ["1"], "sync_commits: {}", sync_commits);
+        assert_eq!(ids(&svc, "title:third"), ["1"]);
+    }
+}
+
+#[test]
+fn a_batch_failing_midway_leaves_other_writes_alone() {
+    let schema = priced().extend(posts_schema("zh_ngram", "zh_ngram", "default"));
+    let index = open_index(&Default::default(), schema.clone(), IndexSettings::default(), true).unwrap();
+    let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+    let pipeline = IngestPipeline { extra_fields: vec!["price".to_string()], ..IngestPipeline::default() };
+    // Another request's write, pending when the batch starts
+    index_post(&mut writer, &schema, &pipeline, post("0", "Pending", "before the batch")).unwrap();
+
+    let ops = vec![BatchOp::Index { doc: with_price("1", 10.into()) }, BatchOp::Index { doc: with_price("2", "cheap".into()) }];
+    assert!(apply_batch(&writer, &schema, &pipeline, ops).is_err());
+    writer.commit().unwrap();
+
+    let reader: IndexReader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into().unwrap();
+    let searcher = reader.searcher();
+    assert_eq!(searcher.num_docs(), 1);
+    let f_id = schema.get_field("id").unwrap();
+    let doc: TantivyDocument = searcher.doc(DocAddress::new(0, 0)).unwrap();
+    assert_eq!(doc.get_first(f_id).and_then(|v| v.as_str()), Some("0"));
+}
+
+#[tokio::test]
+async fn search_after_pages_through_every_hit_once() {
+    // Every third post is archived, so archive pages interleave the tiers by relative score
+    let archive_old = RetentionRule { name: "old".to_string(), query: "*".to_string(), older_than_days: 1, action: RetentionAction::Archive };
+    let svc = TestService::with_config(ServiceConfig { retention_rules: vec![archive_old], ..ServiceConfig::default() }).unwrap();
+    // Repeating the word spreads the scores, and equal lengths give ties too
+    let posts = (0..25).map(|i| BlogPost { create_at: (i % 3 == 0).then_some(1_000_000), ..post(&format!("p{:02}", i), "Paged", &"rust ".repeat(1 + i % 4)) });
+    svc.seed(posts).await.unwrap();
+    svc.service().run_retention(false).unwrap();
+    svc.service().refresh().unwrap();
+
+    let f_id = svc.service().schema().get_field("id").unwrap();
+    for include_archive in [false, true] {
+        let all = svc.search_ids_with(&SearchRequest { q: "rust".to_string(), limit: 25, include_archive, ..SearchRequest::default() }).unwrap();
+        assert_eq!(all.len(), if include_archive { 25 } else { 16 });
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let req = SearchRequest { q: "rust".to_string(), limit: 4, search_after: after, include_archive, ..SearchRequest::default() };
+            let found = svc.service().search(&req).unwrap();
+            let Some(last) = found.hits.last() else { break };
+            after = Some(SearchAfter::after(last, found.tier_best));
+            paged.extend(found.hits.iter().filter_map(|hit| hit.doc.get_first(f_id).and_then(|v| v.as_str()).map(str::to_string)));
+        }
+        assert_eq!(paged, all, "include_archive={}", include_archive);
+    }
+}
+
+#[tokio::test]
+async fn a_timed_out_search_with_the_archive_still_pages() {
+    let svc = TestService::new().unwrap();
+    svc.seed((0..60).map(|i| post(&format!("p{:02}", i), "Paged", &"rust ".repeat(1 + i % 4)))).await.unwrap();
+    let page = SearchRequest { q: "rust".to_string(), offset: 20, limit: 5, include_archive: true, matches: true, ..SearchRequest::default() };
+    let all = svc.search_ids_with(&SearchRequest { offset: 0, limit: 60, ..page.clone() }).unwrap();
+    let started = Instant::now();
+    svc.service().search(&page).unwrap();
+    let full = started.elapsed();
+
+    // Deadlines spread over a full search's time run out while hits are being fetched
+    let f_id = svc.service().schema().get_field("id").unwrap();
+    for step in 0..200 {
+        let req = SearchRequest { deadline: Some(Instant::now() + full * step / 100), ..page.clone() };
+        let found = svc.service().search(&req).unwrap();
+        let ids: Vec<&str> = found.hits.iter().filter_map(|hit| hit.doc.get_first(f_id).and_then(|v| v.as_str())).collect();
+        assert!(ids.len() <= 5, "{} hits past a deadline: {:?}", ids.len(), ids);
+        assert!(ids.iter().all(|id| all[20..].iter().any(|a| a == id)), "hits before the offset: {:?}", ids);
+    }
+}
+
+#[tokio::test]
+async fn search_after_rejects_offset() {
+    let svc = TestService::new().unwrap();
+    svc.seed([post("1", "Fast search", "tantivy in rust")]).await.unwrap();
+    let hit = svc.service().search(&SearchRequest { q: "rust".to_string(), limit: 1, ..SearchRequest::default() }).unwrap().hits.remove(0);
+    let req = SearchRequest { q: "rust".to_string(), offset: 1, search_after: Some(SearchAfter::after(&hit, None)), ..SearchRequest::default() };
+    assert!(matches!(svc.service().search(&req), Err(ServiceError::Invalid(_))));
+    assert!(SearchAfter::decode(&SearchAfter::after(&hit, None).encode()).is_ok());
+    assert!(SearchAfter::decode("not a cursor").is_err());
+}
+
+#[tokio::test]
+async fn create_conflicts_with_a_committed_post() {
+    let svc = TestService::new().unwrap();
+    svc.seed([post("1", "Fast search", "tantivy in rust")]).await.unwrap();
+    let err = svc.service().index_document(post("1", "Again", "other body"), OpType::Create).await.unwrap_err();
+    assert!(matches!(err, ServiceError::Conflict(_)), "{}", err);
+    svc.assert_hits("body:rust", &["1"]);
+    // An upsert still replaces it
+    svc.service().index_document(post("1", "Again", "other body"), OpType::Upsert).await.unwrap();
+    svc.assert_hits("body:other", &["1"]);
+    svc.assert_count("body:rust", 0);
+}
+
+#[tokio::test]
+async fn create_conflicts_with_a_write_not_committed_yet() {
+    let svc = deferred();
+    svc.index_document(post("1", "First", "one"), OpType::Create).await.unwrap();
+    let err = svc.index_document(post("1", "Second", "two"), OpType::Create).await.unwrap_err();
+    assert!(matches!(err, ServiceError::Conflict(_)), "{}", err);
+    // Deleting it, still uncommitted, frees the id again
+    svc.delete_document("1", &None).unwrap();
+    svc.index_document(post("1", "Third", "three"), OpType::Create).await.unwrap();
+    svc.refresh().unwrap();
+    assert_eq!(ids(&svc, "title:third"), ["1"]);
+    assert_eq!(ids(&svc, "title:first OR title:second"), Vec::<String>::new());
+    // Committed now, and still taken
+    let err = svc.index_document(post("1", "Fourth", "four"), OpType::Create).await.unwrap_err();
+    assert!(matches!(err, ServiceError::Conflict(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn racing_creates_for_one_id_let_one_through() {
+    let svc = deferred();
+    let (a, b) = tokio::join!(
+        svc.index_document(post("1", "First", "one"), OpType::Create),
+        svc.index_document(post("1", "Second", "two"), OpType::Create),
+    );
+    assert_eq!([a.is_ok(), b.is_ok()].iter().filter(|ok| **ok).count(), 1);
+    svc.refresh().unwrap();
+    assert_eq!(ids(&svc, "id:1"), ["1"]);
+}
+
+#[tokio::test]
+async fn patches_build_on_writes_not_committed_yet() {
+    let svc = deferred();
+    svc.update_document(post("1", "First", "one")).await.unwrap();
+    let [a, b] = ["a", "b"].map(|t| PostPatch { add_tags: vec![t.to_string()], ..PostPatch::default() });
+    let (a, b) = tokio::join!(svc.patch_document("1", &a, &None), svc.patch_document("1", &b, &None));
+    a.unwrap().unwrap();
+    b.unwrap().unwrap();
+    svc.patch_document("1", &PostPatch { title: Some("Patched".to_string()), ..PostPatch::default() }, &None).await.unwrap();
+    svc.refresh().unwrap();
+    assert_eq!(ids(&svc, "title:patched AND tags:a AND tags:b AND body:one"), ["1"]);
+}
+
+/// A journaled service with the old post `1` moved into the archive by retention.
+async fn archived() -> TestService {
+    let archive_old = RetentionRule { name: "old".to_string(), query: "*".to_string(), older_than_days: 1, action: RetentionAction::Archive };
+    let config = ServiceConfig { journal_max_entries: Some(100), retention_rules: vec![archive_old], ..ServiceConfig::default() };
+    let svc = TestService::with_config(config).unwrap();
+    svc.seed([BlogPost { create_at: Some(1_000_000), ..post("1", "Old search", "tantivy in rust") }]).await.unwrap();
+    svc.service().run_retention(false).unwrap();
+    svc.service().refresh().unwrap();
+    svc
+}
+
+/// Panics unless `search` matches `hot` in the hot tier and `all` with the archive, on the
+/// leader and on a follower replaying its journal.
+#[track_caller]
+fn assert_tiers(leader: &TestService, hot: &[&str], all: &[&str]) {
+    let entries = leader.service().journal().unwrap().read(1, 100).unwrap();
+    let follower = TestService::new().unwrap();
+    follower.replay(entries).unwrap();
+    for svc in [leader, &follower] {
+        assert_eq!(svc.search_ids("search").unwrap(), hot);
+        let with_archive = SearchRequest { q: "search".to_string(), limit: 10, include_archive: true, ..SearchRequest::default() };
+        assert_eq!(svc.search_ids_with(&with_archive).unwrap(), all);
+    }
+}
+
+#[tokio::test]
+async fn retention_runs_reach_followers() {
+    let archive_old = RetentionRule { name: "old".to_string(), query: "status:published".to_string(), older_than_days: 1, action: RetentionAction::Archive };
+    let drop_drafts = RetentionRule { name: "drafts".to_string(), query: "status:draft".to_string(), older_than_days: 1, action: RetentionAction::Delete };
+    let config = ServiceConfig { journal_max_entries: Some(100), retention_rules: vec![archive_old, drop_drafts], ..ServiceConfig::default() };
+    let leader = TestService::with_config(config).unwrap();
+    let old = |id, status: &str| BlogPost { create_at: Some(1_000_000), status: status.to_string(), ..post(id, "Old search", "tantivy in rust") };
+    leader.seed([old("1", "published"), old("2", "draft"), post("3", "New search", "tantivy in rust")]).await.unwrap();
+    leader.service().run_retention(false).unwrap();
+    leader.service().refresh().unwrap();
+    assert_tiers(&leader, &["3"], &["3", "1"]);
+}
+
+#[tokio::test]
+async fn patching_an_archived_post_journals_its_move_back() {
+    let leader = archived().await;
+    assert_tiers(&leader, &[], &["1"]);
+    let patch = PostPatch { status: Some("draft".to_string()), ..PostPatch::default() };
+    leader.service().patch_document("1", &patch, &None).await.unwrap().unwrap();
+
+    let entries = leader.service().journal().unwrap().read(1, 100).unwrap();
+    let JournalOp::Batch { ops } = &entries.last().unwrap().op else { panic!("expected a batch") };
+    assert!(matches!(&ops[..], [BatchOp::Delete { id }, BatchOp::Update { doc }] if id == "1" && doc.status == "draft"), "{:?}", ops);
+    assert_tiers(&leader, &["1"], &["1"]);
+    leader.assert_hits("status:draft", &["1"]);
+}
+
+#[tokio::test]
+async fn updating_an_archived_post_moves_it_back() {
+    let leader = archived().await;
+    leader.update(post("1", "Old search", "tantivy in go")).await.unwrap();
+    assert_tiers(&leader, &["1"], &["1"]);
+    leader.assert_hits("body:go", &["1"]);
+}
+
+#[tokio::test]
+async fn upserting_an_archived_post_moves_it_back() {
+    let leader = archived().await;
+    leader.service().index_document(post("1", "Old search", "tantivy in go"), OpType::Upsert).await.unwrap();
+    assert_tiers(&leader, &["1"], &["1"]);
+    leader.assert_hits("body:go", &["1"]);
+}
+
+#[tokio::test]
+async fn batch_writes_reach_the_archived_post() {
+    let leader = archived().await;
+    leader.service().apply_batch(vec![BatchOp::Delete { id: "1".to_string() }], &None).await.unwrap();
+    assert_tiers(&leader, &[], &[]);
+
+    let leader = archived().await;
+    leader.service().apply_batch(vec![BatchOp::Index { doc: post("1", "Old search", "tantivy in go") }], &None).await.unwrap();
+    assert_tiers(&leader, &["1"], &["1"]);
+}
+
+#[tokio::test]
+async fn a_follower_replaying_the_journal_ends_up_with_the_leader_posts() {
+    let config = ServiceConfig { journal_max_entries: Some(100), ..ServiceConfig::default() };
+    let leader = TestService::with_config(config).unwrap();
+    leader.seed([post("1", "Fast search", "tantivy in rust"), post("2", "Slow search", "grep")]).await.unwrap();
+    leader.update(post("1", "Fast search", "tantivy in go")).await.unwrap();
+    leader.service().index_document(post("3", "New search", "ripgrep"), OpType::Create).await.unwrap();
+    leader.delete("2").unwrap();
+
+    let journal = leader.service().journal().unwrap();
+    let entries = journal.read(1, 100).unwrap();
+    let sizes: Vec<usize> = entries.iter().map(|e| if let JournalOp::Batch { ops } = &e.op { ops.len() } else { 0 }).collect();
+    // The seed batch, the update, the create and the delete, in the order they were applied
+    assert_eq!(sizes, [2, 1, 1, 1]);
+    assert!(matches!(&entries[3].op, JournalOp::Batch { ops } if matches!(&ops[0], BatchOp::Delete { id } if id == "2")));
+
+    let follower = TestService::new().unwrap();
+    follower.replay(entries).unwrap();
+    for q in ["search", "body:rust", "body:go", "body:grep", "body:ripgrep"] {
+        assert_eq!(follower.search_ids(q).unwrap(), leader.search_ids(q).unwrap(), "hits of {:?}", q);
+    }
+    follower.assert_hits_unordered("search", &["1", "3"]);
+}
+
+#[tokio::test]
+async fn deleting_another_tenants_post_is_not_found() {
+    for sync_commits in [true, false] {
+        let svc = tenanted(sync_commits);
+        svc.index_document(owned("1", "a", "rust"), OpType::Upsert).await.unwrap();
+        let err = svc.delete_document("1", &tenant("b")).unwrap_err();
+        assert!(matches!(err, ServiceError::NotFound(_)), "{}", err);
+        let err = svc.apply_batch(vec![BatchOp::Delete { id: "1".to_string() }], &tenant("b")).await.unwrap_err();
+        assert!(matches!(err, ServiceError::NotFound(_)), "{}", err);
+        svc.refresh().unwrap();
+        assert_eq!(ids(&svc, "body:rust"), ["1"], "sync_commits: {}", sync_commits);
+
+        svc.delete_document("1", &tenant("a")).unwrap();
+        svc.refresh().unwrap();
+        assert_eq!(ids(&svc, "body:rust"), Vec::<String>::new());
+    }
+}
+
+#[tokio::test]
+async fn writing_over_another_tenants_post_is_not_found() {
+    for sync_commits in [true, false] {
+        let svc = tenanted(sync_commits);
+        svc.index_document(owned("1", "a", "rust"), OpType::Upsert).await.unwrap();
+        for op_type in [OpType::Upsert, OpType::Create] {
+            let err = svc.index_document(owned("1", "b", "taken"), op_type).await.unwrap_err();
+            assert!(matches!(err, ServiceError::NotFound(_)), "{}", err);
+        }
+        let err = svc.update_document(owned("1", "b", "taken")).await.unwrap_err();
+        assert!(matches!(err, ServiceError::NotFound(_)), "{}", err);
+        let err = svc.apply_batch(vec![BatchOp::Update { doc: owned("1", "b", "taken") }], &tenant("b")).await.unwrap_err();
+        assert!(matches!(err, ServiceError::NotFound(_)), "{}", err);
+        svc.refresh().unwrap();
+        assert_eq!(ids(&svc, "body:rust"), ["1"], "sync_commits: {}", sync_commits);
+        assert_eq!(ids(&svc, "body:taken"), Vec::<String>::new());
+        let usage = svc.usage_report().unwrap();
+        let docs_indexed = |name: &str| usage.iter().find(|u| u.tenant == name).map(|u| u.docs_indexed);
+        assert_eq!((docs_indexed("a"), docs_indexed("b")), (Some(1), Some(0)));
+
+        // The owner may still replace it
+        svc.update_document(owned("1", "a", "go")).await.unwrap();
+        svc.refresh().unwrap();
+        assert_eq!(ids(&svc, "body:go"), ["1"]);
+    }
+}
+
+#[tokio::test]
+async fn every_posts_read_hides_restricted_posts_outside_the_callers_groups() {
+    let svc = TestService::new().unwrap();
+    let public = BlogPost { tags: vec!["rust".to_string()], create_at: Some(1), ..post("public", "Open", "rust for all") };
+    let restricted = BlogPost {
+        tags: vec!["rust".to_string(), "roadmap".to_string()],
+        create_at: Some(2),
+        allowed_groups: vec!["eng".to_string()],
+        ..post("restricted", "Closed", "rust plans")
+    };
+    svc.seed([public, restricted]).await.unwrap();
+    let svc = svc.service();
+    let f_id = svc.schema().get_field("id").unwrap();
+    let id_of = |doc: &TantivyDocument| doc.get_first(f_id).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+    for (groups, visible) in [(vec!["sales".to_string()], vec!["public"]), (vec!["eng".to_string()], vec!["restricted", "public"])] {
+        let g = Some(groups.as_slice());
+        let req = SearchRequest { q: "body:rust".to_string(), limit: 10, groups: Some(groups.clone()), ..SearchRequest::default() };
+        let mut searched: Vec<String> = svc.search(&req).unwrap().hits.iter().map(|hit| id_of(&hit.doc)).collect();
+        searched.sort();
+        let mut expected = visible.clone();
+        expected.sort();
+        assert_eq!(searched, expected, "search, groups {:?}", groups);
+
+        let latest: Vec<String> = svc.latest(&[], None, 10, g).unwrap().iter().map(|(_, doc)| id_of(doc)).collect();
+        assert_eq!(latest, visible, "latest, groups {:?}", groups);
+        let indexed = svc.indexed_since(&[], None, 10, g).unwrap();
+        assert_eq!(indexed.len(), visible.len(), "indexed_since, groups {:?}", groups);
+        assert_eq!(svc.export_snapshot(None, g).unwrap().1.len(), visible.len(), "export, groups {:?}", groups);
+
+        let composite = CompositeRequest { q: None, sources: vec!["tags".to_string()], size: 10, after: None, groups: Some(groups.clone()) };
+        let buckets: Vec<(String, u64)> = svc.composite(&composite).unwrap().buckets.into_iter().map(|b| (b.key.join(","), b.doc_count)).collect();
+        let restricted_seen = visible.contains(&"restricted");
+        match restricted_seen {
+            true => assert_eq!(buckets, [("roadmap".to_string(), 1), ("rust".to_string(), 2)]),
+            false => assert_eq!(buckets, [("rust".to_string(), 1)]),
+        }
+        let aggs = serde_json::from_value(serde_json::json!({ "n": { "value_count": { "field": "create_at" } } })).unwrap();
+        let counted = serde_json::to_value(svc.aggregate(None, aggs, g).unwrap()).unwrap();
+        assert_eq!(counted["n"]["value"], visible.len() as f64, "aggregate, groups {:?}", groups);
+        let significant = svc.significant_terms("body:plans", "tags", 10, 1, g).unwrap();
+        assert_eq!((significant.doc_count, significant.bg_count), (u64::from(restricted_seen), visible.len() as u64));
+        assert_eq!(significant.terms.iter().any(|t| t.term == "roadmap"), restricted_seen);
+
+        assert_eq!(svc.document("restricted", g).unwrap().is_some(), restricted_seen, "document, groups {:?}", groups);
+        assert_eq!(svc.document_terms("restricted", g).unwrap().is_some(), restricted_seen);
+        assert!(svc.document("public", g).unwrap().is_some());
+    }
+}